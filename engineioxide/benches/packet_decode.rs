@@ -11,7 +11,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| Packet::try_from(packet.as_str()).unwrap())
     });
     c.bench_function("Decode packet message", |b| {
-        let packet: String = Packet::Message(black_box("Hello").to_string())
+        let packet: String = Packet::Message(black_box("Hello").to_string().into())
             .try_into()
             .unwrap();
         b.iter(|| Packet::try_from(packet.as_str()).unwrap())
@@ -1,3 +1,4 @@
+#![allow(clippy::result_large_err)]
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use engineioxide::{config::EngineIoConfig, sid::Sid, OpenPacket, Packet, TransportType};
 
@@ -19,9 +20,17 @@ fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| TryInto::<String>::try_into(packet.clone()))
     });
     c.bench_function("Encode packet message", |b| {
-        let packet = Packet::Message(black_box("Hello").to_string());
+        let packet = Packet::Message(black_box("Hello").to_string().into());
         b.iter(|| TryInto::<String>::try_into(packet.clone()))
     });
+    c.bench_function("Encode packet message from owned string", |b| {
+        let msg = black_box("Hello");
+        b.iter(|| TryInto::<String>::try_into(Packet::Message(msg.to_string().into())))
+    });
+    c.bench_function("Encode packet message from borrowed str", |b| {
+        let msg = black_box("Hello");
+        b.iter(|| TryInto::<String>::try_into(Packet::Message(msg.into())))
+    });
     c.bench_function("Encode packet noop", |b| {
         let packet = Packet::Noop;
         b.iter(|| TryInto::<String>::try_into(packet.clone()))
@@ -0,0 +1,95 @@
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use engineioxide::{
+    config::{EngineIoConfig, V3EncoderOptions},
+    encode_v4_payload, v3_bin_packet_encoder, v3_bin_packet_encoder_with_buf, Packet,
+};
+
+/// Small binary packets that are base64 encoded through the string branch of the encoder
+fn packets() -> Vec<Packet> {
+    (0..1000)
+        .map(|_| Packet::Binary(vec![1, 2, 3, 4]))
+        .collect()
+}
+
+/// 1MB of message packets of 1KB
+fn message_packets_1mb() -> Vec<Packet> {
+    (0..1000)
+        .map(|_| Packet::Message("a".repeat(1000).into()))
+        .collect()
+}
+
+/// A 1MB binary packet, base64 encoded in the payload
+fn binary_packet_1mb() -> Vec<Packet> {
+    vec![Packet::Binary(vec![0xab; 1_000_000])]
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let config = EngineIoConfig::default();
+    let max_payload = 2_000_000;
+    c.bench_function("Encode v4 payload of 1MB of messages into bytes", |b| {
+        b.iter_batched(
+            message_packets_1mb,
+            |packets| {
+                rt.block_on(encode_v4_payload(black_box(packets), max_payload, &config))
+                    .unwrap()
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("Encode v4 payload of a 1MB binary packet into bytes", |b| {
+        b.iter_batched(
+            binary_packet_1mb,
+            |packets| {
+                rt.block_on(encode_v4_payload(black_box(packets), max_payload, &config))
+                    .unwrap()
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("Encode v3 binary payload of 1000 small packets", |b| {
+        b.iter_batched(
+            packets,
+            |packets| {
+                let mut data = BytesMut::new();
+                for packet in packets {
+                    v3_bin_packet_encoder(black_box(packet), &mut data).unwrap();
+                }
+                data
+            },
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    c.bench_function(
+        "Encode v3 binary payload of 1000 small packets with a shared buffer",
+        |b| {
+            b.iter_batched(
+                packets,
+                |packets| {
+                    let mut data = BytesMut::new();
+                    let mut buf = String::new();
+                    let opts = V3EncoderOptions::default();
+                    for packet in packets {
+                        v3_bin_packet_encoder_with_buf(
+                            black_box(packet),
+                            &mut data,
+                            &mut buf,
+                            &opts,
+                        )
+                        .unwrap();
+                    }
+                    data
+                },
+                criterion::BatchSize::SmallInput,
+            )
+        },
+    );
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
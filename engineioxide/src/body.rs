@@ -1,10 +1,12 @@
 //! Response Body wrapper in order to return a custom body or the body from the inner service
 
 use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
 use http_body::{Body, Frame, SizeHint};
 use http_body_util::Full;
 use pin_project_lite::pin_project;
 use std::pin::Pin;
+use std::sync::Mutex;
 use std::task::{Context, Poll};
 
 pin_project! {
@@ -14,6 +16,13 @@ pin_project! {
         CustomBody {
             #[pin]
             body: Full<Bytes>,
+            unsent_hook: Option<DropHook>,
+            drop_hook: Option<DropHook>,
+        },
+        StreamBody {
+            // The stream is only accessed mutably, the mutex is never locked and only makes the body `Sync`
+            body: Mutex<BoxStream<'static, Bytes>>,
+            drop_hook: Option<DropHook>,
         },
         Body {
             #[pin]
@@ -32,7 +41,56 @@ impl<B> ResponseBody<B> {
     }
 
     pub fn custom_response(body: Full<Bytes>) -> Self {
-        ResponseBody::CustomBody { body }
+        ResponseBody::CustomBody {
+            body,
+            unsent_hook: None,
+            drop_hook: None,
+        }
+    }
+
+    /// A custom body streamed chunk by chunk, without a known length
+    pub fn stream_response(body: BoxStream<'static, Bytes>) -> Self {
+        ResponseBody::StreamBody {
+            body: Mutex::new(body),
+            drop_hook: None,
+        }
+    }
+
+    /// Set a hook called if a custom body is dropped before its data was polled, e.g. if the client disconnected.
+    ///
+    /// This is a best effort detection: once the data is polled there is no way to know if it was entirely written.
+    pub fn with_unsent_hook(self, hook: impl FnOnce() + Send + Sync + 'static) -> Self {
+        match self {
+            ResponseBody::CustomBody {
+                body, drop_hook, ..
+            } => ResponseBody::CustomBody {
+                body,
+                unsent_hook: Some(DropHook::new(hook)),
+                drop_hook,
+            },
+            body => body,
+        }
+    }
+
+    /// Set a hook called when a custom body is dropped, whether its data was polled or not.
+    /// It is called after the [unsent hook](Self::with_unsent_hook).
+    ///
+    /// The hook is dropped right away, and therefore called, if the body is not a custom body.
+    pub fn with_drop_hook(self, hook: DropHook) -> Self {
+        match self {
+            ResponseBody::CustomBody {
+                body, unsent_hook, ..
+            } => ResponseBody::CustomBody {
+                body,
+                unsent_hook,
+                drop_hook: Some(hook),
+            },
+            ResponseBody::StreamBody { body, .. } => ResponseBody::StreamBody {
+                body,
+                drop_hook: Some(hook),
+            },
+            body => body,
+        }
     }
 
     pub fn new(body: B) -> Self {
@@ -53,7 +111,8 @@ where
         match &self {
             ResponseBody::EmptyResponse => true,
             ResponseBody::Body { body } => body.is_end_stream(),
-            ResponseBody::CustomBody { body } => body.is_end_stream(),
+            ResponseBody::CustomBody { body, .. } => body.is_end_stream(),
+            ResponseBody::StreamBody { .. } => false,
         }
     }
 
@@ -65,7 +124,8 @@ where
                 hint
             }
             ResponseBody::Body { body } => body.size_hint(),
-            ResponseBody::CustomBody { body } => body.size_hint(),
+            ResponseBody::CustomBody { body, .. } => body.size_hint(),
+            ResponseBody::StreamBody { .. } => SizeHint::default(),
         }
     }
 
@@ -76,7 +136,104 @@ where
         match self.project() {
             BodyProj::EmptyResponse => Poll::Ready(None),
             BodyProj::Body { body } => body.poll_frame(cx),
-            BodyProj::CustomBody { body } => body.poll_frame(cx).map_err(|err| match err {}),
+            BodyProj::StreamBody { body, .. } => body
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner())
+                .poll_next_unpin(cx)
+                .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk)))),
+            BodyProj::CustomBody {
+                body, unsent_hook, ..
+            } => {
+                let res = body.poll_frame(cx).map_err(|err| match err {});
+                if res.is_ready() {
+                    // The data is handed to the connection, the hook should not be called anymore
+                    if let Some(hook) = unsent_hook.take() {
+                        hook.disarm();
+                    }
+                }
+                res
+            }
+        }
+    }
+}
+
+/// A hook called on drop if it was not disarmed
+pub struct DropHook(Option<Box<dyn FnOnce() + Send + Sync>>);
+impl DropHook {
+    pub fn new(hook: impl FnOnce() + Send + Sync + 'static) -> Self {
+        DropHook(Some(Box::new(hook)))
+    }
+
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+impl Drop for DropHook {
+    fn drop(&mut self) {
+        if let Some(hook) = self.0.take() {
+            hook();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unsent_hook() {
+        let called = Arc::new(AtomicBool::new(false));
+        let called_ = called.clone();
+        let body = ResponseBody::<Full<Bytes>>::custom_response(Full::new("data".into()))
+            .with_unsent_hook(move || called_.store(true, Ordering::SeqCst));
+        drop(body);
+        assert!(called.load(Ordering::SeqCst));
+
+        let called = Arc::new(AtomicBool::new(false));
+        let called_ = called.clone();
+        let body = ResponseBody::<Full<Bytes>>::custom_response(Full::new("data".into()))
+            .with_unsent_hook(move || called_.store(true, Ordering::SeqCst));
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "data");
+        assert!(!called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn drop_hook() {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (calls1, calls2) = (calls.clone(), calls.clone());
+        let body = ResponseBody::<Full<Bytes>>::custom_response(Full::new("data".into()))
+            .with_drop_hook(DropHook::new(move || calls1.lock().unwrap().push("drop")))
+            .with_unsent_hook(move || calls2.lock().unwrap().push("unsent"));
+        drop(body);
+        assert_eq!(*calls.lock().unwrap(), ["unsent", "drop"]);
+
+        let called = Arc::new(AtomicBool::new(false));
+        let called_ = called.clone();
+        let body = ResponseBody::<Full<Bytes>>::custom_response(Full::new("data".into()))
+            .with_drop_hook(DropHook::new(move || called_.store(true, Ordering::SeqCst)));
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "data");
+        assert!(called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn stream_body() {
+        let called = Arc::new(AtomicBool::new(false));
+        let called_ = called.clone();
+        let chunks = futures::stream::iter([Bytes::from("da"), Bytes::from("ta")]);
+        let body = ResponseBody::<Full<Bytes>>::stream_response(chunks.boxed())
+            .with_drop_hook(DropHook::new(move || called_.store(true, Ordering::SeqCst)));
+        assert_eq!(body.size_hint().exact(), None);
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "data");
+        assert!(called.load(Ordering::SeqCst));
+    }
+}
@@ -29,9 +29,16 @@
 //! let svc = EngineIoService::with_config(MyHandler, config);
 //! ```
 
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, sync::Arc, time::Duration};
 
-use crate::service::TransportType;
+use http::{request::Parts, HeaderMap};
+
+#[cfg(feature = "shared-polling")]
+use crate::store::SharedPolling;
+use crate::{
+    service::TransportType,
+    sid::{RandomSidGenerator, SidGenerator},
+};
 
 /// Configuration for the engine.io engine & transports
 #[derive(Debug, Clone)]
@@ -55,13 +62,314 @@ pub struct EngineIoConfig {
     /// Defaults to 128 packets
     pub max_buffer_size: usize,
 
+    /// What to do when a packet is emitted to a socket whose buffer is full,
+    /// because its client does not receive the packets fast enough.
+    ///
+    /// Defaults to [`OverflowPolicy::Error`].
+    pub overflow_policy: OverflowPolicy,
+
     /// The maximum number of bytes that can be received per http request.
+    /// It also limits the size of each websocket frame and message, a larger frame closes the connection.
     /// Defaults to 100kb.
     pub max_payload: u64,
 
     /// Allowed transports on this server
     /// It is represented as a bitfield to allow to combine any number of transports easily
     pub transports: u8,
+
+    /// The amount of time a polling request will keep waiting for new packets
+    /// after receiving its first one, in order to batch them in the same payload.
+    ///
+    /// It is only used with the engine.io v4 protocol.
+    ///
+    /// Defaults to `None`: the payload is sent as soon as there is a packet to send.
+    pub flush_timeout: Option<Duration>,
+
+    /// The number of packets a polling payload encoder drains from the buffer
+    /// before yielding back to the runtime, so a flooded session cannot starve the other tasks.
+    ///
+    /// A value of 0 disables yielding.
+    ///
+    /// Defaults to 32 packets.
+    pub encoder_yield_interval: usize,
+
+    /// The maximum number of packets encoded in a single polling payload, independently of its size in bytes.
+    /// Once it is reached, the remaining packets are left in the buffer for the next polling request.
+    ///
+    /// A payload always contains at least one packet.
+    ///
+    /// Defaults to `None` (unlimited).
+    pub max_packets_per_payload: Option<usize>,
+
+    /// If enabled, the packets of a polling payload that could not be sent
+    /// (because the client dropped the connection before the response was written)
+    /// are pushed back in the socket buffer to be sent with the next polling request.
+    ///
+    /// This gives an *at-least-once* delivery instead of an *at-most-once* one:
+    /// as there is no way to know if a response was entirely received by the client,
+    /// some packets may be sent twice. It also requires to copy each sent packet.
+    ///
+    /// Defaults to `false`.
+    pub requeue_unsent_packets: bool,
+
+    /// If enabled, the engine.io v4 polling payloads are streamed packet by packet in a chunked response,
+    /// rather than being encoded in memory before being sent.
+    ///
+    /// The payloads are still limited to `max_payload` bytes. A payload is not streamed if it is compressed
+    /// or if [`requeue_unsent_packets`](Self::requeue_unsent_packets) is enabled, as both need the whole payload.
+    ///
+    /// Defaults to `false`.
+    pub stream_polling_payloads: bool,
+
+    /// What to do with a message packet that is too large to fit in a polling payload.
+    ///
+    /// It is only used with the engine.io v4 protocol.
+    ///
+    /// Defaults to [`OversizePolicy::Drop`].
+    pub oversize_policy: OversizePolicy,
+
+    /// Options for the engine.io v3 polling payload encoders.
+    ///
+    /// Defaults to the protocol values.
+    #[cfg(feature = "v3")]
+    pub v3_encoder: V3EncoderOptions,
+
+    /// The minimum size in bytes of a websocket message to compress it with the `permessage-deflate` extension,
+    /// if the client supports it.
+    ///
+    /// `None` disables the websocket compression: the extension is not negotiated.
+    ///
+    /// Defaults to 1024 bytes.
+    #[cfg(feature = "compression")]
+    pub ws_compression_threshold: Option<usize>,
+
+    /// The generator of the session ids.
+    ///
+    /// Defaults to the [`RandomSidGenerator`].
+    pub sid_generator: Arc<dyn SidGenerator>,
+
+    /// The websocket subprotocols supported by the server.
+    ///
+    /// The first subprotocol of the `Sec-WebSocket-Protocol` request header that is supported
+    /// is echoed in the upgrade response.
+    ///
+    /// Defaults to an empty list: the header is ignored.
+    pub ws_subprotocols: Vec<Cow<'static, str>>,
+
+    /// If enabled, the websocket connections that don't request any of the [`ws_subprotocols`](Self::ws_subprotocols)
+    /// are rejected with a `400 Bad Request` error.
+    ///
+    /// Defaults to `false`.
+    pub require_ws_subprotocol: bool,
+
+    /// The polling sessions shared between several nodes through a store,
+    /// see the [`store`](crate::store) module.
+    ///
+    /// Defaults to `None`: the requests of a polling session must be routed to the node that opened it.
+    #[cfg(feature = "shared-polling")]
+    pub shared_polling: Option<SharedPolling>,
+
+    /// The limit applied to the handshakes opening new sessions, for all the transports.
+    ///
+    /// Defaults to `None`: the handshakes are never limited.
+    pub handshake_limit: Option<HandshakeLimit>,
+
+    /// The circuit breaker disconnecting the unresponsive clients before the ping timeout.
+    ///
+    /// Defaults to `None`: a client is only disconnected once the ping timeout elapses.
+    pub circuit_breaker: Option<CircuitBreaker>,
+
+    /// The hook called with the headers of the handshake responses, see [`HandshakeResponseHook`].
+    ///
+    /// Defaults to `None`: the handshake responses are not modified.
+    pub handshake_response_hook: Option<HandshakeResponseHook>,
+}
+
+type BoxedHandshakeResponseHook = Arc<dyn Fn(&Parts, &mut HeaderMap) + Send + Sync + 'static>;
+
+/// A hook called with the request and the headers of each handshake response before it is sent,
+/// to add custom headers or cookies, e.g. a CSRF token.
+///
+/// It is called for the handshake of the polling sessions, for the handshake of the websocket sessions
+/// and for the response switching a polling session to websocket.
+/// The `Set-Cookie` header may already be set by the server, so the cookies should be added with [`HeaderMap::append`].
+#[derive(Clone)]
+pub struct HandshakeResponseHook(BoxedHandshakeResponseHook);
+
+impl HandshakeResponseHook {
+    /// Creates a new [`HandshakeResponseHook`] from a function
+    pub fn new(hook: impl Fn(&Parts, &mut HeaderMap) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn call(&self, req: &Parts, headers: &mut HeaderMap) {
+        (self.0)(req, headers)
+    }
+}
+
+impl std::fmt::Debug for HandshakeResponseHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandshakeResponseHook").finish()
+    }
+}
+
+/// The limit applied to the handshakes opening new sessions, enforced with a token bucket:
+/// `burst` handshakes can be accepted at once, then `handshakes_per_second` handshakes per second.
+///
+/// It protects the server and its downstream services from a storm of reconnections, e.g. after a deploy.
+/// A handshake exceeding the limit is queued if its turn comes within the [`max_queue_time`](Self::max_queue_time),
+/// otherwise it is rejected with a `503 Service Unavailable` response
+/// and a `Retry-After` header holding the number of seconds to wait before retrying.
+///
+/// The requests of the existing sessions, such as the websocket upgrades, are never limited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HandshakeLimit {
+    /// The number of handshakes accepted per second once the burst is consumed, it must be positive
+    pub handshakes_per_second: f64,
+    /// The maximum number of handshakes accepted at once
+    pub burst: u32,
+    /// The maximum time a handshake exceeding the limit waits for its turn before being rejected
+    pub max_queue_time: Duration,
+}
+
+impl HandshakeLimit {
+    /// Creates a new [`HandshakeLimit`] rejecting the handshakes exceeding the limit right away
+    pub fn new(handshakes_per_second: f64, burst: u32) -> Self {
+        Self {
+            handshakes_per_second,
+            burst,
+            max_queue_time: Duration::ZERO,
+        }
+    }
+
+    /// The maximum time a handshake exceeding the limit waits for its turn before being rejected.
+    ///
+    /// Defaults to 0: the handshakes exceeding the limit are rejected right away.
+    pub fn max_queue_time(mut self, max_queue_time: Duration) -> Self {
+        self.max_queue_time = max_queue_time;
+        self
+    }
+}
+
+/// A circuit breaker disconnecting the unresponsive clients early,
+/// with the [`DisconnectReason::UnresponsiveClient`](crate::socket::DisconnectReason::UnresponsiveClient) reason.
+///
+/// A ping that is not answered within the `pong_timeout` counts as a missed pong and a new ping is sent right away.
+/// The client is disconnected once it misses `max_missed_pongs` consecutive pongs,
+/// the [`ping_timeout`](EngineIoConfig::ping_timeout) still applies to each heartbeat cycle.
+/// Only the v4 protocol is concerned, with the v3 protocol the pings are sent by the client.
+///
+/// The breaker can also trip when too many bytes were emitted to the client since its last pong,
+/// see [`max_unacked_bytes`](Self::max_unacked_bytes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    /// The number of consecutive pongs a client can miss before being disconnected, it must be positive
+    pub max_missed_pongs: u32,
+    /// The time to wait for a pong before counting it as missed
+    pub pong_timeout: Duration,
+    /// The maximum number of bytes emitted to a client since its last pong
+    pub max_unacked_bytes: Option<u64>,
+}
+
+impl CircuitBreaker {
+    /// Creates a new [`CircuitBreaker`] disconnecting a client after `max_missed_pongs` consecutive pings
+    /// not answered within the `pong_timeout`.
+    ///
+    /// # Panics
+    /// If `max_missed_pongs` is 0
+    pub fn new(max_missed_pongs: u32, pong_timeout: Duration) -> Self {
+        assert!(max_missed_pongs > 0, "max_missed_pongs must be positive");
+        Self {
+            max_missed_pongs,
+            pong_timeout,
+            max_unacked_bytes: None,
+        }
+    }
+
+    /// The maximum number of bytes emitted to a client since its last pong.
+    /// The emit that exceeds it fails with a [`TrySendError::Full`](tokio::sync::mpsc::error::TrySendError::Full) error
+    /// and disconnects the client.
+    ///
+    /// A pong means that the client received everything emitted before its ping,
+    /// so the limit should be above the bytes normally emitted during a [`ping_interval`](EngineIoConfig::ping_interval).
+    ///
+    /// Defaults to `None`: the emitted bytes are not limited.
+    pub fn max_unacked_bytes(mut self, max_unacked_bytes: u64) -> Self {
+        self.max_unacked_bytes = Some(max_unacked_bytes);
+        self
+    }
+}
+
+/// The policy applied to a message packet that is too large to fit in a polling payload,
+/// even if it is alone in the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// The packet is dropped and the polling request fails with a `413 Payload Too Large` error.
+    #[default]
+    Drop,
+    /// The message is truncated on a UTF-8 char boundary so that it fits in the payload.
+    /// A multibyte char is never split.
+    ///
+    /// Binary packets are always dropped.
+    TruncateAtCharBoundary,
+}
+
+/// The policy applied when a packet is emitted to a socket whose buffer is full.
+///
+/// The buffer size is set with [`EngineIoConfig::max_buffer_size`].
+/// Only the packets emitted by the handler are concerned, the internal packets (e.g. ping packets) are never buffered over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// The packet is not buffered and the `emit()` method returns a [`TrySendError::Full`](tokio::sync::mpsc::error::TrySendError::Full) error.
+    #[default]
+    Error,
+    /// The session is closed with the [`DisconnectReason::SlowClient`](crate::socket::DisconnectReason::SlowClient) reason
+    /// and the `emit()` method returns a [`TrySendError::Full`](tokio::sync::mpsc::error::TrySendError::Full) error.
+    DisconnectSlowClient,
+    /// The oldest buffered message is dropped to make room for the new one.
+    ///
+    /// If the buffer is being drained at the same time, the `emit()` method returns an error as with [`OverflowPolicy::Error`].
+    DropOldest,
+    /// The [`emit_async()`](crate::socket::Socket::emit_async) and [`emit_binary_async()`](crate::socket::Socket::emit_binary_async)
+    /// methods wait until the buffer has room for the packet.
+    ///
+    /// The synchronous `emit()` methods cannot wait without blocking a thread of the runtime,
+    /// so they return a [`TrySendError::Full`](tokio::sync::mpsc::error::TrySendError::Full) error as with [`OverflowPolicy::Error`].
+    Block,
+}
+
+/// Options for the engine.io v3 polling payload encoders.
+///
+/// Only use this if a client or an intermediary does not follow the protocol (e.g. a proxy rewriting separators).
+#[cfg(feature = "v3")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V3EncoderOptions {
+    /// The separator between the length and the data of a packet in a *string* payload.
+    /// Defaults to `b':'`.
+    pub string_separator: u8,
+
+    /// The separator between the length and the data of a packet in a *binary* payload.
+    /// Defaults to `0xff`.
+    pub binary_separator: u8,
+
+    /// The number of digits reserved for the length of each packet when estimating the size of a payload.
+    /// Defaults to `None`: the number of digits of each packet length is used for binary payloads
+    /// and the number of digits of the `max_payload` for string payloads.
+    pub max_packet_size_len: Option<usize>,
+}
+
+#[cfg(feature = "v3")]
+impl Default for V3EncoderOptions {
+    fn default() -> Self {
+        use crate::transport::polling::payload::{
+            BINARY_PACKET_SEPARATOR_V3, STRING_PACKET_SEPARATOR_V3,
+        };
+        Self {
+            string_separator: STRING_PACKET_SEPARATOR_V3,
+            binary_separator: BINARY_PACKET_SEPARATOR_V3,
+            max_packet_size_len: None,
+        }
+    }
 }
 
 impl Default for EngineIoConfig {
@@ -71,8 +379,27 @@ impl Default for EngineIoConfig {
             ping_interval: Duration::from_millis(25000),
             ping_timeout: Duration::from_millis(20000),
             max_buffer_size: 128,
+            overflow_policy: OverflowPolicy::Error,
             max_payload: 1e5 as u64, // 100kb
             transports: TransportType::Polling as u8 | TransportType::Websocket as u8,
+            flush_timeout: None,
+            encoder_yield_interval: 32,
+            max_packets_per_payload: None,
+            requeue_unsent_packets: false,
+            stream_polling_payloads: false,
+            oversize_policy: OversizePolicy::Drop,
+            #[cfg(feature = "v3")]
+            v3_encoder: V3EncoderOptions::default(),
+            #[cfg(feature = "compression")]
+            ws_compression_threshold: Some(1024),
+            sid_generator: Arc::new(RandomSidGenerator),
+            ws_subprotocols: Vec::new(),
+            require_ws_subprotocol: false,
+            #[cfg(feature = "shared-polling")]
+            shared_polling: None,
+            handshake_limit: None,
+            circuit_breaker: None,
+            handshake_response_hook: None,
         }
     }
 }
@@ -103,7 +430,8 @@ impl EngineIoConfigBuilder {
         }
     }
 
-    /// The path to listen for engine.io requests on.
+    /// The path to listen for engine.io requests on, with its sub-paths.
+    /// A request path only sharing a prefix with it, such as `/engine.io2`, is not matched.
     /// Defaults to "/engine.io".
     pub fn req_path(mut self, req_path: impl Into<Cow<'static, str>>) -> Self {
         self.config.req_path = req_path.into();
@@ -163,7 +491,17 @@ impl EngineIoConfigBuilder {
         self
     }
 
+    /// What to do when a packet is emitted to a socket whose buffer is full,
+    /// because its client does not receive the packets fast enough.
+    ///
+    /// Defaults to [`OverflowPolicy::Error`].
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.config.overflow_policy = overflow_policy;
+        self
+    }
+
     /// The maximum number of bytes that can be received per http request.
+    /// It also limits the size of each websocket frame and message, a larger frame closes the connection.
     /// Defaults to 100kb.
     pub fn max_payload(mut self, max_payload: u64) -> Self {
         self.config.max_payload = max_payload;
@@ -172,12 +510,15 @@ impl EngineIoConfigBuilder {
 
     /// Allowed transports on this server
     ///
-    /// The `transports` array should have a size of 1 or 2
+    /// The `transports` array should have a size of 1 to 3.
+    /// [`TransportType::WebTransport`] must be allowed explicitly, the sessions are then served with
+    /// [`EngineIoService::on_webtransport`](crate::service::EngineIoService::on_webtransport)
+    /// and the polling sessions advertise it as an upgrade.
     ///
     /// Defaults to :
     /// `[TransportType::Polling, TransportType::Websocket]`
     pub fn transports<const N: usize>(mut self, transports: [TransportType; N]) -> Self {
-        assert!(N > 0 && N <= 2);
+        assert!(N > 0 && N <= 3);
         self.config.transports = 0;
         for transport in transports {
             self.config.transports |= transport as u8;
@@ -185,6 +526,166 @@ impl EngineIoConfigBuilder {
         self
     }
 
+    /// The amount of time a polling request will keep waiting for new packets
+    /// after receiving its first one, in order to batch them in the same payload.
+    ///
+    /// It is only used with the engine.io v4 protocol.
+    ///
+    /// Defaults to `None`: the payload is sent as soon as there is a packet to send.
+    pub fn flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.config.flush_timeout = Some(flush_timeout);
+        self
+    }
+
+    /// The number of packets a polling payload encoder drains from the buffer
+    /// before yielding back to the runtime, so a flooded session cannot starve the other tasks.
+    ///
+    /// A value of 0 disables yielding.
+    ///
+    /// Defaults to 32 packets.
+    pub fn encoder_yield_interval(mut self, encoder_yield_interval: usize) -> Self {
+        self.config.encoder_yield_interval = encoder_yield_interval;
+        self
+    }
+
+    /// The maximum number of packets encoded in a single polling payload, independently of its size in bytes.
+    /// Once it is reached, the remaining packets are left in the buffer for the next polling request.
+    ///
+    /// A payload always contains at least one packet.
+    ///
+    /// Defaults to unlimited.
+    pub fn max_packets_per_payload(mut self, max_packets_per_payload: usize) -> Self {
+        self.config.max_packets_per_payload = Some(max_packets_per_payload);
+        self
+    }
+
+    /// If enabled, the packets of a polling payload that could not be sent
+    /// (because the client dropped the connection before the response was written)
+    /// are pushed back in the socket buffer to be sent with the next polling request.
+    ///
+    /// This gives an *at-least-once* delivery instead of an *at-most-once* one:
+    /// as there is no way to know if a response was entirely received by the client,
+    /// some packets may be sent twice. It also requires to copy each sent packet.
+    ///
+    /// Defaults to `false`.
+    pub fn requeue_unsent_packets(mut self, requeue_unsent_packets: bool) -> Self {
+        self.config.requeue_unsent_packets = requeue_unsent_packets;
+        self
+    }
+
+    /// If enabled, the engine.io v4 polling payloads are streamed packet by packet in a chunked response,
+    /// rather than being encoded in memory before being sent.
+    ///
+    /// The payloads are still limited to `max_payload` bytes. A payload is not streamed if it is compressed
+    /// or if [`requeue_unsent_packets`](Self::requeue_unsent_packets) is enabled, as both need the whole payload.
+    ///
+    /// Defaults to `false`.
+    pub fn stream_polling_payloads(mut self, stream_polling_payloads: bool) -> Self {
+        self.config.stream_polling_payloads = stream_polling_payloads;
+        self
+    }
+
+    /// What to do with a message packet that is too large to fit in a polling payload.
+    ///
+    /// It is only used with the engine.io v4 protocol.
+    ///
+    /// Defaults to [`OversizePolicy::Drop`].
+    pub fn oversize_policy(mut self, oversize_policy: OversizePolicy) -> Self {
+        self.config.oversize_policy = oversize_policy;
+        self
+    }
+
+    /// Options for the engine.io v3 polling payload encoders.
+    ///
+    /// Defaults to the protocol values.
+    #[cfg(feature = "v3")]
+    pub fn v3_encoder(mut self, options: V3EncoderOptions) -> Self {
+        self.config.v3_encoder = options;
+        self
+    }
+
+    /// The minimum size in bytes of a websocket message to compress it with the `permessage-deflate` extension,
+    /// if the client supports it.
+    ///
+    /// `None` disables the websocket compression: the extension is not negotiated.
+    ///
+    /// Defaults to 1024 bytes.
+    #[cfg(feature = "compression")]
+    pub fn ws_compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.config.ws_compression_threshold = threshold;
+        self
+    }
+
+    /// The generator of the session ids, see [`SidGenerator`] for the requirements of the generated ids.
+    ///
+    /// Defaults to the [`RandomSidGenerator`].
+    pub fn sid_generator(mut self, generator: impl SidGenerator) -> Self {
+        self.config.sid_generator = Arc::new(generator);
+        self
+    }
+
+    /// The websocket subprotocols supported by the server.
+    ///
+    /// The first subprotocol of the `Sec-WebSocket-Protocol` request header that is supported
+    /// is echoed in the upgrade response.
+    ///
+    /// Defaults to an empty list: the header is ignored.
+    pub fn ws_subprotocols<I, S>(mut self, subprotocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Cow<'static, str>>,
+    {
+        self.config.ws_subprotocols = subprotocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// If enabled, the websocket connections that don't request any of the supported subprotocols
+    /// are rejected with a `400 Bad Request` error.
+    ///
+    /// Defaults to `false`.
+    pub fn require_ws_subprotocol(mut self, require_ws_subprotocol: bool) -> Self {
+        self.config.require_ws_subprotocol = require_ws_subprotocol;
+        self
+    }
+
+    /// Shares the polling sessions between several nodes through a store,
+    /// so that their requests can be served by any node. See the [`store`](crate::store) module.
+    ///
+    /// Defaults to `None`: the requests of a polling session must be routed to the node that opened it.
+    #[cfg(feature = "shared-polling")]
+    pub fn shared_polling(mut self, shared_polling: SharedPolling) -> Self {
+        self.config.shared_polling = Some(shared_polling);
+        self
+    }
+
+    /// The limit applied to the handshakes opening new sessions, see [`HandshakeLimit`].
+    ///
+    /// Defaults to `None`: the handshakes are never limited.
+    pub fn handshake_limit(mut self, handshake_limit: HandshakeLimit) -> Self {
+        self.config.handshake_limit = Some(handshake_limit);
+        self
+    }
+
+    /// The circuit breaker disconnecting the unresponsive clients before the ping timeout, see [`CircuitBreaker`].
+    ///
+    /// Defaults to `None`: a client is only disconnected once the ping timeout elapses.
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.config.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// The hook called with the request and the headers of each handshake response before it is sent,
+    /// see [`HandshakeResponseHook`].
+    ///
+    /// Defaults to `None`: the handshake responses are not modified.
+    pub fn handshake_response_hook(
+        mut self,
+        hook: impl Fn(&Parts, &mut HeaderMap) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.handshake_response_hook = Some(HandshakeResponseHook::new(hook));
+        self
+    }
+
     /// Build the config
     pub fn build(self) -> EngineIoConfig {
         self.config
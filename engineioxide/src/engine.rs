@@ -1,12 +1,15 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use http::request::Parts;
 
 use crate::{
-    config::EngineIoConfig,
+    config::{EngineIoConfig, HandshakeLimit},
+    errors::Error,
     handler::EngineIoHandler,
     service::TransportType,
     socket::{DisconnectReason, Socket},
@@ -15,6 +18,9 @@ use crate::{service::ProtocolVersion, sid::Sid};
 
 type SocketMap<T> = RwLock<HashMap<Sid, Arc<T>>>;
 
+/// The number of ids generated for a new session before falling back to a random one if they are all already used
+const SID_GENERATION_ATTEMPTS: usize = 8;
+
 /// The [`EngineIo`] struct holds the state of the engine.io server as well as utility methods to manage the state
 pub struct EngineIo<H: EngineIoHandler> {
     /// A map of all the sockets connected to the server
@@ -25,15 +31,20 @@ pub struct EngineIo<H: EngineIoHandler> {
 
     /// The config for the engine.io server
     pub config: EngineIoConfig,
+
+    /// The token bucket enforcing the [`HandshakeLimit`] of the config
+    handshake_bucket: Mutex<HandshakeBucket>,
 }
 
 impl<H: EngineIoHandler> EngineIo<H> {
     /// Create a new Engine.IO server with a [`EngineIoHandler`] and a [`EngineIoConfig`]
     pub fn new(handler: H, config: EngineIoConfig) -> Self {
+        let burst = config.handshake_limit.map(|l| l.burst).unwrap_or_default();
         Self {
             sockets: RwLock::new(HashMap::new()),
             config,
             handler,
+            handshake_bucket: Mutex::new(HandshakeBucket::new(burst)),
         }
     }
 }
@@ -50,7 +61,11 @@ impl<H: EngineIoHandler> EngineIo<H> {
         let engine = self.clone();
         let close_fn = Box::new(move |sid, reason| engine.close_session(sid, reason));
 
-        let socket = Socket::new(
+        let heartbeat = self.handler.heartbeat(&req);
+        // The lock is held until the socket is inserted so that a generated id cannot be used twice
+        let mut sockets = self.sockets.write().unwrap();
+        let mut socket = Socket::new(
+            self.generate_sid(&sockets),
             protocol,
             transport,
             &self.config,
@@ -59,15 +74,51 @@ impl<H: EngineIoHandler> EngineIo<H> {
             #[cfg(feature = "v3")]
             supports_binary,
         );
+        if let Some((ping_interval, ping_timeout)) = heartbeat {
+            socket.ping_interval = ping_interval;
+            socket.ping_timeout = ping_timeout;
+        }
         let socket = Arc::new(socket);
-        self.sockets
-            .write()
-            .unwrap()
-            .insert(socket.id, socket.clone());
+        sockets.insert(socket.id, socket.clone());
+        drop(sockets);
         self.handler.on_connect(socket.clone());
         socket
     }
 
+    /// Generates the id of a new session with the [`SidGenerator`](crate::sid::SidGenerator) of the config
+    fn generate_sid(&self, sockets: &HashMap<Sid, Arc<Socket<H::Data>>>) -> Sid {
+        for _ in 0..SID_GENERATION_ATTEMPTS {
+            match Sid::from_str(&self.config.sid_generator.generate()) {
+                Ok(sid) if !sockets.contains_key(&sid) => return sid,
+                Ok(_sid) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("generated sid {_sid} is already used");
+                }
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!("invalid generated sid: {_e}");
+                    break;
+                }
+            }
+        }
+        Sid::new()
+    }
+
+    /// Reserves the turn of a new handshake according to the [`HandshakeLimit`] of the config.
+    ///
+    /// Returns the time to wait before handling the handshake, or a [`Error::HandshakeLimitExceeded`] error
+    /// with the time after which the client should retry if it is longer than the max queue time.
+    pub(crate) fn admit_handshake(&self) -> Result<Duration, Error> {
+        match &self.config.handshake_limit {
+            Some(limit) => self
+                .handshake_bucket
+                .lock()
+                .unwrap()
+                .reserve(limit, Instant::now()),
+            None => Ok(Duration::ZERO),
+        }
+    }
+
     /// Get a socket by its sid
     /// Clones the socket ref to avoid holding the lock
     pub fn get_socket(&self, sid: Sid) -> Option<Arc<Socket<H::Data>>> {
@@ -93,6 +144,49 @@ impl<H: EngineIoHandler> EngineIo<H> {
     }
 }
 
+/// A token bucket limiting the handshakes of the new sessions.
+///
+/// The queued handshakes take their token in advance, so the number of tokens is negative
+/// when handshakes are waiting for their turn.
+#[derive(Debug)]
+struct HandshakeBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl HandshakeBucket {
+    fn new(burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket according to the elapsed time, then takes a token
+    /// if it is available within the max queue time and returns the time to wait for it
+    fn reserve(&mut self, limit: &HandshakeLimit, now: Instant) -> Result<Duration, Error> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = elapsed
+            .mul_add(limit.handshakes_per_second, self.tokens)
+            .min(limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(Duration::ZERO);
+        }
+        let wait = Duration::try_from_secs_f64((1.0 - self.tokens) / limit.handshakes_per_second)
+            .unwrap_or(Duration::MAX);
+        if wait <= limit.max_queue_time {
+            self.tokens -= 1.0;
+            Ok(wait)
+        } else {
+            Err(Error::HandshakeLimitExceeded(wait))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use http::Request;
@@ -140,6 +234,59 @@ mod tests {
         assert!(socket.is_http());
     }
 
+    /// Generates `shard-a-` followed by a counter, the counter being reset after 2 ids
+    #[derive(Debug, Default)]
+    struct ShardSidGenerator(std::sync::atomic::AtomicUsize);
+    impl crate::sid::SidGenerator for ShardSidGenerator {
+        fn generate(&self) -> String {
+            let i = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            format!("shard-a-{:08}", i % 2)
+        }
+    }
+
+    #[derive(Debug)]
+    struct InvalidSidGenerator;
+    impl crate::sid::SidGenerator for InvalidSidGenerator {
+        fn generate(&self) -> String {
+            "not a valid sid!".into()
+        }
+    }
+
+    fn new_session(engine: &Arc<EngineIo<MockHandler>>) -> Arc<Socket<()>> {
+        engine.create_session(
+            ProtocolVersion::V4,
+            TransportType::Polling,
+            Request::<()>::default().into_parts().0,
+            #[cfg(feature = "v3")]
+            true,
+        )
+    }
+
+    #[tokio::test]
+    async fn custom_sid_generator() {
+        let config = EngineIoConfig::builder()
+            .sid_generator(ShardSidGenerator::default())
+            .build();
+        let engine = Arc::new(EngineIo::new(MockHandler, config));
+        assert_eq!(new_session(&engine).id.to_string(), "shard-a-00000000");
+        assert_eq!(new_session(&engine).id.to_string(), "shard-a-00000001");
+
+        // All the generated ids are then already used, a random id is used after a few attempts
+        let socket = new_session(&engine);
+        assert!(!socket.id.to_string().starts_with("shard-a-"));
+    }
+
+    #[tokio::test]
+    async fn invalid_generated_sid() {
+        let config = EngineIoConfig::builder()
+            .sid_generator(InvalidSidGenerator)
+            .build();
+        let engine = Arc::new(EngineIo::new(MockHandler, config));
+        let socket = new_session(&engine);
+        assert_eq!(socket.id.to_string().len(), 16);
+        assert_ne!(socket.id.to_string(), "not a valid sid!");
+    }
+
     #[tokio::test]
     async fn close_session() {
         let config = EngineIoConfig::default();
@@ -172,4 +319,26 @@ mod tests {
         assert_eq!(socket.protocol, ProtocolVersion::V4);
         assert!(socket.is_http());
     }
+
+    #[test]
+    fn handshake_bucket() {
+        let limit = HandshakeLimit::new(10.0, 2).max_queue_time(Duration::from_millis(150));
+        let start = Instant::now();
+        let mut bucket = HandshakeBucket::new(limit.burst);
+        let ms = |ms: u64| Duration::from_millis(ms);
+        assert_eq!(bucket.reserve(&limit, start).unwrap(), Duration::ZERO);
+        assert_eq!(bucket.reserve(&limit, start).unwrap(), Duration::ZERO);
+        // The next tokens are refilled every 100ms
+        assert_eq!(bucket.reserve(&limit, start).unwrap(), ms(100));
+        match bucket.reserve(&limit, start) {
+            Err(Error::HandshakeLimitExceeded(retry_after)) => assert_eq!(retry_after, ms(200)),
+            res => panic!("unexpected result: {res:?}"),
+        }
+        // The queued handshake took the token refilled at 100ms
+        assert_eq!(bucket.reserve(&limit, start + ms(100)).unwrap(), ms(100));
+        assert_eq!(
+            bucket.reserve(&limit, start + ms(500)).unwrap(),
+            Duration::ZERO
+        );
+    }
 }
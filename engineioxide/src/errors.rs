@@ -1,4 +1,6 @@
-use http::{Response, StatusCode};
+use std::time::Duration;
+
+use http::{header, Response, StatusCode};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite;
 
@@ -7,6 +9,7 @@ use crate::packet::Packet;
 use crate::sid::Sid;
 
 #[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("error serializing json packet: {0:?}")]
     Serialize(#[from] serde_json::Error),
@@ -28,6 +31,8 @@ pub enum Error {
     RecvChannel(#[from] mpsc::error::TryRecvError),
     #[error("heartbeat timeout")]
     HeartbeatTimeout,
+    #[error("unresponsive client")]
+    UnresponsiveClient,
     #[error("upgrade error")]
     UpgradeError,
     #[error("aborted connection")]
@@ -40,8 +45,12 @@ pub enum Error {
     UnknownSessionID(Sid),
     #[error("transport mismatch")]
     TransportMismatch,
-    #[error("payload too large")]
-    PayloadTooLarge,
+    #[error("packet variant does not match the protocol version of the encoder")]
+    ProtocolMismatch,
+    #[error("payload too large: {size} bytes, max is {max} bytes")]
+    PayloadTooLarge { size: u64, max: u64 },
+    #[error("handshake limit exceeded, retry after {0:?}")]
+    HandshakeLimitExceeded(Duration),
 
     #[error("Invalid packet length")]
     InvalidPacketLength,
@@ -72,11 +81,21 @@ impl<B> From<Error> for Response<ResponseBody<B>> {
                     .body(ResponseBody::empty_response())
                     .unwrap()
             }
-            Error::PayloadTooLarge => Response::builder()
+            Error::PayloadTooLarge { .. } => Response::builder()
                 .status(413)
                 .body(ResponseBody::empty_response())
                 .unwrap(),
 
+            // The delay is rounded up to the next second as the header doesn't support fractions
+            Error::HandshakeLimitExceeded(retry_after) => Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(
+                    header::RETRY_AFTER,
+                    retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64,
+                )
+                .body(ResponseBody::empty_response())
+                .unwrap(),
+
             Error::UnknownSessionID(_) => {
                 conn_err_resp("{\"code\":\"1\",\"message\":\"Session ID unknown\"}")
             }
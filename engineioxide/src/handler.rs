@@ -38,7 +38,9 @@
 //! // Create an engine io service with the given handler
 //! let svc = EngineIoService::new(MyHandler::default());
 //! ```
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+use http::request::Parts;
 
 use crate::socket::{DisconnectReason, Socket};
 
@@ -60,6 +62,19 @@ pub trait EngineIoHandler: std::fmt::Debug + Send + Sync + 'static {
 
     /// Called when a binary message is received from the client.
     fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<Self::Data>>);
+
+    /// Called when the transport of a socket is upgraded from polling to websocket.
+    fn on_upgrade(&self, _socket: Arc<Socket<Self::Data>>) {}
+
+    /// Called before creating a new session to override its heartbeat parameters.
+    ///
+    /// It returns the `(ping_interval, ping_timeout)` to use for this session instead of the
+    /// [`EngineIoConfig`](crate::config::EngineIoConfig) ones. They are sent to the client in the handshake.
+    ///
+    /// By default, the config values are used.
+    fn heartbeat(&self, _req: &Parts) -> Option<(Duration, Duration)> {
+        None
+    }
 }
 
 impl<T: EngineIoHandler> EngineIoHandler for Arc<T> {
@@ -80,4 +95,12 @@ impl<T: EngineIoHandler> EngineIoHandler for Arc<T> {
     fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<Self::Data>>) {
         (**self).on_binary(data, socket)
     }
+
+    fn on_upgrade(&self, socket: Arc<Socket<Self::Data>>) {
+        (**self).on_upgrade(socket)
+    }
+
+    fn heartbeat(&self, req: &Parts) -> Option<(Duration, Duration)> {
+        (**self).heartbeat(req)
+    }
 }
@@ -2,7 +2,7 @@
 #![warn(
     clippy::all,
     clippy::todo,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::mem_forget,
     clippy::unused_self,
     clippy::filter_map_next,
@@ -10,9 +10,7 @@
     clippy::needless_borrow,
     clippy::match_wildcard_for_single_variants,
     clippy::if_let_mutex,
-    clippy::mismatched_target_os,
     clippy::await_holding_lock,
-    clippy::match_on_vec_items,
     clippy::imprecise_flops,
     clippy::suboptimal_flops,
     clippy::lossy_float_literal,
@@ -30,13 +28,19 @@
     nonstandard_style,
     missing_docs
 )]
+// The tungstenite error carried by `Error::WsTransport` is large but boxing it would break the public API
+#![allow(clippy::result_large_err)]
 #![doc = include_str!("../Readme.md")]
 
 pub use service::{ProtocolVersion, TransportType};
-pub use socket::{DisconnectReason, Socket};
+pub use socket::{CloseFrame, DisconnectReason, Socket};
 
 #[cfg(feature = "test-utils")]
 pub use packet::*;
+#[cfg(feature = "test-utils")]
+pub use transport::polling::payload::{decode_v4_payload, encode_v4_payload};
+#[cfg(all(feature = "test-utils", feature = "v3"))]
+pub use transport::polling::payload::{v3_bin_packet_encoder, v3_bin_packet_encoder_with_buf};
 
 pub mod config;
 pub mod handler;
@@ -44,6 +48,9 @@ pub mod layer;
 pub mod service;
 pub mod sid;
 pub mod socket;
+#[cfg(feature = "shared-polling")]
+#[cfg_attr(docsrs, doc(cfg(feature = "shared-polling")))]
+pub mod store;
 
 mod body;
 mod engine;
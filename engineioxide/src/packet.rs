@@ -1,9 +1,12 @@
+use std::borrow::Cow;
+
 use base64::{engine::general_purpose, Engine};
 use serde::Serialize;
 
 use crate::config::EngineIoConfig;
 use crate::errors::Error;
 use crate::sid::Sid;
+use crate::socket::Socket;
 use crate::TransportType;
 
 /// A Packet type to use when receiving and sending data from the client
@@ -26,7 +29,10 @@ pub enum Packet {
     PongUpgrade,
 
     /// Message packet used to send a message to the client
-    Message(String),
+    ///
+    /// The message can be borrowed from a `&'static str` to avoid allocating a new [`String`]
+    /// before encoding it
+    Message(Cow<'static, str>),
     /// Upgrade packet to upgrade the connection from polling to websocket
     Upgrade,
 
@@ -59,7 +65,7 @@ impl Packet {
     /// If the packet is a message packet (text), it returns the message
     pub(crate) fn into_message(self) -> String {
         match self {
-            Packet::Message(msg) => msg,
+            Packet::Message(msg) => msg.into_owned(),
             _ => panic!("Packet is not a message"),
         }
     }
@@ -107,12 +113,12 @@ impl Packet {
     }
 }
 
-/// Serialize a [Packet] to a [String] according to the Engine.IO protocol
-impl TryInto<String> for Packet {
-    type Error = Error;
-    fn try_into(self) -> Result<String, Self::Error> {
-        let len = self.get_size_hint(true);
-        let mut buffer = String::with_capacity(len);
+impl Packet {
+    /// Serialize the packet according to the Engine.IO protocol and append it to the given buffer
+    ///
+    /// It allows to reuse the same buffer allocation when serializing multiple packets
+    pub(crate) fn encode_into(self, buffer: &mut String) -> Result<(), Error> {
+        buffer.reserve(self.get_size_hint(true));
         match self {
             Packet::Open(open) => {
                 buffer.push('0');
@@ -131,13 +137,23 @@ impl TryInto<String> for Packet {
             Packet::Noop => buffer.push('6'),
             Packet::Binary(data) => {
                 buffer.push('b');
-                general_purpose::STANDARD.encode_string(data, &mut buffer);
+                general_purpose::STANDARD.encode_string(data, buffer);
             }
             Packet::BinaryV3(data) => {
                 buffer.push_str("b4");
-                general_purpose::STANDARD.encode_string(data, &mut buffer);
+                general_purpose::STANDARD.encode_string(data, buffer);
             }
         };
+        Ok(())
+    }
+}
+
+/// Serialize a [Packet] to a [String] according to the Engine.IO protocol
+impl TryInto<String> for Packet {
+    type Error = Error;
+    fn try_into(self) -> Result<String, Self::Error> {
+        let mut buffer = String::new();
+        self.encode_into(&mut buffer)?;
         Ok(buffer)
     }
 }
@@ -156,13 +172,13 @@ impl TryFrom<&str> for Packet {
             b'2' => Packet::Ping,
             b'3' if is_upgrade => Packet::PongUpgrade,
             b'3' => Packet::Pong,
-            b'4' => Packet::Message(value[1..].to_string()),
+            b'4' => Packet::Message(value[1..].to_string().into()),
             b'5' => Packet::Upgrade,
             b'6' => Packet::Noop,
             b'b' if value.as_bytes().get(1) == Some(&b'4') => {
-                Packet::BinaryV3(general_purpose::STANDARD.decode(value[2..].as_bytes())?)
+                Packet::BinaryV3(general_purpose::STANDARD.decode(&value.as_bytes()[2..])?)
             }
-            b'b' => Packet::Binary(general_purpose::STANDARD.decode(value[1..].as_bytes())?),
+            b'b' => Packet::Binary(general_purpose::STANDARD.decode(&value.as_bytes()[1..])?),
             c => Err(Error::InvalidPacketType(Some(*c as char)))?,
         };
         Ok(res)
@@ -189,13 +205,18 @@ pub struct OpenPacket {
 
 impl OpenPacket {
     /// Create a new [OpenPacket]
-    /// If the current transport is polling, the server will always allow the client to upgrade to websocket
+    /// If the current transport is polling, the server will always allow the client to upgrade to websocket,
+    /// and to webtransport if it is allowed with the `webtransport` feature
     pub fn new(transport: TransportType, sid: Sid, config: &EngineIoConfig) -> Self {
-        let upgrades = if transport == TransportType::Polling {
-            vec!["websocket".to_string()]
-        } else {
-            vec![]
-        };
+        let mut upgrades = vec![];
+        if transport == TransportType::Polling {
+            upgrades.push("websocket".to_string());
+            if cfg!(feature = "webtransport")
+                && config.allowed_transport(TransportType::WebTransport)
+            {
+                upgrades.push("webtransport".to_string());
+            }
+        }
         OpenPacket {
             sid,
             upgrades,
@@ -204,6 +225,25 @@ impl OpenPacket {
             max_payload: config.max_payload,
         }
     }
+
+    /// Removes the transports advertised for an upgrade
+    #[cfg(feature = "shared-polling")]
+    pub(crate) fn without_upgrades(mut self) -> Self {
+        self.upgrades.clear();
+        self
+    }
+
+    /// Overrides the config values with the ones of the session:
+    /// the heartbeat parameters and the `maxPayload` enforced by the polling encoder
+    pub(crate) fn with_session<D>(mut self, socket: &Socket<D>) -> Self
+    where
+        D: Default + Send + Sync + 'static,
+    {
+        self.ping_interval = socket.ping_interval.as_millis() as u64;
+        self.ping_timeout = socket.ping_timeout.as_millis() as u64;
+        self.max_payload = socket.max_payload();
+        self
+    }
 }
 
 #[cfg(test)]
@@ -232,6 +272,14 @@ mod tests {
         assert_eq!(packet_str, "4hello");
     }
 
+    #[test]
+    fn test_borrowed_message_packet() {
+        let packet = Packet::Message(Cow::Borrowed("hello"));
+        assert_eq!(packet, Packet::Message("hello".to_string().into()));
+        let packet_str: String = packet.try_into().unwrap();
+        assert_eq!(packet_str, "4hello");
+    }
+
     #[test]
     fn test_message_packet_deserialize() {
         let packet_str = "4hello".to_string();
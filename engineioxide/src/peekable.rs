@@ -1,4 +1,18 @@
-use tokio::sync::mpsc::{error::TryRecvError, Receiver};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::{self, Either};
+use tokio::sync::{
+    mpsc::{
+        error::{TryRecvError, TrySendError},
+        OwnedPermit, Receiver, Sender, WeakSender,
+    },
+    Notify,
+};
+
+use crate::packet::Packet;
 
 /// Peekable receiver for polling transport
 /// It is a thin wrapper around a [`Receiver`](tokio::sync::mpsc::Receiver) that allows to peek the next packet without consuming it
@@ -8,46 +22,308 @@ use tokio::sync::mpsc::{error::TryRecvError, Receiver};
 #[derive(Debug)]
 pub struct PeekableReceiver<T> {
     rx: Receiver<T>,
-    next: Option<T>,
+    /// Values received from the channel but not consumed yet, in order
+    next: VecDeque<T>,
+    /// Cached size hint of the first peeked value with the `b64` flag used to compute it
+    next_size_hint: Option<(bool, usize)>,
+    /// Index of the peeked value being measured, it is only set if the measure panicked
+    processing: Option<usize>,
+    /// Copy of the values consumed since the journal was started
+    journal: Option<Vec<T>>,
+    /// Values pushed back from outside of the receiver, received before the other values
+    requeued: Arc<Requeued<T>>,
+    /// A sender of the channel used to keep the slots of the peeked values reserved,
+    /// see [`reserve_peeked_slots`](Self::reserve_peeked_slots)
+    tx: Option<WeakSender<T>>,
+    /// The channel slots reserved for the peeked values, there is never more permits than peeked values
+    permits: Vec<OwnedPermit<T>>,
+    /// Set once the receiver is [closed](Self::close)
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Requeued<T> {
+    values: Mutex<VecDeque<T>>,
+    notify: Notify,
+}
+
+/// A handle to push back values at the head of a [`PeekableReceiver`] without locking it
+///
+/// The values are pushed in a side queue that is drained by the receiver before any other value,
+/// so it cannot fail even if the receiver is being used at the same time.
+#[derive(Debug)]
+pub struct RequeueHandle<T>(Arc<Requeued<T>>);
+impl<T> RequeueHandle<T> {
+    /// Push back the values so that they are received before the values still in the receiver, in the given order
+    pub fn requeue(&self, values: Vec<T>) {
+        self.0.values.lock().unwrap().extend(values);
+        self.0.notify.notify_one();
+    }
 }
-impl<T> PeekableReceiver<T> {
+impl<T: Clone> PeekableReceiver<T> {
     pub fn new(rx: Receiver<T>) -> Self {
-        Self { rx, next: None }
+        Self {
+            rx,
+            next: VecDeque::new(),
+            next_size_hint: None,
+            processing: None,
+            journal: None,
+            requeued: Arc::new(Requeued {
+                values: Mutex::new(VecDeque::new()),
+                notify: Notify::new(),
+            }),
+            tx: None,
+            permits: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Keep a slot of the channel reserved for each peeked value until it is consumed.
+    ///
+    /// Otherwise the slot of a peeked value is freed as soon as it is peeked,
+    /// and the channel with the peeked values can hold more values than its capacity.
+    /// With it, the capacity of the channel also accounts for the peeked values.
+    pub fn reserve_peeked_slots(mut self, tx: &Sender<T>) -> Self {
+        self.tx = Some(tx.downgrade());
+        self
     }
     pub fn peek(&mut self) -> Option<&T> {
-        if self.next.is_none() {
-            self.next = self.rx.try_recv().ok();
+        self.drain_requeued();
+        if self.next.is_empty() {
+            if let Ok(value) = self.rx.try_recv() {
+                self.buffer(value);
+            }
         }
-        self.next.as_ref()
+        self.next.front()
     }
-    pub async fn recv(&mut self) -> Option<T> {
-        if self.next.is_none() {
-            self.rx.recv().await
-        } else {
-            self.next.take()
+    /// Peek up to `n` values without consuming them.
+    ///
+    /// The returned slice can be shorter than `n` if there are not enough values in the channel.
+    /// The peeked values are still delivered in order by subsequent calls to `peek` and `recv`.
+    pub fn peek_many(&mut self, n: usize) -> &[T] {
+        self.drain_requeued();
+        while self.next.len() < n {
+            match self.rx.try_recv() {
+                Ok(value) => self.buffer(value),
+                Err(_) => break,
+            }
         }
+        let len = self.next.len().min(n);
+        &self.next.make_contiguous()[..len]
+    }
+    /// Wait for the next value to be available and peek it without consuming it
+    ///
+    /// It returns `None` if the channel is closed
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe: if it is cancelled, no value is lost.
+    pub async fn peek_wait(&mut self) -> Option<&T> {
+        self.wait_next().await;
+        self.next.front()
+    }
+    /// Receive the next value, waiting for it if there is none.
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe: a value received while waiting is buffered before being consumed,
+    /// so if it is cancelled no value is lost.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.wait_next().await;
+        let value = self.pop_next();
+        self.record(value.as_ref());
+        value
     }
     pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
-        if self.next.is_none() {
-            self.rx.try_recv()
-        } else {
-            Ok(self.next.take().unwrap())
+        self.drain_requeued();
+        let value = match self.pop_next() {
+            Some(value) => Ok(value),
+            None => self.rx.try_recv(),
+        };
+        self.record(value.as_ref().ok());
+        value
+    }
+
+    /// Get a [`RequeueHandle`] to push back values at the head of the receiver without locking it
+    pub fn requeue_handle(&self) -> RequeueHandle<T> {
+        RequeueHandle(self.requeued.clone())
+    }
+
+    /// Buffer a value received from the channel with the peeked values and reserve its slot
+    fn buffer(&mut self, value: T) {
+        let permit = self
+            .tx
+            .as_ref()
+            .and_then(WeakSender::upgrade)
+            .and_then(|tx| tx.try_reserve_owned().ok());
+        self.permits.extend(permit);
+        self.next.push_back(value);
+    }
+
+    /// Consume the first peeked value and release its slot
+    fn pop_next(&mut self) -> Option<T> {
+        let value = self.next.pop_front()?;
+        self.next_size_hint = None;
+        self.permits.truncate(self.next.len());
+        Some(value)
+    }
+
+    /// Move the requeued values at the head of the peeked values
+    fn drain_requeued(&mut self) {
+        let mut requeued = self.requeued.values.lock().unwrap();
+        if !requeued.is_empty() {
+            self.next_size_hint = None;
+            for value in requeued.drain(..).rev() {
+                self.next.push_front(value);
+            }
+        }
+    }
+
+    /// Wait for a value to be available, either in the channel or requeued, and buffer it with the peeked values
+    async fn wait_next(&mut self) {
+        loop {
+            self.drain_requeued();
+            if !self.next.is_empty() {
+                return;
+            }
+            let value = {
+                let notified = self.requeued.notify.notified();
+                let recv = self.rx.recv();
+                futures::pin_mut!(notified, recv);
+                match future::select(recv, notified).await {
+                    Either::Left((value, _)) => value,
+                    Either::Right(_) => continue,
+                }
+            };
+            if let Some(value) = value {
+                self.buffer(value);
+            }
+            return;
+        }
+    }
+
+    /// Drop the oldest value matching the predicate, either peeked or still in the channel,
+    /// and send the given value to the channel with `tx`.
+    ///
+    /// It allows to add a value to a full channel without exceeding its capacity.
+    /// The values preceding the dropped one are moved to the peeked values so that the order is kept
+    /// and no other value is dropped.
+    ///
+    /// If no value matches, a [`TrySendError::Full`] error is returned.
+    /// If another sender takes the freed slot in the meantime, the oldest value is still dropped
+    /// and a [`TrySendError::Full`] error is returned.
+    ///
+    /// The [peeked slots](Self::reserve_peeked_slots) should be reserved,
+    /// otherwise dropping a peeked value doesn't free a slot in the channel.
+    pub fn send_dropping_oldest(
+        &mut self,
+        tx: &Sender<T>,
+        value: T,
+        predicate: impl Fn(&T) -> bool,
+    ) -> Result<(), TrySendError<T>> {
+        self.drain_requeued();
+        let index = match self.next.iter().position(&predicate) {
+            Some(index) => index,
+            None => loop {
+                match self.rx.try_recv() {
+                    Ok(queued) => {
+                        let matches = predicate(&queued);
+                        self.buffer(queued);
+                        if matches {
+                            break self.next.len() - 1;
+                        }
+                    }
+                    Err(_) => return Err(TrySendError::Full(value)),
+                }
+            },
+        };
+        if index == 0 {
+            self.next_size_hint = None;
+        }
+        self.next.remove(index);
+        self.permits.truncate(self.next.len());
+        tx.try_send(value)
+    }
+
+    /// Start recording a copy of every consumed value, until the journal is [taken](Self::take_journal).
+    ///
+    /// It allows to [requeue](RequeueHandle::requeue) the consumed values if they could not be processed.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stop recording the consumed values and return them in the order they were received
+    pub fn take_journal(&mut self) -> Vec<T> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    fn record(&mut self, value: Option<&T>) {
+        if let (Some(journal), Some(value)) = (self.journal.as_mut(), value) {
+            journal.push(value.clone());
         }
     }
 
     pub fn close(&mut self) {
+        self.closed = true;
         self.rx.close()
     }
+
+    /// Check if the receiver was [closed](Self::close), e.g. after a close packet was received
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Reset the receiver into a known-good state after a panic while its values were processed.
+    ///
+    /// If the panic occurred while a peeked value was being [measured](PeekableReceiver::peeked_size_hint),
+    /// this value is discarded and the other peeked values are kept in order.
+    /// A value that was already consumed is not in the receiver anymore, so if the panic occurred
+    /// while processing it, nothing is discarded.
+    pub fn reset(&mut self) {
+        self.next_size_hint = None;
+        if let Some(index) = self.processing.take() {
+            self.next.remove(index);
+            self.permits.truncate(self.next.len());
+        }
+    }
+}
+
+impl PeekableReceiver<Packet> {
+    /// Peek the next packet and get its [size hint](Packet::get_size_hint).
+    ///
+    /// The size hint is computed only once per peeked packet
+    /// so that a packet peeked many times is not measured again.
+    pub fn peek_size_hint(&mut self, b64: bool) -> Option<usize> {
+        self.peek()?;
+        self.peeked_size_hint(0, b64)
+    }
+
+    /// Get the [size hint](Packet::get_size_hint) of the already peeked packet at `index`.
+    ///
+    /// The packet is recorded as being processed while it is measured,
+    /// so that it is the one discarded by [`reset`](PeekableReceiver::reset) if the measure panics.
+    pub fn peeked_size_hint(&mut self, index: usize, b64: bool) -> Option<usize> {
+        match self.next_size_hint {
+            Some((cached_b64, size)) if index == 0 && cached_b64 == b64 => return Some(size),
+            _ => (),
+        }
+        let packet = self.next.get(index)?;
+        self.processing = Some(index);
+        let size = packet.get_size_hint(b64);
+        self.processing = None;
+        if index == 0 {
+            self.next_size_hint = Some((b64, size));
+        }
+        Some(size)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::packet::Packet;
     use tokio::sync::Mutex;
 
     #[tokio::test]
     async fn peek() {
         use super::PeekableReceiver;
-        use crate::packet::Packet;
         use tokio::sync::mpsc::channel;
 
         let (tx, rx) = channel(1);
@@ -76,4 +352,209 @@ mod tests {
         assert_eq!(rx.recv().await, Some(Packet::Close));
         assert!(rx.peek().is_none());
     }
+
+    #[tokio::test]
+    async fn peek_size_hint() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(2);
+        let rx = Mutex::new(PeekableReceiver::new(rx));
+        let mut rx = rx.lock().await;
+
+        assert_eq!(rx.peek_size_hint(true), None);
+
+        tx.send(Packet::Binary(vec![1, 2, 3])).await.unwrap();
+        tx.send(Packet::Message("hello".into())).await.unwrap();
+        assert_eq!(rx.peek_size_hint(true), Some(5));
+        assert_eq!(rx.next_size_hint, Some((true, 5)));
+        assert_eq!(rx.peek_size_hint(true), Some(5));
+        assert_eq!(rx.peek_size_hint(false), Some(4));
+
+        // The cached size hint is reset with the next packet
+        assert_eq!(rx.recv().await, Some(Packet::Binary(vec![1, 2, 3])));
+        assert_eq!(rx.next_size_hint, None);
+        assert_eq!(rx.peek_size_hint(true), Some(6));
+    }
+
+    #[tokio::test]
+    async fn peek_many() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(10);
+        let rx = Mutex::new(PeekableReceiver::new(rx));
+        let mut rx = rx.lock().await;
+
+        assert!(rx.peek_many(3).is_empty());
+
+        tx.send(Packet::Ping).await.unwrap();
+        tx.send(Packet::Pong).await.unwrap();
+        assert_eq!(rx.peek(), Some(&Packet::Ping));
+        assert_eq!(rx.peek_many(3), &[Packet::Ping, Packet::Pong]);
+
+        tx.send(Packet::Noop).await.unwrap();
+        tx.send(Packet::Close).await.unwrap();
+        assert_eq!(rx.peek_many(3), &[Packet::Ping, Packet::Pong, Packet::Noop]);
+        assert_eq!(rx.peek_many(1), &[Packet::Ping]);
+
+        // Buffered packets are still delivered in order
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.peek(), Some(&Packet::Pong));
+        assert_eq!(rx.try_recv().unwrap(), Packet::Pong);
+        assert_eq!(rx.recv().await, Some(Packet::Noop));
+        assert_eq!(rx.recv().await, Some(Packet::Close));
+        assert!(rx.peek().is_none());
+    }
+
+    #[tokio::test]
+    async fn reset() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(10);
+        let rx = Mutex::new(PeekableReceiver::new(rx));
+        let mut rx = rx.lock().await;
+
+        tx.send(Packet::Ping).await.unwrap();
+        tx.send(Packet::Pong).await.unwrap();
+        tx.send(Packet::Noop).await.unwrap();
+        assert_eq!(rx.peek_many(3).len(), 3);
+
+        // Nothing is discarded if no packet was being measured
+        rx.reset();
+        assert_eq!(rx.peek_many(3), &[Packet::Ping, Packet::Pong, Packet::Noop]);
+
+        // Simulate a panic while measuring the second packet
+        assert_eq!(rx.peeked_size_hint(0, false), Some(1));
+        rx.processing = Some(1);
+        rx.reset();
+        assert_eq!(rx.next_size_hint, None);
+        assert_eq!(rx.processing, None);
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Noop));
+        assert!(rx.peek().is_none());
+    }
+
+    #[tokio::test]
+    async fn journal_requeue() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(10);
+        let rx = Mutex::new(PeekableReceiver::new(rx));
+        let mut rx = rx.lock().await;
+
+        tx.send(Packet::Ping).await.unwrap();
+        tx.send(Packet::Pong).await.unwrap();
+        tx.send(Packet::Noop).await.unwrap();
+
+        // Values consumed before the journal is started are not recorded
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        rx.start_journal();
+        assert_eq!(rx.peek(), Some(&Packet::Pong));
+        assert_eq!(rx.try_recv().unwrap(), Packet::Pong);
+        assert_eq!(rx.recv().await, Some(Packet::Noop));
+
+        let journal = rx.take_journal();
+        assert_eq!(journal, vec![Packet::Pong, Packet::Noop]);
+        assert!(rx.take_journal().is_empty());
+
+        // Push back the consumed values in their original order, even while the receiver is locked
+        tx.send(Packet::Close).await.unwrap();
+        assert_eq!(rx.peek(), Some(&Packet::Close));
+        rx.requeue_handle().requeue(journal);
+        assert_eq!(rx.recv().await, Some(Packet::Pong));
+        assert_eq!(rx.recv().await, Some(Packet::Noop));
+        assert_eq!(rx.recv().await, Some(Packet::Close));
+    }
+
+    #[tokio::test]
+    async fn requeue_wakes_receiver() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (_tx, rx) = channel(10);
+        let mut rx = PeekableReceiver::<Packet>::new(rx);
+        let requeue = rx.requeue_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            requeue.requeue(vec![Packet::Ping, Packet::Pong]);
+        });
+        assert_eq!(rx.peek_wait().await, Some(&Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Pong));
+    }
+
+    #[tokio::test]
+    async fn send_dropping_oldest() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(3);
+        let rx = Mutex::new(PeekableReceiver::new(rx).reserve_peeked_slots(&tx));
+        let mut rx = rx.lock().await;
+        let is_message = |p: &Packet| matches!(p, Packet::Message(_));
+
+        tx.send(Packet::Ping).await.unwrap();
+        tx.send(Packet::Message("1".into())).await.unwrap();
+        assert_eq!(rx.peek(), Some(&Packet::Ping));
+        tx.send(Packet::Message("2".into())).await.unwrap();
+
+        // The oldest message is dropped, the other packets are kept in order
+        rx.send_dropping_oldest(&tx, Packet::Message("3".into()), is_message)
+            .unwrap();
+        assert_eq!(tx.capacity(), 0);
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Message("2".into())));
+        assert_eq!(rx.recv().await, Some(Packet::Message("3".into())));
+        assert_eq!(tx.capacity(), 3);
+
+        // The oldest message is already peeked
+        tx.send(Packet::Message("4".into())).await.unwrap();
+        tx.send(Packet::Ping).await.unwrap();
+        assert_eq!(rx.peek(), Some(&Packet::Message("4".into())));
+        tx.send(Packet::Pong).await.unwrap();
+        rx.send_dropping_oldest(&tx, Packet::Message("5".into()), is_message)
+            .unwrap();
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Pong));
+        assert_eq!(rx.recv().await, Some(Packet::Message("5".into())));
+
+        // Nothing can be dropped
+        for _ in 0..3 {
+            tx.send(Packet::Noop).await.unwrap();
+        }
+        let res = rx.send_dropping_oldest(&tx, Packet::Message("6".into()), is_message);
+        assert!(res.is_err());
+        for _ in 0..3 {
+            assert_eq!(rx.recv().await, Some(Packet::Noop));
+        }
+    }
+
+    #[tokio::test]
+    async fn reserve_peeked_slots() {
+        use super::PeekableReceiver;
+        use tokio::sync::mpsc::channel;
+
+        let (tx, rx) = channel(3);
+        let mut rx = PeekableReceiver::new(rx).reserve_peeked_slots(&tx);
+        tx.try_send(Packet::Ping).unwrap();
+        tx.try_send(Packet::Pong).unwrap();
+        tx.try_send(Packet::Noop).unwrap();
+
+        // The peeked packets still take their slot in the channel
+        assert_eq!(rx.peek_many(2), &[Packet::Ping, Packet::Pong]);
+        assert_eq!(tx.capacity(), 0);
+        assert!(tx.try_send(Packet::Close).is_err());
+
+        // The slots are released once the packets are consumed
+        assert_eq!(rx.try_recv().unwrap(), Packet::Ping);
+        assert_eq!(tx.capacity(), 1);
+        tx.try_send(Packet::Close).unwrap();
+        assert_eq!(rx.recv().await, Some(Packet::Pong));
+        assert_eq!(rx.recv().await, Some(Packet::Noop));
+        assert_eq!(rx.recv().await, Some(Packet::Close));
+        assert_eq!(tx.capacity(), 3);
+    }
 }
@@ -92,6 +92,45 @@ impl<S: Clone, H: EngineIoHandler> EngineIoService<H, S> {
     }
 }
 
+#[cfg(feature = "webtransport")]
+#[cfg_attr(docsrs, doc(cfg(feature = "webtransport")))]
+impl<H: EngineIoHandler, S> EngineIoService<H, S> {
+    /// Serves an engine.io session over a WebTransport session accepted by an HTTP/3 server.
+    ///
+    /// engineioxide does not terminate HTTP/3, the server hands over the `req` parts of the
+    /// session request and the first bidirectional stream opened by the client.
+    /// If the server exposes the two halves of the stream separately, they can be joined with
+    /// [`tokio::io::join`].
+    ///
+    /// The returned future resolves when the session is closed.
+    /// The stream is dropped right away if [`TransportType::WebTransport`] is not allowed
+    /// in the [`EngineIoConfig`] transports.
+    pub async fn on_webtransport<T>(&self, req: http::request::Parts, stream: T)
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    {
+        if !self
+            .engine
+            .config
+            .allowed_transport(TransportType::WebTransport)
+        {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("webtransport is not an allowed transport");
+            return;
+        }
+        match crate::transport::webtransport::on_session(self.engine.clone(), stream, req).await {
+            Ok(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("webtransport session closed")
+            }
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("webtransport session closed with error: {:?}", _e)
+            }
+        }
+    }
+}
+
 impl<S: Clone, H: EngineIoHandler> Clone for EngineIoService<H, S> {
     fn clone(&self) -> Self {
         EngineIoService {
@@ -125,8 +164,7 @@ where
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let path = self.engine.config.req_path.as_ref();
-        if req.uri().path().starts_with(path) {
+        if match_path(&self.engine.config.req_path, req.uri().path()) {
             dispatch_req(req, self.engine.clone())
         } else {
             ResponseFuture::new(self.inner.call(req))
@@ -149,8 +187,7 @@ where
     type Future = ResponseFuture<S::Future, ResBody>;
 
     fn call(&self, req: Request<ReqBody>) -> Self::Future {
-        let path = self.engine.config.req_path.as_ref();
-        if req.uri().path().starts_with(path) {
+        if match_path(&self.engine.config.req_path, req.uri().path()) {
             dispatch_req(req, self.engine.clone())
         } else {
             ResponseFuture::new(self.inner.call(req))
@@ -158,6 +195,17 @@ where
     }
 }
 
+/// Returns true if the request path is the engine.io path or one of its sub-paths.
+///
+/// The paths only sharing a prefix with it are not matched, e.g. `/chat-admin` for `/chat`,
+/// so that several services can be mounted on the same server with distinct paths.
+fn match_path(req_path: &str, path: &str) -> bool {
+    match path.strip_prefix(req_path.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
 /// A MakeService that always returns a clone of the [`EngineIoService`] it was created with.
 pub struct MakeEngineIoService<H: EngineIoHandler, S> {
     svc: EngineIoService<H, S>,
@@ -221,3 +269,19 @@ impl<ReqBody> HyperSvc<Request<ReqBody>> for NotFoundService {
             .unwrap()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::match_path;
+
+    #[test]
+    fn match_req_path() {
+        assert!(match_path("/engine.io", "/engine.io"));
+        assert!(match_path("/engine.io", "/engine.io/"));
+        assert!(match_path("/engine.io/", "/engine.io/"));
+        assert!(match_path("/chat", "/chat/"));
+        assert!(!match_path("/chat", "/chat-admin/"));
+        assert!(!match_path("/chat", "/"));
+        assert!(match_path("/", "/chat/"));
+    }
+}
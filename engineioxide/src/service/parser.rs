@@ -1,6 +1,6 @@
 //! A Parser module to parse any `EngineIo` query
 
-use std::{str::FromStr, sync::Arc};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 use futures::Future;
 use http::{Method, Request, Response};
@@ -9,6 +9,7 @@ use crate::{
     body::ResponseBody,
     config::EngineIoConfig,
     engine::EngineIo,
+    errors::Error,
     handler::EngineIoHandler,
     service::futures::ResponseFuture,
     sid::Sid,
@@ -36,20 +37,37 @@ where
             method: Method::GET,
             #[cfg(feature = "v3")]
             b64,
-        }) => ResponseFuture::ready(polling::open_req(
-            engine,
-            protocol,
-            req,
-            #[cfg(feature = "v3")]
-            !b64,
-        )),
+        }) => handshake(engine.admit_handshake(), move || {
+            polling::open_req(
+                engine,
+                protocol,
+                req,
+                #[cfg(feature = "v3")]
+                !b64,
+            )
+        }),
+        #[cfg(feature = "shared-polling")]
+        Ok(RequestInfo {
+            protocol: ProtocolVersion::V4,
+            sid: Some(sid),
+            transport: TransportType::Polling,
+            ..
+        }) if engine.config.shared_polling.is_some() => {
+            ResponseFuture::async_response(Box::pin(polling::shared::req(engine, sid, req)))
+        }
         Ok(RequestInfo {
             protocol,
             sid: Some(sid),
             transport: TransportType::Polling,
             method: Method::GET,
             ..
-        }) => ResponseFuture::async_response(Box::pin(polling::polling_req(engine, protocol, sid))),
+        }) => ResponseFuture::async_response(Box::pin(polling::polling_req(
+            engine,
+            protocol,
+            sid,
+            #[cfg(feature = "compression")]
+            polling::Compression::from_headers(req.headers()),
+        ))),
         Ok(RequestInfo {
             protocol,
             sid: Some(sid),
@@ -65,7 +83,16 @@ where
             transport: TransportType::Websocket,
             method: Method::GET,
             ..
-        }) => ResponseFuture::ready(ws::new_req(engine, protocol, sid, req)),
+        }) => {
+            // Only the websocket connections opening a new session are handshakes, not the upgrades
+            let admission = match sid {
+                None => engine.admit_handshake(),
+                Some(_) => Ok(Duration::ZERO),
+            };
+            handshake(admission, move || async move {
+                ws::new_req(engine, protocol, sid, req)
+            })
+        }
         Err(e) => {
             #[cfg(feature = "tracing")]
             tracing::debug!("error parsing request: {:?}", e);
@@ -79,6 +106,29 @@ where
     }
 }
 
+/// Handles a handshake request once it is admitted by the [`HandshakeLimit`](crate::config::HandshakeLimit),
+/// after the time to wait for its turn
+fn handshake<F, B, H, Fut>(admission: Result<Duration, Error>, handshake: H) -> ResponseFuture<F, B>
+where
+    B: Send + 'static,
+    H: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response<ResponseBody<B>>, Error>> + Send + 'static,
+{
+    match admission {
+        Ok(wait) => ResponseFuture::async_response(Box::pin(async move {
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            handshake().await
+        })),
+        Err(e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("handshake rejected: {e}");
+            ResponseFuture::ready(Err(e))
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParseError {
     #[error("transport unknown")]
@@ -154,6 +204,8 @@ pub enum TransportType {
     Polling = 0x01,
     /// Websocket transport
     Websocket = 0x02,
+    /// WebTransport transport, over an HTTP/3 session handed over by the server
+    WebTransport = 0x04,
 }
 
 impl From<u8> for TransportType {
@@ -161,6 +213,7 @@ impl From<u8> for TransportType {
         match t {
             0x01 => TransportType::Polling,
             0x02 => TransportType::Websocket,
+            0x04 => TransportType::WebTransport,
             _ => panic!("unknown transport type"),
         }
     }
@@ -173,6 +226,7 @@ impl FromStr for TransportType {
         match s {
             "websocket" => Ok(TransportType::Websocket),
             "polling" => Ok(TransportType::Polling),
+            "webtransport" => Ok(TransportType::WebTransport),
             _ => Err(ParseError::UnknownTransport),
         }
     }
@@ -182,6 +236,7 @@ impl From<TransportType> for &'static str {
         match t {
             TransportType::Polling => "polling",
             TransportType::Websocket => "websocket",
+            TransportType::WebTransport => "webtransport",
         }
     }
 }
@@ -190,6 +245,7 @@ impl From<TransportType> for String {
         match t {
             TransportType::Polling => "polling".into(),
             TransportType::Websocket => "websocket".into(),
+            TransportType::WebTransport => "webtransport".into(),
         }
     }
 }
@@ -327,8 +383,6 @@ mod tests {
     #[test]
     #[cfg(feature = "v3")]
     fn request_info_polling_withb64() {
-        assert!(cfg!(feature = "v3"));
-
         let req = build_request("http://localhost:3000/socket.io/?EIO=3&transport=polling&b64=1");
         let req = RequestInfo::parse(&req, &EngineIoConfig::default()).unwrap();
         assert!(req.b64);
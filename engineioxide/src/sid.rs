@@ -1,6 +1,9 @@
 //! [`Socket`](crate::Socket) id type and generator
 //!
 //! It it stored as a 128 bit id and it represent a base64 16 char string
+//!
+//! The ids of the new sessions are produced by the [`SidGenerator`] of the
+//! [`EngineIoConfig`](crate::config::EngineIoConfig), the [`RandomSidGenerator`] by default.
 use std::{
     fmt::{Debug, Display, Formatter},
     str::FromStr,
@@ -27,6 +30,30 @@ impl Sid {
     }
 }
 
+/// A generator of session ids, set with [`EngineIoConfigBuilder::sid_generator`].
+///
+/// The generated ids must be 16 chars long and only contain url safe base64 chars (`A-Z`, `a-z`, `0-9`, `-` and `_`).
+/// They must also be unpredictable and collision resistant: a session id is the only credential of a polling client.
+/// For example, a shard key can be used as a prefix of random chars.
+///
+/// An invalid id is replaced by a random one, and a new id is generated if it is already used by another session.
+///
+/// [`EngineIoConfigBuilder::sid_generator`]: crate::config::EngineIoConfigBuilder::sid_generator
+pub trait SidGenerator: Debug + Send + Sync + 'static {
+    /// Generates the id of a new session
+    fn generate(&self) -> String;
+}
+
+/// The default [`SidGenerator`], generating 96 random bits encoded as base64
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomSidGenerator;
+
+impl SidGenerator for RandomSidGenerator {
+    fn generate(&self) -> String {
+        Sid::new().to_string()
+    }
+}
+
 /// Error type for [`Sid::from_str`]
 #[derive(Debug, thiserror::Error)]
 pub enum SidDecodeError {
@@ -54,8 +54,9 @@
 //! let svc = EngineIoService::new(MyHandler::default());
 //! ```
 use std::{
+    borrow::Cow,
     sync::{
-        atomic::{AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
         Arc,
     },
     time::Duration,
@@ -66,18 +67,25 @@ use tokio::{
     sync::{
         mpsc::{self},
         mpsc::{error::TrySendError, Receiver},
-        Mutex,
+        watch, Mutex,
     },
     task::JoinHandle,
 };
 use tokio_tungstenite::tungstenite;
 
 use crate::{
-    config::EngineIoConfig, errors::Error, packet::Packet, peekable::PeekableReceiver,
+    body::DropHook,
+    config::{CircuitBreaker, EngineIoConfig, OverflowPolicy},
+    errors::Error,
+    packet::Packet,
+    peekable::PeekableReceiver,
     service::ProtocolVersion,
 };
 use crate::{service::TransportType, sid::Sid};
 
+/// Value stored in the rtt of a socket when no heartbeat cycle has completed yet
+const NO_RTT: u64 = u64::MAX;
+
 /// A [`DisconnectReason`] represents the reason why a [`Socket`] was closed.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DisconnectReason {
@@ -94,6 +102,37 @@ pub enum DisconnectReason {
     HeartbeatTimeout,
     /// The server is being closed
     ClosingServer,
+    /// The client did not receive the packets fast enough and its buffer was full,
+    /// with the [`OverflowPolicy::DisconnectSlowClient`] policy
+    SlowClient,
+    /// The server closed the connection with [`Socket::close_with_frame`]
+    ServerClose,
+    /// The client missed too many pongs or too many bytes were emitted to it since its last pong,
+    /// see the [`CircuitBreaker`](crate::config::CircuitBreaker)
+    UnresponsiveClient,
+}
+
+/// The close code and reason sent in the close frame of a websocket connection closed by the server.
+///
+/// The code should be one of the [close codes](https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1)
+/// that an endpoint can send, e.g. `1008` for a policy violation,
+/// or an application code between `4000` and `4999`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The close code
+    pub code: u16,
+    /// The close reason, truncated to the 123 bytes allowed in a close frame
+    pub reason: Cow<'static, str>,
+}
+
+impl CloseFrame {
+    /// Creates a new [`CloseFrame`] with a code and a reason
+    pub fn new(code: u16, reason: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
 }
 
 /// Convert an [`Error`] to a [`DisconnectReason`] if possible
@@ -104,12 +143,19 @@ impl From<&Error> for Option<DisconnectReason> {
         use Error::*;
         match err {
             WsTransport(tungstenite::Error::ConnectionClosed) => None,
-            WsTransport(_) | Io(_) => Some(DisconnectReason::TransportError),
-            BadPacket(_) | Serialize(_) | Base64(_) | StrUtf8(_) | PayloadTooLarge
-            | InvalidPacketLength | InvalidPacketType(_) => {
+            WsTransport(tungstenite::Error::Capacity(_)) => {
                 Some(DisconnectReason::PacketParsingError)
             }
+            WsTransport(_) | Io(_) => Some(DisconnectReason::TransportError),
+            BadPacket(_)
+            | Serialize(_)
+            | Base64(_)
+            | StrUtf8(_)
+            | PayloadTooLarge { .. }
+            | InvalidPacketLength
+            | InvalidPacketType(_) => Some(DisconnectReason::PacketParsingError),
             HeartbeatTimeout => Some(DisconnectReason::HeartbeatTimeout),
+            UnresponsiveClient => Some(DisconnectReason::UnresponsiveClient),
             _ => None,
         }
     }
@@ -120,7 +166,7 @@ impl From<&Error> for Option<DisconnectReason> {
 ///
 /// It handles :
 /// * the packet communication between with the `Engine`
-/// and the user defined [`Handler`](crate::handler::EngineIoHandler).
+///   and the user defined [`Handler`](crate::handler::EngineIoHandler).
 /// * the user defined [`Data`](crate::handler::EngineIoHandler::Data) bound to the socket.
 /// * the heartbeat job that verify that the connection is still up by sending packets periodically.
 pub struct Socket<D>
@@ -138,6 +184,9 @@ where
     /// without any mutex
     transport: AtomicU8,
 
+    /// Set if the upgrade requests of the client are refused, see [`Socket::force_polling`]
+    upgrades_disabled: AtomicBool,
+
     /// Channel to receive [`Packet`] from the connection
     ///
     /// It is used and managed by the [`EngineIo`](crate::engine) struct depending on the transport type
@@ -150,12 +199,37 @@ where
     /// * From the [encoder](crate::service::encoder) if the transport is polling
     /// * From the fn [`on_ws_req_init`](crate::engine::EngineIo) if the transport is websocket
     /// * Automatically via the [`close_session fn`](crate::engine::EngineIo::close_session) as a fallback.
-    /// Because with polling transport, if the client is not currently polling then the encoder will never be able to close the channel
-    pub(crate) internal_rx: Mutex<PeekableReceiver<Packet>>,
+    ///   Because with polling transport, if the client is not currently polling then the encoder will never be able to close the channel
+    pub(crate) internal_rx: Arc<Mutex<PeekableReceiver<Packet>>>,
 
     /// Channel to send [Packet] to the internal connection
     internal_tx: mpsc::Sender<Packet>,
 
+    /// What to do when a message is emitted while the internal channel is full
+    overflow_policy: OverflowPolicy,
+
+    /// The maximum size in bytes of the polling payloads sent to this socket.
+    /// It is initialized with the [`EngineIoConfig::max_payload`] value and can be changed at runtime
+    max_payload: AtomicU64,
+
+    /// The heartbeat parameters of this socket.
+    /// They are initialized with the [`EngineIoConfig`] values and can be overridden by the handler
+    pub(crate) ping_interval: Duration,
+    pub(crate) ping_timeout: Duration,
+
+    /// The last measured round-trip time between a ping and its pong, in nanoseconds.
+    /// It is set to [`NO_RTT`] until the first heartbeat cycle completes
+    rtt: AtomicU64,
+
+    /// The circuit breaker disconnecting the client early if it is unresponsive
+    circuit_breaker: Option<CircuitBreaker>,
+    /// The number of bytes emitted to the client since its last pong, checked against the [`CircuitBreaker`]
+    unacked_bytes: AtomicU64,
+
+    /// Set while the payload of a polling response is being sent,
+    /// an upgraded transport waits for it before sending the buffered packets
+    polling_payload: watch::Sender<bool>,
+
     /// Internal channel to receive Pong [`Packets`](Packet) (v4 protocol) or Ping (v3 protocol) in the heartbeat job
     /// which is running in a separate task
     heartbeat_rx: Mutex<Receiver<()>>,
@@ -167,6 +241,8 @@ where
 
     /// Function to call when the socket is closed
     close_fn: Box<dyn Fn(Sid, DisconnectReason) + Send + Sync>,
+    /// The frame sent to a websocket client when the connection is closed by the server
+    close_frame: std::sync::Mutex<Option<CloseFrame>>,
     /// User data bound to the socket
     pub data: D,
 
@@ -183,6 +259,7 @@ where
     D: Default + Send + Sync + 'static,
 {
     pub(crate) fn new(
+        id: Sid,
         protocol: ProtocolVersion,
         transport: TransportType,
         config: &EngineIoConfig,
@@ -194,17 +271,30 @@ where
         let (heartbeat_tx, heartbeat_rx) = mpsc::channel(1);
 
         Self {
-            id: Sid::new(),
+            id,
             protocol,
             transport: AtomicU8::new(transport as u8),
+            upgrades_disabled: AtomicBool::new(false),
 
-            internal_rx: Mutex::new(PeekableReceiver::new(internal_rx)),
+            // The peeked packets are still accounted in the buffer size
+            internal_rx: Arc::new(Mutex::new(
+                PeekableReceiver::new(internal_rx).reserve_peeked_slots(&internal_tx),
+            )),
             internal_tx,
+            overflow_policy: config.overflow_policy,
+            max_payload: AtomicU64::new(config.max_payload),
+            ping_interval: config.ping_interval,
+            ping_timeout: config.ping_timeout,
+            rtt: AtomicU64::new(NO_RTT),
+            circuit_breaker: config.circuit_breaker,
+            unacked_bytes: AtomicU64::new(0),
+            polling_payload: watch::channel(false).0,
 
             heartbeat_rx: Mutex::new(heartbeat_rx),
             heartbeat_tx,
             heartbeat_handle: Mutex::new(None),
             close_fn,
+            close_frame: std::sync::Mutex::new(None),
 
             data: D::default(),
             req_parts,
@@ -229,17 +319,104 @@ where
         Ok(())
     }
 
+    /// Sends a message packet emitted by the handler to the connection.
+    ///
+    /// If the internal channel is full, the [`OverflowPolicy`] is applied.
+    /// If the message exceeds the unacked bytes of the [`CircuitBreaker`], the client is disconnected.
+    ///
+    /// With the [`OverflowPolicy::Block`] policy, a full channel is handled as with [`OverflowPolicy::Error`],
+    /// only [`send_message_async`](Self::send_message_async) waits for room in the channel.
+    fn send_message(&self, packet: Packet) -> Result<(), TrySendError<Packet>> {
+        let Some(size) = self.check_unacked_bytes(&packet) else {
+            return Err(TrySendError::Full(packet));
+        };
+        let res = self.enqueue_message(packet);
+        if res.is_ok() {
+            self.unacked_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+        res
+    }
+
+    /// Enqueues a message packet, applying the [`OverflowPolicy`] if the internal channel is full
+    fn enqueue_message(&self, packet: Packet) -> Result<(), TrySendError<Packet>> {
+        let packet = match self.send(packet) {
+            Err(TrySendError::Full(packet)) => packet,
+            res => return res,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            "[sid={}] buffer full, applying {:?} policy",
+            self.id,
+            self.overflow_policy
+        );
+        match self.overflow_policy {
+            OverflowPolicy::Error => Err(TrySendError::Full(packet)),
+            OverflowPolicy::DisconnectSlowClient => {
+                self.close(DisconnectReason::SlowClient);
+                Err(TrySendError::Full(packet))
+            }
+            OverflowPolicy::DropOldest => {
+                let Ok(mut rx) = self.internal_rx.try_lock() else {
+                    return Err(TrySendError::Full(packet));
+                };
+                rx.send_dropping_oldest(&self.internal_tx, packet, |p| {
+                    p.is_binary() || matches!(p, Packet::Message(_))
+                })
+            }
+            OverflowPolicy::Block => Err(TrySendError::Full(packet)),
+        }
+    }
+
+    /// Sends a message packet emitted by the handler to the connection like [`send_message`](Self::send_message),
+    /// but with the [`OverflowPolicy::Block`] policy it waits for the channel to have room for the packet.
+    async fn send_message_async(&self, packet: Packet) -> Result<(), TrySendError<Packet>> {
+        if self.overflow_policy != OverflowPolicy::Block {
+            return self.send_message(packet);
+        }
+        let Some(size) = self.check_unacked_bytes(&packet) else {
+            return Err(TrySendError::Full(packet));
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] sending packet: {:?}", self.id, packet);
+        self.internal_tx
+            .send(packet)
+            .await
+            .map_err(|e| TrySendError::Closed(e.0))?;
+        self.unacked_bytes.fetch_add(size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Checks that the packet does not exceed the unacked bytes of the [`CircuitBreaker`],
+    /// otherwise the client is disconnected and `None` is returned.
+    ///
+    /// Returns the size to add to the unacked bytes once the packet is buffered.
+    fn check_unacked_bytes(&self, packet: &Packet) -> Option<u64> {
+        let Some(max) = self.circuit_breaker.and_then(|b| b.max_unacked_bytes) else {
+            return Some(0);
+        };
+        let size = packet.get_size_hint(false) as u64;
+        if self.unacked_bytes.load(Ordering::Relaxed) + size > max {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] too many unacked bytes, closing", self.id);
+            self.close(DisconnectReason::UnresponsiveClient);
+            return None;
+        }
+        Some(size)
+    }
+
     /// Spawn the heartbeat job
     ///
     /// Keep a handle to the job so that it can be aborted when the socket is closed
-    pub(crate) fn spawn_heartbeat(self: Arc<Self>, interval: Duration, timeout: Duration) {
+    pub(crate) fn spawn_heartbeat(self: Arc<Self>) {
         let socket = self.clone();
+        let (interval, timeout) = (self.ping_interval, self.ping_timeout);
 
         let handle = tokio::spawn(async move {
-            if let Err(_e) = socket.heartbeat_job(interval, timeout).await {
-                socket.close(DisconnectReason::HeartbeatTimeout);
+            if let Err(e) = socket.heartbeat_job(interval, timeout).await {
+                let reason: Option<DisconnectReason> = (&e).into();
+                socket.close(reason.unwrap_or(DisconnectReason::HeartbeatTimeout));
                 #[cfg(feature = "tracing")]
-                tracing::debug!("[sid={}] heartbeat error: {:?}", socket.id, _e);
+                tracing::debug!("[sid={}] heartbeat error: {:?}", socket.id, e);
             }
         });
         self.heartbeat_handle
@@ -292,17 +469,69 @@ where
             // Some clients send the pong packet in first. If that happens, we should consume it.
             heartbeat_rx.try_recv().ok();
 
+            let ping_instant = tokio::time::Instant::now();
             self.internal_tx
                 .try_send(Packet::Ping)
                 .map_err(|_| Error::HeartbeatTimeout)?;
-            tokio::time::timeout(timeout, heartbeat_rx.recv())
-                .await
-                .map_err(|_| Error::HeartbeatTimeout)?
-                .ok_or(Error::HeartbeatTimeout)?;
+            let ping_instant = match self.circuit_breaker {
+                Some(breaker) => {
+                    self.wait_pong_with_breaker(&mut heartbeat_rx, ping_instant, timeout, breaker)
+                        .await?
+                }
+                None => {
+                    tokio::time::timeout(timeout, heartbeat_rx.recv())
+                        .await
+                        .map_err(|_| Error::HeartbeatTimeout)?
+                        .ok_or(Error::HeartbeatTimeout)?;
+                    ping_instant
+                }
+            };
+            let rtt = ping_instant.elapsed().as_nanos().min(NO_RTT as u128 - 1) as u64;
+            self.rtt.store(rtt, Ordering::Relaxed);
+            self.unacked_bytes.store(0, Ordering::Relaxed);
             interval_tick.tick().await;
         }
     }
 
+    /// Waits for the pong of a ping sent at `ping_instant`, sending a new ping each time a pong is missed.
+    ///
+    /// Returns the instant of the last ping sent, or an error if the client missed too many pongs
+    /// or if the `timeout` elapsed.
+    async fn wait_pong_with_breaker(
+        &self,
+        heartbeat_rx: &mut Receiver<()>,
+        mut ping_instant: tokio::time::Instant,
+        timeout: Duration,
+        breaker: CircuitBreaker,
+    ) -> Result<tokio::time::Instant, Error> {
+        let deadline = ping_instant + timeout;
+        let mut missed_pongs = 0;
+        loop {
+            let now = tokio::time::Instant::now();
+            let wait = breaker
+                .pong_timeout
+                .min(deadline.saturating_duration_since(now));
+            match tokio::time::timeout(wait, heartbeat_rx.recv()).await {
+                Ok(pong) => return pong.map(|_| ping_instant).ok_or(Error::HeartbeatTimeout),
+                Err(_) if tokio::time::Instant::now() >= deadline => {
+                    return Err(Error::HeartbeatTimeout)
+                }
+                Err(_) => {
+                    missed_pongs += 1;
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("[sid={}] missed pong {missed_pongs}", self.id);
+                    if missed_pongs >= breaker.max_missed_pongs {
+                        return Err(Error::UnresponsiveClient);
+                    }
+                    ping_instant = tokio::time::Instant::now();
+                    self.internal_tx
+                        .try_send(Packet::Ping)
+                        .map_err(|_| Error::HeartbeatTimeout)?;
+                }
+            }
+        }
+    }
+
     #[cfg(feature = "v3")]
     async fn heartbeat_job_v3(&self, interval: Duration, timeout: Duration) -> Result<(), Error> {
         let mut heartbeat_rx = self
@@ -321,26 +550,85 @@ where
 
             #[cfg(feature = "tracing")]
             tracing::debug!("[sid={}] ping received, sending pong", self.id);
+            self.unacked_bytes.store(0, Ordering::Relaxed);
             self.internal_tx
                 .try_send(Packet::Pong)
                 .map_err(|_| Error::HeartbeatTimeout)?;
         }
     }
 
-    /// Returns true if the [`Socket`] has a websocket [`TransportType`]
-    pub(crate) fn is_ws(&self) -> bool {
-        self.transport.load(Ordering::Relaxed) == TransportType::Websocket as u8
-    }
     /// returns true if the [`Socket`] has an HTTP [`TransportType`]
     pub(crate) fn is_http(&self) -> bool {
         self.transport.load(Ordering::Relaxed) == TransportType::Polling as u8
     }
 
-    /// Sets the [`TransportType`] to WebSocket
-    /// Used when the client upgrade the connection from HTTP to WebSocket
-    pub(crate) fn upgrade_to_websocket(&self) {
-        self.transport
-            .store(TransportType::Websocket as u8, Ordering::Relaxed);
+    /// Returns true if the client is allowed to upgrade the [`Socket`] to another transport
+    pub(crate) fn is_upgradable(&self) -> bool {
+        self.is_http() && !self.upgrades_disabled.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the [`Socket`] on the polling transport by refusing the upgrade requests of its client from now on.
+    /// The upgrade probe of the client fails and it keeps polling.
+    ///
+    /// Engine.io has no packet to downgrade a connection, so it returns `false` if the socket
+    /// already uses another transport. An upgrade whose probe was already answered is not cancelled either.
+    pub fn force_polling(&self) -> bool {
+        self.upgrades_disabled.store(true, Ordering::Relaxed);
+        self.is_http()
+    }
+
+    /// Sets the [`TransportType`] of the [`Socket`]
+    /// Used when the client upgrade the connection from HTTP to WebSocket or WebTransport
+    ///
+    /// It waits for any polling request to finish and switches the transport while holding the internal channel,
+    /// so that no other polling request can take the buffered packets.
+    /// Then it waits for the final polling payload to be sent, or requeued if it was dropped,
+    /// so that the packets are delivered in order across the two transports.
+    pub(crate) async fn upgrade_to(&self, transport: TransportType) {
+        let rx = self.internal_rx.lock().await;
+        self.transport.store(transport as u8, Ordering::Relaxed);
+        drop(rx);
+        self.polling_payload
+            .subscribe()
+            .wait_for(|sending| !sending)
+            .await
+            .ok();
+    }
+
+    /// Marks a polling payload as being sent until the returned hook is dropped with the response body.
+    pub(crate) fn start_polling_payload(self: &Arc<Self>) -> DropHook {
+        self.polling_payload.send_replace(true);
+        let socket = self.clone();
+        DropHook::new(move || {
+            socket.polling_payload.send_replace(false);
+        })
+    }
+
+    /// Returns the maximum size in bytes of the polling payloads sent to this socket
+    pub fn max_payload(&self) -> u64 {
+        self.max_payload.load(Ordering::Relaxed)
+    }
+
+    /// Changes the maximum size in bytes of the polling payloads sent to this socket.
+    ///
+    /// It is applied from the next polling request, so it can be used to shrink the payloads
+    /// of a client on a metered connection without re-creating the session.
+    ///
+    /// It has no effect on websocket transport.
+    pub fn set_max_payload(&self, max_payload: u64) {
+        self.max_payload.store(max_payload, Ordering::Relaxed);
+    }
+
+    /// Returns the round-trip time measured during the last heartbeat cycle,
+    /// between the moment the server sent a ping and the moment it received the pong.
+    ///
+    /// It is updated at every heartbeat cycle and returns `None` until the first cycle completes.
+    /// With the v3 protocol the client initiates the heartbeat so the rtt is never measured.
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.rtt.load(Ordering::Relaxed) {
+            NO_RTT => None,
+            rtt => Some(Duration::from_nanos(rtt)),
+        }
     }
 
     /// Returns the current [`TransportType`] of the [`Socket`]
@@ -356,10 +644,24 @@ where
     ///
     /// ⚠️ If the buffer is full or the socket is disconnected, an error will be returned with the original data
     pub fn emit(&self, msg: String) -> Result<(), TrySendError<String>> {
-        self.send(Packet::Message(msg)).map_err(|e| match e {
-            TrySendError::Full(p) => TrySendError::Full(p.into_message()),
-            TrySendError::Closed(p) => TrySendError::Closed(p.into_message()),
-        })
+        self.send_message(Packet::Message(msg.into()))
+            .map_err(|e| match e {
+                TrySendError::Full(p) => TrySendError::Full(p.into_message()),
+                TrySendError::Closed(p) => TrySendError::Closed(p.into_message()),
+            })
+    }
+
+    /// Emits a message to the client like [`emit`](Self::emit).
+    ///
+    /// With the [`OverflowPolicy::Block`] policy, it waits for the buffer to have room for the message
+    /// instead of returning a [`TrySendError::Full`] error.
+    pub async fn emit_async(&self, msg: String) -> Result<(), TrySendError<String>> {
+        self.send_message_async(Packet::Message(msg.into()))
+            .await
+            .map_err(|e| match e {
+                TrySendError::Full(p) => TrySendError::Full(p.into_message()),
+                TrySendError::Closed(p) => TrySendError::Closed(p.into_message()),
+            })
     }
 
     /// Immediately closes the socket and the underlying connection.
@@ -369,6 +671,32 @@ where
         self.send(Packet::Close).ok();
     }
 
+    /// Immediately closes the socket and the underlying connection like [`close`](Self::close),
+    /// with a close code and reason sent in the close frame of a websocket connection.
+    ///
+    /// The frame is ignored with the other transports.
+    pub fn close_with_frame(&self, reason: DisconnectReason, frame: CloseFrame) {
+        self.set_close_frame(frame);
+        self.close(reason);
+    }
+
+    /// Sets the frame sent to a websocket client when the connection is closed
+    pub(crate) fn set_close_frame(&self, frame: CloseFrame) {
+        self.close_frame.lock().unwrap().replace(frame);
+    }
+
+    /// Takes the frame to send to a websocket client when the connection is closed
+    pub(crate) fn take_close_frame(&self) -> Option<CloseFrame> {
+        self.close_frame.lock().unwrap().take()
+    }
+
+    /// Returns the number of packets buffered for the client, waiting to be sent
+    ///
+    /// It includes the packets already peeked by the transport but not sent yet.
+    pub fn buffered_packets(&self) -> usize {
+        self.internal_tx.max_capacity() - self.internal_tx.capacity()
+    }
+
     /// Returns true if the socket is closed
     /// It means that no more packets can be sent to the client
     pub fn is_closed(&self) -> bool {
@@ -389,15 +717,31 @@ where
     /// ⚠️ If the buffer is full or the socket is disconnected, an error will be returned with the original data
     pub fn emit_binary(&self, data: Vec<u8>) -> Result<(), TrySendError<Vec<u8>>> {
         if self.protocol == ProtocolVersion::V3 {
-            self.send(Packet::BinaryV3(data))
+            self.send_message(Packet::BinaryV3(data))
         } else {
-            self.send(Packet::Binary(data))
+            self.send_message(Packet::Binary(data))
         }
         .map_err(|e| match e {
             TrySendError::Full(p) => TrySendError::Full(p.into_binary()),
             TrySendError::Closed(p) => TrySendError::Closed(p.into_binary()),
         })
     }
+
+    /// Emits a binary message to the client like [`emit_binary`](Self::emit_binary).
+    ///
+    /// With the [`OverflowPolicy::Block`] policy, it waits for the buffer to have room for the message
+    /// instead of returning a [`TrySendError::Full`] error.
+    pub async fn emit_binary_async(&self, data: Vec<u8>) -> Result<(), TrySendError<Vec<u8>>> {
+        let packet = if self.protocol == ProtocolVersion::V3 {
+            Packet::BinaryV3(data)
+        } else {
+            Packet::Binary(data)
+        };
+        self.send_message_async(packet).await.map_err(|e| match e {
+            TrySendError::Full(p) => TrySendError::Full(p.into_binary()),
+            TrySendError::Closed(p) => TrySendError::Closed(p.into_binary()),
+        })
+    }
 }
 
 impl<D: Default + Send + Sync + 'static> std::fmt::Debug for Socket<D> {
@@ -411,6 +755,7 @@ impl<D: Default + Send + Sync + 'static> std::fmt::Debug for Socket<D> {
             .field("heartbeat_rx", &self.heartbeat_rx)
             .field("heartbeat_tx", &self.heartbeat_tx)
             .field("heartbeat_handle", &self.heartbeat_handle)
+            .field("rtt", &self.rtt())
             .field("req_data", &self.req_parts)
             .finish()
     }
@@ -444,14 +789,26 @@ where
             id: sid,
             protocol: ProtocolVersion::V4,
             transport: AtomicU8::new(TransportType::Websocket as u8),
+            upgrades_disabled: AtomicBool::new(false),
 
-            internal_rx: Mutex::new(PeekableReceiver::new(internal_rx)),
+            internal_rx: Arc::new(Mutex::new(
+                PeekableReceiver::new(internal_rx).reserve_peeked_slots(&internal_tx),
+            )),
             internal_tx,
+            overflow_policy: OverflowPolicy::Error,
+            max_payload: AtomicU64::new(EngineIoConfig::default().max_payload),
+            ping_interval: EngineIoConfig::default().ping_interval,
+            ping_timeout: EngineIoConfig::default().ping_timeout,
+            rtt: AtomicU64::new(NO_RTT),
+            circuit_breaker: None,
+            unacked_bytes: AtomicU64::new(0),
+            polling_payload: watch::channel(false).0,
 
             heartbeat_rx: Mutex::new(heartbeat_rx),
             heartbeat_tx,
             heartbeat_handle: Mutex::new(None),
             close_fn,
+            close_frame: std::sync::Mutex::new(None),
 
             data: D::default(),
             req_parts: http::Request::<()>::default().into_parts().0,
@@ -460,4 +817,150 @@ where
             supports_binary: true,
         }
     }
+
+    /// Receives the next packet sent to the client of a dummy socket.
+    /// Returns `None` once the socket is closed and all its packets are received.
+    pub async fn recv_dummy(&self) -> Option<Packet> {
+        self.internal_rx.lock().await.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_rtt() {
+        let config = EngineIoConfig {
+            ping_interval: Duration::from_millis(300),
+            ping_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let req_parts = http::Request::<()>::default().into_parts().0;
+        let socket: Arc<Socket<()>> = Arc::new(Socket::new(
+            Sid::new(),
+            ProtocolVersion::V4,
+            TransportType::Websocket,
+            &config,
+            req_parts,
+            Box::new(|_, _| {}),
+            #[cfg(feature = "v3")]
+            true,
+        ));
+        assert_eq!(socket.rtt(), None);
+
+        socket.clone().spawn_heartbeat();
+        let mut rx = socket.internal_rx.try_lock().unwrap();
+        for delay in [30, 120, 75] {
+            assert_eq!(rx.recv().await, Some(Packet::Ping));
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            socket.heartbeat_tx.try_send(()).unwrap();
+            // Let the heartbeat job process the pong
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            // The tokio timer has a millisecond resolution
+            let rtt = socket.rtt().unwrap();
+            let delay = Duration::from_millis(delay);
+            assert!(
+                rtt >= delay && rtt <= delay + Duration::from_millis(2),
+                "{rtt:?}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn circuit_breaker_missed_pongs() {
+        let config = EngineIoConfig {
+            ping_interval: Duration::from_millis(300),
+            ping_timeout: Duration::from_millis(1000),
+            circuit_breaker: Some(CircuitBreaker::new(3, Duration::from_millis(50))),
+            ..Default::default()
+        };
+        let (tx, mut close_rx) = mpsc::unbounded_channel();
+        let req_parts = http::Request::<()>::default().into_parts().0;
+        let socket: Arc<Socket<()>> = Arc::new(Socket::new(
+            Sid::new(),
+            ProtocolVersion::V4,
+            TransportType::Websocket,
+            &config,
+            req_parts,
+            Box::new(move |_, reason| tx.send(reason).unwrap()),
+            #[cfg(feature = "v3")]
+            true,
+        ));
+
+        socket.clone().spawn_heartbeat();
+        let mut rx = socket.internal_rx.try_lock().unwrap();
+
+        // A missed pong followed by a pong does not trip the breaker
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        socket.heartbeat_tx.try_send(()).unwrap();
+
+        // The client stops answering
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        let first_ping = tokio::time::Instant::now();
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        assert_eq!(rx.recv().await, Some(Packet::Ping));
+        let reason = close_rx.recv().await.unwrap();
+        assert_eq!(reason, DisconnectReason::UnresponsiveClient);
+        let elapsed = first_ping.elapsed();
+        assert!(elapsed < config.ping_timeout, "{elapsed:?}");
+        assert_eq!(rx.recv().await, Some(Packet::Close));
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_unacked_bytes() {
+        let config = EngineIoConfig {
+            circuit_breaker: Some(
+                CircuitBreaker::new(1, Duration::from_secs(1)).max_unacked_bytes(10),
+            ),
+            ..Default::default()
+        };
+        let (tx, mut close_rx) = mpsc::unbounded_channel();
+        let req_parts = http::Request::<()>::default().into_parts().0;
+        let socket: Socket<()> = Socket::new(
+            Sid::new(),
+            ProtocolVersion::V4,
+            TransportType::Websocket,
+            &config,
+            req_parts,
+            Box::new(move |_, reason| tx.send(reason).unwrap()),
+            #[cfg(feature = "v3")]
+            true,
+        );
+
+        socket.emit("hello".into()).unwrap();
+        assert_eq!(
+            socket.emit("world".into()),
+            Err(TrySendError::Full("world".into()))
+        );
+        let reason = close_rx.recv().await.unwrap();
+        assert_eq!(reason, DisconnectReason::UnresponsiveClient);
+    }
+    #[tokio::test]
+    async fn circuit_breaker_unacked_bytes_rejected_packet() {
+        let config = EngineIoConfig {
+            circuit_breaker: Some(
+                CircuitBreaker::new(1, Duration::from_secs(1)).max_unacked_bytes(100),
+            ),
+            max_buffer_size: 1,
+            ..Default::default()
+        };
+        let req_parts = http::Request::<()>::default().into_parts().0;
+        let socket: Socket<()> = Socket::new(
+            Sid::new(),
+            ProtocolVersion::V4,
+            TransportType::Websocket,
+            &config,
+            req_parts,
+            Box::new(|_, _| {}),
+            #[cfg(feature = "v3")]
+            true,
+        );
+
+        socket.emit("hello".into()).unwrap();
+        let size = socket.unacked_bytes.load(Ordering::Relaxed);
+        assert!(socket.emit("world".into()).is_err());
+        assert_eq!(socket.unacked_bytes.load(Ordering::Relaxed), size);
+    }
 }
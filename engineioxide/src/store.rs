@@ -0,0 +1,349 @@
+//! Polling sessions shared between several nodes, so that a load-balanced deployment doesn't need sticky sessions.
+//!
+//! By default the state of a polling session lives in the process that opened it,
+//! so all its `GET` and `POST` requests must be routed to the same node.
+//! When a [`SharedPolling`] config is set with [`EngineIoConfigBuilder::shared_polling`](crate::config::EngineIoConfigBuilder::shared_polling),
+//! the packets of the polling sessions are exchanged through a [`PollingStore`] shared by all the nodes:
+//! * The node that opened a session keeps its [`Socket`](crate::socket::Socket) and calls the handler.
+//!   It moves the packets emitted to the socket to the store, and dispatches the packets posted to the store by the client.
+//! * Any node serves a `GET` request with the packets found in the store and a `POST` request by pushing its packets to the store.
+//!   A `GET` request overlapping another one of the same session, even on another node, is rejected
+//!   and the session is closed with [`MultipleHttpPollingError`](crate::socket::DisconnectReason::MultipleHttpPollingError).
+//!
+//! The handshake response sets an `io` cookie holding the session id signed with the key of the [`SharedPolling`] config.
+//! The following polling requests are only accepted with this cookie, so all the nodes must share the same key.
+//!
+//! Only the engine.io v4 protocol is supported, the sessions of the v3 clients stay bound to their node.
+//! The shared sessions cannot be upgraded to websocket, they are opened without any upgrade advertised
+//! in the handshake. The websocket connections opened directly are not concerned.
+//! The polling payloads of the shared sessions are not compressed.
+//!
+//! #### Example
+//! ```
+//! # use engineioxide::{config::EngineIoConfig, store::{MemoryPollingStore, SharedPolling}};
+//! let store = MemoryPollingStore::new();
+//! let config = EngineIoConfig::builder()
+//!     .shared_polling(SharedPolling::new(store, "my secret key"))
+//!     .build();
+//! ```
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use hmac::{Hmac, Mac};
+use http::{header, HeaderMap, HeaderValue};
+use sha2::Sha256;
+
+use crate::sid::Sid;
+
+/// The name of the cookie holding the signed session id, it is the one used by the engine.io server
+pub(crate) const COOKIE_NAME: &str = "io";
+
+/// A store shared by several nodes, holding the packets exchanged with the polling sessions.
+///
+/// Packets are stored serialized according to the engine.io protocol, e.g. `4hello` for a message packet.
+/// Implementations are responsible for keeping the packets in order.
+///
+/// The operations are asynchronous so that the stores backed by a remote service can await their network I/O.
+/// The futures are boxed with [`BoxFuture`], stores completing their operations right away,
+/// such as the [`MemoryPollingStore`], can return a [`ready`](future::ready) future.
+pub trait PollingStore: fmt::Debug + Send + Sync + 'static {
+    /// Registers a new session.
+    fn open(&self, sid: Sid) -> BoxFuture<'_, ()>;
+
+    /// Removes a session and its pending packets.
+    fn close(&self, sid: Sid) -> BoxFuture<'_, ()>;
+
+    /// Appends packets to send to the client of a session.
+    /// Returns `false` if the session does not exist.
+    fn push_outgoing(&self, sid: Sid, packets: Vec<String>) -> BoxFuture<'_, bool>;
+
+    /// Removes and returns the first packets to send to the client of a session, in order.
+    ///
+    /// The length of the packets taken, with a separator between each of them, must not exceed `max_payload`,
+    /// except for a single packet larger than it. Returns `None` if the session does not exist.
+    fn take_outgoing(&self, sid: Sid, max_payload: u64) -> BoxFuture<'_, Option<Vec<String>>>;
+
+    /// Appends packets received from the client of a session.
+    /// Returns `false` if the session does not exist.
+    fn push_incoming(&self, sid: Sid, packets: Vec<String>) -> BoxFuture<'_, bool>;
+
+    /// Removes and returns all the packets received from the client of a session, in order.
+    /// Returns `None` if the session does not exist.
+    fn take_incoming(&self, sid: Sid) -> BoxFuture<'_, Option<Incoming>>;
+
+    /// Marks a `GET` request of a session as pending, until [`end_polling`](PollingStore::end_polling) is called.
+    ///
+    /// Returns `false` if another `GET` request is already pending, the session is then marked as overlapped
+    /// for its owner node to close it. Returns `None` if the session does not exist.
+    fn start_polling(&self, sid: Sid) -> BoxFuture<'_, Option<bool>>;
+
+    /// Ends the pending `GET` request of a session.
+    fn end_polling(&self, sid: Sid) -> BoxFuture<'_, ()>;
+}
+
+/// The packets received from the client of a session, taken with [`PollingStore::take_incoming`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Incoming {
+    /// The packets received, in order
+    pub packets: Vec<String>,
+    /// True if two `GET` requests of the session overlapped, see [`PollingStore::start_polling`]
+    pub overlapped: bool,
+}
+
+#[derive(Debug, Default)]
+struct StoredSession {
+    outgoing: VecDeque<String>,
+    incoming: Vec<String>,
+    polling: bool,
+    overlapped: bool,
+}
+
+/// An in-memory [`PollingStore`].
+///
+/// Its clones share the same sessions, it can be used to test a multi-node setup in a single process.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPollingStore {
+    sessions: Arc<Mutex<HashMap<Sid, StoredSession>>>,
+}
+
+impl MemoryPollingStore {
+    /// Creates a new empty [`MemoryPollingStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PollingStore for MemoryPollingStore {
+    fn open(&self, sid: Sid) -> BoxFuture<'_, ()> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(sid, Default::default());
+        future::ready(()).boxed()
+    }
+
+    fn close(&self, sid: Sid) -> BoxFuture<'_, ()> {
+        self.sessions.lock().unwrap().remove(&sid);
+        future::ready(()).boxed()
+    }
+
+    fn push_outgoing(&self, sid: Sid, packets: Vec<String>) -> BoxFuture<'_, bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let pushed = match sessions.get_mut(&sid) {
+            Some(session) => {
+                session.outgoing.extend(packets);
+                true
+            }
+            None => false,
+        };
+        future::ready(pushed).boxed()
+    }
+
+    fn take_outgoing(&self, sid: Sid, max_payload: u64) -> BoxFuture<'_, Option<Vec<String>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let packets = sessions.get_mut(&sid).map(|session| {
+            let mut len = 0;
+            let mut count = 0;
+            for packet in &session.outgoing {
+                // Each packet is preceded by a separator, except the first one
+                len += packet.len() as u64 + (count > 0) as u64;
+                if count > 0 && len > max_payload {
+                    break;
+                }
+                count += 1;
+            }
+            session.outgoing.drain(..count).collect()
+        });
+        future::ready(packets).boxed()
+    }
+
+    fn push_incoming(&self, sid: Sid, packets: Vec<String>) -> BoxFuture<'_, bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let pushed = match sessions.get_mut(&sid) {
+            Some(session) => {
+                session.incoming.extend(packets);
+                true
+            }
+            None => false,
+        };
+        future::ready(pushed).boxed()
+    }
+
+    fn take_incoming(&self, sid: Sid) -> BoxFuture<'_, Option<Incoming>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let incoming = sessions.get_mut(&sid).map(|session| Incoming {
+            packets: std::mem::take(&mut session.incoming),
+            overlapped: session.overlapped,
+        });
+        future::ready(incoming).boxed()
+    }
+
+    fn start_polling(&self, sid: Sid) -> BoxFuture<'_, Option<bool>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let started = sessions.get_mut(&sid).map(|session| {
+            session.overlapped |= session.polling;
+            !std::mem::replace(&mut session.polling, true)
+        });
+        future::ready(started).boxed()
+    }
+
+    fn end_polling(&self, sid: Sid) -> BoxFuture<'_, ()> {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&sid) {
+            session.polling = false;
+        }
+        future::ready(()).boxed()
+    }
+}
+
+/// The config of the polling sessions shared between several nodes, see the [module](self) documentation.
+#[derive(Clone)]
+pub struct SharedPolling {
+    pub(crate) store: Arc<dyn PollingStore>,
+    key: Arc<[u8]>,
+    pub(crate) poll_interval: Duration,
+}
+
+impl SharedPolling {
+    /// Creates a new [`SharedPolling`] config with the store shared by the nodes
+    /// and the key used to sign the session cookies, which must be the same on all the nodes.
+    pub fn new(store: impl PollingStore, key: impl AsRef<[u8]>) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: key.as_ref().into(),
+            poll_interval: Duration::from_millis(50),
+        }
+    }
+
+    /// The interval at which the store is checked for new packets,
+    /// by the pending `GET` requests and by the node that opened the session.
+    ///
+    /// Defaults to 50ms.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    fn mac(&self, sid: Sid) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac accepts any key size");
+        mac.update(sid.to_string().as_bytes());
+        mac
+    }
+
+    /// Returns the `Set-Cookie` header value holding the signed session id
+    pub(crate) fn session_cookie(&self, sid: Sid) -> HeaderValue {
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(sid).finalize().into_bytes());
+        let cookie = format!("{COOKIE_NAME}={sid}.{signature}; Path=/; HttpOnly; SameSite=Lax");
+        HeaderValue::try_from(cookie).expect("session cookie is a valid header value")
+    }
+
+    /// Returns true if the request holds a session cookie correctly signed for the given session id
+    pub(crate) fn verify_cookie(&self, headers: &HeaderMap, sid: Sid) -> bool {
+        let cookies = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'));
+        for cookie in cookies {
+            let Some((name, value)) = cookie.trim().split_once('=') else {
+                continue;
+            };
+            if name != COOKIE_NAME {
+                continue;
+            }
+            let Some((cookie_sid, signature)) = value.split_once('.') else {
+                continue;
+            };
+            if cookie_sid != sid.to_string() {
+                continue;
+            }
+            let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+                continue;
+            };
+            if self.mac(sid).verify_slice(&signature).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl fmt::Debug for SharedPolling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedPolling")
+            .field("store", &self.store)
+            .field("poll_interval", &self.poll_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_store_take_outgoing() {
+        let store = MemoryPollingStore::new();
+        let sid = Sid::new();
+        assert!(!store.push_outgoing(sid, vec!["4hello".into()]).await);
+        assert_eq!(store.take_outgoing(sid, 100).await, None);
+
+        store.open(sid).await;
+        let packets = vec!["4hello".into(), "4world".into(), "2".into()];
+        assert!(store.push_outgoing(sid, packets).await);
+        // "4hello" + separator + "4world" = 13 bytes
+        let packets = store.take_outgoing(sid, 13).await.unwrap();
+        assert_eq!(packets, ["4hello", "4world"]);
+        assert_eq!(store.take_outgoing(sid, 13).await.unwrap(), ["2"]);
+        assert!(store.take_outgoing(sid, 13).await.unwrap().is_empty());
+
+        // A packet larger than the max payload is taken alone
+        let packets = vec!["4hello".into(), "2".into()];
+        store.push_outgoing(sid, packets).await;
+        assert_eq!(store.take_outgoing(sid, 2).await.unwrap(), ["4hello"]);
+
+        store.close(sid).await;
+        assert_eq!(store.take_incoming(sid).await, None);
+    }
+
+    #[tokio::test]
+    async fn memory_store_overlapping_polling() {
+        let store = MemoryPollingStore::new();
+        let sid = Sid::new();
+        assert_eq!(store.start_polling(sid).await, None);
+
+        store.open(sid).await;
+        assert_eq!(store.start_polling(sid).await, Some(true));
+        store.end_polling(sid).await;
+        assert_eq!(store.start_polling(sid).await, Some(true));
+        assert!(!store.take_incoming(sid).await.unwrap().overlapped);
+
+        assert_eq!(store.start_polling(sid).await, Some(false));
+        assert!(store.take_incoming(sid).await.unwrap().overlapped);
+    }
+
+    #[test]
+    fn session_cookie() {
+        let shared = SharedPolling::new(MemoryPollingStore::new(), "key");
+        let sid = Sid::new();
+        let cookie = shared.session_cookie(sid);
+        let value = cookie.to_str().unwrap().split(';').next().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, format!("foo=bar; {value}").parse().unwrap());
+        assert!(shared.verify_cookie(&headers, sid));
+        assert!(!shared.verify_cookie(&headers, Sid::new()));
+
+        let other = SharedPolling::new(MemoryPollingStore::new(), "other key");
+        assert!(!other.verify_cookie(&headers, sid));
+        assert!(!shared.verify_cookie(&HeaderMap::new(), sid));
+    }
+}
@@ -1,4 +1,6 @@
 //! All transports modules available in engineioxide
 
 pub mod polling;
+#[cfg(feature = "webtransport")]
+pub mod webtransport;
 pub mod ws;
@@ -1,25 +1,40 @@
 //! The polling transport module handles polling, post and init requests
-use std::sync::Arc;
+use std::{
+    ops::{Deref, DerefMut},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use bytes::Bytes;
-use futures::StreamExt;
+use futures::{Future, FutureExt, StreamExt};
 use http::{Request, Response, StatusCode};
 use http_body::Body;
 use http_body_util::Full;
+use tokio::sync::OwnedMutexGuard;
 
 use crate::{
-    body::ResponseBody,
+    body::{DropHook, ResponseBody},
     engine::EngineIo,
     errors::Error,
     handler::EngineIoHandler,
     packet::{OpenPacket, Packet},
+    peekable::PeekableReceiver,
     service::{ProtocolVersion, TransportType},
     sid::Sid,
-    transport::polling::payload::Payload,
+    socket::Socket,
     DisconnectReason,
 };
+use payload::Payload;
+
+pub(crate) mod payload;
+#[cfg(feature = "shared-polling")]
+pub(crate) mod shared;
 
-mod payload;
+#[cfg(feature = "compression")]
+pub use payload::Compression;
 
 /// Create a response for http request
 fn http_response<B, D>(
@@ -31,10 +46,11 @@ where
     D: Into<Bytes>,
 {
     use http::header::*;
+    // The content length is not set manually: the body has an exact size hint
+    // that hyper uses to frame the response for both HTTP/1.1 and HTTP/2.
+    // It also stays correct if a middleware alters the body, e.g. to compress it.
     let body: Bytes = data.into();
-    let res = Response::builder()
-        .status(code)
-        .header(CONTENT_LENGTH, body.len());
+    let res = Response::builder().status(code);
     if is_binary {
         res.header(CONTENT_TYPE, "application/octet-stream")
     } else {
@@ -43,7 +59,7 @@ where
     .body(ResponseBody::custom_response(Full::new(body)))
 }
 
-pub fn open_req<H, B, R>(
+pub async fn open_req<H, B, R>(
     engine: Arc<EngineIo<H>>,
     protocol: ProtocolVersion,
     req: Request<R>,
@@ -61,9 +77,26 @@ where
         supports_binary,
     );
 
-    let packet = OpenPacket::new(TransportType::Polling, socket.id, &engine.config);
+    let packet =
+        OpenPacket::new(TransportType::Polling, socket.id, &engine.config).with_session(&socket);
+
+    // The v4 sessions are shared between the nodes, without upgrades as the websocket would be bound to a single node
+    #[cfg(feature = "shared-polling")]
+    let shared = engine
+        .config
+        .shared_polling
+        .as_ref()
+        .filter(|_| protocol == ProtocolVersion::V4);
+    #[cfg(feature = "shared-polling")]
+    let packet = match shared {
+        Some(shared) => {
+            shared::open(engine.clone(), shared, socket.clone()).await;
+            packet.without_upgrades()
+        }
+        None => packet,
+    };
 
-    socket.spawn_heartbeat(engine.config.ping_interval, engine.config.ping_timeout);
+    socket.clone().spawn_heartbeat();
 
     let packet: String = Packet::Open(packet).try_into().unwrap();
     let packet = {
@@ -80,7 +113,16 @@ where
         #[cfg(not(feature = "v3"))]
         packet
     };
-    http_response(StatusCode::OK, packet, false).map_err(Error::Http)
+    let mut res = http_response(StatusCode::OK, packet, false)?;
+    #[cfg(feature = "shared-polling")]
+    if let Some(shared) = shared {
+        res.headers_mut()
+            .insert(http::header::SET_COOKIE, shared.session_cookie(socket.id));
+    }
+    if let Some(hook) = &engine.config.handshake_response_hook {
+        hook.call(&socket.req_parts, res.headers_mut());
+    }
+    Ok(res)
 }
 
 /// Handle http polling request
@@ -91,6 +133,7 @@ pub async fn polling_req<B, H>(
     engine: Arc<EngineIo<H>>,
     protocol: ProtocolVersion,
     sid: Sid,
+    #[cfg(feature = "compression")] compression: payload::Compression,
 ) -> Result<Response<ResponseBody<B>>, Error>
 where
     B: Send + 'static,
@@ -103,7 +146,7 @@ where
 
     // If the socket is already locked, it means that the socket is being used by another request
     // In case of multiple http polling, session should be closed
-    let rx = match socket.internal_rx.try_lock() {
+    let mut rx = match socket.internal_rx.clone().try_lock_owned() {
         Ok(s) => s,
         Err(_) => {
             socket.close(DisconnectReason::MultipleHttpPollingError);
@@ -111,20 +154,231 @@ where
         }
     };
 
+    // The session may have been upgraded before the lock was taken
+    if !socket.is_http() {
+        return Err(Error::TransportMismatch);
+    }
+    // An upgraded transport waits for this payload to be sent, until the response body is dropped
+    let payload_hook = socket.start_polling_payload();
+
     #[cfg(feature = "tracing")]
     tracing::debug!("[sid={sid}] polling request");
 
-    let max_payload = engine.config.max_payload;
+    #[cfg(feature = "compression")]
+    let compressed = compression != payload::Compression::None;
+    #[cfg(not(feature = "compression"))]
+    let compressed = false;
+    if engine.config.stream_polling_payloads
+        && protocol == ProtocolVersion::V4
+        && !engine.config.requeue_unsent_packets
+        && !compressed
+    {
+        let max_payload = socket.max_payload();
+        return stream_polling_payload(engine, sid, rx, max_payload, payload_hook).await;
+    }
+
+    if engine.config.requeue_unsent_packets {
+        rx.start_journal();
+    }
+
+    let encoder = payload::encoder(
+        &mut *rx,
+        protocol,
+        #[cfg(feature = "v3")]
+        socket.supports_binary,
+        socket.max_payload(),
+        &engine.config,
+    );
+    // Tie the encoder span to the session
+    #[cfg(feature = "tracing")]
+    let encoder = tracing::Instrument::instrument(encoder, tracing::debug_span!("polling", %sid));
+    let payload = match catch_encoder_panic(encoder).await {
+        Some(payload) => payload,
+        None => {
+            #[cfg(feature = "tracing")]
+            tracing::error!("[sid={sid}] payload encoder panicked, resetting the receiver");
+            rx.reset();
+            Err(Error::Aborted)
+        }
+    };
+
+    // The journal is stopped before the lock is released so that it never outlives the request
+    let sent = rx.take_journal();
+    let requeue = rx.requeue_handle();
+    drop(rx);
+    let payload = payload?;
+    // The encoders wait for at least one packet before returning
+    debug_assert!(!payload.is_empty(), "empty polling payload");
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        "[sid={sid}] sending {} packets: {:?}",
+        payload.packet_count(),
+        payload.data
+    );
+
+    // The close packet is sent in this payload so the session can be closed right away
+    // rather than waiting for the next polling request to discover the closed channel
+    if payload.closed {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={sid}] close packet sent, closing session");
+        engine.close_session(sid, DisconnectReason::TransportClose);
+    }
+    let has_binary = payload.has_binary;
+    let closed = payload.closed;
+
+    #[cfg(feature = "compression")]
+    let res = {
+        let payload = payload.compress(compression)?;
+        let content_encoding = payload.compression.content_encoding();
+        let mut res = http_response(StatusCode::OK, payload.into_bytes(), has_binary)?;
+        if let Some(encoding) = content_encoding {
+            res.headers_mut().insert(
+                http::header::CONTENT_ENCODING,
+                http::HeaderValue::from_static(encoding),
+            );
+        }
+        res
+    };
+    #[cfg(not(feature = "compression"))]
+    let res = http_response(StatusCode::OK, payload.into_bytes(), has_binary)?;
+
+    let res = res.map(|body| body.with_drop_hook(payload_hook));
+
+    if sent.is_empty() || closed {
+        return Ok(res);
+    }
+    // If the response is dropped before being sent, the packets are pushed back in the buffer
+    Ok(res.map(|body| {
+        body.with_unsent_hook(move || {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={sid}] polling response not sent, requeuing packets");
+            requeue.requeue(sent);
+        })
+    }))
+}
+
+/// Run the payload encoder and catch its panic.
+///
+/// Tokio mutexes are not poisoned when a task panics while holding them: the guard is released
+/// during unwinding and the next polling request can lock the receiver as usual.
+/// However if the panic occurred while measuring a peeked packet, this packet is still in the receiver
+/// and every subsequent polling request would panic again on it and wedge the session.
+///
+/// If the encoder panics, `None` is returned so that the receiver can be [reset](crate::peekable::PeekableReceiver::reset),
+/// discarding only the packet that was being measured, and the in-flight request fails with [`Error::Aborted`].
+/// A packet already consumed by the encoder when it panicked is lost, it is not requeued.
+async fn catch_encoder_panic<F>(encoder: F) -> Option<Result<Payload, Error>>
+where
+    F: Future<Output = Result<Payload, Error>>,
+{
+    AssertUnwindSafe(encoder).catch_unwind().await.ok()
+}
+
+/// The socket receiver locked by a streamed polling payload, until the response body is dropped
+struct StreamedRx<H: EngineIoHandler> {
+    rx: Option<OwnedMutexGuard<PeekableReceiver<Packet>>>,
+    engine: Arc<EngineIo<H>>,
+    sid: Sid,
+    /// Set if the encoder panicked while streaming the payload
+    panicked: Arc<AtomicBool>,
+}
+impl<H: EngineIoHandler> Deref for StreamedRx<H> {
+    type Target = PeekableReceiver<Packet>;
+    fn deref(&self) -> &Self::Target {
+        self.rx.as_ref().unwrap()
+    }
+}
+impl<H: EngineIoHandler> DerefMut for StreamedRx<H> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.rx.as_mut().unwrap()
+    }
+}
+impl<H: EngineIoHandler> Drop for StreamedRx<H> {
+    fn drop(&mut self) {
+        let Some(mut rx) = self.rx.take() else {
+            return;
+        };
+        if self.panicked.load(Ordering::Relaxed) {
+            rx.reset();
+        }
+        let closed = rx.is_closed();
+        drop(rx);
+        // A close packet was streamed, the session can be closed once the receiver is released
+        if closed {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] close packet sent, closing session", self.sid);
+            self.engine
+                .close_session(self.sid, DisconnectReason::TransportClose);
+        }
+    }
+}
+
+/// Stream a v4 payload packet by packet in a chunked response,
+/// see [`stream_polling_payloads`](crate::config::EngineIoConfig::stream_polling_payloads).
+///
+/// The receiver stays locked until the response body is dropped.
+/// The first packet is awaited before returning the response, so that an error is still sent as an http error.
+/// Once the response is returned, an error can only end the payload early.
+async fn stream_polling_payload<B, H>(
+    engine: Arc<EngineIo<H>>,
+    sid: Sid,
+    rx: OwnedMutexGuard<PeekableReceiver<Packet>>,
+    max_payload: u64,
+    payload_hook: DropHook,
+) -> Result<Response<ResponseBody<B>>, Error>
+where
+    H: EngineIoHandler,
+{
+    let panicked = Arc::new(AtomicBool::new(false));
+    let rx = StreamedRx {
+        rx: Some(rx),
+        engine: engine.clone(),
+        sid,
+        panicked: panicked.clone(),
+    };
+    let encoder = payload::v4_stream_encoder(rx, max_payload, &engine.config);
+    let mut encoder = AssertUnwindSafe(Box::pin(encoder)).catch_unwind();
+
+    let first = match encoder.next().await {
+        Some(Ok(Ok(chunk))) => chunk,
+        Some(Ok(Err(e))) => return Err(e),
+        Some(Err(_)) | None => {
+            #[cfg(feature = "tracing")]
+            tracing::error!("[sid={sid}] payload encoder panicked, resetting the receiver");
+            panicked.store(true, Ordering::Relaxed);
+            return Err(Error::Aborted);
+        }
+    };
 
-    #[cfg(feature = "v3")]
-    let Payload { data, has_binary } =
-        payload::encoder(rx, protocol, socket.supports_binary, max_payload).await?;
-    #[cfg(not(feature = "v3"))]
-    let Payload { data, has_binary } = payload::encoder(rx, protocol, max_payload).await?;
+    let chunks = encoder.filter_map(move |chunk| {
+        let chunk = match chunk {
+            Ok(Ok(chunk)) => Some(chunk),
+            Ok(Err(_e)) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={sid}] error while streaming the payload: {_e:?}");
+                None
+            }
+            Err(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::error!("[sid={sid}] payload encoder panicked, resetting the receiver");
+                panicked.store(true, Ordering::Relaxed);
+                None
+            }
+        };
+        futures::future::ready(chunk)
+    });
+    let body = futures::stream::once(futures::future::ready(first))
+        .chain(chunks)
+        .boxed();
 
     #[cfg(feature = "tracing")]
-    tracing::debug!("[sid={sid}] sending data: {:?}", data);
-    Ok(http_response(StatusCode::OK, data, has_binary)?)
+    tracing::debug!("[sid={sid}] streaming payload");
+    let res = Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "text/plain; charset=UTF-8")
+        .body(ResponseBody::stream_response(body).with_drop_hook(payload_hook))?;
+    Ok(res)
 }
 
 /// Handle http polling post request
@@ -153,29 +407,12 @@ where
 
     while let Some(packet) = packets.next().await {
         match packet {
-            Ok(Packet::Close) => {
-                #[cfg(feature = "tracing")]
-                tracing::debug!("[sid={sid}] closing session");
-                socket.send(Packet::Noop)?;
-                engine.close_session(sid, DisconnectReason::TransportClose);
-                break;
-            }
-            Ok(Packet::Pong | Packet::Ping) => socket
-                .heartbeat_tx
-                .try_send(())
-                .map_err(|_| Error::HeartbeatTimeout),
-            Ok(Packet::Message(msg)) => {
-                engine.handler.on_message(msg, socket.clone());
-                Ok(())
-            }
-            Ok(Packet::Binary(bin) | Packet::BinaryV3(bin)) => {
-                engine.handler.on_binary(bin, socket.clone());
-                Ok(())
-            }
-            Ok(p) => {
-                #[cfg(feature = "tracing")]
-                tracing::debug!("[sid={sid}] bad packet received: {:?}", &p);
-                Err(Error::BadPacket(p))
+            Ok(packet) => {
+                let close = packet == Packet::Close;
+                dispatch_packet(&engine, &socket, packet)?;
+                if close {
+                    break;
+                }
             }
             Err(e) => {
                 #[cfg(feature = "tracing")]
@@ -183,7 +420,119 @@ where
                 engine.close_session(sid, DisconnectReason::PacketParsingError);
                 return Err(e);
             }
-        }?;
+        }
     }
     Ok(http_response(StatusCode::OK, "ok", false)?)
 }
+
+/// Dispatches a packet received from the client of a polling session
+fn dispatch_packet<H: EngineIoHandler>(
+    engine: &Arc<EngineIo<H>>,
+    socket: &Arc<Socket<H::Data>>,
+    packet: Packet,
+) -> Result<(), Error> {
+    match packet {
+        Packet::Close => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] closing session", socket.id);
+            socket.send(Packet::Noop)?;
+            engine.close_session(socket.id, DisconnectReason::TransportClose);
+            Ok(())
+        }
+        Packet::Pong | Packet::Ping => socket
+            .heartbeat_tx
+            .try_send(())
+            .map_err(|_| Error::HeartbeatTimeout),
+        Packet::Message(msg) => {
+            engine.handler.on_message(msg.into_owned(), socket.clone());
+            Ok(())
+        }
+        Packet::Binary(bin) | Packet::BinaryV3(bin) => {
+            engine.handler.on_binary(bin, socket.clone());
+            Ok(())
+        }
+        p => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] bad packet received: {:?}", socket.id, &p);
+            Err(Error::BadPacket(p))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::*;
+    use crate::peekable::PeekableReceiver;
+
+    #[tokio::test]
+    async fn encoder_panic_resets_receiver() {
+        let (tx, rx) = mpsc::channel(10);
+        let internal_rx = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("poison".into())).unwrap();
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+
+        let mut rx = internal_rx.lock().await;
+        let encoder = async {
+            rx.peek_many(2);
+            rx.try_recv().unwrap();
+            panic!("encoder panic");
+        };
+        assert!(catch_encoder_panic(encoder).await.is_none());
+        rx.reset();
+
+        // The consumed packet is lost but the peeked packets are still available
+        assert_eq!(rx.try_recv().unwrap(), Packet::Message("hello".into()));
+        assert!(rx.peek().is_none());
+    }
+
+    #[tokio::test]
+    async fn session_max_payload() {
+        let config = crate::config::EngineIoConfig::default();
+        let socket = crate::socket::Socket::<()>::new(
+            crate::sid::Sid::new(),
+            ProtocolVersion::V4,
+            TransportType::Polling,
+            &config,
+            Request::<()>::default().into_parts().0,
+            Box::new(|_, _| {}),
+            #[cfg(feature = "v3")]
+            false,
+        );
+        let encode = || async {
+            let rx = socket.internal_rx.lock().await;
+            #[cfg(feature = "v3")]
+            let payload = payload::encoder(
+                rx,
+                ProtocolVersion::V4,
+                false,
+                socket.max_payload(),
+                &config,
+            );
+            #[cfg(not(feature = "v3"))]
+            let payload = payload::encoder(rx, ProtocolVersion::V4, socket.max_payload(), &config);
+            payload.await.unwrap().data
+        };
+        assert_eq!(socket.max_payload(), config.max_payload);
+
+        socket.emit("hello".into()).unwrap();
+        socket.emit("hello".into()).unwrap();
+        assert_eq!(encode().await, "4hello\x1e4hello");
+
+        // The new limit is observed by the next encoding without re-creating the session
+        socket.set_max_payload(8);
+        socket.emit("hello".into()).unwrap();
+        socket.emit("hello".into()).unwrap();
+        assert_eq!(encode().await, "4hello");
+        assert_eq!(encode().await, "4hello");
+    }
+
+    #[tokio::test]
+    async fn encoder_result_is_forwarded() {
+        let res = catch_encoder_panic(async { Err(Error::Aborted) }).await;
+        assert!(matches!(res, Some(Err(Error::Aborted))));
+        let res = catch_encoder_panic(async { Ok(Payload::new("4hello", false)) }).await;
+        assert!(matches!(res, Some(Ok(Payload { ref data, .. })) if data == "4hello"));
+    }
+}
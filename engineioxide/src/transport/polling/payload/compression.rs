@@ -0,0 +1,180 @@
+//! ## Compression of http polling payloads
+//!
+//! The compression is negotiated with the `Accept-Encoding` header of the polling request.
+//! Payloads are compressed once fully encoded so the `max_payload` limit still applies to the uncompressed size.
+
+use std::io::Write;
+
+use bytes::Bytes;
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression as Level,
+};
+use http::{header::ACCEPT_ENCODING, HeaderMap};
+
+use crate::errors::Error;
+
+/// The compression algorithm applied to a [`Payload`](super::Payload)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// The payload is sent as is
+    #[default]
+    None,
+    /// The payload is compressed with gzip
+    Gzip,
+    /// The payload is compressed with deflate
+    Deflate,
+}
+
+impl Compression {
+    /// Get the compression advertised by the client in the `Accept-Encoding` header.
+    ///
+    /// Gzip is preferred over deflate if both are accepted.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut compression = Compression::None;
+        let encodings = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for encoding in encodings {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            // An encoding with a zero quality value is not accepted
+            let rejected = parts.any(|p| matches!(p, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            match name {
+                "gzip" if !rejected => return Compression::Gzip,
+                "deflate" if !rejected => compression = Compression::Deflate,
+                _ => (),
+            }
+        }
+        compression
+    }
+
+    /// The value of the `Content-Encoding` header for this compression
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Deflate => Some("deflate"),
+        }
+    }
+
+    /// Compress the given data with this compression algorithm
+    pub fn compress(&self, data: Bytes) -> Result<Bytes, Error> {
+        let data = match self {
+            Compression::None => return Ok(data),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(&data)?;
+                encoder.finish()?
+            }
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(&data)?;
+                encoder.finish()?
+            }
+        };
+        Ok(data.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use http::HeaderValue;
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        packet::Packet,
+        peekable::PeekableReceiver,
+        transport::polling::payload::{encoder::v4_encoder, Payload},
+    };
+
+    fn headers(accept_encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+        headers
+    }
+
+    async fn encode_v4_payload() -> Payload {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello€".repeat(10).into()))
+            .unwrap();
+        tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
+        tx.try_send(Packet::Message("hello€".repeat(10).into()))
+            .unwrap();
+        let rx = mutex.lock().await;
+        v4_encoder(rx, 1e6 as u64, None, 32, None, Default::default())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn from_headers() {
+        assert_eq!(
+            Compression::from_headers(&HeaderMap::new()),
+            Compression::None
+        );
+        assert_eq!(Compression::from_headers(&headers("br")), Compression::None);
+        assert_eq!(
+            Compression::from_headers(&headers("gzip")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_headers(&headers("deflate, gzip;q=1.0, *;q=0.5")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_headers(&headers("gzip;q=0, deflate")),
+            Compression::Deflate
+        );
+        assert_eq!(
+            Compression::from_headers(&headers("gzip;q=0")),
+            Compression::None
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_round_trip_v4() {
+        let payload = encode_v4_payload().await;
+        let raw = payload.data.clone();
+        let payload = payload.compress(Compression::Gzip).unwrap();
+        assert_eq!(payload.compression, Compression::Gzip);
+        assert!(payload.data.len() < raw.len());
+
+        let mut data = Vec::new();
+        GzDecoder::new(&payload.data[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, raw);
+    }
+
+    #[tokio::test]
+    async fn deflate_round_trip_v4() {
+        let payload = encode_v4_payload().await;
+        let raw = payload.data.clone();
+        let payload = payload.compress(Compression::Deflate).unwrap();
+        assert_eq!(payload.compression, Compression::Deflate);
+        assert!(payload.data.len() < raw.len());
+
+        let mut data = Vec::new();
+        DeflateDecoder::new(&payload.data[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, raw);
+    }
+
+    #[tokio::test]
+    async fn no_compression() {
+        let payload = encode_v4_payload().await;
+        let raw = payload.data.clone();
+        let payload = payload.compress(Compression::None).unwrap();
+        assert_eq!(payload.compression, Compression::None);
+        assert_eq!(payload.data, raw);
+    }
+}
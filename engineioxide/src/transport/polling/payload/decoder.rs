@@ -61,12 +61,16 @@ where
             Err(Error::HttpErrorResponse(StatusCode::BAD_REQUEST))
         }
     }?;
-    if state.current_payload_size + (data.remaining() as u64) <= max_payload {
-        state.current_payload_size += data.remaining() as u64;
+    let size = state.current_payload_size + data.remaining() as u64;
+    if size <= max_payload {
+        state.current_payload_size = size;
         state.buffer.push(data);
         Ok(())
     } else {
-        Err(Error::PayloadTooLarge)
+        Err(Error::PayloadTooLarge {
+            size,
+            max: max_payload,
+        })
     }
 }
 
@@ -376,7 +380,7 @@ mod tests {
             payload.next().await.unwrap().unwrap(),
             Packet::Message(msg) if msg == "f"
         ));
-        assert_eq!(payload.next().await.is_none(), true);
+        assert!(payload.next().await.is_none());
     }
 
     #[tokio::test]
@@ -403,7 +407,7 @@ mod tests {
                 payload.next().await.unwrap().unwrap(),
                 Packet::Message(msg) if msg == "fo"
             ));
-            assert_eq!(payload.next().await.is_none(), true);
+            assert!(payload.next().await.is_none());
         }
     }
 
@@ -420,15 +424,13 @@ mod tests {
             let payload = v4_decoder(stream, MAX_PAYLOAD);
             futures::pin_mut!(payload);
             let packet = payload.next().await.unwrap();
-            assert!(matches!(packet, Err(Error::PayloadTooLarge)));
+            assert!(matches!(packet, Err(Error::PayloadTooLarge { .. })));
         }
     }
 
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn string_payload_iterator_v3() {
-        assert!(cfg!(feature = "v3"));
-
         let data = Full::new(Bytes::from("4:4foo3:4€f11:4faaaaaaaaa"));
         let payload = v3_string_decoder(data, MAX_PAYLOAD);
         futures::pin_mut!(payload);
@@ -450,8 +452,6 @@ mod tests {
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn binary_payload_iterator_v3() {
-        assert!(cfg!(feature = "v3"));
-
         const PAYLOAD: &[u8] = &[
             0, 9, 255, 52, 104, 101, 108, 108, 111, 226, 130, 172, 1, 5, 255, 4, 1, 2, 3, 4,
         ];
@@ -473,7 +473,6 @@ mod tests {
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn string_payload_stream_v3() {
-        assert!(cfg!(feature = "v3"));
         const DATA: &[u8] = "4:4foo3:4€f11:4baaaaaaaar".as_bytes();
         for i in 1..DATA.len() {
             println!("payload stream v3 chunk size: {i}");
@@ -504,8 +503,6 @@ mod tests {
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn binary_payload_stream_v3() {
-        assert!(cfg!(feature = "v3"));
-
         const PAYLOAD: &[u8] = &[
             0, 9, 255, 52, 104, 101, 108, 108, 111, 226, 130, 172, 1, 5, 255, 4, 1, 2, 3, 4,
         ];
@@ -536,7 +533,6 @@ mod tests {
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn max_payload_v3() {
-        assert!(cfg!(feature = "v3"));
         const DATA: &[u8] = "4:4foo3:4€f11:4baaaaaaaar".as_bytes();
         const MAX_PAYLOAD: u64 = 3;
         for i in 1..DATA.len() {
@@ -548,7 +544,7 @@ mod tests {
             let payload = v3_binary_decoder(stream, MAX_PAYLOAD);
             futures::pin_mut!(payload);
             let packet = payload.next().await.unwrap();
-            assert!(matches!(packet, Err(Error::PayloadTooLarge)));
+            assert!(matches!(packet, Err(Error::PayloadTooLarge { .. })));
         }
         for i in 1..DATA.len() {
             let stream = StreamBody::new(futures::stream::iter(
@@ -559,7 +555,7 @@ mod tests {
             let payload = v3_string_decoder(stream, MAX_PAYLOAD);
             futures::pin_mut!(payload);
             let packet = payload.next().await.unwrap();
-            assert!(matches!(packet, Err(Error::PayloadTooLarge)));
+            assert!(matches!(packet, Err(Error::PayloadTooLarge { .. })));
         }
     }
 }
@@ -7,10 +7,16 @@
 //!    * binary encoder (used when there is binary packets and the client supports binary)
 //!
 
-use tokio::sync::MutexGuard;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::Stream;
+use std::{borrow::Cow, ops::DerefMut, time::Duration};
+use tokio::time::Instant;
 
+#[cfg(feature = "v3")]
+use crate::config::V3EncoderOptions;
 use crate::{
-    errors::Error, packet::Packet, peekable::PeekableReceiver, transport::polling::payload::Payload,
+    config::OversizePolicy, errors::Error, packet::Packet, peekable::PeekableReceiver,
+    service::ProtocolVersion, transport::polling::payload::Payload,
 };
 
 /// Try to immediately poll a new packet from the rx channel and check that the new packet can be added to the payload
@@ -22,15 +28,25 @@ use crate::{
 /// * `rx` - The channel to poll
 /// * `payload_len` - The current payload length
 /// * `max_payload` - The maximum payload length
+/// * `packet_count` - The current number of packets in the payload
+/// * `max_packets` - The maximum number of packets in the payload, if any
 /// * `b64` - If binary packets should be encoded in base64
 fn try_recv_packet(
-    rx: &mut MutexGuard<'_, PeekableReceiver<Packet>>,
+    rx: &mut PeekableReceiver<Packet>,
     payload_len: usize,
     max_payload: u64,
+    packet_count: usize,
+    max_packets: Option<usize>,
     b64: bool,
 ) -> Option<Packet> {
-    if let Some(packet) = rx.peek() {
-        if (payload_len + packet.get_size_hint(b64)) as u64 > max_payload {
+    if matches!(max_packets, Some(max) if packet_count >= max) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("packet limit reached, stopping encoding for this payload");
+        return None;
+    }
+
+    if let Some(size_hint) = rx.peek_size_hint(b64) {
+        if (payload_len + size_hint) as u64 > max_payload {
             #[cfg(feature = "tracing")]
             tracing::debug!("payload too big, stopping encoding for this payload");
             return None;
@@ -51,10 +67,66 @@ fn try_recv_packet(
     packet
 }
 
+/// Check that a packet alone can fit in a payload
+///
+/// If it is not the case the packet can never be sent, so an [`Error::PayloadTooLarge`] is returned
+fn check_packet_size(packet: &Packet, max_payload: u64, b64: bool) -> Result<(), Error> {
+    let size = packet.get_size_hint(b64) as u64;
+    if size > max_payload {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("packet too big to fit in a payload: {size} > {max_payload}");
+        Err(Error::PayloadTooLarge {
+            size,
+            max: max_payload,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Truncate a message on a UTF-8 char boundary so that the message packet fits in `max_payload`
+fn truncate_message(msg: &mut Cow<'static, str>, max_payload: u64) {
+    // The packet type takes one byte
+    let max_len = usize::try_from(max_payload.saturating_sub(1)).unwrap_or(usize::MAX);
+    if msg.len() <= max_len {
+        return;
+    }
+    let mut len = max_len;
+    while !msg.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!("truncating message from {} to {len} bytes", msg.len());
+    match msg {
+        Cow::Borrowed(s) => *s = &s[..len],
+        Cow::Owned(s) => s.truncate(len),
+    }
+}
+
 /// Same as [`try_recv_packet`]
 /// but wait for a new packet if there is no packet in the buffer
-async fn recv_packet(rx: &mut MutexGuard<'_, PeekableReceiver<Packet>>) -> Result<Packet, Error> {
-    let packet = rx.recv().await.ok_or(Error::Aborted)?;
+///
+/// If the received packet is larger than `max_payload`, the [`OversizePolicy`] is applied.
+/// If the packet is dropped an [`Error::PayloadTooLarge`] is returned
+///
+/// # Cancel safety
+/// This function is cancel safe: if the polling request is dropped while waiting,
+/// no packet is consumed from the channel. Everything after the only await point
+/// (including the [`Packet::Close`] handling) runs only if a packet was actually received.
+async fn recv_packet(
+    rx: &mut PeekableReceiver<Packet>,
+    max_payload: u64,
+    b64: bool,
+    oversize_policy: OversizePolicy,
+) -> Result<Packet, Error> {
+    let mut packet = rx.recv().await.ok_or(Error::Aborted)?;
+    if let (OversizePolicy::TruncateAtCharBoundary, Packet::Message(msg)) =
+        (oversize_policy, &mut packet)
+    {
+        truncate_message(msg, max_payload);
+    }
+    check_packet_size(&packet, max_payload, b64)?;
     if packet == Packet::Close {
         #[cfg(feature = "tracing")]
         tracing::debug!("Received close packet, closing channel");
@@ -66,68 +138,436 @@ async fn recv_packet(rx: &mut MutexGuard<'_, PeekableReceiver<Packet>>) -> Resul
     Ok(packet)
 }
 
+/// Check that the packet variant matches the protocol version implemented by the encoder
+///
+/// A [`Packet::BinaryV3`] can only be encoded by the v3 encoders
+/// and a [`Packet::Binary`] can only be encoded by the v4 encoders.
+/// Otherwise an [`Error::ProtocolMismatch`] is returned
+fn check_packet_protocol(packet: &Packet, protocol: ProtocolVersion) -> Result<(), Error> {
+    match (packet, protocol) {
+        (Packet::BinaryV3(_), ProtocolVersion::V4) | (Packet::Binary(_), ProtocolVersion::V3) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("packet {packet:?} cannot be encoded with {protocol:?} encoder");
+            Err(Error::ProtocolMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check if the packet should end the payload:
+/// nothing can be sent after a close packet and a noop packet must be sent right away
+/// so that the client can finish its websocket upgrade
+fn ends_payload(packet: &Packet) -> bool {
+    matches!(packet, Packet::Close | Packet::Noop)
+}
+
+/// Cooperatively yield back to the runtime every `yield_interval` encoded packets,
+/// so that draining a flooded channel doesn't starve the other tasks.
+///
+/// A `yield_interval` of 0 never yields
+async fn yield_every(packet_count: usize, yield_interval: usize) {
+    if yield_interval > 0 && packet_count % yield_interval == 0 {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Record the final payload length and packet count on the current encoder span
+#[inline]
+fn record_payload(data: &BytesMut, packet_count: usize) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::Span::current();
+        span.record("payload_len", data.len());
+        span.record("packet_count", packet_count);
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (data, packet_count);
+}
+
+/// Encode a packet into a v4 payload chunk.
+///
+/// Binary packets are base64 encoded with the `b` prefix, as a v4 payload has no framing for raw binary data.
+fn v4_packet_encoder(packet: Packet, data: &mut BytesMut) -> Result<(), Error> {
+    match packet {
+        // Fast path for the upgrade completion
+        Packet::Noop => data.put_u8(b'6'),
+        packet => {
+            let packet: String = packet.try_into()?;
+            data.extend_from_slice(packet.as_bytes());
+        }
+    }
+    Ok(())
+}
+
 /// Encode multiple packets into a string payload according to the
 /// [engine.io v4 protocol](https://socket.io/fr/docs/v4/engine-io-protocol/#http-long-polling-1)
+///
+/// If a `flush_timeout` is set, once there is at least one packet in the payload,
+/// the encoder will keep waiting for new packets until the timeout elapses or the `max_payload` is reached.
+///
+/// While draining the channel, the encoder yields back to the runtime every `yield_interval` packets.
+///
+/// If `max_packets` is set, no more than `max_packets` packets are encoded in the payload.
+/// The remaining packets are left in the channel for the next payload.
+///
+/// The `oversize_policy` is applied to a message that cannot fit alone in a payload.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        name = "encoder",
+        skip_all,
+        fields(
+            version = "v4",
+            max_payload,
+            payload_len = tracing::field::Empty,
+            packet_count = tracing::field::Empty
+        )
+    )
+)]
 pub async fn v4_encoder(
-    mut rx: MutexGuard<'_, PeekableReceiver<Packet>>,
+    mut rx: impl DerefMut<Target = PeekableReceiver<Packet>>,
     max_payload: u64,
+    max_packets: Option<usize>,
+    yield_interval: usize,
+    flush_timeout: Option<Duration>,
+    oversize_policy: OversizePolicy,
 ) -> Result<Payload, Error> {
     use crate::transport::polling::payload::PACKET_SEPARATOR_V4;
 
-    #[cfg(feature = "tracing")]
-    tracing::debug!("encoding payload with v4 encoder");
-    let mut data: String = String::new();
+    let mut data = BytesMut::new();
+    let mut packet_count = 0;
+    // Set if a close packet is encoded in the payload
+    let mut closed = false;
+    // Set if a packet ending the payload is encoded
+    let mut ended = false;
 
-    // Send all packets in the buffer
+    // Send all packets in the buffer.
+    // The packets are peeked by batches so that the payload boundary is computed in one pass
     const PUNCTUATION_LEN: usize = 1;
-    while let Some(packet) =
-        try_recv_packet(&mut rx, data.len() + PUNCTUATION_LEN, max_payload, true)
-    {
-        let packet: String = packet.try_into()?;
+    const PEEK_BATCH_LEN: usize = 32;
+    loop {
+        let batch_len = match max_packets {
+            Some(max) => PEEK_BATCH_LEN.min(max.saturating_sub(packet_count)),
+            None => PEEK_BATCH_LEN,
+        };
+        let batch_len = rx.peek_many(batch_len).len();
+        let mut payload_len = data.len();
+        let count = (0..batch_len)
+            .take_while(|&i| {
+                let size = rx.peeked_size_hint(i, true).unwrap_or_default();
+                // Same boundary as `try_recv_packet`: the separator is counted even before the first packet
+                let fits = (payload_len + PUNCTUATION_LEN + size) as u64 <= max_payload;
+                if payload_len > 0 {
+                    payload_len += PUNCTUATION_LEN;
+                }
+                payload_len += size;
+                fits
+            })
+            .count();
+
+        for _ in 0..count {
+            let packet = rx.try_recv()?;
+            check_packet_protocol(&packet, ProtocolVersion::V4)?;
+            packet_count += 1;
+            if packet == Packet::Close {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("Received close packet, closing channel");
+                closed = true;
+                rx.close();
+            }
+            ended = ends_payload(&packet);
 
-        if !data.is_empty() {
-            data.push(std::char::from_u32(PACKET_SEPARATOR_V4 as u32).unwrap());
+            if !data.is_empty() {
+                data.put_u8(PACKET_SEPARATOR_V4);
+            }
+            v4_packet_encoder(packet, &mut data)?;
+            if ended {
+                break;
+            }
+            yield_every(packet_count, yield_interval).await;
+        }
+
+        // Stop if the payload is full or if there is no more packet in the buffer
+        if ended || count < PEEK_BATCH_LEN {
+            break;
         }
-        data.push_str(&packet);
     }
 
     // If there is no packet in the buffer, wait for the next packet
     if data.is_empty() {
-        let packet = recv_packet(&mut rx).await?;
-        let packet: String = packet.try_into()?;
-        data.push_str(&packet);
+        let packet = recv_packet(&mut rx, max_payload, true, oversize_policy).await?;
+        check_packet_protocol(&packet, ProtocolVersion::V4)?;
+        packet_count += 1;
+        closed |= packet == Packet::Close;
+        ended = ends_payload(&packet);
+        v4_packet_encoder(packet, &mut data)?;
+    }
+
+    // Coalesce the packets received before the flush timeout
+    if let (Some(flush_timeout), false) = (flush_timeout, ended) {
+        let deadline = tokio::time::Instant::now() + flush_timeout;
+        while let Ok(Some(_)) = tokio::time::timeout_at(deadline, rx.peek_wait()).await {
+            let Some(packet) = try_recv_packet(
+                &mut rx,
+                data.len() + PUNCTUATION_LEN,
+                max_payload,
+                packet_count,
+                max_packets,
+                true,
+            ) else {
+                break;
+            };
+            check_packet_protocol(&packet, ProtocolVersion::V4)?;
+            packet_count += 1;
+            closed |= packet == Packet::Close;
+            let ended = ends_payload(&packet);
+            data.put_u8(PACKET_SEPARATOR_V4);
+            v4_packet_encoder(packet, &mut data)?;
+            if ended {
+                break;
+            }
+            yield_every(packet_count, yield_interval).await;
+        }
+    }
+
+    record_payload(&data, packet_count);
+    Ok(Payload {
+        closed,
+        packet_count,
+        ..Payload::new(data.freeze(), false)
+    })
+}
+
+/// Phase of a [`v4_stream_encoder`]
+enum V4StreamPhase {
+    /// Taking the buffered packets, or waiting for the first one
+    Drain,
+    /// Coalescing the packets received until the end of the flush timeout, if any
+    Flush(Option<Instant>),
+}
+
+/// State of a [`v4_stream_encoder`] between two chunks
+struct V4StreamState<R> {
+    rx: R,
+    phase: V4StreamPhase,
+    /// The length of the chunks already yielded
+    payload_len: usize,
+    packet_count: usize,
+    ended: bool,
+}
+
+/// Get the next packet of a streamed v4 payload, with the same boundary as the [`v4_encoder`]:
+/// the buffered packets are taken while they fit in the payload,
+/// the first packet is awaited and the packets received before the flush timeout are coalesced.
+///
+/// Returns `None` once the payload is complete.
+async fn v4_stream_next_packet<R>(
+    state: &mut V4StreamState<R>,
+    max_payload: u64,
+    max_packets: Option<usize>,
+    flush_timeout: Option<Duration>,
+    oversize_policy: OversizePolicy,
+) -> Result<Option<Packet>, Error>
+where
+    R: DerefMut<Target = PeekableReceiver<Packet>>,
+{
+    const PUNCTUATION_LEN: usize = 1;
+    let rx = &mut *state.rx;
+    let payload_len = state.payload_len + PUNCTUATION_LEN;
+
+    if let V4StreamPhase::Drain = state.phase {
+        let packet = try_recv_packet(
+            rx,
+            payload_len,
+            max_payload,
+            state.packet_count,
+            max_packets,
+            true,
+        );
+        if packet.is_some() {
+            return Ok(packet);
+        }
+        // If there is no packet in the buffer, wait for the next packet
+        let packet = match state.packet_count {
+            0 => Some(recv_packet(rx, max_payload, true, oversize_policy).await?),
+            _ => None,
+        };
+        state.phase = V4StreamPhase::Flush(flush_timeout.map(|timeout| Instant::now() + timeout));
+        if packet.is_some() {
+            return Ok(packet);
+        }
     }
 
-    Ok(Payload::new(data, false))
+    let V4StreamPhase::Flush(Some(deadline)) = state.phase else {
+        return Ok(None);
+    };
+    match tokio::time::timeout_at(deadline, rx.peek_wait()).await {
+        Ok(Some(_)) => Ok(try_recv_packet(
+            rx,
+            payload_len,
+            max_payload,
+            state.packet_count,
+            max_packets,
+            true,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Stream a payload according to the
+/// [engine.io v4 protocol](https://socket.io/fr/docs/v4/engine-io-protocol/#http-long-polling-1)
+/// packet by packet, rather than encoding it in memory like the [`v4_encoder`].
+///
+/// Each chunk is a packet preceded by the packet separator, except for the first one.
+/// The stream ends once the next packet would exceed the `max_payload`,
+/// with the same boundary as the [`v4_encoder`]. Binary packets are base64 encoded.
+///
+/// The `max_packets`, `flush_timeout` and `oversize_policy` parameters behave like for the [`v4_encoder`].
+/// The stream ends after yielding an error.
+pub fn v4_stream_encoder<R>(
+    rx: R,
+    max_payload: u64,
+    max_packets: Option<usize>,
+    flush_timeout: Option<Duration>,
+    oversize_policy: OversizePolicy,
+) -> impl Stream<Item = Result<Bytes, Error>>
+where
+    R: DerefMut<Target = PeekableReceiver<Packet>>,
+{
+    use crate::transport::polling::payload::PACKET_SEPARATOR_V4;
+
+    let state = V4StreamState {
+        rx,
+        phase: V4StreamPhase::Drain,
+        payload_len: 0,
+        packet_count: 0,
+        ended: false,
+    };
+    futures::stream::unfold(state, move |mut state| async move {
+        if state.ended {
+            return None;
+        }
+        let packet = v4_stream_next_packet(
+            &mut state,
+            max_payload,
+            max_packets,
+            flush_timeout,
+            oversize_policy,
+        )
+        .await;
+        let chunk = packet.and_then(|packet| {
+            let Some(packet) = packet else {
+                return Ok(None);
+            };
+            check_packet_protocol(&packet, ProtocolVersion::V4)?;
+            state.ended = ends_payload(&packet);
+
+            let mut data = BytesMut::new();
+            if state.packet_count > 0 {
+                data.put_u8(PACKET_SEPARATOR_V4);
+            }
+            v4_packet_encoder(packet, &mut data)?;
+            state.packet_count += 1;
+            state.payload_len += data.len();
+            Ok(Some(data.freeze()))
+        });
+        match chunk {
+            Ok(Some(chunk)) => Some((Ok(chunk), state)),
+            Ok(None) => None,
+            Err(e) => {
+                state.ended = true;
+                Some((Err(e), state))
+            }
+        }
+    })
+}
+
+/// Decode a payload produced by the v4 polling encoder back into its packets.
+///
+/// Packets are split on the v4 packet separator, binary packets are detected with their `b` prefix
+/// and decoded from base64.
+#[cfg_attr(not(feature = "test-utils"), allow(dead_code))]
+pub fn decode_v4_payload(data: &[u8]) -> Result<Vec<Packet>, Error> {
+    use crate::transport::polling::payload::PACKET_SEPARATOR_V4;
+    use base64::{engine::general_purpose, Engine};
+
+    data.split(|b| *b == PACKET_SEPARATOR_V4)
+        .map(|packet| match packet.first() {
+            Some(b'b') => Ok(Packet::Binary(
+                general_purpose::STANDARD.decode(&packet[1..])?,
+            )),
+            _ => Packet::try_from(std::str::from_utf8(packet)?),
+        })
+        .collect()
+}
+
+/// Get the packet type byte of a packet encoded in a v3 *binary* payload, derived from its variant
+///
+/// The [`Packet::Binary`] variant is specific to the v4 protocol and cannot be encoded
+/// in a v3 binary payload, an [`Error::InvalidPacketType`] is returned
+#[cfg(feature = "v3")]
+fn v3_bin_packet_type(packet: &Packet) -> Result<u8, Error> {
+    match packet {
+        Packet::Open(_) => Ok(0x00),
+        Packet::Close => Ok(0x01),
+        Packet::Ping | Packet::PingUpgrade => Ok(0x02),
+        Packet::Pong | Packet::PongUpgrade => Ok(0x03),
+        Packet::Message(_) | Packet::BinaryV3(_) => Ok(0x04),
+        Packet::Upgrade => Ok(0x05),
+        Packet::Noop => Ok(0x06),
+        Packet::Binary(_) => Err(Error::InvalidPacketType(None)),
+    }
 }
 
 /// Encode one packet into a *binary* payload according to the
 /// [engine.io v3 protocol](https://github.com/socketio/engine.io-protocol/tree/v3#payload)
 #[cfg(feature = "v3")]
-pub fn v3_bin_packet_encoder(packet: Packet, data: &mut Vec<u8>) -> Result<(), Error> {
-    use crate::transport::polling::payload::BINARY_PACKET_SEPARATOR_V3;
+#[cfg_attr(not(feature = "test-utils"), allow(dead_code))]
+pub fn v3_bin_packet_encoder(packet: Packet, data: &mut BytesMut) -> Result<(), Error> {
+    v3_bin_packet_encoder_with_buf(
+        packet,
+        data,
+        &mut String::new(),
+        &V3EncoderOptions::default(),
+    )
+}
+
+/// Same as [`v3_bin_packet_encoder`] but string packets are serialized in the given scratch buffer.
+///
+/// The buffer is cleared before being used so it can be shared between consecutive packets
+#[cfg(feature = "v3")]
+pub fn v3_bin_packet_encoder_with_buf(
+    packet: Packet,
+    data: &mut BytesMut,
+    buf: &mut String,
+    opts: &V3EncoderOptions,
+) -> Result<(), Error> {
+    let packet_type = v3_bin_packet_type(&packet)?;
     match packet {
-        Packet::BinaryV3(bin) => {
-            data.push(0x1);
+        Packet::BinaryV3(ref bin) => {
+            data.put_u8(0x1);
 
             let len = (bin.len() + 1).to_string();
             for char in len.chars() {
-                data.push(char as u8 - 48);
+                data.put_u8(char as u8 - 48);
             }
-            data.push(BINARY_PACKET_SEPARATOR_V3); // separator
-            data.push(0x04); // message packet type
-            data.extend_from_slice(&bin); // raw data
+            data.put_u8(opts.binary_separator); // separator
+            data.put_u8(packet_type);
+            data.extend_from_slice(bin); // raw data
         }
         packet => {
-            let packet: String = packet.try_into()?;
-            data.push(0x0); // 0 = string
+            buf.clear();
+            packet.encode_into(buf)?;
+            debug_assert_eq!(buf.as_bytes().first(), Some(&(b'0' + packet_type)));
+            data.put_u8(0x0); // 0 = string
 
-            let len = packet.len().to_string();
+            let len = buf.len().to_string();
             for char in len.chars() {
-                data.push(char as u8 - 48);
+                data.put_u8(char as u8 - 48);
             }
-            data.push(BINARY_PACKET_SEPARATOR_V3); // separator
-            data.extend_from_slice(packet.as_bytes()); // packet
+            data.put_u8(opts.binary_separator); // separator
+            data.extend_from_slice(buf.as_bytes()); // packet
         }
     };
     Ok(())
@@ -136,13 +576,21 @@ pub fn v3_bin_packet_encoder(packet: Packet, data: &mut Vec<u8>) -> Result<(), E
 /// Encode one packet into a *string* payload according to the
 /// [engine.io v3 protocol](https://github.com/socketio/engine.io-protocol/tree/v3#payload)
 #[cfg(feature = "v3")]
-pub fn v3_string_packet_encoder(packet: Packet, data: &mut Vec<u8>) -> Result<(), Error> {
-    use crate::transport::polling::payload::STRING_PACKET_SEPARATOR_V3;
+pub fn v3_string_packet_encoder(
+    packet: Packet,
+    data: &mut BytesMut,
+    opts: &V3EncoderOptions,
+) -> Result<(), Error> {
+    // Fast path for the upgrade completion
+    if packet == Packet::Noop {
+        data.extend_from_slice(&[b'1', opts.string_separator, b'6']);
+        return Ok(());
+    }
     let packet: String = packet.try_into()?;
     let packet = format!(
         "{}{}{}",
         packet.chars().count(),
-        STRING_PACKET_SEPARATOR_V3 as char,
+        opts.string_separator as char,
         packet
     );
     data.extend_from_slice(packet.as_bytes());
@@ -152,92 +600,179 @@ pub fn v3_string_packet_encoder(packet: Packet, data: &mut Vec<u8>) -> Result<()
 /// Encode multiple packet packet into a *string* payload if there is no binary packet or into a *binary* payload if there is binary packets
 /// according to the [engine.io v3 protocol](https://github.com/socketio/engine.io-protocol/tree/v3#payload)
 #[cfg(feature = "v3")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        name = "encoder",
+        skip_all,
+        fields(
+            version = "v3_binary",
+            max_payload,
+            payload_len = tracing::field::Empty,
+            packet_count = tracing::field::Empty
+        )
+    )
+)]
 pub async fn v3_binary_encoder(
-    mut rx: MutexGuard<'_, PeekableReceiver<Packet>>,
+    mut rx: impl DerefMut<Target = PeekableReceiver<Packet>>,
     max_payload: u64,
+    max_packets: Option<usize>,
+    yield_interval: usize,
+    opts: &V3EncoderOptions,
 ) -> Result<Payload, Error> {
-    let mut data: Vec<u8> = Vec::new();
+    let mut data = BytesMut::new();
+    // Set if a close packet is encoded in the payload
+    let mut closed = false;
     let mut packet_buffer: Vec<Packet> = Vec::new();
 
     // estimated size of the `packet_buffer` in bytes
     let mut estimated_size: usize = 0;
-    // number of digits of the max packet size, used to approximate the payload size
-    let max_packet_size_len = max_payload.checked_ilog10().unwrap_or(0) as usize + 1;
 
-    #[cfg(feature = "tracing")]
-    tracing::debug!("encoding payload with v3 binary encoder");
     // buffer all packets to find if there is binary packets
     let mut has_binary = false;
 
-    while let Some(packet) = try_recv_packet(&mut rx, estimated_size, max_payload, false) {
+    while let Some(packet) = try_recv_packet(
+        &mut rx,
+        estimated_size,
+        max_payload,
+        packet_buffer.len(),
+        max_packets,
+        false,
+    ) {
+        check_packet_protocol(&packet, ProtocolVersion::V3)?;
         if packet.is_binary() {
             has_binary = true;
         }
 
         const PUNCTUATION_LEN: usize = 2;
-        estimated_size += packet.get_size_hint(false) + max_packet_size_len + PUNCTUATION_LEN;
+        let size_hint = packet.get_size_hint(false);
+        // number of digits of the packet length, written before each packet
+        let packet_size_len = opts
+            .max_packet_size_len
+            .unwrap_or(size_hint.checked_ilog10().unwrap_or(0) as usize + 1);
+        estimated_size += size_hint + packet_size_len + PUNCTUATION_LEN;
 
+        closed |= packet == Packet::Close;
+        let ended = ends_payload(&packet);
         packet_buffer.push(packet);
+        if ended {
+            break;
+        }
+        yield_every(packet_buffer.len(), yield_interval).await;
     }
 
+    let mut packet_count = packet_buffer.len();
     if has_binary {
+        // scratch buffer shared by all the string packets of the payload
+        let mut buf = String::new();
         for packet in packet_buffer {
-            v3_bin_packet_encoder(packet, &mut data)?;
+            v3_bin_packet_encoder_with_buf(packet, &mut data, &mut buf, opts)?;
         }
     } else {
         for packet in packet_buffer {
-            v3_string_packet_encoder(packet, &mut data)?;
+            v3_string_packet_encoder(packet, &mut data, opts)?;
         }
     }
 
     // If there is no packet in the buffer, wait for the next packet
     if data.is_empty() {
-        let packet = recv_packet(&mut rx).await?;
+        let packet = recv_packet(&mut rx, max_payload, false, OversizePolicy::Drop).await?;
+        check_packet_protocol(&packet, ProtocolVersion::V3)?;
+        packet_count += 1;
+        closed |= packet == Packet::Close;
 
         match packet {
             Packet::BinaryV3(_) | Packet::Binary(_) => {
-                v3_bin_packet_encoder(packet, &mut data)?;
+                v3_bin_packet_encoder_with_buf(packet, &mut data, &mut String::new(), opts)?;
                 has_binary = true;
             }
             packet => {
-                v3_string_packet_encoder(packet, &mut data)?;
+                v3_string_packet_encoder(packet, &mut data, opts)?;
             }
         };
     }
 
     #[cfg(feature = "tracing")]
     tracing::debug!("sending packet: {:?}", &data);
-    Ok(Payload::new(data, has_binary))
+    record_payload(&data, packet_count);
+    Ok(Payload {
+        closed,
+        packet_count,
+        ..Payload::new(data.freeze(), has_binary)
+    })
 }
 
 /// Encode multiple packet packet into a *string* payload according to the
 /// [engine.io v3 protocol](https://github.com/socketio/engine.io-protocol/tree/v3#payload)
 #[cfg(feature = "v3")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        name = "encoder",
+        skip_all,
+        fields(
+            version = "v3_string",
+            max_payload,
+            payload_len = tracing::field::Empty,
+            packet_count = tracing::field::Empty
+        )
+    )
+)]
 pub async fn v3_string_encoder(
-    mut rx: MutexGuard<'_, PeekableReceiver<Packet>>,
+    mut rx: impl DerefMut<Target = PeekableReceiver<Packet>>,
     max_payload: u64,
+    max_packets: Option<usize>,
+    yield_interval: usize,
+    opts: &V3EncoderOptions,
 ) -> Result<Payload, Error> {
-    let mut data: Vec<u8> = Vec::new();
-
-    #[cfg(feature = "tracing")]
-    tracing::debug!("encoding payload with v3 string encoder");
+    let mut data = BytesMut::new();
+    let mut packet_count = 0;
+    // Set if a close packet is encoded in the payload
+    let mut closed = false;
 
     const PUNCTUATION_LEN: usize = 2;
     // number of digits of the max packet size, used to approximate the payload size
-    let max_packet_size_len = max_payload.checked_ilog10().unwrap_or(0) as usize + 1;
+    let max_packet_size_len = opts
+        .max_packet_size_len
+        .unwrap_or(max_payload.checked_ilog10().unwrap_or(0) as usize + 1);
     // Current size of the payload
     let current_size = data.len() + PUNCTUATION_LEN + max_packet_size_len;
-    while let Some(packet) = try_recv_packet(&mut rx, current_size, max_payload, true) {
-        v3_string_packet_encoder(packet, &mut data)?;
+    while let Some(packet) = try_recv_packet(
+        &mut rx,
+        current_size,
+        max_payload,
+        packet_count,
+        max_packets,
+        true,
+    ) {
+        check_packet_protocol(&packet, ProtocolVersion::V3)?;
+        packet_count += 1;
+        closed |= packet == Packet::Close;
+        let ended = ends_payload(&packet);
+        v3_string_packet_encoder(packet, &mut data, opts)?;
+        if ended {
+            break;
+        }
+        yield_every(packet_count, yield_interval).await;
     }
 
     // If there is no packet in the buffer, wait for the next packet
     if data.is_empty() {
-        let packet = recv_packet(&mut rx).await?;
-        v3_string_packet_encoder(packet, &mut data)?;
+        let packet = recv_packet(&mut rx, max_payload, true, OversizePolicy::Drop).await?;
+        check_packet_protocol(&packet, ProtocolVersion::V3)?;
+        packet_count += 1;
+        closed |= packet == Packet::Close;
+        v3_string_packet_encoder(packet, &mut data, opts)?;
     }
 
-    Ok(Payload::new(data, false))
+    record_payload(&data, packet_count);
+    Ok(Payload {
+        closed,
+        packet_count,
+        ..Payload::new(data.freeze(), false)
+    })
 }
 
 #[cfg(test)]
@@ -247,6 +782,7 @@ mod tests {
 
     use super::*;
     const MAX_PAYLOAD: u64 = 100_000;
+    const YIELD_INTERVAL: usize = 32;
 
     #[tokio::test]
     async fn encode_v4_payload() {
@@ -257,8 +793,87 @@ mod tests {
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
         tx.try_send(Packet::Message("hello€".into())).unwrap();
-        let Payload { data, .. } = v4_encoder(rx, MAX_PAYLOAD).await.unwrap();
-        assert_eq!(data, PAYLOAD.as_bytes());
+        let payload = v4_encoder(
+            rx,
+            MAX_PAYLOAD,
+            None,
+            YIELD_INTERVAL,
+            None,
+            OversizePolicy::Drop,
+        )
+        .await
+        .unwrap();
+        assert_eq!(payload.packet_count(), 3);
+        assert!(!payload.is_empty());
+        assert_eq!(payload.data, PAYLOAD.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn decode_v4_payload_round_trip() {
+        let packets = vec![
+            Packet::Message("hello€".into()),
+            Packet::Binary(vec![1, 2, 3, 4]),
+            Packet::Message("hello€".into()),
+        ];
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let rx = Mutex::new(PeekableReceiver::new(rx));
+        let rx = rx.lock().await;
+        for packet in packets.clone() {
+            tx.try_send(packet).unwrap();
+        }
+        let Payload { data, .. } = v4_encoder(
+            rx,
+            MAX_PAYLOAD,
+            None,
+            YIELD_INTERVAL,
+            None,
+            OversizePolicy::Drop,
+        )
+        .await
+        .unwrap();
+        assert_eq!(decode_v4_payload(&data).unwrap(), packets);
+
+        // A base64 payload starting with a `4` must not be mistaken for a v3 binary packet
+        let packets = vec![Packet::Binary(vec![0xe0, 1])];
+        assert_eq!(decode_v4_payload(b"b4AE=").unwrap(), packets);
+    }
+
+    #[tokio::test]
+    async fn close_packet_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { closed, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert!(!closed);
+        }
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        tx.try_send(Packet::Close).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, closed, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4hello\x1e1".as_bytes());
+            assert!(closed);
+        }
     }
 
     #[tokio::test]
@@ -272,21 +887,587 @@ mod tests {
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v4_encoder(rx, MAX_PAYLOAD).await.unwrap();
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
             assert_eq!(data, "4hello€".as_bytes());
         }
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v4_encoder(rx, MAX_PAYLOAD + 10).await.unwrap();
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD + 10,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
             assert_eq!(data, "bAQIDBA==\x1e4hello€".as_bytes());
         }
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v4_encoder(rx, MAX_PAYLOAD + 10).await.unwrap();
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD + 10,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
             assert_eq!(data, "4hello€".as_bytes());
         }
     }
 
+    #[tokio::test]
+    async fn max_payload_boundary_v4() {
+        // "4hello€" is 9 bytes and "4a" is 2 bytes, with the separator the payload is 12 bytes
+        for (max_payload, expected) in [(12, "4hello€\x1e4a"), (11, "4hello€")] {
+            let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+            let mut rx = PeekableReceiver::new(rx);
+            tx.try_send(Packet::Message("hello€".into())).unwrap();
+            tx.try_send(Packet::Message("a".into())).unwrap();
+            let Payload { data, .. } = v4_encoder(
+                &mut rx,
+                max_payload,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn many_packets_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(100);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        for _ in 0..100 {
+            tx.try_send(Packet::Ping).unwrap();
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, vec!["2"; 100].join("\x1e").as_bytes());
+        }
+        {
+            // The packets are still delivered in order after being peeked by batches
+            tx.try_send(Packet::Ping).unwrap();
+            tx.try_send(Packet::Pong).unwrap();
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v4_encoder(rx, 4, None, YIELD_INTERVAL, None, OversizePolicy::Drop)
+                    .await
+                    .unwrap();
+            assert_eq!(data, "2\x1e3".as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn protocol_mismatch_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello€".into())).unwrap();
+        tx.try_send(Packet::BinaryV3(vec![1, 2, 3, 4])).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await;
+            assert!(matches!(res, Err(Error::ProtocolMismatch)));
+        }
+        tx.try_send(Packet::BinaryV3(vec![1, 2, 3, 4])).unwrap();
+        {
+            // Also when waiting for the first packet
+            let rx = mutex.lock().await;
+            let res = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await;
+            assert!(matches!(res, Err(Error::ProtocolMismatch)));
+        }
+    }
+
+    #[tokio::test]
+    async fn yield_interval_v4() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(1000);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        for _ in 0..1000 {
+            tx.try_send(Packet::Ping).unwrap();
+        }
+
+        // Another task running on the same single threaded runtime
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticks_ = ticks.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                ticks_.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let rx = mutex.lock().await;
+        let Payload { data, .. } =
+            v4_encoder(rx, MAX_PAYLOAD, None, 32, None, OversizePolicy::Drop)
+                .await
+                .unwrap();
+        assert_eq!(data.len(), 1999);
+        // The other task could make progress while the payload was being encoded
+        assert!(ticks.load(Ordering::Relaxed) >= 1000 / 32 - 1);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn max_packets_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(1000);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        for _ in 0..1000 {
+            tx.try_send(Packet::Message("a".into())).unwrap();
+        }
+        for _ in 0..2 {
+            let rx = mutex.lock().await;
+            let Payload {
+                data, packet_count, ..
+            } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                Some(10),
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(packet_count, 10);
+            assert_eq!(data, ["4a"; 10].join("\x1e").as_bytes());
+        }
+
+        // The flush loop is also bounded
+        let rx = mutex.lock().await;
+        let flush_timeout = Some(Duration::from_millis(10));
+        let Payload { packet_count, .. } = v4_encoder(
+            rx,
+            MAX_PAYLOAD,
+            Some(10),
+            YIELD_INTERVAL,
+            flush_timeout,
+            OversizePolicy::Drop,
+        )
+        .await
+        .unwrap();
+        assert_eq!(packet_count, 10);
+        assert_eq!(
+            mutex.lock().await.try_recv().unwrap(),
+            Packet::Message("a".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn truncate_oversized_message_v4() {
+        use OversizePolicy::TruncateAtCharBoundary;
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+
+        // "hello€" is 8 bytes long, the packet is 9 bytes long
+        tx.try_send(Packet::Message("hello€".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res = v4_encoder(rx, 8, None, YIELD_INTERVAL, None, OversizePolicy::Drop).await;
+            assert!(matches!(
+                res,
+                Err(Error::PayloadTooLarge { size: 9, max: 8 })
+            ));
+        }
+        for max_payload in 6..=8 {
+            // The € char is never split
+            tx.try_send(Packet::Message("hello€".into())).unwrap();
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                max_payload,
+                None,
+                YIELD_INTERVAL,
+                None,
+                TruncateAtCharBoundary,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4hello".as_bytes());
+        }
+        tx.try_send(Packet::Message("hello€".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v4_encoder(rx, 9, None, YIELD_INTERVAL, None, TruncateAtCharBoundary)
+                    .await
+                    .unwrap();
+            assert_eq!(data, "4hello€".as_bytes());
+        }
+        tx.try_send(Packet::Message(Cow::Borrowed("€€"))).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v4_encoder(rx, 6, None, YIELD_INTERVAL, None, TruncateAtCharBoundary)
+                    .await
+                    .unwrap();
+            assert_eq!(data, "4€".as_bytes());
+        }
+        // Binary packets are still dropped
+        tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res = v4_encoder(rx, 6, None, YIELD_INTERVAL, None, TruncateAtCharBoundary).await;
+            assert!(matches!(res, Err(Error::PayloadTooLarge { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn noop_ends_payload_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        tx.try_send(Packet::Noop).unwrap();
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let payload = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                Default::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(payload.data, "4hello\x1e6".as_bytes());
+            assert!(!payload.closed);
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                Default::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4hello".as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn noop_flushes_payload_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Noop).unwrap();
+
+        // A queued noop packet doesn't wait for the flush timeout
+        let rx = mutex.lock().await;
+        let flush_timeout = Some(Duration::from_secs(10));
+        let encoder = v4_encoder(
+            rx,
+            MAX_PAYLOAD,
+            None,
+            YIELD_INTERVAL,
+            flush_timeout,
+            Default::default(),
+        );
+        let Payload { data, .. } = tokio::time::timeout(Duration::from_millis(100), encoder)
+            .await
+            .expect("noop packet should be flushed immediately")
+            .unwrap();
+        assert_eq!(data, "6".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn recv_packet_cancel_safety() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        let mut rx = mutex.lock().await;
+
+        tokio::select! {
+            _ = recv_packet(&mut rx, MAX_PAYLOAD, true, OversizePolicy::Drop) => {
+                panic!("no packet should be received")
+            }
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+        };
+
+        // The cancelled call neither consumed a packet nor closed the channel
+        tx.try_send(Packet::Ping).unwrap();
+        tx.try_send(Packet::Close).unwrap();
+        let packet = recv_packet(&mut rx, MAX_PAYLOAD, true, OversizePolicy::Drop)
+            .await
+            .unwrap();
+        assert_eq!(packet, Packet::Ping);
+        assert!(!tx.is_closed());
+        let packet = recv_packet(&mut rx, MAX_PAYLOAD, true, OversizePolicy::Drop)
+            .await
+            .unwrap();
+        assert_eq!(packet, Packet::Close);
+        assert!(tx.is_closed());
+    }
+
+    #[tokio::test]
+    async fn flush_timeout_v4() {
+        const FLUSH_TIMEOUT: Duration = Duration::from_millis(50);
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                tx.send(Packet::Message("hello".into())).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tokio::time::sleep(FLUSH_TIMEOUT * 2).await;
+            tx.send(Packet::Message("world".into())).await.unwrap();
+        });
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                Some(FLUSH_TIMEOUT),
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4hello\x1e4hello\x1e4hello".as_bytes());
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                Some(FLUSH_TIMEOUT),
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4world".as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_packet_v4() {
+        const MAX_PAYLOAD: u64 = 10;
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello world€".into())).unwrap();
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await;
+            assert!(matches!(
+                res,
+                Err(Error::PayloadTooLarge {
+                    size: 15,
+                    max: MAX_PAYLOAD
+                })
+            ));
+        }
+        {
+            // The oversized packet is discarded
+            let rx = mutex.lock().await;
+            let Payload { data, .. } = v4_encoder(
+                rx,
+                MAX_PAYLOAD,
+                None,
+                YIELD_INTERVAL,
+                None,
+                OversizePolicy::Drop,
+            )
+            .await
+            .unwrap();
+            assert_eq!(data, "4hello".as_bytes());
+        }
+    }
+
+    /// Collect the chunks of a streamed v4 payload
+    async fn stream_chunks(
+        rx: &mut PeekableReceiver<Packet>,
+        max_payload: u64,
+        max_packets: Option<usize>,
+        flush_timeout: Option<Duration>,
+    ) -> Result<Vec<Bytes>, Error> {
+        use futures::TryStreamExt;
+        v4_stream_encoder(
+            rx,
+            max_payload,
+            max_packets,
+            flush_timeout,
+            OversizePolicy::Drop,
+        )
+        .try_collect()
+        .await
+    }
+
+    #[tokio::test]
+    async fn stream_encoder_v4() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mut rx = PeekableReceiver::new(rx);
+        tx.try_send(Packet::Message("hello€".into())).unwrap();
+        tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
+        tx.try_send(Packet::Message("hello€".into())).unwrap();
+        let chunks = stream_chunks(&mut rx, MAX_PAYLOAD, None, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            ["4hello€", "\x1ebAQIDBA==", "\x1e4hello€"].map(Bytes::from)
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_encoder_v4_max_payload_boundary() {
+        let packets = || {
+            [
+                Packet::Message("hello€".into()),
+                Packet::Binary(vec![1, 2, 3, 4]),
+                Packet::Message("a".into()),
+                Packet::Ping,
+                Packet::Message("hello€".into()),
+                Packet::Close,
+            ]
+        };
+        // From a payload fitting a single packet to one fitting all of them
+        for max_payload in 10..=40 {
+            let (tx1, rx1) = tokio::sync::mpsc::channel::<Packet>(10);
+            let (tx2, rx2) = tokio::sync::mpsc::channel::<Packet>(10);
+            let mut buffered_rx = PeekableReceiver::new(rx1);
+            let mut streamed_rx = PeekableReceiver::new(rx2);
+            for packet in packets() {
+                tx1.try_send(packet.clone()).unwrap();
+                tx2.try_send(packet).unwrap();
+            }
+
+            while !buffered_rx.is_closed() {
+                let Payload { data, .. } = v4_encoder(
+                    &mut buffered_rx,
+                    max_payload,
+                    None,
+                    YIELD_INTERVAL,
+                    None,
+                    OversizePolicy::Drop,
+                )
+                .await
+                .unwrap();
+                let chunks = stream_chunks(&mut streamed_rx, max_payload, None, None)
+                    .await
+                    .unwrap();
+                assert_eq!(chunks.concat(), data, "max_payload: {max_payload}");
+                assert!(chunks.concat().len() as u64 <= max_payload);
+            }
+            assert!(streamed_rx.is_closed());
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_encoder_v4_max_packets() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mut rx = PeekableReceiver::new(rx);
+        for _ in 0..3 {
+            tx.try_send(Packet::Message("hello".into())).unwrap();
+        }
+        let chunks = stream_chunks(&mut rx, MAX_PAYLOAD, Some(2), None)
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), "4hello\x1e4hello".as_bytes());
+        let chunks = stream_chunks(&mut rx, MAX_PAYLOAD, Some(2), None)
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), "4hello".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn stream_encoder_v4_flush_timeout() {
+        const FLUSH_TIMEOUT: Duration = Duration::from_millis(50);
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mut rx = PeekableReceiver::new(rx);
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                tx.send(Packet::Message("hello".into())).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            tokio::time::sleep(FLUSH_TIMEOUT * 2).await;
+            tx.send(Packet::Message("world".into())).await.unwrap();
+        });
+        let chunks = stream_chunks(&mut rx, MAX_PAYLOAD, None, Some(FLUSH_TIMEOUT))
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), "4hello\x1e4hello\x1e4hello".as_bytes());
+        let chunks = stream_chunks(&mut rx, MAX_PAYLOAD, None, Some(FLUSH_TIMEOUT))
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), "4world".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn stream_encoder_v4_error_ends_stream() {
+        use futures::StreamExt;
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mut rx = PeekableReceiver::new(rx);
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        tx.try_send(Packet::BinaryV3(vec![1, 2, 3, 4])).unwrap();
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        let stream = v4_stream_encoder(&mut rx, MAX_PAYLOAD, None, None, OversizePolicy::Drop);
+        let chunks: Vec<_> = stream.collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].as_ref().unwrap(), "4hello".as_bytes());
+        assert!(matches!(chunks[1], Err(Error::ProtocolMismatch)));
+    }
+
     #[cfg(feature = "v3")]
     #[tokio::test]
     async fn encode_v3b64_payload() {
@@ -298,11 +1479,12 @@ mod tests {
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         tx.try_send(Packet::BinaryV3(vec![1, 2, 3, 4])).unwrap();
         tx.try_send(Packet::Message("hello€".into())).unwrap();
-        let Payload {
-            data, has_binary, ..
-        } = v3_string_encoder(rx, MAX_PAYLOAD).await.unwrap();
-        assert_eq!(data, PAYLOAD.as_bytes());
-        assert!(!has_binary);
+        let payload = v3_string_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(payload.packet_count(), 3);
+        assert_eq!(payload.data, PAYLOAD.as_bytes());
+        assert!(!payload.has_binary);
     }
 
     #[cfg(feature = "v3")]
@@ -318,12 +1500,23 @@ mod tests {
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v3_string_encoder(rx, MAX_PAYLOAD).await.unwrap();
+            let Payload { data, .. } =
+                v3_string_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                    .await
+                    .unwrap();
             assert_eq!(data, "7:4hello€".as_bytes());
         }
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v3_string_encoder(rx, MAX_PAYLOAD + 10).await.unwrap();
+            let Payload { data, .. } = v3_string_encoder(
+                rx,
+                MAX_PAYLOAD + 10,
+                None,
+                YIELD_INTERVAL,
+                &Default::default(),
+            )
+            .await
+            .unwrap();
             assert_eq!(data, "10:b4AQIDBA==7:4hello€7:4hello€".as_bytes());
         }
     }
@@ -340,11 +1533,93 @@ mod tests {
 
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         tx.try_send(Packet::BinaryV3(vec![1, 2, 3, 4])).unwrap();
-        let Payload {
-            data, has_binary, ..
-        } = v3_binary_encoder(rx, MAX_PAYLOAD).await.unwrap();
-        assert_eq!(data, PAYLOAD);
-        assert!(has_binary);
+        let payload = v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(payload.packet_count(), 2);
+        assert_eq!(payload.data, PAYLOAD[..]);
+        assert!(payload.has_binary);
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn v3_bin_packet_type_from_variant() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::BinaryV3(vec![1, 2, 3])).unwrap();
+        tx.try_send(Packet::Noop).unwrap();
+        let rx = mutex.lock().await;
+        let Payload { data, .. } =
+            v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                .await
+                .unwrap();
+        // The binary message carries the message packet type, the noop packet its own type
+        assert_eq!(data[..], [1, 4, 255, 4, 1, 2, 3, 0, 1, 255, b'6']);
+
+        // A close packet sent with a binary packet is encoded with its own type
+        tx.try_send(Packet::BinaryV3(vec![1])).unwrap();
+        tx.try_send(Packet::Close).unwrap();
+        let rx = mutex.lock().await;
+        let Payload { data, closed, .. } =
+            v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                .await
+                .unwrap();
+        assert_eq!(data[..], [1, 2, 255, 4, 1, 0, 1, 255, b'1']);
+        assert!(closed);
+
+        // The v4 binary packets cannot be binary-encoded in a v3 payload
+        let mut data = BytesMut::new();
+        let res = v3_bin_packet_encoder(Packet::Binary(vec![1]), &mut data);
+        assert!(matches!(res, Err(Error::InvalidPacketType(None))));
+        assert!(data.is_empty());
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn packet_size_len_v3_binary() {
+        const MAX_PAYLOAD: u64 = 100;
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(30);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        for _ in 0..25 {
+            tx.try_send(Packet::Message("a".into())).unwrap();
+        }
+        let rx = mutex.lock().await;
+        let Payload { data, .. } =
+            v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                .await
+                .unwrap();
+        // Estimating with the digits of the max payload would only allow 15 packets
+        assert_eq!(data, "2:4a".repeat(20).as_bytes());
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn custom_separator_v3() {
+        let opts = V3EncoderOptions {
+            string_separator: b'|',
+            binary_separator: 0xfe,
+            max_packet_size_len: None,
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v3_string_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &opts)
+                    .await
+                    .unwrap();
+            assert_eq!(data, "6|4hello".as_bytes());
+        }
+        tx.try_send(Packet::BinaryV3(vec![1, 2])).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &opts)
+                    .await
+                    .unwrap();
+            assert_eq!(data, [1, 3, 0xfe, 4, 1, 2][..]);
+        }
     }
 
     #[cfg(feature = "v3")]
@@ -364,13 +1639,105 @@ mod tests {
         tx.try_send(Packet::Message("hello€".into())).unwrap();
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v3_binary_encoder(rx, MAX_PAYLOAD).await.unwrap();
-            assert_eq!(data, PAYLOAD);
+            let Payload { data, .. } =
+                v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                    .await
+                    .unwrap();
+            assert_eq!(data, PAYLOAD[..]);
         }
         {
             let rx = mutex.lock().await;
-            let Payload { data, .. } = v3_binary_encoder(rx, MAX_PAYLOAD).await.unwrap();
+            let Payload { data, .. } =
+                v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                    .await
+                    .unwrap();
             assert_eq!(data, "7:4hello€7:4hello€".as_bytes());
         }
     }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn max_packets_v3() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(1000);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        for _ in 0..1000 {
+            tx.try_send(Packet::Message("a".into())).unwrap();
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload {
+                data, packet_count, ..
+            } = v3_string_encoder(
+                rx,
+                MAX_PAYLOAD,
+                Some(10),
+                YIELD_INTERVAL,
+                &Default::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(packet_count, 10);
+            assert_eq!(data, "2:4a".repeat(10).as_bytes());
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload { packet_count, .. } = v3_binary_encoder(
+                rx,
+                MAX_PAYLOAD,
+                Some(10),
+                YIELD_INTERVAL,
+                &Default::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(packet_count, 10);
+        }
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn protocol_mismatch_v3() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res =
+                v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default()).await;
+            assert!(matches!(res, Err(Error::ProtocolMismatch)));
+        }
+        tx.try_send(Packet::Binary(vec![1, 2, 3, 4])).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let res =
+                v3_string_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default()).await;
+            assert!(matches!(res, Err(Error::ProtocolMismatch)));
+        }
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn noop_ends_payload_v3() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Packet>(10);
+        let mutex = Mutex::new(PeekableReceiver::new(rx));
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        tx.try_send(Packet::Noop).unwrap();
+        tx.try_send(Packet::Message("hello".into())).unwrap();
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v3_string_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                    .await
+                    .unwrap();
+            assert_eq!(data, "6:4hello1:6".as_bytes());
+        }
+        {
+            let rx = mutex.lock().await;
+            let Payload { data, .. } =
+                v3_binary_encoder(rx, MAX_PAYLOAD, None, YIELD_INTERVAL, &Default::default())
+                    .await
+                    .unwrap();
+            assert_eq!(data, "6:4hello".as_bytes());
+        }
+    }
 }
@@ -1,19 +1,33 @@
 //! Payload encoder and decoder for polling transport.
 
-use crate::{errors::Error, packet::Packet, peekable::PeekableReceiver, service::ProtocolVersion};
+use crate::{
+    config::EngineIoConfig, errors::Error, packet::Packet, peekable::PeekableReceiver,
+    service::ProtocolVersion,
+};
+use bytes::Bytes;
 use futures::Stream;
 use http::Request;
-use tokio::sync::MutexGuard;
+use std::ops::DerefMut;
 
 mod buf;
+#[cfg(feature = "compression")]
+mod compression;
 mod decoder;
 mod encoder;
 
+#[cfg(feature = "compression")]
+pub use compression::Compression;
+
+#[cfg(feature = "test-utils")]
+pub use encoder::decode_v4_payload;
+#[cfg(all(feature = "test-utils", feature = "v3"))]
+pub use encoder::{v3_bin_packet_encoder, v3_bin_packet_encoder_with_buf};
+
 const PACKET_SEPARATOR_V4: u8 = b'\x1e';
 #[cfg(feature = "v3")]
-const STRING_PACKET_SEPARATOR_V3: u8 = b':';
+pub(crate) const STRING_PACKET_SEPARATOR_V3: u8 = b':';
 #[cfg(feature = "v3")]
-const BINARY_PACKET_SEPARATOR_V3: u8 = 0xff;
+pub(crate) const BINARY_PACKET_SEPARATOR_V3: u8 = 0xff;
 #[cfg(feature = "v3")]
 const STRING_PACKET_IDENTIFIER_V3: u8 = 0x00;
 #[cfg(feature = "v3")]
@@ -50,38 +64,232 @@ pub fn decoder(
 }
 
 /// A payload to transmit to the client through http polling
+///
+/// It is backed by [`Bytes`] so it can be given to the http response body without any copy
 pub struct Payload {
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub has_binary: bool,
+    /// Set if the payload contains a close packet, the session should then be closed
+    pub closed: bool,
+    /// The compression applied to the data
+    #[cfg(feature = "compression")]
+    pub compression: Compression,
+    /// The number of packets encoded in the payload, set by the encoders
+    packet_count: usize,
 }
 impl Payload {
-    pub fn new(data: impl Into<Vec<u8>>, has_binary: bool) -> Self {
+    pub fn new(data: impl Into<Bytes>, has_binary: bool) -> Self {
         Self {
             data: data.into(),
             has_binary,
+            closed: false,
+            #[cfg(feature = "compression")]
+            compression: Compression::None,
+            packet_count: 0,
         }
     }
+
+    /// The number of packets encoded in the payload
+    pub fn packet_count(&self) -> usize {
+        self.packet_count
+    }
+
+    /// Check if there is no packet encoded in the payload
+    pub fn is_empty(&self) -> bool {
+        self.packet_count() == 0
+    }
+
+    /// Compress the payload data.
+    ///
+    /// It should be called once the payload is fully encoded,
+    /// so the `max_payload` limit is applied to the uncompressed size.
+    #[cfg(feature = "compression")]
+    pub fn compress(self, compression: Compression) -> Result<Self, Error> {
+        Ok(Self {
+            data: compression.compress(self.data)?,
+            compression,
+            ..self
+        })
+    }
+
+    /// Consumes the payload and returns the underlying [`Bytes`]
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
 }
 
+/// Encode the buffered packets into a payload of at most `max_payload` bytes according to the protocol version
+/// and the polling settings of the [`EngineIoConfig`]
 pub async fn encoder(
-    rx: MutexGuard<'_, PeekableReceiver<Packet>>,
+    rx: impl DerefMut<Target = PeekableReceiver<Packet>>,
     #[allow(unused_variables)] protocol: ProtocolVersion,
     #[cfg(feature = "v3")] supports_binary: bool,
     max_payload: u64,
+    config: &EngineIoConfig,
 ) -> Result<Payload, Error> {
+    let yield_interval = config.encoder_yield_interval;
+    let max_packets = config.max_packets_per_payload;
+    let v4_encoder = |rx| {
+        encoder::v4_encoder(
+            rx,
+            max_payload,
+            max_packets,
+            yield_interval,
+            config.flush_timeout,
+            config.oversize_policy,
+        )
+    };
+
     #[cfg(feature = "v3")]
     {
         match protocol {
-            ProtocolVersion::V4 => encoder::v4_encoder(rx, max_payload).await,
+            ProtocolVersion::V4 => v4_encoder(rx).await,
             ProtocolVersion::V3 if supports_binary => {
-                encoder::v3_binary_encoder(rx, max_payload).await
+                encoder::v3_binary_encoder(
+                    rx,
+                    max_payload,
+                    max_packets,
+                    yield_interval,
+                    &config.v3_encoder,
+                )
+                .await
+            }
+            ProtocolVersion::V3 => {
+                encoder::v3_string_encoder(
+                    rx,
+                    max_payload,
+                    max_packets,
+                    yield_interval,
+                    &config.v3_encoder,
+                )
+                .await
             }
-            ProtocolVersion::V3 => encoder::v3_string_encoder(rx, max_payload).await,
         }
     }
 
     #[cfg(not(feature = "v3"))]
     {
-        encoder::v4_encoder(rx, max_payload).await
+        v4_encoder(rx).await
+    }
+}
+
+/// Stream the buffered packets as a v4 payload of at most `max_payload` bytes,
+/// with the polling settings of the [`EngineIoConfig`]
+pub fn v4_stream_encoder(
+    rx: impl DerefMut<Target = PeekableReceiver<Packet>>,
+    max_payload: u64,
+    config: &EngineIoConfig,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    encoder::v4_stream_encoder(
+        rx,
+        max_payload,
+        config.max_packets_per_payload,
+        config.flush_timeout,
+        config.oversize_policy,
+    )
+}
+
+/// Encode the packets into a v4 payload of at most `max_payload` bytes with the polling settings
+/// of the [`EngineIoConfig`], and return the bytes given to the http response body
+#[cfg(feature = "test-utils")]
+pub async fn encode_v4_payload(
+    packets: Vec<Packet>,
+    max_payload: u64,
+    config: &EngineIoConfig,
+) -> Result<Bytes, Error> {
+    let (tx, rx) = tokio::sync::mpsc::channel(packets.len().max(1));
+    for packet in packets {
+        tx.try_send(packet).unwrap();
+    }
+    let mut rx = PeekableReceiver::new(rx);
+    let payload = encoder(
+        &mut rx,
+        ProtocolVersion::V4,
+        #[cfg(feature = "v3")]
+        false,
+        max_payload,
+        config,
+    )
+    .await?;
+    Ok(payload.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn rx_with(packets: Vec<Packet>) -> PeekableReceiver<Packet> {
+        let (tx, rx) = mpsc::channel(packets.len());
+        for packet in packets {
+            tx.try_send(packet).unwrap();
+        }
+        PeekableReceiver::new(rx)
+    }
+
+    async fn encode(
+        rx: &mut PeekableReceiver<Packet>,
+        protocol: ProtocolVersion,
+        config: &EngineIoConfig,
+    ) -> Payload {
+        encoder(
+            rx,
+            protocol,
+            #[cfg(feature = "v3")]
+            false,
+            config.max_payload,
+            config,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn v4_packet_count() {
+        let config = EngineIoConfig::default();
+        let mut rx = rx_with(vec![
+            Packet::Message("a".into()),
+            Packet::Binary(vec![1, 2]),
+            Packet::Ping,
+        ]);
+        let payload = encode(&mut rx, ProtocolVersion::V4, &config).await;
+        assert_eq!(payload.packet_count(), 3);
+        assert!(!payload.is_empty());
+    }
+
+    #[tokio::test]
+    async fn max_packets_count() {
+        let config = EngineIoConfig::builder().max_packets_per_payload(2).build();
+        let mut rx = rx_with(vec![
+            Packet::Message("a".into()),
+            Packet::Message("b".into()),
+            Packet::Message("c".into()),
+        ]);
+        let payload = encode(&mut rx, ProtocolVersion::V4, &config).await;
+        assert_eq!(payload.packet_count(), 2);
+        let payload = encode(&mut rx, ProtocolVersion::V4, &config).await;
+        assert_eq!(payload.packet_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_packet_count() {
+        let config = EngineIoConfig::default();
+        let mut rx = rx_with(vec![
+            Packet::Message("a".into()),
+            Packet::Close,
+            Packet::Message("b".into()),
+        ]);
+        let payload = encode(&mut rx, ProtocolVersion::V4, &config).await;
+        assert_eq!(payload.packet_count(), 2);
+        assert!(payload.closed);
+    }
+
+    #[cfg(feature = "v3")]
+    #[tokio::test]
+    async fn v3_packet_count() {
+        let config = EngineIoConfig::default();
+        let mut rx = rx_with(vec![Packet::Message("a".into()), Packet::Ping]);
+        let payload = encode(&mut rx, ProtocolVersion::V3, &config).await;
+        assert_eq!(payload.packet_count(), 2);
     }
 }
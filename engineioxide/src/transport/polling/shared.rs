@@ -0,0 +1,246 @@
+//! The polling requests of the sessions shared between several nodes through a [`PollingStore`](crate::store::PollingStore).
+//!
+//! The node that opened a session runs a relay job moving the packets between the socket and the store,
+//! any node serves the `GET` and `POST` requests of the session from the store.
+use std::sync::Arc;
+
+use futures::StreamExt;
+use http::{Method, Request, Response, StatusCode};
+use http_body::Body;
+use tokio::time::Instant;
+
+use crate::{
+    body::ResponseBody,
+    config::EngineIoConfig,
+    engine::EngineIo,
+    errors::Error,
+    handler::EngineIoHandler,
+    packet::Packet,
+    service::ProtocolVersion,
+    sid::Sid,
+    socket::{DisconnectReason, Socket},
+    store::{Incoming, PollingStore, SharedPolling},
+};
+
+use super::{dispatch_packet, http_response, payload};
+
+/// The separator between the packets of an engine.io v4 payload
+const PACKET_SEPARATOR: &str = "\x1e";
+
+/// Registers a new session in the store and spawns the job relaying its packets
+pub(crate) async fn open<H: EngineIoHandler>(
+    engine: Arc<EngineIo<H>>,
+    shared: &SharedPolling,
+    socket: Arc<Socket<H::Data>>,
+) {
+    shared.store.open(socket.id).await;
+    tokio::spawn(relay(engine, shared.clone(), socket));
+}
+
+/// Moves the packets emitted to the socket to the store,
+/// and dispatches the packets posted to the store by the client.
+///
+/// The internal channel is only locked while waiting for packets, like with the websocket transport.
+async fn relay<H: EngineIoHandler>(
+    engine: Arc<EngineIo<H>>,
+    shared: SharedPolling,
+    socket: Arc<Socket<H::Data>>,
+) {
+    let sid = socket.id;
+    let store = &shared.store;
+    let closed = loop {
+        let mut rx = socket.internal_rx.lock().await;
+        let packet = match tokio::time::timeout(shared.poll_interval, rx.recv()).await {
+            Ok(Some(packet)) => Some(packet),
+            Ok(None) => break false,
+            Err(_) => None,
+        };
+        if let Some(packet) = packet {
+            let mut packets = vec![packet];
+            while let Ok(packet) = rx.try_recv() {
+                packets.push(packet);
+            }
+            drop(rx);
+            let closed = packets.contains(&Packet::Close);
+            let packets = packets
+                .into_iter()
+                .filter_map(|p| p.try_into().ok())
+                .collect();
+            if !store.push_outgoing(sid, packets).await {
+                engine.close_session(sid, DisconnectReason::TransportError);
+                break false;
+            }
+            if closed {
+                break true;
+            }
+        } else {
+            drop(rx);
+        }
+
+        let Some(Incoming {
+            packets,
+            overlapped,
+        }) = store.take_incoming(sid).await
+        else {
+            engine.close_session(sid, DisconnectReason::TransportError);
+            break false;
+        };
+        if overlapped {
+            engine.close_session(sid, DisconnectReason::MultipleHttpPollingError);
+            break false;
+        }
+        for packet in packets {
+            let res = Packet::try_from(packet).and_then(|p| dispatch_packet(&engine, &socket, p));
+            if let Err(ref _e) = res {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={sid}] error dispatching shared packet: {:?}", _e);
+            }
+        }
+    };
+
+    // Gives one polling cycle to the client to retrieve the close packet
+    if closed {
+        tokio::time::sleep(socket.ping_interval).await;
+    }
+    #[cfg(feature = "tracing")]
+    tracing::debug!("[sid={sid}] shared session closed");
+    store.close(sid).await;
+}
+
+/// Handles a `GET` or `POST` polling request of a shared session, on any node.
+///
+/// The request must hold the session cookie set in the handshake response.
+pub(crate) async fn req<R, B, H>(
+    engine: Arc<EngineIo<H>>,
+    sid: Sid,
+    req: Request<R>,
+) -> Result<Response<ResponseBody<B>>, Error>
+where
+    H: EngineIoHandler,
+    R: Body + Send + Unpin + 'static,
+    <R as Body>::Error: std::fmt::Debug,
+    <R as Body>::Data: Send,
+    B: Send + 'static,
+{
+    let shared = engine
+        .config
+        .shared_polling
+        .as_ref()
+        .expect("shared polling request without a shared polling config");
+    if !shared.verify_cookie(req.headers(), sid) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={sid}] missing or invalid session cookie");
+        return Err(Error::UnknownSessionID(sid));
+    }
+    match *req.method() {
+        Method::GET => polling_req(shared, sid, &engine.config).await,
+        Method::POST => post_req(shared, sid, req, engine.config.max_payload).await,
+        _ => Err(Error::HttpErrorResponse(StatusCode::BAD_REQUEST)),
+    }
+}
+
+/// Waits for packets in the store and sends them in a payload.
+///
+/// A `GET` request overlapping another one of the session is rejected, the owner node then closes the session.
+/// Without any packet for a whole heartbeat cycle, the request is released with a noop packet.
+async fn polling_req<B>(
+    shared: &SharedPolling,
+    sid: Sid,
+    config: &EngineIoConfig,
+) -> Result<Response<ResponseBody<B>>, Error>
+where
+    B: Send + 'static,
+{
+    match shared.store.start_polling(sid).await {
+        Some(true) => (),
+        Some(false) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={sid}] overlapping shared polling request");
+            return Err(Error::HttpErrorResponse(StatusCode::BAD_REQUEST));
+        }
+        None => return Err(Error::UnknownSessionID(sid)),
+    }
+    let guard = PollingGuard {
+        store: Some(shared.store.clone()),
+        sid,
+    };
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!("[sid={sid}] shared polling request");
+    let deadline = Instant::now() + config.ping_interval + config.ping_timeout;
+    let packets = loop {
+        let packets = shared
+            .store
+            .take_outgoing(sid, config.max_payload)
+            .await
+            .ok_or(Error::UnknownSessionID(sid))?;
+        if !packets.is_empty() {
+            break packets;
+        }
+        if Instant::now() >= deadline {
+            break vec![Packet::Noop.try_into()?];
+        }
+        tokio::time::sleep(shared.poll_interval).await;
+    };
+    guard.end().await;
+
+    // The session is closed once its close packet is sent
+    if packets.iter().any(|p| p == "1") {
+        shared.store.close(sid).await;
+    }
+    Ok(http_response(
+        StatusCode::OK,
+        packets.join(PACKET_SEPARATOR),
+        false,
+    )?)
+}
+
+/// Ends the pending `GET` request of a session in the store, even if the request is dropped while waiting
+struct PollingGuard {
+    store: Option<Arc<dyn PollingStore>>,
+    sid: Sid,
+}
+
+impl PollingGuard {
+    /// Ends the request before the response is sent, so that the next request of the client doesn't overlap it
+    async fn end(mut self) {
+        if let Some(store) = self.store.take() {
+            store.end_polling(self.sid).await;
+        }
+    }
+}
+
+impl Drop for PollingGuard {
+    fn drop(&mut self) {
+        if let Some(store) = self.store.take() {
+            let sid = self.sid;
+            tokio::spawn(async move { store.end_polling(sid).await });
+        }
+    }
+}
+
+/// Decodes the packets of the payload and pushes them to the store
+async fn post_req<R, B>(
+    shared: &SharedPolling,
+    sid: Sid,
+    body: Request<R>,
+    max_payload: u64,
+) -> Result<Response<ResponseBody<B>>, Error>
+where
+    R: Body + Send + Unpin + 'static,
+    <R as Body>::Error: std::fmt::Debug,
+    <R as Body>::Data: Send,
+    B: Send + 'static,
+{
+    let packets = payload::decoder(body, ProtocolVersion::V4, max_payload);
+    futures::pin_mut!(packets);
+
+    let mut received = Vec::new();
+    while let Some(packet) = packets.next().await {
+        received.push(packet?.try_into()?);
+    }
+    if !shared.store.push_incoming(sid, received).await {
+        return Err(Error::UnknownSessionID(sid));
+    }
+    Ok(http_response(StatusCode::OK, "ok", false)?)
+}
@@ -0,0 +1,360 @@
+//! The webtransport transport module is responsible for handling the engine.io sessions opened over WebTransport.
+//!
+//! engineioxide does not terminate HTTP/3: the server accepting the WebTransport session hands over
+//! the first bidirectional stream opened by the client with
+//! [`EngineIoService::on_webtransport`](crate::service::EngineIoService::on_webtransport).
+//!
+//! The packets are framed on the stream with a length header, like the engine.io `webtransport` transport:
+//! * If the payload is shorter than 126 bytes, a single byte holding its length.
+//! * Otherwise `126` followed by the length as a `u16`, or `127` followed by the length as a `u64`, in network byte order.
+//!
+//! The highest bit of the first byte is set for binary packets, which are sent raw.
+//! The other packets are encoded as text, like with the websocket transport.
+//!
+//! The client starts with an open packet, holding the id of its session if it upgrades from polling.
+//! Only the engine.io v4 protocol is supported.
+
+use std::sync::Arc;
+
+use http::request::Parts;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    task::JoinHandle,
+};
+
+use crate::{
+    engine::EngineIo,
+    errors::Error,
+    handler::EngineIoHandler,
+    packet::{OpenPacket, Packet},
+    service::{ProtocolVersion, TransportType},
+    sid::Sid,
+    DisconnectReason, Socket,
+};
+
+/// The flag set on the first byte of the header of a binary packet
+const BINARY_FLAG: u8 = 0x80;
+
+/// A packet frame read from the stream, before it is decoded
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// The data of the open packet sent by a client upgrading from polling
+#[derive(Deserialize)]
+struct OpenRequest {
+    sid: Sid,
+}
+
+/// Handles a WebTransport stream until the session is closed.
+///
+/// Sends an open packet if it is not an upgrade from a polling session.
+pub(crate) async fn on_session<H: EngineIoHandler, S>(
+    engine: Arc<EngineIo<H>>,
+    stream: S,
+    req_data: Parts,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let max_payload = engine.config.max_payload;
+    let (mut rx, mut tx) = tokio::io::split(stream);
+
+    let sid = match read_frame(&mut rx, max_payload).await? {
+        Some(Frame::Text(open)) if open.starts_with('0') => match &open[1..] {
+            "" => None,
+            data => Some(serde_json::from_str::<OpenRequest>(data)?.sid),
+        },
+        _ => return Err(Error::UpgradeError),
+    };
+
+    let socket = if let Some(sid) = sid {
+        match engine.get_socket(sid) {
+            None => return Err(Error::UnknownSessionID(sid)),
+            Some(socket) if !socket.is_http() || socket.protocol != ProtocolVersion::V4 => {
+                return Err(Error::UpgradeError)
+            }
+            // The shared polling sessions are not upgradable as their requests can land on any node
+            #[cfg(feature = "shared-polling")]
+            Some(_) if engine.config.shared_polling.is_some() => return Err(Error::UpgradeError),
+            Some(socket) => {
+                upgrade_handshake::<H, S>(&socket, &mut rx, &mut tx, max_payload).await?;
+                engine.handler.on_upgrade(socket.clone());
+                socket
+            }
+        }
+    } else {
+        let wait = engine.admit_handshake()?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        let socket = engine.create_session(
+            ProtocolVersion::V4,
+            TransportType::WebTransport,
+            req_data,
+            #[cfg(feature = "v3")]
+            false,
+        );
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] new webtransport session", socket.id);
+        let packet = OpenPacket::new(TransportType::WebTransport, socket.id, &engine.config)
+            .with_session(&socket);
+        write_packet(&mut tx, Packet::Open(packet)).await?;
+        tx.flush().await?;
+        socket.clone().spawn_heartbeat();
+        socket
+    };
+
+    let tx_handle = forward_to_socket::<H, S>(socket.clone(), tx);
+    match forward_to_handler(&engine, rx, &socket, max_payload).await {
+        Err(ref e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] error when handling packet: {:?}", socket.id, e);
+            if let Some(reason) = e.into() {
+                engine.close_session(socket.id, reason);
+            }
+        }
+        Ok(()) => engine.close_session(socket.id, DisconnectReason::TransportClose),
+    }
+    tx_handle.abort();
+    Ok(())
+}
+
+/// Forwards all packets received from the stream to a EngineIo [`Socket`]
+async fn forward_to_handler<H: EngineIoHandler, S>(
+    engine: &Arc<EngineIo<H>>,
+    mut rx: ReadHalf<S>,
+    socket: &Arc<Socket<H::Data>>,
+    max_payload: u64,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    while let Some(frame) = read_frame(&mut rx, max_payload).await? {
+        match frame {
+            Frame::Text(msg) => match Packet::try_from(msg)? {
+                Packet::Close => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("[sid={}] closing session", socket.id);
+                    engine.close_session(socket.id, DisconnectReason::TransportClose);
+                    break;
+                }
+                Packet::Pong | Packet::Ping => socket
+                    .heartbeat_tx
+                    .try_send(())
+                    .map_err(|_| Error::HeartbeatTimeout),
+                Packet::Message(msg) => {
+                    engine.handler.on_message(msg.into_owned(), socket.clone());
+                    Ok(())
+                }
+                p => return Err(Error::BadPacket(p)),
+            },
+            Frame::Binary(data) => {
+                engine.handler.on_binary(data, socket.clone());
+                Ok(())
+            }
+        }?
+    }
+    Ok(())
+}
+
+/// Forwards all packets waiting to be sent to the stream.
+///
+/// Like with the websocket transport, the stream is flushed only when the internal channel is drained
+/// and the internal channel is not locked while the packets are written.
+fn forward_to_socket<H: EngineIoHandler, S>(
+    socket: Arc<Socket<H::Data>>,
+    mut tx: WriteHalf<S>,
+) -> JoinHandle<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let batch = {
+                let mut internal_rx = socket.internal_rx.lock().await;
+                let Some(item) = internal_rx.recv().await else {
+                    break;
+                };
+                let mut batch = vec![item];
+                while let Ok(item) = internal_rx.try_recv() {
+                    batch.push(item);
+                }
+                batch
+            };
+            let closed = batch.contains(&Packet::Close);
+            for item in batch {
+                // A Noop Packet maybe sent by the server to upgrade from a polling connection
+                // it should be discarded here
+                if item == Packet::Noop {
+                    continue;
+                }
+                if let Err(_e) = write_packet(&mut tx, item).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("[sid={}] error sending packet: {}", socket.id, _e);
+                }
+            }
+            tx.flush().await.ok();
+            if closed {
+                socket.internal_rx.lock().await.close();
+                break;
+            }
+        }
+        tx.shutdown().await.ok();
+    })
+}
+
+/// Upgrade a session from a polling request to a webtransport session.
+///
+/// It follows the same probe handshake as the websocket upgrade, once the client sent its open packet :
+/// the client sends a `2probe` ping packet, the server answers with a `3probe` pong packet
+/// and the client sends an upgrade packet.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(socket, rx, tx), fields(sid = socket.id.to_string())))]
+async fn upgrade_handshake<H: EngineIoHandler, S>(
+    socket: &Arc<Socket<H::Data>>,
+    rx: &mut ReadHalf<S>,
+    tx: &mut WriteHalf<S>,
+    max_payload: u64,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    #[cfg(feature = "tracing")]
+    tracing::debug!("webtransport session upgrade");
+
+    match read_frame(rx, max_payload).await? {
+        // The probe fails so that the client keeps polling
+        Some(Frame::Text(_)) if !socket.is_upgradable() => Err(Error::UpgradeError)?,
+        Some(Frame::Text(msg)) if Packet::try_from(msg.as_str())? == Packet::PingUpgrade => {
+            write_packet(tx, Packet::PongUpgrade).await?;
+            tx.flush().await?;
+        }
+        _ => Err(Error::UpgradeError)?,
+    };
+
+    // send a NOOP packet to any pending polling request so it closes gracefully
+    socket.send(Packet::Noop)?;
+
+    match read_frame(rx, max_payload).await? {
+        Some(Frame::Text(msg)) if Packet::try_from(msg.as_str())? == Packet::Upgrade => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("webtransport upgraded successful")
+        }
+        _ => Err(Error::UpgradeError)?,
+    };
+
+    // wait for any polling connection to finish and for its payload to be sent
+    socket.upgrade_to(TransportType::WebTransport).await;
+    Ok(())
+}
+
+/// Reads the next frame of the stream, or `None` if the stream is closed between two frames.
+///
+/// The frames larger than `max_payload` are rejected from their header, before their payload is buffered.
+async fn read_frame<R: AsyncRead + Unpin>(
+    rx: &mut R,
+    max_payload: u64,
+) -> Result<Option<Frame>, Error> {
+    let header = match rx.read_u8().await {
+        Ok(header) => header,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let len = match header & !BINARY_FLAG {
+        126 => rx.read_u16().await? as u64,
+        127 => rx.read_u64().await?,
+        len => len as u64,
+    };
+    if len > max_payload {
+        return Err(Error::PayloadTooLarge {
+            size: len,
+            max: max_payload,
+        });
+    }
+    let mut data = vec![0; len as usize];
+    rx.read_exact(&mut data).await?;
+    if header & BINARY_FLAG == 0 {
+        let text = String::from_utf8(data).map_err(|e| e.utf8_error())?;
+        Ok(Some(Frame::Text(text)))
+    } else {
+        Ok(Some(Frame::Binary(data)))
+    }
+}
+
+/// Writes a packet to the stream with its length header, without flushing it
+async fn write_packet<W: AsyncWrite + Unpin>(tx: &mut W, packet: Packet) -> Result<(), Error> {
+    let (data, flag) = match packet {
+        Packet::Binary(bin) | Packet::BinaryV3(bin) => (bin, BINARY_FLAG),
+        packet => {
+            let packet: String = packet.try_into()?;
+            (packet.into_bytes(), 0)
+        }
+    };
+    let mut frame = Vec::with_capacity(data.len() + 9);
+    match data.len() {
+        len if len < 126 => frame.push(len as u8 | flag),
+        len if len <= u16::MAX as usize => {
+            frame.push(126 | flag);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127 | flag);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&data);
+    tx.write_all(&frame).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frame_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(1 << 20);
+        let long = "a".repeat(200);
+        let huge = vec![1; 70_000];
+        write_packet(&mut client, Packet::Message("hello".into()))
+            .await
+            .unwrap();
+        write_packet(&mut client, Packet::Message(long.clone().into()))
+            .await
+            .unwrap();
+        write_packet(&mut client, Packet::Binary(huge.clone()))
+            .await
+            .unwrap();
+        drop(client);
+
+        let frame = read_frame(&mut server, 100_000).await.unwrap();
+        assert_eq!(frame, Some(Frame::Text("4hello".into())));
+        let frame = read_frame(&mut server, 100_000).await.unwrap();
+        assert_eq!(frame, Some(Frame::Text(format!("4{long}"))));
+        let frame = read_frame(&mut server, 100_000).await.unwrap();
+        assert_eq!(frame, Some(Frame::Binary(huge)));
+        assert_eq!(read_frame(&mut server, 100_000).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn frame_header() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_packet(&mut client, Packet::Binary(vec![1, 2, 3]))
+            .await
+            .unwrap();
+        let mut header = [0; 4];
+        server.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [0x83, 1, 2, 3]);
+
+        // The length is checked before the payload is read
+        client
+            .write_all(&[127, 0, 0, 0, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+        let err = read_frame(&mut server, 1024).await.unwrap_err();
+        assert!(matches!(err, Error::PayloadTooLarge { size: 65536, .. }));
+    }
+}
@@ -3,8 +3,13 @@
 //!
 //! Other functions are used internally to handle the websocket connection through tasks and channels
 //! and to handle upgrade from polling to ws
+//!
+//! With the `compression` feature, the `permessage-deflate` extension is negotiated with the clients supporting it.
+//!
+//! A subprotocol is negotiated with the clients sending a `Sec-WebSocket-Protocol` header
+//! if [`EngineIoConfig::ws_subprotocols`] is set.
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use futures::{
     stream::{SplitSink, SplitStream},
@@ -16,10 +21,18 @@ use tokio::{
     task::JoinHandle,
 };
 use tokio_tungstenite::{
-    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    tungstenite::{
+        self,
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame as WsCloseFrame, Role, WebSocketConfig},
+        Message,
+    },
     WebSocketStream,
 };
 
+#[cfg(feature = "compression")]
+mod deflate;
+
 use crate::{
     body::ResponseBody,
     config::EngineIoConfig,
@@ -30,22 +43,36 @@ use crate::{
     service::ProtocolVersion,
     service::TransportType,
     sid::Sid,
+    socket::CloseFrame,
     DisconnectReason, Socket,
 };
 
+/// The time given to the forward task to send the close frame of a connection closed by the server
+const CLOSE_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Create a response for websocket upgrade
-fn ws_response<B>(ws_key: &HeaderValue) -> Result<Response<ResponseBody<B>>, http::Error> {
+fn ws_response<B>(
+    ws_key: &HeaderValue,
+    extensions: Option<HeaderValue>,
+    subprotocol: Option<HeaderValue>,
+) -> Result<Response<ResponseBody<B>>, http::Error> {
     let derived = derive_accept_key(ws_key.as_bytes());
     let sec = derived.parse::<HeaderValue>().unwrap();
-    Response::builder()
+    let mut res = Response::builder()
         .status(StatusCode::SWITCHING_PROTOCOLS)
         .header(http::header::UPGRADE, HeaderValue::from_static("websocket"))
         .header(
             http::header::CONNECTION,
             HeaderValue::from_static("Upgrade"),
         )
-        .header(http::header::SEC_WEBSOCKET_ACCEPT, sec)
-        .body(ResponseBody::empty_response())
+        .header(http::header::SEC_WEBSOCKET_ACCEPT, sec);
+    if let Some(extensions) = extensions {
+        res = res.header(http::header::SEC_WEBSOCKET_EXTENSIONS, extensions);
+    }
+    if let Some(subprotocol) = subprotocol {
+        res = res.header(http::header::SEC_WEBSOCKET_PROTOCOL, subprotocol);
+    }
+    res.body(ResponseBody::empty_response())
 }
 
 /// Upgrade a websocket request to create a websocket connection.
@@ -68,18 +95,55 @@ pub fn new_req<R: Send + 'static, B, H: EngineIoHandler>(
         .0;
 
     parts.headers.extend(req.headers().clone());
+    // The extensions can't be cloned, only the peer address is kept if it was set by the server
+    if let Some(addr) = req.extensions().get::<std::net::SocketAddr>() {
+        parts.extensions.insert(*addr);
+    }
     let ws_key = parts
         .headers
         .get("Sec-WebSocket-Key")
         .ok_or(Error::HttpErrorResponse(StatusCode::BAD_REQUEST))?
         .clone();
+    let subprotocol = negotiate_subprotocol(&engine.config, &parts.headers)?;
+
+    #[cfg(feature = "compression")]
+    let extensions = engine
+        .config
+        .ws_compression_threshold
+        .and_then(|_| deflate::negotiate(&parts.headers));
+    #[cfg(feature = "compression")]
+    let compression = extensions
+        .as_ref()
+        .and(engine.config.ws_compression_threshold);
+    #[cfg(not(feature = "compression"))]
+    let extensions = None;
+
+    let mut res = ws_response(&ws_key, extensions, subprotocol)?;
+    if let Some(hook) = &engine.config.handshake_response_hook {
+        hook.call(&parts, res.headers_mut());
+    }
 
     tokio::spawn(async move {
         let conn = hyper::upgrade::on(req)
             .await
             .map(hyper_util::rt::TokioIo::new);
         let res = match conn {
-            Ok(conn) => on_init(engine, conn, protocol, sid, parts).await,
+            Ok(conn) => {
+                #[cfg(feature = "compression")]
+                let max_payload = usize::try_from(engine.config.max_payload).unwrap_or(usize::MAX);
+                #[cfg(feature = "compression")]
+                let conn = deflate::DeflateStream::new(conn, compression.is_some(), max_payload);
+                on_init(
+                    engine,
+                    conn,
+                    protocol,
+                    sid,
+                    parts,
+                    #[cfg(feature = "compression")]
+                    compression,
+                )
+                .await
+            }
             Err(_e) => {
                 #[cfg(feature = "tracing")]
                 tracing::debug!("ws upgrade error: {}", _e);
@@ -99,7 +163,35 @@ pub fn new_req<R: Send + 'static, B, H: EngineIoHandler>(
         }
     });
 
-    Ok(ws_response(&ws_key)?)
+    Ok(res)
+}
+
+/// Selects the first subprotocol requested by the client that is supported by the server.
+///
+/// Returns an error if a subprotocol is required and none of them is supported.
+fn negotiate_subprotocol(
+    config: &EngineIoConfig,
+    headers: &http::HeaderMap,
+) -> Result<Option<HeaderValue>, Error> {
+    if config.ws_subprotocols.is_empty() {
+        return Ok(None);
+    }
+    let subprotocol = headers
+        .get_all(http::header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .find(|protocol| config.ws_subprotocols.iter().any(|p| p == protocol));
+    match subprotocol {
+        Some(protocol) => Ok(HeaderValue::from_str(protocol).ok()),
+        None if config.require_ws_subprotocol => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("no supported websocket subprotocol requested");
+            Err(Error::HttpErrorResponse(StatusCode::BAD_REQUEST))
+        }
+        None => Ok(None),
+    }
 }
 
 /// Handle a websocket connection upgrade
@@ -113,18 +205,29 @@ async fn on_init<H: EngineIoHandler, S>(
     protocol: ProtocolVersion,
     sid: Option<Sid>,
     req_data: Parts,
+    #[cfg(feature = "compression")] compression: Option<usize>,
 ) -> Result<(), Error>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let ws_init = move || WebSocketStream::from_raw_socket(conn, Role::Server, None);
+    let ws_config = ws_config(&engine.config);
+    let ws_init = move || WebSocketStream::from_raw_socket(conn, Role::Server, Some(ws_config));
     let (socket, ws) = if let Some(sid) = sid {
         match engine.get_socket(sid) {
             None => return Err(Error::UnknownSessionID(sid)),
-            Some(socket) if socket.is_ws() => return Err(Error::UpgradeError),
+            Some(socket) if !socket.is_http() => return Err(Error::UpgradeError),
+            // The shared polling sessions are not upgradable as their requests can land on any node
+            #[cfg(feature = "shared-polling")]
+            Some(socket)
+                if engine.config.shared_polling.is_some()
+                    && socket.protocol == ProtocolVersion::V4 =>
+            {
+                return Err(Error::UpgradeError)
+            }
             Some(socket) => {
                 let mut ws = ws_init().await;
                 upgrade_handshake::<H, S>(&socket, &mut ws).await?;
+                engine.handler.on_upgrade(socket.clone());
                 (socket, ws)
             }
         }
@@ -139,28 +242,66 @@ where
         #[cfg(feature = "tracing")]
         tracing::debug!("[sid={}] new websocket connection", socket.id);
         let mut ws = ws_init().await;
-        init_handshake(socket.id, &mut ws, &engine.config).await?;
-        socket
-            .clone()
-            .spawn_heartbeat(engine.config.ping_interval, engine.config.ping_timeout);
+        init_handshake(&socket, &mut ws, &engine.config).await?;
+        socket.clone().spawn_heartbeat();
         (socket, ws)
     };
     let (tx, rx) = ws.split();
-    let rx_handle = forward_to_socket::<H, S>(socket.clone(), tx);
+    let mut rx_handle = forward_to_socket::<H, S>(
+        socket.clone(),
+        tx,
+        #[cfg(feature = "compression")]
+        compression,
+    );
 
-    if let Err(ref e) = forward_to_handler(&engine, rx, &socket).await {
-        #[cfg(feature = "tracing")]
-        tracing::debug!("[sid={}] error when handling packet: {:?}", socket.id, e);
-        if let Some(reason) = e.into() {
-            engine.close_session(socket.id, reason);
+    match forward_to_handler(&engine, rx, &socket).await {
+        Err(Error::WsTransport(ref e)) if is_message_too_large(e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] frame too large: {}", socket.id, e);
+            let frame = CloseFrame::new(CloseCode::Size.into(), "message too big");
+            socket.close_with_frame(DisconnectReason::PacketParsingError, frame);
+            // Let the forward task send the close frame before tearing the connection down
+            tokio::time::timeout(CLOSE_FRAME_TIMEOUT, &mut rx_handle)
+                .await
+                .ok();
         }
-    } else {
-        engine.close_session(socket.id, DisconnectReason::TransportClose);
+        Err(ref e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] error when handling packet: {:?}", socket.id, e);
+            if let Some(reason) = e.into() {
+                engine.close_session(socket.id, reason);
+            }
+        }
+        Ok(()) => engine.close_session(socket.id, DisconnectReason::TransportClose),
     }
     rx_handle.abort();
     Ok(())
 }
 
+/// Returns true if the error was raised because a received frame or message exceeds the `max_payload` limit.
+///
+/// It is checked by tungstenite, and by the [`DeflateStream`](deflate::DeflateStream) for the compressed messages.
+fn is_message_too_large(err: &tungstenite::Error) -> bool {
+    match err {
+        tungstenite::Error::Capacity(_) => true,
+        #[cfg(feature = "compression")]
+        tungstenite::Error::Io(e) => deflate::is_message_too_large(e),
+        _ => false,
+    }
+}
+
+/// The websocket config enforcing the `max_payload` of the engine config on each incoming frame and message.
+///
+/// The frames larger than the limit are rejected from their header, before their payload is buffered.
+fn ws_config(config: &EngineIoConfig) -> WebSocketConfig {
+    let max_payload = usize::try_from(config.max_payload).unwrap_or(usize::MAX);
+    WebSocketConfig {
+        max_frame_size: Some(max_payload),
+        max_message_size: Some(max_payload),
+        ..Default::default()
+    }
+}
+
 /// Forwards all packets received from a websocket to a EngineIo [`Socket`]
 async fn forward_to_handler<H: EngineIoHandler, S>(
     engine: &Arc<EngineIo<H>>,
@@ -184,7 +325,7 @@ where
                     .try_send(())
                     .map_err(|_| Error::HeartbeatTimeout),
                 Packet::Message(msg) => {
-                    engine.handler.on_message(msg, socket.clone());
+                    engine.handler.on_message(msg.into_owned(), socket.clone());
                     Ok(())
                 }
                 p => return Err(Error::BadPacket(p)),
@@ -200,32 +341,58 @@ where
     Ok(())
 }
 
+/// Maps a [`CloseFrame`] to a websocket close frame,
+/// the reason is truncated on a char boundary to fit in the 123 bytes allowed by the protocol
+fn ws_close_frame(frame: CloseFrame) -> WsCloseFrame<'static> {
+    let mut reason = frame.reason;
+    if reason.len() > 123 {
+        let end = (0..=123)
+            .rev()
+            .find(|&i| reason.is_char_boundary(i))
+            .unwrap_or(0);
+        reason = reason[..end].to_owned().into();
+    }
+    WsCloseFrame {
+        code: CloseCode::from(frame.code),
+        reason,
+    }
+}
+
 /// Forwards all packets waiting to be sent to the websocket
 ///
-/// The websocket stream is flushed only when the internal channel is drained
+/// The websocket stream is flushed only when the internal channel is drained.
+/// The internal channel is only locked while the packets are received and not while they are sent,
+/// so that the [`OverflowPolicy::DropOldest`](crate::config::OverflowPolicy::DropOldest) policy
+/// can drop the buffered packets of a slow client.
+///
+/// The close frame set with [`Socket::close_with_frame`] is sent when the connection is closed, with a close code.
 fn forward_to_socket<H: EngineIoHandler, S>(
     socket: Arc<Socket<H::Data>>,
     mut tx: SplitSink<WebSocketStream<S>, Message>,
+    #[cfg(feature = "compression")] compression: Option<usize>,
 ) -> JoinHandle<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     // Pipe between websocket and internal socket channel
     tokio::spawn(async move {
-        let mut internal_rx = socket.internal_rx.try_lock().unwrap();
-
         // map a packet to a websocket message
         // It is declared as a macro rather than a closure to avoid ownership issues
         macro_rules! map_fn {
-            ($item:ident) => {
+            ($item:ident, $forward:lifetime) => {
                 let res = match $item {
                     Packet::Binary(bin) | Packet::BinaryV3(bin) => {
-                        tx.feed(Message::Binary(bin)).await
+                        #[cfg(feature = "compression")]
+                        let msg = compress_message(Message::Binary(bin), compression);
+                        #[cfg(not(feature = "compression"))]
+                        let msg = Message::Binary(bin);
+                        tx.feed(msg).await
                     }
                     Packet::Close => {
-                        tx.send(Message::Close(None)).await.ok();
-                        internal_rx.close();
-                        break;
+                        let frame = socket.take_close_frame().map(ws_close_frame);
+                        tx.send(Message::Close(frame)).await.ok();
+                        socket.internal_rx.lock().await.close();
+                        break $forward;
                     },
                     // A Noop Packet maybe sent by the server to upgrade from a polling connection
                     // In the case that the packet was not poll in time it will remain in the buffer and therefore
@@ -233,7 +400,11 @@ where
                     Packet::Noop => Ok(()),
                     _ => {
                         let packet: String = $item.try_into().unwrap();
-                        tx.feed(Message::Text(packet)).await
+                        #[cfg(feature = "compression")]
+                        let msg = compress_message(Message::Text(packet), compression);
+                        #[cfg(not(feature = "compression"))]
+                        let msg = Message::Text(packet);
+                        tx.feed(msg).await
                     }
                 };
                 if let Err(_e) = res {
@@ -243,28 +414,68 @@ where
             };
         }
 
-        while let Some(item) = internal_rx.recv().await {
-            map_fn!(item);
-
-            // For every available packet we continue to send until the channel is drained
-            while let Ok(item) = internal_rx.try_recv() {
-                map_fn!(item);
+        'forward: loop {
+            let batch = {
+                let mut internal_rx = socket.internal_rx.lock().await;
+                let Some(item) = internal_rx.recv().await else {
+                    // The channel was closed before the close packet could be sent
+                    if let Some(frame) = socket.take_close_frame() {
+                        tx.send(Message::Close(Some(ws_close_frame(frame))))
+                            .await
+                            .ok();
+                    }
+                    break;
+                };
+                // For every available packet we continue to send until the channel is drained
+                let mut batch = vec![item];
+                while let Ok(item) = internal_rx.try_recv() {
+                    batch.push(item);
+                }
+                batch
+            };
+            for item in batch {
+                map_fn!(item, 'forward);
             }
 
             tx.flush().await.ok();
         }
     })
 }
+/// Compresses the message if it is larger than the compression threshold
+#[cfg(feature = "compression")]
+fn compress_message(msg: Message, threshold: Option<usize>) -> Message {
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::Data;
+    let (data, opcode) = match (&msg, threshold) {
+        (Message::Text(text), Some(threshold)) if text.len() >= threshold => {
+            (text.as_bytes(), Data::Text)
+        }
+        (Message::Binary(bin), Some(threshold)) if bin.len() >= threshold => {
+            (bin.as_slice(), Data::Binary)
+        }
+        _ => return msg,
+    };
+    match deflate::compress_frame(data, opcode) {
+        Ok(frame) => Message::Frame(frame),
+        Err(_e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("error compressing message: {}", _e);
+            msg
+        }
+    }
+}
+
 /// Send a Engine.IO [`OpenPacket`] to initiate a websocket connection
-async fn init_handshake<S>(
-    sid: Sid,
+async fn init_handshake<D, S>(
+    socket: &Socket<D>,
     ws: &mut WebSocketStream<S>,
     config: &EngineIoConfig,
 ) -> Result<(), Error>
 where
+    D: Default + Send + Sync + 'static,
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let packet = Packet::Open(OpenPacket::new(TransportType::Websocket, sid, config));
+    let packet = OpenPacket::new(TransportType::Websocket, socket.id, config).with_session(socket);
+    let packet = Packet::Open(packet);
     ws.send(Message::Text(packet.try_into()?)).await?;
     Ok(())
 }
@@ -309,6 +520,8 @@ where
         _ => Err(Error::UpgradeError)?,
     };
     match Packet::try_from(msg)? {
+        // The probe fails so that the client keeps polling
+        Packet::PingUpgrade if !socket.is_upgradable() => Err(Error::UpgradeError)?,
         Packet::PingUpgrade => {
             // Respond with a PongUpgrade packet
             ws.send(Message::Text(Packet::PongUpgrade.try_into()?))
@@ -342,8 +555,7 @@ where
         p => Err(Error::BadPacket(p))?,
     };
 
-    // wait for any polling connection to finish by waiting for the socket to be unlocked
-    let _ = socket.internal_rx.lock().await;
-    socket.upgrade_to_websocket();
+    // wait for any polling connection to finish and for its payload to be sent
+    socket.upgrade_to(TransportType::Websocket).await;
     Ok(())
 }
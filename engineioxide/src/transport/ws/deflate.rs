@@ -0,0 +1,403 @@
+//! The `permessage-deflate` websocket extension ([RFC 7692](https://datatracker.ietf.org/doc/html/rfc7692)).
+//!
+//! tungstenite doesn't support websocket extensions, so:
+//! * Outgoing messages are compressed here and sent as raw frames with the `RSV1` bit set.
+//! * Incoming compressed messages are inflated by the [`DeflateStream`] wrapping the connection,
+//!   it rewrites them as plain frames before they reach tungstenite.
+//!
+//! The server never reuses its compression context between messages (`server_no_context_takeover`),
+//! while the context of the client is kept so that any offer can be accepted.
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use http::{HeaderMap, HeaderValue};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::protocol::frame::{
+    coding::{Data, OpCode},
+    Frame,
+};
+
+const EXTENSION_NAME: &str = "permessage-deflate";
+
+/// The trailer of a flushed deflate block, it is removed from the compressed messages
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// The error returned when a received message exceeds the `max_payload` limit,
+/// either compressed or once inflated
+#[derive(Debug)]
+pub(crate) struct MessageTooLarge;
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("message too large")
+    }
+}
+impl std::error::Error for MessageTooLarge {}
+
+/// Returns true if the io error was raised because a message exceeds the `max_payload` limit
+pub(crate) fn is_message_too_large(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |e| e.is::<MessageTooLarge>())
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, MessageTooLarge)
+}
+
+/// Returns the `Sec-WebSocket-Extensions` response header if one of the client offers can be accepted
+pub(crate) fn negotiate(headers: &HeaderMap) -> Option<HeaderValue> {
+    let mut offers = headers
+        .get_all(http::header::SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','));
+
+    offers
+        .any(|offer| {
+            let mut params = offer.split(';').map(str::trim);
+            params.next() == Some(EXTENSION_NAME) && params.all(is_supported_param)
+        })
+        .then(|| HeaderValue::from_static("permessage-deflate; server_no_context_takeover"))
+}
+
+fn is_supported_param(param: &str) -> bool {
+    let (name, value) = match param.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+        None => (param, None),
+    };
+    match (name, value) {
+        ("server_no_context_takeover" | "client_no_context_takeover", None) => true,
+        // Messages are always compressed with a 15 bits window
+        ("server_max_window_bits", Some("15")) => true,
+        // Any window can be inflated with a 15 bits window
+        ("client_max_window_bits", None) => true,
+        ("client_max_window_bits", Some(bits)) => matches!(bits.parse::<u8>(), Ok(8..=15)),
+        _ => false,
+    }
+}
+
+/// Compresses a message and returns it as a single frame with the `RSV1` bit set
+pub(crate) fn compress_frame(data: &[u8], opcode: Data) -> io::Result<Frame> {
+    let mut compress = Compress::new(Compression::default(), false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress
+            .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        // The flush is complete once all the input is consumed without filling the output
+        if compress.total_in() as usize == data.len() && out.len() < out.capacity() {
+            break;
+        }
+        out.reserve(out.capacity());
+    }
+    if out.ends_with(&TRAILER) {
+        out.truncate(out.len() - TRAILER.len());
+    }
+
+    let mut frame = Frame::message(out, OpCode::Data(opcode), true);
+    frame.header_mut().rsv1 = true;
+    Ok(frame)
+}
+
+/// Inflates a compressed message with the kept context of the client.
+///
+/// The inflated message is limited to `max_size` bytes, so that a small compressed message cannot exhaust the memory.
+fn inflate(decompress: &mut Decompress, data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    let input = [data, &TRAILER].concat();
+    let start = decompress.total_in();
+    // The output buffer never grows over the limit by more than one byte
+    let limit = max_size.saturating_add(1);
+    let mut out = Vec::with_capacity((input.len() * 2).min(limit));
+    loop {
+        let consumed = (decompress.total_in() - start) as usize;
+        let written = out.len();
+        let status = decompress
+            .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let consumed = (decompress.total_in() - start) as usize;
+
+        if out.len() > max_size {
+            return Err(too_large());
+        }
+        if status == Status::StreamEnd || (consumed == input.len() && out.len() < out.capacity()) {
+            return Ok(out);
+        }
+        if out.len() < out.capacity() && out.len() == written {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid compressed message",
+            ));
+        }
+        out.reserve_exact(out.capacity().min(limit - out.len()));
+    }
+}
+
+/// Appends an unfragmented frame to the buffer.
+/// It is masked with an empty key, as tungstenite requires the client frames to be masked
+fn write_frame(buf: &mut Vec<u8>, opcode: u8, payload: &[u8]) {
+    buf.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => buf.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            buf.push(0x80 | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(0x80 | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(payload);
+}
+
+/// A connection wrapper inflating the compressed messages sent by the client.
+///
+/// When the extension is not negotiated, the bytes are forwarded untouched.
+pub(crate) struct DeflateStream<S> {
+    inner: S,
+    enabled: bool,
+    /// Bytes read from the connection that don't make a complete frame yet
+    read_buf: Vec<u8>,
+    /// Bytes ready to be read by tungstenite
+    out_buf: Vec<u8>,
+    out_pos: usize,
+    /// The opcode and the payload of the compressed message being received
+    message: Option<(u8, Vec<u8>)>,
+    decompress: Decompress,
+    /// The maximum size of a received message, compressed or inflated
+    max_message_size: usize,
+}
+
+impl<S> DeflateStream<S> {
+    pub(crate) fn new(inner: S, enabled: bool, max_message_size: usize) -> Self {
+        Self {
+            inner,
+            enabled,
+            read_buf: Vec::new(),
+            out_buf: Vec::new(),
+            out_pos: 0,
+            message: None,
+            decompress: Decompress::new(false),
+            max_message_size,
+        }
+    }
+
+    /// Processes the next complete frame of the read buffer.
+    /// Returns false if there is not enough data to read a frame.
+    fn process_frame(&mut self) -> io::Result<bool> {
+        let buf = &self.read_buf;
+        if buf.len() < 2 {
+            return Ok(false);
+        }
+        let (b0, b1) = (buf[0], buf[1]);
+        let (mut header_len, len) = match b1 & 0x7f {
+            126 if buf.len() >= 4 => (4, u16::from_be_bytes([buf[2], buf[3]]) as u64),
+            127 if buf.len() >= 10 => (10, u64::from_be_bytes(buf[2..10].try_into().unwrap())),
+            126 | 127 => return Ok(false),
+            len => (2, len as u64),
+        };
+        let masked = b1 & 0x80 != 0;
+        if masked {
+            header_len += 4;
+        }
+        // The frames larger than the limit are rejected from their header, before their payload is buffered
+        if len > self.max_message_size as u64 {
+            return Err(too_large());
+        }
+        let frame_len = header_len + len as usize;
+        if buf.len() < frame_len {
+            return Ok(false);
+        }
+
+        let mut frame: Vec<u8> = self.read_buf.drain(..frame_len).collect();
+        let (fin, rsv1, opcode) = (b0 & 0x80 != 0, b0 & 0x40 != 0, b0 & 0x0f);
+        let is_control = opcode & 0x08 != 0;
+        let payload = |frame: &mut Vec<u8>| {
+            if masked {
+                let key: [u8; 4] = frame[header_len - 4..header_len].try_into().unwrap();
+                for (i, byte) in frame[header_len..].iter_mut().enumerate() {
+                    *byte ^= key[i % 4];
+                }
+            }
+            frame.split_off(header_len)
+        };
+
+        match self.message {
+            // The first frame of a compressed message
+            None if rsv1 && !is_control && opcode != 0 => {
+                self.message = Some((opcode, payload(&mut frame)));
+            }
+            // A continuation frame of a compressed message
+            Some((_, ref mut data)) if !rsv1 && opcode == 0 => {
+                data.extend(payload(&mut frame));
+                if data.len() > self.max_message_size {
+                    return Err(too_large());
+                }
+            }
+            // Control frames, uncompressed messages and invalid frames are forwarded to tungstenite
+            _ => {
+                self.out_buf.extend_from_slice(&frame);
+                return Ok(true);
+            }
+        }
+
+        if fin {
+            let (opcode, data) = self.message.take().unwrap();
+            let data = inflate(&mut self.decompress, &data, self.max_message_size)?;
+            write_frame(&mut self.out_buf, opcode, &data);
+        }
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.enabled {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if this.out_pos < this.out_buf.len() {
+                let n = buf.remaining().min(this.out_buf.len() - this.out_pos);
+                buf.put_slice(&this.out_buf[this.out_pos..this.out_pos + n]);
+                this.out_pos += n;
+                if this.out_pos == this.out_buf.len() {
+                    this.out_buf.clear();
+                    this.out_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if this.process_frame()? {
+                continue;
+            }
+
+            let mut chunk = [0; 4096];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // The connection is closed, the incomplete frame is forwarded and tungstenite will report it
+                if this.read_buf.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                this.out_buf.append(&mut this.read_buf);
+            } else {
+                this.read_buf.extend_from_slice(chunk.filled());
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn headers(extensions: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static(extensions),
+        );
+        headers
+    }
+
+    #[test]
+    fn negotiation() {
+        assert!(negotiate(&HeaderMap::new()).is_none());
+        assert!(negotiate(&headers("permessage-deflate")).is_some());
+        assert!(negotiate(&headers("permessage-deflate; client_max_window_bits")).is_some());
+        assert!(negotiate(&headers(
+            "permessage-deflate; client_no_context_takeover; client_max_window_bits=10"
+        ))
+        .is_some());
+        assert!(negotiate(&headers("permessage-deflate; server_max_window_bits=10")).is_none());
+        // The second offer is acceptable
+        assert!(negotiate(&headers(
+            "permessage-deflate; server_max_window_bits=10, permessage-deflate"
+        ))
+        .is_some());
+        assert!(negotiate(&headers("x-webkit-deflate-frame")).is_none());
+    }
+
+    #[tokio::test]
+    async fn inflate_fragmented_message() {
+        let message = "4".to_string() + &"hello world ".repeat(100);
+        let frame = compress_frame(message.as_bytes(), Data::Text).unwrap();
+        let payload = frame.into_data();
+        let (first, second) = payload.split_at(payload.len() / 2);
+
+        // A masked compressed message split in two frames, followed by a plain ping
+        let mut raw = Vec::new();
+        let key = [1, 2, 3, 4];
+        for (header, data) in [(0x40 | 0x01, first), (0x80, second)] {
+            raw.push(header);
+            raw.push(0x80 | 126);
+            raw.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            raw.extend_from_slice(&key);
+            raw.extend(data.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        }
+        raw.extend_from_slice(&[0x89, 0x80, 0, 0, 0, 0]);
+
+        let mut stream = DeflateStream::new(&raw[..], true, usize::MAX);
+        let mut out = Vec::new();
+        stream.read_to_end(&mut out).await.unwrap();
+
+        let mut expected = Vec::new();
+        write_frame(&mut expected, 0x01, message.as_bytes());
+        expected.extend_from_slice(&[0x89, 0x80, 0, 0, 0, 0]);
+        assert_eq!(out, expected);
+    }
+
+    #[tokio::test]
+    async fn inflate_max_message_size() {
+        let message = "4".to_string() + &"a".repeat(100_000);
+        let payload = compress_frame(message.as_bytes(), Data::Text)
+            .unwrap()
+            .into_data();
+        let mut raw = vec![0x40 | 0x81, 126];
+        raw.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        raw.extend_from_slice(&payload);
+
+        // The compressed message fits but not the inflated one
+        let mut stream = DeflateStream::new(&raw[..], true, 1024);
+        let err = stream.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(is_message_too_large(&err));
+
+        // The compressed frame is rejected from its header
+        let mut stream = DeflateStream::new(&raw[..4], true, 16);
+        let err = stream.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(is_message_too_large(&err));
+
+        let mut stream = DeflateStream::new(&raw[..], true, message.len());
+        let mut out = Vec::new();
+        stream.read_to_end(&mut out).await.unwrap();
+        assert!(out.ends_with(message.as_bytes()));
+    }
+}
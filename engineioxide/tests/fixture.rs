@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::{
     collections::VecDeque,
     net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -61,7 +63,13 @@ pub async fn send_req(
 }
 
 pub async fn create_polling_connection(port: u16) -> String {
-    let body = send_req(port, format!("transport=polling"), http::Method::GET, None).await;
+    let body = send_req(
+        port,
+        "transport=polling".to_string(),
+        http::Method::GET,
+        None,
+    )
+    .await;
     let open_packet: OpenPacket = serde_json::from_str(&body).unwrap();
     open_packet.sid
 }
@@ -80,7 +88,14 @@ pub async fn create_server<H: EngineIoHandler>(handler: H, port: u16) {
         .ping_timeout(Duration::from_millis(200))
         .max_payload(1e6 as u64)
         .build();
+    create_server_with_config(handler, port, config).await;
+}
 
+pub async fn create_server_with_config<H: EngineIoHandler>(
+    handler: H,
+    port: u16,
+    config: EngineIoConfig,
+) {
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
 
     let svc = EngineIoService::with_config(handler, config);
@@ -0,0 +1,93 @@
+//! Tests for the limit applied to the handshakes of the new sessions
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use engineioxide::{
+    config::{EngineIoConfig, HandshakeLimit},
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use futures::future::join_all;
+use http::{header, Request, StatusCode};
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+mod fixture;
+
+use fixture::create_server_with_config;
+
+#[derive(Debug, Clone)]
+struct MyHandler;
+
+impl EngineIoHandler for MyHandler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+/// Fires `count` simultaneous polling handshakes and returns their responses
+async fn handshakes(port: u16, count: usize) -> Vec<http::Response<hyper::body::Incoming>> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let reqs = (0..count).map(|_| {
+        let req = Request::get(format!(
+            "http://127.0.0.1:{port}/engine.io/?EIO=4&transport=polling"
+        ))
+        .body(Empty::new())
+        .unwrap();
+        client.request(req)
+    });
+    join_all(reqs)
+        .await
+        .into_iter()
+        .map(|res| res.unwrap())
+        .collect()
+}
+
+#[tokio::test]
+pub async fn handshake_limit_reject() {
+    const PORT: u16 = 3160;
+    let config = EngineIoConfig::builder()
+        .handshake_limit(HandshakeLimit::new(1.0, 5))
+        .build();
+    create_server_with_config(MyHandler, PORT, config).await;
+
+    let responses = handshakes(PORT, 20).await;
+    let accepted = responses
+        .iter()
+        .filter(|res| res.status() == StatusCode::OK)
+        .count();
+    assert_eq!(accepted, 5);
+    for res in responses
+        .iter()
+        .filter(|res| res.status() != StatusCode::OK)
+    {
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after: u64 = res.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after >= 1, "unexpected retry after: {retry_after}");
+    }
+}
+
+#[tokio::test]
+pub async fn handshake_limit_queue() {
+    const PORT: u16 = 3161;
+    let limit = HandshakeLimit::new(20.0, 2).max_queue_time(Duration::from_secs(1));
+    let config = EngineIoConfig::builder().handshake_limit(limit).build();
+    create_server_with_config(MyHandler, PORT, config).await;
+
+    // The 8 handshakes exceeding the burst are queued and accepted at 20 per second
+    let start = Instant::now();
+    let responses = handshakes(PORT, 10).await;
+    assert!(responses.iter().all(|res| res.status() == StatusCode::OK));
+    assert!(start.elapsed() >= Duration::from_millis(390));
+}
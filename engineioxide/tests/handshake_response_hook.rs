@@ -0,0 +1,79 @@
+//! Tests for the hook adding headers to the handshake responses
+use std::sync::Arc;
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use http::{header::SET_COOKIE, HeaderValue, Request};
+use http_body_util::{BodyExt, Empty};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde_json::Value;
+
+mod fixture;
+
+use fixture::create_server_with_config;
+
+#[derive(Debug, Clone)]
+struct Handler;
+
+impl EngineIoHandler for Handler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+fn csrf_token(res_headers: &http::HeaderMap) -> Option<&str> {
+    res_headers.get("x-csrf-token").map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+pub async fn handshake_response_headers() {
+    const PORT: u16 = 3180;
+    let config = EngineIoConfig::builder()
+        .handshake_response_hook(|req, headers| {
+            let is_ws = req.uri.query().unwrap_or_default().contains("websocket");
+            let token = if is_ws { "ws-token" } else { "polling-token" };
+            headers.insert("x-csrf-token", HeaderValue::from_static(token));
+            headers.append(SET_COOKIE, HeaderValue::from_static("csrf=1; HttpOnly"));
+        })
+        .build();
+    create_server_with_config(Handler, PORT, config).await;
+
+    // Polling handshake
+    let req = Request::get(format!(
+        "http://127.0.0.1:{PORT}/engine.io/?EIO=4&transport=polling"
+    ))
+    .body(Empty::<bytes::Bytes>::new())
+    .unwrap();
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await
+        .unwrap();
+    assert_eq!(csrf_token(res.headers()), Some("polling-token"));
+    assert_eq!(res.headers()[SET_COOKIE], "csrf=1; HttpOnly");
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    let open: Value = serde_json::from_slice(&body[1..]).unwrap();
+    let sid = open["sid"].as_str().unwrap();
+
+    // Websocket handshake
+    let (_ws, res) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{PORT}/engine.io/?EIO=4&transport=websocket"
+    ))
+    .await
+    .unwrap();
+    assert_eq!(csrf_token(res.headers()), Some("ws-token"));
+
+    // Upgrade of the polling session
+    let (_ws, res) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{PORT}/engine.io/?EIO=4&transport=websocket&sid={sid}"
+    ))
+    .await
+    .unwrap();
+    assert_eq!(csrf_token(res.headers()), Some("ws-token"));
+}
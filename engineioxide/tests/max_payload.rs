@@ -0,0 +1,130 @@
+//! Tests for the `maxPayload` advertised in the handshake, which must match the limit
+//! applied by the polling encoder of the session, and for the limit applied to the websocket frames
+
+use std::sync::Arc;
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+mod fixture;
+
+use fixture::{create_server_with_config, create_ws_connection, send_req};
+
+#[derive(Debug, Clone)]
+struct MyHandler {
+    /// Overrides the max payload of each session when it is created
+    max_payload: Option<u64>,
+    disconnect_tx: Option<mpsc::UnboundedSender<DisconnectReason>>,
+}
+
+impl EngineIoHandler for MyHandler {
+    type Data = ();
+
+    fn on_connect(&self, socket: Arc<Socket<()>>) {
+        if let Some(max_payload) = self.max_payload {
+            socket.set_max_payload(max_payload);
+        }
+    }
+    fn on_disconnect(&self, _: Arc<Socket<()>>, reason: DisconnectReason) {
+        if let Some(tx) = &self.disconnect_tx {
+            tx.send(reason).ok();
+        }
+    }
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+async fn polling_handshake(port: u16) -> Value {
+    let body = send_req(
+        port,
+        "transport=polling".to_string(),
+        http::Method::GET,
+        None,
+    )
+    .await;
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn ws_handshake(port: u16) -> Value {
+    let mut stream = create_ws_connection(port).await;
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    serde_json::from_str(&msg[1..]).unwrap()
+}
+
+#[tokio::test]
+pub async fn handshake_max_payload_from_config() {
+    const PORT: u16 = 3140;
+    let config = EngineIoConfig::builder().max_payload(12345).build();
+    create_server_with_config(
+        MyHandler {
+            max_payload: None,
+            disconnect_tx: None,
+        },
+        PORT,
+        config,
+    )
+    .await;
+
+    assert_eq!(polling_handshake(PORT).await["maxPayload"], 12345);
+    assert_eq!(ws_handshake(PORT).await["maxPayload"], 12345);
+}
+
+#[tokio::test]
+pub async fn handshake_max_payload_from_session() {
+    const PORT: u16 = 3141;
+    let config = EngineIoConfig::builder().max_payload(12345).build();
+    let handler = MyHandler {
+        max_payload: Some(512),
+        disconnect_tx: None,
+    };
+    create_server_with_config(handler, PORT, config).await;
+
+    assert_eq!(polling_handshake(PORT).await["maxPayload"], 512);
+    assert_eq!(ws_handshake(PORT).await["maxPayload"], 512);
+}
+
+#[tokio::test]
+pub async fn ws_oversized_frame() {
+    const PORT: u16 = 3142;
+    let config = EngineIoConfig::builder().max_payload(1024).build();
+    let (disconnect_tx, mut disconnect_rx) = mpsc::unbounded_channel();
+    let handler = MyHandler {
+        max_payload: None,
+        disconnect_tx: Some(disconnect_tx),
+    };
+    create_server_with_config(handler, PORT, config).await;
+
+    let mut stream = create_ws_connection(PORT).await;
+    let _open = stream.next().await.unwrap().unwrap();
+
+    // A frame within the limit is accepted
+    stream
+        .send(Message::Text(format!("4{}", "a".repeat(1000))))
+        .await
+        .unwrap();
+
+    stream
+        .send(Message::Text(format!("4{}", "a".repeat(2000))))
+        .await
+        .unwrap();
+    let msg = tokio::time::timeout(std::time::Duration::from_secs(1), stream.next())
+        .await
+        .expect("the connection should be closed")
+        .unwrap()
+        .unwrap();
+    match msg {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Size),
+        msg => panic!("unexpected message: {msg:?}"),
+    }
+    assert_eq!(
+        disconnect_rx.recv().await.unwrap(),
+        DisconnectReason::PacketParsingError
+    );
+}
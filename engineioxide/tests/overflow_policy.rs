@@ -0,0 +1,154 @@
+//! Tests for the overflow policies applied when the buffer of a socket is full
+//! The mock client opens a polling session and does not poll until the buffer is full.
+
+use std::{sync::Arc, time::Duration};
+
+use engineioxide::{
+    config::{EngineIoConfig, OverflowPolicy},
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use tokio::sync::mpsc;
+
+mod fixture;
+
+use fixture::{create_polling_connection, create_server_with_config, send_req};
+
+#[derive(Debug, Clone)]
+struct MyHandler {
+    connect_tx: mpsc::UnboundedSender<Arc<Socket<()>>>,
+    disconnect_tx: mpsc::UnboundedSender<DisconnectReason>,
+}
+
+impl EngineIoHandler for MyHandler {
+    type Data = ();
+
+    fn on_connect(&self, socket: Arc<Socket<()>>) {
+        self.connect_tx.send(socket).unwrap();
+    }
+    fn on_disconnect(&self, _: Arc<Socket<()>>, reason: DisconnectReason) {
+        self.disconnect_tx.send(reason).unwrap();
+    }
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+/// Creates a server with a buffer of 2 packets and connects a polling client that never polls
+async fn setup(
+    port: u16,
+    policy: OverflowPolicy,
+) -> (
+    Arc<Socket<()>>,
+    String,
+    mpsc::UnboundedReceiver<DisconnectReason>,
+) {
+    let (connect_tx, mut connect_rx) = mpsc::unbounded_channel();
+    let (disconnect_tx, disconnect_rx) = mpsc::unbounded_channel();
+    let config = EngineIoConfig::builder()
+        .max_buffer_size(2)
+        .overflow_policy(policy)
+        .build();
+    let handler = MyHandler {
+        connect_tx,
+        disconnect_tx,
+    };
+    create_server_with_config(handler, port, config).await;
+    let sid = create_polling_connection(port).await;
+    let socket = connect_rx.recv().await.unwrap();
+    (socket, sid, disconnect_rx)
+}
+
+/// Polls the buffered messages
+async fn poll(port: u16, sid: &str) -> Vec<String> {
+    let body = send_req(
+        port,
+        format!("transport=polling&sid={sid}"),
+        http::Method::GET,
+        None,
+    )
+    .await;
+    // The first char of the payload is stripped by `send_req`
+    format!("4{body}")
+        .split('\x1e')
+        .map(|packet| packet.trim_start_matches('4').to_string())
+        .collect()
+}
+
+#[tokio::test]
+pub async fn overflow_error() {
+    let (socket, sid, mut disconnect_rx) = setup(3110, OverflowPolicy::Error).await;
+    socket.emit("m1".into()).unwrap();
+    socket.emit("m2".into()).unwrap();
+    assert!(matches!(
+        socket.emit("m3".into()),
+        Err(mpsc::error::TrySendError::Full(msg)) if msg == "m3"
+    ));
+    assert_eq!(poll(3110, &sid).await, ["m1", "m2"]);
+    assert!(disconnect_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn overflow_disconnect_slow_client() {
+    let (socket, _, mut disconnect_rx) = setup(3111, OverflowPolicy::DisconnectSlowClient).await;
+    socket.emit("m1".into()).unwrap();
+    socket.emit("m2".into()).unwrap();
+    assert!(socket.emit("m3".into()).is_err());
+
+    let reason = tokio::time::timeout(Duration::from_millis(100), disconnect_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reason, DisconnectReason::SlowClient);
+}
+
+#[tokio::test]
+pub async fn overflow_drop_oldest() {
+    let (socket, sid, _) = setup(3112, OverflowPolicy::DropOldest).await;
+    for msg in ["m1", "m2", "m3", "m4"] {
+        socket.emit(msg.into()).unwrap();
+    }
+    assert_eq!(poll(3112, &sid).await, ["m3", "m4"]);
+}
+
+#[tokio::test]
+pub async fn overflow_block() {
+    let (socket, sid, _) = setup(3113, OverflowPolicy::Block).await;
+    socket.emit("m1".into()).unwrap();
+    socket.emit("m2".into()).unwrap();
+
+    // The synchronous emit cannot wait for the buffer to have room
+    assert!(matches!(
+        socket.emit("m3".into()),
+        Err(mpsc::error::TrySendError::Full(msg)) if msg == "m3"
+    ));
+
+    let blocked = tokio::spawn({
+        let socket = socket.clone();
+        async move { socket.emit_async("m3".into()).await.unwrap() }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!blocked.is_finished());
+
+    // Polling drains the buffer and wakes up the emit
+    let mut messages = poll(3113, &sid).await;
+    tokio::time::timeout(Duration::from_millis(100), blocked)
+        .await
+        .unwrap()
+        .unwrap();
+    socket.emit_async("m4".into()).await.unwrap();
+    messages.extend(poll(3113, &sid).await);
+    assert_eq!(messages, ["m1", "m2", "m3", "m4"]);
+}
+
+#[tokio::test(flavor = "current_thread")]
+pub async fn overflow_block_current_thread() {
+    let (socket, sid, _) = setup(3114, OverflowPolicy::Block).await;
+    socket.emit("m1".into()).unwrap();
+    socket.emit("m2".into()).unwrap();
+
+    // The polling request is served by the same thread while the emit is waiting
+    let (res, messages) = tokio::join!(socket.emit_async("m3".into()), poll(3114, &sid));
+    res.unwrap();
+    assert_eq!(messages, ["m1", "m2"]);
+    assert_eq!(poll(3114, &sid).await, ["m3"]);
+}
@@ -0,0 +1,148 @@
+//! Tests for the polling transport over HTTP/2
+//! All the requests of a session are multiplexed on a single h2 connection
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use engineioxide::{
+    handler::EngineIoHandler,
+    service::EngineIoService,
+    socket::{DisconnectReason, Socket},
+};
+use http::{header, Request, Response, Version};
+use http_body_util::{BodyExt, Full};
+use hyper::server::conn::http2;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone)]
+struct EchoHandler;
+
+impl EngineIoHandler for EchoHandler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+
+    fn on_message(&self, msg: String, socket: Arc<Socket<()>>) {
+        socket.emit(msg).ok();
+    }
+
+    fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<()>>) {
+        socket.emit_binary(data).ok();
+    }
+}
+
+type H2Client = Client<HttpConnector, Full<Bytes>>;
+
+/// Serves the engine.io service with HTTP/2 only (prior knowledge, without TLS)
+async fn create_h2_server(port: u16) {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+    let svc = EngineIoService::new(EchoHandler);
+    let listener = TcpListener::bind(&addr).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+            let svc = svc.clone();
+            tokio::spawn(async move {
+                if let Err(err) = http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(io, svc)
+                    .await
+                {
+                    println!("Error serving connection: {:?}", err);
+                }
+            });
+        }
+    });
+}
+
+fn h2_client() -> H2Client {
+    Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http()
+}
+
+async fn send_req(
+    client: &H2Client,
+    port: u16,
+    params: &str,
+    method: http::Method,
+    body: Option<&'static [u8]>,
+) -> Response<Bytes> {
+    let body = body.map(Bytes::from_static).unwrap_or_default();
+    let req = Request::builder()
+        .method(method)
+        .uri(format!("http://127.0.0.1:{port}/engine.io/?EIO=4&{params}"))
+        .body(Full::new(body))
+        .unwrap();
+    let res = client.request(req).await.unwrap();
+    let (parts, body) = res.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    Response::from_parts(parts, body)
+}
+
+/// Checks that the response is framed for HTTP/2 and returns its body
+fn check_h2_response(res: Response<Bytes>) -> Bytes {
+    assert_eq!(res.version(), Version::HTTP_2);
+    assert!(res.headers().get(header::CONNECTION).is_none());
+    assert!(res.headers().get(header::TRANSFER_ENCODING).is_none());
+    let len = res.headers().get(header::CONTENT_LENGTH).unwrap();
+    assert_eq!(len.to_str().unwrap(), res.body().len().to_string());
+    res.into_body()
+}
+
+async fn open_session(client: &H2Client, port: u16) -> String {
+    let res = send_req(client, port, "transport=polling", http::Method::GET, None).await;
+    let body = check_h2_response(res);
+    let open: Value = serde_json::from_slice(&body[1..]).unwrap();
+    open["sid"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+pub async fn polling_round_trip() {
+    const PORT: u16 = 3130;
+    create_h2_server(PORT).await;
+    let client = h2_client();
+    let sid = open_session(&client, PORT).await;
+    let params = format!("transport=polling&sid={sid}");
+
+    let res = send_req(&client, PORT, &params, http::Method::POST, Some(b"4hello")).await;
+    assert_eq!(check_h2_response(res), "ok");
+
+    let res = send_req(&client, PORT, &params, http::Method::GET, None).await;
+    assert_eq!(check_h2_response(res), "4hello");
+
+    // Binary payloads are base64 encoded in a text polling payload
+    let res = send_req(&client, PORT, &params, http::Method::POST, Some(b"bAQID")).await;
+    assert_eq!(check_h2_response(res), "ok");
+
+    let res = send_req(&client, PORT, &params, http::Method::GET, None).await;
+    assert_eq!(check_h2_response(res), "bAQID");
+}
+
+#[tokio::test]
+pub async fn polling_multiplexed_requests() {
+    const PORT: u16 = 3131;
+    create_h2_server(PORT).await;
+    let client = h2_client();
+    let sid = open_session(&client, PORT).await;
+    let params = format!("transport=polling&sid={sid}");
+
+    // The pending polling request must not block the post request sent on the same connection
+    let poll = send_req(&client, PORT, &params, http::Method::GET, None);
+    let post = async {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        send_req(&client, PORT, &params, http::Method::POST, Some(b"4hello")).await
+    };
+    let (poll, post) = tokio::join!(poll, post);
+    assert_eq!(check_h2_response(post), "ok");
+    assert_eq!(check_h2_response(poll), "4hello");
+}
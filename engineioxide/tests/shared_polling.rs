@@ -0,0 +1,235 @@
+//! Tests for the polling sessions shared between two nodes through a common store
+//! Each node is a different server, the requests of a session alternate between them.
+#![cfg(feature = "shared-polling")]
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+    store::{MemoryPollingStore, SharedPolling},
+};
+use http::{header, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+mod fixture;
+
+use fixture::create_server_with_config;
+
+#[derive(Debug, Clone)]
+struct EchoHandler {
+    node: &'static str,
+    events: mpsc::UnboundedSender<String>,
+}
+
+impl EngineIoHandler for EchoHandler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {
+        self.events.send(format!("{} connect", self.node)).ok();
+    }
+    fn on_disconnect(&self, _: Arc<Socket<()>>, reason: DisconnectReason) {
+        self.events
+            .send(format!("{} disconnect {reason:?}", self.node))
+            .ok();
+    }
+
+    fn on_message(&self, msg: String, socket: Arc<Socket<()>>) {
+        socket.emit(format!("{} {msg}", self.node)).ok();
+    }
+
+    fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<()>>) {
+        socket.emit_binary(data).ok();
+    }
+}
+
+async fn send_req(
+    port: u16,
+    params: &str,
+    method: http::Method,
+    cookie: Option<&str>,
+    body: &'static str,
+) -> Response<Bytes> {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(format!("http://127.0.0.1:{port}/engine.io/?EIO=4&{params}"));
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    let req = req
+        .body(Full::new(Bytes::from_static(body.as_bytes())))
+        .unwrap();
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await
+        .unwrap();
+    let (parts, body) = res.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    Response::from_parts(parts, body)
+}
+
+#[tokio::test]
+pub async fn shared_polling_alternate_nodes() {
+    const NODE_A: u16 = 3150;
+    const NODE_B: u16 = 3151;
+    let store = MemoryPollingStore::new();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for (node, port) in [("A", NODE_A), ("B", NODE_B)] {
+        let shared = SharedPolling::new(store.clone(), "secret key")
+            .poll_interval(Duration::from_millis(10));
+        let config = EngineIoConfig::builder().shared_polling(shared).build();
+        let handler = EchoHandler {
+            node,
+            events: tx.clone(),
+        };
+        create_server_with_config(handler, port, config).await;
+    }
+
+    // The session is opened on the node A
+    let res = send_req(NODE_A, "transport=polling", http::Method::GET, None, "").await;
+    let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let open: Value = serde_json::from_slice(&res.body()[1..]).unwrap();
+    let sid = open["sid"].as_str().unwrap();
+    assert_eq!(open["upgrades"], Value::Array(vec![]));
+    assert_eq!(rx.recv().await.unwrap(), "A connect");
+
+    let params = format!("transport=polling&sid={sid}");
+    let req = |port, method, body| send_req(port, &params, method, Some(&cookie), body);
+
+    // The requests alternate between the nodes, the owner node A handles the messages
+    let res = req(NODE_B, http::Method::POST, "4hello").await;
+    assert_eq!(res.body(), "ok");
+    let res = req(NODE_A, http::Method::GET, "").await;
+    assert_eq!(res.body(), "4A hello");
+
+    let res = req(NODE_A, http::Method::POST, "4world\x1ebAQID").await;
+    assert_eq!(res.body(), "ok");
+    let res = req(NODE_B, http::Method::GET, "").await;
+    assert_eq!(res.body(), "4A world\x1ebAQID");
+
+    // The requests without a valid session cookie are rejected
+    let res = send_req(NODE_B, &params, http::Method::GET, None, "").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let forged = format!("io={sid}.AAAA");
+    let res = send_req(NODE_B, &params, http::Method::POST, Some(&forged), "4hi").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // The session is closed by the client through the node B
+    let res = req(NODE_B, http::Method::POST, "1").await;
+    assert_eq!(res.body(), "ok");
+    let event = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event, "A disconnect TransportClose");
+    assert!(
+        rx.try_recv().is_err(),
+        "node B should not handle the session"
+    );
+}
+
+/// Opens a session on the node A, the requests of the client are then sent with its session cookie
+async fn open_session(port: u16) -> (String, String) {
+    let res = send_req(port, "transport=polling", http::Method::GET, None, "").await;
+    let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let open: Value = serde_json::from_slice(&res.body()[1..]).unwrap();
+    (open["sid"].as_str().unwrap().to_string(), cookie)
+}
+
+#[tokio::test]
+pub async fn shared_polling_overlapping_requests() {
+    const NODE_A: u16 = 3152;
+    const NODE_B: u16 = 3153;
+    let store = MemoryPollingStore::new();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for (node, port) in [("A", NODE_A), ("B", NODE_B)] {
+        let shared = SharedPolling::new(store.clone(), "secret key")
+            .poll_interval(Duration::from_millis(10));
+        let config = EngineIoConfig::builder().shared_polling(shared).build();
+        let handler = EchoHandler {
+            node,
+            events: tx.clone(),
+        };
+        create_server_with_config(handler, port, config).await;
+    }
+
+    let (sid, cookie) = open_session(NODE_A).await;
+    assert_eq!(rx.recv().await.unwrap(), "A connect");
+    let params = format!("transport=polling&sid={sid}");
+
+    // A GET request is pending on the node B while another one is sent to the node A
+    let pending = {
+        let (params, cookie) = (params.clone(), cookie.clone());
+        tokio::spawn(async move {
+            send_req(NODE_B, &params, http::Method::GET, Some(&cookie), "").await
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let res = send_req(NODE_A, &params, http::Method::GET, Some(&cookie), "").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let event = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event, "A disconnect MultipleHttpPollingError");
+    let res = tokio::time::timeout(Duration::from_secs(1), pending)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+pub async fn shared_polling_request_released() {
+    const NODE_A: u16 = 3154;
+    const NODE_B: u16 = 3155;
+    let store = MemoryPollingStore::new();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // The node B waits for the packets of a session at most for its own heartbeat cycle
+    let nodes = [
+        ("A", NODE_A, Duration::from_secs(25)),
+        ("B", NODE_B, Duration::from_millis(100)),
+    ];
+    for (node, port, heartbeat) in nodes {
+        let shared = SharedPolling::new(store.clone(), "secret key")
+            .poll_interval(Duration::from_millis(10));
+        let config = EngineIoConfig::builder()
+            .ping_interval(heartbeat)
+            .ping_timeout(heartbeat)
+            .shared_polling(shared)
+            .build();
+        let handler = EchoHandler {
+            node,
+            events: tx.clone(),
+        };
+        create_server_with_config(handler, port, config).await;
+    }
+
+    let (sid, cookie) = open_session(NODE_A).await;
+    assert_eq!(rx.recv().await.unwrap(), "A connect");
+    let params = format!("transport=polling&sid={sid}");
+
+    // Without any packet, the request is released with a noop packet
+    let res = tokio::time::timeout(
+        Duration::from_secs(1),
+        send_req(NODE_B, &params, http::Method::GET, Some(&cookie), ""),
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.body(), "6");
+
+    // The session is still open, the next request is not an overlapping one
+    let res = send_req(NODE_B, &params, http::Method::POST, Some(&cookie), "4hello").await;
+    assert_eq!(res.body(), "ok");
+    let res = send_req(NODE_B, &params, http::Method::GET, Some(&cookie), "").await;
+    assert_eq!(res.body(), "4A hello");
+}
@@ -0,0 +1,83 @@
+//! Tests for the polling payloads streamed in a chunked response
+
+use std::sync::Arc;
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use http::{header, Request};
+use http_body_util::{BodyExt, Empty};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+mod fixture;
+
+use fixture::{create_polling_connection, create_server_with_config};
+
+#[derive(Debug, Clone)]
+struct MyHandler;
+
+impl EngineIoHandler for MyHandler {
+    type Data = ();
+
+    fn on_connect(&self, socket: Arc<Socket<()>>) {
+        for msg in ["hello", "world", "foo"] {
+            socket.emit(msg.to_string()).unwrap();
+        }
+    }
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+async fn poll(port: u16, sid: &str) -> (http::response::Parts, String) {
+    let req = Request::builder()
+        .method(http::Method::GET)
+        .uri(format!(
+            "http://127.0.0.1:{port}/engine.io/?EIO=4&transport=polling&sid={sid}"
+        ))
+        .body(Empty::<bytes::Bytes>::new())
+        .unwrap();
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await
+        .unwrap();
+    let (parts, body) = res.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    (parts, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+pub async fn stream_polling_payload() {
+    const PORT: u16 = 3104;
+    // "4hello\x1e4world" is 13 bytes, the last packet is sent in the next payload
+    let config = EngineIoConfig::builder()
+        .max_payload(13)
+        .stream_polling_payloads(true)
+        .build();
+    create_server_with_config(MyHandler, PORT, config).await;
+
+    let sid = create_polling_connection(PORT).await;
+    let (parts, body) = poll(PORT, &sid).await;
+    assert_eq!(body, "4hello\x1e4world");
+    assert_eq!(parts.headers[header::TRANSFER_ENCODING], "chunked");
+    assert!(parts.headers.get(header::CONTENT_LENGTH).is_none());
+
+    let (_, body) = poll(PORT, &sid).await;
+    assert_eq!(body, "4foo");
+}
+
+#[tokio::test]
+pub async fn buffered_polling_payload() {
+    const PORT: u16 = 3105;
+    let config = EngineIoConfig::builder().max_payload(13).build();
+    create_server_with_config(MyHandler, PORT, config).await;
+
+    let sid = create_polling_connection(PORT).await;
+    let (parts, body) = poll(PORT, &sid).await;
+    assert_eq!(body, "4hello\x1e4world");
+    assert_eq!(parts.headers[header::CONTENT_LENGTH], "13");
+    assert!(parts.headers.get(header::TRANSFER_ENCODING).is_none());
+}
@@ -0,0 +1,212 @@
+//! Tests for the webtransport transport
+//! The HTTP/3 server is replaced by a mock client writing the engine.io frames to an in-memory stream.
+#![cfg(feature = "webtransport")]
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    service::EngineIoService,
+    socket::{DisconnectReason, Socket},
+    TransportType,
+};
+use http::Request;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::TcpListener,
+    sync::mpsc,
+};
+
+mod fixture;
+use fixture::send_req;
+
+#[derive(Debug, Clone)]
+struct EchoHandler {
+    disconnect_tx: mpsc::UnboundedSender<DisconnectReason>,
+}
+
+impl EngineIoHandler for EchoHandler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, reason: DisconnectReason) {
+        self.disconnect_tx.send(reason).ok();
+    }
+
+    fn on_message(&self, msg: String, socket: Arc<Socket<()>>) {
+        if msg == "transport" {
+            let transport: &str = socket.transport_type().into();
+            socket.emit(transport.to_string()).ok();
+        } else {
+            socket.emit(msg).ok();
+        }
+    }
+
+    fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<()>>) {
+        socket.emit_binary(data).ok();
+    }
+}
+
+/// A client writing and reading the frames of the engine.io webtransport transport
+struct MockClient {
+    stream: DuplexStream,
+}
+
+impl MockClient {
+    /// Opens a bidirectional stream and hands it over to the service, like an HTTP/3 server would do
+    fn connect(svc: &EngineIoService<EchoHandler>) -> Self {
+        let (client, server) = tokio::io::duplex(1 << 16);
+        let req = Request::builder()
+            .uri("https://127.0.0.1/engine.io/")
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        let svc = svc.clone();
+        tokio::spawn(async move { svc.on_webtransport(req, server).await });
+        MockClient { stream: client }
+    }
+
+    async fn send(&mut self, data: &[u8], binary: bool) {
+        assert!(data.len() < 126);
+        let header = data.len() as u8 | if binary { 0x80 } else { 0 };
+        self.stream.write_all(&[header]).await.unwrap();
+        self.stream.write_all(data).await.unwrap();
+    }
+
+    async fn send_text(&mut self, packet: &str) {
+        self.send(packet.as_bytes(), false).await;
+    }
+
+    /// Reads the next frame, returning its payload and whether it is binary
+    async fn recv(&mut self) -> (Vec<u8>, bool) {
+        let header = self.stream.read_u8().await.unwrap();
+        let len = match header & 0x7f {
+            126 => self.stream.read_u16().await.unwrap() as usize,
+            127 => self.stream.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut data = vec![0; len];
+        self.stream.read_exact(&mut data).await.unwrap();
+        (data, header & 0x80 != 0)
+    }
+
+    async fn recv_text(&mut self) -> String {
+        let (data, binary) = tokio::time::timeout(Duration::from_secs(1), self.recv())
+            .await
+            .expect("no frame received");
+        assert!(!binary);
+        String::from_utf8(data).unwrap()
+    }
+}
+
+fn create_svc(
+    config: EngineIoConfig,
+) -> (
+    EngineIoService<EchoHandler>,
+    mpsc::UnboundedReceiver<DisconnectReason>,
+) {
+    let (disconnect_tx, disconnect_rx) = mpsc::unbounded_channel();
+    let svc = EngineIoService::with_config(EchoHandler { disconnect_tx }, config);
+    (svc, disconnect_rx)
+}
+
+fn webtransport_config() -> EngineIoConfig {
+    EngineIoConfig::builder()
+        .transports([
+            TransportType::Polling,
+            TransportType::Websocket,
+            TransportType::WebTransport,
+        ])
+        .build()
+}
+
+#[tokio::test]
+pub async fn webtransport_session() {
+    let (svc, mut disconnect_rx) = create_svc(webtransport_config());
+    let mut client = MockClient::connect(&svc);
+
+    client.send_text("0").await;
+    let open = client.recv_text().await;
+    let open: Value = serde_json::from_str(open.strip_prefix('0').unwrap()).unwrap();
+    assert!(open["sid"].is_string());
+    assert_eq!(open["upgrades"], Value::Array(vec![]));
+
+    client.send_text("4hello").await;
+    assert_eq!(client.recv_text().await, "4hello");
+    client.send_text("4transport").await;
+    assert_eq!(client.recv_text().await, "4webtransport");
+
+    client.send(&[1, 2, 3], true).await;
+    assert_eq!(client.recv().await, (vec![1, 2, 3], true));
+
+    // A message larger than 125 bytes has an extended length header
+    let long = format!("4{}", "a".repeat(300));
+    client.stream.write_all(&[126, 1, 45]).await.unwrap();
+    client.stream.write_all(long.as_bytes()).await.unwrap();
+    assert_eq!(client.recv_text().await, long);
+
+    client.send_text("1").await;
+    let reason = tokio::time::timeout(Duration::from_secs(1), disconnect_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reason, DisconnectReason::TransportClose);
+}
+
+#[tokio::test]
+pub async fn webtransport_disabled() {
+    let (svc, _) = create_svc(EngineIoConfig::default());
+    let mut client = MockClient::connect(&svc);
+    client.send_text("0").await;
+    // The stream is dropped without any open packet
+    let mut buf = Vec::new();
+    let len = client.stream.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(len, 0);
+}
+
+#[tokio::test]
+pub async fn webtransport_upgrade() {
+    const PORT: u16 = 3170;
+    let (svc, _) = create_svc(webtransport_config());
+    let http_svc = svc.clone();
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), PORT);
+    let listener = TcpListener::bind(&addr).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = http_svc.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), svc));
+        }
+    });
+
+    let open = send_req(PORT, "transport=polling".into(), http::Method::GET, None).await;
+    let open: Value = serde_json::from_str(&open).unwrap();
+    assert_eq!(
+        open["upgrades"],
+        serde_json::json!(["websocket", "webtransport"])
+    );
+    let sid = open["sid"].as_str().unwrap();
+
+    let mut client = MockClient::connect(&svc);
+    client.send_text(&format!("0{{\"sid\":\"{sid}\"}}")).await;
+    client.send_text("2probe").await;
+    assert_eq!(client.recv_text().await, "3probe");
+    client.send_text("5").await;
+
+    client.send_text("4transport").await;
+    assert_eq!(client.recv_text().await, "4webtransport");
+
+    // The session is not reachable with polling anymore
+    let params = format!("transport=polling&sid={sid}");
+    let res = send_req(PORT, params, http::Method::GET, None).await;
+    assert!(res.contains("Bad request"), "unexpected response: {res}");
+}
@@ -0,0 +1,252 @@
+//! Tests for the `permessage-deflate` websocket extension
+//! The websocket frames are written and parsed by hand because tungstenite doesn't support extensions
+#![cfg(feature = "compression")]
+
+use std::sync::Arc;
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+mod fixture;
+
+use fixture::{create_server, create_server_with_config};
+
+#[derive(Debug, Clone)]
+struct EchoHandler;
+
+impl EngineIoHandler for EchoHandler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+
+    fn on_message(&self, msg: String, socket: Arc<Socket<()>>) {
+        socket.emit(msg).ok();
+    }
+
+    fn on_binary(&self, data: Vec<u8>, socket: Arc<Socket<()>>) {
+        socket.emit_binary(data).ok();
+    }
+}
+
+struct Frame {
+    rsv1: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Opens a websocket connection offering the `permessage-deflate` extension
+/// and returns the stream with the response headers
+async fn connect(port: u16) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let req = format!(
+        "GET /engine.io/?EIO=4&transport=websocket HTTP/1.1\r\n\
+        Host: 127.0.0.1:{port}\r\n\
+        Connection: Upgrade\r\n\
+        Upgrade: websocket\r\n\
+        Sec-WebSocket-Version: 13\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n"
+    );
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut res = Vec::new();
+    while !res.ends_with(b"\r\n\r\n") {
+        res.push(stream.read_u8().await.unwrap());
+    }
+    (stream, String::from_utf8(res).unwrap().to_lowercase())
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Frame {
+    let [b0, b1] = [
+        stream.read_u8().await.unwrap(),
+        stream.read_u8().await.unwrap(),
+    ];
+    assert!(b0 & 0x80 != 0, "fragmented frames are not expected");
+    let len = match b1 & 0x7f {
+        126 => stream.read_u16().await.unwrap() as usize,
+        127 => stream.read_u64().await.unwrap() as usize,
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    Frame {
+        rsv1: b0 & 0x40 != 0,
+        opcode: b0 & 0x0f,
+        payload,
+    }
+}
+
+/// Reads the next text frame, skipping the ping packets
+async fn read_text_frame(stream: &mut TcpStream) -> Frame {
+    loop {
+        let frame = read_frame(stream).await;
+        if frame.opcode == 0x1 && (frame.rsv1 || frame.payload != b"2") {
+            return frame;
+        }
+    }
+}
+
+/// Writes a masked frame as a client would do
+async fn write_frame(stream: &mut TcpStream, rsv1: bool, opcode: u8, payload: &[u8]) {
+    const MASK: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+    let mut buf = vec![0x80 | if rsv1 { 0x40 } else { 0 } | opcode];
+    match payload.len() {
+        len @ 0..=125 => buf.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            buf.push(0x80 | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(0x80 | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(&MASK);
+    buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+    stream.write_all(&buf).await.unwrap();
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 64);
+    Compress::new(Compression::default(), false)
+        .compress_vec(data, &mut out, FlushCompress::Sync)
+        .unwrap();
+    assert!(out.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+    out.truncate(out.len() - 4);
+    out
+}
+
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+    let mut out = Vec::with_capacity(1 << 16);
+    Decompress::new(false)
+        .decompress_vec(&data, &mut out, FlushDecompress::Sync)
+        .unwrap();
+    out
+}
+
+#[tokio::test]
+pub async fn ws_compression() {
+    create_server(EchoHandler, 3100).await;
+    let (mut stream, headers) = connect(3100).await;
+    assert!(headers.starts_with("http/1.1 101"), "{headers}");
+    assert!(
+        headers
+            .contains("sec-websocket-extensions: permessage-deflate; server_no_context_takeover"),
+        "{headers}"
+    );
+
+    // The open packet is too small to be compressed
+    let open = read_frame(&mut stream).await;
+    assert!(!open.rsv1);
+    assert!(open.payload.starts_with(b"0{\"sid\":"));
+
+    // A large message is echoed compressed
+    let msg = format!("4{}", "a".repeat(4096));
+    write_frame(&mut stream, false, 0x1, msg.as_bytes()).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(frame.rsv1);
+    assert!(frame.payload.len() < msg.len());
+    assert_eq!(inflate(&frame.payload), msg.as_bytes());
+
+    // Compressed messages from the client are inflated
+    let msg = format!("4{}", "b".repeat(4096));
+    write_frame(&mut stream, true, 0x1, &deflate(msg.as_bytes())).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(frame.rsv1);
+    assert_eq!(inflate(&frame.payload), msg.as_bytes());
+
+    // Small messages are sent as is
+    write_frame(&mut stream, true, 0x1, &deflate(b"4hello")).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(!frame.rsv1);
+    assert_eq!(frame.payload, b"4hello");
+}
+
+#[tokio::test]
+pub async fn ws_compression_max_payload() {
+    let config = EngineIoConfig::builder().max_payload(1024).build();
+    create_server_with_config(EchoHandler, 3101, config).await;
+    let (mut stream, _) = connect(3101).await;
+    let _open = read_frame(&mut stream).await;
+
+    // The compressed message is small but it exceeds the limit once inflated
+    let msg = format!("4{}", "a".repeat(100_000));
+    let compressed = deflate(msg.as_bytes());
+    assert!(compressed.len() < 1024);
+    write_frame(&mut stream, true, 0x1, &compressed).await;
+
+    let frame = loop {
+        let frame = read_frame(&mut stream).await;
+        if frame.opcode == 0x8 {
+            break frame;
+        }
+    };
+    // 1009: message too big
+    assert_eq!(frame.payload[..2], 1009u16.to_be_bytes());
+}
+
+#[tokio::test]
+pub async fn ws_compression_disabled() {
+    let config = EngineIoConfig::builder()
+        .ws_compression_threshold(None)
+        .build();
+    create_server_with_config(EchoHandler, 3102, config).await;
+    let (mut stream, headers) = connect(3102).await;
+    assert!(headers.starts_with("http/1.1 101"), "{headers}");
+    assert!(!headers.contains("sec-websocket-extensions"), "{headers}");
+    let _open = read_frame(&mut stream).await;
+
+    // Even a large message is sent as is
+    let msg = format!("4{}", "a".repeat(4096));
+    write_frame(&mut stream, false, 0x1, msg.as_bytes()).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(!frame.rsv1);
+    assert_eq!(frame.payload, msg.as_bytes());
+}
+
+#[tokio::test]
+pub async fn ws_compression_below_threshold() {
+    let config = EngineIoConfig::builder()
+        .ws_compression_threshold(Some(64))
+        .build();
+    create_server_with_config(EchoHandler, 3103, config).await;
+    let (mut stream, headers) = connect(3103).await;
+    assert!(headers.contains("sec-websocket-extensions"), "{headers}");
+    let _open = read_frame(&mut stream).await;
+
+    // A message just below the threshold is sent as is
+    let msg = format!("4{}", "a".repeat(62));
+    write_frame(&mut stream, false, 0x1, msg.as_bytes()).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(!frame.rsv1);
+    assert_eq!(frame.payload, msg.as_bytes());
+
+    // As well as a binary message
+    write_frame(&mut stream, false, 0x2, &[1; 63]).await;
+    let frame = loop {
+        let frame = read_frame(&mut stream).await;
+        if frame.opcode == 0x2 {
+            break frame;
+        }
+    };
+    assert!(!frame.rsv1);
+    assert_eq!(frame.payload, [1; 63]);
+
+    // A message reaching the threshold is compressed
+    let msg = format!("4{}", "a".repeat(63));
+    write_frame(&mut stream, false, 0x1, msg.as_bytes()).await;
+    let frame = read_text_frame(&mut stream).await;
+    assert!(frame.rsv1);
+    assert_eq!(inflate(&frame.payload), msg.as_bytes());
+}
@@ -0,0 +1,57 @@
+//! Tests for the websocket subprotocol negotiation
+use std::sync::Arc;
+
+use engineioxide::{
+    config::EngineIoConfig,
+    handler::EngineIoHandler,
+    socket::{DisconnectReason, Socket},
+};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Error};
+
+mod fixture;
+
+use fixture::create_server_with_config;
+
+#[derive(Debug, Clone)]
+struct Handler;
+
+impl EngineIoHandler for Handler {
+    type Data = ();
+
+    fn on_connect(&self, _: Arc<Socket<()>>) {}
+    fn on_disconnect(&self, _: Arc<Socket<()>>, _: DisconnectReason) {}
+    fn on_message(&self, _: String, _: Arc<Socket<()>>) {}
+    fn on_binary(&self, _: Vec<u8>, _: Arc<Socket<()>>) {}
+}
+
+async fn connect(port: u16, subprotocols: &'static str) -> Result<Option<String>, Error> {
+    let mut req = format!("ws://127.0.0.1:{port}/engine.io/?EIO=4&transport=websocket")
+        .into_client_request()
+        .unwrap();
+    req.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(subprotocols),
+    );
+    let (_, res) = tokio_tungstenite::connect_async(req).await?;
+    Ok(res
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .map(|v| v.to_str().unwrap().to_string()))
+}
+
+#[tokio::test]
+pub async fn ws_subprotocol_negotiation() {
+    let config = EngineIoConfig::builder()
+        .ws_subprotocols(["mqtt", "graphql-ws"])
+        .require_ws_subprotocol(true)
+        .build();
+    create_server_with_config(Handler, 3120, config).await;
+
+    let protocol = connect(3120, "chat, graphql-ws, mqtt").await.unwrap();
+    assert_eq!(protocol.as_deref(), Some("graphql-ws"));
+
+    match connect(3120, "chat").await {
+        Err(Error::Http(res)) => assert_eq!(res.status(), 400),
+        res => panic!("expected a 400 response, got {res:?}"),
+    }
+}
@@ -43,14 +43,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         s.on(
             "update-store",
-            |s: SocketRef, Data::<Vec<Todo>>(new_todos), State(Todos(todos))| {
+            |s: SocketRef, Data::<Vec<Todo>>(new_todos), State(Todos(todos))| async move {
                 info!("Received update-store event: {:?}", new_todos);
 
-                let mut todos = todos.lock().unwrap();
-                todos.clear();
-                todos.extend_from_slice(&new_todos);
+                {
+                    let mut todos = todos.lock().unwrap();
+                    todos.clear();
+                    todos.extend_from_slice(&new_todos);
+                }
 
-                s.broadcast().emit("update-store", [new_todos]).unwrap();
+                s.broadcast()
+                    .emit("update-store", [new_todos])
+                    .await
+                    .unwrap();
             },
         );
     });
@@ -13,9 +13,9 @@ fn on_connect(socket: SocketRef, Data(data): Data<Value>) {
 
     socket.on(
         "message",
-        |socket: SocketRef, Data::<Value>(data), Bin(bin)| {
+        |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
             info!("Received event: {:?} {:?}", data, bin);
-            socket.bin(bin).emit("message-back", data).ok();
+            socket.bin(bin).emit("message-back", data).await.ok();
         },
     );
 
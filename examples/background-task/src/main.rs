@@ -13,9 +13,9 @@ async fn background_task(io: SocketIo) {
     loop {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         info!("Background task");
-        let cnt = io.of("/").unwrap().sockets().unwrap().len();
+        let cnt = io.of("/").unwrap().sockets().await.unwrap().len();
         let msg = format!("{}s, {} socket connected", i, cnt);
-        io.emit("tic tac !", msg).unwrap();
+        io.emit("tic tac !", msg).await.unwrap();
 
         i += 1;
     }
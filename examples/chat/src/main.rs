@@ -59,18 +59,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (layer, io) = SocketIo::builder().with_state(UserCnt::new()).build_layer();
 
     io.ns("/", |s: SocketRef| {
-        s.on("new message", |s: SocketRef, Data::<String>(msg)| {
-            let username = s.extensions.get::<Username>().unwrap().clone();
-            let msg = Res::Message {
-                username,
-                message: msg,
-            };
-            s.broadcast().emit("new message", msg).ok();
-        });
+        s.on(
+            "new message",
+            |s: SocketRef, Data::<String>(msg)| async move {
+                let username = s.extensions.get::<Username>().unwrap().clone();
+                let msg = Res::Message {
+                    username,
+                    message: msg,
+                };
+                s.broadcast().emit("new message", msg).await.ok();
+            },
+        );
 
         s.on(
             "add user",
-            |s: SocketRef, Data::<String>(username), user_cnt: State<UserCnt>| {
+            |s: SocketRef, Data::<String>(username), user_cnt: State<UserCnt>| async move {
                 if s.extensions.get::<Username>().is_some() {
                     return;
                 }
@@ -82,32 +85,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     num_users,
                     username: Username(username),
                 };
-                s.broadcast().emit("user joined", res).ok();
+                s.broadcast().emit("user joined", res).await.ok();
             },
         );
 
-        s.on("typing", |s: SocketRef| {
+        s.on("typing", |s: SocketRef| async move {
             let username = s.extensions.get::<Username>().unwrap().clone();
             s.broadcast()
                 .emit("typing", Res::Username { username })
+                .await
                 .ok();
         });
 
-        s.on("stop typing", |s: SocketRef| {
+        s.on("stop typing", |s: SocketRef| async move {
             let username = s.extensions.get::<Username>().unwrap().clone();
             s.broadcast()
                 .emit("stop typing", Res::Username { username })
+                .await
                 .ok();
         });
 
-        s.on_disconnect(|s: SocketRef, user_cnt: State<UserCnt>| {
-            if let Some(username) = s.extensions.get::<Username>() {
+        s.on_disconnect(|s: SocketRef, user_cnt: State<UserCnt>| async move {
+            let username = s
+                .extensions
+                .get::<Username>()
+                .map(|username| username.clone());
+            if let Some(username) = username {
                 let num_users = user_cnt.remove_user();
                 let res = Res::UserEvent {
                     num_users,
-                    username: username.clone(),
+                    username,
                 };
-                s.broadcast().emit("user left", res).ok();
+                s.broadcast().emit("user left", res).await.ok();
             }
         });
     });
@@ -38,13 +38,13 @@ struct PrivateMessageReq {
     content: String,
 }
 
-pub fn on_connection(
+pub async fn on_connection(
     s: SocketRef,
     TryData(auth): TryData<Auth>,
     sessions: State<Sessions>,
     msgs: State<Messages>,
 ) {
-    if let Err(e) = session_connect(&s, auth, sessions.0, msgs.0) {
+    if let Err(e) = session_connect(&s, auth, sessions.0, msgs.0).await {
         error!("Failed to connect: {:?}", e);
         s.disconnect().ok();
         return;
@@ -52,7 +52,7 @@ pub fn on_connection(
 
     s.on(
         "private message",
-        |s: SocketRef, Data(PrivateMessageReq { to, content }), State(Messages(msg))| {
+        |s: SocketRef, Data(PrivateMessageReq { to, content }), State(Messages(msg))| async move {
             let user_id = s.extensions.get::<Session>().unwrap().user_id;
             let message = Message {
                 from: user_id,
@@ -62,11 +62,12 @@ pub fn on_connection(
             msg.write().unwrap().push(message.clone());
             s.within(to.to_string())
                 .emit("private message", message)
+                .await
                 .ok();
         },
     );
 
-    s.on_disconnect(|s: SocketRef, State(Sessions(sessions))| {
+    s.on_disconnect(|s: SocketRef, State(Sessions(sessions))| async move {
         let mut session = s.extensions.get::<Session>().unwrap().clone();
         session.connected = false;
 
@@ -77,11 +78,12 @@ pub fn on_connection(
             .unwrap()
             .connected = false;
 
-        s.broadcast().emit("user disconnected", session).ok();
+        s.broadcast().emit("user disconnected", session).await.ok();
     });
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum ConnectError {
     InvalidUsername,
     EncodeError(serde_json::Error),
@@ -90,29 +92,30 @@ enum ConnectError {
 }
 
 /// Handles the connection of a new user
-fn session_connect(
+async fn session_connect(
     s: &SocketRef,
     auth: Result<Auth, serde_json::Error>,
     Sessions(session_state): &Sessions,
     Messages(msg_state): &Messages,
 ) -> Result<(), ConnectError> {
     let auth = auth.map_err(ConnectError::EncodeError)?;
-    let mut sessions = session_state.write().unwrap();
-    if let Some(session) = auth.session_id.and_then(|id| sessions.get_mut(&id)) {
-        session.connected = true;
-        s.extensions.insert(session.clone());
-    } else {
-        let username = auth.username.ok_or(ConnectError::InvalidUsername)?;
-        let session = Session::new(username);
-        s.extensions.insert(session.clone());
-
-        sessions.insert(session.session_id, session);
-    };
-    drop(sessions);
-
-    let session = s.extensions.get::<Session>().unwrap();
-
-    s.join(session.user_id.to_string()).ok();
+    {
+        let mut sessions = session_state.write().unwrap();
+        if let Some(session) = auth.session_id.and_then(|id| sessions.get_mut(&id)) {
+            session.connected = true;
+            s.extensions.insert(session.clone());
+        } else {
+            let username = auth.username.ok_or(ConnectError::InvalidUsername)?;
+            let session = Session::new(username);
+            s.extensions.insert(session.clone());
+
+            sessions.insert(session.session_id, session);
+        };
+    }
+
+    let session = s.extensions.get::<Session>().unwrap().clone();
+
+    s.join(session.user_id.to_string()).await.ok();
     s.emit("session", session.clone())
         .map_err(ConnectError::SocketError)?;
 
@@ -141,6 +144,7 @@ fn session_connect(
 
     s.broadcast()
         .emit("user connected", res)
+        .await
         .map_err(ConnectError::BroadcastError)?;
     Ok(())
 }
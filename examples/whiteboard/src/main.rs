@@ -19,8 +19,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (layer, io) = SocketIo::new_layer();
 
     io.ns("/", |s: SocketRef| {
-        s.on("drawing", |s: SocketRef, Data::<Value>(data)| {
-            s.broadcast().emit("drawing", data).unwrap();
+        s.on("drawing", |s: SocketRef, Data::<Value>(data)| async move {
+            s.broadcast().emit("drawing", data).await.unwrap();
         });
     });
 
@@ -1,9 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use engineioxide::sid::Sid;
-use socketioxide::{
-    packet::{Packet, PacketData},
-    ProtocolVersion,
-};
+use socketioxide::{Packet, PacketData, ProtocolVersion};
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("Decode packet connect on /", |b| {
         let packet: String =
@@ -27,10 +24,13 @@ fn criterion_benchmark(c: &mut Criterion) {
     const BINARY: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
     c.bench_function("Decode packet event on /", |b| {
         let data = serde_json::to_value(DATA).unwrap();
-        let packet: String =
-            Packet::event(black_box("/"), black_box("event"), black_box(data.clone()))
-                .try_into()
-                .unwrap();
+        let packet: String = Packet::event(
+            black_box("/"),
+            black_box("event"),
+            black_box(Some(data.clone())),
+        )
+        .try_into()
+        .unwrap();
         b.iter(|| Packet::try_from(packet.clone()).unwrap())
     });
 
@@ -39,7 +39,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet: String = Packet::event(
             black_box("custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
         )
         .try_into()
         .unwrap();
@@ -48,8 +48,11 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("Decode packet event with ack on /", |b| {
         let data = serde_json::to_value(DATA).unwrap();
-        let packet: Packet =
-            Packet::event(black_box("/"), black_box("event"), black_box(data.clone()));
+        let packet: Packet = Packet::event(
+            black_box("/"),
+            black_box("event"),
+            black_box(Some(data.clone())),
+        );
         match packet.inner {
             PacketData::Event(_, _, mut ack) => ack.insert(black_box(0)),
             _ => panic!("Wrong packet type"),
@@ -63,7 +66,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet = Packet::event(
             black_box("/custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
         );
         match packet.inner {
             PacketData::Event(_, _, mut ack) => ack.insert(black_box(0)),
@@ -99,7 +102,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet: String = Packet::bin_event(
             black_box("/"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
             black_box(vec![BINARY.to_vec().clone()]),
         )
         .try_into()
@@ -112,7 +115,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet: String = Packet::bin_event(
             black_box("/custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
             black_box(vec![BINARY.to_vec().clone()]),
         )
         .try_into()
@@ -1,9 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use engineioxide::sid::Sid;
-use socketioxide::{
-    packet::{Packet, PacketData},
-    ProtocolVersion,
-};
+use socketioxide::{Packet, PacketData, ProtocolVersion};
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("Encode packet connect on /", |b| {
         let packet = Packet::connect(black_box("/"), black_box(Sid::ZERO), ProtocolVersion::V5);
@@ -26,7 +23,11 @@ fn criterion_benchmark(c: &mut Criterion) {
     const BINARY: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
     c.bench_function("Encode packet event on /", |b| {
         let data = serde_json::to_value(DATA).unwrap();
-        let packet = Packet::event(black_box("/"), black_box("event"), black_box(data.clone()));
+        let packet = Packet::event(
+            black_box("/"),
+            black_box("event"),
+            black_box(Some(data.clone())),
+        );
         b.iter(|| {
             let _: String = packet.clone().try_into().unwrap();
         })
@@ -37,7 +38,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet = Packet::event(
             black_box("custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
         );
         b.iter(|| {
             let _: String = packet.clone().try_into().unwrap();
@@ -46,7 +47,11 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("Encode packet event with ack on /", |b| {
         let data = serde_json::to_value(DATA).unwrap();
-        let packet = Packet::event(black_box("/"), black_box("event"), black_box(data.clone()));
+        let packet = Packet::event(
+            black_box("/"),
+            black_box("event"),
+            black_box(Some(data.clone())),
+        );
         match packet.inner {
             PacketData::Event(_, _, mut ack) => ack.insert(black_box(0)),
             _ => panic!("Wrong packet type"),
@@ -61,7 +66,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet = Packet::event(
             black_box("/custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
         );
         match packet.inner {
             PacketData::Event(_, _, mut ack) => ack.insert(black_box(0)),
@@ -97,7 +102,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet = Packet::bin_event(
             black_box("/"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
             black_box(vec![BINARY.to_vec().clone()]),
         );
         b.iter(|| {
@@ -110,7 +115,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let packet = Packet::bin_event(
             black_box("/custom_nsp"),
             black_box("event"),
-            black_box(data.clone()),
+            black_box(Some(data.clone())),
             black_box(vec![BINARY.to_vec().clone()]),
         );
         b.iter(|| {
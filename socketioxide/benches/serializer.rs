@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::{json, Value};
+use socketioxide::serializer::{SerdeJson, Serializer, SimdJson};
+
+/// A chat message event with some metadata, as a client would send it
+fn event_payload() -> Value {
+    let messages: Vec<Value> = (0..20)
+        .map(|i| {
+            json!({
+                "id": format!("msg-{i}"),
+                "author": { "id": i * 31, "name": "John Doe", "avatar": "https://example.com/avatar.png" },
+                "content": "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt",
+                "timestamp": 1_700_000_000 + i,
+                "reactions": [{ "emoji": "👍", "count": i }, { "emoji": "🎉", "count": 2 }],
+                "edited": i % 3 == 0,
+            })
+        })
+        .collect();
+    json!(["messages", { "room": "general", "messages": messages }])
+}
+
+fn bench_serializer<S: Serializer>(c: &mut Criterion, name: &str) {
+    let payload = event_payload();
+    let encoded = S::to_vec(&payload).unwrap();
+
+    c.bench_with_input(
+        BenchmarkId::new("Serialize event payload", name),
+        &payload,
+        |b, payload| b.iter(|| S::to_vec(black_box(payload)).unwrap()),
+    );
+    c.bench_with_input(
+        BenchmarkId::new("Deserialize event payload", name),
+        &encoded,
+        |b, encoded| {
+            b.iter_batched_ref(
+                || encoded.clone(),
+                |data| S::from_slice::<Value>(black_box(data)).unwrap(),
+                criterion::BatchSize::SmallInput,
+            )
+        },
+    );
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    bench_serializer::<SerdeJson>(c, "serde_json");
+    bench_serializer::<SimdJson>(c, "simd-json");
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
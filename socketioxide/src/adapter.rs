@@ -1,25 +1,30 @@
 //! Adapters are responsible for managing the state of the server.
 //! When a socket joins or leaves a room, the adapter is responsible for updating the state.
 //! The default adapter is the [`LocalAdapter`], which stores the state in memory.
-//! Other adapters can be made to share the state between multiple servers.
+//! Other adapters can be made to share the state between multiple servers,
+//! such as the [`RedisAdapter`](redis::RedisAdapter).
+
+pub mod redis;
 
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     convert::Infallible,
-    sync::{RwLock, Weak},
+    sync::{Arc, RwLock, Weak},
     time::Duration,
 };
 
 use engineioxide::sid::Sid;
 use futures::{
-    stream::{self, BoxStream},
-    StreamExt,
+    future::{self, BoxFuture},
+    stream::{self, BoxStream, FuturesUnordered},
+    Future, FutureExt, StreamExt,
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::broadcast;
 
 use crate::{
-    errors::{AckError, AdapterError, BroadcastError},
+    errors::{AckError, AdapterError, BroadcastError, SendError},
     extract::SocketRef,
     ns::Namespace,
     operators::RoomParam,
@@ -30,8 +35,19 @@ use crate::{
 /// A room identifier
 pub type Room = Cow<'static, str>;
 
+/// The number of room events buffered for a slow [`Adapter::room_events`] stream.
+/// Older events are skipped once it is reached.
+const ROOM_EVENTS_CAPACITY: usize = 1024;
+
+/// A stream of acknowledgements returned by a broadcast with ack.
+/// Each item is the id of a socket and its ack response.
+pub type AckStream<V> = BoxStream<'static, (Sid, Result<AckResponse<V>, AckError>)>;
+
+/// A snapshot of the rooms with the ids of their sockets, returned by [`Adapter::rooms`].
+pub type RoomsSnapshot = Vec<(Room, Vec<Sid>)>;
+
 /// Flags that can be used to modify the behavior of the broadcast methods.
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BroadcastFlags {
     /// Broadcast only to the current server
     Local,
@@ -39,10 +55,31 @@ pub enum BroadcastFlags {
     Broadcast,
     /// Add a custom timeout to the ack callback
     Timeout(Duration),
+    /// Drop the packet for the sockets whose buffer is full instead of returning an error
+    Volatile,
+}
+
+/// A room membership change, yielded by the [`Adapter::room_events`] stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomEvent {
+    /// The socket joined the room
+    Join {
+        /// The id of the socket
+        sid: Sid,
+        /// The room joined
+        room: Room,
+    },
+    /// The socket left the room, explicitly or because it was disconnected
+    Leave {
+        /// The id of the socket
+        sid: Sid,
+        /// The room left
+        room: Room,
+    },
 }
 
 /// Options that can be used to modify the behavior of the broadcast methods.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BroadcastOptions {
     /// The flags to apply to the broadcast.
     pub flags: HashSet<BroadcastFlags>,
@@ -50,6 +87,9 @@ pub struct BroadcastOptions {
     pub rooms: HashSet<Room>,
     /// The rooms to exclude from the broadcast.
     pub except: HashSet<Room>,
+    /// The sockets to exclude from the broadcast.
+    #[serde(default)]
+    pub except_sids: HashSet<Sid>,
     /// The socket id of the sender.
     pub sid: Option<Sid>,
 }
@@ -59,15 +99,22 @@ impl BroadcastOptions {
             flags: HashSet::new(),
             rooms: HashSet::new(),
             except: HashSet::new(),
+            except_sids: HashSet::new(),
             sid,
         }
     }
 }
 
-//TODO: Make an AsyncAdapter trait
 /// An adapter is responsible for managing the state of the server.
 /// This adapter can be implemented to share the state between multiple servers.
 /// The default adapter is the [`LocalAdapter`], which stores the state in memory.
+///
+/// Most of the operations are asynchronous so that the adapters sharing the state through a remote backend
+/// can await their network I/O. The futures are boxed with [`BoxFuture`], adapters completing their operations
+/// right away, such as the [`LocalAdapter`], can return a [`ready`](future::ready) future.
+///
+/// When an operation is triggered from a synchronous context, such as the disconnection of a socket,
+/// its future is polled once and then spawned on the tokio runtime if it is still pending.
 pub trait Adapter: std::fmt::Debug + Send + Sync + 'static {
     /// An error that can occur when using the adapter. The default [`LocalAdapter`] has an [`Infallible`] error.
     type Error: std::error::Error + Into<AdapterError> + Send + 'static;
@@ -78,50 +125,113 @@ pub trait Adapter: std::fmt::Debug + Send + Sync + 'static {
         Self: Sized;
 
     /// Initializes the adapter.
-    fn init(&self) -> Result<(), Self::Error>;
+    fn init(&self) -> BoxFuture<'_, Result<(), Self::Error>>;
     /// Closes the adapter.
-    fn close(&self) -> Result<(), Self::Error>;
+    fn close(&self) -> BoxFuture<'_, Result<(), Self::Error>>;
 
     /// Returns the number of servers.
-    fn server_count(&self) -> Result<u16, Self::Error>;
+    fn server_count(&self) -> BoxFuture<'_, Result<u16, Self::Error>>;
 
     /// Adds the socket to all the rooms.
-    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Self::Error>;
+    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Self::Error>>;
     /// Removes the socket from the rooms.
-    fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Self::Error>;
+    fn del(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Self::Error>>;
     /// Removes the socket from all the rooms.
-    fn del_all(&self, sid: Sid) -> Result<(), Self::Error>;
+    fn del_all(&self, sid: Sid) -> BoxFuture<'_, Result<(), Self::Error>>;
 
     /// Broadcasts the packet to the sockets that match the [`BroadcastOptions`].
-    fn broadcast(&self, packet: Packet<'_>, opts: BroadcastOptions) -> Result<(), BroadcastError>;
+    ///
+    /// Returns the number of sockets the packet was enqueued to.
+    fn broadcast(
+        &self,
+        packet: Packet<'static>,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<usize, BroadcastError>>;
 
     /// Broadcasts the packet to the sockets that match the [`BroadcastOptions`] and return a stream of ack responses.
-    fn broadcast_with_ack<V: DeserializeOwned>(
+    ///
+    /// The stream yields one item per socket matched at the time of the broadcast,
+    /// with the socket id and its ack response. It ends once every socket has responded or timed out.
+    fn broadcast_with_ack<V: DeserializeOwned + 'static>(
         &self,
         packet: Packet<'static>,
         opts: BroadcastOptions,
-    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError>;
+    ) -> BoxFuture<'_, Result<AckStream<V>, BroadcastError>>;
 
     /// Returns the sockets ids that match the [`BroadcastOptions`].
-    fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Self::Error>;
+    fn sockets(&self, rooms: impl RoomParam) -> BoxFuture<'_, Result<Vec<Sid>, Self::Error>>;
+
+    /// Returns the number of sockets in the room.
+    ///
+    /// The default implementation counts the sockets returned by [`Adapter::sockets`],
+    /// adapters that can read the size of the room directly should override it.
+    fn room_size(&self, room: Room) -> BoxFuture<'_, Result<usize, Self::Error>> {
+        self.sockets(room)
+            .map(|sockets| sockets.map(|sockets| sockets.len()))
+            .boxed()
+    }
+
+    /// Returns a snapshot of the rooms with their sockets, the empty rooms are not returned.
+    ///
+    /// The snapshot should be consistent: a room membership changed concurrently is either fully
+    /// applied or not at all.
+    fn rooms(&self) -> BoxFuture<'_, Result<RoomsSnapshot, Self::Error>>;
 
     /// Returns the rooms of the socket.
-    fn socket_rooms(&self, sid: Sid) -> Result<Vec<Room>, Self::Error>;
+    fn socket_rooms(&self, sid: Sid) -> BoxFuture<'_, Result<Vec<Room>, Self::Error>>;
 
     /// Returns the sockets that match the [`BroadcastOptions`].
-    fn fetch_sockets(&self, opts: BroadcastOptions) -> Result<Vec<SocketRef<Self>>, Self::Error>
+    fn fetch_sockets(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<Vec<SocketRef<Self>>, Self::Error>>
     where
         Self: Sized;
 
     /// Adds the sockets that match the [`BroadcastOptions`] to the rooms.
-    fn add_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam)
-        -> Result<(), Self::Error>;
+    fn add_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Self::Error>>;
     /// Removes the sockets that match the [`BroadcastOptions`] from the rooms.
-    fn del_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam)
-        -> Result<(), Self::Error>;
+    fn del_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Self::Error>>;
 
     /// Disconnects the sockets that match the [`BroadcastOptions`].
-    fn disconnect_socket(&self, opts: BroadcastOptions) -> Result<(), BroadcastError>;
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<(), BroadcastError>>;
+
+    /// Returns a stream of the room membership changes of this namespace, starting from now.
+    ///
+    /// The default implementation returns an empty stream for adapters that don't track them.
+    fn room_events(&self) -> BoxStream<'static, RoomEvent> {
+        stream::empty().boxed()
+    }
+
+    /// Keeps the last `capacity` packets broadcast to the room, so that they can be replayed
+    /// to the sockets joining it with [`Socket::join_with_history`](crate::socket::Socket::join_with_history).
+    /// A `capacity` of 0 disables the history of the room and drops its packets.
+    ///
+    /// The default implementation does nothing for adapters that don't keep a history.
+    #[allow(unused_variables)]
+    fn set_room_history(&self, room: Room, capacity: usize) {}
+
+    /// Returns the packets kept for the room, from the oldest to the newest.
+    ///
+    /// The default implementation returns no packets.
+    #[allow(unused_variables)]
+    fn room_history(
+        &self,
+        room: &Room,
+    ) -> BoxFuture<'_, Result<Vec<Packet<'static>>, Self::Error>> {
+        future::ready(Ok(Vec::new())).boxed()
+    }
 
     //TODO: implement
     // fn server_side_emit(&self, packet: Packet, opts: BroadcastOptions) -> Result<u64, Error>;
@@ -129,10 +239,30 @@ pub trait Adapter: std::fmt::Debug + Send + Sync + 'static {
     // fn restore_session(&self, sid: i64) -> Session;
 }
 
+/// Runs an adapter operation from a synchronous context.
+///
+/// The operation is polled once, which is enough for the adapters completing their operations right away,
+/// and spawned on the tokio runtime if it is still pending.
+pub(crate) fn run_adapter_op(op: impl Future<Output = ()> + Send + 'static) {
+    let mut op = Box::pin(op);
+    if op.as_mut().now_or_never().is_some() {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn(op);
+        }
+        Err(_e) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("pending adapter operation dropped outside of a tokio runtime: {_e}");
+        }
+    }
+}
+
 /// The default adapter. Store the state in memory.
 #[derive(Debug)]
 pub struct LocalAdapter {
-    rooms: RwLock<HashMap<Room, HashSet<Sid>>>,
+    state: LocalState,
     ns: Weak<Namespace<Self>>,
 }
 
@@ -142,142 +272,325 @@ impl From<Infallible> for AdapterError {
     }
 }
 
+/// The operations of the local adapter complete right away, their futures are always ready.
 impl Adapter for LocalAdapter {
     type Error = Infallible;
 
     fn new(ns: Weak<Namespace<Self>>) -> Self {
         Self {
-            rooms: HashMap::new().into(),
+            state: LocalState::new(),
             ns,
         }
     }
 
-    fn init(&self) -> Result<(), Infallible> {
-        Ok(())
+    fn init(&self) -> BoxFuture<'_, Result<(), Infallible>> {
+        future::ready(Ok(())).boxed()
     }
 
-    fn close(&self) -> Result<(), Infallible> {
+    fn close(&self) -> BoxFuture<'_, Result<(), Infallible>> {
         #[cfg(feature = "tracing")]
         tracing::debug!("closing local adapter: {}", self.ns.upgrade().unwrap().path);
+        self.state.clear();
+        future::ready(Ok(())).boxed()
+    }
+
+    fn server_count(&self) -> BoxFuture<'_, Result<u16, Infallible>> {
+        future::ready(Ok(1)).boxed()
+    }
+
+    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.state.add_all(sid, rooms);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.state.del(sid, rooms);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del_all(&self, sid: Sid) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.state.del_all(sid);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn broadcast(
+        &self,
+        packet: Packet<'static>,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<usize, BroadcastError>> {
+        future::ready(self.state.broadcast(&self.ns(), packet, opts)).boxed()
+    }
+
+    fn broadcast_with_ack<V: DeserializeOwned + 'static>(
+        &self,
+        packet: Packet<'static>,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<AckStream<V>, BroadcastError>> {
+        let stream = self.state.broadcast_with_ack(&self.ns(), packet, opts);
+        future::ready(Ok(stream)).boxed()
+    }
+
+    fn sockets(&self, rooms: impl RoomParam) -> BoxFuture<'_, Result<Vec<Sid>, Infallible>> {
+        future::ready(Ok(self.state.sockets(&self.ns(), rooms))).boxed()
+    }
+
+    fn room_size(&self, room: Room) -> BoxFuture<'_, Result<usize, Infallible>> {
+        future::ready(Ok(self.state.room_size(&room))).boxed()
+    }
+
+    fn rooms(&self) -> BoxFuture<'_, Result<RoomsSnapshot, Infallible>> {
+        future::ready(Ok(self.state.rooms())).boxed()
+    }
+
+    fn socket_rooms(&self, sid: Sid) -> BoxFuture<'_, Result<Vec<Room>, Infallible>> {
+        future::ready(Ok(self.state.socket_rooms(sid))).boxed()
+    }
+
+    fn fetch_sockets(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<Vec<SocketRef<Self>>, Infallible>> {
+        future::ready(Ok(self.state.apply_opts(&self.ns(), opts))).boxed()
+    }
+
+    fn add_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.state.add_sockets(&self.ns(), opts, rooms);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.state.del_sockets(&self.ns(), opts, rooms);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<(), BroadcastError>> {
+        future::ready(self.state.disconnect_socket(&self.ns(), opts)).boxed()
+    }
+
+    fn room_events(&self) -> BoxStream<'static, RoomEvent> {
+        self.state.room_events()
+    }
+
+    fn set_room_history(&self, room: Room, capacity: usize) {
+        self.state.set_room_history(room, capacity);
+    }
+
+    fn room_history(&self, room: &Room) -> BoxFuture<'_, Result<Vec<Packet<'static>>, Infallible>> {
+        future::ready(Ok(self.state.room_history(room))).boxed()
+    }
+}
+
+impl LocalAdapter {
+    fn ns(&self) -> Arc<Namespace<Self>> {
+        self.ns.upgrade().unwrap()
+    }
+}
+
+/// The last packets broadcast to a room, see [`Adapter::set_room_history`]
+#[derive(Debug)]
+struct RoomHistory {
+    capacity: usize,
+    packets: VecDeque<Packet<'static>>,
+}
+
+/// The in-memory rooms of the sockets connected to this server.
+///
+/// It is shared by the [`LocalAdapter`] and the adapters that also need to deliver the packets
+/// to the local sockets, such as the [`RedisAdapter`](redis::RedisAdapter).
+#[derive(Debug)]
+pub(crate) struct LocalState {
+    rooms: RwLock<HashMap<Room, HashSet<Sid>>>,
+    room_events: broadcast::Sender<RoomEvent>,
+    history: RwLock<HashMap<Room, RoomHistory>>,
+}
+
+impl LocalState {
+    pub fn new() -> Self {
+        Self {
+            rooms: HashMap::new().into(),
+            room_events: broadcast::channel(ROOM_EVENTS_CAPACITY).0,
+            history: HashMap::new().into(),
+        }
+    }
+
+    pub fn clear(&self) {
         let mut rooms = self.rooms.write().unwrap();
         rooms.clear();
         rooms.shrink_to_fit();
-        Ok(())
-    }
-
-    fn server_count(&self) -> Result<u16, Infallible> {
-        Ok(1)
     }
 
-    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
+    pub fn add_all(&self, sid: Sid, rooms: impl RoomParam) {
         let mut rooms_map = self.rooms.write().unwrap();
         for room in rooms.into_room_iter() {
-            rooms_map.entry(room).or_default().insert(sid);
+            if rooms_map.entry(room.clone()).or_default().insert(sid) {
+                self.send_room_event(RoomEvent::Join { sid, room });
+            }
         }
-        Ok(())
     }
 
-    fn del(&self, sid: Sid, rooms: impl RoomParam) -> Result<(), Infallible> {
+    pub fn del(&self, sid: Sid, rooms: impl RoomParam) {
         let mut rooms_map = self.rooms.write().unwrap();
         for room in rooms.into_room_iter() {
-            if let Some(room) = rooms_map.get_mut(&room) {
-                room.remove(&sid);
+            if let Some(sids) = rooms_map.get_mut(&room) {
+                if sids.remove(&sid) {
+                    self.send_room_event(RoomEvent::Leave { sid, room });
+                }
             }
         }
-        Ok(())
     }
 
-    fn del_all(&self, sid: Sid) -> Result<(), Infallible> {
+    pub fn del_all(&self, sid: Sid) {
         let mut rooms_map = self.rooms.write().unwrap();
-        for room in rooms_map.values_mut() {
-            room.remove(&sid);
+        for (room, sids) in rooms_map.iter_mut() {
+            if sids.remove(&sid) {
+                let room = room.clone();
+                self.send_room_event(RoomEvent::Leave { sid, room });
+            }
         }
-        Ok(())
     }
 
-    fn broadcast(&self, packet: Packet<'_>, opts: BroadcastOptions) -> Result<(), BroadcastError> {
-        let sockets = self.apply_opts(opts);
+    /// Sends the packet to the local sockets that match the [`BroadcastOptions`]
+    /// and returns the number of sockets the packet was enqueued to.
+    pub fn broadcast<A: Adapter>(
+        &self,
+        ns: &Namespace<A>,
+        packet: Packet<'_>,
+        opts: BroadcastOptions,
+    ) -> Result<usize, BroadcastError> {
+        let volatile = opts.flags.contains(&BroadcastFlags::Volatile);
+        self.push_history(&opts.rooms, &packet);
+        let sockets = self.apply_opts(ns, opts);
 
         #[cfg(feature = "tracing")]
         tracing::debug!("broadcasting packet to {} sockets", sockets.len());
+        let count = sockets.len();
         let errors: Vec<_> = sockets
             .into_iter()
             .filter_map(|socket| socket.send(packet.clone()).err())
             .collect();
+        let sent = count - errors.len();
+        let errors: Vec<_> = errors
+            .into_iter()
+            .filter(|e| !(volatile && matches!(e, SendError::InternalChannelFull)))
+            .collect();
         if errors.is_empty() {
-            Ok(())
+            Ok(sent)
         } else {
             Err(errors.into())
         }
     }
 
-    fn broadcast_with_ack<V: DeserializeOwned>(
+    pub fn broadcast_with_ack<A: Adapter, V: DeserializeOwned>(
         &self,
+        ns: &Namespace<A>,
         packet: Packet<'static>,
         opts: BroadcastOptions,
-    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
+    ) -> AckStream<V> {
         let duration = opts.flags.iter().find_map(|flag| match flag {
             BroadcastFlags::Timeout(duration) => Some(*duration),
             _ => None,
         });
-        let sockets = self.apply_opts(opts);
+        let sockets = self.apply_opts(ns, opts);
         #[cfg(feature = "tracing")]
         tracing::debug!(
             "broadcasting packet to {} sockets: {:?}",
             sockets.len(),
             sockets.iter().map(|s| s.id).collect::<Vec<_>>()
         );
-        let count = sockets.len();
-        let ack_futs = sockets.into_iter().map(move |socket| {
-            let packet = packet.clone();
-            async move { socket.send_with_ack(packet, duration).await }
-        });
-        Ok(stream::iter(ack_futs).buffer_unordered(count).boxed())
+        // The packets are sent right away so that only the sockets matched at emit time are awaited
+        let ack_futs: FuturesUnordered<_> = sockets
+            .into_iter()
+            .map(|socket| {
+                let res = socket.send_ack_packet(packet.clone());
+                async move {
+                    let res = match res {
+                        Ok((ack, rx)) => socket.wait_ack(ack, rx, duration).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    (socket.id, res)
+                }
+            })
+            .collect();
+        ack_futs.boxed()
     }
 
-    fn sockets(&self, rooms: impl RoomParam) -> Result<Vec<Sid>, Infallible> {
+    pub fn sockets<A: Adapter>(&self, ns: &Namespace<A>, rooms: impl RoomParam) -> Vec<Sid> {
         let mut opts = BroadcastOptions::new(None);
         opts.rooms.extend(rooms.into_room_iter());
-        Ok(self
-            .apply_opts(opts)
+        self.apply_opts(ns, opts)
             .into_iter()
             .map(|socket| socket.id)
-            .collect())
+            .collect()
+    }
+
+    /// Returns the number of sockets in the room, without collecting them
+    pub fn room_size(&self, room: &Room) -> usize {
+        let rooms_map = self.rooms.read().unwrap();
+        rooms_map.get(room).map(HashSet::len).unwrap_or(0)
     }
 
     //TODO: make this operation O(1)
-    fn socket_rooms(&self, sid: Sid) -> Result<Vec<Cow<'static, str>>, Infallible> {
+    pub fn socket_rooms(&self, sid: Sid) -> Vec<Room> {
         let rooms_map = self.rooms.read().unwrap();
-        Ok(rooms_map
+        rooms_map
             .iter()
             .filter(|(_, sockets)| sockets.contains(&sid))
             .map(|(room, _)| room.clone())
-            .collect())
+            .collect()
     }
 
-    fn fetch_sockets(&self, opts: BroadcastOptions) -> Result<Vec<SocketRef<Self>>, Infallible> {
-        Ok(self.apply_opts(opts))
+    /// Returns the rooms with their sockets, read under a single lock
+    pub fn rooms(&self) -> RoomsSnapshot {
+        let rooms_map = self.rooms.read().unwrap();
+        rooms_map
+            .iter()
+            .filter(|(_, sockets)| !sockets.is_empty())
+            .map(|(room, sockets)| (room.clone(), sockets.iter().copied().collect()))
+            .collect()
     }
 
-    fn add_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
+    pub fn add_sockets<A: Adapter>(
+        &self,
+        ns: &Namespace<A>,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) {
         let rooms: Vec<Room> = rooms.into_room_iter().collect();
-        for socket in self.apply_opts(opts) {
-            self.add_all(socket.id, rooms.clone()).unwrap();
+        for socket in self.apply_opts(ns, opts) {
+            self.add_all(socket.id, rooms.clone());
         }
-        Ok(())
     }
 
-    fn del_sockets(&self, opts: BroadcastOptions, rooms: impl RoomParam) -> Result<(), Infallible> {
+    pub fn del_sockets<A: Adapter>(
+        &self,
+        ns: &Namespace<A>,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) {
         let rooms: Vec<Room> = rooms.into_room_iter().collect();
-        for socket in self.apply_opts(opts) {
-            self.del(socket.id, rooms.clone()).unwrap();
+        for socket in self.apply_opts(ns, opts) {
+            self.del(socket.id, rooms.clone());
         }
-        Ok(())
     }
 
-    fn disconnect_socket(&self, opts: BroadcastOptions) -> Result<(), BroadcastError> {
+    pub fn disconnect_socket<A: Adapter>(
+        &self,
+        ns: &Namespace<A>,
+        opts: BroadcastOptions,
+    ) -> Result<(), BroadcastError> {
         let errors: Vec<_> = self
-            .apply_opts(opts)
+            .apply_opts(ns, opts)
             .into_iter()
             .filter_map(|socket| socket.disconnect().err())
             .collect();
@@ -287,18 +600,82 @@ impl Adapter for LocalAdapter {
             Err(errors.into())
         }
     }
-}
 
-impl LocalAdapter {
+    pub fn room_events(&self) -> BoxStream<'static, RoomEvent> {
+        let rx = self.room_events.subscribe();
+        stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    // The stream was too slow, the oldest events are skipped
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+
+    pub fn set_room_history(&self, room: Room, capacity: usize) {
+        let mut history = self.history.write().unwrap();
+        if capacity == 0 {
+            history.remove(&room);
+            return;
+        }
+        let history = history.entry(room).or_insert_with(|| RoomHistory {
+            capacity,
+            packets: VecDeque::with_capacity(capacity),
+        });
+        history.capacity = capacity;
+        while history.packets.len() > capacity {
+            history.packets.pop_front();
+        }
+    }
+
+    pub fn room_history(&self, room: &Room) -> Vec<Packet<'static>> {
+        let history = self.history.read().unwrap();
+        history
+            .get(room)
+            .map(|history| history.packets.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Appends the packet to the history of the given rooms, if they keep one
+    fn push_history(&self, rooms: &HashSet<Room>, packet: &Packet<'_>) {
+        let mut history = self.history.write().unwrap();
+        if history.is_empty() {
+            return;
+        }
+        let mut owned = None;
+        for room in rooms {
+            if let Some(history) = history.get_mut(room) {
+                let packet = owned.get_or_insert_with(|| packet.clone().into_owned());
+                if history.packets.len() == history.capacity {
+                    history.packets.pop_front();
+                }
+                history.packets.push_back(packet.clone());
+            }
+        }
+    }
+
+    /// Notifies the [`Adapter::room_events`] streams, if there are any
+    fn send_room_event(&self, event: RoomEvent) {
+        // An error only means that there is no stream
+        self.room_events.send(event).ok();
+    }
+
     /// Applies the given `opts` and return the sockets that match.
-    fn apply_opts(&self, opts: BroadcastOptions) -> Vec<SocketRef<Self>> {
+    pub fn apply_opts<A: Adapter>(
+        &self,
+        ns: &Namespace<A>,
+        opts: BroadcastOptions,
+    ) -> Vec<SocketRef<A>> {
+        let except = self.get_except_sids(&opts);
         let rooms = opts.rooms;
-
-        let except = self.get_except_sids(&opts.except);
-        let ns = self.ns.upgrade().unwrap();
         if !rooms.is_empty() {
             let rooms_map = self.rooms.read().unwrap();
-            rooms
+            // A socket in several of the rooms is only selected once
+            let sids: HashSet<Sid> = rooms
                 .iter()
                 .filter_map(|room| rooms_map.get(room))
                 .flatten()
@@ -307,7 +684,10 @@ impl LocalAdapter {
                         && (!opts.flags.contains(&BroadcastFlags::Broadcast)
                             || opts.sid.map(|s| s != **sid).unwrap_or(true))
                 })
-                .filter_map(|sid| ns.get_socket(*sid).ok())
+                .copied()
+                .collect();
+            sids.into_iter()
+                .filter_map(|sid| ns.get_socket(sid).ok())
                 .map(SocketRef::new)
                 .collect()
         } else if opts.flags.contains(&BroadcastFlags::Broadcast) {
@@ -326,10 +706,10 @@ impl LocalAdapter {
         }
     }
 
-    fn get_except_sids(&self, except: &HashSet<Room>) -> HashSet<Sid> {
-        let mut except_sids = HashSet::new();
+    fn get_except_sids(&self, opts: &BroadcastOptions) -> HashSet<Sid> {
+        let mut except_sids = opts.except_sids.clone();
         let rooms_map = self.rooms.read().unwrap();
-        for room in except {
+        for room in &opts.except {
             if let Some(sockets) = rooms_map.get(room) {
                 except_sids.extend(sockets);
             }
@@ -341,6 +721,7 @@ impl LocalAdapter {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::packet::PacketData;
     use std::sync::Arc;
 
     macro_rules! hash_set {
@@ -353,7 +734,7 @@ mod test {
     async fn test_server_count() {
         let ns = Namespace::new_dummy([]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        assert_eq!(adapter.server_count().unwrap(), 1);
+        assert_eq!(adapter.server_count().await.unwrap(), 1);
     }
 
     #[tokio::test]
@@ -361,8 +742,8 @@ mod test {
         let socket = Sid::new();
         let ns = Namespace::new_dummy([socket]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket, ["room1", "room2"]).unwrap();
-        let rooms_map = adapter.rooms.read().unwrap();
+        adapter.add_all(socket, ["room1", "room2"]).await.unwrap();
+        let rooms_map = adapter.state.rooms.read().unwrap();
         assert_eq!(rooms_map.len(), 2);
         assert_eq!(rooms_map.get("room1").unwrap().len(), 1);
         assert_eq!(rooms_map.get("room2").unwrap().len(), 1);
@@ -373,9 +754,9 @@ mod test {
         let socket = Sid::new();
         let ns = Namespace::new_dummy([socket]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket, ["room1", "room2"]).unwrap();
-        adapter.del(socket, "room1").unwrap();
-        let rooms_map = adapter.rooms.read().unwrap();
+        adapter.add_all(socket, ["room1", "room2"]).await.unwrap();
+        adapter.del(socket, "room1").await.unwrap();
+        let rooms_map = adapter.state.rooms.read().unwrap();
         assert_eq!(rooms_map.len(), 2);
         assert_eq!(rooms_map.get("room1").unwrap().len(), 0);
         assert_eq!(rooms_map.get("room2").unwrap().len(), 1);
@@ -386,9 +767,9 @@ mod test {
         let socket = Sid::new();
         let ns = Namespace::new_dummy([socket]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket, ["room1", "room2"]).unwrap();
-        adapter.del_all(socket).unwrap();
-        let rooms_map = adapter.rooms.read().unwrap();
+        adapter.add_all(socket, ["room1", "room2"]).await.unwrap();
+        adapter.del_all(socket).await.unwrap();
+        let rooms_map = adapter.state.rooms.read().unwrap();
         assert_eq!(rooms_map.len(), 2);
         assert_eq!(rooms_map.get("room1").unwrap().len(), 0);
         assert_eq!(rooms_map.get("room2").unwrap().len(), 0);
@@ -401,19 +782,61 @@ mod test {
         let sid3 = Sid::new();
         let ns = Namespace::new_dummy([sid1, sid2, sid3]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(sid1, ["room1", "room2"]).unwrap();
-        adapter.add_all(sid2, ["room1"]).unwrap();
-        adapter.add_all(sid3, ["room2"]).unwrap();
+        adapter.add_all(sid1, ["room1", "room2"]).await.unwrap();
+        adapter.add_all(sid2, ["room1"]).await.unwrap();
+        adapter.add_all(sid3, ["room2"]).await.unwrap();
         assert!(adapter
             .socket_rooms(sid1)
+            .await
             .unwrap()
             .contains(&"room1".into()));
         assert!(adapter
             .socket_rooms(sid1)
+            .await
             .unwrap()
             .contains(&"room2".into()));
-        assert_eq!(adapter.socket_rooms(sid2).unwrap(), ["room1"]);
-        assert_eq!(adapter.socket_rooms(sid3).unwrap(), ["room2"]);
+        assert_eq!(adapter.socket_rooms(sid2).await.unwrap(), ["room1"]);
+        assert_eq!(adapter.socket_rooms(sid3).await.unwrap(), ["room2"]);
+    }
+
+    #[tokio::test]
+    async fn test_room_size() {
+        let sid1 = Sid::new();
+        let sid2 = Sid::new();
+        let ns = Namespace::new_dummy([sid1, sid2]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        assert_eq!(adapter.room_size("room1".into()).await.unwrap(), 0);
+        adapter.add_all(sid1, ["room1", "room2"]).await.unwrap();
+        adapter.add_all(sid2, ["room1"]).await.unwrap();
+        assert_eq!(adapter.room_size("room1".into()).await.unwrap(), 2);
+        assert_eq!(adapter.room_size("room2".into()).await.unwrap(), 1);
+        adapter.del(sid1, "room1").await.unwrap();
+        assert_eq!(adapter.room_size("room1".into()).await.unwrap(), 1);
+        adapter.del_all(sid2).await.unwrap();
+        assert_eq!(adapter.room_size("room1".into()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rooms() {
+        let sid1 = Sid::new();
+        let sid2 = Sid::new();
+        let ns = Namespace::new_dummy([sid1, sid2]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(sid1, ["room1", "room2"]).await.unwrap();
+        adapter.add_all(sid2, ["room1", "room3"]).await.unwrap();
+        adapter.del(sid2, "room3").await.unwrap();
+
+        let mut rooms = adapter.rooms().await.unwrap();
+        rooms.sort();
+        for (_, sids) in &mut rooms {
+            sids.sort();
+        }
+        let mut room1 = vec![sid1, sid2];
+        room1.sort();
+        assert_eq!(
+            rooms,
+            [("room1".into(), room1), ("room2".into(), vec![sid1])]
+        );
     }
 
     #[tokio::test]
@@ -421,12 +844,12 @@ mod test {
         let socket = Sid::new();
         let ns = Namespace::new_dummy([socket]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket, ["room1"]).unwrap();
+        adapter.add_all(socket, ["room1"]).await.unwrap();
 
         let mut opts = BroadcastOptions::new(Some(socket));
         opts.rooms = hash_set!["room1".into()];
-        adapter.add_sockets(opts, "room2").unwrap();
-        let rooms_map = adapter.rooms.read().unwrap();
+        adapter.add_sockets(opts, "room2").await.unwrap();
+        let rooms_map = adapter.state.rooms.read().unwrap();
 
         assert_eq!(rooms_map.len(), 2);
         assert!(rooms_map.get("room1").unwrap().contains(&socket));
@@ -438,14 +861,14 @@ mod test {
         let socket = Sid::new();
         let ns = Namespace::new_dummy([socket]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket, ["room1"]).unwrap();
+        adapter.add_all(socket, ["room1"]).await.unwrap();
 
         let mut opts = BroadcastOptions::new(Some(socket));
         opts.rooms = hash_set!["room1".into()];
-        adapter.add_sockets(opts, "room2").unwrap();
+        adapter.add_sockets(opts, "room2").await.unwrap();
 
         {
-            let rooms_map = adapter.rooms.read().unwrap();
+            let rooms_map = adapter.state.rooms.read().unwrap();
 
             assert_eq!(rooms_map.len(), 2);
             assert!(rooms_map.get("room1").unwrap().contains(&socket));
@@ -454,10 +877,10 @@ mod test {
 
         let mut opts = BroadcastOptions::new(Some(socket));
         opts.rooms = hash_set!["room1".into()];
-        adapter.del_sockets(opts, "room2").unwrap();
+        adapter.del_sockets(opts, "room2").await.unwrap();
 
         {
-            let rooms_map = adapter.rooms.read().unwrap();
+            let rooms_map = adapter.state.rooms.read().unwrap();
 
             assert_eq!(rooms_map.len(), 2);
             assert!(rooms_map.get("room1").unwrap().contains(&socket));
@@ -472,26 +895,50 @@ mod test {
         let socket2 = Sid::new();
         let ns = Namespace::new_dummy([socket0, socket1, socket2]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
-        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
-        adapter.add_all(socket1, ["room1", "room3"]).unwrap();
-        adapter.add_all(socket2, ["room2", "room3"]).unwrap();
+        adapter.add_all(socket0, ["room1", "room2"]).await.unwrap();
+        adapter.add_all(socket1, ["room1", "room3"]).await.unwrap();
+        adapter.add_all(socket2, ["room2", "room3"]).await.unwrap();
 
-        let sockets = adapter.sockets("room1").unwrap();
+        let sockets = adapter.sockets("room1").await.unwrap();
         assert_eq!(sockets.len(), 2);
         assert!(sockets.contains(&socket0));
         assert!(sockets.contains(&socket1));
 
-        let sockets = adapter.sockets("room2").unwrap();
+        let sockets = adapter.sockets("room2").await.unwrap();
         assert_eq!(sockets.len(), 2);
         assert!(sockets.contains(&socket0));
         assert!(sockets.contains(&socket2));
 
-        let sockets = adapter.sockets("room3").unwrap();
+        let sockets = adapter.sockets("room3").await.unwrap();
         assert_eq!(sockets.len(), 2);
         assert!(sockets.contains(&socket1));
         assert!(sockets.contains(&socket2));
     }
 
+    #[tokio::test]
+    async fn test_broadcast_count() {
+        let sockets = [Sid::new(), Sid::new(), Sid::new(), Sid::new()];
+        let ns = Namespace::<LocalAdapter>::new_dummy(sockets);
+        for sid in &sockets[..3] {
+            ns.adapter.add_all(*sid, "room1").await.unwrap();
+        }
+
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room1".into()];
+        let packet = Packet::event("/", "test", None);
+        assert_eq!(ns.adapter.broadcast(packet.clone(), opts).await.unwrap(), 3);
+
+        // The sender is not counted
+        let mut opts = BroadcastOptions::new(Some(sockets[0]));
+        opts.flags.insert(BroadcastFlags::Broadcast);
+        assert_eq!(ns.adapter.broadcast(packet.clone(), opts).await.unwrap(), 3);
+
+        // Nobody is listening
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room2".into()];
+        assert_eq!(ns.adapter.broadcast(packet, opts).await.unwrap(), 0);
+    }
+
     #[tokio::test]
     async fn test_disconnect_socket() {
         let socket0 = Sid::new();
@@ -501,17 +948,20 @@ mod test {
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
         adapter
             .add_all(socket0, ["room1", "room2", "room4"])
+            .await
             .unwrap();
         adapter
             .add_all(socket1, ["room1", "room3", "room5"])
+            .await
             .unwrap();
         adapter
             .add_all(socket2, ["room2", "room3", "room6"])
+            .await
             .unwrap();
 
         let mut opts = BroadcastOptions::new(Some(socket0));
         opts.rooms = hash_set!["room5".into()];
-        match adapter.disconnect_socket(opts) {
+        match adapter.disconnect_socket(opts).await {
             // todo it returns Ok, in previous commits it also returns Ok
             Err(BroadcastError::SendError(_)) | Ok(_) => {}
             e => panic!(
@@ -520,7 +970,7 @@ mod test {
             ),
         }
 
-        let sockets = adapter.sockets("room2").unwrap();
+        let sockets = adapter.sockets("room2").await.unwrap();
         assert_eq!(sockets.len(), 2);
         assert!(sockets.contains(&socket2));
         assert!(sockets.contains(&socket0));
@@ -533,25 +983,26 @@ mod test {
         let ns = Namespace::new_dummy([socket0, socket1, socket2]);
         let adapter = LocalAdapter::new(Arc::downgrade(&ns));
         // Add socket 0 to room1 and room2
-        adapter.add_all(socket0, ["room1", "room2"]).unwrap();
+        adapter.add_all(socket0, ["room1", "room2"]).await.unwrap();
         // Add socket 1 to room1 and room3
-        adapter.add_all(socket1, ["room1", "room3"]).unwrap();
+        adapter.add_all(socket1, ["room1", "room3"]).await.unwrap();
         // Add socket 2 to room2 and room3
         adapter
             .add_all(socket2, ["room1", "room2", "room3"])
+            .await
             .unwrap();
 
         // socket 2 is the sender
         let mut opts = BroadcastOptions::new(Some(socket2));
         opts.rooms = hash_set!["room1".into()];
         opts.except = hash_set!["room2".into()];
-        let sockets = adapter.fetch_sockets(opts).unwrap();
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
         assert_eq!(sockets.len(), 1);
         assert_eq!(sockets[0].id, socket1);
 
         let mut opts = BroadcastOptions::new(Some(socket2));
         opts.flags.insert(BroadcastFlags::Broadcast);
-        let sockets = adapter.fetch_sockets(opts).unwrap();
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
         assert_eq!(sockets.len(), 2);
         sockets.iter().for_each(|s| {
             assert!(s.id == socket0 || s.id == socket1);
@@ -560,16 +1011,179 @@ mod test {
         let mut opts = BroadcastOptions::new(Some(socket2));
         opts.flags.insert(BroadcastFlags::Broadcast);
         opts.except = hash_set!["room2".into()];
-        let sockets = adapter.fetch_sockets(opts).unwrap();
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
         assert_eq!(sockets.len(), 1);
 
         let opts = BroadcastOptions::new(Some(socket2));
-        let sockets = adapter.fetch_sockets(opts).unwrap();
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
         assert_eq!(sockets.len(), 1);
         assert_eq!(sockets[0].id, socket2);
 
         let opts = BroadcastOptions::new(Some(Sid::new()));
-        let sockets = adapter.fetch_sockets(opts).unwrap();
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
         assert_eq!(sockets.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_except_sids() {
+        let socket0 = Sid::new();
+        let socket1 = Sid::new();
+        let socket2 = Sid::new();
+        let socket3 = Sid::new();
+        let ns = Namespace::new_dummy([socket0, socket1, socket2, socket3]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1"]).await.unwrap();
+        adapter.add_all(socket1, ["room1"]).await.unwrap();
+        adapter.add_all(socket2, ["room1", "room2"]).await.unwrap();
+        adapter.add_all(socket3, ["room3"]).await.unwrap();
+
+        // A room of three with one excluded socket
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room1".into()];
+        opts.except_sids = hash_set![socket1];
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
+        assert_eq!(sockets.len(), 2);
+        assert!(sockets.iter().all(|s| s.id == socket0 || s.id == socket2));
+
+        // Excluded sockets and rooms are combined across multiple targets
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room1".into(), "room3".into()];
+        opts.except = hash_set!["room2".into()];
+        opts.except_sids = hash_set![socket0, socket3];
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].id, socket1);
+
+        // A room named after a socket id doesn't exclude the socket
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room1".into()];
+        opts.except = hash_set![socket1.to_string().into()];
+        let sockets = adapter.fetch_sockets(opts).await.unwrap();
+        assert_eq!(sockets.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_room_history() {
+        let ns = Namespace::<LocalAdapter>::new_dummy([]);
+        let room: Room = "room1".into();
+        async fn broadcast(
+            ns: &Namespace<LocalAdapter>,
+            event: &'static str,
+            rooms: HashSet<Room>,
+        ) {
+            let mut opts = BroadcastOptions::new(None);
+            opts.rooms = rooms;
+            ns.adapter
+                .broadcast(Packet::event("/", event, None), opts)
+                .await
+                .unwrap();
+        }
+        async fn events(ns: &Namespace<LocalAdapter>, room: &Room) -> Vec<String> {
+            ns.adapter
+                .room_history(room)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|p| match p.inner {
+                    PacketData::Event(e, _, _) => e.into_owned(),
+                    _ => panic!("unexpected packet"),
+                })
+                .collect()
+        }
+
+        // Without history nothing is kept
+        broadcast(&ns, "e0", hash_set![room.clone()]).await;
+        assert!(events(&ns, &room).await.is_empty());
+
+        ns.adapter.set_room_history(room.clone(), 2);
+        broadcast(&ns, "e1", hash_set![room.clone(), "room2".into()]).await;
+        broadcast(&ns, "e2", hash_set!["room2".into()]).await;
+        broadcast(&ns, "e3", hash_set![room.clone()]).await;
+        broadcast(&ns, "e4", hash_set![room.clone()]).await;
+        assert_eq!(events(&ns, &room).await, ["e3", "e4"]);
+
+        // Shrinking the history drops the oldest packets
+        ns.adapter.set_room_history(room.clone(), 1);
+        assert_eq!(events(&ns, &room).await, ["e4"]);
+
+        ns.adapter.set_room_history(room.clone(), 0);
+        broadcast(&ns, "e5", hash_set![room.clone()]).await;
+        assert!(events(&ns, &room).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_room_events() {
+        let sid = Sid::new();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let mut events = ns.adapter.room_events();
+        let socket = ns.get_socket(sid).unwrap();
+
+        socket.join(["room1", "room2"]).await.unwrap();
+        socket.leave("room1").await.unwrap();
+        // Leaving a room that the socket is not in emits nothing
+        socket.leave("room3").await.unwrap();
+        socket.disconnect().unwrap();
+
+        let join = |room: &'static str| RoomEvent::Join {
+            sid,
+            room: room.into(),
+        };
+        let leave = |room: &'static str| RoomEvent::Leave {
+            sid,
+            room: room.into(),
+        };
+        for event in [join("room1"), join("room2"), leave("room1"), leave("room2")] {
+            assert_eq!(events.next().await.unwrap(), event);
+        }
+        let next = tokio::time::timeout(Duration::from_millis(10), events.next()).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_ack() {
+        use crate::packet::PacketData;
+        use serde_json::Value;
+
+        let socket0 = Sid::new();
+        let socket1 = Sid::new();
+        let socket2 = Sid::new();
+        let socket3 = Sid::new();
+        let ns = Namespace::new_dummy([socket0, socket1, socket2, socket3]);
+        let adapter = LocalAdapter::new(Arc::downgrade(&ns));
+        adapter.add_all(socket0, ["room1"]).await.unwrap();
+        adapter.add_all(socket1, ["room1"]).await.unwrap();
+        adapter.add_all(socket2, ["room1"]).await.unwrap();
+
+        let mut opts = BroadcastOptions::new(None);
+        opts.rooms = hash_set!["room1".into()];
+        opts.flags
+            .insert(BroadcastFlags::Timeout(Duration::from_millis(50)));
+        let packet = Packet::event("/", "test", None);
+        let stream = adapter
+            .broadcast_with_ack::<String>(packet, opts)
+            .await
+            .unwrap();
+
+        // A socket joining the room after the emit is not awaited
+        adapter.add_all(socket3, ["room1"]).await.unwrap();
+
+        // socket2 never acks the message
+        for sid in [socket0, socket1] {
+            let socket = ns.get_socket(sid).unwrap();
+            socket
+                .recv(PacketData::EventAck(Value::String(sid.to_string()), 1))
+                .unwrap();
+        }
+
+        let mut acks: Vec<_> = stream.collect().await;
+        assert_eq!(acks.len(), 3);
+        acks.sort_by_key(|(sid, _)| *sid != socket2);
+        let (sid, res) = acks.remove(0);
+        assert_eq!(sid, socket2);
+        assert!(matches!(res, Err(AckError::Timeout(_))));
+        for (sid, res) in acks {
+            assert!(sid == socket0 || sid == socket1);
+            assert_eq!(res.unwrap().data, sid.to_string());
+        }
+    }
 }
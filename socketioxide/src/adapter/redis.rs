@@ -0,0 +1,811 @@
+//! A [`RedisAdapter`] sharing the broadcasts and the room memberships between multiple servers
+//! through a redis pub/sub channel.
+//!
+//! Each namespace publishes on its own `socket.io#<namespace>#` channel:
+//! * the packets broadcast to more than a single socket,
+//! * the sockets added to or removed from rooms by the operators, and disconnected by the operators,
+//! * the room memberships of its sockets, so that each server knows the rooms of the other servers,
+//! * a heartbeat, so that a server that stops without closing its namespace (e.g. if it crashed) is forgotten
+//!   with its rooms and sockets once it didn't publish anything for the [heartbeat timeout](RedisDriver::heartbeat_timeout).
+//!
+//! The packets are always delivered to the local sockets first, so if redis is unavailable
+//! only the other servers miss them. The subscription is retried with a backoff until redis is available again.
+//!
+//! Acknowledgements and [`fetch_sockets`](Adapter::fetch_sockets) only apply to the local sockets.
+//!
+//! The redis client is abstracted behind the [`RedisDriver`] trait.
+//!
+//! ## Example
+//! ```
+//! # use socketioxide::{SocketIo, extract::*, adapter::redis::{RedisAdapter, RedisDriver, RedisError}};
+//! # use futures::{future::BoxFuture, stream::BoxStream};
+//! #[derive(Default)]
+//! struct MyRedisClient;
+//!
+//! impl RedisDriver for MyRedisClient {
+//!     fn publish(&self, channel: String, message: Vec<u8>) -> BoxFuture<'static, Result<(), RedisError>> {
+//!         // Publish the message with the redis client
+//! #       Box::pin(async { Ok(()) })
+//!     }
+//!     fn subscribe(&self, channel: String) -> BoxFuture<'static, Result<BoxStream<'static, Vec<u8>>, RedisError>> {
+//!         // Subscribe to the channel with the redis client
+//! #       Box::pin(async { Ok(Box::pin(futures::stream::empty()) as BoxStream<'static, Vec<u8>>) })
+//!     }
+//! }
+//!
+//! let (_, io) = SocketIo::builder()
+//!     .with_adapter::<RedisAdapter<MyRedisClient>>()
+//!     .build_svc();
+//! io.ns("/", |socket: SocketRef<RedisAdapter<MyRedisClient>>| async move {
+//!     socket.join("room1").await.ok();
+//!     // Sent to the sockets of room1 on all the servers
+//!     socket.to("room1").emit("hello", "world").await.ok();
+//! });
+//! ```
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::Duration,
+};
+
+use engineioxide::sid::Sid;
+use futures::{
+    future::{self, BoxFuture},
+    stream::{BoxStream, StreamExt},
+    FutureExt,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+
+use super::{
+    AckStream, Adapter, BroadcastFlags, BroadcastOptions, LocalState, Room, RoomEvent,
+    RoomsSnapshot,
+};
+use crate::{
+    errors::BroadcastError,
+    extract::SocketRef,
+    ns::Namespace,
+    operators::RoomParam,
+    packet::{BinaryPacket, Packet, PacketData},
+};
+
+/// The delay before the first attempt to subscribe again once the connection to redis is lost.
+/// It doubles after each failed attempt.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+/// The maximum delay between two attempts to subscribe.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+/// The default interval between two heartbeats of a server.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// The default delay after which a server that didn't publish anything is forgotten.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An error returned by a [`RedisDriver`].
+pub type RedisError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The redis pub/sub operations used by the [`RedisAdapter`], it should be implemented on top of a redis client.
+///
+/// Because adapters are created by their namespace without any parameter, drivers are created with [`Default`].
+/// They should get their connection from a client shared by all the namespaces, such as a static one.
+pub trait RedisDriver: Default + Send + Sync + 'static {
+    /// Publishes a message on the channel.
+    fn publish(
+        &self,
+        channel: String,
+        message: Vec<u8>,
+    ) -> BoxFuture<'static, Result<(), RedisError>>;
+
+    /// Subscribes to the channel and returns the stream of the received messages.
+    /// The stream should end when the connection to redis is lost.
+    fn subscribe(
+        &self,
+        channel: String,
+    ) -> BoxFuture<'static, Result<BoxStream<'static, Vec<u8>>, RedisError>>;
+
+    /// The interval between two heartbeats published by the server, 5 seconds by default.
+    fn heartbeat_interval(&self) -> Duration {
+        HEARTBEAT_INTERVAL
+    }
+
+    /// The delay after which a server that didn't publish anything, not even a heartbeat,
+    /// is forgotten with its rooms and sockets. 10 seconds by default.
+    ///
+    /// It should be greater than the [heartbeat interval](RedisDriver::heartbeat_interval) of all the servers.
+    fn heartbeat_timeout(&self) -> Duration {
+        HEARTBEAT_TIMEOUT
+    }
+}
+
+/// A message published by a server on the channel of a namespace
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    /// The id of the server that published the message
+    uid: Sid,
+    message: Message,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    /// Announces a server with the rooms of its sockets.
+    /// The other servers answer with their own rooms, with `reply` set.
+    Hello {
+        rooms: Vec<(Room, Vec<Sid>)>,
+        reply: bool,
+    },
+    /// The server is closing its namespace
+    Bye,
+    /// The server is still alive
+    Heartbeat,
+    Join {
+        sid: Sid,
+        rooms: Vec<Room>,
+    },
+    Leave {
+        sid: Sid,
+        rooms: Vec<Room>,
+    },
+    LeaveAll {
+        sid: Sid,
+    },
+    Broadcast {
+        event: String,
+        data: Option<Value>,
+        bin: Vec<Vec<u8>>,
+        opts: BroadcastOptions,
+    },
+    AddSockets {
+        opts: BroadcastOptions,
+        rooms: Vec<Room>,
+    },
+    DelSockets {
+        opts: BroadcastOptions,
+        rooms: Vec<Room>,
+    },
+    DisconnectSockets {
+        opts: BroadcastOptions,
+    },
+}
+
+/// An [`Adapter`] sharing the broadcasts and the room memberships between multiple servers with redis.
+///
+/// See the [module](self) documentation for more details.
+pub struct RedisAdapter<D: RedisDriver> {
+    /// The id of this server
+    uid: Sid,
+    driver: Arc<D>,
+    local: LocalState,
+    /// The other servers, by server id
+    remote: RwLock<HashMap<Sid, RemoteServer>>,
+    /// The messages to publish, in order
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    /// The receiver of the messages to publish, it's taken by the publish task when the adapter is initialized
+    rx: Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>,
+    /// The subscribe and heartbeat tasks
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    ns: Weak<Namespace<Self>>,
+}
+
+/// Another server known through the channel
+#[derive(Debug)]
+struct RemoteServer {
+    /// The room memberships of the sockets of the server
+    rooms: HashMap<Room, HashSet<Sid>>,
+    /// When the last message of the server was received
+    last_seen: Instant,
+}
+
+impl RemoteServer {
+    fn new(rooms: HashMap<Room, HashSet<Sid>>) -> Self {
+        Self {
+            rooms,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+impl<D: RedisDriver> std::fmt::Debug for RedisAdapter<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisAdapter")
+            .field("uid", &self.uid)
+            .field("local", &self.local)
+            .field("remote", &self.remote)
+            .finish()
+    }
+}
+
+impl<D: RedisDriver> Adapter for RedisAdapter<D> {
+    type Error = Infallible;
+
+    fn new(ns: Weak<Namespace<Self>>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            uid: Sid::new(),
+            driver: Arc::new(D::default()),
+            local: LocalState::new(),
+            remote: RwLock::new(HashMap::new()),
+            tx,
+            rx: Mutex::new(Some(rx)),
+            tasks: Mutex::new(Vec::new()),
+            ns,
+        }
+    }
+
+    fn init(&self) -> BoxFuture<'_, Result<(), Infallible>> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("redis adapter created outside of a tokio runtime, it is disabled");
+            return future::ready(Ok(())).boxed();
+        }
+        let channel = format!("socket.io#{}#", self.ns().path);
+        if let Some(rx) = self.rx.lock().unwrap().take() {
+            tokio::spawn(publish_task(self.driver.clone(), channel.clone(), rx));
+        }
+        let subscriber = tokio::spawn(subscribe_task(
+            self.driver.clone(),
+            channel,
+            self.ns.clone(),
+        ));
+        let heartbeat = tokio::spawn(heartbeat_task(
+            self.driver.heartbeat_interval(),
+            self.driver.heartbeat_timeout(),
+            self.ns.clone(),
+        ));
+        self.tasks.lock().unwrap().extend([subscriber, heartbeat]);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<(), Infallible>> {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("closing redis adapter of server {}", self.uid);
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.publish(Message::Bye);
+        self.local.clear();
+        self.remote.write().unwrap().clear();
+        future::ready(Ok(())).boxed()
+    }
+
+    fn server_count(&self) -> BoxFuture<'_, Result<u16, Infallible>> {
+        let count = 1 + self.remote.read().unwrap().len() as u16;
+        future::ready(Ok(count)).boxed()
+    }
+
+    fn add_all(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.join(sid, rooms.into_room_iter().collect());
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.leave(sid, rooms.into_room_iter().collect());
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del_all(&self, sid: Sid) -> BoxFuture<'_, Result<(), Infallible>> {
+        self.local.del_all(sid);
+        self.publish(Message::LeaveAll { sid });
+        future::ready(Ok(())).boxed()
+    }
+
+    /// Returns the number of local sockets the packet was enqueued to,
+    /// the sockets of the other servers are not counted.
+    fn broadcast(
+        &self,
+        packet: Packet<'static>,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<usize, BroadcastError>> {
+        if is_remote(&opts) {
+            let parts = match &packet.inner {
+                PacketData::Event(e, data, None) => Some((e.to_string(), data.clone(), vec![])),
+                PacketData::BinaryEvent(e, bin, None) => {
+                    // The placeholders are added back when the packet is rebuilt
+                    let data = bin.data.clone().map(|d| BinaryPacket::incoming(d).data);
+                    Some((e.to_string(), data.flatten(), bin.bin.clone()))
+                }
+                _ => None,
+            };
+            if let Some((event, data, bin)) = parts {
+                let opts = opts.clone();
+                self.publish(Message::Broadcast {
+                    event,
+                    data,
+                    bin,
+                    opts,
+                });
+            }
+        }
+        future::ready(self.local.broadcast(&self.ns(), packet, opts)).boxed()
+    }
+
+    fn broadcast_with_ack<V: DeserializeOwned + 'static>(
+        &self,
+        packet: Packet<'static>,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<AckStream<V>, BroadcastError>> {
+        let stream = self.local.broadcast_with_ack(&self.ns(), packet, opts);
+        future::ready(Ok(stream)).boxed()
+    }
+
+    fn sockets(&self, rooms: impl RoomParam) -> BoxFuture<'_, Result<Vec<Sid>, Infallible>> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        let mut sockets = self.local.sockets(&self.ns(), rooms.clone());
+        let remote = self.remote.read().unwrap();
+        let remote_sockets: HashSet<Sid> = remote
+            .values()
+            .flat_map(|server| rooms.iter().filter_map(|room| server.rooms.get(room)))
+            .flatten()
+            .copied()
+            .collect();
+        sockets.extend(remote_sockets);
+        future::ready(Ok(sockets)).boxed()
+    }
+
+    fn room_size(&self, room: Room) -> BoxFuture<'_, Result<usize, Infallible>> {
+        let remote = self.remote.read().unwrap();
+        let remote_size: usize = remote
+            .values()
+            .filter_map(|server| server.rooms.get(&room))
+            .map(HashSet::len)
+            .sum();
+        future::ready(Ok(self.local.room_size(&room) + remote_size)).boxed()
+    }
+
+    /// Returns the rooms of this server merged with the rooms of the other servers.
+    fn rooms(&self) -> BoxFuture<'_, Result<RoomsSnapshot, Infallible>> {
+        let mut rooms: HashMap<Room, Vec<Sid>> = self.local.rooms().into_iter().collect();
+        let remote = self.remote.read().unwrap();
+        for (room, sids) in remote.values().flat_map(|server| &server.rooms) {
+            rooms.entry(room.clone()).or_default().extend(sids);
+        }
+        future::ready(Ok(rooms.into_iter().collect())).boxed()
+    }
+
+    fn socket_rooms(&self, sid: Sid) -> BoxFuture<'_, Result<Vec<Room>, Infallible>> {
+        future::ready(Ok(self.local.socket_rooms(sid))).boxed()
+    }
+
+    fn fetch_sockets(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<Vec<SocketRef<Self>>, Infallible>> {
+        future::ready(Ok(self.local.apply_opts(&self.ns(), opts))).boxed()
+    }
+
+    fn add_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Infallible>> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        if is_remote(&opts) {
+            let (opts, rooms) = (opts.clone(), rooms.clone());
+            self.publish(Message::AddSockets { opts, rooms });
+        }
+        // The local sockets publish their own membership changes
+        for socket in self.local.apply_opts(&self.ns(), opts) {
+            self.join(socket.id, rooms.clone());
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn del_sockets(
+        &self,
+        opts: BroadcastOptions,
+        rooms: impl RoomParam,
+    ) -> BoxFuture<'_, Result<(), Infallible>> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        if is_remote(&opts) {
+            let (opts, rooms) = (opts.clone(), rooms.clone());
+            self.publish(Message::DelSockets { opts, rooms });
+        }
+        for socket in self.local.apply_opts(&self.ns(), opts) {
+            self.leave(socket.id, rooms.clone());
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn disconnect_socket(
+        &self,
+        opts: BroadcastOptions,
+    ) -> BoxFuture<'_, Result<(), BroadcastError>> {
+        if is_remote(&opts) {
+            let opts = opts.clone();
+            self.publish(Message::DisconnectSockets { opts });
+        }
+        future::ready(self.local.disconnect_socket(&self.ns(), opts)).boxed()
+    }
+
+    fn room_events(&self) -> BoxStream<'static, RoomEvent> {
+        self.local.room_events()
+    }
+
+    /// The history is kept by each server, with the packets broadcast to the room from any server
+    fn set_room_history(&self, room: Room, capacity: usize) {
+        self.local.set_room_history(room, capacity);
+    }
+
+    fn room_history(&self, room: &Room) -> BoxFuture<'_, Result<Vec<Packet<'static>>, Infallible>> {
+        future::ready(Ok(self.local.room_history(room))).boxed()
+    }
+}
+
+/// Returns `true` if the operation should also be applied by the other servers
+fn is_remote(opts: &BroadcastOptions) -> bool {
+    !opts.flags.contains(&BroadcastFlags::Local)
+        && (opts.flags.contains(&BroadcastFlags::Broadcast) || !opts.rooms.is_empty())
+}
+
+impl<D: RedisDriver> RedisAdapter<D> {
+    fn ns(&self) -> Arc<Namespace<Self>> {
+        self.ns.upgrade().unwrap()
+    }
+
+    /// Adds a local socket to the rooms and publishes its new memberships
+    fn join(&self, sid: Sid, rooms: Vec<Room>) {
+        self.local.add_all(sid, rooms.clone());
+        self.publish(Message::Join { sid, rooms });
+    }
+
+    /// Removes a local socket from the rooms and publishes its memberships changes
+    fn leave(&self, sid: Sid, rooms: Vec<Room>) {
+        self.local.del(sid, rooms.clone());
+        self.publish(Message::Leave { sid, rooms });
+    }
+
+    /// Queues a message to publish, they are published in order by the publish task
+    fn publish(&self, message: Message) {
+        let envelope = Envelope {
+            uid: self.uid,
+            message,
+        };
+        match serde_json::to_vec(&envelope) {
+            // An error means that the adapter is being dropped
+            Ok(message) => self.tx.send(message).ok(),
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("error serializing redis adapter message: {_e}");
+                None
+            }
+        };
+    }
+
+    /// Forgets the servers that didn't publish anything for longer than the timeout
+    fn expire_servers(&self, timeout: Duration) {
+        self.remote.write().unwrap().retain(|_uid, server| {
+            let alive = server.last_seen.elapsed() < timeout;
+            #[cfg(feature = "tracing")]
+            if !alive {
+                tracing::warn!("server {_uid} timed out, forgetting its rooms");
+            }
+            alive
+        });
+    }
+
+    /// Handles a message received from the channel
+    fn on_message(&self, ns: &Namespace<Self>, message: &[u8]) {
+        let Envelope { uid, message } = match serde_json::from_slice(message) {
+            Ok(envelope) => envelope,
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("invalid redis adapter message: {_e}");
+                return;
+            }
+        };
+        if uid == self.uid {
+            return;
+        }
+        let known = match self.remote.write().unwrap().get_mut(&uid) {
+            Some(server) => {
+                server.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        };
+        match message {
+            Message::Hello { rooms, reply } => {
+                let rooms = rooms
+                    .into_iter()
+                    .map(|(room, sids)| (room, sids.into_iter().collect()))
+                    .collect();
+                self.remote
+                    .write()
+                    .unwrap()
+                    .insert(uid, RemoteServer::new(rooms));
+                if !reply {
+                    self.publish(Message::Hello {
+                        rooms: self.local.rooms(),
+                        reply: true,
+                    });
+                }
+            }
+            Message::Bye => {
+                self.remote.write().unwrap().remove(&uid);
+            }
+            // The rooms of a server that was forgotten or missed are requested by announcing this server again
+            Message::Heartbeat if !known => self.publish(Message::Hello {
+                rooms: self.local.rooms(),
+                reply: false,
+            }),
+            Message::Heartbeat => (),
+            Message::Join { sid, rooms } => {
+                let mut remote = self.remote.write().unwrap();
+                let server = remote
+                    .entry(uid)
+                    .or_insert_with(|| RemoteServer::new(HashMap::new()));
+                for room in rooms {
+                    server.rooms.entry(room).or_default().insert(sid);
+                }
+            }
+            Message::Leave { sid, rooms } => {
+                if let Some(server) = self.remote.write().unwrap().get_mut(&uid) {
+                    for room in rooms {
+                        if let Some(sids) = server.rooms.get_mut(&room) {
+                            sids.remove(&sid);
+                        }
+                    }
+                    server.rooms.retain(|_, sids| !sids.is_empty());
+                }
+            }
+            Message::LeaveAll { sid } => {
+                if let Some(server) = self.remote.write().unwrap().get_mut(&uid) {
+                    server.rooms.values_mut().for_each(|sids| {
+                        sids.remove(&sid);
+                    });
+                    server.rooms.retain(|_, sids| !sids.is_empty());
+                }
+            }
+            Message::Broadcast {
+                event,
+                data,
+                bin,
+                opts,
+            } => {
+                let packet = if bin.is_empty() {
+                    Packet::event(ns.path.clone(), event, data)
+                } else {
+                    Packet::bin_event(ns.path.clone(), event, data, bin)
+                };
+                if let Err(_e) = self.local.broadcast(ns, packet, opts) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("error broadcasting packet from server {uid}: {_e:?}");
+                }
+            }
+            Message::AddSockets { opts, rooms } => {
+                for socket in self.local.apply_opts(ns, opts) {
+                    self.join(socket.id, rooms.clone());
+                }
+            }
+            Message::DelSockets { opts, rooms } => {
+                for socket in self.local.apply_opts(ns, opts) {
+                    self.leave(socket.id, rooms.clone());
+                }
+            }
+            Message::DisconnectSockets { opts } => {
+                if let Err(_e) = self.local.disconnect_socket(ns, opts) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("error disconnecting sockets from server {uid}: {_e:?}");
+                }
+            }
+        }
+    }
+}
+
+impl<D: RedisDriver> Drop for RedisAdapter<D> {
+    fn drop(&mut self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+/// Publishes the queued messages in order.
+/// If redis is unavailable the messages are dropped, the local sockets already received them.
+async fn publish_task<D: RedisDriver>(
+    driver: Arc<D>,
+    channel: String,
+    mut rx: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    while let Some(message) = rx.recv().await {
+        if let Err(_e) = driver.publish(channel.clone(), message).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("error publishing on {channel}: {_e}");
+        }
+    }
+}
+
+/// Publishes a heartbeat at each interval and forgets the other servers that timed out.
+async fn heartbeat_task<D: RedisDriver>(
+    interval: Duration,
+    timeout: Duration,
+    ns: Weak<Namespace<RedisAdapter<D>>>,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(ns) = ns.upgrade() else {
+            return;
+        };
+        ns.adapter.expire_servers(timeout);
+        ns.adapter.publish(Message::Heartbeat);
+    }
+}
+
+/// Subscribes to the channel of the namespace and handles the received messages.
+/// Once the connection is lost, it subscribes again with a backoff.
+async fn subscribe_task<D: RedisDriver>(
+    driver: Arc<D>,
+    channel: String,
+    ns: Weak<Namespace<RedisAdapter<D>>>,
+) {
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        match driver.subscribe(channel.clone()).await {
+            Ok(mut stream) => {
+                delay = RECONNECT_MIN_DELAY;
+                // Announces this server so that the others answer with their rooms
+                match ns.upgrade() {
+                    Some(ns) => ns.adapter.publish(Message::Hello {
+                        rooms: ns.adapter.local.rooms(),
+                        reply: false,
+                    }),
+                    None => return,
+                }
+                while let Some(message) = stream.next().await {
+                    match ns.upgrade() {
+                        Some(ns) => ns.adapter.on_message(&ns, &message),
+                        None => return,
+                    }
+                }
+                #[cfg(feature = "tracing")]
+                tracing::warn!("subscription to {channel} lost");
+                // The rooms of the other servers are unknown until they answer again
+                match ns.upgrade() {
+                    Some(ns) => ns.adapter.remote.write().unwrap().clear(),
+                    None => return,
+                }
+            }
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("error subscribing to {channel}: {_e}");
+            }
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::stream;
+    use std::future::Future;
+    use tokio::sync::{broadcast, Notify};
+
+    /// An in-process bus shared by the servers of a test
+    struct Bus {
+        tx: broadcast::Sender<Vec<u8>>,
+        /// Notified each time a server handled a message
+        handled: Notify,
+    }
+
+    thread_local! {
+        // Each test runs on its own thread, so it gets its own bus
+        static BUS: Arc<Bus> = Arc::new(Bus {
+            tx: broadcast::channel(64).0,
+            handled: Notify::new(),
+        });
+    }
+
+    /// A driver sharing the messages of all the channels through the bus of the test
+    struct TestDriver(Arc<Bus>);
+
+    impl Default for TestDriver {
+        fn default() -> Self {
+            Self(BUS.with(Arc::clone))
+        }
+    }
+
+    impl RedisDriver for TestDriver {
+        fn publish(
+            &self,
+            _: String,
+            message: Vec<u8>,
+        ) -> BoxFuture<'static, Result<(), RedisError>> {
+            self.0.tx.send(message).ok();
+            Box::pin(async { Ok(()) })
+        }
+
+        fn subscribe(
+            &self,
+            _: String,
+        ) -> BoxFuture<'static, Result<BoxStream<'static, Vec<u8>>, RedisError>> {
+            let bus = self.0.clone();
+            let rx = bus.tx.subscribe();
+            // The next message is only requested once the previous one is handled
+            let stream = stream::unfold(rx, move |mut rx| {
+                bus.handled.notify_waiters();
+                async move { rx.recv().await.ok().map(|message| (message, rx)) }
+            });
+            Box::pin(async move { Ok(stream.boxed()) })
+        }
+
+        fn heartbeat_interval(&self) -> Duration {
+            Duration::from_millis(20)
+        }
+
+        fn heartbeat_timeout(&self) -> Duration {
+            Duration::from_millis(100)
+        }
+    }
+
+    /// Waits until the condition holds, it is checked again each time a server handled a message
+    async fn until<F: Future<Output = bool>>(condition: impl Fn() -> F) {
+        let bus = BUS.with(Arc::clone);
+        let wait = async {
+            loop {
+                let handled = bus.handled.notified();
+                if condition().await {
+                    return;
+                }
+                handled.await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .expect("condition not met");
+    }
+
+    #[tokio::test]
+    async fn remote_rooms() {
+        let (sid1, sid2, sid3) = (Sid::new(), Sid::new(), Sid::new());
+        let ns1 = Namespace::<RedisAdapter<TestDriver>>::new_dummy([sid1]);
+        let ns2 = Namespace::<RedisAdapter<TestDriver>>::new_dummy([sid2, sid3]);
+        ns2.adapter.add_all(sid2, ["room1", "room2"]).await.unwrap();
+        until(|| async { ns1.adapter.room_size("room2".into()).await.unwrap() == 1 }).await;
+
+        assert_eq!(ns1.adapter.server_count().await.unwrap(), 2);
+        until(|| async { ns2.adapter.server_count().await.unwrap() == 2 }).await;
+
+        ns1.adapter.add_all(sid1, ["room1"]).await.unwrap();
+        ns2.adapter.add_all(sid3, ["room1"]).await.unwrap();
+        until(|| async { ns1.adapter.room_size("room1".into()).await.unwrap() == 3 }).await;
+        let mut sockets = ns1.adapter.sockets("room1").await.unwrap();
+        sockets.sort();
+        let mut expected = vec![sid1, sid2, sid3];
+        expected.sort();
+        assert_eq!(sockets, expected);
+        let mut rooms = ns1.adapter.rooms().await.unwrap();
+        rooms.sort();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0].1.len(), 3);
+        assert_eq!(rooms[1], ("room2".into(), vec![sid2]));
+
+        ns2.adapter.del_all(sid2).await.unwrap();
+        ns2.adapter.del(sid3, "room1").await.unwrap();
+        until(|| async { ns1.adapter.room_size("room1".into()).await.unwrap() == 1 }).await;
+        assert_eq!(ns1.adapter.sockets("room1").await.unwrap(), vec![sid1]);
+        assert!(ns1.adapter.sockets("room2").await.unwrap().is_empty());
+
+        ns2.adapter.close().await.unwrap();
+        until(|| async { ns1.adapter.server_count().await.unwrap() == 1 }).await;
+    }
+
+    #[tokio::test]
+    async fn crashed_server_expires() {
+        let (sid1, sid2) = (Sid::new(), Sid::new());
+        let ns1 = Namespace::<RedisAdapter<TestDriver>>::new_dummy([sid1]);
+        let ns2 = Namespace::<RedisAdapter<TestDriver>>::new_dummy([sid2]);
+        ns2.adapter.add_all(sid2, ["room1"]).await.unwrap();
+        until(|| async { ns1.adapter.room_size("room1".into()).await.unwrap() == 1 }).await;
+
+        // The heartbeats keep the server alive
+        tokio::time::sleep(ns1.adapter.driver.heartbeat_timeout() * 2).await;
+        assert_eq!(ns1.adapter.server_count().await.unwrap(), 2);
+
+        // The server stops without saying goodbye
+        ns2.clean_dummy_sockets();
+        drop(ns2);
+        until(|| async { ns1.adapter.server_count().await.unwrap() == 1 }).await;
+        assert!(ns1.adapter.sockets("room1").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+//! Event arguments mixing JSON values and binary attachments.
+//!
+//! With [`Args`], the binary attachments keep their position among the other arguments:
+//! they are replaced by placeholders in the emitted packet and put back in place when a packet is received.
+//! It can be emitted with [`Socket::emit_args`](crate::socket::Socket::emit_args) or
+//! [`Operators::emit_args`](crate::operators::Operators::emit_args) and used as an extractor
+//! in a [`MessageHandler`](crate::handler::MessageHandler).
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{SocketIo, args::{Arg, Args}, extract::SocketRef};
+//! # use serde_json::json;
+//! let (_, io) = SocketIo::new_svc();
+//! io.ns("/", |socket: SocketRef| {
+//!     socket.on("upload", |socket: SocketRef, args: Args| {
+//!         for arg in args.iter() {
+//!             match arg {
+//!                 Arg::Json(value) => println!("json argument: {value}"),
+//!                 Arg::Bin(bin) => println!("binary argument of {} bytes", bin.len()),
+//!             }
+//!         }
+//!         let args = Args::new()
+//!             .json(json!({ "name": "file.bin" }))
+//!             .unwrap()
+//!             .bin(vec![1, 2, 3]);
+//!         socket.emit_args("download", args).ok();
+//!     });
+//! });
+//! ```
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// An event argument, either a JSON value or a binary attachment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    /// A JSON value
+    Json(Value),
+    /// A binary attachment
+    Bin(Vec<u8>),
+}
+
+/// An ordered list of event arguments mixing JSON values and binary attachments.
+///
+/// As an extractor, it consumes the event data and its binary payloads, so it should be the last argument of the handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args(Vec<Arg>);
+
+impl Args {
+    /// Creates an empty list of arguments
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a JSON argument, serialized from the given value.
+    pub fn json(mut self, value: impl Serialize) -> Result<Self, serde_json::Error> {
+        self.0.push(Arg::Json(serde_json::to_value(value)?));
+        Ok(self)
+    }
+
+    /// Adds a binary argument, e.g. a `Vec<u8>` or a `Bytes` buffer
+    pub fn bin(mut self, bin: impl Into<Vec<u8>>) -> Self {
+        self.0.push(Arg::Bin(bin.into()));
+        self
+    }
+
+    /// Appends an argument
+    pub fn push(&mut self, arg: Arg) {
+        self.0.push(arg);
+    }
+
+    /// Returns an iterator over the arguments
+    pub fn iter(&self) -> std::slice::Iter<'_, Arg> {
+        self.0.iter()
+    }
+
+    /// Returns the number of arguments
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there is no argument
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the arguments
+    pub fn into_vec(self) -> Vec<Arg> {
+        self.0
+    }
+
+    /// Splits the arguments into the JSON data, with a placeholder for each binary argument, and the binary payloads
+    pub(crate) fn into_parts(self) -> (Value, Vec<Vec<u8>>) {
+        let mut bins = Vec::new();
+        let data = self
+            .0
+            .into_iter()
+            .map(|arg| match arg {
+                Arg::Json(value) => value,
+                Arg::Bin(bin) => {
+                    bins.push(bin);
+                    json!({ "_placeholder": true, "num": bins.len() - 1 })
+                }
+            })
+            .collect();
+        (Value::Array(data), bins)
+    }
+
+    /// Puts the binary payloads back at the positions of their placeholders in the data.
+    ///
+    /// The payloads without a valid position are appended after the JSON arguments.
+    pub(crate) fn from_parts(
+        data: Value,
+        bin: Vec<Vec<u8>>,
+        placeholders: &[(usize, usize)],
+    ) -> Self {
+        let values = match data {
+            Value::Array(values) => values,
+            Value::Null => vec![],
+            value => vec![value],
+        };
+        let mut bins: Vec<_> = bin.into_iter().map(Some).collect();
+        let mut values = values.into_iter();
+        let mut args = Vec::with_capacity(values.len() + bins.len());
+        for i in 0..values.len() + placeholders.len() {
+            match placeholders.iter().find(|(pos, _)| *pos == i) {
+                Some((_, num)) => {
+                    if let Some(bin) = bins.get_mut(*num).and_then(Option::take) {
+                        args.push(Arg::Bin(bin));
+                    }
+                }
+                None => {
+                    if let Some(value) = values.next() {
+                        args.push(Arg::Json(value));
+                    }
+                }
+            }
+        }
+        args.extend(values.map(Arg::Json));
+        args.extend(bins.into_iter().flatten().map(Arg::Bin));
+        Self(args)
+    }
+}
+
+impl From<Vec<Arg>> for Args {
+    fn from(args: Vec<Arg>) -> Self {
+        Self(args)
+    }
+}
+
+impl FromIterator<Arg> for Args {
+    fn from_iter<I: IntoIterator<Item = Arg>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Args {
+    type Item = Arg;
+    type IntoIter = std::vec::IntoIter<Arg>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parts_round_trip() {
+        let args = Args::new()
+            .bin(vec![1])
+            .json("meta")
+            .unwrap()
+            .bin(vec![2, 3]);
+        let (data, bins) = args.clone().into_parts();
+        assert_eq!(
+            data,
+            json!([{ "_placeholder": true, "num": 0 }, "meta", { "_placeholder": true, "num": 1 }])
+        );
+        assert_eq!(bins, vec![vec![1], vec![2, 3]]);
+
+        let data = json!(["meta"]);
+        let args2 = Args::from_parts(data, bins, &[(0, 0), (2, 1)]);
+        assert_eq!(args2, args);
+    }
+
+    #[test]
+    fn from_parts_without_positions() {
+        let args = Args::from_parts(json!(["a", "b"]), vec![vec![1]], &[]);
+        let expected = Args::from(vec![
+            Arg::Json(json!("a")),
+            Arg::Json(json!("b")),
+            Arg::Bin(vec![1]),
+        ]);
+        assert_eq!(args, expected);
+    }
+}
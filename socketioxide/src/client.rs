@@ -1,21 +1,29 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, RwLock,
+};
+use std::time::Duration;
 
 use engineioxide::handler::EngineIoHandler;
 use engineioxide::socket::{DisconnectReason as EIoDisconnectReason, Socket as EIoSocket};
 use futures::TryFutureExt;
 
 use engineioxide::sid::Sid;
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, time::Instant};
 
 use crate::adapter::Adapter;
-use crate::handler::ConnectHandler;
+use crate::handler::{ConnectErrorHandler, ConnectErrorKind, ConnectHandler};
+use crate::lifecycle::{LifecycleEvent, LifecycleEvents};
 use crate::ProtocolVersion;
 use crate::{
-    errors::Error,
-    ns::Namespace,
+    errors::{Error, NsPatternError},
+    io::{Metrics, ParseErrorPolicy, ShutdownSummary},
+    ns::{DynNamespace, Namespace, NsHeartbeat},
     packet::{Packet, PacketData},
+    parser::{self, DefaultParser, Parser},
+    socket::Socket,
     SocketIoConfig,
 };
 
@@ -23,6 +31,13 @@ use crate::{
 pub struct Client<A: Adapter> {
     pub(crate) config: Arc<SocketIoConfig>,
     ns: RwLock<HashMap<Cow<'static, str>, Arc<Namespace<A>>>>,
+    dyn_ns: RwLock<Vec<DynNamespace<A>>>,
+    /// Set when the server is shutting down, new connections are then refused
+    closing: AtomicBool,
+    /// The handler called on failed connections
+    pub(crate) connect_error_handler: ConnectErrorHandler,
+    /// The sender of the lifecycle events of the sockets
+    pub(crate) lifecycle: LifecycleEvents,
 }
 
 impl<A: Adapter> Client<A> {
@@ -33,6 +48,10 @@ impl<A: Adapter> Client<A> {
         Self {
             config,
             ns: RwLock::new(HashMap::new()),
+            dyn_ns: RwLock::new(Vec::new()),
+            closing: AtomicBool::new(false),
+            connect_error_handler: ConnectErrorHandler::default(),
+            lifecycle: LifecycleEvents::default(),
         }
     }
 
@@ -47,7 +66,22 @@ impl<A: Adapter> Client<A> {
         tracing::debug!("auth: {:?}", auth);
 
         let sid = esocket.id;
-        if let Some(ns) = self.get_ns(ns_path) {
+        if self.closing.load(Ordering::Relaxed) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                "server is shutting down, refusing connection to {}",
+                ns_path
+            );
+            let kind = ConnectErrorKind::ShuttingDown;
+            let packet = Packet::connect_error(ns_path, kind.message());
+            if let Err(_e) = parser::send_packet(esocket, &self.config, packet) {
+                #[cfg(feature = "tracing")]
+                tracing::error!("error while sending connect error packet: {}", _e);
+            }
+            self.connect_error_handler
+                .call(kind, ns_path, &esocket.req_parts);
+            Ok(())
+        } else if let Some(ns) = self.get_ns(ns_path).or_else(|| self.get_dyn_ns(ns_path)) {
             ns.connect(sid, esocket.clone(), auth, self.config.clone())?;
 
             // cancel the connect timeout task for v5
@@ -64,11 +98,16 @@ impl<A: Adapter> Client<A> {
             esocket.close(EIoDisconnectReason::TransportClose);
             Ok(())
         } else {
-            let packet = Packet::invalid_namespace(ns_path).try_into().unwrap();
-            if let Err(_e) = esocket.emit(packet) {
+            let packet = Packet::invalid_namespace(ns_path);
+            if let Err(_e) = parser::send_packet(esocket, &self.config, packet) {
                 #[cfg(feature = "tracing")]
                 tracing::error!("error while sending invalid namespace packet: {}", _e);
             }
+            self.connect_error_handler.call(
+                ConnectErrorKind::InvalidNamespace,
+                ns_path,
+                &esocket.req_parts,
+            );
             Ok(())
         }
     }
@@ -84,6 +123,58 @@ impl<A: Adapter> Client<A> {
         }
     }
 
+    /// Applies the [`ParseErrorPolicy`] of the config to a connection that sent a malformed packet
+    fn on_parse_error(&self, socket: &EIoSocket<SocketData>) {
+        match self.config.parse_error_policy {
+            ParseErrorPolicy::Disconnect => socket.close(EIoDisconnectReason::PacketParsingError),
+            ParseErrorPolicy::Ignore => (),
+        }
+    }
+
+    /// Handles a decoded packet: connects, caches the partial binary packets or propagates it to its namespace
+    fn on_packet(&self, packet: Packet<'static>, socket: Arc<EIoSocket<SocketData>>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("Packet: {:?}", packet);
+
+        let res: Result<(), Error> = match packet.inner {
+            PacketData::Connect(auth) => self.sock_connect(auth, &packet.ns, &socket),
+            PacketData::BinaryEvent(_, ref bin, _) | PacketData::BinaryAck(ref bin, _)
+                if bin.payload_count() > self.config.max_binary_attachments =>
+            {
+                Err(Error::TooManyBinaryAttachments {
+                    count: bin.payload_count(),
+                    max: self.config.max_binary_attachments,
+                })
+            }
+            PacketData::BinaryEvent(_, ref bin, _) | PacketData::BinaryAck(ref bin, _)
+                if !bin.is_complete() =>
+            {
+                // Cache-in the socket data until all the binary payloads are received
+                let timeout = self.config.binary_reassembly_timeout;
+                let partial = PartialBinPacket::new(packet, timeout, &socket);
+                socket
+                    .data
+                    .partial_bin_packet
+                    .lock()
+                    .unwrap()
+                    .replace(partial);
+                Ok(())
+            }
+            _ => self.sock_propagate_packet(packet, socket.id),
+        };
+        if let Err(ref err) = res {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                "error while processing packet to socket {}: {}",
+                socket.id,
+                err
+            );
+            if let Some(reason) = err.into() {
+                socket.close(reason);
+            }
+        }
+    }
+
     /// Spawn a task that will close the socket if it is not connected to a namespace
     /// after the [`SocketIoConfig::connect_timeout`] duration
     fn spawn_connect_timeout_task(&self, socket: Arc<EIoSocket<SocketData>>) {
@@ -102,15 +193,41 @@ impl<A: Adapter> Client<A> {
     }
 
     /// Adds a new namespace handler
-    pub fn add_ns<C, T>(&self, path: Cow<'static, str>, callback: C)
+    pub fn add_ns<C, T>(&self, path: Cow<'static, str>, callback: C) -> Arc<Namespace<A>>
     where
         C: ConnectHandler<A, T>,
         T: Send + Sync + 'static,
     {
         #[cfg(feature = "tracing")]
         tracing::debug!("adding namespace {}", path);
-        let ns = Namespace::new(path.clone(), callback);
-        self.ns.write().unwrap().insert(path, ns);
+        let ns = Namespace::new(
+            path.clone(),
+            callback,
+            self.config.session_store.clone(),
+            self.connect_error_handler.clone(),
+            self.lifecycle.clone(),
+        );
+        self.ns.write().unwrap().insert(path, ns.clone());
+        ns
+    }
+
+    /// Adds a new dynamic namespace handler matching the given pattern
+    pub fn add_dyn_ns<C, T>(&self, pattern: &str, callback: C) -> Result<(), NsPatternError>
+    where
+        C: ConnectHandler<A, T>,
+        T: Send + Sync + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("adding dynamic namespace {}", pattern);
+        let ns = DynNamespace::new(
+            pattern,
+            callback,
+            self.config.session_store.clone(),
+            self.connect_error_handler.clone(),
+            self.lifecycle.clone(),
+        )?;
+        self.dyn_ns.write().unwrap().push(ns);
+        Ok(())
     }
 
     /// Deletes a namespace handler
@@ -124,6 +241,101 @@ impl<A: Adapter> Client<A> {
         self.ns.read().unwrap().get(path).cloned()
     }
 
+    /// Gets a socket by its [`Sid`] across all the namespaces, the root namespace `/` is looked up first
+    pub fn get_socket(&self, sid: Sid) -> Option<Arc<Socket<A>>> {
+        let ns = self.ns.read().unwrap();
+        ns.get("/")
+            .into_iter()
+            .chain(ns.iter().filter(|(path, _)| *path != "/").map(|(_, ns)| ns))
+            .find_map(|ns| ns.get_socket(sid).ok())
+    }
+
+    /// Creates a child namespace from the first dynamic namespace matching the path.
+    /// It is then registered as a regular namespace for the next connections.
+    fn get_dyn_ns(&self, path: &str) -> Option<Arc<Namespace<A>>> {
+        let ns = self
+            .dyn_ns
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|dyn_ns| dyn_ns.try_match(path))?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!("creating namespace {} from a dynamic namespace", path);
+        let mut ns_map = self.ns.write().unwrap();
+        Some(ns_map.entry(ns.path.clone()).or_insert(ns).clone())
+    }
+
+    /// Computes the metrics of the server from the namespaces and their adapters
+    pub(crate) async fn metrics(&self) -> Result<Metrics, A::Error> {
+        let ns = self.ns.read().unwrap().clone();
+        let mut metrics = Metrics {
+            namespaces: ns.len() + self.dyn_ns.read().unwrap().len(),
+            ..Default::default()
+        };
+        // A client connected to several namespaces shares the same engine.io session
+        let mut sessions = HashMap::new();
+        for ns in ns.values() {
+            let mut rooms = HashSet::new();
+            for socket in ns.get_sockets() {
+                metrics.sockets += 1;
+                rooms.extend(socket.rooms().await?);
+                sessions
+                    .entry(socket.id)
+                    .or_insert_with(|| socket.buffered_packets());
+            }
+            metrics.rooms += rooms.len();
+            metrics.rejected_events += ns.rejected_events.load(Ordering::Relaxed);
+        }
+        metrics.buffered_packets = sessions.values().sum();
+        Ok(metrics)
+    }
+
+    /// Gracefully shuts down the server:
+    /// * New connections to namespaces are refused with a connect error
+    /// * A disconnect packet is sent to every socket
+    /// * The acknowledgements pending at that time are awaited for at most `timeout`
+    /// * All the namespaces are closed
+    pub(crate) async fn shutdown(&self, timeout: Duration) -> ShutdownSummary {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("shutting down the server");
+        self.closing.store(true, Ordering::Relaxed);
+
+        let ns = self.ns.read().unwrap().clone();
+        let pending: Vec<_> = ns
+            .values()
+            .flat_map(|ns| ns.get_sockets())
+            .map(|socket| {
+                if let Err(_e) = socket.send(Packet::disconnect(socket.ns())) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("error while sending disconnect packet: {:?}", _e);
+                }
+                let acks = socket.pending_acks();
+                (socket, acks)
+            })
+            .collect();
+        let total: usize = pending.iter().map(|(_, acks)| acks.len()).sum();
+
+        let drain = pending
+            .iter()
+            .map(|(socket, acks)| socket.wait_pending_acks(acks));
+        tokio::time::timeout(timeout, futures::future::join_all(drain))
+            .await
+            .ok();
+
+        let abandoned_acks = pending
+            .iter()
+            .map(|(socket, acks)| socket.count_pending_acks(acks))
+            .sum();
+        #[cfg(feature = "tracing")]
+        tracing::debug!("{abandoned_acks} acknowledgements abandoned on {total}");
+
+        self.close().await;
+        ShutdownSummary {
+            completed_acks: total - abandoned_acks,
+            abandoned_acks,
+        }
+    }
+
     /// Closes all engine.io connections and all clients
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
     pub(crate) async fn close(&self) {
@@ -140,10 +352,78 @@ impl<A: Adapter> Client<A> {
 pub struct SocketData {
     /// Partial binary packet that is being received
     /// Stored here until all the binary payloads are received
-    pub partial_bin_packet: Mutex<Option<Packet<'static>>>,
+    pub partial_bin_packet: Mutex<Option<PartialBinPacket>>,
 
     /// Channel used to notify the socket that it has been connected to a namespace for v5
     pub connect_recv_tx: Mutex<Option<oneshot::Sender<()>>>,
+
+    /// Set once the client sent a packet decoded by the binary parser of the config,
+    /// the session then uses this parser instead of the default one
+    pub binary_parser: AtomicBool,
+}
+
+impl SocketData {
+    /// Returns true if the session uses the binary parser of the config
+    pub fn uses_binary_parser(&self) -> bool {
+        self.binary_parser.load(Ordering::Relaxed)
+    }
+
+    /// Returns the parser negotiated for the session
+    pub fn parser<'a>(&self, config: &'a SocketIoConfig) -> &'a dyn Parser {
+        if self.uses_binary_parser() {
+            config.parser.as_ref()
+        } else {
+            &DefaultParser
+        }
+    }
+}
+
+/// A binary packet waiting for its binary payloads
+#[derive(Debug)]
+pub struct PartialBinPacket {
+    pub packet: Packet<'static>,
+    /// The packet is dropped if it is not complete after this deadline
+    deadline: Instant,
+    /// The task dropping the packet at the deadline, it is aborted once the packet is dropped
+    _timeout: AbortOnDrop,
+}
+
+#[derive(Debug)]
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl PartialBinPacket {
+    /// Caches the packet until all its payloads are received or the timeout elapses
+    fn new(
+        packet: Packet<'static>,
+        timeout: Duration,
+        socket: &Arc<EIoSocket<SocketData>>,
+    ) -> Self {
+        let deadline = Instant::now() + timeout;
+        let socket = Arc::downgrade(socket);
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            let Some(socket) = socket.upgrade() else {
+                return;
+            };
+            let mut partial = socket.data.partial_bin_packet.lock().unwrap();
+            // The packet may have been replaced by a newer one
+            if matches!(*partial, Some(ref p) if p.deadline <= deadline) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={}] binary payloads not received in time", socket.id);
+                partial.take();
+            }
+        });
+        Self {
+            packet,
+            deadline,
+            _timeout: AbortOnDrop(handle),
+        }
+    }
 }
 
 impl<A: Adapter> EngineIoHandler for Client<A> {
@@ -174,23 +454,17 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
     fn on_disconnect(&self, socket: Arc<EIoSocket<SocketData>>, reason: EIoDisconnectReason) {
         #[cfg(feature = "tracing")]
         tracing::debug!("eio socket disconnected");
-        let _res: Result<Vec<_>, _> = self
+        let sockets: Vec<_> = self
             .ns
             .read()
             .unwrap()
             .values()
             .filter_map(|ns| ns.get_socket(socket.id).ok())
-            .map(|s| s.close(reason.clone().into()))
             .collect();
-
         #[cfg(feature = "tracing")]
-        match _res {
-            Ok(vec) => {
-                tracing::debug!("disconnect handle spawned for {} namespaces", vec.len())
-            }
-            Err(_e) => {
-                tracing::debug!("error while disconnecting socket: {}", _e)
-            }
+        tracing::debug!("disconnect handle spawned for {} namespaces", sockets.len());
+        for socket in sockets {
+            socket.close(reason.clone().into());
         }
     }
 
@@ -201,49 +475,48 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
             Ok(packet) => packet,
             Err(_e) => {
                 #[cfg(feature = "tracing")]
-                tracing::debug!("socket serialization error: {}", _e);
-                socket.close(EIoDisconnectReason::PacketParsingError);
+                tracing::debug!("[sid={}] malformed packet: {}", socket.id, _e);
+                self.on_parse_error(&socket);
                 return;
             }
         };
-        #[cfg(feature = "tracing")]
-        tracing::debug!("Packet: {:?}", packet);
+        self.on_packet(packet, socket);
+    }
 
-        let res: Result<(), Error> = match packet.inner {
-            PacketData::Connect(auth) => self
-                .sock_connect(auth, &packet.ns, &socket)
-                .map_err(Into::into),
-            PacketData::BinaryEvent(_, _, _) | PacketData::BinaryAck(_, _) => {
-                // Cache-in the socket data until all the binary payloads are received
-                socket
-                    .data
-                    .partial_bin_packet
-                    .lock()
-                    .unwrap()
-                    .replace(packet);
-                Ok(())
-            }
-            _ => self.sock_propagate_packet(packet, socket.id),
-        };
-        if let Err(ref err) = res {
-            #[cfg(feature = "tracing")]
-            tracing::debug!(
-                "error while processing packet to socket {}: {}",
-                socket.id,
-                err
-            );
-            if let Some(reason) = err.into() {
-                socket.close(reason);
-            }
-        }
+    fn on_upgrade(&self, socket: Arc<EIoSocket<SocketData>>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] eio socket upgraded", socket.id);
+        self.lifecycle
+            .send(LifecycleEvent::UpgradeCompleted { sid: socket.id });
     }
 
     /// When a binary payload is received from a socket, it is applied to the partial binary packet
     ///
-    /// If the packet is complete, it is propagated to the namespace
+    /// If the packet is complete, it is propagated to the namespace.
+    /// Otherwise, if no packet is waiting for payloads, it is decoded with the binary parser of the config.
     fn on_binary(&self, data: Vec<u8>, socket: Arc<EIoSocket<SocketData>>) {
+        if socket.data.partial_bin_packet.lock().unwrap().is_none() {
+            match self.config.parser.decode_binary(&data) {
+                Some(Ok(packet)) => {
+                    socket.data.binary_parser.store(true, Ordering::Relaxed);
+                    self.on_packet(packet, socket);
+                }
+                Some(Err(_e)) if socket.data.uses_binary_parser() => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("socket binary deserialization error: {}", _e);
+                    self.on_parse_error(&socket);
+                }
+                _ => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("[sid={}] unexpected binary payload", socket.id);
+                }
+            }
+            return;
+        }
+
         if apply_payload_on_packet(data, &socket) {
-            if let Some(packet) = socket.data.partial_bin_packet.lock().unwrap().take() {
+            let partial = socket.data.partial_bin_packet.lock().unwrap().take();
+            if let Some(PartialBinPacket { packet, .. }) = partial {
                 if let Err(ref err) = self.sock_propagate_packet(packet, socket.id) {
                     #[cfg(feature = "tracing")]
                     tracing::debug!(
@@ -258,6 +531,47 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
             }
         }
     }
+
+    /// Uses the heartbeat parameters of the namespace the session primarily connects through,
+    /// falling back to the engine.io config for the unset ones
+    fn heartbeat(&self, req: &http::request::Parts) -> Option<(Duration, Duration)> {
+        let ns = self.get_ns(&primary_ns(req))?;
+        let heartbeat = *ns.heartbeat.read().unwrap();
+        let config = &self.config.engine_config;
+        match heartbeat {
+            NsHeartbeat {
+                ping_interval: None,
+                ping_timeout: None,
+            } => None,
+            NsHeartbeat {
+                ping_interval,
+                ping_timeout,
+            } => Some((
+                ping_interval.unwrap_or(config.ping_interval),
+                ping_timeout.unwrap_or(config.ping_timeout),
+            )),
+        }
+    }
+}
+
+/// Gets the namespace a session primarily connects through from the `ns` query parameter
+/// of the handshake request. It defaults to the root namespace.
+fn primary_ns(req: &http::request::Parts) -> Cow<'_, str> {
+    let ns = req
+        .uri
+        .query()
+        .and_then(|q| q.split('&').find_map(|p| p.strip_prefix("ns=")));
+    match ns {
+        Some(ns) if ns.starts_with('/') => Cow::Borrowed(ns),
+        // The slash may be percent encoded
+        Some(ns) => match ns.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("%2F") => {
+                Cow::Owned(format!("/{}", &ns[3..]))
+            }
+            _ => Cow::Owned(format!("/{ns}")),
+        },
+        None => Cow::Borrowed("/"),
+    }
 }
 
 /// Utility that applies an incoming binary payload to a partial binary packet
@@ -267,8 +581,8 @@ impl<A: Adapter> EngineIoHandler for Client<A> {
 fn apply_payload_on_packet(data: Vec<u8>, socket: &EIoSocket<SocketData>) -> bool {
     #[cfg(feature = "tracing")]
     tracing::debug!("[sid={}] applying payload on packet", socket.id);
-    if let Some(ref mut packet) = *socket.data.partial_bin_packet.lock().unwrap() {
-        match packet.inner {
+    if let Some(ref mut partial) = *socket.data.partial_bin_packet.lock().unwrap() {
+        match partial.packet.inner {
             PacketData::BinaryEvent(_, ref mut bin, _) | PacketData::BinaryAck(ref mut bin, _) => {
                 bin.add_payload(data);
                 bin.is_complete()
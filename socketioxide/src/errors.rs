@@ -22,6 +22,12 @@ pub enum Error {
 
     #[error("adapter error: {0}")]
     Adapter(#[from] AdapterError),
+
+    #[error("too many binary attachments: {count} > {max}")]
+    TooManyBinaryAttachments { count: usize, max: usize },
+
+    #[error("malformed packet: {0}")]
+    Parse(#[from] ParseError),
 }
 
 /// Convert an [`Error`] to an [`EIoDisconnectReason`] if possible
@@ -33,10 +39,90 @@ impl From<&Error> for Option<EIoDisconnectReason> {
         use EIoDisconnectReason::*;
         match value {
             Error::SocketGone(_) => Some(TransportClose),
-            Error::Serialize(_) | Error::InvalidPacketType | Error::InvalidEventName => {
-                Some(PacketParsingError)
-            }
-            Error::Adapter(_) | Error::InvalidNamespace => None,
+            Error::Serialize(_)
+            | Error::InvalidPacketType
+            | Error::InvalidEventName
+            | Error::Parse(_) => Some(PacketParsingError),
+            Error::Adapter(_)
+            | Error::InvalidNamespace
+            | Error::TooManyBinaryAttachments { .. } => None,
+        }
+    }
+}
+
+/// The reason why an incoming text packet could not be decoded,
+/// with the byte offset in the packet where the problem was detected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The packet is empty
+    #[error("empty packet")]
+    Empty,
+
+    /// The packet type is unknown or cannot be sent by a client
+    #[error("invalid packet type at offset {offset}")]
+    InvalidPacketType {
+        /// The offset of the packet type
+        offset: usize,
+    },
+
+    /// The number of binary attachments of a binary packet is missing or is not followed by a `-`
+    #[error("invalid binary attachments count at offset {offset}")]
+    InvalidAttachmentsCount {
+        /// The offset where the count or its separator was expected
+        offset: usize,
+    },
+
+    /// The ack id is not a valid integer
+    #[error("invalid ack id at offset {offset}")]
+    InvalidAckId {
+        /// The offset of the ack id
+        offset: usize,
+    },
+
+    /// An ack packet without ack id
+    #[error("missing ack id at offset {offset}")]
+    MissingAckId {
+        /// The offset where the ack id was expected
+        offset: usize,
+    },
+
+    /// The payload is missing for a packet requiring one
+    #[error("missing payload at offset {offset}")]
+    MissingPayload {
+        /// The offset where the payload was expected
+        offset: usize,
+    },
+
+    /// The payload is not valid JSON
+    #[error("invalid payload at offset {offset}: {reason}")]
+    InvalidPayload {
+        /// The offset of the payload
+        offset: usize,
+        /// The JSON error
+        reason: String,
+    },
+
+    /// The payload of an event is not an array starting with the event name
+    #[error("invalid event name at offset {offset}")]
+    InvalidEventName {
+        /// The offset of the payload
+        offset: usize,
+    },
+}
+
+impl ParseError {
+    /// Returns the byte offset in the packet where the problem was detected
+    pub fn offset(&self) -> usize {
+        use ParseError::*;
+        match self {
+            Empty => 0,
+            InvalidPacketType { offset }
+            | InvalidAttachmentsCount { offset }
+            | InvalidAckId { offset }
+            | MissingAckId { offset }
+            | MissingPayload { offset }
+            | InvalidPayload { offset, .. }
+            | InvalidEventName { offset } => *offset,
         }
     }
 }
@@ -61,6 +147,22 @@ pub enum AckError {
     SendChannel(#[from] SendError),
 }
 
+/// Error type for dynamic namespace patterns.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum NsPatternError {
+    /// A `{` parameter delimiter is not closed
+    #[error("unclosed parameter in namespace pattern")]
+    UnclosedParam,
+
+    /// A parameter name is empty or invalid
+    #[error("invalid parameter name in namespace pattern")]
+    InvalidParamName,
+
+    /// Two parameters are not separated by a static part, so they cannot be told apart
+    #[error("namespace pattern parameters must be separated by a static part")]
+    AdjacentParams,
+}
+
 /// Error type for broadcast operations.
 #[derive(Debug, thiserror::Error)]
 pub enum BroadcastError {
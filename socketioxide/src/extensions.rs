@@ -268,6 +268,16 @@ impl Extensions {
     pub fn len(&self) -> usize {
         self.map.len()
     }
+
+    /// Moves all the extensions of `other` into this `Extensions`, replacing the existing ones of the same types.
+    pub(crate) fn move_from(&self, other: &Extensions) {
+        let keys: Vec<TypeId> = other.map.iter().map(|entry| *entry.key()).collect();
+        for key in keys {
+            if let Some((key, val)) = other.map.remove(&key) {
+                self.map.insert(key, val);
+            }
+        }
+    }
 }
 
 impl fmt::Debug for Extensions {
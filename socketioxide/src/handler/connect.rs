@@ -13,7 +13,7 @@
 //! # use socketioxide::extract::*;
 //! let (svc, io) = SocketIo::new_svc();
 //! // Here the handler is sync,
-//! // if there is a serialization error, the handler is not called
+//! // if there is a serialization error, the handler is not called and the connection is rejected
 //! io.ns("/nsp", move |s: SocketRef, Data(auth): Data<String>| {
 //!     println!("Socket connected on /nsp namespace with id: {} and data: {}", s.id, auth);
 //! });
@@ -52,7 +52,7 @@
 //! io.ns("/", handler);
 //! io.ns("/admin", handler);
 //! ```
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use futures::Future;
 
@@ -61,7 +61,9 @@ use crate::{adapter::Adapter, socket::Socket};
 use super::MakeErasedHandler;
 
 /// A Type Erased [`ConnectHandler`] so it can be stored in a HashMap
-pub(crate) type BoxedConnectHandler<A> = Box<dyn ErasedConnectHandler<A>>;
+///
+/// It is shared between all the namespaces created from a dynamic namespace pattern.
+pub(crate) type BoxedConnectHandler<A> = Arc<dyn ErasedConnectHandler<A>>;
 pub(crate) trait ErasedConnectHandler<A: Adapter>: Send + Sync + 'static {
     fn call(&self, s: Arc<Socket<A>>, auth: Option<String>);
 }
@@ -71,8 +73,8 @@ where
     T: Send + Sync + 'static,
     H: ConnectHandler<A, T> + Send + Sync + 'static,
 {
-    pub fn new_ns_boxed(inner: H) -> Box<dyn ErasedConnectHandler<A>> {
-        Box::new(MakeErasedHandler::new(inner))
+    pub fn new_ns_boxed(inner: H) -> BoxedConnectHandler<A> {
+        Arc::new(MakeErasedHandler::new(inner))
     }
 }
 
@@ -89,7 +91,8 @@ where
 
 /// A trait used to extract the arguments from the connect event.
 /// The `Result` associated type is used to return an error if the extraction fails,
-/// in this case the [`ConnectHandler`] is not called.
+/// in this case the [`ConnectHandler`] is not called and the connection is rejected
+/// with a connect error packet containing the error message.
 ///
 /// * See the [`connect`](super::connect) module doc for more details on connect handler.
 /// * See the [`extract`](super::extract) module doc for more details on available extractors.
@@ -98,7 +101,7 @@ pub trait FromConnectParts<A: Adapter>: Sized {
     type Error: std::error::Error + 'static;
 
     /// Extract the arguments from the connect event.
-    /// If it fails, the handler is not called
+    /// If it fails, the handler is not called and the connection is rejected
     fn from_connect_parts(s: &Arc<Socket<A>>, auth: &Option<String>) -> Result<Self, Self::Error>;
 }
 
@@ -117,6 +120,133 @@ pub trait ConnectHandler<A: Adapter, T>: Send + Sync + 'static {
     }
 }
 
+/// The reason a client failed to connect to a namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectErrorKind {
+    /// The namespace does not exist
+    InvalidNamespace,
+    /// The connection was rejected by a [`ConnectMiddleware`], with its error message
+    Middleware(String),
+    /// An extractor of the [`ConnectHandler`] failed, with its error message.
+    /// For example the auth payload could not be deserialized by the [`AuthData`](super::extract::AuthData) extractor.
+    Extractor(String),
+    /// The server is shutting down and refuses the new connections
+    ShuttingDown,
+    /// The namespace reached its [`max_connections`](crate::NsHandle::with_max_connections) limit
+    TooManyConnections,
+}
+
+impl ConnectErrorKind {
+    /// The message sent to the client in the connect error packet
+    pub(crate) fn message(&self) -> &str {
+        match self {
+            ConnectErrorKind::InvalidNamespace => "Invalid namespace",
+            ConnectErrorKind::Middleware(message) | ConnectErrorKind::Extractor(message) => message,
+            ConnectErrorKind::ShuttingDown => "server is shutting down",
+            ConnectErrorKind::TooManyConnections => "too many connections",
+        }
+    }
+}
+
+/// A failed connection to a namespace, passed to the handler set with
+/// [`SocketIo::on_connect_error`](crate::SocketIo::on_connect_error).
+#[derive(Debug)]
+pub struct ConnectFailure<'a> {
+    /// The reason of the failure
+    pub kind: ConnectErrorKind,
+    /// The namespace the client tried to connect to
+    pub ns: &'a str,
+    /// The request made by the client to open the engine.io session, with its headers and uri
+    pub req_parts: &'a http::request::Parts,
+}
+
+type BoxedConnectErrorHandler = Box<dyn Fn(&ConnectFailure<'_>) + Send + Sync + 'static>;
+
+/// The handler called on failed connections, shared between the client and all the namespaces
+#[derive(Clone, Default)]
+pub(crate) struct ConnectErrorHandler(Arc<RwLock<Option<BoxedConnectErrorHandler>>>);
+
+impl ConnectErrorHandler {
+    pub fn set(&self, handler: impl Fn(&ConnectFailure<'_>) + Send + Sync + 'static) {
+        self.0.write().unwrap().replace(Box::new(handler));
+    }
+
+    pub fn call(&self, kind: ConnectErrorKind, ns: &str, req_parts: &http::request::Parts) {
+        if let Some(handler) = self.0.read().unwrap().as_ref() {
+            handler(&ConnectFailure {
+                kind,
+                ns,
+                req_parts,
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for ConnectErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectErrorHandler").finish()
+    }
+}
+
+/// A Type Erased [`ConnectMiddleware`] so it can be stored in a namespace
+pub(crate) type BoxedConnectMiddleware<A> = Box<dyn ErasedConnectMiddleware<A>>;
+pub(crate) trait ErasedConnectMiddleware<A: Adapter>: Send + Sync + 'static {
+    fn call(&self, s: &Arc<Socket<A>>, auth: &Option<String>) -> Result<(), String>;
+}
+
+impl<A: Adapter, T, M> MakeErasedHandler<M, A, T>
+where
+    T: Send + Sync + 'static,
+    M: ConnectMiddleware<A, T> + Send + Sync + 'static,
+{
+    pub fn new_middleware_boxed(inner: M) -> BoxedConnectMiddleware<A> {
+        Box::new(MakeErasedHandler::new(inner))
+    }
+}
+
+impl<A: Adapter, T, M> ErasedConnectMiddleware<A> for MakeErasedHandler<M, A, T>
+where
+    M: ConnectMiddleware<A, T> + Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    #[inline(always)]
+    fn call(&self, s: &Arc<Socket<A>>, auth: &Option<String>) -> Result<(), String> {
+        self.handler.call(s, auth)
+    }
+}
+
+/// Define a middleware for the connect event.
+/// It is implemented for closures with up to 16 arguments implementing the [`FromConnectParts`] trait
+/// and returning a `Result<(), E>` where `E` implements [`Display`](std::fmt::Display).
+///
+/// Middlewares are registered with [`NsHandle::with_middleware`](crate::NsHandle::with_middleware)
+/// and run in their registration order before the [`ConnectHandler`].
+/// If a middleware returns an error, the next middlewares and the handler are not called
+/// and the connection is rejected with a connect error packet containing the error message.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |s: SocketRef| println!("Socket connected: {}", s.id))
+///     .with_middleware(|s: SocketRef| {
+///         match s.req_parts().headers.get("x-api-key") {
+///             Some(_) => Ok(()),
+///             None => Err("missing api key"),
+///         }
+///     });
+/// ```
+pub trait ConnectMiddleware<A: Adapter, T>: Send + Sync + 'static {
+    /// Call the middleware with the given arguments.
+    /// The returned error message is sent to the client if the connection is rejected.
+    fn call(&self, s: &Arc<Socket<A>>, auth: &Option<String>) -> Result<(), String>;
+
+    #[doc(hidden)]
+    fn phantom(&self) -> std::marker::PhantomData<T> {
+        std::marker::PhantomData
+    }
+}
+
 mod private {
     #[derive(Debug, Copy, Clone)]
     pub enum Sync {}
@@ -140,16 +270,20 @@ macro_rules! impl_handler_async {
                 $(
                     let $ty = match $ty::from_connect_parts(&s, &auth) {
                         Ok(v) => v,
-                        Err(_e) => {
+                        Err(e) => {
                             #[cfg(feature = "tracing")]
-                            tracing::error!("Error while extracting data: {}", _e);
+                            tracing::error!("Error while extracting data: {}", e);
+                            s.reject_connect(ConnectErrorKind::Extractor(e.to_string()));
                             return;
                         },
                     };
                 )*
+                if s.send_connect().is_err() {
+                    return;
+                }
 
                 let fut = (self.clone())($($ty,)*);
-                tokio::spawn(fut);
+                tokio::spawn(s.cancel_on_disconnect(fut));
 
             }
         }
@@ -171,19 +305,48 @@ macro_rules! impl_handler {
                 $(
                     let $ty = match $ty::from_connect_parts(&s, &auth) {
                         Ok(v) => v,
-                        Err(_e) => {
+                        Err(e) => {
                             #[cfg(feature = "tracing")]
-                            tracing::error!("Error while extracting data: {}", _e);
+                            tracing::error!("Error while extracting data: {}", e);
+                            s.reject_connect(ConnectErrorKind::Extractor(e.to_string()));
                             return;
                         },
                     };
                 )*
+                if s.send_connect().is_err() {
+                    return;
+                }
 
                 (self.clone())($($ty,)*);
             }
         }
     };
 }
+macro_rules! impl_middleware {
+    (
+        [$($ty:ident),*]
+    ) => {
+        #[allow(non_snake_case, unused)]
+        impl<A, F, E, $($ty,)*> ConnectMiddleware<A, ($($ty,)*)> for F
+        where
+            F: FnOnce($($ty,)*) -> Result<(), E> + Send + Sync + Clone + 'static,
+            E: std::fmt::Display,
+            A: Adapter,
+            $( $ty: FromConnectParts<A> + Send, )*
+        {
+            fn call(&self, s: &Arc<Socket<A>>, auth: &Option<String>) -> Result<(), String> {
+                $(
+                    let $ty = $ty::from_connect_parts(s, auth).map_err(|e| {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!("Error while extracting data: {}", e);
+                        e.to_string()
+                    })?;
+                )*
+                (self.clone())($($ty,)*).map_err(|e| e.to_string())
+            }
+        }
+    };
+}
 #[rustfmt::skip]
 macro_rules! all_the_tuples {
     ($name:ident) => {
@@ -209,3 +372,4 @@ macro_rules! all_the_tuples {
 
 all_the_tuples!(impl_handler_async);
 all_the_tuples!(impl_handler);
+all_the_tuples!(impl_middleware);
@@ -5,14 +5,28 @@
 //! * [`Data`]: extracts and deserialize to json any data, if a deserialization error occurs the handler won't be called:
 //!     - for [`ConnectHandler`](super::ConnectHandler): extracts and deserialize to json the auth data
 //!     - for [`MessageHandler`](super::MessageHandler): extracts and deserialize to json the message data
+//! * [`AuthData`]: extracts and deserialize to json the auth data of a [`ConnectHandler`](super::ConnectHandler),
+//!   if a deserialization error occurs the connection is rejected
+//! * [`Arg`]: extracts and deserialize to json one argument of a message, the `Arg` extractors of a handler
+//!   taking the arguments in order
 //! * [`TryData`]: extracts and deserialize to json any data but with a `Result` type in case of error:
 //!     - for [`ConnectHandler`](super::ConnectHandler): extracts and deserialize to json the auth data
 //!     - for [`MessageHandler`](super::MessageHandler): extracts and deserialize to json the message data
+//! * [`NsParams`]: extracts the parameters of a namespace registered with [`SocketIo::dyn_ns`](crate::SocketIo::dyn_ns)
+//! * [`Query`]: extracts and deserialize the query parameters of the handshake request,
+//!   if a deserialization error occurs the connection is rejected
+//! * [`QueryMap`]: extracts the raw query parameters of the handshake request
 //! * [`SocketRef`]: extracts a reference to the [`Socket`]
 //! * [`Bin`]: extract a binary payload for a given message. Because it consumes the event it should be the last argument
-//! * [`AckSender`]: Can be used to send an ack response to the current message event
+//! * [`Args`]: extracts the arguments of a message with its binary payloads at their original positions.
+//!   Because it consumes the event it should be the last argument
+//! * [`AckSender`]: Can be used to send an ack response to the current message event,
+//!   or several ones with [`AckSender::stream`]
 //! * [`ProtocolVersion`](crate::ProtocolVersion): extracts the protocol version
 //! * [`TransportType`](crate::TransportType): extracts the transport type
+//! * [`ClientIp`]: extracts the ip address of the client, considering the trusted proxy headers
+//! * [`SocketData`]: extracts the typed data of the socket set with [`Socket::set_data`]
+//! * [`PacketMeta`]: extracts the namespace, the event name and the ack id of the incoming packet
 //! * [`DisconnectReason`](crate::socket::DisconnectReason): extracts the reason of the disconnection
 //! * [`State`]: extracts a reference to a state previously set with [`SocketIoBuilder::with_state`](crate::io::SocketIoBuilder).
 //!
@@ -82,7 +96,10 @@
 //! let (svc, io) = SocketIo::new_svc();
 //! io.ns("/", handler);
 //! // Use the service with your favorite http server
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use super::message::FromMessageParts;
@@ -91,6 +108,7 @@ use super::{connect::FromConnectParts, message::FromMessage};
 use crate::socket::DisconnectReason;
 use crate::{
     adapter::{Adapter, LocalAdapter},
+    args::Args,
     packet::Packet,
     socket::Socket,
     SendError,
@@ -102,13 +120,13 @@ use serde_json::Value;
 #[cfg_attr(docsrs, doc(cfg(feature = "state")))]
 pub use state_extract::*;
 
-/// Utility function to unwrap an array with a single element
-fn upwrap_array(v: &mut Value) {
+/// Utility function to unwrap an array with a single element.
+///
+/// The data is not modified so that it is left intact for the next extractors.
+fn upwrap_array(v: &Value) -> &Value {
     match v {
-        Value::Array(vec) if vec.len() == 1 => {
-            *v = vec.pop().unwrap();
-        }
-        _ => (),
+        Value::Array(vec) if vec.len() == 1 => &vec[0],
+        v => v,
     }
 }
 
@@ -141,8 +159,219 @@ where
         _: &mut Vec<Vec<u8>>,
         _: &Option<i64>,
     ) -> Result<Self, Self::Error> {
-        upwrap_array(v);
-        serde_json::from_value(v.clone()).map(Data)
+        serde_json::from_value(upwrap_array(v).clone()).map(Data)
+    }
+}
+
+/// An Extractor that returns one argument of the event, deserialized to `T`.
+///
+/// The `Arg` extractors of a handler take the arguments of the event in order, the first one gets the first argument,
+/// the second one the second argument, etc. It avoids deserializing all the arguments as a tuple with the [`Data`] extractor.
+/// The extra arguments of the event are ignored.
+///
+/// If the event has less arguments than `Arg` extractors or if an argument can't be deserialized,
+/// the handler is not called and an [`ArgError`] is logged if the `tracing` feature is enabled.
+/// The binary payloads are not counted as arguments, they can be extracted with the [`Bin`] extractor.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |socket: SocketRef| {
+///     // Handles a `socket.emit("move", "player1", { x: 1, y: 2 })` event sent by the client
+///     socket.on("move", |Arg(player): Arg<String>, Arg(pos): Arg<(i32, i32)>| {
+///         println!("{player} moved to {pos:?}");
+///     });
+/// });
+/// ```
+pub struct Arg<T: DeserializeOwned>(pub T);
+
+/// The error returned by the [`Arg`] extractor
+#[derive(Debug, thiserror::Error)]
+pub enum ArgError {
+    /// The event has less arguments than `Arg` extractors
+    #[error("missing argument {index}, the event has {count} arguments")]
+    Missing {
+        /// The index of the missing argument
+        index: usize,
+        /// The number of arguments of the event
+        count: usize,
+    },
+    /// The argument can't be deserialized to the type of the extractor
+    #[error("invalid argument {index}: {source}")]
+    Invalid {
+        /// The index of the invalid argument
+        index: usize,
+        /// The deserialization error
+        source: serde_json::Error,
+    },
+}
+
+impl<T, A> FromMessageParts<A> for Arg<T>
+where
+    T: DeserializeOwned,
+    A: Adapter,
+{
+    type Error = ArgError;
+    fn from_message_parts(
+        _: &Arc<Socket<A>>,
+        v: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, ArgError> {
+        let index = CURRENT_ARG.with(|arg| arg.replace(arg.get() + 1));
+        let args = match v {
+            Value::Array(args) => &args[..],
+            v => std::slice::from_ref(v),
+        };
+        let arg = args.get(index).ok_or(ArgError::Missing {
+            index,
+            count: args.len(),
+        })?;
+        serde_json::from_value(arg.clone())
+            .map(Arg)
+            .map_err(|source| ArgError::Invalid { index, source })
+    }
+}
+
+/// An Extractor that returns the deserialized auth data sent by the client in the handshake.
+/// If a deserialization error occurs, the [`ConnectHandler`](super::ConnectHandler) won't be called
+/// and the connection will be rejected with a connect error packet.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Auth {
+///     token: String,
+/// }
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |socket: SocketRef, AuthData(auth): AuthData<Auth>| {
+///     println!("Socket {} connected with token {}", socket.id, auth.token);
+/// });
+/// ```
+pub struct AuthData<T: DeserializeOwned>(pub T);
+impl<T, A> FromConnectParts<A> for AuthData<T>
+where
+    T: DeserializeOwned,
+    A: Adapter,
+{
+    type Error = serde_json::Error;
+    fn from_connect_parts(_: &Arc<Socket<A>>, auth: &Option<String>) -> Result<Self, Self::Error> {
+        auth.as_ref()
+            .map(|a| serde_json::from_str::<T>(a))
+            .unwrap_or(serde_json::from_str::<T>("{}"))
+            .map(AuthData)
+    }
+}
+
+/// An Extractor that returns the parameters extracted from the namespace path
+/// when it matches a pattern registered with [`SocketIo::dyn_ns`](crate::SocketIo::dyn_ns).
+///
+/// The map is empty for namespaces registered with [`SocketIo::ns`](crate::SocketIo::ns).
+pub struct NsParams(pub HashMap<String, String>);
+impl<A: Adapter> FromConnectParts<A> for NsParams {
+    type Error = Infallible;
+    fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Infallible> {
+        Ok(NsParams(s.ns_params().clone()))
+    }
+}
+impl<A: Adapter> FromMessageParts<A> for NsParams {
+    type Error = Infallible;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, Infallible> {
+        Ok(NsParams(s.ns_params().clone()))
+    }
+}
+
+/// An Extractor that returns the query parameters of the handshake request, deserialized to `T`.
+///
+/// The query also contains the engine.io parameters (`EIO`, `transport` and `sid`), the fields that are not
+/// declared in `T` are ignored. If a deserialization error occurs, the [`ConnectHandler`](super::ConnectHandler)
+/// won't be called and the connection will be rejected with a connect error packet.
+/// For a [`MessageHandler`](super::MessageHandler) the handler won't be called.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Params {
+///     token: String,
+///     version: Option<u32>,
+/// }
+/// let (_, io) = SocketIo::new_svc();
+/// // Connected with `io("http://localhost:3000", { query: { token: "abc" } })`
+/// io.ns("/", |socket: SocketRef, Query(params): Query<Params>| {
+///     println!("Socket {} connected with token {}", socket.id, params.token);
+/// });
+/// ```
+pub struct Query<T: DeserializeOwned>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    fn from_socket<A: Adapter>(s: &Socket<A>) -> Result<Self, serde_urlencoded::de::Error> {
+        let query = s.req_parts().uri.query().unwrap_or_default();
+        serde_urlencoded::from_str(query).map(Query)
+    }
+}
+impl<T, A> FromConnectParts<A> for Query<T>
+where
+    T: DeserializeOwned,
+    A: Adapter,
+{
+    type Error = serde_urlencoded::de::Error;
+    fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Self::Error> {
+        Self::from_socket(s)
+    }
+}
+impl<T, A> FromMessageParts<A> for Query<T>
+where
+    T: DeserializeOwned,
+    A: Adapter,
+{
+    type Error = serde_urlencoded::de::Error;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, Self::Error> {
+        Self::from_socket(s)
+    }
+}
+
+/// An Extractor that returns the raw query parameters of the handshake request, including the engine.io ones.
+///
+/// If a parameter is repeated, the last value is kept.
+pub struct QueryMap(pub HashMap<String, String>);
+
+impl QueryMap {
+    fn from_socket<A: Adapter>(s: &Socket<A>) -> Self {
+        let query = s.req_parts().uri.query().unwrap_or_default();
+        let params: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+        QueryMap(params.into_iter().collect())
+    }
+}
+impl<A: Adapter> FromConnectParts<A> for QueryMap {
+    type Error = Infallible;
+    fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Infallible> {
+        Ok(Self::from_socket(s))
+    }
+}
+impl<A: Adapter> FromMessageParts<A> for QueryMap {
+    type Error = Infallible;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, Infallible> {
+        Ok(Self::from_socket(s))
     }
 }
 
@@ -175,8 +404,7 @@ where
         _: &mut Vec<Vec<u8>>,
         _: &Option<i64>,
     ) -> Result<Self, Infallible> {
-        upwrap_array(v);
-        Ok(TryData(serde_json::from_value(v.clone())))
+        Ok(TryData(serde_json::from_value(upwrap_array(v).clone())))
     }
 }
 /// An Extractor that returns a reference to a [`Socket`].
@@ -227,6 +455,24 @@ impl<A: Adapter> SocketRef<A> {
     pub fn disconnect(self) -> Result<(), SendError> {
         self.0.disconnect()
     }
+
+    /// Disconnect the socket from the current namespace with a reason sent to the client,
+    /// see [`Socket::disconnect_with_reason`].
+    ///
+    /// It will also call the disconnect handler if it is set.
+    #[inline(always)]
+    pub fn disconnect_with_reason(self, reason: &str) -> Result<(), SendError> {
+        self.0.disconnect_with_reason(reason)
+    }
+
+    /// Disconnect the socket from the current namespace and close the underlying connection
+    /// with a websocket close code and a reason, see [`Socket::disconnect_with_code`].
+    ///
+    /// It will also call the disconnect handler if it is set.
+    #[inline(always)]
+    pub fn disconnect_with_code(self, code: u16, reason: &str) -> Result<(), SendError> {
+        self.0.disconnect_with_code(code, reason)
+    }
 }
 
 /// An Extractor that returns the binary data of the message.
@@ -272,7 +518,20 @@ impl<A: Adapter> AckSender<A> {
         }
     }
 
-    /// Add binary data to the ack response.
+    /// Add binary data to the ack response, it is then sent as a binary ack packet
+    /// followed by the binary payloads.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("thumbnail", |Data::<String>(name), ack: AckSender| {
+    ///         let thumbnail = vec![0x89, 0x50, 0x4e, 0x47];
+    ///         ack.bin(vec![thumbnail]).send(name).ok();
+    ///     });
+    /// });
+    /// ```
     pub fn bin(mut self, bin: Vec<Vec<u8>>) -> Self {
         self.binary = bin;
         self
@@ -293,6 +552,81 @@ impl<A: Adapter> AckSender<A> {
             Ok(())
         }
     }
+
+    /// Turns the ack response into a stream of responses, see [`AckStreamSender`].
+    pub fn stream(self) -> AckStreamSender<A> {
+        AckStreamSender {
+            socket: self.socket,
+            ack_id: self.ack_id,
+        }
+    }
+}
+
+/// Sends several responses to the same ack id, for a server streaming pattern.
+/// It is created with [`AckSender::stream`].
+///
+/// Each response is sent as an ack packet holding it as its single argument: `[data]`.
+/// The end of the stream is marked by an ack packet without any argument: `[]`.
+/// The client must keep its ack callback until it receives this end marker.
+/// It is sent with [`AckStreamSender::end`], or when the sender is dropped.
+///
+/// If the client sent a normal message without expecting an ack, the responses are not sent.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |s: SocketRef| {
+///     s.on("search", |Data::<String>(query), ack: AckSender| async move {
+///         let mut results = ack.stream();
+///         for page in 0..3 {
+///             results.send(format!("{query}: page {page}")).ok();
+///         }
+///         results.end().ok();
+///     });
+/// });
+/// ```
+#[derive(Debug)]
+pub struct AckStreamSender<A: Adapter = LocalAdapter> {
+    socket: Arc<Socket<A>>,
+    /// Taken once the end marker is sent
+    ack_id: Option<i64>,
+}
+
+impl<A: Adapter> AckStreamSender<A> {
+    /// Sends a response to the client.
+    pub fn send(&mut self, data: impl Serialize) -> Result<(), SendError> {
+        match self.ack_id {
+            Some(ack_id) => {
+                let data = Value::Array(vec![serde_json::to_value(&data)?]);
+                self.socket
+                    .send(Packet::ack(self.socket.ns(), data, ack_id))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the end marker to the client, no more responses can be sent after it.
+    pub fn end(mut self) -> Result<(), SendError> {
+        self.send_end()
+    }
+
+    fn send_end(&mut self) -> Result<(), SendError> {
+        match self.ack_id.take() {
+            Some(ack_id) => {
+                let data = Value::Array(vec![]);
+                self.socket
+                    .send(Packet::ack(self.socket.ns(), data, ack_id))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<A: Adapter> Drop for AckStreamSender<A> {
+    fn drop(&mut self) {
+        self.send_end().ok();
+    }
 }
 
 impl<A: Adapter> FromConnectParts<A> for crate::ProtocolVersion {
@@ -319,6 +653,105 @@ impl<A: Adapter> FromDisconnectParts<A> for crate::ProtocolVersion {
     }
 }
 
+/// An Extractor that returns the ip address of the client.
+///
+/// It is resolved from the peer address and the proxy headers according to the
+/// [`ClientIpConfig`](crate::ClientIpConfig) set with [`SocketIoBuilder::client_ip`](crate::SocketIoBuilder::client_ip).
+/// It is `None` if the peer address was not set by the server,
+/// see the [`ClientIpConfig`](crate::ClientIpConfig) documentation to insert it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<A: Adapter> FromConnectParts<A> for ClientIp {
+    type Error = Infallible;
+    fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Infallible> {
+        Ok(ClientIp(s.client_ip()))
+    }
+}
+impl<A: Adapter> FromMessageParts<A> for ClientIp {
+    type Error = Infallible;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, Infallible> {
+        Ok(ClientIp(s.client_ip()))
+    }
+}
+impl<A: Adapter> FromDisconnectParts<A> for ClientIp {
+    type Error = Infallible;
+    fn from_disconnect_parts(s: &Arc<Socket<A>>, _: DisconnectReason) -> Result<Self, Infallible> {
+        Ok(ClientIp(s.client_ip()))
+    }
+}
+
+/// An Extractor that returns the typed data of the socket set with [`Socket::set_data`].
+/// It implements [`std::ops::Deref`] to access the inner type.
+///
+/// If no data of type `T` was set, the handler won't be called
+/// and an error log will be print if the `tracing` feature is enabled.
+///
+/// ### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::*};
+/// struct User {
+///     name: String,
+/// }
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |socket: SocketRef, Data::<String>(name)| {
+///     socket.set_data(User { name });
+///     socket.on("message", |user: SocketData<User>, Data::<String>(msg)| {
+///         println!("{}: {}", user.name, msg);
+///     });
+/// });
+/// ```
+pub struct SocketData<T>(pub Arc<T>);
+
+/// No data of the requested type was set on the socket, so the handler won't be called.
+#[derive(Debug, thiserror::Error)]
+#[error("socket data of type {0} not found")]
+pub struct SocketDataNotFound(&'static str);
+
+impl<T> std::ops::Deref for SocketData<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> SocketData<T> {
+    fn from_socket<A: Adapter>(s: &Socket<A>) -> Result<Self, SocketDataNotFound> {
+        s.data()
+            .map(SocketData)
+            .ok_or(SocketDataNotFound(std::any::type_name::<T>()))
+    }
+}
+
+impl<A: Adapter, T: Send + Sync + 'static> FromConnectParts<A> for SocketData<T> {
+    type Error = SocketDataNotFound;
+    fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Self::Error> {
+        Self::from_socket(s)
+    }
+}
+impl<A: Adapter, T: Send + Sync + 'static> FromMessageParts<A> for SocketData<T> {
+    type Error = SocketDataNotFound;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        _: &Option<i64>,
+    ) -> Result<Self, Self::Error> {
+        Self::from_socket(s)
+    }
+}
+impl<A: Adapter, T: Send + Sync + 'static> FromDisconnectParts<A> for SocketData<T> {
+    type Error = SocketDataNotFound;
+    fn from_disconnect_parts(s: &Arc<Socket<A>>, _: DisconnectReason) -> Result<Self, Self::Error> {
+        Self::from_socket(s)
+    }
+}
+
 impl<A: Adapter> FromConnectParts<A> for crate::TransportType {
     type Error = Infallible;
     fn from_connect_parts(s: &Arc<Socket<A>>, _: &Option<String>) -> Result<Self, Infallible> {
@@ -343,6 +776,99 @@ impl<A: Adapter> FromDisconnectParts<A> for crate::TransportType {
     }
 }
 
+thread_local! {
+    /// The name of the event being dispatched to its handler on this thread.
+    /// The extractors are always called synchronously when a handler is called.
+    static CURRENT_EVENT: RefCell<String> = const { RefCell::new(String::new()) };
+    /// The positions of the binary placeholders removed from the data of the event being dispatched
+    static CURRENT_PLACEHOLDERS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+    /// The index of the next argument taken by an [`Arg`] extractor
+    static CURRENT_ARG: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Calls the handler of the given event, making its name available to the [`PacketMeta`] extractor,
+/// the positions of its binary placeholders available to the [`Args`] extractor
+/// and resetting the argument index of the [`Arg`] extractors
+pub(crate) fn with_event<R>(
+    event: &str,
+    placeholders: &[(usize, usize)],
+    f: impl FnOnce() -> R,
+) -> R {
+    CURRENT_EVENT.with(|current| {
+        let mut current = current.borrow_mut();
+        current.clear();
+        current.push_str(event);
+    });
+    CURRENT_PLACEHOLDERS.with(|current| {
+        let mut current = current.borrow_mut();
+        current.clear();
+        current.extend_from_slice(placeholders);
+    });
+    CURRENT_ARG.with(|arg| arg.set(0));
+    f()
+}
+
+impl<A: Adapter> FromMessage<A> for Args {
+    type Error = Infallible;
+    fn from_message(
+        _: Arc<Socket<A>>,
+        data: serde_json::Value,
+        bin: Vec<Vec<u8>>,
+        _: Option<i64>,
+    ) -> Result<Self, Infallible> {
+        let args = CURRENT_PLACEHOLDERS.with(|p| Args::from_parts(data, bin, &p.borrow()));
+        Ok(args)
+    }
+}
+
+/// An Extractor that returns the metadata of the incoming packet:
+/// its namespace, its event name and its ack id if the client requested an ack.
+///
+/// It can be used by generic handlers to decide dynamically whether to send an ack.
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, extract::{AckSender, PacketMeta, SocketRef}};
+/// let (_, io) = SocketIo::new_svc();
+/// io.ns("/", |socket: SocketRef| {
+///     socket.on("test", |meta: PacketMeta, ack: AckSender| {
+///         if meta.ack_requested() {
+///             ack.send(&meta.event).ok();
+///         }
+///     });
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketMeta {
+    /// The namespace of the packet
+    pub ns: String,
+    /// The name of the event
+    pub event: String,
+    /// The ack id of the packet, set if the client requested an ack
+    pub ack_id: Option<i64>,
+}
+impl PacketMeta {
+    /// Returns true if the client requested an ack for this packet
+    #[inline]
+    pub fn ack_requested(&self) -> bool {
+        self.ack_id.is_some()
+    }
+}
+impl<A: Adapter> FromMessageParts<A> for PacketMeta {
+    type Error = Infallible;
+    fn from_message_parts(
+        s: &Arc<Socket<A>>,
+        _: &mut serde_json::Value,
+        _: &mut Vec<Vec<u8>>,
+        ack_id: &Option<i64>,
+    ) -> Result<Self, Infallible> {
+        Ok(Self {
+            ns: s.ns().to_string(),
+            event: CURRENT_EVENT.with(|e| e.borrow().clone()),
+            ack_id: *ack_id,
+        })
+    }
+}
+
 impl<A: Adapter> FromDisconnectParts<A> for DisconnectReason {
     type Error = Infallible;
     fn from_disconnect_parts(
@@ -7,7 +7,13 @@
 //! You can also implement the [`FromMessageParts`] and [`FromMessage`] traits for your own types.
 //! See the [`extract`](super::extract) module doc for more details on available extractors.
 //!
-//! Handlers can be _optionally_ async.
+//! Handlers can be _optionally_ async. Async handlers are spawned on the tokio runtime, so the events of a socket
+//! are processed concurrently unless it is limited with [`NsHandle::with_handler_concurrency`](crate::NsHandle::with_handler_concurrency).
+//!
+//! Handlers can return `()` or a `Result<(), E>` where `E` implements [`Display`](std::fmt::Display).
+//! If a handler returns an error or panics while the client requested an ack,
+//! an ack response carrying the error is sent back in the form of `{ "message": "<error>" }`
+//! so that the client doesn't wait for it until its timeout.
 //!
 //! ## Example with sync closures
 //! ```rust
@@ -54,6 +60,27 @@
 //! });
 //! ```
 //!
+//! ## Example with fallible handlers
+//! ```rust
+//! # use socketioxide::SocketIo;
+//! # use socketioxide::extract::*;
+//! let (svc, io) = SocketIo::new_svc();
+//! io.ns("/", |s: SocketRef| {
+//!     // If the data is empty, the client receives `{ "message": "empty data" }` as ack response
+//!     s.on("event", |Data::<String>(data), ack: AckSender| {
+//!         if data.is_empty() {
+//!             return Err("empty data");
+//!         }
+//!         ack.send(data).ok();
+//!         Ok(())
+//!     });
+//!     s.on("async_event", |Data::<String>(data)| async move {
+//!         data.parse::<u32>()?;
+//!         Ok::<_, std::num::ParseIntError>(())
+//!     });
+//! });
+//! ```
+//!
 //! ## Example with async non anonymous handler
 //! ```rust
 //! # use socketioxide::SocketIo;
@@ -71,9 +98,12 @@
 //!     s.on("event_2", on_event);
 //! });
 //! ```
-use std::sync::Arc;
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+};
 
-use futures::Future;
+use futures::{Future, FutureExt};
 use serde_json::Value;
 
 use crate::adapter::Adapter;
@@ -84,12 +114,17 @@ use super::MakeErasedHandler;
 /// A Type Erased [`MessageHandler`] so it can be stored in a HashMap
 pub(crate) type BoxedMessageHandler<A> = Box<dyn ErasedMessageHandler<A>>;
 
+/// A Type Erased event middleware, see [`NsHandle::on_any`](crate::NsHandle::on_any)
+pub(crate) type BoxedEventMiddleware<A> =
+    Box<dyn Fn(&Arc<Socket<A>>, &str, &mut Value) -> Result<(), String> + Send + Sync + 'static>;
+
 pub(crate) trait ErasedMessageHandler<A: Adapter>: Send + Sync + 'static {
     fn call(&self, s: Arc<Socket<A>>, v: Value, p: Vec<Vec<u8>>, ack_id: Option<i64>);
 }
 
 /// Define a handler for the connect event.
 /// It is implemented for closures with up to 16 arguments. They must implement the [`FromMessageParts`] trait or the [`FromMessage`] trait for the last one.
+/// They can return `()` or a `Result<(), E>`, see [`MessageHandlerOutput`].
 ///
 /// * See the [`message`](super::message) module doc for more details on message handler.
 /// * See the [`extract`](super::extract) module doc for more details on available extractors.
@@ -132,6 +167,74 @@ where
     }
 }
 
+/// The output of a [`MessageHandler`]: `()` or a `Result<(), E>` where `E` implements [`Display`](std::fmt::Display).
+///
+/// If the handler fails and the client requested an ack, the error is sent back as an ack response.
+pub trait MessageHandlerOutput: Send + 'static {
+    /// Converts the output of the handler to a result with the error message
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl MessageHandlerOutput for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: std::fmt::Display + Send + 'static> MessageHandlerOutput for Result<(), E> {
+    fn into_result(self) -> Result<(), String> {
+        self.map_err(|e| e.to_string())
+    }
+}
+
+/// Sends an ack response with the error of the handler if it failed or panicked and the client requested an ack
+fn handle_output<A: Adapter>(
+    s: &Socket<A>,
+    ack_id: Option<i64>,
+    res: std::thread::Result<Result<(), String>>,
+) {
+    let message = match res {
+        Ok(Ok(())) => return,
+        Ok(Err(message)) => message,
+        Err(_) => "event handler panicked".to_string(),
+    };
+    #[cfg(feature = "tracing")]
+    tracing::error!("[sid={}] event handler error: {message}", s.id);
+    if let Some(ack_id) = ack_id {
+        s.send_ack_error(ack_id, &message);
+    }
+}
+
+/// Calls a sync handler, catching its panics
+fn call_sync<A: Adapter, R: MessageHandlerOutput>(
+    s: &Socket<A>,
+    ack_id: Option<i64>,
+    handler: impl FnOnce() -> R,
+) {
+    let res = catch_unwind(AssertUnwindSafe(|| handler().into_result()));
+    handle_output(s, ack_id, res);
+}
+
+/// Spawns the future of an async handler, catching its panics.
+///
+/// The future waits for a permit if the namespace limits the concurrency of the handlers of a socket.
+/// It is cancelled when the socket disconnects if the namespace cancels the handlers.
+fn spawn_async<A: Adapter, R: MessageHandlerOutput>(
+    s: Arc<Socket<A>>,
+    ack_id: Option<i64>,
+    fut: impl Future<Output = R> + Send + 'static,
+) {
+    let permit = s.reserve_handler_permit();
+    let socket = s.clone();
+    tokio::spawn(socket.cancel_on_disconnect(async move {
+        let _permit = permit.await;
+        let res = AssertUnwindSafe(fut.map(R::into_result))
+            .catch_unwind()
+            .await;
+        handle_output(&s, ack_id, res);
+    }));
+}
+
 mod private {
     #[derive(Debug, Clone, Copy)]
     pub enum ViaParts {}
@@ -213,26 +316,28 @@ where
 }
 
 /// Empty Async handler
-impl<A, F, Fut> MessageHandler<A, (private::Async,)> for F
+impl<A, F, Fut, R> MessageHandler<A, (private::Async, R)> for F
 where
     F: FnOnce() -> Fut + Send + Sync + Clone + 'static,
-    Fut: Future<Output = ()> + Send + 'static,
+    Fut: Future<Output = R> + Send + 'static,
+    R: MessageHandlerOutput,
     A: Adapter,
 {
-    fn call(&self, _: Arc<Socket<A>>, _: Value, _: Vec<Vec<u8>>, _: Option<i64>) {
+    fn call(&self, s: Arc<Socket<A>>, _: Value, _: Vec<Vec<u8>>, ack_id: Option<i64>) {
         let fut = (self.clone())();
-        tokio::spawn(fut);
+        spawn_async(s, ack_id, fut);
     }
 }
 
 /// Empty Sync handler
-impl<A, F> MessageHandler<A, (private::Sync,)> for F
+impl<A, F, R> MessageHandler<A, (private::Sync, R)> for F
 where
-    F: FnOnce() + Send + Sync + Clone + 'static,
+    F: FnOnce() -> R + Send + Sync + Clone + 'static,
+    R: MessageHandlerOutput,
     A: Adapter,
 {
-    fn call(&self, _: Arc<Socket<A>>, _: Value, _: Vec<Vec<u8>>, _: Option<i64>) {
-        (self.clone())();
+    fn call(&self, s: Arc<Socket<A>>, _: Value, _: Vec<Vec<u8>>, ack_id: Option<i64>) {
+        call_sync(&s, ack_id, self.clone());
     }
 }
 
@@ -241,10 +346,11 @@ macro_rules! impl_async_handler {
         [$($ty:ident),*], $last:ident
     ) => {
         #[allow(non_snake_case, unused)]
-        impl<A, F, M, $($ty,)* $last, Fut> MessageHandler<A, (private::Async, M, $($ty,)* $last,)> for F
+        impl<A, F, M, R, $($ty,)* $last, Fut> MessageHandler<A, (private::Async, R, M, $($ty,)* $last,)> for F
         where
             F: FnOnce($($ty,)* $last,) -> Fut + Send + Sync + Clone + 'static,
-            Fut: Future<Output = ()> + Send + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: MessageHandlerOutput,
             A: Adapter,
             $( $ty: FromMessageParts<A> + Send, )*
             $last: FromMessage<A, M> + Send,
@@ -260,6 +366,7 @@ macro_rules! impl_async_handler {
                         },
                     };
                 )*
+                let socket = s.clone();
                 let last = match $last::from_message(s, v, p, ack_id) {
                     Ok(v) => v,
                     Err(_e) => {
@@ -270,7 +377,7 @@ macro_rules! impl_async_handler {
                 };
 
                 let fut = (self.clone())($($ty,)* last);
-                tokio::spawn(fut);
+                spawn_async(socket, ack_id, fut);
             }
         }
     };
@@ -280,9 +387,10 @@ macro_rules! impl_handler {
         [$($ty:ident),*], $last:ident
     ) => {
         #[allow(non_snake_case, unused)]
-        impl<A, F, M, $($ty,)* $last> MessageHandler<A, (private::Sync, M, $($ty,)* $last,)> for F
+        impl<A, F, M, R, $($ty,)* $last> MessageHandler<A, (private::Sync, R, M, $($ty,)* $last,)> for F
         where
-            F: FnOnce($($ty,)* $last,) + Send + Sync + Clone + 'static,
+            F: FnOnce($($ty,)* $last,) -> R + Send + Sync + Clone + 'static,
+            R: MessageHandlerOutput,
             A: Adapter,
             $( $ty: FromMessageParts<A> + Send, )*
             $last: FromMessage<A, M> + Send,
@@ -294,12 +402,13 @@ macro_rules! impl_handler {
                         Err(_) => return,
                     };
                 )*
+                let socket = s.clone();
                 let last = match $last::from_message(s, v, p, ack_id) {
                     Ok(v) => v,
                     Err(_) => return,
                 };
 
-                (self.clone())($($ty,)* last);
+                call_sync(&socket, ack_id, || (self.clone())($($ty,)* last));
             }
         }
     };
@@ -6,12 +6,14 @@ pub mod disconnect;
 pub mod extract;
 pub mod message;
 
-pub(crate) use connect::BoxedConnectHandler;
-pub use connect::{ConnectHandler, FromConnectParts};
+pub(crate) use connect::{BoxedConnectHandler, BoxedConnectMiddleware, ConnectErrorHandler};
+pub use connect::{
+    ConnectErrorKind, ConnectFailure, ConnectHandler, ConnectMiddleware, FromConnectParts,
+};
 pub(crate) use disconnect::BoxedDisconnectHandler;
 pub use disconnect::{DisconnectHandler, FromDisconnectParts};
-pub(crate) use message::BoxedMessageHandler;
-pub use message::{FromMessage, FromMessageParts, MessageHandler};
+pub(crate) use message::{BoxedEventMiddleware, BoxedMessageHandler};
+pub use message::{FromMessage, FromMessageParts, MessageHandler, MessageHandlerOutput};
 /// A struct used to erase the type of a [`ConnectHandler`] or [`MessageHandler`] so it can be stored in a map
 pub(crate) struct MakeErasedHandler<H, A, T> {
     handler: H,
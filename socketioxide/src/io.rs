@@ -1,24 +1,35 @@
-use std::{borrow::Cow, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use engineioxide::{
-    config::{EngineIoConfig, EngineIoConfigBuilder},
+    config::{
+        CircuitBreaker, EngineIoConfig, EngineIoConfigBuilder, HandshakeLimit, OverflowPolicy,
+    },
     service::NotFoundService,
-    sid::Sid,
+    sid::{Sid, SidGenerator},
     TransportType,
 };
 use futures::stream::BoxStream;
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::{
-    adapter::{Adapter, LocalAdapter},
+    adapter::{AckStream, Adapter, LocalAdapter, Room, RoomEvent, RoomsSnapshot},
     client::Client,
     extract::SocketRef,
-    handler::ConnectHandler,
+    handler::{ConnectFailure, ConnectHandler, ConnectMiddleware},
     layer::SocketIoLayer,
-    operators::{Operators, RoomParam},
+    lifecycle::LifecycleEvent,
+    ns::Namespace,
+    operators::{ExceptParam, MultiOperators, Operators, RoomParam, SocketsOperators},
+    parser::{DefaultParser, Parser},
     service::SocketIoService,
-    socket::AckResponse,
-    AckError, BroadcastError,
+    session::SessionStore,
+    BroadcastError, NsPatternError,
 };
 
 /// Configuration for Socket.IO & Engine.IO
@@ -36,6 +47,71 @@ pub struct SocketIoConfig {
     ///
     /// Defaults to 45 seconds.
     pub connect_timeout: Duration,
+
+    /// The maximum number of binary attachments a client can declare for a single binary event or ack.
+    /// Packets declaring more attachments are dropped.
+    ///
+    /// Defaults to 32 attachments.
+    pub max_binary_attachments: usize,
+
+    /// The amount of time the server will wait for all the binary attachments of an event or ack.
+    /// After this delay, the partially received packet is dropped.
+    ///
+    /// Defaults to 10 seconds.
+    pub binary_reassembly_timeout: Duration,
+
+    /// The store used to recover the sessions of the abruptly disconnected sockets.
+    /// See the [`session`](crate::session) module for more details.
+    ///
+    /// Defaults to `None`, sessions are not recovered.
+    pub session_store: Option<Arc<dyn SessionStore>>,
+
+    /// The parser negotiated with the clients sending binary encoded packets.
+    /// See the [`parser`](crate::parser) module for more details.
+    ///
+    /// Defaults to the [`DefaultParser`].
+    pub parser: Arc<dyn Parser>,
+
+    /// The amount of time an abruptly disconnected socket is kept alive when its session is saved
+    /// in the [`SessionStore`]. If its client reconnects with the session within this period, the new socket
+    /// takes over the previous one: its disconnect handler is never called and its extensions are moved to the new socket.
+    /// Otherwise the previous socket is torn down once the period elapses.
+    ///
+    /// Defaults to `None`, sockets are torn down immediately.
+    pub disconnect_grace_period: Option<Duration>,
+
+    /// The limit applied to the events received by each socket.
+    ///
+    /// Defaults to `None`, events are not limited.
+    pub rate_limit: Option<RateLimit>,
+
+    /// The configuration used to resolve the ip address of the clients with the [`ClientIp`](crate::extract::ClientIp) extractor.
+    ///
+    /// Defaults to the peer address, the proxy headers are not read.
+    pub client_ip: ClientIpConfig,
+
+    /// What to do when a client sends a malformed packet.
+    ///
+    /// Defaults to [`ParseErrorPolicy::Disconnect`].
+    pub parse_error_policy: ParseErrorPolicy,
+
+    /// Whether the events received without a registered handler are rejected.
+    /// A rejected event is answered with an error ack if the client requested one, logged
+    /// and counted in the [`Metrics::rejected_events`].
+    ///
+    /// Defaults to `false`, these events are silently dropped.
+    pub reject_unknown_events: bool,
+
+    /// The maximum size in bytes of a received event once decoded and reassembled with its binary attachments:
+    /// the size of its JSON data plus the size of its attachments.
+    /// Unlike the [`max_payload`](EngineIoConfig::max_payload), it applies to a single event,
+    /// whatever the number of frames or polling payloads it was split into.
+    ///
+    /// The events exceeding it are dropped before their handler is called.
+    /// If the client requested an ack, it receives `{ "message": "event too large" }` as ack response.
+    ///
+    /// Defaults to `None`, the events are only limited by the `max_payload`.
+    pub max_event_size: Option<usize>,
 }
 
 impl Default for SocketIoConfig {
@@ -47,6 +123,16 @@ impl Default for SocketIoConfig {
             },
             ack_timeout: Duration::from_secs(5),
             connect_timeout: Duration::from_secs(45),
+            max_binary_attachments: 32,
+            binary_reassembly_timeout: Duration::from_secs(10),
+            session_store: None,
+            parser: Arc::new(DefaultParser),
+            disconnect_grace_period: None,
+            rate_limit: None,
+            client_ip: ClientIpConfig::default(),
+            parse_error_policy: ParseErrorPolicy::default(),
+            reject_unknown_events: false,
+            max_event_size: None,
         }
     }
 }
@@ -72,6 +158,10 @@ impl<A: Adapter> SocketIoBuilder<A> {
 
     /// The path to listen for socket.io requests on.
     ///
+    /// Only this path and its sub-paths are handled, the other requests are forwarded to the inner service.
+    /// Several [`SocketIo`] instances can therefore be mounted on the same server with distinct paths,
+    /// such as `/chat` and `/chat-admin`, each one with its own namespaces and sockets.
+    ///
     /// Defaults to "/socket.io".
     #[inline]
     pub fn req_path(mut self, req_path: impl Into<Cow<'static, str>>) -> Self {
@@ -107,8 +197,22 @@ impl<A: Adapter> SocketIoBuilder<A> {
         self
     }
 
+    /// What to do when a packet is emitted to a socket whose buffer is full,
+    /// because its client does not receive the packets fast enough.
+    ///
+    /// Defaults to [`OverflowPolicy::Error`]: the `emit()` method returns a [`SendError::InternalChannelFull`](crate::SendError::InternalChannelFull) error.
+    ///
+    /// The socket.io emits never wait for the buffer to have room,
+    /// so with [`OverflowPolicy::Block`] they return an error as with [`OverflowPolicy::Error`].
+    #[inline]
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.engine_config_builder = self.engine_config_builder.overflow_policy(overflow_policy);
+        self
+    }
+
     /// The maximum size of a payload in bytes.
     /// If a payload is bigger than this value the `emit()` method will return an error.
+    /// A websocket frame or message received bigger than this value closes the connection.
     ///
     /// Defaults to 100 kb.
     #[inline]
@@ -117,9 +221,57 @@ impl<A: Adapter> SocketIoBuilder<A> {
         self
     }
 
+    /// The limit applied to the handshakes opening new sessions, to protect the server from a storm of reconnections.
+    /// See [`HandshakeLimit`] for more details.
+    ///
+    /// Defaults to `None`: the handshakes are never limited.
+    #[inline]
+    pub fn handshake_limit(mut self, handshake_limit: HandshakeLimit) -> Self {
+        self.engine_config_builder = self.engine_config_builder.handshake_limit(handshake_limit);
+        self
+    }
+
+    /// The circuit breaker disconnecting the unresponsive clients before the ping timeout,
+    /// with the [`DisconnectReason::UnresponsiveClient`](crate::socket::DisconnectReason::UnresponsiveClient) reason.
+    /// See [`CircuitBreaker`] for more details.
+    ///
+    /// Defaults to `None`: a client is only disconnected once the ping timeout elapses.
+    #[inline]
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.engine_config_builder = self.engine_config_builder.circuit_breaker(circuit_breaker);
+        self
+    }
+
+    /// A hook called with the request and the headers of each handshake response before it is sent,
+    /// to add custom headers or cookies, e.g. a CSRF token.
+    ///
+    /// It is called for the polling and websocket handshakes, and for the response upgrading a polling session to websocket.
+    /// The cookies should be added with [`HeaderMap::append`](http::HeaderMap::append) to keep the ones set by the server.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::SocketIo;
+    /// # use http::{header::SET_COOKIE, HeaderValue};
+    /// let (_, io) = SocketIo::builder()
+    ///     .handshake_response_hook(|_req, headers| {
+    ///         headers.append(SET_COOKIE, HeaderValue::from_static("csrf=abc; HttpOnly"));
+    ///     })
+    ///     .build_svc();
+    /// ```
+    #[inline]
+    pub fn handshake_response_hook(
+        mut self,
+        hook: impl Fn(&http::request::Parts, &mut http::HeaderMap) + Send + Sync + 'static,
+    ) -> Self {
+        self.engine_config_builder = self.engine_config_builder.handshake_response_hook(hook);
+        self
+    }
+
     /// Allowed transports on this server
     ///
-    /// The `transports` array should have a size of 1 or 2
+    /// The `transports` array should have a size of 1 to 3.
+    /// [`TransportType::WebTransport`] must be allowed explicitly, the sessions are then served with
+    /// `SocketIoService::on_webtransport` and the `webtransport` feature.
     ///
     /// Defaults to :
     /// `[TransportType::Polling, TransportType::Websocket]`
@@ -129,6 +281,54 @@ impl<A: Adapter> SocketIoBuilder<A> {
         self
     }
 
+    /// The amount of time a polling request will keep waiting for new packets
+    /// after receiving its first one, in order to batch them in the same payload.
+    ///
+    /// Defaults to `None`: the payload is sent as soon as there is a packet to send.
+    #[inline]
+    pub fn flush_timeout(mut self, flush_timeout: Duration) -> Self {
+        self.engine_config_builder = self.engine_config_builder.flush_timeout(flush_timeout);
+        self
+    }
+
+    /// If enabled, the polling payloads are streamed packet by packet in a chunked response,
+    /// rather than being encoded in memory before being sent.
+    ///
+    /// It only applies to the engine.io v4 protocol and to the payloads that are not compressed.
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn stream_polling_payloads(mut self, stream_polling_payloads: bool) -> Self {
+        self.engine_config_builder = self
+            .engine_config_builder
+            .stream_polling_payloads(stream_polling_payloads);
+        self
+    }
+
+    /// The number of packets a polling request drains from the buffer before yielding back to the runtime,
+    /// so a flooded socket cannot starve the other tasks.
+    ///
+    /// A value of 0 disables yielding.
+    ///
+    /// Defaults to 32 packets.
+    #[inline]
+    pub fn encoder_yield_interval(mut self, encoder_yield_interval: usize) -> Self {
+        self.engine_config_builder = self
+            .engine_config_builder
+            .encoder_yield_interval(encoder_yield_interval);
+        self
+    }
+
+    /// The generator of the session ids, which are also the ids of the sockets.
+    ///
+    /// See [`SidGenerator`] for the requirements of the generated ids.
+    /// Defaults to random ids.
+    #[inline]
+    pub fn sid_generator(mut self, generator: impl SidGenerator) -> Self {
+        self.engine_config_builder = self.engine_config_builder.sid_generator(generator);
+        self
+    }
+
     /// The amount of time the server will wait for an acknowledgement from the client before closing the connection.
     ///
     /// Defaults to 5 seconds.
@@ -147,6 +347,109 @@ impl<A: Adapter> SocketIoBuilder<A> {
         self
     }
 
+    /// The maximum number of binary attachments a client can declare for a single binary event or ack.
+    /// Packets declaring more attachments are dropped.
+    ///
+    /// Defaults to 32 attachments.
+    #[inline]
+    pub fn max_binary_attachments(mut self, max_binary_attachments: usize) -> Self {
+        self.config.max_binary_attachments = max_binary_attachments;
+        self
+    }
+
+    /// The amount of time the server will wait for all the binary attachments of an event or ack.
+    /// After this delay, the partially received packet is dropped.
+    ///
+    /// Defaults to 10 seconds.
+    #[inline]
+    pub fn binary_reassembly_timeout(mut self, binary_reassembly_timeout: Duration) -> Self {
+        self.config.binary_reassembly_timeout = binary_reassembly_timeout;
+        self
+    }
+
+    /// Enables the connection state recovery with the given [`SessionStore`],
+    /// such as the default [`MemorySessionStore`](crate::session::MemorySessionStore).
+    ///
+    /// See the [`session`](crate::session) module for more details.
+    #[inline]
+    pub fn with_session_store(mut self, store: impl SessionStore) -> Self {
+        self.config.session_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Limits the events received by each socket with the given [`RateLimit`].
+    /// The events exceeding the limit are handled according to its [`RateLimitPolicy`].
+    ///
+    /// Defaults to `None`, events are not limited.
+    #[inline]
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.config.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Sets the [`ClientIpConfig`] used to resolve the ip address of the clients behind reverse proxies
+    /// with the [`ClientIp`](crate::extract::ClientIp) extractor.
+    ///
+    /// Defaults to the peer address, the proxy headers are not read.
+    #[inline]
+    pub fn client_ip(mut self, client_ip: ClientIpConfig) -> Self {
+        self.config.client_ip = client_ip;
+        self
+    }
+
+    /// Sets what to do when a client sends a malformed packet, see [`ParseErrorPolicy`].
+    ///
+    /// Defaults to [`ParseErrorPolicy::Disconnect`].
+    #[inline]
+    pub fn parse_error_policy(mut self, policy: ParseErrorPolicy) -> Self {
+        self.config.parse_error_policy = policy;
+        self
+    }
+
+    /// Rejects the events received without a registered handler instead of silently dropping them.
+    ///
+    /// If the client requested an ack, it receives `{ "message": "unknown event" }` as ack response.
+    /// The rejected events are logged and counted in the [`Metrics::rejected_events`].
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn reject_unknown_events(mut self, reject: bool) -> Self {
+        self.config.reject_unknown_events = reject;
+        self
+    }
+
+    /// The maximum size in bytes of a received event once decoded and reassembled with its binary attachments.
+    /// The events exceeding it are dropped, with an error ack if the client requested one.
+    /// See [`SocketIoConfig::max_event_size`] for more details.
+    ///
+    /// Defaults to `None`, the events are only limited by the [`max_payload`](Self::max_payload).
+    #[inline]
+    pub fn max_event_size(mut self, max_event_size: usize) -> Self {
+        self.config.max_event_size = Some(max_event_size);
+        self
+    }
+
+    /// Sets the amount of time an abruptly disconnected socket is kept alive to let its client reconnect
+    /// and take it over with its session. It requires a session store set with [`SocketIoBuilder::with_session_store`].
+    ///
+    /// Defaults to `None`, sockets are torn down immediately.
+    #[inline]
+    pub fn disconnect_grace_period(mut self, disconnect_grace_period: Duration) -> Self {
+        self.config.disconnect_grace_period = Some(disconnect_grace_period);
+        self
+    }
+
+    /// Sets the [`Parser`] negotiated with the clients sending binary encoded packets,
+    /// such as the `MsgPackParser` of the `msgpack` feature flag.
+    /// The other clients keep using the [`DefaultParser`].
+    ///
+    /// See the [`parser`](crate::parser) module for more details.
+    #[inline]
+    pub fn with_parser(mut self, parser: impl Parser) -> Self {
+        self.config.parser = Arc::new(parser);
+        self
+    }
+
     /// Sets a custom [`SocketIoConfig`] created previously for this [`SocketIoBuilder`]
     #[inline]
     pub fn with_config(mut self, config: SocketIoConfig) -> Self {
@@ -188,7 +491,7 @@ impl<A: Adapter> SocketIoBuilder<A> {
     ///
     /// This service will be a _standalone_ service that return a 404 error for every non-socket.io request
     /// It can be used as a hyper service
-    pub fn build_svc(mut self) -> (SocketIoService<NotFoundService>, SocketIo) {
+    pub fn build_svc(mut self) -> (SocketIoService<NotFoundService, A>, SocketIo<A>) {
         self.config.engine_config = self.engine_config_builder.build();
 
         let (svc, client) =
@@ -199,7 +502,10 @@ impl<A: Adapter> SocketIoBuilder<A> {
     /// Builds a [`SocketIoService`] and a [`SocketIo`] instance with an inner service
     ///
     /// It can be used as a hyper service
-    pub fn build_with_inner_svc<S: Clone>(mut self, svc: S) -> (SocketIoService<S>, SocketIo) {
+    pub fn build_with_inner_svc<S: Clone>(
+        mut self,
+        svc: S,
+    ) -> (SocketIoService<S, A>, SocketIo<A>) {
         self.config.engine_config = self.engine_config_builder.build();
 
         let (svc, client) = SocketIoService::with_config_inner(svc, Arc::new(self.config));
@@ -216,7 +522,7 @@ impl Default for SocketIoBuilder {
 /// The [`SocketIo`] instance can be cheaply cloned and moved around everywhere in your program.
 /// It can be used as the main handle to access the whole socket.io context.
 #[derive(Debug)]
-pub struct SocketIo<A: Adapter = LocalAdapter>(Arc<Client<A>>);
+pub struct SocketIo<A: Adapter = LocalAdapter>(pub(crate) Arc<Client<A>>);
 
 impl SocketIo<LocalAdapter> {
     /// Creates a new [`SocketIoBuilder`] with a default config
@@ -334,13 +640,67 @@ impl<A: Adapter> SocketIo<A> {
     /// });
     ///
     /// ```
+    ///
+    /// The returned [`NsHandle`] can be used to add [`ConnectMiddleware`]s to the namespace.
     #[inline]
-    pub fn ns<C, T>(&self, path: impl Into<Cow<'static, str>>, callback: C)
+    pub fn ns<C, T>(&self, path: impl Into<Cow<'static, str>>, callback: C) -> NsHandle<A>
     where
         C: ConnectHandler<A, T>,
         T: Send + Sync + 'static,
     {
-        self.0.add_ns(path.into(), callback);
+        NsHandle(self.0.add_ns(path.into(), callback))
+    }
+
+    /// ### Registers a [`ConnectHandler`] for all the namespaces matching the given pattern.
+    ///
+    /// A pattern is made of static parts and `{name}` parameters matching any non empty string without `/`.
+    /// The parameters can be extracted in the handlers with the [`NsParams`](crate::extract::NsParams) extractor.
+    ///
+    /// Namespaces registered with [`SocketIo::ns`] are always preferred over dynamic ones.
+    /// If several patterns match a namespace, the first registered one is used.
+    ///
+    /// ## Errors
+    /// If the pattern is invalid, a [`NsPatternError`] is returned.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.dyn_ns("/chat-{room}", |socket: SocketRef, NsParams(params): NsParams| {
+    ///     println!("Socket connected to the chat room {}", params["room"]);
+    /// })
+    /// .unwrap();
+    /// ```
+    #[inline]
+    pub fn dyn_ns<C, T>(&self, pattern: &str, callback: C) -> Result<(), NsPatternError>
+    where
+        C: ConnectHandler<A, T>,
+        T: Send + Sync + 'static,
+    {
+        self.0.add_dyn_ns(pattern, callback)
+    }
+
+    /// Sets the handler called each time a client fails to connect to a namespace:
+    /// the namespace does not exist, the connection is rejected by a middleware or an extractor,
+    /// or the server is shutting down.
+    ///
+    /// The handler receives a [`ConnectFailure`] with the [`ConnectErrorKind`](crate::handler::ConnectErrorKind) and the request of the client,
+    /// it can be used to log or detect attack patterns. The connect error packet is still sent to the client.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, handler::ConnectErrorKind};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.on_connect_error(|failure| {
+    ///     if let ConnectErrorKind::Middleware(message) = &failure.kind {
+    ///         let ua = failure.req_parts.headers.get("user-agent");
+    ///         println!("connection to {} rejected: {message} {ua:?}", failure.ns);
+    ///     }
+    /// });
+    /// ```
+    #[inline]
+    pub fn on_connect_error(&self, handler: impl Fn(&ConnectFailure<'_>) + Send + Sync + 'static) {
+        self.0.connect_error_handler.set(handler);
     }
 
     /// Deletes the namespace with the given path
@@ -357,6 +717,47 @@ impl<A: Adapter> SocketIo<A> {
         self.0.close().await;
     }
 
+    /// ### Gracefully shuts down the server while draining the pending acknowledgements.
+    ///
+    /// * New connections to namespaces are refused with a connect error packet.
+    /// * A disconnect packet is sent to every socket.
+    /// * The acknowledgements awaited at that time are given at most `timeout` to resolve.
+    /// * All the connections are closed like with [`SocketIo::close`].
+    ///
+    /// The returned [`ShutdownSummary`] tells how many acknowledgements resolved in time
+    /// and how many were abandoned.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::SocketIo;
+    /// # use std::time::Duration;
+    /// async fn stop(io: SocketIo) {
+    ///     let summary = io.shutdown(Duration::from_secs(5)).await;
+    ///     println!("{} acks abandoned", summary.abandoned_acks);
+    /// }
+    /// ```
+    #[inline]
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownSummary {
+        self.0.shutdown(timeout).await
+    }
+
+    /// Returns a snapshot of the [`Metrics`] of the server, computed from the namespaces and their adapters.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::SocketIo;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// let metrics = io.metrics().await.unwrap();
+    /// println!("{} sockets connected", metrics.sockets);
+    /// # }
+    /// ```
+    #[inline]
+    pub async fn metrics(&self) -> Result<Metrics, A::Error> {
+        self.0.metrics().await
+    }
+
     // Chaining operators fns
 
     /// Selects a specific namespace to perform operations on
@@ -364,6 +765,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("custom_ns", |socket: SocketRef| {
     ///     println!("Socket connected on /custom_ns namespace with id: {}", socket.id);
@@ -371,15 +774,108 @@ impl<A: Adapter> SocketIo<A> {
     ///
     /// // Later in your code you can select the custom_ns namespace
     /// // and show all sockets connected to it
-    /// let sockets = io.of("custom_ns").unwrap().sockets().unwrap();
+    /// let sockets = io.of("custom_ns").unwrap().sockets().await.unwrap();
     /// for socket in sockets {
     ///    println!("found socket on /custom_ns namespace with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
     pub fn of<'a>(&self, path: impl Into<&'a str>) -> Option<Operators<A>> {
         self.get_op(path.into())
     }
 
+    /// Selects several namespaces to emit the same message to their sockets in one call.
+    ///
+    /// The namespaces that are not found are ignored.
+    /// See [`MultiOperators`] for the handling of the clients connected to several of them.
+    ///
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/a", || {});
+    /// io.ns("/b", || {});
+    ///
+    /// io.of_many(["/a", "/b"]).emit("event", "hello").await.unwrap();
+    /// # }
+    /// ```
+    pub fn of_many<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> MultiOperators<A> {
+        MultiOperators::new(paths.into_iter().filter_map(|p| self.get_op(p)).collect())
+    }
+
+    /// Returns a stream of the room membership changes of the given namespace, starting from now.
+    ///
+    /// It yields a [`RoomEvent`] each time a socket joins or leaves a room,
+    /// including the leaves caused by a disconnection.
+    /// It can be used to track the presence of the sockets in the rooms from a single place.
+    ///
+    /// If the namespace is not found, it returns `None`.
+    /// With adapters that don't track room changes, the stream is empty.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, adapter::RoomEvent, extract::SocketRef};
+    /// # use futures::stream::StreamExt;
+    /// async fn track_presence(io: SocketIo) {
+    ///     let mut events = io.room_events("/").unwrap();
+    ///     while let Some(event) = events.next().await {
+    ///         match event {
+    ///             RoomEvent::Join { sid, room } => println!("{sid} joined {room}"),
+    ///             RoomEvent::Leave { sid, room } => println!("{sid} left {room}"),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| async move {
+    ///     socket.join("room1").await.ok();
+    /// });
+    /// ```
+    pub fn room_events<'a>(
+        &self,
+        path: impl Into<&'a str>,
+    ) -> Option<BoxStream<'static, RoomEvent>> {
+        self.0
+            .get_ns(path.into())
+            .map(|ns| ns.adapter.room_events())
+    }
+
+    /// Returns a snapshot of the rooms of the given namespace with the ids of their sockets,
+    /// taken at once from the adapter with [`Adapter::rooms`].
+    ///
+    /// If the namespace is not found, it returns `None`.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// async fn print_rooms(io: SocketIo) {
+    ///     let rooms = io.rooms("/").await.unwrap().unwrap();
+    ///     for (room, sids) in rooms {
+    ///         println!("{room}: {} sockets", sids.len());
+    ///     }
+    /// }
+    /// ```
+    pub async fn rooms<'a>(
+        &self,
+        path: impl Into<&'a str>,
+    ) -> Option<Result<RoomsSnapshot, A::Error>> {
+        let ns = self.0.get_ns(path.into())?;
+        Some(ns.adapter.rooms().await)
+    }
+
+    /// Returns a stream of the connection lifecycle events of the sockets of all the namespaces, starting from now.
+    ///
+    /// It yields a [`LifecycleEvent`] when a socket connects to or disconnects from a namespace,
+    /// and when its underlying connection is upgraded to websocket.
+    ///
+    /// Each stream buffers a bounded number of events, the oldest ones are dropped
+    /// when the stream lags behind. See the [`lifecycle`](crate::lifecycle) module for more details.
+    pub fn lifecycle_events(&self) -> BoxStream<'static, LifecycleEvent> {
+        self.0.lifecycle.subscribe()
+    }
+
     /// Selects all sockets in the given rooms on the root namespace.
     ///
     /// Alias for `io.of("/").unwrap().to(rooms)`
@@ -390,6 +886,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -397,15 +895,42 @@ impl<A: Adapter> SocketIo<A> {
     ///
     /// // Later in your code you can select all sockets in the room "room1"
     /// // and for example show all sockets connected to it
-    /// let sockets = io.to("room1").sockets().unwrap();
+    /// let sockets = io.to("room1").sockets().await.unwrap();
     /// for socket in sockets {
     ///   println!("found socket on / ns in room1 with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
     pub fn to(&self, rooms: impl RoomParam) -> Operators<A> {
         self.get_default_op().to(rooms)
     }
 
+    /// Selects the sockets with the given ids on the root namespace.
+    ///
+    /// Unlike a broadcast, the message is serialized once and the encoded packet
+    /// is directly enqueued to each of the sockets, see [`SocketsOperators`].
+    ///
+    /// ## Panics
+    /// If the **default namespace "/" is not found** this fn will panic!
+    ///
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", || {});
+    ///
+    /// // Later in your code you can emit to a list of known sockets
+    /// let sids = io.sockets().await.unwrap().iter().map(|s| s.id).collect::<Vec<_>>();
+    /// io.to_sockets(sids).emit("hello", "world").unwrap();
+    /// # }
+    /// ```
+    pub fn to_sockets(&self, sids: impl IntoIterator<Item = Sid>) -> SocketsOperators<A> {
+        let ns = self.0.get_ns("/").expect("default namespace not found");
+        SocketsOperators::new(ns, sids)
+    }
+
     /// Selects all sockets in the given rooms on the root namespace.
     ///
     /// Alias for :
@@ -418,6 +943,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -425,10 +952,11 @@ impl<A: Adapter> SocketIo<A> {
     ///
     /// // Later in your code you can select all sockets in the room "room1"
     /// // and for example show all sockets connected to it
-    /// let sockets = io.within("room1").sockets().unwrap();
+    /// let sockets = io.within("room1").sockets().await.unwrap();
     /// for socket in sockets {
     ///   println!("found socket on / ns in room1 with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
     pub fn within(&self, rooms: impl RoomParam) -> Operators<A> {
         self.get_default_op().within(rooms)
@@ -444,26 +972,29 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
-    ///     socket.on("register1", |socket: SocketRef| {
-    ///         socket.join("room1");
+    ///     socket.on("register1", |socket: SocketRef| async move {
+    ///         socket.join("room1").await.ok();
     ///     });
-    ///     socket.on("register2", |socket: SocketRef| {
-    ///         socket.join("room2");
+    ///     socket.on("register2", |socket: SocketRef| async move {
+    ///         socket.join("room2").await.ok();
     ///     });
     /// });
     ///
     ///
     /// // Later in your code you can select all sockets in the root namespace that are not in the room1
     /// // and for example show all sockets connected to it
-    /// let sockets = io.except("room1").sockets().unwrap();
+    /// let sockets = io.except("room1").sockets().await.unwrap();
     /// for socket in sockets {
     ///   println!("found socket on / ns in room1 with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
-    pub fn except(&self, rooms: impl RoomParam) -> Operators<A> {
+    pub fn except(&self, rooms: impl ExceptParam) -> Operators<A> {
         self.get_default_op().except(rooms)
     }
 
@@ -478,6 +1009,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -485,10 +1018,11 @@ impl<A: Adapter> SocketIo<A> {
     ///
     /// // Later in your code you can select all sockets in the local node and on the root namespace
     /// // and for example show all sockets connected to it
-    /// let sockets = io.local().sockets().unwrap();
+    /// let sockets = io.local().sockets().await.unwrap();
     /// for socket in sockets {
     ///   println!("found socket on / ns in room1 with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
     pub fn local(&self) -> Operators<A> {
         self.get_default_op().local()
@@ -507,6 +1041,8 @@ impl<A: Adapter> SocketIo<A> {
     /// # use futures::stream::StreamExt;
     /// # use std::time::Duration;
     /// # use serde_json::Value;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -519,13 +1055,16 @@ impl<A: Adapter> SocketIo<A> {
     ///   .except("room2")
     ///   .timeout(Duration::from_secs(5))
     ///   .emit_with_ack::<Value>("message-back", "I expect an ack in 5s!")
+    ///   .await
     ///   .unwrap()
-    ///   .for_each(|ack| async move {
+    ///   .for_each(|(sid, ack)| async move {
     ///      match ack {
-    ///          Ok(ack) => println!("Ack received {:?}", ack),
-    ///          Err(err) => println!("Ack error {:?}", err),
+    ///          Ok(ack) => println!("Ack received from {sid}: {:?}", ack),
+    ///          Err(err) => println!("Ack error from {sid}: {:?}", err),
     ///      }
-    ///   });
+    ///   })
+    ///   .await;
+    /// # }
     #[inline]
     pub fn timeout(&self, timeout: Duration) -> Operators<A> {
         self.get_default_op().timeout(timeout)
@@ -542,6 +1081,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
     /// # use serde_json::Value;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -553,7 +1094,10 @@ impl<A: Adapter> SocketIo<A> {
     ///   .to("room3")
     ///   .except("room2")
     ///   .bin(vec![vec![1, 2, 3, 4]])
-    ///   .emit("test", ());
+    ///   .emit("test", ())
+    ///   .await
+    ///   .ok();
+    /// # }
     #[inline]
     pub fn bin(&self, binary: Vec<Vec<u8>>) -> Operators<A> {
         self.get_default_op().bin(binary)
@@ -561,6 +1105,8 @@ impl<A: Adapter> SocketIo<A> {
 
     /// Emits a message to all sockets selected with the previous operators.
     ///
+    /// Returns the number of sockets the message was enqueued to, which is not a delivery confirmation.
+    ///
     /// Alias for `io.of("/").unwrap().emit(event, data)`
     ///
     /// ## Panics
@@ -570,6 +1116,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
     /// # use serde_json::Value;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -580,14 +1128,59 @@ impl<A: Adapter> SocketIo<A> {
     /// io.to("room1")
     ///   .to("room3")
     ///   .except("room2")
-    ///   .emit("Hello World!", ());
+    ///   .emit("Hello World!", ())
+    ///   .await
+    ///   .ok();
+    /// # }
     #[inline]
-    pub fn emit(
+    pub async fn emit(
         &self,
         event: impl Into<Cow<'static, str>>,
         data: impl serde::Serialize,
-    ) -> Result<(), BroadcastError> {
-        self.get_default_op().emit(event, data)
+    ) -> Result<usize, BroadcastError> {
+        self.get_default_op().emit(event, data).await
+    }
+
+    /// Emits a message to all sockets and retains it for the sockets connecting to the namespace afterwards.
+    /// See [`Operators::emit_buffered`] for more details.
+    ///
+    /// Alias for `io.of("/").unwrap().emit_buffered(event, data)`
+    ///
+    /// ## Panics
+    /// If the **default namespace "/" is not found** this fn will panic!
+    ///
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     println!("Socket connected on / namespace with id: {}", socket.id);
+    /// });
+    ///
+    /// // Emitted on startup, the message is delivered to the sockets once they connect
+    /// io.emit_buffered("motd", "Welcome!").await.ok();
+    /// # }
+    /// ```
+    #[inline]
+    pub async fn emit_buffered(
+        &self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl serde::Serialize,
+    ) -> Result<usize, BroadcastError> {
+        self.get_default_op().emit_buffered(event, data).await
+    }
+
+    /// Removes the message retained for an event with [`emit_buffered`](Self::emit_buffered).
+    ///
+    /// Alias for `io.of("/").unwrap().clear_retained(event)`
+    ///
+    /// ## Panics
+    /// If the **default namespace "/" is not found** this fn will panic!
+    #[inline]
+    pub fn clear_retained(&self, event: &str) -> bool {
+        self.get_default_op().clear_retained(event)
     }
 
     /// Emits a message to all sockets selected with the previous operators and return a stream of acknowledgements.
@@ -604,6 +1197,8 @@ impl<A: Adapter> SocketIo<A> {
     /// # use socketioxide::{SocketIo, extract::SocketRef};
     /// # use futures::stream::StreamExt;
     /// # use serde_json::Value;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -614,19 +1209,20 @@ impl<A: Adapter> SocketIo<A> {
     /// io.to("room1")
     ///   .to("room3")
     ///   .except("room2")
-    ///   .emit_with_ack::<Value>("message-back", "I expect an ack!").unwrap().for_each(|ack| async move {
+    ///   .emit_with_ack::<Value>("message-back", "I expect an ack!").await.unwrap().for_each(|(sid, ack)| async move {
     ///      match ack {
-    ///          Ok(ack) => println!("Ack received {:?}", ack),
-    ///          Err(err) => println!("Ack error {:?}", err),
+    ///          Ok(ack) => println!("Ack received from {sid}: {:?}", ack),
+    ///          Err(err) => println!("Ack error from {sid}: {:?}", err),
     ///      }
-    ///   });
+    ///   }).await;
+    /// # }
     #[inline]
-    pub fn emit_with_ack<V: DeserializeOwned + Send>(
+    pub async fn emit_with_ack<V: DeserializeOwned + Send + 'static>(
         &self,
         event: impl Into<Cow<'static, str>>,
         data: impl serde::Serialize,
-    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
-        self.get_default_op().emit_with_ack(event, data)
+    ) -> Result<AckStream<V>, BroadcastError> {
+        self.get_default_op().emit_with_ack(event, data).await
     }
 
     /// Gets all sockets selected with the previous operators.
@@ -642,6 +1238,8 @@ impl<A: Adapter> SocketIo<A> {
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
     /// # use serde_json::Value;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
@@ -649,13 +1247,14 @@ impl<A: Adapter> SocketIo<A> {
     ///
     /// // Later in your code you can select all sockets in the room "room1"
     /// // and for example show all sockets connected to it
-    /// let sockets = io.within("room1").sockets().unwrap();
+    /// let sockets = io.within("room1").sockets().await.unwrap();
     /// for socket in sockets {
     ///   println!("found socket on / ns in room1 with id: {}", socket.id);
     /// }
+    /// # }
     #[inline]
-    pub fn sockets(&self) -> Result<Vec<SocketRef<A>>, A::Error> {
-        self.get_default_op().sockets()
+    pub async fn sockets(&self) -> Result<Vec<SocketRef<A>>, A::Error> {
+        self.get_default_op().sockets().await
     }
 
     /// Disconnects all sockets selected with the previous operators.
@@ -668,16 +1267,19 @@ impl<A: Adapter> SocketIo<A> {
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
     /// });
     ///
     /// // Later in your code you can disconnect all sockets in the root namespace
-    /// io.disconnect();
+    /// io.disconnect().await.ok();
+    /// # }
     #[inline]
-    pub fn disconnect(&self) -> Result<(), BroadcastError> {
-        self.get_default_op().disconnect()
+    pub async fn disconnect(&self) -> Result<(), BroadcastError> {
+        self.get_default_op().disconnect().await
     }
 
     /// Makes all sockets selected with the previous operators join the given room(s).
@@ -690,16 +1292,19 @@ impl<A: Adapter> SocketIo<A> {
     /// ### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
     /// });
     ///
     /// // Later in your code you can for example add all sockets on the root namespace to the room1 and room3
-    /// io.join(["room1", "room3"]).unwrap();
+    /// io.join(["room1", "room3"]).await.unwrap();
+    /// # }
     #[inline]
-    pub fn join(self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.get_default_op().join(rooms)
+    pub async fn join(self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        self.get_default_op().join(rooms).await
     }
 
     /// Makes all sockets selected with the previous operators leave the given room(s).
@@ -712,22 +1317,46 @@ impl<A: Adapter> SocketIo<A> {
     /// ### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::SocketRef};
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     println!("Socket connected on / namespace with id: {}", socket.id);
     /// });
     ///
     /// // Later in your code you can for example remove all sockets on the root namespace from the room1 and room3
-    /// io.leave(["room1", "room3"]).unwrap();
+    /// io.leave(["room1", "room3"]).await.unwrap();
+    /// # }
     #[inline]
-    pub fn leave(self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.get_default_op().leave(rooms)
+    pub async fn leave(self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        self.get_default_op().leave(rooms).await
     }
 
-    /// Gets a [`SocketRef`] by the specified [`Sid`].
-    #[inline]
+    /// Gets a [`SocketRef`] by the specified [`Sid`], looking it up across all the namespaces.
+    ///
+    /// A client connected to several namespaces has a socket with the same [`Sid`] in each of them:
+    /// the socket of the root namespace `/` is returned first. Use [`SocketIo::of`] and
+    /// [`Operators::get_socket`] to get the socket of a specific namespace.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*, socket::Sid};
+    /// # use std::sync::{Arc, Mutex};
+    /// let (_, io) = SocketIo::new_svc();
+    /// let uploader = Arc::new(Mutex::new(None::<Sid>));
+    /// let uploader_ = uploader.clone();
+    /// io.ns("/upload", move |socket: SocketRef| {
+    ///     *uploader_.lock().unwrap() = Some(socket.id);
+    /// });
+    ///
+    /// // Later, for example once the upload is processed, notify the socket that started it
+    /// let sid = *uploader.lock().unwrap();
+    /// if let Some(socket) = sid.and_then(|sid| io.get_socket(sid)) {
+    ///     socket.emit("upload_done", ()).ok();
+    /// }
+    /// ```
     pub fn get_socket(&self, sid: Sid) -> Option<SocketRef<A>> {
-        self.get_default_op().get_socket(sid)
+        self.0.get_socket(sid).map(SocketRef::new)
     }
 
     /// Returns a new operator on the given namespace
@@ -755,6 +1384,462 @@ impl<A: Adapter> Clone for SocketIo<A> {
     }
 }
 
+/// The limit applied to the events received by each socket, enforced with a token bucket:
+/// a socket can send `burst` events at once, then `events_per_second` events per second.
+///
+/// Only the event packets are counted, the acknowledgements and the connect packets are never limited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The number of events a socket can send per second once its burst is consumed
+    pub events_per_second: f64,
+    /// The maximum number of events a socket can send at once
+    pub burst: u32,
+    /// The policy applied to the events exceeding the limit
+    pub policy: RateLimitPolicy,
+}
+
+impl RateLimit {
+    /// Creates a new [`RateLimit`] dropping the events exceeding the limit
+    pub fn new(events_per_second: f64, burst: u32) -> Self {
+        Self {
+            events_per_second,
+            burst,
+            policy: RateLimitPolicy::default(),
+        }
+    }
+
+    /// Sets the policy applied to the events exceeding the limit
+    pub fn policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+/// The policy applied to the events exceeding the [`RateLimit`] of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitPolicy {
+    /// The event is dropped, its handler is not called
+    #[default]
+    Drop,
+    /// The event is dropped and the socket is disconnected from the namespace with the
+    /// [`DisconnectReason::RateLimitExceeded`](crate::socket::DisconnectReason::RateLimitExceeded) reason
+    Disconnect,
+}
+
+/// The policy applied when a client sends a packet that cannot be decoded.
+///
+/// The reason is reported with a [`ParseError`](crate::ParseError) in the `tracing` logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseErrorPolicy {
+    /// The whole connection of the client is closed,
+    /// its sockets are disconnected with the [`DisconnectReason::PacketParsingError`](crate::socket::DisconnectReason::PacketParsingError) reason
+    #[default]
+    Disconnect,
+    /// The packet is dropped and the connection is kept open
+    Ignore,
+}
+
+/// The configuration used to resolve the ip address of a client with the [`ClientIp`](crate::extract::ClientIp) extractor.
+///
+/// The peer address is read from the [`SocketAddr`] extension of the request made to open the session.
+/// It is not inserted by the http servers by default: it should be inserted in front of the [`SocketIoService`]
+/// or the [`SocketIoLayer`](crate::layer::SocketIoLayer), for example with the address of each accepted connection
+/// in a hyper `service_fn`. With axum, the address provided as a `ConnectInfo<SocketAddr>` extension
+/// should be copied as a bare [`SocketAddr`] by a middleware, see the example below.
+///
+/// If a header is set and the peer is one of the trusted proxies, the header is read from right to left
+/// and the first address that is not a trusted proxy is returned. It supports comma separated lists
+/// such as `x-forwarded-for` and single values such as `x-real-ip`. The header is ignored if the peer is not trusted,
+/// so that the clients connecting directly cannot spoof their address.
+///
+/// #### Example
+/// ```
+/// # use socketioxide::{SocketIo, ClientIpConfig};
+/// let (_, io) = SocketIo::builder()
+///     .client_ip(ClientIpConfig::new("x-forwarded-for").trusted_proxies(["10.0.0.1".parse().unwrap()]))
+///     .build_svc();
+/// ```
+///
+/// #### Example with axum
+/// ```
+/// # use socketioxide::{SocketIo, ClientIpConfig};
+/// use std::net::SocketAddr;
+/// use axum::{extract::{ConnectInfo, Request}, middleware::map_request, Router};
+///
+/// async fn insert_peer_addr(ConnectInfo(addr): ConnectInfo<SocketAddr>, mut req: Request) -> Request {
+///     req.extensions_mut().insert(addr);
+///     req
+/// }
+///
+/// let (layer, io) = SocketIo::builder()
+///     .client_ip(ClientIpConfig::new("x-forwarded-for").trusted_proxies(["10.0.0.1".parse().unwrap()]))
+///     .build_layer();
+/// // The last layer runs first, so the address is inserted before the request reaches socket.io
+/// let app: Router = Router::new().layer(layer).layer(map_request(insert_peer_addr));
+/// // The connection info is only provided with `into_make_service_with_connect_info`
+/// let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+/// // axum::serve(listener, make_service).await.unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIpConfig {
+    /// The header set by the reverse proxies with the client address, such as `x-forwarded-for` or `x-real-ip`
+    pub header: Option<http::HeaderName>,
+    /// The addresses of the trusted reverse proxies, the header is only read if the peer is one of them
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ClientIpConfig {
+    /// Creates a new [`ClientIpConfig`] reading the given header, without any trusted proxy.
+    ///
+    /// # Panics
+    /// If the header name is invalid.
+    pub fn new(header: &'static str) -> Self {
+        Self {
+            header: Some(http::HeaderName::from_static(header)),
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// Sets the addresses of the trusted reverse proxies
+    pub fn trusted_proxies(mut self, trusted_proxies: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies.into_iter().collect();
+        self
+    }
+
+    /// Resolves the ip address of the client from the request made to open the session
+    pub(crate) fn resolve(&self, req_parts: &http::request::Parts) -> Option<IpAddr> {
+        let peer = req_parts.extensions.get::<SocketAddr>().map(SocketAddr::ip);
+        let (Some(header), Some(ip)) = (&self.header, peer) else {
+            return peer;
+        };
+        if !self.trusted_proxies.contains(&ip) {
+            return peer;
+        }
+        let values = req_parts
+            .headers
+            .get_all(header)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        let mut client = peer;
+        for value in values.into_iter().rev() {
+            let value = value.trim();
+            let ip = value
+                .parse::<IpAddr>()
+                .or_else(|_| value.parse::<SocketAddr>().map(|addr| addr.ip()));
+            match ip {
+                Ok(ip) => {
+                    client = Some(ip);
+                    if !self.trusted_proxies.contains(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+/// The result of a [`SocketIo::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// The number of acknowledgements that resolved during the grace period
+    pub completed_acks: usize,
+    /// The number of acknowledgements that were still pending when the connections were closed
+    pub abandoned_acks: usize,
+}
+
+/// A snapshot of the counters of the server returned by [`SocketIo::metrics`].
+///
+/// The rooms and sockets are those of the current server only, even with an adapter shared between servers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The number of sockets connected to a namespace, a client connected to two namespaces counts twice
+    pub sockets: usize,
+    /// The number of registered namespaces
+    pub namespaces: usize,
+    /// The number of rooms with at least one socket, the rooms of each namespace are counted separately
+    pub rooms: usize,
+    /// The number of packets buffered in the underlying engine.io sessions, waiting to be sent to the clients
+    pub buffered_packets: usize,
+    /// The number of events rejected by the namespaces because they had no registered handler,
+    /// see [`SocketIoBuilder::reject_unknown_events`]
+    pub rejected_events: usize,
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "prometheus")))]
+#[cfg(feature = "prometheus")]
+impl Metrics {
+    /// Formats the metrics with the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/)
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "socketio_sockets",
+                "Number of connected sockets",
+                "gauge",
+                self.sockets,
+            ),
+            (
+                "socketio_namespaces",
+                "Number of namespaces",
+                "gauge",
+                self.namespaces,
+            ),
+            (
+                "socketio_rooms",
+                "Number of non-empty rooms",
+                "gauge",
+                self.rooms,
+            ),
+            (
+                "socketio_buffered_packets",
+                "Number of packets waiting to be sent",
+                "gauge",
+                self.buffered_packets,
+            ),
+            (
+                "socketio_rejected_events",
+                "Number of events rejected because they had no handler",
+                "counter",
+                self.rejected_events,
+            ),
+        ];
+        metrics
+            .iter()
+            .map(|(name, help, kind, value)| {
+                format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n")
+            })
+            .collect()
+    }
+}
+
+/// A handle to a namespace registered with [`SocketIo::ns`].
+pub struct NsHandle<A: Adapter = LocalAdapter>(Arc<Namespace<A>>);
+
+impl<A: Adapter> NsHandle<A> {
+    /// ### Adds a [`ConnectMiddleware`] to the namespace.
+    ///
+    /// Middlewares are called before the connect handler, in their registration order.
+    /// If a middleware returns an error, the connection is rejected with a connect error packet
+    /// containing the error message and the following middlewares and the handler are not called.
+    ///
+    /// Middlewares only apply to the connections made after they are registered.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// #[derive(Debug, serde::Deserialize)]
+    /// struct Auth {
+    ///     token: String,
+    /// }
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| println!("Socket connected: {}", s.id))
+    ///     .with_middleware(|s: SocketRef| match s.req_parts().headers.get("x-api-key") {
+    ///         Some(_) => Ok(()),
+    ///         None => Err("missing api key"),
+    ///     })
+    ///     .with_middleware(|Data(auth): Data<Auth>| {
+    ///         if auth.token.is_empty() {
+    ///             Err("invalid token")
+    ///         } else {
+    ///             Ok(())
+    ///         }
+    ///     });
+    /// ```
+    pub fn with_middleware<M, T>(self, middleware: M) -> Self
+    where
+        M: ConnectMiddleware<A, T>,
+        T: Send + Sync + 'static,
+    {
+        self.0.add_middleware(middleware);
+        self
+    }
+
+    /// ### Overrides the engine.io [`ping_interval`](SocketIoBuilder::ping_interval) for this namespace.
+    ///
+    /// Because the heartbeat is negotiated during the engine.io handshake, before any namespace connection,
+    /// it only applies to the sessions opened with the `ns` query parameter set to this namespace
+    /// (e.g. `/socket.io/?EIO=4&transport=websocket&ns=/iot`).
+    /// Sessions without this parameter primarily connect through the `/` namespace.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use std::time::Duration;
+    /// let (_, io) = SocketIo::new_svc();
+    /// // Low power devices only send a heartbeat every 5 minutes
+    /// io.ns("/iot", |s: SocketRef| println!("Device connected: {}", s.id))
+    ///     .with_ping_interval(Duration::from_secs(300))
+    ///     .with_ping_timeout(Duration::from_secs(60));
+    /// ```
+    pub fn with_ping_interval(self, ping_interval: Duration) -> Self {
+        self.0.heartbeat.write().unwrap().ping_interval = Some(ping_interval);
+        self
+    }
+
+    /// ### Overrides the engine.io [`ping_timeout`](SocketIoBuilder::ping_timeout) for this namespace.
+    ///
+    /// See [`NsHandle::with_ping_interval`] for more details on how the namespace is selected.
+    pub fn with_ping_timeout(self, ping_timeout: Duration) -> Self {
+        self.0.heartbeat.write().unwrap().ping_timeout = Some(ping_timeout);
+        self
+    }
+
+    /// ### Limits the number of sockets connected at the same time to this namespace.
+    ///
+    /// Once the limit is reached, the new connections are rejected with a `too many connections` connect error
+    /// until a socket disconnects. The sockets are counted until they are removed from the namespace,
+    /// whether they disconnected gracefully or their connection was abruptly dropped.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/game", |s: SocketRef| println!("Player connected: {}", s.id))
+    ///     .with_max_connections(100);
+    /// ```
+    pub fn with_max_connections(self, max_connections: usize) -> Self {
+        *self.0.max_connections.write().unwrap() = Some(max_connections);
+        self
+    }
+
+    /// ### Limits the number of async event handlers running at the same time for each socket of this namespace.
+    ///
+    /// By default, the async handlers are spawned as soon as their event is received, so a slow handler doesn't block
+    /// the following events but a socket can spawn an unbounded number of tasks. Once the limit is reached,
+    /// the handlers of the new events wait for a running handler to complete, in the order the events were received.
+    /// With a concurrency of 1, the events of a socket are processed one after the other.
+    ///
+    /// Sync handlers are called as soon as their event is received and are not limited.
+    /// The limit only applies to the sockets connected after it is set.
+    ///
+    /// ## Panics
+    /// If the concurrency is 0.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| {
+    ///     s.on("order", |s: SocketRef, Data::<String>(order)| async move {
+    ///         // The orders of a client are processed in the order they were sent
+    ///         s.emit("processed", order).ok();
+    ///     });
+    /// })
+    /// .with_handler_concurrency(1);
+    /// ```
+    pub fn with_handler_concurrency(self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "handler concurrency must be at least 1");
+        *self.0.handler_concurrency.write().unwrap() = Some(concurrency);
+        self
+    }
+
+    /// ### Cancels the async handlers of a socket of this namespace when it disconnects.
+    ///
+    /// The futures of the async connect and event handlers still running are dropped once the socket
+    /// is disconnected, right before its disconnect handler is called, so that the resources they hold are freed.
+    /// The handlers are cancelled at their next `.await` point, a handler waiting for a
+    /// [concurrency permit](Self::with_handler_concurrency) is never called.
+    /// A handler can also wait for the disconnection itself with [`Socket::disconnected`](crate::socket::Socket::disconnected).
+    ///
+    /// Sync handlers are not concerned. It only applies to the sockets connected after it is set.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use std::time::Duration;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| {
+    ///     s.on("export", |s: SocketRef| async move {
+    ///         // The export is stopped if the client disconnects in the meantime
+    ///         tokio::time::sleep(Duration::from_secs(60)).await;
+    ///         s.emit("exported", ()).ok();
+    ///     });
+    /// })
+    /// .with_handler_cancellation();
+    /// ```
+    pub fn with_handler_cancellation(self) -> Self {
+        self.0
+            .cancel_handlers
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        self
+    }
+
+    /// ### Keeps the last `capacity` packets broadcast to a room of this namespace.
+    ///
+    /// The sockets joining the room with [`Socket::join_with_history`](crate::socket::Socket::join_with_history)
+    /// receive these packets, e.g. the last messages of a chat room. It can be called again to change the capacity,
+    /// a `capacity` of 0 disables the history of the room.
+    /// Only the packets broadcast to the room through the adapter are kept, without the ones emitted with an ack.
+    ///
+    /// With adapters that don't keep a history, the sockets joining the room don't receive any packet.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| async move {
+    ///     s.join_with_history("news").await.ok();
+    /// })
+    /// .with_room_history("news", 10);
+    ///
+    /// io.to("news").emit("headline", "socketioxide released").await.ok();
+    /// # }
+    /// ```
+    pub fn with_room_history(self, room: impl Into<Room>, capacity: usize) -> Self {
+        self.0.adapter.set_room_history(room.into(), capacity);
+        self
+    }
+
+    /// ### Adds an event middleware to the namespace.
+    ///
+    /// Event middlewares are called with the event name and its raw payload each time a socket
+    /// of this namespace receives an event, before its handler and in their registration order.
+    /// The payload is the array of the event arguments, it can be mutated before being passed
+    /// to the next middleware and to the handler.
+    /// For binary events, only the JSON part of the payload is given.
+    ///
+    /// If a middleware returns an error, the event is dropped and the following middlewares are not called.
+    /// If the client requested an acknowledgement, the error is sent back as an ack response
+    /// in the form of `{ "message": "<error>" }`.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use serde_json::Value;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| {
+    ///     s.on("message", |Data::<Value>(data)| println!("{data}"));
+    /// })
+    /// .on_any(|s: SocketRef, event: &str, _: &mut Value| {
+    ///     println!("socket {} received {event}", s.id);
+    ///     Ok::<_, std::convert::Infallible>(())
+    /// })
+    /// .on_any(|_: SocketRef, _: &str, data: &mut Value| match data.get_mut(0) {
+    ///     Some(Value::Object(obj)) => {
+    ///         obj.remove("password");
+    ///         Ok(())
+    ///     }
+    ///     _ => Err("the first argument should be an object"),
+    /// });
+    /// ```
+    pub fn on_any<F, E>(self, middleware: F) -> Self
+    where
+        F: Fn(SocketRef<A>, &str, &mut Value) -> Result<(), E> + Send + Sync + 'static,
+        E: std::fmt::Display,
+    {
+        self.0
+            .add_event_middleware(Box::new(move |socket, event, data| {
+                middleware(SocketRef::new(socket.clone()), event, data).map_err(|e| e.to_string())
+            }));
+        self
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -799,6 +1884,74 @@ mod tests {
         assert!(io.get_socket(Sid::new()).is_none());
     }
 
+    #[test]
+    fn get_socket_across_namespaces() {
+        use engineioxide::Socket;
+        let sid = Sid::new();
+        let (_, io) = SocketIo::builder().build_svc();
+        io.ns("/chat", || {});
+
+        let socket = Socket::new_dummy(sid, Box::new(|_, _| {})).into();
+        let config = SocketIoConfig::default().into();
+        io.0.get_ns("/chat")
+            .unwrap()
+            .connect(sid, socket, None, config)
+            .unwrap();
+
+        assert_eq!(io.get_socket(sid).unwrap().ns(), "/chat");
+        assert!(io.get_socket(Sid::new()).is_none());
+    }
+
+    #[test]
+    fn client_ip_resolve() {
+        let req = |peer: Option<&str>, header: Option<&str>| {
+            let mut req = http::Request::builder();
+            if let Some(header) = header {
+                req = req.header("x-forwarded-for", header);
+            }
+            let mut req = req.body(()).unwrap();
+            if let Some(peer) = peer {
+                req.extensions_mut()
+                    .insert(SocketAddr::new(peer.parse().unwrap(), 1234));
+            }
+            req.into_parts().0
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        let config = ClientIpConfig::new("x-forwarded-for")
+            .trusted_proxies(["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()]);
+
+        // Without proxy header, the peer address is used
+        assert_eq!(config.resolve(&req(Some("1.2.3.4"), None)), ip("1.2.3.4"));
+        assert_eq!(config.resolve(&req(None, Some("1.2.3.4"))), None);
+        assert_eq!(config.resolve(&req(Some("10.0.0.1"), None)), ip("10.0.0.1"));
+
+        // The header is read from right to left, skipping the trusted proxies
+        let header = Some("6.6.6.6, 1.2.3.4:5678, 10.0.0.2");
+        assert_eq!(
+            config.resolve(&req(Some("10.0.0.1"), header)),
+            ip("1.2.3.4")
+        );
+        let header = Some("10.0.0.2");
+        assert_eq!(
+            config.resolve(&req(Some("10.0.0.1"), header)),
+            ip("10.0.0.2")
+        );
+        let header = Some("invalid, 10.0.0.2");
+        assert_eq!(
+            config.resolve(&req(Some("10.0.0.1"), header)),
+            ip("10.0.0.2")
+        );
+
+        // The header sent by an untrusted source is ignored
+        let header = Some("1.2.3.4");
+        assert_eq!(config.resolve(&req(Some("6.6.6.6"), header)), ip("6.6.6.6"));
+        let config = ClientIpConfig::default();
+        assert_eq!(
+            config.resolve(&req(Some("10.0.0.1"), header)),
+            ip("10.0.0.1")
+        );
+    }
+
     #[test]
     fn every_op_should_be_broadcast() {
         let (_, io) = SocketIo::builder().build_svc();
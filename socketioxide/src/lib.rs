@@ -2,7 +2,7 @@
 #![warn(
     clippy::all,
     clippy::todo,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::mem_forget,
     clippy::unused_self,
     clippy::filter_map_next,
@@ -10,9 +10,7 @@
     clippy::needless_borrow,
     clippy::match_wildcard_for_single_variants,
     clippy::if_let_mutex,
-    clippy::mismatched_target_os,
     clippy::await_holding_lock,
-    clippy::match_on_vec_items,
     clippy::imprecise_flops,
     clippy::suboptimal_flops,
     clippy::lossy_float_literal,
@@ -151,6 +149,7 @@
 //! * [`TryData`](extract::Data): extracts and deserialize to json any data but with a `Result` type in case of error
 //!     - for [`ConnectHandler`](handler::ConnectHandler): extracts and deserialize to json the auth data
 //!     - for [`MessageHandler`](handler::MessageHandler): extracts and deserialize to json the message data
+//! * [`Arg`](extract::Arg): extracts and deserialize to json the next argument of a message event, if a deserialize error occurs the handler won't be called
 //! * [`SocketRef`](extract::Data): extracts a reference to the [`Socket`](socket::Socket)
 //! * [`Bin`](extract::Data): extract a binary payload for a given message. Because it consumes the event it should be the last argument
 //! * [`AckSender`](extract::Data): Can be used to send an ack response to the current message event
@@ -190,7 +189,7 @@
 //!
 //! #### Emitting with operators
 //! To configure the emit, you can chain [`Operators`](operators::Operators) methods to the emit call. With that you can easily configure the following options:
-//! * rooms: emit, join, leave to specific rooms
+//! * rooms: emit, join, leave to specific rooms, or to the rooms of the current socket with [`broadcast_rooms`](socket::Socket::broadcast_rooms)
 //! * namespace: emit to a specific namespace (only from the [`SocketIo`] handle)
 //! * timeout: set a custom timeout when waiting for an ack
 //! * binary: emit a binary payload with the message
@@ -205,6 +204,7 @@
 //! They are implemented with the [`AckSender`](extract::AckSender) extractor.
 //! You can send an ack response with an optional binary payload with the [`AckSender::send`](extract::AckSender) method.
 //! If the client doesn't send an ack response, the [`AckSender::send`](extract::AckSender) method will do nothing.
+//! Several responses can be streamed to the same ack id with the [`AckSender::stream`](extract::AckSender::stream) method.
 //!
 //! #### Client acknowledgements
 //! You can use the [`Socket::emit_with_ack`](socket::Socket) method to emit a message with an ack callback.
@@ -240,8 +240,17 @@
 //! * `tracing`: enable logging with [`tracing`] calls
 //! * `extensions`: enable per-socket state with the [`extensions`] module
 //! * `state`: enable global state management
+//! * `simd-json`: parse and serialize the packet payloads with [`simd_json`] instead of [`serde_json`],
+//!   see the [`serializer`] module
+//! * `msgpack`: enable the [`MsgPackParser`](parser::MsgPackParser) to encode the packets with MessagePack,
+//!   see the [`parser`] module
+//! * `prometheus`: enable [`Metrics::to_prometheus`] to format the metrics with the Prometheus text format
+//! * `webtransport`: enable the WebTransport transport, served with `SocketIoService::on_webtransport`
+//!   over a stream handed over by an HTTP/3 server
+//! * `test-utils`: enable the in-memory [`TestClient`](testing::TestClient) of the [`testing`] module
 //!
 pub mod adapter;
+pub mod args;
 
 #[cfg_attr(docsrs, doc(cfg(feature = "extensions")))]
 #[cfg(feature = "extensions")]
@@ -251,23 +260,37 @@ mod state;
 
 pub mod handler;
 pub mod layer;
+pub mod lifecycle;
 pub mod operators;
+pub mod packet;
+pub mod parser;
+pub mod serializer;
 pub mod service;
+pub mod session;
 pub mod socket;
+#[cfg(feature = "test-utils")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-utils")))]
+pub mod testing;
 
 #[cfg(feature = "test-utils")]
 pub use packet::*;
 
-pub use engineioxide::TransportType;
-pub use errors::{AckError, BroadcastError, SendError};
+pub use engineioxide::{
+    config::{CircuitBreaker, HandshakeLimit, OverflowPolicy},
+    sid::SidGenerator,
+    TransportType,
+};
+pub use errors::{AckError, BroadcastError, NsPatternError, ParseError, SendError};
 pub use handler::extract;
-pub use io::{SocketIo, SocketIoBuilder, SocketIoConfig};
+pub use io::{
+    ClientIpConfig, Metrics, NsHandle, ParseErrorPolicy, RateLimit, RateLimitPolicy,
+    ShutdownSummary, SocketIo, SocketIoBuilder, SocketIoConfig,
+};
 
 mod client;
 mod errors;
 mod io;
 mod ns;
-mod packet;
 
 /// Socket.IO protocol version.
 /// It is accessible with the [`Socket::protocol`](socket::Socket) method or as an extractor
@@ -0,0 +1,103 @@
+//! A global stream of the connection lifecycle events of the sockets, returned by [`SocketIo::lifecycle_events`].
+//!
+//! It allows a single task to observe the connections and disconnections of all the namespaces,
+//! for example to drive metrics or audit logs, without registering handlers on each namespace.
+//!
+//! #### Buffering
+//! Each stream buffers up to [`LIFECYCLE_EVENTS_CAPACITY`] events. When a stream is slower
+//! than the events are produced, the oldest buffered events are dropped and the stream
+//! resumes with the most recent ones. The sockets are never slowed down by a lagging stream.
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{SocketIo, lifecycle::LifecycleEvent};
+//! # use futures::stream::StreamExt;
+//! async fn audit(io: SocketIo) {
+//!     let mut events = io.lifecycle_events();
+//!     while let Some(event) = events.next().await {
+//!         match event {
+//!             LifecycleEvent::Connected { sid, ns } => println!("{sid} connected to {ns}"),
+//!             LifecycleEvent::Disconnected { sid, ns, reason } => {
+//!                 println!("{sid} disconnected from {ns}: {reason}")
+//!             }
+//!             LifecycleEvent::UpgradeCompleted { sid } => println!("{sid} upgraded to websocket"),
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! [`SocketIo::lifecycle_events`]: crate::SocketIo::lifecycle_events
+use std::borrow::Cow;
+
+use engineioxide::sid::Sid;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio::sync::broadcast;
+
+use crate::socket::DisconnectReason;
+
+/// The number of lifecycle events buffered for a slow stream.
+/// Older events are dropped once it is reached.
+pub const LIFECYCLE_EVENTS_CAPACITY: usize = 1024;
+
+/// A connection lifecycle event of a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// The socket is connected to the namespace, after its middlewares and connect handler extractors succeeded
+    Connected {
+        /// The id of the socket
+        sid: Sid,
+        /// The namespace path
+        ns: Cow<'static, str>,
+    },
+    /// The socket is disconnected from the namespace
+    Disconnected {
+        /// The id of the socket
+        sid: Sid,
+        /// The namespace path
+        ns: Cow<'static, str>,
+        /// The reason of the disconnection
+        reason: DisconnectReason,
+    },
+    /// The underlying connection of the socket is upgraded from polling to websocket.
+    /// It is emitted once for all the namespaces the client is connected to.
+    UpgradeCompleted {
+        /// The id of the socket
+        sid: Sid,
+    },
+}
+
+/// The sender of the lifecycle events, shared by the client and its namespaces
+#[derive(Debug, Clone)]
+pub(crate) struct LifecycleEvents(broadcast::Sender<LifecycleEvent>);
+
+impl Default for LifecycleEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(LIFECYCLE_EVENTS_CAPACITY).0)
+    }
+}
+
+impl LifecycleEvents {
+    /// Notifies the lifecycle event streams, if there are any
+    pub fn send(&self, event: LifecycleEvent) {
+        // An error only means that there is no stream
+        self.0.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> BoxStream<'static, LifecycleEvent> {
+        let rx = self.0.subscribe();
+        stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    // The stream was too slow, the oldest events are dropped
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+}
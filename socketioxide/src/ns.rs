@@ -1,39 +1,134 @@
 use std::{
     borrow::Cow,
     collections::HashMap,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
 };
 
 use crate::{
-    adapter::Adapter,
-    errors::Error,
-    handler::{BoxedConnectHandler, ConnectHandler, MakeErasedHandler},
+    adapter::{run_adapter_op, Adapter},
+    adapter::{BroadcastFlags, BroadcastOptions},
+    errors::{Error, NsPatternError},
+    handler::{
+        BoxedConnectHandler, BoxedConnectMiddleware, BoxedEventMiddleware, ConnectErrorHandler,
+        ConnectErrorKind, ConnectHandler, ConnectMiddleware, MakeErasedHandler,
+    },
+    lifecycle::LifecycleEvents,
     packet::{Packet, PacketData},
-    socket::Socket,
+    session::{Session, SessionStore},
+    socket::{DisconnectReason, Socket},
     SocketIoConfig,
 };
 use crate::{client::SocketData, errors::AdapterError};
 use engineioxide::sid::Sid;
 
+/// The heartbeat parameters of a namespace, `None` values fall back to the engine.io config
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct NsHeartbeat {
+    pub ping_interval: Option<Duration>,
+    pub ping_timeout: Option<Duration>,
+}
+
+/// A socket kept during the disconnect grace period with the reason of its disconnection
+type DisconnectingSocket<A> = (Arc<Socket<A>>, DisconnectReason);
+
 pub struct Namespace<A: Adapter> {
     pub path: Cow<'static, str>,
+    /// The parameters extracted from the path if the namespace was created from a [`DynNamespace`]
+    pub(crate) params: HashMap<String, String>,
     pub(crate) adapter: A,
     handler: BoxedConnectHandler<A>,
+    /// Middlewares called in order before the connect handler
+    middlewares: RwLock<Vec<BoxedConnectMiddleware<A>>>,
+    /// Middlewares called in order before the event handlers of the sockets
+    event_middlewares: RwLock<Vec<BoxedEventMiddleware<A>>>,
+    /// Heartbeat parameters overriding the engine.io config for the sessions primarily connecting to this namespace
+    pub(crate) heartbeat: RwLock<NsHeartbeat>,
+    /// The maximum number of sockets connected at the same time to this namespace
+    pub(crate) max_connections: RwLock<Option<usize>>,
+    /// The maximum number of async event handlers running at the same time for each socket
+    pub(crate) handler_concurrency: RwLock<Option<usize>>,
+    /// Set if the async handlers of the sockets are cancelled when they disconnect
+    pub(crate) cancel_handlers: AtomicBool,
+    /// The number of events rejected because they had no registered handler
+    pub(crate) rejected_events: AtomicUsize,
+    /// The store used to recover the sessions of the disconnected sockets
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+    /// The handler called when a connection to this namespace is rejected
+    pub(crate) connect_error_handler: ConnectErrorHandler,
+    /// The sender of the lifecycle events of the sockets
+    pub(crate) lifecycle: LifecycleEvents,
     sockets: RwLock<HashMap<Sid, Arc<Socket<A>>>>,
+    /// The abruptly disconnected sockets kept during the disconnect grace period, by private session id
+    disconnecting: Mutex<HashMap<Sid, DisconnectingSocket<A>>>,
+    /// The last packet emitted with [`Operators::emit_buffered`](crate::operators::Operators::emit_buffered)
+    /// for each event, delivered to the sockets when they connect
+    retained: RwLock<HashMap<Cow<'static, str>, Packet<'static>>>,
 }
 
 impl<A: Adapter> Namespace<A> {
-    pub fn new<C, T>(path: Cow<'static, str>, handler: C) -> Arc<Self>
+    pub(crate) fn new<C, T>(
+        path: Cow<'static, str>,
+        handler: C,
+        session_store: Option<Arc<dyn SessionStore>>,
+        connect_error_handler: ConnectErrorHandler,
+        lifecycle: LifecycleEvents,
+    ) -> Arc<Self>
     where
         C: ConnectHandler<A, T> + Send + Sync + 'static,
         T: Send + Sync + 'static,
     {
-        Arc::new_cyclic(|ns| Self {
+        Self::new_boxed(
             path,
-            handler: MakeErasedHandler::new_ns_boxed(handler),
+            HashMap::new(),
+            MakeErasedHandler::new_ns_boxed(handler),
+            session_store,
+            connect_error_handler,
+            lifecycle,
+        )
+    }
+
+    fn new_boxed(
+        path: Cow<'static, str>,
+        params: HashMap<String, String>,
+        handler: BoxedConnectHandler<A>,
+        session_store: Option<Arc<dyn SessionStore>>,
+        connect_error_handler: ConnectErrorHandler,
+        lifecycle: LifecycleEvents,
+    ) -> Arc<Self> {
+        let ns = Arc::new_cyclic(|ns| Self {
+            path,
+            params,
+            handler,
+            middlewares: RwLock::new(Vec::new()),
+            event_middlewares: RwLock::new(Vec::new()),
+            heartbeat: RwLock::new(NsHeartbeat::default()),
+            max_connections: RwLock::new(None),
+            handler_concurrency: RwLock::new(None),
+            cancel_handlers: AtomicBool::new(false),
+            rejected_events: AtomicUsize::new(0),
+            session_store,
+            connect_error_handler,
+            lifecycle,
             sockets: HashMap::new().into(),
+            disconnecting: Mutex::new(HashMap::new()),
+            retained: RwLock::new(HashMap::new()),
             adapter: A::new(ns.clone()),
-        })
+        });
+        let init_ns = ns.clone();
+        run_adapter_op(async move {
+            if let Err(_e) = init_ns.adapter.init().await {
+                #[cfg(feature = "tracing")]
+                tracing::error!(
+                    "error initializing adapter of namespace {}: {_e:?}",
+                    init_ns.path
+                );
+            }
+        });
+        ns
     }
 
     /// Connects a socket to a namespace
@@ -46,28 +141,152 @@ impl<A: Adapter> Namespace<A> {
     ) -> Result<(), serde_json::Error> {
         let socket: Arc<Socket<A>> = Socket::new(sid, self.clone(), esocket.clone(), config).into();
 
-        self.sockets.write().unwrap().insert(sid, socket.clone());
+        {
+            let mut sockets = self.sockets.write().unwrap();
+            let max = *self.max_connections.read().unwrap();
+            if matches!(max, Some(max) if sockets.len() >= max) {
+                drop(sockets);
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={sid}] connection rejected, too many connections");
+                socket.reject_connect(ConnectErrorKind::TooManyConnections);
+                return Ok(());
+            }
+            sockets.insert(sid, socket.clone());
+        }
+
+        if let Some((pid, session)) = self.restore_session(&auth) {
+            let previous = self.disconnecting.lock().unwrap().remove(&pid);
+            socket.recover(session, previous.map(|(socket, _)| socket));
+        }
 
-        let protocol = esocket.protocol.into();
-        if let Err(_e) = socket.send(Packet::connect(&self.path, socket.id, protocol)) {
-            #[cfg(feature = "tracing")]
-            tracing::debug!("error sending connect packet: {:?}, closing conn", _e);
-            esocket.close(engineioxide::DisconnectReason::PacketParsingError);
-            return Ok(());
+        for middleware in self.middlewares.read().unwrap().iter() {
+            if let Err(message) = middleware.call(&socket, &auth) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={sid}] connection rejected by middleware: {message}");
+                socket.reject_connect(ConnectErrorKind::Middleware(message));
+                return Ok(());
+            }
         }
 
+        // The connect packet is sent by the handler once its arguments are extracted
         self.handler.call(socket, auth);
         Ok(())
     }
 
+    /// Takes back the session matching the private session id sent in the auth payload
+    fn restore_session(&self, auth: &Option<String>) -> Option<(Sid, Session)> {
+        let store = self.session_store.as_ref()?;
+        let auth: serde_json::Value = serde_json::from_str(auth.as_deref()?).ok()?;
+        let pid = auth.get("pid")?.as_str()?.parse().ok()?;
+        store.restore(&self.path, pid).map(|session| (pid, session))
+    }
+
+    /// Buffers a broadcast packet for the saved sessions matching the broadcast options,
+    /// so that it can be replayed once their clients reconnect.
+    pub(crate) fn buffer_broadcast(&self, packet: &Packet<'_>, opts: &BroadcastOptions) {
+        let Some(store) = &self.session_store else {
+            return;
+        };
+        let recoverable = matches!(packet.inner, PacketData::Event(_, _, None))
+            && !opts.flags.contains(&BroadcastFlags::Volatile)
+            && (!opts.rooms.is_empty() || opts.flags.contains(&BroadcastFlags::Broadcast));
+        if !recoverable {
+            return;
+        }
+        let Ok(packet) = TryInto::<String>::try_into(packet.clone()) else {
+            return;
+        };
+        let filter = |session: &Session| {
+            (opts.rooms.is_empty() || session.rooms.iter().any(|r| opts.rooms.contains(r)))
+                && !session.rooms.iter().any(|r| opts.except.contains(r))
+                && !opts.except_sids.contains(&session.sid)
+        };
+        store.buffer(&self.path, &filter, &packet);
+    }
+
+    /// Retains a packet for an event, replacing the previous one
+    pub(crate) fn retain(&self, event: Cow<'static, str>, packet: Packet<'static>) {
+        self.retained.write().unwrap().insert(event, packet);
+    }
+
+    /// Removes the packet retained for an event, returns `true` if there was one
+    pub(crate) fn clear_retained(&self, event: &str) -> bool {
+        self.retained.write().unwrap().remove(event).is_some()
+    }
+
+    /// Returns the retained packets to deliver to a newly connected socket
+    pub(crate) fn retained_packets(&self) -> Vec<Packet<'static>> {
+        self.retained.read().unwrap().values().cloned().collect()
+    }
+
+    /// Adds a middleware called before the connect handler
+    pub fn add_middleware<M, T>(&self, middleware: M)
+    where
+        M: ConnectMiddleware<A, T>,
+        T: Send + Sync + 'static,
+    {
+        let middleware = MakeErasedHandler::new_middleware_boxed(middleware);
+        self.middlewares.write().unwrap().push(middleware);
+    }
+
+    /// Adds a middleware called before the event handlers
+    pub fn add_event_middleware(&self, middleware: BoxedEventMiddleware<A>) {
+        self.event_middlewares.write().unwrap().push(middleware);
+    }
+
+    /// Calls the event middlewares in order, it stops at the first error
+    pub(crate) fn apply_event_middlewares(
+        &self,
+        socket: &Arc<Socket<A>>,
+        event: &str,
+        data: &mut serde_json::Value,
+    ) -> Result<(), String> {
+        self.event_middlewares
+            .read()
+            .unwrap()
+            .iter()
+            .try_for_each(|middleware| middleware(socket, event, data))
+    }
+
     /// Removes a socket from a namespace and propagate the event to the adapter
-    pub fn remove_socket(&self, sid: Sid) -> Result<(), AdapterError> {
+    pub async fn remove_socket(&self, sid: Sid) -> Result<(), AdapterError> {
         self.sockets.write().unwrap().remove(&sid);
         self.adapter
             .del_all(sid)
+            .await
             .map_err(|err| AdapterError(Box::new(err)))
     }
 
+    /// Keeps an abruptly disconnected socket until its client reconnects with its session
+    /// or the grace period elapses. In the latter case, its disconnect handler is then called.
+    pub(crate) fn keep_disconnecting(
+        self: &Arc<Self>,
+        pid: Sid,
+        socket: Arc<Socket<A>>,
+        reason: DisconnectReason,
+        grace_period: Duration,
+    ) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] keeping socket for {:?}", socket.id, grace_period);
+        self.disconnecting
+            .lock()
+            .unwrap()
+            .insert(pid, (socket, reason));
+        let ns = Arc::downgrade(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(grace_period).await;
+            let Some(ns) = ns.upgrade() else {
+                return;
+            };
+            let socket = ns.disconnecting.lock().unwrap().remove(&pid);
+            if let Some((socket, reason)) = socket {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={}] disconnect grace period elapsed", socket.id);
+                socket.call_disconnect_handler(reason).await;
+            }
+        });
+    }
+
     pub fn has(&self, sid: Sid) -> bool {
         self.sockets.read().unwrap().values().any(|s| s.id == sid)
     }
@@ -75,7 +294,7 @@ impl<A: Adapter> Namespace<A> {
     pub fn recv(&self, sid: Sid, packet: PacketData<'_>) -> Result<(), Error> {
         match packet {
             PacketData::Connect(_) => unreachable!("connect packets should be handled before"),
-            PacketData::ConnectError => Err(Error::InvalidPacketType),
+            PacketData::ConnectError(_) => Err(Error::InvalidPacketType),
             packet => self.get_socket(sid)?.recv(packet),
         }
     }
@@ -98,11 +317,15 @@ impl<A: Adapter> Namespace<A> {
     /// * Closes all the sockets and their underlying connections
     /// * Removes all the sockets from the namespace
     pub async fn close(&self) {
-        self.adapter.close().ok();
+        self.adapter.close().await.ok();
         #[cfg(feature = "tracing")]
         tracing::debug!("closing all sockets in namespace {}", self.path);
         let sockets = self.sockets.read().unwrap().clone();
         futures::future::join_all(sockets.values().map(|s| s.close_underlying_transport())).await;
+        let disconnecting = std::mem::take(&mut *self.disconnecting.lock().unwrap());
+        for (socket, reason) in disconnecting.into_values() {
+            socket.call_disconnect_handler(reason).await;
+        }
         self.sockets.write().unwrap().shrink_to_fit();
         #[cfg(feature = "tracing")]
         tracing::debug!("all sockets in namespace {} closed", self.path);
@@ -112,7 +335,13 @@ impl<A: Adapter> Namespace<A> {
 #[cfg(test)]
 impl<A: Adapter> Namespace<A> {
     pub fn new_dummy<const S: usize>(sockets: [Sid; S]) -> Arc<Self> {
-        let ns = Namespace::new(Cow::Borrowed("/"), || {});
+        let ns = Namespace::new(
+            Cow::Borrowed("/"),
+            || {},
+            None,
+            Default::default(),
+            Default::default(),
+        );
         for sid in sockets {
             ns.sockets
                 .write()
@@ -127,12 +356,175 @@ impl<A: Adapter> Namespace<A> {
     }
 }
 
+/// A namespace registered with a pattern such as `/chat-{room}`.
+///
+/// A child [`Namespace`] sharing the same connect handler is created for each path matching the pattern.
+pub struct DynNamespace<A: Adapter> {
+    pattern: NsPattern,
+    handler: BoxedConnectHandler<A>,
+    session_store: Option<Arc<dyn SessionStore>>,
+    connect_error_handler: ConnectErrorHandler,
+    lifecycle: LifecycleEvents,
+}
+
+impl<A: Adapter> DynNamespace<A> {
+    pub(crate) fn new<C, T>(
+        pattern: &str,
+        handler: C,
+        session_store: Option<Arc<dyn SessionStore>>,
+        connect_error_handler: ConnectErrorHandler,
+        lifecycle: LifecycleEvents,
+    ) -> Result<Self, NsPatternError>
+    where
+        C: ConnectHandler<A, T> + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        Ok(Self {
+            pattern: NsPattern::new(pattern)?,
+            handler: MakeErasedHandler::new_ns_boxed(handler),
+            session_store,
+            connect_error_handler,
+            lifecycle,
+        })
+    }
+
+    /// Creates a child namespace for the given path if it matches the pattern
+    pub fn try_match(&self, path: &str) -> Option<Arc<Namespace<A>>> {
+        let params = self.pattern.matches(path)?;
+        let path = Cow::Owned(path.to_string());
+        Some(Namespace::new_boxed(
+            path,
+            params,
+            self.handler.clone(),
+            self.session_store.clone(),
+            self.connect_error_handler.clone(),
+            self.lifecycle.clone(),
+        ))
+    }
+}
+
+impl<A: Adapter> std::fmt::Debug for DynNamespace<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynNamespace")
+            .field("pattern", &self.pattern)
+            .finish()
+    }
+}
+
+/// A part of a [`NsPattern`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternPart {
+    Static(String),
+    Param(String),
+}
+
+/// A namespace pattern made of static parts and `{name}` parameters.
+///
+/// A parameter matches a non empty string without any `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NsPattern(Vec<PatternPart>);
+
+impl NsPattern {
+    fn new(pattern: &str) -> Result<Self, NsPatternError> {
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest.find('}').ok_or(NsPatternError::UnclosedParam)?;
+                    let name = &rest[1..end];
+                    if name.is_empty() || name.contains('{') {
+                        return Err(NsPatternError::InvalidParamName);
+                    }
+                    if let Some(PatternPart::Param(_)) = parts.last() {
+                        return Err(NsPatternError::AdjacentParams);
+                    }
+                    parts.push(PatternPart::Param(name.to_string()));
+                    rest = &rest[end + 1..];
+                }
+                Some(start) => {
+                    parts.push(PatternPart::Static(rest[..start].to_string()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    parts.push(PatternPart::Static(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+        Ok(Self(parts))
+    }
+
+    /// Returns the parameters extracted from the path if it matches the pattern
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut rest = path;
+        for (i, part) in self.0.iter().enumerate() {
+            match part {
+                PatternPart::Static(s) => rest = rest.strip_prefix(s.as_str())?,
+                PatternPart::Param(name) => {
+                    // A parameter extends until the next static part or the end of the path
+                    let end = match self.0.get(i + 1) {
+                        Some(PatternPart::Static(next)) => rest.find(next.as_str())?,
+                        _ => rest.len(),
+                    };
+                    let value = &rest[..end];
+                    if value.is_empty() || value.contains('/') {
+                        return None;
+                    }
+                    params.insert(name.clone(), value.to_string());
+                    rest = &rest[end..];
+                }
+            }
+        }
+        rest.is_empty().then_some(params)
+    }
+}
+
 impl<A: Adapter + std::fmt::Debug> std::fmt::Debug for Namespace<A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Namespace")
             .field("path", &self.path)
+            .field("params", &self.params)
             .field("adapter", &self.adapter)
             .field("sockets", &self.sockets)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ns_pattern_matches() {
+        let pattern = NsPattern::new("/chat-{room}").unwrap();
+        let params = pattern.matches("/chat-42").unwrap();
+        assert_eq!(params.get("room").unwrap(), "42");
+        assert!(pattern.matches("/chat-").is_none());
+        assert!(pattern.matches("/chat-42/foo").is_none());
+        assert!(pattern.matches("/admin").is_none());
+
+        let pattern = NsPattern::new("/{org}/chat-{room}").unwrap();
+        let params = pattern.matches("/rust/chat-42").unwrap();
+        assert_eq!(params.get("org").unwrap(), "rust");
+        assert_eq!(params.get("room").unwrap(), "42");
+        assert!(pattern.matches("/rust/chat").is_none());
+    }
+
+    #[test]
+    fn ns_pattern_errors() {
+        assert_eq!(
+            NsPattern::new("/chat-{room"),
+            Err(NsPatternError::UnclosedParam)
+        );
+        assert_eq!(
+            NsPattern::new("/chat-{}"),
+            Err(NsPatternError::InvalidParamName)
+        );
+        assert_eq!(
+            NsPattern::new("/{org}{room}"),
+            Err(NsPatternError::AdjacentParams)
+        );
+    }
+}
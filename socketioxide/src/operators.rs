@@ -4,18 +4,18 @@ use std::borrow::Cow;
 use std::{sync::Arc, time::Duration};
 
 use engineioxide::sid::Sid;
-use futures::stream::BoxStream;
+use futures::StreamExt;
 use serde::de::DeserializeOwned;
 
 use crate::adapter::LocalAdapter;
-use crate::errors::BroadcastError;
+use crate::args::{Arg, Args};
+use crate::errors::{BroadcastError, SendError};
 use crate::extract::SocketRef;
-use crate::socket::AckResponse;
 use crate::{
-    adapter::{Adapter, BroadcastFlags, BroadcastOptions, Room},
-    errors::AckError,
+    adapter::{AckStream, Adapter, BroadcastFlags, BroadcastOptions, Room},
     ns::Namespace,
     packet::Packet,
+    parser::EncodedPacket,
 };
 
 /// A trait for types that can be used as a room parameter.
@@ -96,12 +96,73 @@ impl RoomParam for Sid {
     }
 }
 
+/// A trait for types that can be used as a parameter of the `except` operators:
+/// the rooms or the socket ids to exclude.
+///
+/// It is implemented for all the [`RoomParam`] types, except for [`Sid`], which excludes the socket itself,
+/// as well as [`Vec<Sid>`] and const arrays of [`Sid`].
+pub trait ExceptParam: 'static {
+    /// Split `self` into the rooms and the socket ids to exclude.
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>);
+}
+
+macro_rules! impl_except_rooms {
+    ($($ty:ty),*) => {
+        $(impl ExceptParam for $ty {
+            #[inline(always)]
+            fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+                (self.into_room_iter().collect(), Vec::new())
+            }
+        })*
+    };
+}
+impl_except_rooms!(
+    Room,
+    String,
+    Vec<String>,
+    Vec<&'static str>,
+    Vec<Room>,
+    &'static str
+);
+impl<const COUNT: usize> ExceptParam for [&'static str; COUNT] {
+    #[inline(always)]
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+        (self.into_room_iter().collect(), Vec::new())
+    }
+}
+impl<const COUNT: usize> ExceptParam for [String; COUNT] {
+    #[inline(always)]
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+        (self.into_room_iter().collect(), Vec::new())
+    }
+}
+impl ExceptParam for Sid {
+    #[inline(always)]
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+        (Vec::new(), vec![self])
+    }
+}
+impl ExceptParam for Vec<Sid> {
+    #[inline(always)]
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+        (Vec::new(), self)
+    }
+}
+impl<const COUNT: usize> ExceptParam for [Sid; COUNT] {
+    #[inline(always)]
+    fn into_except(self) -> (Vec<Room>, Vec<Sid>) {
+        (Vec::new(), self.into())
+    }
+}
+
 /// Operators are used to select sockets to send a packet to, or to configure the packet that will be emitted.
 #[derive(Debug)]
 pub struct Operators<A: Adapter = LocalAdapter> {
     opts: BroadcastOptions,
     ns: Arc<Namespace<A>>,
     binary: Vec<Vec<u8>>,
+    /// Selects the rooms of the sender when the operators are applied
+    sender_rooms: bool,
 }
 
 impl<A: Adapter> Operators<A> {
@@ -110,9 +171,18 @@ impl<A: Adapter> Operators<A> {
             opts: BroadcastOptions::new(sid),
             ns,
             binary: vec![],
+            sender_rooms: false,
         }
     }
 
+    /// Selects all sockets sharing a room with the current socket, except the current socket.
+    ///
+    /// The rooms are resolved when the operators are applied, e.g. when the message is emitted.
+    pub(crate) fn sender_rooms(mut self) -> Self {
+        self.sender_rooms = true;
+        self.broadcast()
+    }
+
     /// Selects all sockets in the given rooms except the current socket.
     /// If it is called from the `Namespace` level there will be no difference with the `within()` operator
     ///
@@ -130,7 +200,7 @@ impl<A: Adapter> Operators<A> {
     ///             .to("room1")
     ///             .to(["room2", "room3"])
     ///             .to(vec![other_rooms])
-    ///             .emit("test", data);
+    ///             .emit("test", data).await;
     ///     });
     /// });
     pub fn to(mut self, rooms: impl RoomParam) -> Self {
@@ -156,7 +226,7 @@ impl<A: Adapter> Operators<A> {
     ///             .within("room1")
     ///             .within(["room2", "room3"])
     ///             .within(vec![other_rooms])
-    ///             .emit("test", data);
+    ///             .emit("test", data).await;
     ///     });
     /// });
     pub fn within(mut self, rooms: impl RoomParam) -> Self {
@@ -165,6 +235,9 @@ impl<A: Adapter> Operators<A> {
     }
 
     /// Filters out all sockets selected with the previous operators which are in the given rooms.
+    ///
+    /// Socket ids can also be given to exclude specific sockets.
+    /// The exclusion is computed against the room membership at the time of the emission.
     /// #### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -172,24 +245,33 @@ impl<A: Adapter> Operators<A> {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("register1", |socket: SocketRef, Data::<Value>(data)| async move {
-    ///         socket.join("room1");
+    ///         socket.join("room1").await.ok();
     ///     });
     ///     socket.on("register2", |socket: SocketRef, Data::<Value>(data)| async move {
-    ///         socket.join("room2");
+    ///         socket.join("room2").await.ok();
     ///     });
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all sockets in the Namespace
     ///         // except for ones in room1 and the current socket
-    ///         socket.broadcast().except("room1").emit("test", data);
+    ///         socket.broadcast().except("room1").emit("test", data).await;
+    ///     });
+    ///     socket.on("notify", |socket: SocketRef, Data::<Value>(data)| async move {
+    ///         // This message will be broadcast to all sockets in room1 and room2 except the current socket.
+    ///         // It is the same as `socket.to(["room1", "room2"])`
+    ///         socket.within(["room1", "room2"]).except(socket.id).emit("test", data).await;
     ///     });
     /// });
-    pub fn except(mut self, rooms: impl RoomParam) -> Self {
-        self.opts.except.extend(rooms.into_room_iter());
+    pub fn except(mut self, except: impl ExceptParam) -> Self {
+        let (rooms, sids) = except.into_except();
+        self.opts.except.extend(rooms);
+        self.opts.except_sids.extend(sids);
         self.opts.flags.insert(BroadcastFlags::Broadcast);
         self
     }
 
     /// Broadcasts to all sockets only connected on this node (when using multiple nodes).
+    /// It can be combined with the other operators, e.g. `io.to("room").local()` only targets the sockets
+    /// of the room connected on this node, the packet is not sent to the other nodes.
     /// When using the default in-memory adapter, this operator is a no-op.
     /// #### Example
     /// ```
@@ -199,7 +281,7 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all sockets in this namespace and connected on this node
-    ///         socket.local().emit("test", data);
+    ///         socket.local().emit("test", data).await;
     ///     });
     /// });
     pub fn local(mut self) -> Self {
@@ -207,6 +289,26 @@ impl<A: Adapter> Operators<A> {
         self
     }
 
+    /// Marks the message as volatile: it is dropped for the sockets whose buffer is full
+    /// instead of being reported as an [`InternalChannelFull`](crate::SendError::InternalChannelFull) error.
+    ///
+    /// It is useful for data where freshness matters more than delivery, so that slow clients don't build up a backlog.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use serde_json::Value;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
+    ///         // This message will be dropped for the sockets that can't receive it right now
+    ///         socket.broadcast().volatile().emit("position", data).await;
+    ///     });
+    /// });
+    pub fn volatile(mut self) -> Self {
+        self.opts.flags.insert(BroadcastFlags::Volatile);
+        self
+    }
+
     /// Broadcasts to all sockets without any filtering (except the current socket).
     /// #### Example
     /// ```
@@ -216,7 +318,7 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all sockets in this namespace
-    ///         socket.broadcast().emit("test", data);
+    ///         socket.broadcast().emit("test", data).await;
     ///     });
     /// });
     pub fn broadcast(mut self) -> Self {
@@ -241,10 +343,10 @@ impl<A: Adapter> Operators<A> {
     ///             .except("room2")
     ///             .bin(bin)
     ///             .timeout(Duration::from_secs(5))
-    ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
+    ///             .emit_with_ack::<Value>("message-back", data).await.unwrap().for_each(|(sid, ack)| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
-    ///                    Err(err) => println!("Ack error {:?}", err),
+    ///                    Ok(ack) => println!("Ack received from {sid}: {:?}", ack),
+    ///                    Err(err) => println!("Ack error from {sid}: {:?}", err),
     ///                }
     ///             }).await;
     ///    });
@@ -264,7 +366,7 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
     ///         // This will send the binary payload received to all sockets in this namespace with the test message
-    ///         socket.bin(bin).emit("test", data);
+    ///         socket.bin(bin).emit("test", data).await;
     ///     });
     /// });
     pub fn bin(mut self, binary: Vec<Vec<u8>>) -> Self {
@@ -273,6 +375,13 @@ impl<A: Adapter> Operators<A> {
     }
 
     /// Emits a message to all sockets selected with the previous operators.
+    ///
+    /// Returns the number of sockets the message was enqueued to, which is not a delivery confirmation.
+    /// With a distributed adapter, only the sockets of the current server may be counted.
+    /// #### Errors
+    /// * If the data cannot be serialized to JSON, a [`BroadcastError::Serialize`] is returned
+    ///   and the message is not sent to any socket.
+    /// * If the packet could not be enqueued to some of the sockets, a [`BroadcastError::SendError`] is returned.
     /// #### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -281,24 +390,22 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
     ///         // Emit a test message in the room1 and room3 rooms, except for the room2 room with the binary payload received
-    ///         socket.to("room1").to("room3").except("room2").bin(bin).emit("test", data);
+    ///         socket.to("room1").to("room3").except("room2").bin(bin).emit("test", data).await;
     ///     });
     /// });
-    pub fn emit(
-        mut self,
+    pub async fn emit(
+        self,
         event: impl Into<Cow<'static, str>>,
         data: impl serde::Serialize,
-    ) -> Result<(), BroadcastError> {
-        let packet = self.get_packet(event, Some(data))?;
-        if let Err(e) = self.ns.adapter.broadcast(packet, self.opts) {
-            #[cfg(feature = "tracing")]
-            tracing::debug!("broadcast error: {e:?}");
-            return Err(e);
-        }
-        Ok(())
+    ) -> Result<usize, BroadcastError> {
+        let data = serde_json::to_value(data)?;
+        self.emit_value(event.into(), data).await
     }
 
     /// Emits a message to all sockets selected with the previous operators.
+    ///
+    /// Returns the number of sockets the message was enqueued to, which is not a delivery confirmation.
+    /// With a distributed adapter, only the sockets of the current server may be counted.
     /// #### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -307,25 +414,118 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
     ///         // Emit a test message in the room1 and room3 rooms, except for the room2 room with the binary payload received
-    ///         socket.to("room1").to("room3").except("room2").bin(bin).emit("test", data);
+    ///         socket.to("room1").to("room3").except("room2").bin(bin).emit("test", data).await;
     ///     });
     /// });
-    pub fn emit_empty(
+    pub async fn emit_empty(
         mut self,
         event: impl Into<Cow<'static, str>>,
-    ) -> Result<(), BroadcastError> {
+    ) -> Result<usize, BroadcastError> {
+        if !self.resolve_sender_rooms().await? {
+            return Ok(0);
+        }
         let packet = self.get_packet(event, None::<String>)?;
-        if let Err(e) = self.ns.adapter.broadcast(packet, self.opts) {
-            #[cfg(feature = "tracing")]
+        let res = self.ns.adapter.broadcast(packet, self.opts).await;
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
+            tracing::debug!("broadcast error: {e:?}");
+        }
+        res
+    }
+
+    /// Emits a message with arguments mixing JSON values and binary attachments to all sockets selected with the previous operators.
+    ///
+    /// The binary attachments keep their position among the other arguments, see the [`args`](crate::args) module.
+    /// The payloads set with the [`bin`](Self::bin) operator are appended after the arguments.
+    ///
+    /// Returns the number of sockets the message was enqueued to.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, args::Args, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| async move {
+    ///     let args = Args::new().json("metadata").unwrap().bin(vec![1, 2, 3]);
+    ///     socket.broadcast().emit_args("file", args).await.ok();
+    /// });
+    /// ```
+    pub async fn emit_args(
+        mut self,
+        event: impl Into<Cow<'static, str>>,
+        mut args: Args,
+    ) -> Result<usize, BroadcastError> {
+        if !self.resolve_sender_rooms().await? {
+            return Ok(0);
+        }
+        for bin in std::mem::take(&mut self.binary) {
+            args.push(Arg::Bin(bin));
+        }
+        let packet = Packet::args_event(self.ns.path.clone(), event.into(), args);
+        self.ns.buffer_broadcast(&packet, &self.opts);
+        let res = self.ns.adapter.broadcast(packet, self.opts).await;
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
             tracing::debug!("broadcast error: {e:?}");
-            return Err(e);
         }
-        Ok(())
+        res
+    }
+
+    /// Emits a message to all sockets selected with the previous operators and retains it for the namespace,
+    /// like a retained message in MQTT.
+    ///
+    /// The retained message replaces the previous one of the same event and is delivered to each socket
+    /// connecting to the namespace afterwards, right after its connect packet. It lets a server emit some state
+    /// on startup, before any client is connected. The events emitted with [`emit`](Self::emit) are not retained,
+    /// so the choice is made per event.
+    ///
+    /// The selected rooms and flags only apply to the broadcast, the retained message is delivered to every socket
+    /// connecting to this server. It is kept until it is replaced or removed with [`clear_retained`](Self::clear_retained).
+    ///
+    /// Returns the number of sockets the message was enqueued to.
+    /// #### Errors
+    /// If the data cannot be serialized to JSON, a [`BroadcastError::Serialize`] is returned,
+    /// the message is neither sent nor retained.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| println!("Socket connected: {}", socket.id));
+    ///
+    /// // The sockets connecting later receive the last config
+    /// io.of("/").unwrap().emit_buffered("config", "dark-mode").await.ok();
+    /// # }
+    /// ```
+    pub async fn emit_buffered(
+        mut self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl serde::Serialize,
+    ) -> Result<usize, BroadcastError> {
+        let event = event.into();
+        let packet = self.get_packet(event.clone(), Some(data))?;
+        self.ns.retain(event, packet.clone());
+        if !self.resolve_sender_rooms().await? {
+            return Ok(0);
+        }
+        self.broadcast_packet(packet).await
+    }
+
+    /// Removes the message retained for an event with [`emit_buffered`](Self::emit_buffered).
+    ///
+    /// Returns `true` if a message was retained for the event.
+    pub fn clear_retained(self, event: &str) -> bool {
+        self.ns.clear_retained(event)
     }
 
     /// Emits a message to all sockets selected with the previous operators and return a stream of acknowledgements.
     ///
+    /// The stream yields the socket id and the acknowledgement of each socket selected when the message is emitted.
+    /// Sockets joining the selected rooms afterwards are not awaited.
+    ///
     /// Each acknowledgement has a timeout specified in the config (5s by default) or with the `timeout()` operator.
+    /// #### Errors
+    /// If the data cannot be serialized to JSON, a [`BroadcastError::Serialize`] is returned
+    /// and the message is not sent to any socket.
     /// #### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -339,27 +539,33 @@ impl<A: Adapter> Operators<A> {
     ///             .to("room3")
     ///             .except("room2")
     ///             .bin(bin)
-    ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
+    ///             .emit_with_ack::<Value>("message-back", data).await.unwrap().for_each(|(sid, ack)| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
-    ///                    Err(err) => println!("Ack error {:?}", err),
+    ///                    Ok(ack) => println!("Ack received from {sid}: {:?}", ack),
+    ///                    Err(err) => println!("Ack error from {sid}: {:?}", err),
     ///                }
     ///             }).await;
     ///    });
     /// });
-    pub fn emit_with_ack<V: DeserializeOwned + Send>(
+    pub async fn emit_with_ack<V: DeserializeOwned + Send + 'static>(
         mut self,
         event: impl Into<Cow<'static, str>>,
         data: impl serde::Serialize,
-    ) -> Result<BoxStream<'static, Result<AckResponse<V>, AckError>>, BroadcastError> {
+    ) -> Result<AckStream<V>, BroadcastError> {
         let packet = self.get_packet(event, Some(data))?;
-        self.ns.adapter.broadcast_with_ack(packet, self.opts)
+        if !self.resolve_sender_rooms().await? {
+            return Ok(futures::stream::empty().boxed());
+        }
+        self.ns.adapter.broadcast_with_ack(packet, self.opts).await
     }
 
     /// Gets all sockets selected with the previous operators.
     ///
     /// It can be used to retrieve any extension data (with the `extensions` feature enabled) from the sockets or to make some sockets join other rooms.
     ///
+    /// The sockets are a snapshot taken at the time of the call.
+    /// If a socket disconnects afterwards, its handle stays valid but emitting to it returns an error.
+    ///
     /// ### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -367,14 +573,58 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///   socket.on("test", |socket: SocketRef| async move {
     ///     // Find an extension data in each sockets in the room1 and room3 rooms, except for the room2
-    ///     let sockets = socket.within("room1").within("room3").except("room2").sockets().unwrap();
+    ///     let sockets = socket.within("room1").within("room3").except("room2").sockets().await.unwrap();
     ///     for socket in sockets {
     ///         println!("Socket custom string: {:?}", socket.extensions.get::<String>());
     ///     }
     ///   });
     /// });
-    pub fn sockets(self) -> Result<Vec<SocketRef<A>>, A::Error> {
-        self.ns.adapter.fetch_sockets(self.opts)
+    pub async fn sockets(mut self) -> Result<Vec<SocketRef<A>>, A::Error> {
+        if !self.sender_rooms_selected().await? {
+            return Ok(vec![]);
+        }
+        self.ns.adapter.fetch_sockets(self.opts).await
+    }
+
+    /// Gets the number of sockets selected with the previous operators.
+    ///
+    /// When a single room is selected, without any other filter, the size of the room is read directly
+    /// from the adapter with [`Adapter::room_size`] without collecting its sockets. It then includes
+    /// the sockets of the other servers for the adapters sharing their state, unless the [`local`](Self::local) flag is set.
+    ///
+    /// ### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| async move {
+    ///     socket.join("lobby").await.ok();
+    ///     let online = socket.within("lobby").len().await.unwrap();
+    ///     println!("{online} players in the lobby");
+    /// });
+    /// ```
+    pub async fn len(mut self) -> Result<usize, A::Error> {
+        if !self.sender_rooms_selected().await? {
+            return Ok(0);
+        }
+        let opts = &self.opts;
+        let sender_excluded = opts.sid.is_some() && opts.flags.contains(&BroadcastFlags::Broadcast);
+        let local = opts.flags.contains(&BroadcastFlags::Local);
+        if opts.rooms.len() == 1
+            && opts.except.is_empty()
+            && opts.except_sids.is_empty()
+            && !sender_excluded
+            && !local
+        {
+            let room = opts.rooms.iter().next().unwrap().clone();
+            return self.ns.adapter.room_size(room).await;
+        }
+        let sockets = self.ns.adapter.fetch_sockets(self.opts).await?;
+        Ok(sockets.len())
+    }
+
+    /// Returns `true` if no socket is selected with the previous operators, see [`Operators::len`].
+    pub async fn is_empty(self) -> Result<bool, A::Error> {
+        Ok(self.len().await? == 0)
     }
 
     /// Disconnects all sockets selected with the previous operators.
@@ -386,11 +636,14 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///   socket.on("test", |socket: SocketRef| async move {
     ///     // Disconnect all sockets in the room1 and room3 rooms, except for the room2
-    ///     socket.within("room1").within("room3").except("room2").disconnect().unwrap();
+    ///     socket.within("room1").within("room3").except("room2").disconnect().await.unwrap();
     ///   });
     /// });
-    pub fn disconnect(self) -> Result<(), BroadcastError> {
-        self.ns.adapter.disconnect_socket(self.opts)
+    pub async fn disconnect(mut self) -> Result<(), BroadcastError> {
+        if !self.resolve_sender_rooms().await? {
+            return Ok(());
+        }
+        self.ns.adapter.disconnect_socket(self.opts).await
     }
 
     /// Gets a [`SocketRef`] by the specified [`Sid`].
@@ -407,11 +660,14 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///   socket.on("test", |socket: SocketRef| async move {
     ///     // Add all sockets that are in the room1 and room3 to the room4 and room5
-    ///     socket.within("room1").within("room3").join(["room4", "room5"]).unwrap();
+    ///     socket.within("room1").within("room3").join(["room4", "room5"]).await.unwrap();
     ///   });
     /// });
-    pub fn join(self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.ns.adapter.add_sockets(self.opts, rooms)
+    pub async fn join(mut self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        if !self.sender_rooms_selected().await? {
+            return Ok(());
+        }
+        self.ns.adapter.add_sockets(self.opts, rooms).await
     }
 
     /// Makes all sockets selected with the previous operators leave the given room(s).
@@ -423,11 +679,61 @@ impl<A: Adapter> Operators<A> {
     /// io.ns("/", |socket: SocketRef| {
     /// socket.on("test", |socket: SocketRef| async move {
     ///     // Remove all sockets that are in the room1 and room3 from the room4 and room5
-    ///     socket.within("room1").within("room3").leave(["room4", "room5"]).unwrap();
+    ///     socket.within("room1").within("room3").leave(["room4", "room5"]).await.unwrap();
     ///   });
     /// });
-    pub fn leave(self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.ns.adapter.del_sockets(self.opts, rooms)
+    pub async fn leave(mut self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        if !self.sender_rooms_selected().await? {
+            return Ok(());
+        }
+        self.ns.adapter.del_sockets(self.opts, rooms).await
+    }
+
+    /// Emits an already serialized message to all sockets selected with the previous operators.
+    async fn emit_value(
+        mut self,
+        event: Cow<'static, str>,
+        data: serde_json::Value,
+    ) -> Result<usize, BroadcastError> {
+        if !self.resolve_sender_rooms().await? {
+            return Ok(0);
+        }
+        let packet = self.get_value_packet(event, Some(data));
+        self.broadcast_packet(packet).await
+    }
+
+    /// Broadcasts a packet to all sockets selected with the previous operators,
+    /// once the rooms of the sender are resolved.
+    async fn broadcast_packet(self, packet: Packet<'static>) -> Result<usize, BroadcastError> {
+        self.ns.buffer_broadcast(&packet, &self.opts);
+        let res = self.ns.adapter.broadcast(packet, self.opts).await;
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
+            tracing::debug!("broadcast error: {e:?}");
+        }
+        res
+    }
+
+    /// Adds the current rooms of the sender to the selected rooms if they are selected with [`Self::sender_rooms`].
+    ///
+    /// Returns `false` if the sender is in no room, in which case no socket is selected.
+    async fn sender_rooms_selected(&mut self) -> Result<bool, A::Error> {
+        if !self.sender_rooms {
+            return Ok(true);
+        }
+        let Some(sid) = self.opts.sid else {
+            return Ok(false);
+        };
+        let rooms = self.ns.adapter.socket_rooms(sid).await?;
+        self.opts.rooms.extend(rooms);
+        Ok(!self.opts.rooms.is_empty())
+    }
+
+    /// Same as [`Self::sender_rooms_selected`], for the operations returning a [`BroadcastError`].
+    async fn resolve_sender_rooms(&mut self) -> Result<bool, BroadcastError> {
+        self.sender_rooms_selected()
+            .await
+            .map_err(|e| BroadcastError::Adapter(e.into()))
     }
 
     /// Creates a packet with the given event and data.
@@ -436,23 +742,205 @@ impl<A: Adapter> Operators<A> {
         event: impl Into<Cow<'static, str>>,
         data: Option<impl serde::Serialize>,
     ) -> Result<Packet<'static>, serde_json::Error> {
-        let ns = self.ns.path.clone();
-
         let data = match data {
             Some(v) => Some(serde_json::to_value(v)?),
-            None => None
+            None => None,
         };
+        Ok(self.get_value_packet(event.into(), data))
+    }
+
+    /// Creates a packet with the given event and already serialized data.
+    fn get_value_packet(
+        &mut self,
+        event: Cow<'static, str>,
+        data: Option<serde_json::Value>,
+    ) -> Packet<'static> {
+        let ns = self.ns.path.clone();
+        if self.binary.is_empty() {
+            Packet::event(ns, event, data)
+        } else {
+            let binary = std::mem::take(&mut self.binary);
+            Packet::bin_event(ns, event, data, binary)
+        }
+    }
+}
+
+/// Operators selecting the sockets of several namespaces at once, created with [`SocketIo::of_many`].
+///
+/// A client connected to more than one of the namespaces only receives the message once,
+/// on the first of the namespaces it is connected to, in the order they were given.
+///
+/// [`SocketIo::of_many`]: crate::SocketIo::of_many
+#[derive(Debug)]
+pub struct MultiOperators<A: Adapter = LocalAdapter> {
+    ops: Vec<Operators<A>>,
+}
+
+impl<A: Adapter> MultiOperators<A> {
+    pub(crate) fn new(ops: Vec<Operators<A>>) -> Self {
+        Self { ops }
+    }
+
+    /// Filters out the sockets which are in the given rooms, in each of the namespaces.
+    pub fn except(self, except: impl ExceptParam) -> Self {
+        let (rooms, sids) = except.into_except();
+        self.map(|op| op.except(rooms.clone()).except(sids.clone()))
+    }
+
+    /// Broadcasts only to the sockets connected to this server, see [`Operators::local`].
+    pub fn local(self) -> Self {
+        self.map(Operators::local)
+    }
+
+    /// Sets the message as volatile, see [`Operators::volatile`].
+    pub fn volatile(self) -> Self {
+        self.map(Operators::volatile)
+    }
+
+    /// Adds a binary payload to the message.
+    pub fn bin(self, binary: Vec<Vec<u8>>) -> Self {
+        self.map(|op| op.bin(binary.clone()))
+    }
+
+    /// Emits a message to the sockets of all the selected namespaces.
+    ///
+    /// The data is serialized once and reused for each namespace.
+    /// Returns the number of sockets the message was enqueued to.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/chat", || {});
+    /// io.ns("/admin", || {});
+    /// io.of_many(["/chat", "/admin"]).emit("maintenance", "in 5 minutes").await.unwrap();
+    /// # }
+    /// ```
+    pub async fn emit(
+        self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl serde::Serialize,
+    ) -> Result<usize, BroadcastError> {
+        let event = event.into();
+        let data = serde_json::to_value(data)?;
+        let mut reached: Vec<Sid> = Vec::new();
+        let mut count = 0;
+        let mut errors = Vec::new();
+        let last = self.ops.len().saturating_sub(1);
+        for (i, op) in self.ops.into_iter().enumerate() {
+            let op = op.except(reached.clone());
+            if i < last {
+                let sockets = op
+                    .ns
+                    .adapter
+                    .fetch_sockets(op.opts.clone())
+                    .await
+                    .map_err(|e| BroadcastError::Adapter(e.into()))?;
+                reached.extend(sockets.iter().map(|s| s.id));
+            }
+            match op.emit_value(event.clone(), data.clone()).await {
+                Ok(sent) => count += sent,
+                Err(BroadcastError::SendError(e)) => errors.extend(e),
+                Err(e) => return Err(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors.into())
+        }
+    }
+
+    fn map(self, f: impl Fn(Operators<A>) -> Operators<A>) -> Self {
+        Self {
+            ops: self.ops.into_iter().map(f).collect(),
+        }
+    }
+}
+
+/// Operators selecting a list of sockets by their id, created with [`SocketIo::to_sockets`].
+///
+/// The message is serialized a single time, the encoded packet being shared between all the sockets.
+/// It is encoded at most once more if some of the sockets use a different [`parser`](crate::parser).
+///
+/// [`SocketIo::to_sockets`]: crate::SocketIo::to_sockets
+#[derive(Debug)]
+pub struct SocketsOperators<A: Adapter = LocalAdapter> {
+    ns: Arc<Namespace<A>>,
+    sids: Vec<Sid>,
+    binary: Vec<Vec<u8>>,
+}
+
+impl<A: Adapter> SocketsOperators<A> {
+    pub(crate) fn new(ns: Arc<Namespace<A>>, sids: impl IntoIterator<Item = Sid>) -> Self {
+        let mut sids: Vec<Sid> = sids.into_iter().collect();
+        sids.sort_unstable();
+        sids.dedup();
+        Self {
+            ns,
+            sids,
+            binary: vec![],
+        }
+    }
+
+    /// Adds a binary payload to the message.
+    pub fn bin(mut self, binary: Vec<Vec<u8>>) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    /// Emits a message to the selected sockets.
+    ///
+    /// The sockets that are not connected to the namespace are ignored.
+    /// Returns the number of sockets the message was enqueued to.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// let io2 = io.clone();
+    /// io.ns("/", move |socket: SocketRef| {
+    ///     let io = io2.clone();
+    ///     socket.on("notify", move |Data::<Vec<String>>(ids)| {
+    ///         let sids = ids.iter().filter_map(|id| id.parse().ok());
+    ///         io.to_sockets(sids).emit("notification", "hello").ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn emit(
+        self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl serde::Serialize,
+    ) -> Result<usize, BroadcastError> {
+        let data = Some(serde_json::to_value(data)?);
+        let ns = self.ns.path.clone();
         let packet = if self.binary.is_empty() {
             Packet::event(ns, event.into(), data)
         } else {
-            let binary = std::mem::take(&mut self.binary);
-            Packet::bin_event(ns, event.into(), data, binary)
+            Packet::bin_event(ns, event.into(), data, self.binary)
         };
-        Ok(packet)
+        let mut packet = EncodedPacket::new(packet);
+        let mut count = 0;
+        let mut errors = Vec::new();
+        for sid in self.sids {
+            let Ok(socket) = self.ns.get_socket(sid) else {
+                continue;
+            };
+            match socket.send_encoded(&mut packet) {
+                Ok(()) => count += 1,
+                Err(SendError::Serialize(e)) => return Err(e.into()),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(count)
+        } else {
+            Err(errors.into())
+        }
     }
 }
 
-#[cfg(feature = "test-utils")]
+#[cfg(any(test, feature = "test-utils"))]
 impl<A: Adapter> Operators<A> {
     #[allow(dead_code)]
     pub(crate) fn is_broadcast(&self) -> bool {
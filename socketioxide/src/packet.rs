@@ -1,10 +1,13 @@
+//! The socket.io packets, encoded and decoded by the [`Parser`](crate::parser::Parser) of each session.
 use std::borrow::Cow;
 
 use crate::ProtocolVersion;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::errors::Error;
+use crate::args::Args;
+use crate::errors::ParseError;
+use crate::serializer::{DefaultSerializer as Json, Serializer};
 use engineioxide::sid::Sid;
 
 /// The socket.io packet type.
@@ -19,21 +22,28 @@ pub struct Packet<'a> {
 
 impl<'a> Packet<'a> {
     /// Send a connect packet with a default payload for v5 and no payload for v4
-    pub fn connect(
+    pub fn connect(ns: &'a str, sid: Sid, protocol: ProtocolVersion) -> Self {
+        Self::connect_with_pid(ns, sid, None, protocol)
+    }
+
+    /// Create a connect packet for the given namespace with the private session id
+    /// used to recover the session. It is ignored with the protocol v4.
+    pub(crate) fn connect_with_pid(
         ns: &'a str,
         #[allow(unused_variables)] sid: Sid,
+        #[allow(unused_variables)] pid: Option<Sid>,
         #[allow(unused_variables)] protocol: ProtocolVersion,
     ) -> Self {
         #[cfg(not(feature = "v4"))]
         {
-            Self::connect_v5(ns, sid)
+            Self::connect_v5(ns, sid, pid)
         }
 
         #[cfg(feature = "v4")]
         {
             match protocol {
                 ProtocolVersion::V4 => Self::connect_v4(ns),
-                ProtocolVersion::V5 => Self::connect_v5(ns, sid),
+                ProtocolVersion::V5 => Self::connect_v5(ns, sid, pid),
             }
         }
     }
@@ -48,8 +58,8 @@ impl<'a> Packet<'a> {
     }
 
     /// Sends a connect packet with payload.
-    fn connect_v5(ns: &'a str, sid: Sid) -> Self {
-        let val = serde_json::to_string(&ConnectPacket { sid }).unwrap();
+    fn connect_v5(ns: &'a str, sid: Sid, pid: Option<Sid>) -> Self {
+        let val = Json::to_string(&ConnectPacket { sid, pid }).unwrap();
         Self {
             inner: PacketData::Connect(Some(val)),
             ns: Cow::Borrowed(ns),
@@ -59,23 +69,61 @@ impl<'a> Packet<'a> {
     /// Create a disconnect packet for the given namespace
     pub fn disconnect(ns: &'a str) -> Self {
         Self {
-            inner: PacketData::Disconnect,
+            inner: PacketData::Disconnect(None),
             ns: Cow::Borrowed(ns),
         }
     }
+
+    /// Create a disconnect packet for the given namespace with a reason readable by the client
+    pub fn disconnect_with_reason(ns: &'a str, reason: impl Into<String>) -> Self {
+        Self {
+            inner: PacketData::Disconnect(Some(reason.into())),
+            ns: Cow::Borrowed(ns),
+        }
+    }
+
+    /// Converts the packet to a `'static` packet, cloning its borrowed namespace and event name
+    pub(crate) fn into_owned(self) -> Packet<'static> {
+        let inner = match self.inner {
+            PacketData::Connect(data) => PacketData::Connect(data),
+            PacketData::Disconnect(reason) => PacketData::Disconnect(reason),
+            PacketData::Event(e, data, ack) => {
+                PacketData::Event(Cow::Owned(e.into_owned()), data, ack)
+            }
+            PacketData::EventAck(data, ack) => PacketData::EventAck(data, ack),
+            PacketData::ConnectError(message) => PacketData::ConnectError(message),
+            PacketData::BinaryEvent(e, bin, ack) => {
+                PacketData::BinaryEvent(Cow::Owned(e.into_owned()), bin, ack)
+            }
+            PacketData::BinaryAck(bin, ack) => PacketData::BinaryAck(bin, ack),
+        };
+        Packet {
+            inner,
+            ns: Cow::Owned(self.ns.into_owned()),
+        }
+    }
 }
 
 impl<'a> Packet<'a> {
     /// Create a connect error packet for the given namespace
     pub fn invalid_namespace(ns: &'a str) -> Self {
+        Self::connect_error(ns, "Invalid namespace")
+    }
+
+    /// Create a connect error packet for the given namespace with a custom message
+    pub fn connect_error(ns: &'a str, message: impl Into<String>) -> Self {
         Self {
-            inner: PacketData::ConnectError,
+            inner: PacketData::ConnectError(message.into()),
             ns: Cow::Borrowed(ns),
         }
     }
 
     /// Create an event packet for the given namespace
-    pub fn event(ns: impl Into<Cow<'a, str>>, e: impl Into<Cow<'a, str>>, data: Option<Value>) -> Self {
+    pub fn event(
+        ns: impl Into<Cow<'a, str>>,
+        e: impl Into<Cow<'a, str>>,
+        data: Option<Value>,
+    ) -> Self {
         Self {
             inner: PacketData::Event(e.into(), data, None),
             ns: ns.into(),
@@ -83,6 +131,9 @@ impl<'a> Packet<'a> {
     }
 
     /// Create a binary event packet for the given namespace
+    ///
+    /// A `null` data is encoded as no argument before the binary placeholders, like a binary ack.
+    /// To send a `null` argument it should be wrapped in an array: `json!([null])`.
     pub fn bin_event(
         ns: impl Into<Cow<'a, str>>,
         e: impl Into<Cow<'a, str>>,
@@ -98,6 +149,22 @@ impl<'a> Packet<'a> {
         }
     }
 
+    /// Create an event packet from arguments mixing JSON values and binary attachments.
+    /// It is a binary event if there is at least one binary argument.
+    pub fn args_event(ns: impl Into<Cow<'a, str>>, e: impl Into<Cow<'a, str>>, args: Args) -> Self {
+        let (data, bin) = args.into_parts();
+        let inner = if bin.is_empty() {
+            PacketData::Event(e.into(), Some(data), None)
+        } else {
+            let packet = BinaryPacket::outgoing_with_placeholders(data, bin);
+            PacketData::BinaryEvent(e.into(), packet, None)
+        };
+        Self {
+            inner,
+            ns: ns.into(),
+        }
+    }
+
     /// Create an ack packet for the given namespace
     pub fn ack(ns: &'a str, data: Value, ack: i64) -> Self {
         Self {
@@ -130,7 +197,7 @@ impl<'a> Packet<'a> {
         let data_size = match &self.inner {
             Connect(Some(data)) => data.len(),
             Connect(None) => 0,
-            Disconnect => 0,
+            Disconnect(_) => 0,
             Event(_, _, Some(ack)) => {
                 ack.checked_ilog10().unwrap_or(0) as usize + ACK_PUNCTUATION_SIZE
             }
@@ -151,7 +218,7 @@ impl<'a> Packet<'a> {
                     + ACK_PUNCTUATION_SIZE
                     + BINARY_PUNCTUATION_SIZE
             }
-            ConnectError => 31,
+            ConnectError(_) => 0,
         };
 
         let nsp_size = if self.ns == "/" {
@@ -178,14 +245,15 @@ impl<'a> Packet<'a> {
 pub enum PacketData<'a> {
     /// Connect packet with optional payload (only used with v5 for response)
     Connect(Option<String>),
-    /// Disconnect packet, used to disconnect from a namespace
-    Disconnect,
+    /// Disconnect packet with an optional reason, used to disconnect from a namespace
+    Disconnect(Option<String>),
     /// Event packet with optional ack id, to request an ack from the other side
     Event(Cow<'a, str>, Option<Value>, Option<i64>),
     /// Event ack packet, to acknowledge an event
     EventAck(Value, i64),
-    /// Connect error packet, sent when the namespace is invalid
-    ConnectError,
+    /// Connect error packet with its error message,
+    /// sent when the namespace is invalid or when the connection is rejected
+    ConnectError(String),
     /// Binary event packet with optional ack id, to request an ack from the other side
     BinaryEvent(Cow<'a, str>, BinaryPacket, Option<i64>),
     /// Binary ack packet, to acknowledge an event with binary data
@@ -201,16 +269,18 @@ pub struct BinaryPacket {
     pub bin: Vec<Vec<u8>>,
     /// The number of expected payloads (used when receiving data)
     payload_count: usize,
+    /// The positions of the placeholders removed from the incoming data, with the index of their payload
+    placeholders: Vec<(usize, usize)>,
 }
 
 impl<'a> PacketData<'a> {
     fn index(&self) -> char {
         match self {
             PacketData::Connect(_) => '0',
-            PacketData::Disconnect => '1',
+            PacketData::Disconnect(_) => '1',
             PacketData::Event(_, _, _) => '2',
             PacketData::EventAck(_, _) => '3',
-            PacketData::ConnectError => '4',
+            PacketData::ConnectError(_) => '4',
             PacketData::BinaryEvent(_, _, _) => '5',
             PacketData::BinaryAck(_, _) => '6',
         }
@@ -239,30 +309,40 @@ impl<'a> PacketData<'a> {
 impl BinaryPacket {
     /// Create a binary packet from incoming data and remove all placeholders and get the payload count
     pub fn incoming(mut data: Value) -> Self {
-        let payload_count = match &mut data {
+        let placeholders = match &mut data {
             Value::Array(ref mut v) => {
-                let count = v.len();
-                v.retain(|v| v.as_object().and_then(|o| o.get("_placeholder")).is_none());
-                count - v.len()
+                let placeholders: Vec<_> = v
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| Some((i, placeholder_num(v)?)))
+                    .collect();
+                v.retain(|v| placeholder_num(v).is_none());
+                placeholders
             }
-            val => {
-                if val
-                    .as_object()
-                    .and_then(|o| o.get("_placeholder"))
-                    .is_some()
-                {
+            val => match placeholder_num(val) {
+                Some(num) => {
                     data = Value::Array(vec![]);
-                    1
-                } else {
-                    0
+                    vec![(0, num)]
                 }
-            }
+                None => vec![],
+            },
         };
 
         Self {
             data: Some(data),
             bin: Vec::new(),
-            payload_count,
+            payload_count: placeholders.len(),
+            placeholders,
+        }
+    }
+
+    /// Create a binary packet from outgoing data already containing the placeholders of the payloads
+    pub(crate) fn outgoing_with_placeholders(data: Value, bin: Vec<Vec<u8>>) -> Self {
+        Self {
+            data: Some(data),
+            payload_count: bin.len(),
+            bin,
+            placeholders: vec![],
         }
     }
 
@@ -270,8 +350,8 @@ impl BinaryPacket {
     pub fn outgoing(data: Option<Value>, bin: Vec<Vec<u8>>) -> Self {
         let mut data = match data {
             Some(Value::Array(v)) => Value::Array(v),
+            Some(Value::Null) | None => Value::Array(vec![]),
             Some(d) => Value::Array(vec![d]),
-            None => Value::Array(vec![]),
         };
         let payload_count = bin.len();
         (0..payload_count).for_each(|i| {
@@ -285,6 +365,7 @@ impl BinaryPacket {
             data: Some(data),
             bin,
             payload_count,
+            placeholders: vec![],
         }
     }
 
@@ -297,6 +378,23 @@ impl BinaryPacket {
     pub fn is_complete(&self) -> bool {
         self.payload_count == self.bin.len()
     }
+
+    /// The number of expected payloads
+    pub fn payload_count(&self) -> usize {
+        self.payload_count
+    }
+
+    /// The positions of the placeholders removed from the incoming data, with the index of their payload
+    pub(crate) fn placeholders(&self) -> &[(usize, usize)] {
+        &self.placeholders
+    }
+}
+
+/// Returns the index of the payload if the value is a placeholder
+fn placeholder_num(value: &Value) -> Option<usize> {
+    let value = value.as_object()?;
+    value.get("_placeholder")?;
+    Some(value.get("num").and_then(Value::as_u64).unwrap_or(0) as usize)
 }
 
 impl<'a> TryInto<String> for Packet<'a> {
@@ -313,32 +411,38 @@ impl<'a> TryInto<String> for Packet<'a> {
                 let packet = match data {
                     Some(Value::Array(ref mut v)) if !v.is_empty() => {
                         v.insert(0, Value::String((*e).to_string()));
-                        serde_json::to_string(&v)
+                        Json::to_string(&v)
                     }
-                    Some(Value::Array(_)) => serde_json::to_string::<(_, [(); 0])>(&(e, [])),
-                    Some(_) => serde_json::to_string(&(e, data)),
-                    None => serde_json::to_string(&vec![e]),
+                    Some(Value::Array(_)) => Json::to_string::<(_, [(); 0])>(&(e, [])),
+                    Some(_) => Json::to_string(&(e, data)),
+                    None => Json::to_string(&vec![e]),
                 }?;
                 Some(packet)
             }
             EventAck(data, _) => {
                 // Enforce that the packet is an array -> [data]
                 let packet = match data {
-                    Value::Array(_) => serde_json::to_string(&data),
+                    Value::Array(_) => Json::to_string(&data),
                     Value::Null => Ok("[]".to_string()),
-                    _ => serde_json::to_string(&[data]),
+                    _ => Json::to_string(&[data]),
                 }?;
                 Some(packet)
             }
             BinaryAck(BinaryPacket { data, .. }, _) => {
                 // Enforce that the packet is an array -> [data]
                 let packet = match data {
-                    Some(Value::Array(_)) => serde_json::to_string(&data),
+                    Some(Value::Array(_)) => Json::to_string(&data),
                     Some(Value::Null) | None => Ok("[]".to_string()),
-                    _ => serde_json::to_string(&[data]),
+                    _ => Json::to_string(&[data]),
                 }?;
                 Some(packet)
             }
+            ConnectError(message) => Some(Json::to_string(&ConnectErrorPacket {
+                message: message.as_str(),
+            })?),
+            Disconnect(Some(reason)) => Some(Json::to_string(&DisconnectPacket {
+                reason: Cow::Borrowed(reason),
+            })?),
             _ => None,
         };
 
@@ -366,7 +470,8 @@ impl<'a> TryInto<String> for Packet<'a> {
 
         match self.inner {
             PacketData::Connect(Some(data)) => res.push_str(&data),
-            PacketData::Disconnect | PacketData::Connect(None) => (),
+            PacketData::Disconnect(None) | PacketData::Connect(None) => (),
+            PacketData::Disconnect(Some(_)) => res.push_str(&data.unwrap()),
             PacketData::Event(_, _, ack) => {
                 if let Some(ack) = ack {
                     res.push_str(itoa_buf.format(ack));
@@ -378,7 +483,7 @@ impl<'a> TryInto<String> for Packet<'a> {
                 res.push_str(itoa_buf.format(ack));
                 res.push_str(&data.unwrap())
             }
-            PacketData::ConnectError => res.push_str("{\"message\":\"Invalid namespace\"}"),
+            PacketData::ConnectError(_) => res.push_str(&data.unwrap()),
             PacketData::BinaryEvent(_, bin, ack) => {
                 res.push_str(itoa_buf.format(bin.payload_count));
                 res.push('-');
@@ -409,31 +514,44 @@ impl<'a> TryInto<String> for Packet<'a> {
 /// ```text
 /// ["<event name>", ...<JSON-stringified payload without binary>]
 /// ```
-fn deserialize_event_packet(data: &str) -> Result<(String, Value), Error> {
+/// The `offset` of the payload in the packet is used to report errors.
+fn deserialize_event_packet(data: &mut [u8], offset: usize) -> Result<(String, Value), ParseError> {
     #[cfg(feature = "tracing")]
-    tracing::debug!("Deserializing event packet: {:?}", data);
-    let packet = match serde_json::from_str::<Value>(data)? {
-        Value::Array(packet) => packet,
-        _ => return Err(Error::InvalidEventName),
+    tracing::debug!(
+        "Deserializing event packet: {:?}",
+        String::from_utf8_lossy(data)
+    );
+    let packet = match deserialize_packet::<Value>(data, offset)? {
+        Some(Value::Array(packet)) => packet,
+        Some(_) => return Err(ParseError::InvalidEventName { offset }),
+        None => return Err(ParseError::MissingPayload { offset }),
     };
 
     let event = packet
-        .get(0)
-        .ok_or(Error::InvalidEventName)?
-        .as_str()
-        .ok_or(Error::InvalidEventName)?
+        .first()
+        .and_then(Value::as_str)
+        .ok_or(ParseError::InvalidEventName { offset })?
         .to_string();
     let payload = Value::from_iter(packet.into_iter().skip(1));
     Ok((event, payload))
 }
 
-fn deserialize_packet<T: DeserializeOwned>(data: &str) -> Result<Option<T>, serde_json::Error> {
+/// Deserialize the payload of a packet, `None` if it is empty.
+/// The `offset` of the payload in the packet is used to report errors.
+fn deserialize_packet<T: DeserializeOwned>(
+    data: &mut [u8],
+    offset: usize,
+) -> Result<Option<T>, ParseError> {
     #[cfg(feature = "tracing")]
-    tracing::debug!("Deserializing packet: {:?}", data);
+    tracing::debug!("Deserializing packet: {:?}", String::from_utf8_lossy(data));
     let packet = if data.is_empty() {
         None
     } else {
-        Some(serde_json::from_str(data)?)
+        let packet = Json::from_slice(data).map_err(|e| ParseError::InvalidPayload {
+            offset,
+            reason: e.to_string(),
+        })?;
+        Some(packet)
     };
     Ok(packet)
 }
@@ -445,22 +563,22 @@ fn deserialize_packet<T: DeserializeOwned>(data: &str) -> Result<Option<T>, serd
 /// + binary attachments extracted
 /// ```
 impl<'a> TryFrom<String> for Packet<'a> {
-    type Error = Error;
+    type Error = ParseError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
         // It is possible to parse the packet from a byte slice because separators are only ASCII
         let chars = value.as_bytes();
         let mut i = 1;
-        let index = (b'0'..=b'6')
-            .contains(&chars[0])
-            .then_some(chars[0])
-            .ok_or(Error::InvalidPacketType)?;
+        let index = *chars.first().ok_or(ParseError::Empty)?;
 
         // Move the cursor to skip the payload count if it is a binary packet
         if index == b'5' || index == b'6' {
-            while chars.get(i) != Some(&b'-') {
+            while matches!(chars.get(i), Some(c) if c.is_ascii_digit()) {
                 i += 1;
             }
+            if i == 1 || chars.get(i) != Some(&b'-') {
+                return Err(ParseError::InvalidAttachmentsCount { offset: i });
+            }
             i += 1;
         }
 
@@ -490,35 +608,58 @@ impl<'a> TryFrom<String> for Packet<'a> {
         let ack: Option<i64> = loop {
             match chars.get(i) {
                 Some(c) if c.is_ascii_digit() => i += 1,
-                Some(b'[' | b'{') if i > start_index => break value[start_index..i].parse().ok(),
+                // The ack id can end the packet when the payload is missing
+                Some(b'[' | b'{') | None if i > start_index => {
+                    match value[start_index..i].parse() {
+                        Ok(ack) => break Some(ack),
+                        Err(_) => {
+                            return Err(ParseError::InvalidAckId {
+                                offset: start_index,
+                            })
+                        }
+                    }
+                }
                 _ => break None,
             }
         };
 
-        let data = &value[i..];
+        // The payload is parsed in place because some serializers may modify it
+        let mut value = value.into_bytes();
+        let data = &mut value[i..];
+        let missing_ack = ParseError::MissingAckId {
+            offset: start_index,
+        };
         let inner = match index {
-            b'0' => PacketData::Connect((!data.is_empty()).then(|| data.to_string())),
-            b'1' => PacketData::Disconnect,
+            b'0' => PacketData::Connect(
+                (!data.is_empty()).then(|| String::from_utf8_lossy(data).into_owned()),
+            ),
+            b'1' => PacketData::Disconnect(
+                Json::from_slice::<DisconnectPacket<'static>>(data)
+                    .ok()
+                    .map(|p| p.reason.into_owned()),
+            ),
             b'2' => {
-                let (event, payload) = deserialize_event_packet(data)?;
+                let (event, payload) = deserialize_event_packet(data, i)?;
                 PacketData::Event(event.into(), Some(payload), ack)
             }
             b'3' => {
-                let packet = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
-                PacketData::EventAck(packet, ack.ok_or(Error::InvalidPacketType)?)
+                let ack = ack.ok_or(missing_ack)?;
+                let packet =
+                    deserialize_packet(data, i)?.ok_or(ParseError::MissingPayload { offset: i })?;
+                PacketData::EventAck(packet, ack)
             }
             b'5' => {
-                let (event, payload) = deserialize_event_packet(data)?;
+                let (event, payload) = deserialize_event_packet(data, i)?;
                 PacketData::BinaryEvent(event.into(), BinaryPacket::incoming(payload), ack)
             }
             b'6' => {
-                let packet = deserialize_packet(data)?.ok_or(Error::InvalidPacketType)?;
-                PacketData::BinaryAck(
-                    BinaryPacket::incoming(packet),
-                    ack.ok_or(Error::InvalidPacketType)?,
-                )
+                let ack = ack.ok_or(missing_ack)?;
+                let packet =
+                    deserialize_packet(data, i)?.ok_or(ParseError::MissingPayload { offset: i })?;
+                PacketData::BinaryAck(BinaryPacket::incoming(packet), ack)
             }
-            _ => return Err(Error::InvalidPacketType),
+            // The connect error packets are only sent by the server
+            _ => return Err(ParseError::InvalidPacketType { offset: 0 }),
         };
 
         Ok(Self { inner, ns })
@@ -529,6 +670,20 @@ impl<'a> TryFrom<String> for Packet<'a> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectPacket {
     sid: Sid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<Sid>,
+}
+
+/// Connect error packet sent to the client
+#[derive(Debug, Serialize)]
+struct ConnectErrorPacket<'a> {
+    message: &'a str,
+}
+
+/// Disconnect packet payload carrying the reason of the disconnection
+#[derive(Debug, Serialize, Deserialize)]
+struct DisconnectPacket<'a> {
+    reason: Cow<'a, str>,
 }
 
 #[cfg(test)]
@@ -578,6 +733,10 @@ mod test {
         let payload = "1/admin™,".to_string();
         let packet = Packet::try_from(payload).unwrap();
         assert_eq!(Packet::disconnect("/admin™"), packet);
+
+        let payload = r#"1/admin™,{"reason":"kicked"}"#.to_string();
+        let packet = Packet::try_from(payload).unwrap();
+        assert_eq!(Packet::disconnect_with_reason("/admin™", "kicked"), packet);
     }
 
     #[test]
@@ -589,6 +748,18 @@ mod test {
         let payload = "1/admin™,".to_string();
         let packet: String = Packet::disconnect("/admin™").try_into().unwrap();
         assert_eq!(packet, payload);
+
+        let payload = r#"1{"reason":"duplicate login"}"#.to_string();
+        let packet: String = Packet::disconnect_with_reason("/", "duplicate login")
+            .try_into()
+            .unwrap();
+        assert_eq!(packet, payload);
+
+        let payload = r#"1/admin™,{"reason":"duplicate login"}"#.to_string();
+        let packet: String = Packet::disconnect_with_reason("/admin™", "duplicate login")
+            .try_into()
+            .unwrap();
+        assert_eq!(packet, payload);
     }
 
     // Event(String, Value, Option<i64>),
@@ -624,7 +795,8 @@ mod test {
         let mut packet = Packet::try_from(payload).unwrap();
         packet.inner.set_ack_id(1);
 
-        let mut comparison_packet = Packet::event("/admin™", "event", Some(json!([{"data": "value™"}])));
+        let mut comparison_packet =
+            Packet::event("/admin™", "event", Some(json!([{"data": "value™"}])));
         comparison_packet.inner.set_ack_id(1);
 
         assert_eq!(packet, comparison_packet);
@@ -641,7 +813,9 @@ mod test {
 
         // Encode empty data
         let payload = format!("2{}", json!(["event", []]));
-        let packet: String = Packet::event("/", "event", Some(json!([]))).try_into().unwrap();
+        let packet: String = Packet::event("/", "event", Some(json!([])))
+            .try_into()
+            .unwrap();
 
         assert_eq!(packet, payload);
 
@@ -711,6 +885,12 @@ mod test {
         let payload = format!("4/admin™,{}", json!({ "message": "Invalid namespace" }));
         let packet: String = Packet::invalid_namespace("/admin™").try_into().unwrap();
         assert_eq!(packet, payload);
+
+        let payload = format!("4{}", json!({ "message": "invalid \"auth\"" }));
+        let packet: String = Packet::connect_error("/", "invalid \"auth\"")
+            .try_into()
+            .unwrap();
+        assert_eq!(packet, payload);
     }
 
     // BinaryEvent(String, BinaryPacket, Option<i64>),
@@ -719,33 +899,39 @@ mod test {
         let json = json!(["event", { "data": "value™" }, { "_placeholder": true, "num": 0}]);
 
         let payload = format!("51-{}", json);
-        let packet: String =
-            Packet::bin_event("/", "event", Some(json!({ "data": "value™" })), vec![vec![1]])
-                .try_into()
-                .unwrap();
+        let packet: String = Packet::bin_event(
+            "/",
+            "event",
+            Some(json!({ "data": "value™" })),
+            vec![vec![1]],
+        )
+        .try_into()
+        .unwrap();
 
         assert_eq!(packet, payload);
 
         let payload = format!("51-{}", json!(["event", { "_placeholder": true, "num": 0}]));
-        let packet: String =
-            Packet::bin_event("/", "event", None, vec![vec![1]])
-                .try_into()
-                .unwrap();
+        let packet: String = Packet::bin_event("/", "event", None, vec![vec![1]])
+            .try_into()
+            .unwrap();
 
         assert_eq!(packet, payload);
 
         let payload = format!("51-{}", json!(["event", { "_placeholder": true, "num": 0}]));
-        let packet: String =
-            Packet::bin_event("/", "event", Some(().into()), vec![vec![1]])
-                .try_into()
-                .unwrap();
+        let packet: String = Packet::bin_event("/", "event", Some(().into()), vec![vec![1]])
+            .try_into()
+            .unwrap();
 
         assert_eq!(packet, payload);
 
         // Encode with ack ID
         let payload = format!("51-254{}", json);
-        let mut packet =
-            Packet::bin_event("/", "event", Some(json!({ "data": "value™" })), vec![vec![1]]);
+        let mut packet = Packet::bin_event(
+            "/",
+            "event",
+            Some(json!({ "data": "value™" })),
+            vec![vec![1]],
+        );
         packet.inner.set_ack_id(254);
         let packet: String = packet.try_into().unwrap();
 
@@ -777,6 +963,25 @@ mod test {
         assert_eq!(packet, payload);
     }
 
+    #[test]
+    fn packet_encode_binary_event_null_data() {
+        let payload = format!("51-{}", json!(["event", { "_placeholder": true, "num": 0}]));
+        let packet: String = Packet::bin_event("/", "event", Some(Value::Null), vec![vec![1]])
+            .try_into()
+            .unwrap();
+        assert_eq!(packet, payload);
+
+        // A null argument is kept if it is wrapped in an array
+        let payload = format!(
+            "51-{}",
+            json!(["event", null, { "_placeholder": true, "num": 0}])
+        );
+        let packet: String = Packet::bin_event("/", "event", Some(json!([null])), vec![vec![1]])
+            .try_into()
+            .unwrap();
+        assert_eq!(packet, payload);
+    }
+
     #[test]
     fn packet_decode_binary_event() {
         let json = json!(["event", { "data": "value™" }, { "_placeholder": true, "num": 0}]);
@@ -787,6 +992,7 @@ mod test {
                     bin: vec![vec![1]],
                     data: Some(json!([{"data": "value™"}])),
                     payload_count: 1,
+                    placeholders: vec![(1, 0)],
                 },
                 ack,
             ),
@@ -795,9 +1001,8 @@ mod test {
 
         let payload = format!("51-{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryEvent(_, ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
 
         assert_eq!(packet, comparison_packet(None, "/"));
@@ -805,9 +1010,8 @@ mod test {
         // Check with ack ID
         let payload = format!("51-254{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryEvent(_, ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
 
         assert_eq!(packet, comparison_packet(Some(254), "/"));
@@ -815,9 +1019,8 @@ mod test {
         // Check with NS
         let payload = format!("51-/admin™,{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryEvent(_, ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
 
         assert_eq!(packet, comparison_packet(None, "/admin™"));
@@ -825,9 +1028,8 @@ mod test {
         // Check with ack ID and NS
         let payload = format!("51-/admin™,254{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryEvent(_, ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
         assert_eq!(packet, comparison_packet(Some(254), "/admin™"));
     }
@@ -863,6 +1065,7 @@ mod test {
                     bin: vec![vec![1]],
                     data: Some(json!([{"data": "value™"}])),
                     payload_count: 1,
+                    placeholders: vec![(1, 0)],
                 },
                 ack,
             ),
@@ -871,9 +1074,8 @@ mod test {
 
         let payload = format!("61-54{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryAck(ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryAck(ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
 
         assert_eq!(packet, comparison_packet(54, "/"));
@@ -881,9 +1083,8 @@ mod test {
         // Check with NS
         let payload = format!("61-/admin™,54{}", json);
         let mut packet = Packet::try_from(payload).unwrap();
-        match packet.inner {
-            PacketData::BinaryAck(ref mut bin, _) => bin.add_payload(vec![1]),
-            _ => (),
+        if let PacketData::BinaryAck(ref mut bin, _) = packet.inner {
+            bin.add_payload(vec![1]);
         }
 
         assert_eq!(packet, comparison_packet(54, "/admin™"));
@@ -892,15 +1093,20 @@ mod test {
     #[test]
     fn packet_size_hint() {
         let sid = Sid::new();
-        let len = serde_json::to_string(&ConnectPacket { sid }).unwrap().len();
+        let len = serde_json::to_string(&ConnectPacket { sid, pid: None })
+            .unwrap()
+            .len();
         let packet = Packet::connect("/", sid, ProtocolVersion::V5);
         assert_eq!(packet.get_size_hint(), len + 1);
 
         let packet = Packet::connect("/admin", sid, ProtocolVersion::V5);
         assert_eq!(packet.get_size_hint(), len + 8);
 
-        let packet = Packet::connect("admin", sid, ProtocolVersion::V4);
-        assert_eq!(packet.get_size_hint(), 8);
+        #[cfg(feature = "v4")]
+        {
+            let packet = Packet::connect("admin", sid, ProtocolVersion::V4);
+            assert_eq!(packet.get_size_hint(), 8);
+        }
 
         let packet = Packet::disconnect("/");
         assert_eq!(packet.get_size_hint(), 1);
@@ -920,7 +1126,12 @@ mod test {
         let packet = Packet::ack("/admin", json!("data"), 54);
         assert_eq!(packet.get_size_hint(), 10);
 
-        let packet = Packet::bin_event("/", "event", Some(json!({ "data": "value™" })), vec![vec![1]]);
+        let packet = Packet::bin_event(
+            "/",
+            "event",
+            Some(json!({ "data": "value™" })),
+            vec![vec![1]],
+        );
         assert_eq!(packet.get_size_hint(), 3);
 
         let packet = Packet::bin_event(
@@ -934,4 +1145,48 @@ mod test {
         let packet = Packet::bin_ack("/", json!("data"), vec![vec![1]], 54);
         assert_eq!(packet.get_size_hint(), 5);
     }
+
+    #[test]
+    fn packet_decode_malformed() {
+        let decode = |packet: &str| Packet::try_from(packet.to_string()).unwrap_err();
+        assert_eq!(decode(""), ParseError::Empty);
+        assert_eq!(decode("9"), ParseError::InvalidPacketType { offset: 0 });
+        // Connect error packets cannot be sent by a client
+        assert_eq!(decode("4{}"), ParseError::InvalidPacketType { offset: 0 });
+        assert_eq!(
+            decode(r#"5["event"]"#),
+            ParseError::InvalidAttachmentsCount { offset: 1 }
+        );
+        assert_eq!(
+            decode(r#"51["event"]"#),
+            ParseError::InvalidAttachmentsCount { offset: 2 }
+        );
+        assert_eq!(
+            decode(r#"299999999999999999999["event"]"#),
+            ParseError::InvalidAckId { offset: 1 }
+        );
+        assert_eq!(
+            decode(r#"3["data"]"#),
+            ParseError::MissingAckId { offset: 1 }
+        );
+        assert_eq!(
+            decode("/admin,12"),
+            ParseError::InvalidPacketType { offset: 0 }
+        );
+        assert_eq!(decode("2/admin,"), ParseError::MissingPayload { offset: 8 });
+        assert_eq!(decode("312"), ParseError::MissingPayload { offset: 3 });
+        assert_eq!(
+            decode(r#"2{"event":1}"#),
+            ParseError::InvalidEventName { offset: 1 }
+        );
+        assert_eq!(decode("2[1]"), ParseError::InvalidEventName { offset: 1 });
+        assert_eq!(
+            decode("2/admin,[]"),
+            ParseError::InvalidEventName { offset: 8 }
+        );
+
+        let err = decode(r#"2/admin,1["event",]"#);
+        assert!(matches!(err, ParseError::InvalidPayload { offset: 9, .. }));
+        assert_eq!(err.offset(), 9);
+    }
 }
@@ -0,0 +1,154 @@
+//! Parsers used to encode and decode the socket.io packets.
+//!
+//! The [`DefaultParser`] encodes the packets as text with JSON payloads, binary attachments being sent
+//! in separate binary messages. It is the only parser understood by the default socket.io clients.
+//!
+//! With the `msgpack` feature flag, the [`MsgPackParser`] encodes each packet as a single binary
+//! [MessagePack](https://msgpack.org) message, binary payloads are encoded in place.
+//! It is compatible with the clients using the
+//! [socket.io-msgpack-parser](https://github.com/socketio/socket.io-msgpack-parser).
+//! Other encodings can be supported by implementing the [`Parser`] trait.
+//!
+//! The parser is negotiated for each session: when the first packet of a client is a binary message
+//! that can be decoded by the configured parser, the whole session uses it. Otherwise the session
+//! falls back to the [`DefaultParser`], so a server with a binary parser still accepts the default clients.
+//!
+//! The packets replayed by the connection state recovery of the [`session`](crate::session) module
+//! are stored as text, they are only replayed to the sessions using the [`DefaultParser`].
+//!
+//! #### Example
+//! ```
+//! # #[cfg(feature = "msgpack")]
+//! # {
+//! # use socketioxide::{SocketIo, parser::MsgPackParser};
+//! let (layer, io) = SocketIo::builder().with_parser(MsgPackParser).build_layer();
+//! # }
+//! ```
+use std::fmt::Debug;
+
+use engineioxide::Socket as EIoSocket;
+
+use crate::{client::SocketData, packet::Packet, SendError, SocketIoConfig};
+
+#[cfg(feature = "msgpack")]
+mod msgpack;
+
+/// A message produced by a [`Parser`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A text message
+    Text(String),
+    /// A binary message
+    Binary(Vec<u8>),
+}
+
+/// A parser used to encode and decode the socket.io packets of a session.
+///
+/// The text messages are always decoded with the [`DefaultParser`],
+/// a parser only decodes the binary messages of the sessions that negotiated it.
+pub trait Parser: Debug + Send + Sync + 'static {
+    /// Encodes a packet into the messages to send to the client
+    fn encode(&self, packet: Packet<'_>) -> Result<Vec<Message>, serde_json::Error>;
+
+    /// Decodes a packet from a binary message.
+    /// Returns `None` if the parser does not encode packets as binary messages.
+    fn decode_binary(&self, data: &[u8]) -> Option<Result<Packet<'static>, serde_json::Error>>;
+}
+
+/// Encodes the packet with the parser negotiated for the session and sends it to the client
+pub(crate) fn send_packet(
+    socket: &EIoSocket<SocketData>,
+    config: &SocketIoConfig,
+    packet: Packet<'_>,
+) -> Result<(), SendError> {
+    for msg in socket.data.parser(config).encode(packet)? {
+        match msg {
+            Message::Text(msg) => socket.emit(msg)?,
+            Message::Binary(bin) => socket.emit_binary(bin)?,
+        }
+    }
+    Ok(())
+}
+
+/// A packet encoded lazily, at most once for each parser, so that it can be sent to many sessions
+/// without being serialized again for each of them.
+pub(crate) struct EncodedPacket<'a> {
+    packet: Packet<'a>,
+    default: Option<Vec<Message>>,
+    binary: Option<Vec<Message>>,
+}
+
+impl<'a> EncodedPacket<'a> {
+    pub fn new(packet: Packet<'a>) -> Self {
+        Self {
+            packet,
+            default: None,
+            binary: None,
+        }
+    }
+
+    /// Sends the packet encoded with the parser negotiated for the session,
+    /// the encoded messages are kept for the next sessions using the same parser
+    pub fn send(
+        &mut self,
+        socket: &EIoSocket<SocketData>,
+        config: &SocketIoConfig,
+    ) -> Result<(), SendError> {
+        let messages = if socket.data.uses_binary_parser() {
+            &mut self.binary
+        } else {
+            &mut self.default
+        };
+        let messages = match messages {
+            Some(messages) => messages,
+            None => messages.insert(socket.data.parser(config).encode(self.packet.clone())?),
+        };
+        for msg in messages.iter() {
+            match msg {
+                Message::Text(msg) => socket.emit(msg.clone())?,
+                Message::Binary(bin) => socket.emit_binary(bin.clone())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The default parser, with text packets and separate binary attachments
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultParser;
+
+impl Parser for DefaultParser {
+    fn encode(&self, mut packet: Packet<'_>) -> Result<Vec<Message>, serde_json::Error> {
+        use crate::packet::PacketData::*;
+        let bin = match &mut packet.inner {
+            BinaryEvent(_, bin, _) | BinaryAck(bin, _) => std::mem::take(&mut bin.bin),
+            _ => vec![],
+        };
+        let msg: String = packet.try_into()?;
+        let mut messages = Vec::with_capacity(1 + bin.len());
+        messages.push(Message::Text(msg));
+        messages.extend(bin.into_iter().map(Message::Binary));
+        Ok(messages)
+    }
+
+    fn decode_binary(&self, _: &[u8]) -> Option<Result<Packet<'static>, serde_json::Error>> {
+        None
+    }
+}
+
+/// The [MessagePack](https://msgpack.org) parser, with a single binary message for each packet
+#[cfg_attr(docsrs, doc(cfg(feature = "msgpack")))]
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgPackParser;
+
+#[cfg(feature = "msgpack")]
+impl Parser for MsgPackParser {
+    fn encode(&self, packet: Packet<'_>) -> Result<Vec<Message>, serde_json::Error> {
+        Ok(vec![Message::Binary(msgpack::encode(packet)?)])
+    }
+
+    fn decode_binary(&self, data: &[u8]) -> Option<Result<Packet<'static>, serde_json::Error>> {
+        Some(msgpack::decode(data))
+    }
+}
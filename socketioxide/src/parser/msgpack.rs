@@ -0,0 +1,375 @@
+//! The [MessagePack](https://msgpack.org) codec of the [`MsgPackParser`](super::MsgPackParser),
+//! compatible with the official [socket.io-msgpack-parser](https://github.com/socketio/socket.io-msgpack-parser).
+//!
+//! A packet is encoded with [`rmp_serde`] as a map with the `type`, `nsp`, `data` and `id` keys.
+//! Binary payloads are encoded in place in the data, so the binary packet types are never sent.
+use std::{fmt, io::Cursor};
+
+use serde::{
+    de::{self, DeserializeSeed, Error as _, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeMap, SerializeSeq},
+    Deserializer, Serialize, Serializer,
+};
+use serde_json::{json, Map, Number, Value};
+
+use crate::packet::{BinaryPacket, Packet, PacketData};
+
+/// The maximum nesting depth of a decoded value, deeper values are rejected
+const MAX_DEPTH: usize = 128;
+
+/// The packet map, the keys are written in the same order as the official parser
+#[derive(Serialize)]
+struct RawPacket<'a> {
+    #[serde(rename = "type")]
+    index: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<WithBins<'a>>,
+    nsp: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+}
+
+/// Encodes a packet into a MessagePack message
+pub fn encode(packet: Packet<'_>) -> Result<Vec<u8>, serde_json::Error> {
+    use PacketData::*;
+    let (index, data, bins, id) = match packet.inner {
+        Connect(Some(data)) => (0, Some(serde_json::from_str(&data)?), vec![], None),
+        Connect(None) => (0, None, vec![], None),
+        Disconnect(None) => (1, None, vec![], None),
+        Disconnect(Some(reason)) => (1, Some(json!({ "reason": reason })), vec![], None),
+        Event(e, data, ack) => (2, Some(event_data(&e, data)), vec![], ack),
+        BinaryEvent(e, bin, ack) => (2, Some(event_data(&e, bin.data)), bin.bin, ack),
+        EventAck(data, ack) => (3, Some(ack_data(Some(data))), vec![], Some(ack)),
+        BinaryAck(bin, ack) => (3, Some(ack_data(bin.data)), bin.bin, Some(ack)),
+        ConnectError(message) => (4, Some(json!({ "message": message })), vec![], None),
+    };
+    let packet = RawPacket {
+        index,
+        data: data.as_ref().map(|value| WithBins { value, bins: &bins }),
+        nsp: &packet.ns,
+        id,
+    };
+    rmp_serde::to_vec_named(&packet).map_err(serde_json::Error::custom)
+}
+
+/// Decodes a packet from a MessagePack message
+pub fn decode(data: &[u8]) -> Result<Packet<'static>, serde_json::Error> {
+    let mut bins = Vec::new();
+    let mut de = rmp_serde::Deserializer::new(Cursor::new(data));
+    de.set_max_depth(MAX_DEPTH);
+    let value = ValueSeed { bins: &mut bins }
+        .deserialize(&mut de)
+        .map_err(invalid)?;
+    if de.position() as usize != data.len() {
+        return Err(invalid("trailing bytes after the packet"));
+    }
+    let Value::Object(mut packet) = value else {
+        return Err(invalid("the packet should be a map"));
+    };
+    let index = packet
+        .get("type")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid("invalid packet type"))?;
+    let ns = match packet.remove("nsp") {
+        Some(Value::String(ns)) => ns,
+        None => "/".to_string(),
+        Some(_) => return Err(invalid("invalid namespace")),
+    };
+    let id = packet.get("id").and_then(Value::as_i64);
+    let data = packet.remove("data");
+
+    // Binary payloads were replaced by placeholders, they are stripped from the data
+    let binary = |data: Value, bins: Vec<Vec<u8>>| {
+        let mut packet = BinaryPacket::incoming(data);
+        bins.into_iter().for_each(|bin| packet.add_payload(bin));
+        packet
+    };
+    let inner = match index {
+        0 => PacketData::Connect(data.map(|d| d.to_string())),
+        1 => PacketData::Disconnect(
+            data.as_ref()
+                .and_then(|d| d.get("reason"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        ),
+        2 | 5 => {
+            let Some(Value::Array(mut data)) = data else {
+                return Err(invalid("invalid event name"));
+            };
+            if !data.first().map(Value::is_string).unwrap_or(false) {
+                return Err(invalid("invalid event name"));
+            }
+            let Value::String(event) = data.remove(0) else {
+                unreachable!()
+            };
+            if bins.is_empty() {
+                PacketData::Event(event.into(), Some(Value::Array(data)), id)
+            } else {
+                PacketData::BinaryEvent(event.into(), binary(Value::Array(data), bins), id)
+            }
+        }
+        3 | 6 => {
+            let data = data.ok_or_else(|| invalid("missing ack data"))?;
+            let id = id.ok_or_else(|| invalid("missing ack id"))?;
+            if bins.is_empty() {
+                PacketData::EventAck(data, id)
+            } else {
+                PacketData::BinaryAck(binary(data, bins), id)
+            }
+        }
+        _ => return Err(invalid("invalid packet type")),
+    };
+    Ok(Packet {
+        inner,
+        ns: ns.into(),
+    })
+}
+
+/// Expands the event data -> ["event", ...data]
+fn event_data(e: &str, data: Option<Value>) -> Value {
+    let mut args = vec![Value::String(e.to_string())];
+    match data {
+        Some(Value::Array(data)) => args.extend(data),
+        Some(data) => args.push(data),
+        None => (),
+    }
+    Value::Array(args)
+}
+
+/// Enforces that the ack data is an array -> [data]
+fn ack_data(data: Option<Value>) -> Value {
+    match data {
+        Some(Value::Array(data)) => Value::Array(data),
+        Some(Value::Null) | None => Value::Array(vec![]),
+        Some(data) => Value::Array(vec![data]),
+    }
+}
+
+fn invalid(msg: impl fmt::Display) -> serde_json::Error {
+    serde_json::Error::custom(format!("invalid msgpack: {msg}"))
+}
+
+/// Returns the index of the binary payload if the value is a placeholder
+fn placeholder(value: &Map<String, Value>) -> Option<usize> {
+    if value.len() == 2 && value.get("_placeholder") == Some(&Value::Bool(true)) {
+        value.get("num")?.as_u64().map(|num| num as usize)
+    } else {
+        None
+    }
+}
+
+/// Serializes a value with its placeholders replaced by the binary payloads
+struct WithBins<'a> {
+    value: &'a Value,
+    bins: &'a [Vec<u8>],
+}
+
+impl Serialize for WithBins<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let with_bins = |value| WithBins {
+            value,
+            bins: self.bins,
+        };
+        match self.value {
+            Value::Array(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(&with_bins(value))?;
+                }
+                seq.end()
+            }
+            Value::Object(map) => match placeholder(map) {
+                Some(num) => {
+                    let bin = self
+                        .bins
+                        .get(num)
+                        .ok_or_else(|| ser::Error::custom("missing binary payload"))?;
+                    serializer.serialize_bytes(bin)
+                }
+                None => {
+                    let mut ser = serializer.serialize_map(Some(map.len()))?;
+                    for (key, value) in map {
+                        ser.serialize_entry(key, &with_bins(value))?;
+                    }
+                    ser.end()
+                }
+            },
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+/// Deserializes a value, the binary payloads are replaced by placeholders and pushed to `bins`
+struct ValueSeed<'a> {
+    bins: &'a mut Vec<Vec<u8>>,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a msgpack value without extension types")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+        Number::from_f64(v)
+            .map(Value::Number)
+            .ok_or_else(|| E::custom("non finite float"))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value, E> {
+        self.bins.push(v.to_vec());
+        Ok(json!({ "_placeholder": true, "num": self.bins.len() - 1 }))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        // The size hint comes from the message, it is not trusted to preallocate the array
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(ValueSeed {
+            bins: &mut *self.bins,
+        })? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut values = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value_seed(ValueSeed {
+                bins: &mut *self.bins,
+            })?;
+            values.insert(key, value);
+        }
+        Ok(Value::Object(values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_value(value: &Value, bins: &[Vec<u8>]) -> Vec<u8> {
+        rmp_serde::to_vec(&WithBins { value, bins }).unwrap()
+    }
+
+    fn read_value(data: &[u8], bins: &mut Vec<Vec<u8>>) -> Result<Value, rmp_serde::decode::Error> {
+        let mut de = rmp_serde::Deserializer::new(Cursor::new(data));
+        de.set_max_depth(MAX_DEPTH);
+        ValueSeed { bins }.deserialize(&mut de)
+    }
+
+    fn round_trip(value: Value) {
+        let buf = write_value(&value, &[]);
+        assert_eq!(read_value(&buf, &mut vec![]).unwrap(), value);
+    }
+
+    #[test]
+    fn values() {
+        round_trip(json!(null));
+        round_trip(json!([true, false]));
+        for n in [
+            0,
+            1,
+            127,
+            128,
+            255,
+            256,
+            65535,
+            65536,
+            u32::MAX as u64 + 1,
+            u64::MAX,
+        ] {
+            round_trip(json!(n));
+        }
+        for n in [-1, -32, -33, -128, -129, -32768, -32769, i64::MIN] {
+            round_trip(json!(n));
+        }
+        round_trip(json!(1.5));
+        round_trip(json!("a".repeat(31)));
+        round_trip(json!("a".repeat(300)));
+        round_trip(json!("a".repeat(70000)));
+        round_trip(json!((0..20).collect::<Vec<_>>()));
+        round_trip(json!({ "nested": { "array": [1, "2", null], "map": {} } }));
+    }
+
+    #[test]
+    fn binary() {
+        let data = [0x92, 0xc4, 0x02, 1, 2, 0xa1, b'a'];
+        let mut bins = vec![];
+        let value = read_value(&data, &mut bins).unwrap();
+        assert_eq!(value, json!([{ "_placeholder": true, "num": 0 }, "a"]));
+        assert_eq!(bins, vec![vec![1, 2]]);
+
+        assert_eq!(write_value(&value, &bins), data);
+    }
+
+    #[test]
+    fn invalid_values() {
+        // Truncated data
+        assert!(read_value(&[0xa3, b'a'], &mut vec![]).is_err());
+        // Extension types are not supported
+        assert!(read_value(&[0xd4, 0, 0], &mut vec![]).is_err());
+        // Non string keys
+        assert!(read_value(&[0x81, 0x01, 0x01], &mut vec![]).is_err());
+        // Too deeply nested arrays
+        let nested = vec![0x91; MAX_DEPTH + 2];
+        assert!(read_value(&nested, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn packets() {
+        let packet = Packet::bin_event("/admin", "event", Some(json!(["str", 1])), vec![vec![1]]);
+        let decoded = decode(&encode(packet.clone()).unwrap()).unwrap();
+        let PacketData::BinaryEvent(e, bin, None) = decoded.inner else {
+            panic!("expected a binary event: {:?}", decoded);
+        };
+        assert_eq!((e.as_ref(), decoded.ns.as_ref()), ("event", "/admin"));
+        assert_eq!(bin.data, Some(json!(["str", 1])));
+        assert_eq!(bin.bin, vec![vec![1]]);
+
+        let packet = Packet::ack("/", json!("data"), 42);
+        assert_eq!(
+            decode(&encode(packet.clone()).unwrap()).unwrap(),
+            Packet::ack("/", json!(["data"]), 42)
+        );
+
+        let packet = Packet::disconnect("/");
+        assert_eq!(decode(&encode(packet.clone()).unwrap()).unwrap(), packet);
+
+        let packet = Packet::disconnect_with_reason("/", "duplicate login");
+        assert_eq!(decode(&encode(packet.clone()).unwrap()).unwrap(), packet);
+
+        let mut data = encode(packet).unwrap();
+        data.push(0xc0);
+        assert!(decode(&data).is_err());
+    }
+}
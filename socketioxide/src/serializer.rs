@@ -0,0 +1,101 @@
+//! JSON serializer backends used to encode and decode the packet payloads.
+//!
+//! [`serde_json`] is used by default. With the `simd-json` feature flag, [`simd_json`] is used instead,
+//! which is faster to parse large payloads on CPUs supporting SIMD instructions.
+//!
+//! In both cases the payloads are represented with [`serde_json::Value`].
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A JSON serializer backend.
+pub trait Serializer {
+    /// Serializes the given value to a JSON byte vector
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, serde_json::Error>;
+
+    /// Serializes the given value to a JSON string
+    fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, serde_json::Error> {
+        let vec = Self::to_vec(value)?;
+        String::from_utf8(vec).map_err(serde::ser::Error::custom)
+    }
+
+    /// Deserializes a value from a JSON byte slice.
+    ///
+    /// The slice may be modified in place by the parser.
+    fn from_slice<T: DeserializeOwned>(data: &mut [u8]) -> Result<T, serde_json::Error>;
+}
+
+/// The [`serde_json`] backend
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeJson;
+
+impl Serializer for SerdeJson {
+    #[inline]
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(value)
+    }
+
+    #[inline]
+    fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, serde_json::Error> {
+        serde_json::to_string(value)
+    }
+
+    #[inline]
+    fn from_slice<T: DeserializeOwned>(data: &mut [u8]) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(data)
+    }
+}
+
+/// The [`simd_json`] backend
+#[cfg_attr(docsrs, doc(cfg(feature = "simd-json")))]
+#[cfg(feature = "simd-json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdJson;
+
+#[cfg(feature = "simd-json")]
+impl Serializer for SimdJson {
+    #[inline]
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+        simd_json::serde::to_vec(value).map_err(serde::ser::Error::custom)
+    }
+
+    #[inline]
+    fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, serde_json::Error> {
+        simd_json::serde::to_string(value).map_err(serde::ser::Error::custom)
+    }
+
+    #[inline]
+    fn from_slice<T: DeserializeOwned>(data: &mut [u8]) -> Result<T, serde_json::Error> {
+        simd_json::serde::from_slice(data).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The backend used to encode and decode packets, selected with the `simd-json` feature flag
+#[cfg(not(feature = "simd-json"))]
+pub(crate) type DefaultSerializer = SerdeJson;
+/// The backend used to encode and decode packets, selected with the `simd-json` feature flag
+#[cfg(feature = "simd-json")]
+pub(crate) type DefaultSerializer = SimdJson;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn round_trip<S: Serializer>() {
+        let value = json!({ "event": "message", "data": [1, "two", { "three": 3.0 }, null] });
+        let mut vec = S::to_vec(&value).unwrap();
+        assert_eq!(S::to_string(&value).unwrap().as_bytes(), vec);
+        assert_eq!(S::from_slice::<Value>(&mut vec).unwrap(), value);
+        assert!(S::from_slice::<Value>(&mut b"[1,".to_vec()).is_err());
+    }
+
+    #[test]
+    fn serde_json_round_trip() {
+        round_trip::<SerdeJson>();
+    }
+
+    #[cfg(feature = "simd-json")]
+    #[test]
+    fn simd_json_round_trip() {
+        round_trip::<SimdJson>();
+    }
+}
@@ -116,6 +116,21 @@ impl<A: Adapter, S: Clone> SocketIoService<S, A> {
         self.engine_svc.into_make_service()
     }
 
+    /// Serves a socket.io session over a WebTransport session accepted by an HTTP/3 server.
+    ///
+    /// The server hands over the `req` parts of the session request and the first bidirectional stream
+    /// opened by the client, see [`EngineIoService::on_webtransport`].
+    /// The returned future resolves when the session is closed.
+    #[cfg(feature = "webtransport")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "webtransport")))]
+    #[inline(always)]
+    pub async fn on_webtransport<T>(&self, req: http::request::Parts, stream: T)
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    {
+        self.engine_svc.on_webtransport(req, stream).await
+    }
+
     /// Creates a new [`EngineIoService`] with a custom inner service and a custom config.
     pub(crate) fn with_config_inner(
         inner: S,
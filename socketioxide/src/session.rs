@@ -0,0 +1,166 @@
+//! Connection state recovery, it mirrors the socket.io
+//! [connection state recovery](https://socket.io/docs/v4/connection-state-recovery) feature.
+//!
+//! When a [`SessionStore`] is set with [`SocketIoBuilder::with_session_store`](crate::SocketIoBuilder::with_session_store),
+//! each socket receives a private session id (`pid`) in its connect packet.
+//! If the socket is abruptly disconnected (transport close or error, heartbeat timeout),
+//! its session is saved in the store along with its room memberships.
+//! The packets broadcast to these rooms while the client is disconnected are buffered in the session.
+//!
+//! When the client reconnects to the namespace with its previous `pid` in the auth payload
+//! (e.g. `{ "pid": "<pid>" }`, it is done automatically by the socket.io client),
+//! the new socket joins the rooms of the previous session and the buffered packets are replayed
+//! right after the connect packet. [`Socket::recovered`](crate::socket::Socket::recovered) is then `true`.
+//!
+//! With a disconnect grace period set with
+//! [`SocketIoBuilder::disconnect_grace_period`](crate::SocketIoBuilder::disconnect_grace_period),
+//! the disconnected socket is kept alive during this period instead of being torn down immediately.
+//! If the client reconnects in time, the new socket takes it over: the disconnect handler of the previous socket
+//! is never called and its [`extensions`](crate::socket::Socket::extensions) are moved to the new socket.
+//!
+//! Only the socket.io protocol v5 is supported. Binary packets and packets with acknowledgements are not buffered.
+//!
+//! ## Example
+//! ```
+//! # use socketioxide::{SocketIo, extract::*, session::MemorySessionStore};
+//! # use std::time::Duration;
+//! let (_, io) = SocketIo::builder()
+//!     .with_session_store(MemorySessionStore::new(Duration::from_secs(60)))
+//!     .build_svc();
+//! io.ns("/", |socket: SocketRef| async move {
+//!     if socket.recovered() {
+//!         println!("socket {} recovered its session", socket.id);
+//!     } else {
+//!         socket.join("lobby").await.ok();
+//!     }
+//! });
+//! ```
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use engineioxide::sid::Sid;
+
+use crate::adapter::Room;
+
+/// The state of a disconnected socket that can be restored when its client reconnects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The id of the disconnected socket
+    pub sid: Sid,
+    /// The rooms the socket was in when it was disconnected
+    pub rooms: Vec<Room>,
+    /// The serialized packets broadcast to the socket since it was disconnected
+    pub packets: Vec<String>,
+}
+
+/// A store used to save the sessions of the disconnected sockets until their clients reconnect.
+///
+/// Sessions are identified by their namespace and their private session id.
+/// Implementations are responsible for expiring the sessions that are never restored.
+pub trait SessionStore: std::fmt::Debug + Send + Sync + 'static {
+    /// Saves the session of a socket that was abruptly disconnected from the namespace `ns`.
+    fn save(&self, ns: &str, pid: Sid, session: Session);
+
+    /// Removes and returns the session with the given private session id if it has not expired.
+    fn restore(&self, ns: &str, pid: Sid) -> Option<Session>;
+
+    /// Buffers a serialized packet broadcast in the namespace `ns`
+    /// for all the saved sessions matching the given filter.
+    fn buffer(&self, ns: &str, filter: &dyn Fn(&Session) -> bool, packet: &str);
+}
+
+/// The default in-memory [`SessionStore`].
+///
+/// Sessions are kept for at most the given max disconnection duration.
+#[derive(Debug)]
+pub struct MemorySessionStore {
+    max_disconnection_duration: Duration,
+    sessions: Mutex<HashMap<(String, Sid), (Instant, Session)>>,
+}
+
+impl MemorySessionStore {
+    /// Creates a new [`MemorySessionStore`] keeping the sessions for at most `max_disconnection_duration`.
+    pub fn new(max_disconnection_duration: Duration) -> Self {
+        Self {
+            max_disconnection_duration,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Removes the expired sessions
+    fn purge(&self, sessions: &mut HashMap<(String, Sid), (Instant, Session)>) {
+        sessions.retain(|_, (saved_at, _)| saved_at.elapsed() < self.max_disconnection_duration);
+    }
+}
+
+impl Default for MemorySessionStore {
+    /// Creates a new [`MemorySessionStore`] keeping the sessions for 2 minutes, like socket.io does.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(120))
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn save(&self, ns: &str, pid: Sid, session: Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions);
+        sessions.insert((ns.to_string(), pid), (Instant::now(), session));
+    }
+
+    fn restore(&self, ns: &str, pid: Sid) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions);
+        sessions
+            .remove(&(ns.to_string(), pid))
+            .map(|(_, session)| session)
+    }
+
+    fn buffer(&self, ns: &str, filter: &dyn Fn(&Session) -> bool, packet: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions);
+        sessions
+            .iter_mut()
+            .filter(|((session_ns, _), (_, session))| session_ns == ns && filter(session))
+            .for_each(|(_, (_, session))| session.packets.push(packet.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session(rooms: &[&'static str]) -> Session {
+        Session {
+            sid: Sid::new(),
+            rooms: rooms.iter().map(|r| (*r).into()).collect(),
+            packets: vec![],
+        }
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemorySessionStore::default();
+        let (pid1, pid2) = (Sid::new(), Sid::new());
+        store.save("/", pid1, session(&["room1"]));
+        store.save("/", pid2, session(&["room2"]));
+        store.buffer("/", &|s| s.rooms.contains(&"room1".into()), "packet");
+        store.buffer("/admin", &|_| true, "other ns packet");
+
+        assert!(store.restore("/admin", pid1).is_none());
+        let restored = store.restore("/", pid1).unwrap();
+        assert_eq!(restored.packets, vec!["packet".to_string()]);
+        assert!(store.restore("/", pid1).is_none());
+        assert!(store.restore("/", pid2).unwrap().packets.is_empty());
+    }
+
+    #[test]
+    fn memory_store_expiration() {
+        let store = MemorySessionStore::new(Duration::ZERO);
+        let pid = Sid::new();
+        store.save("/", pid, session(&[]));
+        assert!(store.restore("/", pid).is_none());
+    }
+}
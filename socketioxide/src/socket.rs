@@ -1,41 +1,47 @@
 //! A [`Socket`] represents a client connected to a namespace.
 //! The socket struct itself should not be used directly, but through a [`SocketRef`](crate::extract::SocketRef).
 use std::{
+    any::Any,
     borrow::Cow,
     collections::HashMap,
     fmt::Debug,
     sync::Mutex,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicBool, AtomicI64, Ordering},
         Arc, RwLock,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use engineioxide::socket::DisconnectReason as EIoDisconnectReason;
+use engineioxide::socket::{CloseFrame, DisconnectReason as EIoDisconnectReason};
+use futures::{
+    future::{self, BoxFuture, Either},
+    Future, FutureExt,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch, Notify, OwnedSemaphorePermit, Semaphore};
 
 #[cfg(feature = "extensions")]
 use crate::extensions::Extensions;
 
 use crate::{
-    adapter::{Adapter, LocalAdapter, Room},
+    adapter::{run_adapter_op, Adapter, LocalAdapter, Room},
+    args::Args,
     errors::{AckError, Error},
     handler::{
-        BoxedDisconnectHandler, BoxedMessageHandler, DisconnectHandler, MakeErasedHandler,
-        MessageHandler,
+        extract, BoxedDisconnectHandler, BoxedMessageHandler, ConnectErrorKind, DisconnectHandler,
+        MakeErasedHandler, MessageHandler,
     },
+    lifecycle::LifecycleEvent,
     ns::Namespace,
-    operators::{Operators, RoomParam},
+    operators::{ExceptParam, Operators, RoomParam},
     packet::{BinaryPacket, Packet, PacketData},
-    SocketIoConfig,
-};
-use crate::{
-    client::SocketData,
-    errors::{AdapterError, SendError},
+    parser,
+    session::Session,
+    RateLimit, RateLimitPolicy, SocketIoConfig,
 };
+use crate::{client::SocketData, errors::SendError};
 
 pub use engineioxide::sid::Sid;
 
@@ -67,6 +73,18 @@ pub enum DisconnectReason {
 
     /// The server is being closed
     ClosingServer,
+
+    /// The client did not receive the packets fast enough and its buffer was full,
+    /// with the [`OverflowPolicy::DisconnectSlowClient`](crate::OverflowPolicy::DisconnectSlowClient) policy
+    SlowClient,
+
+    /// The client sent more events than allowed by the [`RateLimit`](crate::RateLimit) of the server,
+    /// with the [`RateLimitPolicy::Disconnect`](crate::RateLimitPolicy::Disconnect) policy
+    RateLimitExceeded,
+
+    /// The client missed too many pongs or too many bytes were emitted to it since its last pong,
+    /// see the [`CircuitBreaker`](crate::CircuitBreaker)
+    UnresponsiveClient,
 }
 
 impl std::fmt::Display for DisconnectReason {
@@ -81,6 +99,11 @@ impl std::fmt::Display for DisconnectReason {
             ClientNSDisconnect => "client has manually disconnected the socket from the namespace",
             ServerNSDisconnect => "socket was forcefully disconnected from the namespace",
             ClosingServer => "server is being closed",
+            SlowClient => "client did not receive the packets fast enough",
+            RateLimitExceeded => "client sent more events than allowed by the rate limit",
+            UnresponsiveClient => {
+                "client missed too many pongs or did not acknowledge the emitted bytes"
+            }
         };
         f.write_str(str)
     }
@@ -96,9 +119,32 @@ impl From<EIoDisconnectReason> for DisconnectReason {
             EIoDisconnectReason::MultipleHttpPollingError => MultipleHttpPollingError,
             EIoDisconnectReason::PacketParsingError => PacketParsingError,
             EIoDisconnectReason::ClosingServer => ClosingServer,
+            EIoDisconnectReason::SlowClient => SlowClient,
+            EIoDisconnectReason::ServerClose => ServerNSDisconnect,
+            EIoDisconnectReason::UnresponsiveClient => UnresponsiveClient,
         }
     }
 }
+
+impl DisconnectReason {
+    /// Returns true if the connection was closed on purpose by the client or the server,
+    /// rather than dropped or closed because of an error
+    fn is_clean(&self) -> bool {
+        use DisconnectReason::*;
+        matches!(
+            self,
+            TransportClose | ClientNSDisconnect | ServerNSDisconnect | ClosingServer
+        )
+    }
+}
+
+/// A message emitted to a room when the socket is abruptly disconnected, set with [`Socket::set_last_will`]
+#[derive(Debug)]
+struct LastWill {
+    room: Room,
+    event: Cow<'static, str>,
+    data: Value,
+}
 /// An acknowledgement sent by the client.
 /// It contains the data sent by the client and the binary payloads if there are any.
 #[derive(Debug)]
@@ -121,6 +167,28 @@ pub struct Socket<A: Adapter = LocalAdapter> {
     disconnect_handler: Mutex<Option<BoxedDisconnectHandler<A>>>,
     ack_message: Mutex<HashMap<i64, oneshot::Sender<AckResponse<Value>>>>,
     ack_counter: AtomicI64,
+    /// Notified each time a pending acknowledgement is resolved
+    ack_notify: Notify,
+    /// The private session id used to recover the session, set if a session store is configured
+    pid: Option<Sid>,
+    /// Set if the socket recovered the session of a previous socket
+    recovered: AtomicBool,
+    /// The packets of the recovered session, replayed after the connect packet
+    missed_packets: Mutex<Vec<String>>,
+    /// The token bucket limiting the received events, set if a rate limit is configured
+    rate_limiter: Option<Mutex<TokenBucket>>,
+    /// The permits of the async event handlers running at the same time,
+    /// set if the namespace limits their concurrency with [`NsHandle::with_handler_concurrency`](crate::NsHandle::with_handler_concurrency)
+    handler_permits: Option<Arc<Semaphore>>,
+    /// Set if the async handlers are cancelled when the socket disconnects,
+    /// with [`NsHandle::with_handler_cancellation`](crate::NsHandle::with_handler_cancellation)
+    cancel_handlers: bool,
+    /// Set to `true` once the socket is disconnected, see [`Socket::disconnected`]
+    disconnected: watch::Sender<bool>,
+    /// The typed data set with [`Socket::set_data`]
+    data: RwLock<Option<Arc<dyn Any + Send + Sync>>>,
+    /// The message emitted when the socket is abruptly disconnected
+    last_will: Mutex<Option<LastWill>>,
     /// The socket id
     pub id: Sid,
 
@@ -142,12 +210,32 @@ impl<A: Adapter> Socket<A> {
         esocket: Arc<engineioxide::Socket<SocketData>>,
         config: Arc<SocketIoConfig>,
     ) -> Self {
+        let pid = ns.session_store.as_ref().map(|_| Sid::new());
+        let handler_permits = ns
+            .handler_concurrency
+            .read()
+            .unwrap()
+            .map(|concurrency| Arc::new(Semaphore::new(concurrency)));
+        let cancel_handlers = ns.cancel_handlers.load(Ordering::Relaxed);
         Self {
             ns,
             message_handlers: RwLock::new(HashMap::new()),
             disconnect_handler: Mutex::new(None),
             ack_message: Mutex::new(HashMap::new()),
             ack_counter: AtomicI64::new(0),
+            ack_notify: Notify::new(),
+            pid,
+            recovered: AtomicBool::new(false),
+            missed_packets: Mutex::new(Vec::new()),
+            rate_limiter: config
+                .rate_limit
+                .as_ref()
+                .map(|limit| Mutex::new(TokenBucket::new(limit))),
+            handler_permits,
+            cancel_handlers,
+            disconnected: watch::channel(false).0,
+            data: RwLock::new(None),
+            last_will: Mutex::new(None),
             id: sid,
             #[cfg(feature = "extensions")]
             extensions: Extensions::new(),
@@ -255,7 +343,7 @@ impl<A: Adapter> Socket<A> {
     /// ## Errors
     /// * If the data cannot be serialized to JSON, a [`SendError::Serialize`] is returned.
     /// * If the packet buffer is full, a [`SendError::InternalChannelFull`] is returned.
-    /// See [`SocketIoBuilder::max_buffer_size`](crate::SocketIoBuilder) option for more infos on internal buffer config
+    ///   See [`SocketIoBuilder::max_buffer_size`](crate::SocketIoBuilder) option for more infos on internal buffer config
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -276,19 +364,47 @@ impl<A: Adapter> Socket<A> {
     ) -> Result<(), SendError> {
         let ns = self.ns();
         let data = serde_json::to_value(data)?;
-        if let Err(e) = self.send(Packet::event(ns, event.into(), Some(data))) {
-            #[cfg(feature = "tracing")]
+        let res = self.send(Packet::event(ns, event.into(), Some(data)));
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
             tracing::debug!("sending error during emit message: {e:?}");
-            return Err(e);
         }
-        Ok(())
+        res
+    }
+
+    /// Emits a message to the client with arguments mixing JSON values and binary attachments.
+    ///
+    /// The binary attachments keep their position among the other arguments, see the [`args`](crate::args) module.
+    /// ## Errors
+    /// * If the packet buffer is full, a [`SendError::InternalChannelFull`] is returned.
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, args::Args, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     let args = Args::new().json("metadata").unwrap().bin(vec![1, 2, 3]);
+    ///     socket.emit_args("file", args).ok();
+    /// });
+    /// ```
+    pub fn emit_args(
+        &self,
+        event: impl Into<Cow<'static, str>>,
+        args: Args,
+    ) -> Result<(), SendError> {
+        let ns = self.ns();
+        let res = self.send(Packet::args_event(ns, event.into(), args));
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
+            tracing::debug!("sending error during emit message: {e:?}");
+        }
+        res
     }
 
     /// Emits a message to the client
     /// ## Errors
     /// * If the data cannot be serialized to JSON, a [`SendError::Serialize`] is returned.
     /// * If the packet buffer is full, a [`SendError::InternalChannelFull`] is returned.
-    /// See [`SocketIoBuilder::max_buffer_size`](crate::SocketIoBuilder) option for more infos on internal buffer config
+    ///   See [`SocketIoBuilder::max_buffer_size`](crate::SocketIoBuilder) option for more infos on internal buffer config
     /// ## Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -298,20 +414,18 @@ impl<A: Adapter> Socket<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // Emit a test message to the client
-    ///         socket.emit("test").ok();
+    ///         socket.emit_empty("test").ok();
     ///     });
     /// });
     /// ```
-    pub fn emit_empty( &self,
-        event: impl Into<Cow<'static, str>>,
-    ) -> Result<(), SendError> {
+    pub fn emit_empty(&self, event: impl Into<Cow<'static, str>>) -> Result<(), SendError> {
         let ns = self.ns();
-        if let Err(e) = self.send(Packet::event(ns, event.into(), None)) {
-            #[cfg(feature = "tracing")]
+        let res = self.send(Packet::event(ns, event.into(), None));
+        #[cfg(feature = "tracing")]
+        if let Err(ref e) = res {
             tracing::debug!("sending error during emit message: {e:?}");
-            return Err(e);
         }
-        Ok(())
+        res
     }
 
     /// Emits a message to the client and wait for acknowledgement.
@@ -354,6 +468,49 @@ impl<A: Adapter> Socket<A> {
         self.send_with_ack(packet, None).await
     }
 
+    /// Emits a message to the client and wait for acknowledgement with the given timeout.
+    ///
+    /// It is the same as [`emit_with_ack`](Socket::emit_with_ack) but the timeout specified in the config is
+    /// overridden for this emit only.
+    /// Once the future resolves or times out, the pending acknowledgement is dropped.
+    ///
+    /// ## Errors
+    /// * If the data cannot be serialized to JSON, a [`AckError::Serialize`] is returned.
+    /// * If the packet could not be sent, a [`AckError::SendChannel`] is returned.
+    /// * In case of timeout an [`AckError::Timeout`] is returned.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use serde_json::Value;
+    /// # use std::{sync::Arc, time::Duration};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
+    ///         // Emit a test message and wait 2 seconds at most for an acknowledgement
+    ///         let timeout = Duration::from_secs(2);
+    ///         match socket.emit_with_ack_timeout::<Value>("test", data, timeout).await {
+    ///             Ok(ack) => println!("Ack received {:?}", ack),
+    ///             Err(err) => println!("Ack error {:?}", err),
+    ///         }
+    ///    });
+    /// });
+    /// ```
+    pub async fn emit_with_ack_timeout<V>(
+        &self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl Serialize,
+        timeout: Duration,
+    ) -> Result<AckResponse<V>, AckError>
+    where
+        V: DeserializeOwned + Send + Sync + 'static,
+    {
+        let ns = self.ns();
+        let data = serde_json::to_value(data)?;
+        let packet = Packet::event(Cow::Borrowed(ns), event.into(), Some(data));
+
+        self.send_with_ack(packet, Some(timeout)).await
+    }
+
     // Room actions
 
     /// Joins the given rooms.
@@ -363,8 +520,64 @@ impl<A: Adapter> Socket<A> {
     /// ## Errors
     /// When using a distributed adapter, it can return an [`Adapter::Error`] which is mostly related to network errors.
     /// For the default [`LocalAdapter`] it is always an [`Infallible`](std::convert::Infallible) error
-    pub fn join(&self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.ns.adapter.add_all(self.id, rooms)
+    pub async fn join(&self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        self.ns.adapter.add_all(self.id, rooms).await
+    }
+
+    /// Joins all the given rooms at once.
+    ///
+    /// The memberships are updated with a single adapter operation, so the adapter lock is only taken once.
+    /// It is useful to subscribe a socket to many rooms, such as topics, when it connects.
+    /// To leave many rooms at once, [`Socket::leave`] also accepts a `Vec<Room>`.
+    ///
+    /// ## Errors
+    /// When using a distributed adapter, it can return an [`Adapter::Error`] which is mostly related to network errors.
+    /// For the default [`LocalAdapter`] it is always an [`Infallible`](std::convert::Infallible) error
+    pub async fn join_all(&self, rooms: impl IntoIterator<Item = Room>) -> Result<(), A::Error> {
+        self.join(rooms.into_iter().collect::<Vec<_>>()).await
+    }
+
+    /// Joins the given rooms and replays to the socket the packets kept in their history,
+    /// from the oldest to the newest, room by room.
+    ///
+    /// The history of a room must be enabled with [`NsHandle::with_room_history`](crate::NsHandle::with_room_history),
+    /// otherwise this is the same as [`Socket::join`].
+    /// A packet broadcast to the room between the join and the replay can be received twice.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| async move {
+    ///     // The new socket receives the last 50 messages of the chat
+    ///     socket.join_with_history("chat").await.ok();
+    ///     socket.on("message", |socket: SocketRef, Data::<String>(msg)| async move {
+    ///         socket.within("chat").emit("message", msg).await.ok();
+    ///     });
+    /// })
+    /// .with_room_history("chat", 50);
+    /// ```
+    ///
+    /// ## Errors
+    /// * When using a distributed adapter, it can return a [`SendError::AdapterError`] which is mostly related to network errors.
+    /// * If a packet cannot be sent to the socket, the replay stops and the [`SendError`] is returned.
+    pub async fn join_with_history(&self, rooms: impl RoomParam) -> Result<(), SendError> {
+        let rooms: Vec<Room> = rooms.into_room_iter().collect();
+        self.join(rooms.clone())
+            .await
+            .map_err(|e| SendError::AdapterError(e.into()))?;
+        for room in rooms {
+            let history = self
+                .ns
+                .adapter
+                .room_history(&room)
+                .await
+                .map_err(|e| SendError::AdapterError(e.into()))?;
+            for packet in history {
+                self.send(packet)?;
+            }
+        }
+        Ok(())
     }
 
     /// Leaves the given rooms.
@@ -373,24 +586,24 @@ impl<A: Adapter> Socket<A> {
     /// ## Errors
     /// When using a distributed adapter, it can return an [`Adapter::Error`] which is mostly related to network errors.
     /// For the default [`LocalAdapter`] it is always an [`Infallible`](std::convert::Infallible) error
-    pub fn leave(&self, rooms: impl RoomParam) -> Result<(), A::Error> {
-        self.ns.adapter.del(self.id, rooms)
+    pub async fn leave(&self, rooms: impl RoomParam) -> Result<(), A::Error> {
+        self.ns.adapter.del(self.id, rooms).await
     }
 
     /// Leaves all rooms where the socket is connected.
     /// ## Errors
     /// When using a distributed adapter, it can return an [`Adapter::Error`] which is mostly related to network errors.
     /// For the default [`LocalAdapter`] it is always an [`Infallible`](std::convert::Infallible) error
-    pub fn leave_all(&self) -> Result<(), A::Error> {
-        self.ns.adapter.del_all(self.id)
+    pub async fn leave_all(&self) -> Result<(), A::Error> {
+        self.ns.adapter.del_all(self.id).await
     }
 
     /// Gets all rooms where the socket is connected.
     /// ## Errors
     /// When using a distributed adapter, it can return an [`Adapter::Error`] which is mostly related to network errors.
     /// For the default [`LocalAdapter`] it is always an [`Infallible`](std::convert::Infallible) error
-    pub fn rooms(&self) -> Result<Vec<Room>, A::Error> {
-        self.ns.adapter.socket_rooms(self.id)
+    pub async fn rooms(&self) -> Result<Vec<Room>, A::Error> {
+        self.ns.adapter.socket_rooms(self.id).await
     }
 
     // Socket operators
@@ -412,7 +625,7 @@ impl<A: Adapter> Socket<A> {
     ///             .to("room1")
     ///             .to(["room2", "room3"])
     ///             .to(vec![other_rooms])
-    ///             .emit("test", data);
+    ///             .emit("test", data).await;
     ///     });
     /// });
     pub fn to(&self, rooms: impl RoomParam) -> Operators<A> {
@@ -436,7 +649,7 @@ impl<A: Adapter> Socket<A> {
     ///             .within("room1")
     ///             .within(["room2", "room3"])
     ///             .within(vec![other_rooms])
-    ///             .emit("test", data);
+    ///             .emit("test", data).await;
     ///     });
     /// });
     pub fn within(&self, rooms: impl RoomParam) -> Operators<A> {
@@ -452,18 +665,18 @@ impl<A: Adapter> Socket<A> {
     /// let (_, io) = SocketIo::new_svc();
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("register1", |socket: SocketRef, Data::<Value>(data)| async move {
-    ///         socket.join("room1");
+    ///         socket.join("room1").await.ok();
     ///     });
     ///     socket.on("register2", |socket: SocketRef, Data::<Value>(data)| async move {
-    ///         socket.join("room2");
+    ///         socket.join("room2").await.ok();
     ///     });
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all clients in the Namespace
     ///         // except for ones in room1 and the current socket
-    ///         socket.broadcast().except("room1").emit("test", data);
+    ///         socket.broadcast().except("room1").emit("test", data).await;
     ///     });
     /// });
-    pub fn except(&self, rooms: impl RoomParam) -> Operators<A> {
+    pub fn except(&self, rooms: impl ExceptParam) -> Operators<A> {
         Operators::new(self.ns.clone(), Some(self.id)).except(rooms)
     }
 
@@ -478,13 +691,32 @@ impl<A: Adapter> Socket<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all clients in this namespace and connected on this node
-    ///         socket.local().emit("test", data);
+    ///         socket.local().emit("test", data).await;
     ///     });
     /// });
     pub fn local(&self) -> Operators<A> {
         Operators::new(self.ns.clone(), Some(self.id)).local()
     }
 
+    /// Marks the message as volatile: if the buffer of the socket is full, the message is silently dropped
+    /// instead of returning an [`InternalChannelFull`](crate::SendError::InternalChannelFull) error.
+    ///
+    /// It is useful for data where freshness matters more than delivery, such as telemetry.
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use serde_json::Value;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
+    ///         // The position is dropped if the client is too slow to receive it
+    ///         socket.volatile().emit("position", data).await.ok();
+    ///     });
+    /// });
+    pub fn volatile(&self) -> Operators<A> {
+        Operators::new(self.ns.clone(), Some(self.id)).volatile()
+    }
+
     /// Sets a custom timeout when sending a message with an acknowledgement.
     ///
     /// ##### Example
@@ -503,10 +735,10 @@ impl<A: Adapter> Socket<A> {
     ///             .except("room2")
     ///             .bin(bin)
     ///             .timeout(Duration::from_secs(5))
-    ///             .emit_with_ack::<Value>("message-back", data).unwrap().for_each(|ack| async move {
+    ///             .emit_with_ack::<Value>("message-back", data).await.unwrap().for_each(|(sid, ack)| async move {
     ///                match ack {
-    ///                    Ok(ack) => println!("Ack received {:?}", ack),
-    ///                    Err(err) => println!("Ack error {:?}", err),
+    ///                    Ok(ack) => println!("Ack received from {sid}: {:?}", ack),
+    ///                    Err(err) => println!("Ack error from {sid}: {:?}", err),
     ///                }
     ///             }).await;
     ///    });
@@ -526,7 +758,7 @@ impl<A: Adapter> Socket<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
     ///         // This will send the binary payload received to all clients in this namespace with the test message
-    ///         socket.bin(bin).emit("test", data);
+    ///         socket.bin(bin).emit("test", data).await;
     ///     });
     /// });
     pub fn bin(&self, binary: Vec<Vec<u8>>) -> Operators<A> {
@@ -534,6 +766,8 @@ impl<A: Adapter> Socket<A> {
     }
 
     /// Broadcasts to all clients without any filtering (except the current socket).
+    ///
+    /// To only select the clients sharing a room with the current socket, use [`broadcast_rooms()`](Self::broadcast_rooms).
     /// ##### Example
     /// ```
     /// # use socketioxide::{SocketIo, extract::*};
@@ -543,22 +777,211 @@ impl<A: Adapter> Socket<A> {
     /// io.ns("/", |socket: SocketRef| {
     ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
     ///         // This message will be broadcast to all clients in this namespace
-    ///         socket.broadcast().emit("test", data);
+    ///         socket.broadcast().emit("test", data).await;
     ///     });
     /// });
     pub fn broadcast(&self) -> Operators<A> {
         Operators::new(self.ns.clone(), Some(self.id)).broadcast()
     }
 
+    /// Broadcasts to all clients sharing at least one room with the current socket, except the current socket.
+    ///
+    /// The rooms of the socket are read when the message is emitted, so a room joined or left before
+    /// is taken into account. If the socket is in no room, no client is selected.
+    /// Contrary to [`broadcast()`](Self::broadcast), the clients of the namespace which are in none of these rooms are not selected.
+    /// ##### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use serde_json::Value;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("test", |socket: SocketRef, Data::<Value>(data)| async move {
+    ///         socket.join(["room1", "room2"]).await.ok();
+    ///         // This message will be broadcast to all clients in room1 or room2
+    ///         socket.broadcast_rooms().emit("test", data).await;
+    ///     });
+    /// });
+    pub fn broadcast_rooms(&self) -> Operators<A> {
+        Operators::new(self.ns.clone(), Some(self.id)).sender_rooms()
+    }
+
     /// Disconnects the socket from the current namespace,
     ///
     /// It will also call the disconnect handler if it is set.
     pub fn disconnect(self: Arc<Self>) -> Result<(), SendError> {
         self.send(Packet::disconnect(&self.ns.path))?;
-        self.close(DisconnectReason::ServerNSDisconnect)?;
+        self.close(DisconnectReason::ServerNSDisconnect);
         Ok(())
     }
 
+    /// Disconnects the socket from the current namespace with a reason sent to the client.
+    ///
+    /// The reason is sent in the disconnect packet in the form of `{ "reason": "<reason>" }`,
+    /// so that the client can tell why it was disconnected.
+    /// It will also call the disconnect handler if it is set.
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("login", |socket: SocketRef| {
+    ///         socket.disconnect_with_reason("duplicate login").ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn disconnect_with_reason(self: Arc<Self>, reason: &str) -> Result<(), SendError> {
+        self.send(Packet::disconnect_with_reason(&self.ns.path, reason))?;
+        self.close(DisconnectReason::ServerNSDisconnect);
+        Ok(())
+    }
+
+    /// Disconnects the socket from the current namespace and closes the underlying connection,
+    /// with a websocket close code and a reason.
+    ///
+    /// The reason is first sent in the disconnect packet like with [`disconnect_with_reason`](Self::disconnect_with_reason).
+    /// With the websocket transport, the connection is then closed with a close frame holding the code and the reason,
+    /// so the sockets of the client in the other namespaces are disconnected too.
+    /// The code should be a [close code](https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1)
+    /// that an endpoint can send, e.g. `1008` for a policy violation, or an application code between `4000` and `4999`.
+    ///
+    /// With the other transports, the code is ignored and this is the same as [`disconnect_with_reason`](Self::disconnect_with_reason).
+    /// ## Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("login", |socket: SocketRef| {
+    ///         socket.disconnect_with_code(4001, "duplicate login").ok();
+    ///     });
+    /// });
+    /// ```
+    pub fn disconnect_with_code(self: Arc<Self>, code: u16, reason: &str) -> Result<(), SendError> {
+        if self.transport_type() != crate::TransportType::Websocket {
+            return self.disconnect_with_reason(reason);
+        }
+        self.send(Packet::disconnect_with_reason(&self.ns.path, reason))?;
+        let esocket = self.esocket.clone();
+        self.close(DisconnectReason::ServerNSDisconnect);
+        let frame = CloseFrame::new(code, reason.to_owned());
+        esocket.close_with_frame(EIoDisconnectReason::ServerClose, frame);
+        Ok(())
+    }
+
+    /// Sends the connect packet to the client once the connect handler arguments are extracted.
+    ///
+    /// If the packet cannot be sent, the underlying connection is closed.
+    pub(crate) fn send_connect(&self) -> Result<(), SendError> {
+        let protocol = self.esocket.protocol.into();
+        let packet = Packet::connect_with_pid(&self.ns.path, self.id, self.pid, protocol);
+        if let Err(e) = self.send(packet) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("error sending connect packet: {:?}, closing conn", e);
+            self.esocket.close(EIoDisconnectReason::PacketParsingError);
+            return Err(e);
+        }
+        self.ns.lifecycle.send(LifecycleEvent::Connected {
+            sid: self.id,
+            ns: self.ns.path.clone(),
+        });
+        for packet in self.ns.retained_packets() {
+            self.send(packet)?;
+        }
+        let missed_packets = std::mem::take(&mut *self.missed_packets.lock().unwrap());
+        if self.esocket.data.uses_binary_parser() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                "{} missed packets not replayed to a binary session",
+                missed_packets.len()
+            );
+            return Ok(());
+        }
+        for packet in missed_packets {
+            self.esocket.emit(packet)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the session of the socket if it was abruptly disconnected and a session store is configured.
+    ///
+    /// Returns the private session id if the session was saved.
+    async fn save_session(&self, reason: DisconnectReason) -> Option<Sid> {
+        use DisconnectReason::*;
+        let (Some(store), Some(pid)) = (&self.ns.session_store, self.pid) else {
+            return None;
+        };
+        if !matches!(
+            reason,
+            TransportClose | TransportError | HeartbeatTimeout | MultipleHttpPollingError
+        ) {
+            return None;
+        }
+        match self.ns.adapter.socket_rooms(self.id).await {
+            Ok(rooms) => {
+                let session = Session {
+                    sid: self.id,
+                    rooms,
+                    packets: Vec::new(),
+                };
+                store.save(&self.ns.path, pid, session);
+                Some(pid)
+            }
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={}] error saving session: {:?}", self.id, _e);
+                None
+            }
+        }
+    }
+
+    /// Restores the rooms of a previous session,
+    /// its missed packets are replayed once the connect packet is sent.
+    ///
+    /// If the previous socket is still kept during the disconnect grace period, this socket takes it over:
+    /// the disconnect handler of the previous socket is dropped without being called, its extensions and its data are moved.
+    pub(crate) fn recover(&self, session: Session, previous: Option<Arc<Socket<A>>>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] recovering session of {}", self.id, session.sid);
+        if let Some(_previous) = previous {
+            _previous.disconnect_handler.lock().unwrap().take();
+            if let Some(data) = _previous.data.write().unwrap().take() {
+                self.data.write().unwrap().replace(data);
+            }
+            #[cfg(feature = "extensions")]
+            self.extensions.move_from(&_previous.extensions);
+        }
+        let (ns, sid) = (self.ns.clone(), self.id);
+        run_adapter_op(async move {
+            if let Err(_e) = ns.adapter.add_all(sid, session.rooms).await {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("[sid={sid}] error restoring rooms: {:?}", _e);
+            }
+        });
+        *self.missed_packets.lock().unwrap() = session.packets;
+        self.recovered.store(true, Ordering::SeqCst);
+    }
+
+    /// Rejects the connection to the namespace with the message of the given error.
+    ///
+    /// A connect error packet is sent to the client, the connect error handler is called
+    /// and the socket is removed from the namespace.
+    pub(crate) fn reject_connect(&self, kind: ConnectErrorKind) {
+        let packet = Packet::connect_error(&self.ns.path, kind.message());
+        if let Err(_e) = self.send(packet) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("error sending connect error packet: {:?}", _e);
+        }
+        let ns = &self.ns;
+        ns.connect_error_handler
+            .call(kind, &ns.path, &self.esocket.req_parts);
+        let (ns, sid) = (self.ns.clone(), self.id);
+        run_adapter_op(async move {
+            if let Err(_e) = ns.remove_socket(sid).await {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("error removing rejected socket: {:?}", _e);
+            }
+        });
+    }
+
     /// Closes the engine.io connection if it is not already closed.
     /// Return a future that resolves when the underlying transport is closed.
     pub(crate) async fn close_underlying_transport(&self) {
@@ -575,65 +998,286 @@ impl<A: Adapter> Socket<A> {
         &self.ns.path
     }
 
-    pub(crate) fn send(&self, mut packet: Packet<'_>) -> Result<(), SendError> {
-        let bin_payloads = match packet.inner {
-            PacketData::BinaryEvent(_, ref mut bin, _) | PacketData::BinaryAck(ref mut bin, _) => {
-                Some(std::mem::take(&mut bin.bin))
-            }
-            _ => None,
-        };
+    /// Waits until the socket is disconnected, right before its disconnect handler is called.
+    /// It resolves right away if the socket is already disconnected.
+    ///
+    /// It can be used to stop a long running task of the socket, see also
+    /// [`NsHandle::with_handler_cancellation`](crate::NsHandle::with_handler_cancellation)
+    /// to cancel the async handlers.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// # use std::time::Duration;
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |s: SocketRef| async move {
+    ///     let ticks = async {
+    ///         loop {
+    ///             s.emit("tick", ()).ok();
+    ///             tokio::time::sleep(Duration::from_secs(1)).await;
+    ///         }
+    ///     };
+    ///     tokio::select! {
+    ///         _ = ticks => {}
+    ///         _ = s.disconnected() => println!("socket {} disconnected", s.id),
+    ///     }
+    /// });
+    /// ```
+    pub async fn disconnected(&self) {
+        self.disconnected
+            .subscribe()
+            .wait_for(|disconnected| *disconnected)
+            .await
+            .ok();
+    }
 
-        let msg = packet.try_into()?;
-        self.esocket.emit(msg)?;
-        if let Some(bin_payloads) = bin_payloads {
-            for bin in bin_payloads {
-                self.esocket.emit_binary(bin)?;
-            }
+    /// Runs the future of an async handler, it is dropped when the socket disconnects
+    /// if the namespace cancels the handlers.
+    pub(crate) async fn cancel_on_disconnect(self: Arc<Self>, fut: impl Future<Output = ()>) {
+        if !self.cancel_handlers {
+            return fut.await;
+        }
+        let disconnected = self.disconnected();
+        futures::pin_mut!(fut, disconnected);
+        if let Either::Right(_) = future::select(fut, disconnected).await {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] socket disconnected, handler cancelled", self.id);
         }
+    }
 
-        Ok(())
+    /// Gets the parameters extracted from the namespace path if it was created from a dynamic namespace.
+    pub(crate) fn ns_params(&self) -> &HashMap<String, String> {
+        &self.ns.params
+    }
+
+    /// Sends an ack response carrying an error, in the form of `{ "message": "<error>" }`
+    pub(crate) fn send_ack_error(&self, ack: i64, message: &str) {
+        let data = serde_json::json!({ "message": message });
+        if let Err(_e) = self.send(Packet::ack(self.ns(), data, ack)) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("error sending ack error packet: {:?}", _e);
+        }
+    }
+
+    pub(crate) fn send(&self, packet: Packet<'_>) -> Result<(), SendError> {
+        parser::send_packet(&self.esocket, &self.config, packet)
+    }
+
+    /// Sends a packet shared with other sockets, see [`EncodedPacket`](parser::EncodedPacket)
+    pub(crate) fn send_encoded(
+        &self,
+        packet: &mut parser::EncodedPacket<'_>,
+    ) -> Result<(), SendError> {
+        packet.send(&self.esocket, &self.config)
     }
 
     pub(crate) async fn send_with_ack<'a, V: DeserializeOwned>(
         &self,
-        mut packet: Packet<'a>,
+        packet: Packet<'a>,
         timeout: Option<Duration>,
     ) -> Result<AckResponse<V>, AckError> {
+        let (ack, rx) = self.send_ack_packet(packet)?;
+        self.wait_ack(ack, rx, timeout).await
+    }
+
+    /// Registers a new pending acknowledgement and sends the packet with its ack id.
+    ///
+    /// The returned receiver should then be awaited with [`Socket::wait_ack`].
+    pub(crate) fn send_ack_packet(
+        &self,
+        mut packet: Packet<'_>,
+    ) -> Result<(i64, oneshot::Receiver<AckResponse<Value>>), SendError> {
         let (tx, rx) = oneshot::channel();
         let ack = self.ack_counter.fetch_add(1, Ordering::SeqCst) + 1;
         self.ack_message.lock().unwrap().insert(ack, tx);
         packet.inner.set_ack_id(ack);
-        self.send(packet)?;
+        if let Err(e) = self.send(packet) {
+            self.take_ack(ack);
+            return Err(e);
+        }
+        Ok((ack, rx))
+    }
+
+    /// Waits for the acknowledgement of a packet sent with [`Socket::send_ack_packet`].
+    pub(crate) async fn wait_ack<V: DeserializeOwned>(
+        &self,
+        ack: i64,
+        rx: oneshot::Receiver<AckResponse<Value>>,
+        timeout: Option<Duration>,
+    ) -> Result<AckResponse<V>, AckError> {
         let timeout = timeout.unwrap_or(self.config.ack_timeout);
-        let v = tokio::time::timeout(timeout, rx).await??;
+        let v = match tokio::time::timeout(timeout, rx).await {
+            Ok(v) => v?,
+            Err(e) => {
+                // The client will never be able to ack this packet, so its slot can be released
+                self.take_ack(ack);
+                return Err(e.into());
+            }
+        };
         Ok(AckResponse {
             data: serde_json::from_value(v.data)?,
             binary: v.binary,
         })
     }
 
+    /// Removes a pending acknowledgement and notifies the tasks waiting in [`Socket::wait_pending_acks`]
+    fn take_ack(&self, ack: i64) -> Option<oneshot::Sender<AckResponse<Value>>> {
+        let tx = self.ack_message.lock().unwrap().remove(&ack);
+        self.ack_notify.notify_waiters();
+        tx
+    }
+
+    /// Returns the ids of the acknowledgements that are still awaited
+    pub(crate) fn pending_acks(&self) -> Vec<i64> {
+        self.ack_message.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Returns the number of the given acknowledgements that are still awaited
+    pub(crate) fn count_pending_acks(&self, acks: &[i64]) -> usize {
+        let ack_message = self.ack_message.lock().unwrap();
+        acks.iter()
+            .filter(|ack| ack_message.contains_key(ack))
+            .count()
+    }
+
+    /// Waits until all the given acknowledgements are resolved
+    pub(crate) async fn wait_pending_acks(&self, acks: &[i64]) {
+        loop {
+            // The future must be created before checking to not miss any notification
+            let notified = self.ack_notify.notified();
+            if self.count_pending_acks(acks) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
     /// Called when the socket is gracefully disconnected from the server or the client
     ///
     /// It maybe also close when the underlying transport is closed or failed.
-    pub(crate) fn close(self: Arc<Self>, reason: DisconnectReason) -> Result<(), AdapterError> {
+    ///
+    /// If its session is saved and a disconnect grace period is configured,
+    /// the socket is only torn down once the period elapses without its client reconnecting.
+    ///
+    /// The rooms of the socket are read before it is removed from them,
+    /// so the whole teardown is run as a single adapter operation.
+    pub(crate) fn close(self: Arc<Self>, reason: DisconnectReason) {
+        run_adapter_op(async move {
+            let pid = self.save_session(reason).await;
+            if let (Some(pid), Some(grace_period)) = (pid, self.config.disconnect_grace_period) {
+                self.remove_from_ns().await;
+                self.ns
+                    .clone()
+                    .keep_disconnecting(pid, self, reason, grace_period);
+                return;
+            }
+            self.call_disconnect_handler(reason).await;
+            self.remove_from_ns().await;
+        });
+    }
+
+    /// Removes the socket from its namespace and its rooms
+    async fn remove_from_ns(&self) {
+        if let Err(_e) = self.ns.remove_socket(self.id).await {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] error removing socket: {:?}", self.id, _e);
+        }
+    }
+
+    /// Calls the disconnect handler if it is set, it can only be called once.
+    ///
+    /// The lifecycle event streams are then notified of the disconnection.
+    pub(crate) async fn call_disconnect_handler(self: &Arc<Self>, reason: DisconnectReason) {
+        self.disconnected.send_replace(true);
+        self.emit_last_will(reason).await;
         if let Some(handler) = self.disconnect_handler.lock().unwrap().take() {
             handler.call(self.clone(), reason);
         }
+        self.ns.lifecycle.send(LifecycleEvent::Disconnected {
+            sid: self.id,
+            ns: self.ns.path.clone(),
+            reason,
+        });
+    }
 
-        self.ns.remove_socket(self.id)?;
-        Ok(())
+    /// Emits the last will of the socket if it is set and the socket was not cleanly disconnected
+    async fn emit_last_will(&self, reason: DisconnectReason) {
+        let Some(will) = self.last_will.lock().unwrap().take() else {
+            return;
+        };
+        if reason.is_clean() {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] emitting last will to {}", self.id, will.room);
+        let res = Operators::new(self.ns.clone(), Some(self.id))
+            .to(will.room)
+            .emit(will.event, will.data)
+            .await;
+        if let Err(_e) = res {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("[sid={}] error emitting last will: {:?}", self.id, _e);
+        }
+    }
+
+    /// Takes a token for a received event, returns false if the event exceeds the rate limit
+    fn take_event_token(&self) -> bool {
+        match (&self.rate_limiter, &self.config.rate_limit) {
+            (Some(bucket), Some(limit)) => bucket.lock().unwrap().try_take(limit, Instant::now()),
+            _ => true,
+        }
+    }
+
+    /// Reserves a permit to run an async event handler if the namespace limits their concurrency.
+    ///
+    /// The permit is queued right away, so that the permits are granted in the order the events are received.
+    pub(crate) fn reserve_handler_permit(
+        &self,
+    ) -> BoxFuture<'static, Option<OwnedSemaphorePermit>> {
+        let Some(permits) = self.handler_permits.clone() else {
+            return future::ready(None).boxed();
+        };
+        let mut acquire = permits.acquire_owned().map(Result::ok).boxed();
+        match acquire.as_mut().now_or_never() {
+            Some(permit) => future::ready(permit).boxed(),
+            None => acquire,
+        }
+    }
+
+    /// Applies the [`RateLimitPolicy`] to an event exceeding the rate limit
+    fn on_rate_limit_exceeded(self: Arc<Self>) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("[sid={}] event rate limit exceeded", self.id);
+        match self.config.rate_limit.map(|limit| limit.policy) {
+            Some(RateLimitPolicy::Disconnect) => {
+                if let Err(_e) = self.send(Packet::disconnect(&self.ns.path)) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("error sending disconnect packet: {:?}", _e);
+                }
+                self.close(DisconnectReason::RateLimitExceeded);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
     }
 
     // Receives data from client:
     pub(crate) fn recv(self: Arc<Self>, packet: PacketData<'_>) -> Result<(), Error> {
+        let is_event = matches!(packet, PacketData::Event(..) | PacketData::BinaryEvent(..));
+        if is_event && !self.take_event_token() {
+            return self.on_rate_limit_exceeded();
+        }
         match packet {
-            PacketData::Event(e, data, ack) => self.recv_event(&e, data.map_or(Value::Null, |f|f), ack),
+            PacketData::Event(e, data, ack) => {
+                self.recv_event(&e, data.map_or(Value::Null, |f| f), ack)
+            }
             PacketData::EventAck(data, ack_id) => self.recv_ack(data, ack_id),
             PacketData::BinaryEvent(e, packet, ack) => self.recv_bin_event(&e, packet, ack),
             PacketData::BinaryAck(packet, ack) => self.recv_bin_ack(packet, ack),
-            PacketData::Disconnect => self
-                .close(DisconnectReason::ClientNSDisconnect)
-                .map_err(Error::from),
+            PacketData::Disconnect(_) => {
+                self.close(DisconnectReason::ClientNSDisconnect);
+                Ok(())
+            }
             _ => unreachable!(),
         }
     }
@@ -641,13 +1285,23 @@ impl<A: Adapter> Socket<A> {
     /// Gets the request info made by the client to connect
     ///
     /// Note that the `extensions` field will be empty and will not
-    /// contain extensions set in the previous http layers for requests initialized with ws transport.
+    /// contain extensions set in the previous http layers for requests initialized with ws transport,
+    /// except for the peer [`SocketAddr`](std::net::SocketAddr).
     ///
     /// It is because [`http::Extensions`] is not cloneable and is needed for ws upgrade.
     pub fn req_parts(&self) -> &http::request::Parts {
         &self.esocket.req_parts
     }
 
+    /// Gets the ip address of the client, resolved according to the [`ClientIpConfig`](crate::ClientIpConfig)
+    /// set with [`SocketIoBuilder::client_ip`](crate::SocketIoBuilder::client_ip).
+    ///
+    /// It returns `None` if the peer address was not set by the server.
+    /// It can also be accessed with the [`ClientIp`](crate::extract::ClientIp) extractor.
+    pub fn client_ip(&self) -> Option<std::net::IpAddr> {
+        self.config.client_ip.resolve(&self.esocket.req_parts)
+    }
+
     /// Gets the [`TransportType`](crate::TransportType) used by the client to connect with this [`Socket`]
     ///
     /// It can also be accessed as an extractor:
@@ -662,6 +1316,33 @@ impl<A: Adapter> Socket<A> {
         self.esocket.transport_type()
     }
 
+    /// Keeps the connection of the client on the polling transport by refusing its upgrade requests from now on.
+    /// The upgrade probe of the client fails and it keeps polling, which can be used to work around
+    /// unreliable websocket paths. It can be called from the connect handler to prevent any upgrade.
+    ///
+    /// The engine.io connection is shared with the sockets of the same client on the other namespaces.
+    /// Engine.io has no packet to downgrade a connection, so it returns `false` if the client
+    /// already upgraded to another transport.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     if socket.req_parts().headers.contains_key("x-polling-only") {
+    ///         socket.force_polling();
+    ///     }
+    /// });
+    /// ```
+    pub fn force_polling(&self) -> bool {
+        self.esocket.force_polling()
+    }
+
+    /// Returns the number of packets buffered in the underlying engine.io session
+    pub(crate) fn buffered_packets(&self) -> usize {
+        self.esocket.buffered_packets()
+    }
+
     /// Gets the socket.io [`ProtocolVersion`](crate::ProtocolVersion) used by the client to connect with this [`Socket`]
     ///
     /// It can also be accessed as an extractor:
@@ -677,9 +1358,178 @@ impl<A: Adapter> Socket<A> {
         self.esocket.protocol.into()
     }
 
-    fn recv_event(self: Arc<Self>, e: &str, data: Value, ack: Option<i64>) -> Result<(), Error> {
-        if let Some(handler) = self.message_handlers.read().unwrap().get(e) {
-            handler.call(self.clone(), data, vec![], ack);
+    /// Returns `true` if this socket recovered the session of a previous socket
+    /// disconnected abruptly. Its rooms were then restored and the missed packets replayed.
+    ///
+    /// See the [`session`](crate::session) module for more details.
+    pub fn recovered(&self) -> bool {
+        self.recovered.load(Ordering::SeqCst)
+    }
+
+    /// Sets the typed data of this socket, replacing the data previously set.
+    ///
+    /// It is typically set in the connect handler (e.g. with a user id)
+    /// and retrieved with [`Socket::data`] or with the [`SocketData`](crate::extract::SocketData) extractor.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// struct User {
+    ///     name: String,
+    /// }
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.set_data(User { name: "alice".into() });
+    ///     let user = socket.data::<User>().unwrap();
+    ///     println!("{} connected", user.name);
+    /// });
+    /// ```
+    pub fn set_data<T: Send + Sync + 'static>(&self, data: T) {
+        self.data.write().unwrap().replace(Arc::new(data));
+    }
+
+    /// Registers a "last will" message emitted by the server to a room if the socket is abruptly disconnected,
+    /// e.g. because of a transport error or a heartbeat timeout. It replaces the previous last will if any.
+    ///
+    /// The message is not emitted if the client or the server disconnects the socket on purpose,
+    /// or if it is cancelled with [`Socket::cancel_last_will`].
+    /// The socket itself is excluded from the recipients.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| async move {
+    ///     socket.join("sensors").await.ok();
+    ///     socket.set_last_will("sensors", "offline", socket.id.to_string()).ok();
+    /// });
+    /// ```
+    pub fn set_last_will(
+        &self,
+        room: impl Into<Room>,
+        event: impl Into<Cow<'static, str>>,
+        data: impl Serialize,
+    ) -> Result<(), serde_json::Error> {
+        let will = LastWill {
+            room: room.into(),
+            event: event.into(),
+            data: serde_json::to_value(data)?,
+        };
+        self.last_will.lock().unwrap().replace(will);
+        Ok(())
+    }
+
+    /// Cancels the last will set with [`Socket::set_last_will`].
+    ///
+    /// Returns true if a last will was set.
+    pub fn cancel_last_will(&self) -> bool {
+        self.last_will.lock().unwrap().take().is_some()
+    }
+
+    /// Gets the typed data of this socket set with [`Socket::set_data`].
+    ///
+    /// It returns `None` if no data was set or if it is not of type `T`.
+    pub fn data<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let data = self.data.read().unwrap().clone()?;
+        data.downcast().ok()
+    }
+
+    /// Gets the round-trip time measured during the last heartbeat of the underlying engine.io connection.
+    ///
+    /// It is `None` until the first heartbeat completes and with the v3 protocol,
+    /// where the heartbeat is initiated by the client.
+    ///
+    /// #### Example
+    /// ```
+    /// # use socketioxide::{SocketIo, extract::*};
+    /// let (_, io) = SocketIo::new_svc();
+    /// io.ns("/", |socket: SocketRef| {
+    ///     socket.on("quality", |socket: SocketRef| {
+    ///         if let Some(rtt) = socket.rtt() {
+    ///             println!("rtt: {}ms", rtt.as_millis());
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn rtt(&self) -> Option<Duration> {
+        self.esocket.rtt()
+    }
+
+    /// Runs the namespace event middlewares, if one of them vetoes the event,
+    /// its error is sent back as an ack response if the client requested one.
+    fn apply_event_middlewares(
+        self: &Arc<Self>,
+        e: &str,
+        data: &mut Value,
+        ack: Option<i64>,
+    ) -> bool {
+        match self.ns.apply_event_middlewares(self, e, data) {
+            Ok(()) => true,
+            Err(message) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    "[sid={}] event {e} rejected by middleware: {message}",
+                    self.id
+                );
+                if let Some(ack) = ack {
+                    self.send_ack_error(ack, &message);
+                }
+                false
+            }
+        }
+    }
+
+    /// Checks the size of a reassembled event against the [`SocketIoConfig::max_event_size`],
+    /// returns false if the event is too large, in which case an error ack is sent if the client requested one.
+    fn check_event_size(&self, _e: &str, data: &Value, bin: &[Vec<u8>], ack: Option<i64>) -> bool {
+        let Some(max) = self.config.max_event_size else {
+            return true;
+        };
+        let size = json_size(data) + bin.iter().map(Vec::len).sum::<usize>();
+        if size <= max {
+            return true;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            "[sid={}] event {_e} rejected, {size} bytes exceeds the max event size",
+            self.id
+        );
+        if let Some(ack) = ack {
+            self.send_ack_error(ack, "event too large");
+        }
+        false
+    }
+
+    /// Rejects an event without a registered handler if [`SocketIoConfig::reject_unknown_events`] is set
+    fn on_unknown_event(&self, _e: &str, ack: Option<i64>) {
+        if !self.config.reject_unknown_events {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!("[sid={}] unknown event {_e} rejected", self.id);
+        self.ns.rejected_events.fetch_add(1, Ordering::Relaxed);
+        if let Some(ack) = ack {
+            self.send_ack_error(ack, "unknown event");
+        }
+    }
+
+    fn recv_event(
+        self: Arc<Self>,
+        e: &str,
+        mut data: Value,
+        ack: Option<i64>,
+    ) -> Result<(), Error> {
+        if !self.check_event_size(e, &data, &[], ack) {
+            return Ok(());
+        }
+        if !self.apply_event_middlewares(e, &mut data, ack) {
+            return Ok(());
+        }
+        match self.message_handlers.read().unwrap().get(e) {
+            Some(handler) => {
+                extract::with_event(e, &[], || handler.call(self.clone(), data, vec![], ack))
+            }
+            None => self.on_unknown_event(e, ack),
         }
         Ok(())
     }
@@ -690,14 +1540,25 @@ impl<A: Adapter> Socket<A> {
         packet: BinaryPacket,
         ack: Option<i64>,
     ) -> Result<(), Error> {
-        if let Some(handler) = self.message_handlers.read().unwrap().get(e) {
-            handler.call(self.clone(), packet.data.map_or(Value::Null, |x|x), packet.bin, ack);
+        let placeholders = packet.placeholders().to_vec();
+        let mut data = packet.data.map_or(Value::Null, |x| x);
+        if !self.check_event_size(e, &data, &packet.bin, ack) {
+            return Ok(());
+        }
+        if !self.apply_event_middlewares(e, &mut data, ack) {
+            return Ok(());
+        }
+        match self.message_handlers.read().unwrap().get(e) {
+            Some(handler) => extract::with_event(e, &placeholders, || {
+                handler.call(self.clone(), data, packet.bin, ack)
+            }),
+            None => self.on_unknown_event(e, ack),
         }
         Ok(())
     }
 
     fn recv_ack(self: Arc<Self>, data: Value, ack: i64) -> Result<(), Error> {
-        if let Some(tx) = self.ack_message.lock().unwrap().remove(&ack) {
+        if let Some(tx) = self.take_ack(ack) {
             let res = AckResponse {
                 data,
                 binary: vec![],
@@ -708,7 +1569,7 @@ impl<A: Adapter> Socket<A> {
     }
 
     fn recv_bin_ack(self: Arc<Self>, packet: BinaryPacket, ack: i64) -> Result<(), Error> {
-        if let Some(tx) = self.ack_message.lock().unwrap().remove(&ack) {
+        if let Some(tx) = self.take_ack(ack) {
             let res = AckResponse {
                 data: packet.data.map_or(Value::Null, |x| x),
                 binary: packet.bin,
@@ -719,6 +1580,23 @@ impl<A: Adapter> Socket<A> {
     }
 }
 
+/// Returns the size in bytes of a JSON value once serialized, without allocating it
+fn json_size(value: &Value) -> usize {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value).ok();
+    counter.0
+}
+
 impl<A: Adapter> Debug for Socket<A> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Socket")
@@ -732,7 +1610,7 @@ impl<A: Adapter> Debug for Socket<A> {
 
 #[cfg(test)]
 impl<A: Adapter> Socket<A> {
-    pub fn new_dummy(sid: Sid, ns: Arc<Namespace<A>>) -> Socket<A> {
+    pub(crate) fn new_dummy(sid: Sid, ns: Arc<Namespace<A>>) -> Socket<A> {
         let close_fn = Box::new(move |_, _| ());
         Socket::new(
             sid,
@@ -742,3 +1620,440 @@ impl<A: Adapter> Socket<A> {
         )
     }
 }
+
+/// A token bucket limiting the events received by a socket
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket according to the elapsed time, then takes a token if there is one
+    fn try_take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = elapsed
+            .mul_add(limit.events_per_second, self.tokens)
+            .min(limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limit = RateLimit::new(10.0, 3);
+        let mut bucket = TokenBucket::new(&limit);
+        let now = bucket.last_refill;
+        assert!((0..3).all(|_| bucket.try_take(&limit, now)));
+        assert!(!bucket.try_take(&limit, now));
+
+        // One token is refilled every 100ms, up to the burst
+        assert!(!bucket.try_take(&limit, now + Duration::from_millis(50)));
+        assert!(bucket.try_take(&limit, now + Duration::from_millis(100)));
+        assert!(!bucket.try_take(&limit, now + Duration::from_millis(100)));
+        let later = now + Duration::from_secs(10);
+        assert!((0..3).all(|_| bucket.try_take(&limit, later)));
+        assert!(!bucket.try_take(&limit, later));
+    }
+
+    #[tokio::test]
+    async fn emit_with_ack_timeout() {
+        let sid = Sid::new();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket: Arc<Socket> = Socket::new_dummy(sid, ns).into();
+        let res = socket
+            .emit_with_ack_timeout::<Value>("test", "data", Duration::from_millis(10))
+            .await;
+        assert!(matches!(res, Err(AckError::Timeout(_))));
+        assert!(socket.ack_message.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timeout_operator_overrides_ack_timeout() {
+        use futures::StreamExt;
+        let sid = Sid::new();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        assert_eq!(socket.config.ack_timeout, Duration::from_secs(5));
+
+        let mut acks = socket
+            .timeout(Duration::from_millis(10))
+            .emit_with_ack::<Value>("test", "data")
+            .await
+            .unwrap();
+        let (id, res) = tokio::time::timeout(Duration::from_secs(1), acks.next())
+            .await
+            .expect("the ack timeout should be the one of the operator")
+            .unwrap();
+        assert_eq!(id, sid);
+        assert!(matches!(res, Err(AckError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn volatile_emit() {
+        let sid = Sid::new();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        // Fill the socket buffer
+        while socket.emit("test", "data").is_ok() {}
+        assert!(matches!(
+            socket.emit("test", "data"),
+            Err(SendError::InternalChannelFull)
+        ));
+
+        // Volatile packets are dropped without error
+        socket.volatile().emit("test", "data").await.unwrap();
+        socket.volatile().emit_empty("test").await.unwrap();
+        socket
+            .broadcast()
+            .volatile()
+            .emit("test", "data")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rooms() {
+        let sid = Sid::new();
+        let ns = Namespace::<LocalAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        socket.join(["room1", "room2"]).await.unwrap();
+        let mut rooms = socket.rooms().await.unwrap();
+        rooms.sort();
+        assert_eq!(rooms, ["room1", "room2"]);
+
+        socket.leave("room1").await.unwrap();
+        assert_eq!(socket.rooms().await.unwrap(), ["room2"]);
+    }
+    /// An adapter counting the operations that lock its rooms
+    #[derive(Debug)]
+    struct CountingAdapter {
+        state: crate::adapter::LocalState,
+        locks: std::sync::atomic::AtomicUsize,
+        ns: std::sync::Weak<Namespace<Self>>,
+    }
+
+    impl CountingAdapter {
+        fn lock(&self) -> Arc<Namespace<Self>> {
+            self.locks.fetch_add(1, Ordering::SeqCst);
+            self.ns.upgrade().unwrap()
+        }
+    }
+
+    impl Adapter for CountingAdapter {
+        type Error = std::convert::Infallible;
+
+        fn new(ns: std::sync::Weak<Namespace<Self>>) -> Self {
+            Self {
+                state: crate::adapter::LocalState::new(),
+                locks: 0.into(),
+                ns,
+            }
+        }
+        fn init(&self) -> BoxFuture<'_, Result<(), Self::Error>> {
+            future::ready(Ok(())).boxed()
+        }
+        fn close(&self) -> BoxFuture<'_, Result<(), Self::Error>> {
+            future::ready(Ok(())).boxed()
+        }
+        fn server_count(&self) -> BoxFuture<'_, Result<u16, Self::Error>> {
+            future::ready(Ok(1)).boxed()
+        }
+        fn add_all(
+            &self,
+            sid: Sid,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            self.lock();
+            self.state.add_all(sid, rooms);
+            future::ready(Ok(())).boxed()
+        }
+        fn del(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Self::Error>> {
+            self.lock();
+            self.state.del(sid, rooms);
+            future::ready(Ok(())).boxed()
+        }
+        fn del_all(&self, sid: Sid) -> BoxFuture<'_, Result<(), Self::Error>> {
+            self.lock();
+            self.state.del_all(sid);
+            future::ready(Ok(())).boxed()
+        }
+        fn broadcast(
+            &self,
+            packet: Packet<'static>,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<usize, crate::errors::BroadcastError>> {
+            future::ready(self.state.broadcast(&self.lock(), packet, opts)).boxed()
+        }
+        fn broadcast_with_ack<V: DeserializeOwned + 'static>(
+            &self,
+            packet: Packet<'static>,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<crate::adapter::AckStream<V>, crate::errors::BroadcastError>>
+        {
+            let stream = self.state.broadcast_with_ack(&self.lock(), packet, opts);
+            future::ready(Ok(stream)).boxed()
+        }
+        fn sockets(&self, rooms: impl RoomParam) -> BoxFuture<'_, Result<Vec<Sid>, Self::Error>> {
+            future::ready(Ok(self.state.sockets(&self.lock(), rooms))).boxed()
+        }
+        fn rooms(&self) -> BoxFuture<'_, Result<crate::adapter::RoomsSnapshot, Self::Error>> {
+            self.lock();
+            future::ready(Ok(self.state.rooms())).boxed()
+        }
+        fn socket_rooms(&self, sid: Sid) -> BoxFuture<'_, Result<Vec<Room>, Self::Error>> {
+            self.lock();
+            future::ready(Ok(self.state.socket_rooms(sid))).boxed()
+        }
+        fn fetch_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<Vec<crate::extract::SocketRef<Self>>, Self::Error>> {
+            future::ready(Ok(self.state.apply_opts(&self.lock(), opts))).boxed()
+        }
+        fn add_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            self.state.add_sockets(&self.lock(), opts, rooms);
+            future::ready(Ok(())).boxed()
+        }
+        fn del_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            self.state.del_sockets(&self.lock(), opts, rooms);
+            future::ready(Ok(())).boxed()
+        }
+        fn disconnect_socket(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<(), crate::errors::BroadcastError>> {
+            future::ready(self.state.disconnect_socket(&self.lock(), opts)).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn join_all() {
+        let sid = Sid::new();
+        let ns = Namespace::<CountingAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        let rooms: Vec<Room> = (0..50).map(|i| format!("topic{i}").into()).collect();
+        socket.join_all(rooms.clone()).await.unwrap();
+        assert_eq!(ns.adapter.locks.load(Ordering::SeqCst), 1);
+
+        let mut joined = socket.rooms().await.unwrap();
+        joined.sort();
+        let mut expected = rooms.clone();
+        expected.sort();
+        assert_eq!(joined, expected);
+        for room in &rooms {
+            assert_eq!(ns.adapter.state.sockets(&ns, room.clone()), [sid]);
+        }
+
+        socket.leave(rooms).await.unwrap();
+        assert_eq!(ns.adapter.locks.load(Ordering::SeqCst), 3);
+        assert!(socket.rooms().await.unwrap().is_empty());
+    }
+
+    /// An adapter yielding to the runtime before each operation, like a remote adapter waiting for the network
+    #[derive(Debug)]
+    struct PendingAdapter {
+        state: crate::adapter::LocalState,
+        ns: std::sync::Weak<Namespace<Self>>,
+    }
+
+    impl PendingAdapter {
+        async fn ns(&self) -> Arc<Namespace<Self>> {
+            tokio::task::yield_now().await;
+            self.ns.upgrade().unwrap()
+        }
+    }
+
+    impl Adapter for PendingAdapter {
+        type Error = std::convert::Infallible;
+
+        fn new(ns: std::sync::Weak<Namespace<Self>>) -> Self {
+            Self {
+                state: crate::adapter::LocalState::new(),
+                ns,
+            }
+        }
+        fn init(&self) -> BoxFuture<'_, Result<(), Self::Error>> {
+            tokio::task::yield_now().map(Ok).boxed()
+        }
+        fn close(&self) -> BoxFuture<'_, Result<(), Self::Error>> {
+            tokio::task::yield_now().map(Ok).boxed()
+        }
+        fn server_count(&self) -> BoxFuture<'_, Result<u16, Self::Error>> {
+            tokio::task::yield_now().map(|_| Ok(1)).boxed()
+        }
+        fn add_all(
+            &self,
+            sid: Sid,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            let rooms: Vec<Room> = rooms.into_room_iter().collect();
+            async move {
+                self.ns().await;
+                self.state.add_all(sid, rooms);
+                Ok(())
+            }
+            .boxed()
+        }
+        fn del(&self, sid: Sid, rooms: impl RoomParam) -> BoxFuture<'_, Result<(), Self::Error>> {
+            let rooms: Vec<Room> = rooms.into_room_iter().collect();
+            async move {
+                self.ns().await;
+                self.state.del(sid, rooms);
+                Ok(())
+            }
+            .boxed()
+        }
+        fn del_all(&self, sid: Sid) -> BoxFuture<'_, Result<(), Self::Error>> {
+            async move {
+                self.ns().await;
+                self.state.del_all(sid);
+                Ok(())
+            }
+            .boxed()
+        }
+        fn broadcast(
+            &self,
+            packet: Packet<'static>,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<usize, crate::errors::BroadcastError>> {
+            async move {
+                let ns = self.ns().await;
+                self.state.broadcast(&ns, packet, opts)
+            }
+            .boxed()
+        }
+        fn broadcast_with_ack<V: DeserializeOwned + 'static>(
+            &self,
+            packet: Packet<'static>,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<crate::adapter::AckStream<V>, crate::errors::BroadcastError>>
+        {
+            async move {
+                let ns = self.ns().await;
+                Ok(self.state.broadcast_with_ack(&ns, packet, opts))
+            }
+            .boxed()
+        }
+        fn sockets(&self, rooms: impl RoomParam) -> BoxFuture<'_, Result<Vec<Sid>, Self::Error>> {
+            let rooms: Vec<Room> = rooms.into_room_iter().collect();
+            async move {
+                let ns = self.ns().await;
+                Ok(self.state.sockets(&ns, rooms))
+            }
+            .boxed()
+        }
+        fn rooms(&self) -> BoxFuture<'_, Result<crate::adapter::RoomsSnapshot, Self::Error>> {
+            async move {
+                self.ns().await;
+                Ok(self.state.rooms())
+            }
+            .boxed()
+        }
+        fn socket_rooms(&self, sid: Sid) -> BoxFuture<'_, Result<Vec<Room>, Self::Error>> {
+            async move {
+                self.ns().await;
+                Ok(self.state.socket_rooms(sid))
+            }
+            .boxed()
+        }
+        fn fetch_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<Vec<crate::extract::SocketRef<Self>>, Self::Error>> {
+            async move {
+                let ns = self.ns().await;
+                Ok(self.state.apply_opts(&ns, opts))
+            }
+            .boxed()
+        }
+        fn add_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            let rooms: Vec<Room> = rooms.into_room_iter().collect();
+            async move {
+                let ns = self.ns().await;
+                self.state.add_sockets(&ns, opts, rooms);
+                Ok(())
+            }
+            .boxed()
+        }
+        fn del_sockets(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+            rooms: impl RoomParam,
+        ) -> BoxFuture<'_, Result<(), Self::Error>> {
+            let rooms: Vec<Room> = rooms.into_room_iter().collect();
+            async move {
+                let ns = self.ns().await;
+                self.state.del_sockets(&ns, opts, rooms);
+                Ok(())
+            }
+            .boxed()
+        }
+        fn disconnect_socket(
+            &self,
+            opts: crate::adapter::BroadcastOptions,
+        ) -> BoxFuture<'_, Result<(), crate::errors::BroadcastError>> {
+            async move {
+                let ns = self.ns().await;
+                self.state.disconnect_socket(&ns, opts)
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn pending_adapter() {
+        let sid = Sid::new();
+        let ns = Namespace::<PendingAdapter>::new_dummy([sid]);
+        let socket = ns.get_socket(sid).unwrap();
+        socket.join(["room1", "room2"]).await.unwrap();
+        let mut rooms = socket.rooms().await.unwrap();
+        rooms.sort();
+        assert_eq!(rooms, ["room1", "room2"]);
+        let sockets = socket.within("room1").sockets().await.unwrap();
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].id, sid);
+
+        socket.leave("room1").await.unwrap();
+        assert_eq!(socket.rooms().await.unwrap(), ["room2"]);
+
+        // The teardown is still pending on the adapter when the socket is closed from a synchronous context,
+        // so it is spawned on the runtime
+        socket.close(DisconnectReason::TransportClose);
+        assert!(ns.get_socket(sid).is_err());
+        assert_eq!(ns.adapter.state.socket_rooms(sid), ["room2"]);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(ns.adapter.state.socket_rooms(sid).is_empty());
+    }
+}
@@ -11,15 +11,15 @@ static mut STATE: TypeMap![Send + Sync] = <TypeMap![Send + Sync]>::new();
 
 // SAFETY: the `get_state` is called many times from extractors but never mutably and after the `freeze_state` call
 pub(crate) fn get_state<T: Send + Sync + 'static>() -> Option<&'static T> {
-    unsafe { STATE.try_get::<T>() }
+    unsafe { (*std::ptr::addr_of!(STATE)).try_get::<T>() }
 }
 // SAFETY: the `freeze_state` is only called once at the launch of the server
 pub(crate) fn freeze_state() {
-    unsafe { STATE.freeze() }
+    unsafe { (*std::ptr::addr_of_mut!(STATE)).freeze() }
 }
 // SAFETY: the `set_state` is only called when building the server and can be called multiple times
 pub(crate) fn set_state<T: Send + Sync + 'static>(value: T) {
-    unsafe { STATE.set(value) };
+    unsafe { (*std::ptr::addr_of!(STATE)).set(value) };
 }
 
 mod test {
@@ -29,13 +29,19 @@ mod test {
         // Mimic the parent static state
         static mut STATE: TypeMap![Send + Sync] = <TypeMap![Send + Sync]>::new();
 
-        unsafe { STATE.set(1i32) };
-        unsafe { STATE.set("hello") };
+        unsafe { (*std::ptr::addr_of!(STATE)).set(1i32) };
+        unsafe { (*std::ptr::addr_of!(STATE)).set("hello") };
 
-        assert_eq!(unsafe { STATE.len() }, 2);
-        unsafe { STATE.freeze() }
+        assert_eq!(unsafe { (*std::ptr::addr_of!(STATE)).len() }, 2);
+        unsafe { (*std::ptr::addr_of_mut!(STATE)).freeze() }
 
-        assert_eq!(unsafe { STATE.try_get::<i32>() }, Some(&1));
-        assert_eq!(unsafe { STATE.try_get::<&str>() }, Some(&"hello"));
+        assert_eq!(
+            unsafe { (*std::ptr::addr_of!(STATE)).try_get::<i32>() },
+            Some(&1)
+        );
+        assert_eq!(
+            unsafe { (*std::ptr::addr_of!(STATE)).try_get::<&str>() },
+            Some(&"hello")
+        );
     }
 }
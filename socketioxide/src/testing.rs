@@ -0,0 +1,449 @@
+//! An in-memory client to test a socket.io server without any HTTP server or network connection.
+//!
+//! A [`TestClient`] connects to a namespace of a [`SocketIo`] instance in-process.
+//! It emits events, awaits their acknowledgements and receives the events emitted by the server,
+//! the packets being exchanged as they would be with a websocket client.
+//! The heartbeat of the underlying engine.io session is not run.
+//!
+//! Binary payloads are received, they can only be sent by the client in acknowledgements with [`TestClient::ack_bin`].
+//!
+//! #### Example
+//! ```
+//! # use socketioxide::{SocketIo, extract::*, testing::TestClient};
+//! # use serde_json::Value;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let (_, io) = SocketIo::new_svc();
+//! io.ns("/", |socket: SocketRef| {
+//!     socket.emit("welcome", "hello").ok();
+//!     socket.on("echo", |ack: AckSender, Data::<Value>(data)| {
+//!         ack.send(data).ok();
+//!     });
+//! });
+//!
+//! let mut client = TestClient::connect(&io, "/").await.unwrap();
+//! let msg: String = client.recv_event("welcome").await.unwrap();
+//! assert_eq!(msg, "hello");
+//! let res: String = client.emit_with_ack("echo", "ping").await.unwrap();
+//! assert_eq!(res, "ping");
+//! # }
+//! ```
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use engineioxide::{
+    handler::EngineIoHandler, sid::Sid, socket::DisconnectReason as EIoDisconnectReason,
+    Packet as EIoPacket, Socket as EIoSocket,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    adapter::{Adapter, LocalAdapter},
+    client::{Client, SocketData},
+    errors::ParseError,
+    packet::{BinaryPacket, Packet, PacketData},
+    socket::AckResponse,
+    SocketIo,
+};
+
+type EngineSocket = EIoSocket<SocketData>;
+
+/// An error returned by a [`TestClient`]
+#[derive(thiserror::Error, Debug)]
+pub enum TestClientError {
+    /// The server refused the connection to the namespace, with the message of the connect error packet
+    #[error("connection refused: {0}")]
+    ConnectError(String),
+
+    /// No packet was received before the timeout of the client
+    #[error("timeout waiting for a packet")]
+    Timeout,
+
+    /// The client is disconnected from the namespace
+    #[error("client disconnected")]
+    Disconnected,
+
+    /// The event received is not the one expected
+    #[error("unexpected event {0}")]
+    UnexpectedEvent(String),
+
+    /// The server sent a packet that could not be decoded
+    #[error("malformed packet: {0}")]
+    Parse(#[from] ParseError),
+
+    /// The data could not be serialized or deserialized
+    #[error("error serializing json packet: {0:?}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// An event emitted by the server to a [`TestClient`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedEvent {
+    /// The name of the event
+    pub event: String,
+    /// The data of the event. An array holds the arguments if there are several of them
+    pub data: Value,
+    /// The binary payloads of the event
+    pub bin: Vec<Vec<u8>>,
+    /// The ack id if the server expects an acknowledgement, to send with [`TestClient::ack`]
+    pub ack_id: Option<i64>,
+}
+
+/// A client connected in-process to a namespace of a [`SocketIo`] instance, see the [module](self) doc.
+///
+/// The client is abruptly disconnected when it is dropped, like a client losing its connection.
+/// Use [`TestClient::disconnect`] to disconnect it cleanly.
+pub struct TestClient<A: Adapter = LocalAdapter> {
+    client: Arc<Client<A>>,
+    esocket: Arc<EngineSocket>,
+    ns: String,
+    timeout: Duration,
+    ack_counter: i64,
+    /// The events received and not consumed yet, e.g. while waiting for an ack
+    events: VecDeque<ReceivedEvent>,
+    /// The acknowledgements received and not consumed yet
+    acks: HashMap<i64, AckResponse<Value>>,
+    disconnected: bool,
+}
+
+impl<A: Adapter> TestClient<A> {
+    /// Connects a new client to the given namespace of the server.
+    ///
+    /// It returns once the connection is accepted,
+    /// or with a [`TestClientError::ConnectError`] if it is refused.
+    pub async fn connect(io: &SocketIo<A>, ns: impl Into<String>) -> Result<Self, TestClientError> {
+        Self::connect_inner(io, ns.into(), None).await
+    }
+
+    /// Connects a new client to the given namespace of the server with an auth payload,
+    /// see [`TestClient::connect`].
+    pub async fn connect_with_auth(
+        io: &SocketIo<A>,
+        ns: impl Into<String>,
+        auth: impl Serialize,
+    ) -> Result<Self, TestClientError> {
+        let auth = serde_json::to_string(&auth)?;
+        Self::connect_inner(io, ns.into(), Some(auth)).await
+    }
+
+    async fn connect_inner(
+        io: &SocketIo<A>,
+        ns: String,
+        auth: Option<String>,
+    ) -> Result<Self, TestClientError> {
+        let ns = match ns.starts_with('/') {
+            true => ns,
+            false => format!("/{ns}"),
+        };
+        let client = io.0.clone();
+        let esocket = new_engine_socket(&client);
+        client.on_connect(esocket.clone());
+
+        let mut test_client = TestClient {
+            client,
+            esocket,
+            ns,
+            timeout: Duration::from_secs(1),
+            ack_counter: 0,
+            events: VecDeque::new(),
+            acks: HashMap::new(),
+            disconnected: false,
+        };
+        let packet = Packet {
+            inner: PacketData::Connect(auth),
+            ns: Cow::Borrowed(&test_client.ns),
+        };
+        test_client.send(packet)?;
+        while !test_client.recv_packet().await? {}
+        Ok(test_client)
+    }
+
+    /// Sets the time to wait for a packet from the server before returning a [`TestClientError::Timeout`].
+    ///
+    /// Defaults to 1s.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the id of the socket of the client on the server
+    pub fn id(&self) -> Sid {
+        self.esocket.id
+    }
+
+    /// Emits an event to the server.
+    pub fn emit(
+        &self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl Serialize,
+    ) -> Result<(), TestClientError> {
+        let data = serde_json::to_value(data)?;
+        self.send(Packet::event(self.ns.clone(), event, Some(data)))
+    }
+
+    /// Emits an event to the server and waits for its acknowledgement, deserialized to `T`.
+    ///
+    /// The events received meanwhile are kept, to be returned by [`TestClient::recv`].
+    pub async fn emit_with_ack<T: DeserializeOwned>(
+        &mut self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl Serialize,
+    ) -> Result<T, TestClientError> {
+        Ok(self.emit_with_bin_ack(event, data).await?.data)
+    }
+
+    /// Emits an event to the server and waits for its acknowledgement, deserialized to `T`,
+    /// with the binary payloads of the acknowledgement.
+    ///
+    /// The events received meanwhile are kept, to be returned by [`TestClient::recv`].
+    pub async fn emit_with_bin_ack<T: DeserializeOwned>(
+        &mut self,
+        event: impl Into<Cow<'static, str>>,
+        data: impl Serialize,
+    ) -> Result<AckResponse<T>, TestClientError> {
+        let ack_id = self.ack_counter;
+        self.ack_counter += 1;
+        let data = serde_json::to_value(data)?;
+        let mut packet = Packet::event(self.ns.clone(), event, Some(data));
+        if let PacketData::Event(_, _, ref mut ack) = packet.inner {
+            *ack = Some(ack_id);
+        }
+        self.send(packet)?;
+        loop {
+            if let Some(ack) = self.acks.remove(&ack_id) {
+                return Ok(AckResponse {
+                    data: serde_json::from_value(ack.data)?,
+                    binary: ack.binary,
+                });
+            }
+            self.recv_packet().await?;
+        }
+    }
+
+    /// Acknowledges an event received from the server with [`ReceivedEvent::ack_id`].
+    pub fn ack(&self, ack_id: i64, data: impl Serialize) -> Result<(), TestClientError> {
+        let data = serde_json::to_value(data)?;
+        self.send(Packet::ack(&self.ns, data, ack_id))
+    }
+
+    /// Acknowledges an event received from the server with binary payloads, see [`TestClient::ack`].
+    ///
+    /// The server receives them in the [`AckResponse::binary`] field.
+    pub fn ack_bin(
+        &self,
+        ack_id: i64,
+        data: impl Serialize,
+        bin: Vec<Vec<u8>>,
+    ) -> Result<(), TestClientError> {
+        let data = serde_json::to_value(data)?;
+        if bin.is_empty() {
+            return self.send(Packet::ack(&self.ns, data, ack_id));
+        }
+        self.send(Packet::bin_ack(&self.ns, data, bin, ack_id))
+    }
+
+    /// Receives the next event emitted by the server.
+    pub async fn recv(&mut self) -> Result<ReceivedEvent, TestClientError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            self.recv_packet().await?;
+        }
+    }
+
+    /// Receives the next event emitted by the server, and deserializes its data to `T`.
+    ///
+    /// It returns a [`TestClientError::UnexpectedEvent`] if the event received is not the given one.
+    pub async fn recv_event<T: DeserializeOwned>(
+        &mut self,
+        event: &str,
+    ) -> Result<T, TestClientError> {
+        let received = self.recv().await?;
+        if received.event != event {
+            return Err(TestClientError::UnexpectedEvent(received.event));
+        }
+        Ok(serde_json::from_value(received.data)?)
+    }
+
+    /// Cleanly disconnects the client from the namespace and closes its connection.
+    pub fn disconnect(mut self) -> Result<(), TestClientError> {
+        let packet = Packet {
+            inner: PacketData::Disconnect(None),
+            ns: Cow::Borrowed(&self.ns),
+        };
+        self.send(packet)?;
+        self.disconnected = true;
+        self.esocket.close(EIoDisconnectReason::TransportClose);
+        Ok(())
+    }
+
+    /// Sends a packet to the server, as if it was received by the engine.io session,
+    /// followed by its binary payloads
+    fn send(&self, mut packet: Packet<'_>) -> Result<(), TestClientError> {
+        if self.disconnected || self.esocket.is_closed() {
+            return Err(TestClientError::Disconnected);
+        }
+        let bin = match &mut packet.inner {
+            PacketData::BinaryEvent(_, bin, _) | PacketData::BinaryAck(bin, _) => {
+                std::mem::take(&mut bin.bin)
+            }
+            _ => vec![],
+        };
+        let msg: String = packet.try_into()?;
+        self.client.on_message(msg, self.esocket.clone());
+        for data in bin {
+            self.client.on_binary(data, self.esocket.clone());
+        }
+        Ok(())
+    }
+
+    /// Receives the next packet sent by the server and stores the events and acks of the namespace.
+    ///
+    /// Returns true if it is the connect packet of the namespace.
+    async fn recv_packet(&mut self) -> Result<bool, TestClientError> {
+        if self.disconnected {
+            return Err(TestClientError::Disconnected);
+        }
+        let msg = match self.recv_engine_packet().await? {
+            EIoPacket::Message(msg) => msg,
+            _ => return Ok(false),
+        };
+        if let Some((ns, message)) = parse_connect_error(&msg) {
+            if ns == self.ns {
+                self.disconnected = true;
+                return Err(TestClientError::ConnectError(message));
+            }
+            return Ok(false);
+        }
+
+        let mut packet = Packet::try_from(msg.into_owned())?;
+        if packet.ns != self.ns {
+            return Ok(false);
+        }
+        if let PacketData::BinaryEvent(_, ref mut bin, _) | PacketData::BinaryAck(ref mut bin, _) =
+            packet.inner
+        {
+            while !bin.is_complete() {
+                match self.recv_engine_packet().await? {
+                    EIoPacket::Binary(data) | EIoPacket::BinaryV3(data) => bin.add_payload(data),
+                    _ => (),
+                }
+            }
+        }
+
+        match packet.inner {
+            PacketData::Connect(_) => return Ok(true),
+            PacketData::Disconnect(_) => {
+                self.disconnected = true;
+                return Err(TestClientError::Disconnected);
+            }
+            PacketData::Event(event, data, ack_id) => self.events.push_back(ReceivedEvent {
+                event: event.into_owned(),
+                data: unwrap_array(data.unwrap_or_default()),
+                bin: vec![],
+                ack_id,
+            }),
+            PacketData::BinaryEvent(event, bin, ack_id) => self.events.push_back(ReceivedEvent {
+                event: event.into_owned(),
+                data: unwrap_array(bin.data.unwrap_or_default()),
+                bin: bin.bin,
+                ack_id,
+            }),
+            PacketData::EventAck(data, ack_id) => {
+                let data = unwrap_array(data);
+                let binary = vec![];
+                self.acks.insert(ack_id, AckResponse { data, binary });
+            }
+            PacketData::BinaryAck(BinaryPacket { data, bin, .. }, ack_id) => {
+                let data = unwrap_array(data.unwrap_or_default());
+                let ack = AckResponse { data, binary: bin };
+                self.acks.insert(ack_id, ack);
+            }
+            PacketData::ConnectError(_) => (),
+        }
+        Ok(false)
+    }
+
+    /// Receives the next engine.io packet sent to the client, within the timeout
+    async fn recv_engine_packet(&mut self) -> Result<EIoPacket, TestClientError> {
+        let packet = tokio::time::timeout(self.timeout, self.esocket.recv_dummy())
+            .await
+            .map_err(|_| TestClientError::Timeout)?;
+        match packet {
+            Some(EIoPacket::Close) | None => {
+                self.disconnected = true;
+                Err(TestClientError::Disconnected)
+            }
+            Some(packet) => Ok(packet),
+        }
+    }
+}
+
+impl<A: Adapter> Drop for TestClient<A> {
+    fn drop(&mut self) {
+        self.esocket.close(EIoDisconnectReason::TransportError);
+    }
+}
+
+impl<A: Adapter> std::fmt::Debug for TestClient<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestClient")
+            .field("id", &self.esocket.id)
+            .field("ns", &self.ns)
+            .field("disconnected", &self.disconnected)
+            .finish()
+    }
+}
+
+/// Creates an engine.io socket that notifies the client when it is closed, once
+fn new_engine_socket<A: Adapter>(client: &Arc<Client<A>>) -> Arc<EngineSocket> {
+    let socket_ref: Arc<Mutex<Weak<EngineSocket>>> = Arc::new(Mutex::new(Weak::new()));
+    let closed = AtomicBool::new(false);
+    let close_fn = {
+        let client = Arc::downgrade(client);
+        let socket_ref = socket_ref.clone();
+        Box::new(move |_: Sid, reason: EIoDisconnectReason| {
+            if closed.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            let socket = socket_ref.lock().unwrap().upgrade();
+            if let (Some(client), Some(socket)) = (client.upgrade(), socket) {
+                client.on_disconnect(socket, reason);
+            }
+        })
+    };
+    let socket = Arc::new(EngineSocket::new_dummy(Sid::new(), close_fn));
+    *socket_ref.lock().unwrap() = Arc::downgrade(&socket);
+    socket
+}
+
+/// Parses a connect error packet, returning its namespace and its message
+fn parse_connect_error(msg: &str) -> Option<(&str, String)> {
+    let data = msg.strip_prefix('4')?;
+    let (ns, data) = match data.strip_prefix('/') {
+        Some(_) => data.split_once(',').unwrap_or((data, "")),
+        None => ("/", data),
+    };
+    let message = serde_json::from_str::<Value>(data)
+        .ok()
+        .and_then(|v| v.get("message")?.as_str().map(str::to_owned))
+        .unwrap_or_default();
+    Some((ns, message))
+}
+
+/// Unwraps an array with a single element, like the [`Data`](crate::extract::Data) extractor
+fn unwrap_array(data: Value) -> Value {
+    match data {
+        Value::Array(mut vec) if vec.len() == 1 => vec.pop().unwrap(),
+        data => data,
+    }
+}
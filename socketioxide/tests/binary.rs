@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use socketioxide::{
+    args::{Arg, Args},
+    extract::{AckSender, Bin, SocketRef},
+    SocketIo,
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_ws_connection, spawn_server};
+
+async fn create_server(port: u16) -> mpsc::Receiver<usize> {
+    let (svc, io) = SocketIo::builder()
+        .max_binary_attachments(1)
+        .binary_reassembly_timeout(Duration::from_millis(100))
+        .build_svc();
+    spawn_server(port, svc).await;
+
+    let (tx, rx) = mpsc::channel::<usize>(4);
+    io.ns("/", move |s: SocketRef| {
+        s.on("bin", move |Bin(bin)| {
+            tx.try_send(bin.len()).unwrap();
+        });
+    });
+    rx
+}
+
+#[tokio::test]
+pub async fn too_many_binary_attachments() {
+    let mut rx = create_server(2020).await;
+    let mut stream = create_ws_connection(2020).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    stream
+        .send(Message::Text(
+            r#"452-["bin",{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#.into(),
+        ))
+        .await
+        .unwrap();
+    stream.send(Message::Binary(vec![1])).await.unwrap();
+    stream.send(Message::Binary(vec![2])).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+
+    // The socket is still usable afterwards
+    stream
+        .send(Message::Text(
+            r#"451-["bin",{"_placeholder":true,"num":0}]"#.into(),
+        ))
+        .await
+        .unwrap();
+    stream.send(Message::Binary(vec![1])).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), 1);
+}
+
+#[tokio::test]
+pub async fn binary_reassembly_timeout() {
+    let mut rx = create_server(2021).await;
+    let mut stream = create_ws_connection(2021).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    stream
+        .send(Message::Text(
+            r#"451-["bin",{"_placeholder":true,"num":0}]"#.into(),
+        ))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // The partial packet was dropped, this payload is ignored
+    stream.send(Message::Binary(vec![1])).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+
+    stream
+        .send(Message::Text(
+            r#"451-["bin",{"_placeholder":true,"num":0}]"#.into(),
+        ))
+        .await
+        .unwrap();
+    stream.send(Message::Binary(vec![1])).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), 1);
+}
+
+#[tokio::test]
+pub async fn mixed_args_round_trip() {
+    let (svc, io) = SocketIo::new_svc();
+    spawn_server(2190, svc).await;
+    let (tx, mut rx) = mpsc::channel::<Args>(1);
+    io.ns("/", move |s: SocketRef| {
+        s.on("upload", move |s: SocketRef, args: Args| {
+            tx.try_send(args.clone()).unwrap();
+            s.emit_args("echo", args).unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(2190).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    let msg = r#"451-["upload",{"name":"blob.bin"},{"_placeholder":true,"num":0},"tail"]"#;
+    stream.send(Message::Text(msg.into())).await.unwrap();
+    stream.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+
+    let expected = Args::from(vec![
+        Arg::Json(json!({ "name": "blob.bin" })),
+        Arg::Bin(vec![1, 2, 3]),
+        Arg::Json(json!("tail")),
+    ]);
+    assert_eq!(rx.recv().await.unwrap(), expected);
+
+    let echo = r#"451-["echo",{"name":"blob.bin"},{"_placeholder":true,"num":0},"tail"]"#;
+    assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        Message::Text(echo.into())
+    );
+    assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        Message::Binary(vec![1, 2, 3])
+    );
+}
+
+#[tokio::test]
+pub async fn binary_ack() {
+    let (svc, io) = SocketIo::new_svc();
+    spawn_server(2305, svc).await;
+    io.ns("/", |s: SocketRef| {
+        s.on("thumbnail", |ack: AckSender| {
+            ack.bin(vec![vec![1, 2, 3]]).send("ok").unwrap();
+        });
+    });
+    let mut stream = create_ws_connection(2305).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    stream
+        .send(Message::Text(r#"421["thumbnail"]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap();
+    assert_eq!(
+        msg.to_string(),
+        r#"461-1["ok",{"_placeholder":true,"num":0}]"#
+    );
+    let msg = stream.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Binary(vec![1, 2, 3]));
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::{Data, SocketRef};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Returns the next message of the stream, answering the heartbeat pings received meanwhile.
+/// Returns `None` if no message is received within 50ms.
+async fn recv(stream: &mut WsStream) -> Option<String> {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+        match msg.ok()?.unwrap().unwrap().to_string() {
+            ping if ping == "2" => stream.send(Message::Text("3".into())).await.unwrap(),
+            msg => return Some(msg),
+        }
+    }
+}
+
+/// Asserts that no message is received on the stream
+async fn assert_silent(stream: &mut WsStream) {
+    let res = recv(stream).await;
+    assert!(res.is_none(), "unexpected message: {res:?}");
+}
+
+#[tokio::test]
+pub async fn broadcast_rooms_excludes_sender() {
+    const PORT: u16 = 2270;
+    let io = create_server(PORT).await;
+    io.ns("/", |s: SocketRef| {
+        s.on(
+            "join",
+            |s: SocketRef, Data::<Vec<String>>(rooms)| async move {
+                s.join(rooms).await.unwrap();
+                s.emit("joined", "ok").unwrap();
+            },
+        );
+        s.on("leave", |s: SocketRef, Data::<String>(room)| async move {
+            s.leave(room).await.unwrap();
+            s.emit("left", "ok").unwrap();
+        });
+        s.on("msg", |s: SocketRef, Data::<String>(data)| async move {
+            s.broadcast_rooms().emit("msg", data).await.unwrap();
+        });
+    });
+
+    let mut streams = Vec::new();
+    for rooms in [
+        r#"["room1","room2"]"#,
+        r#"["room1","room2"]"#,
+        r#"["room1"]"#,
+        r#"["room3"]"#,
+    ] {
+        let mut stream = create_ws_connection(PORT).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let _connect = stream.next().await.unwrap().unwrap();
+        let join = format!(r#"42["join",{rooms}]"#);
+        stream.send(Message::Text(join)).await.unwrap();
+        let joined = stream.next().await.unwrap().unwrap();
+        assert_eq!(joined.to_string(), r#"42["joined","ok"]"#);
+        streams.push(stream);
+    }
+
+    // Sharing two rooms with the sender, the second socket only receives the message once
+    let msg = Message::Text(r#"42["msg","hello"]"#.into());
+    streams[0].send(msg).await.unwrap();
+    for stream in &mut streams[1..3] {
+        let msg = recv(stream).await.unwrap();
+        assert_eq!(msg, r#"42["msg","hello"]"#);
+    }
+    for stream in &mut streams {
+        assert_silent(stream).await;
+    }
+
+    // The rooms are read when the message is emitted
+    let leave = Message::Text(r#"42["leave","room1"]"#.into());
+    streams[0].send(leave).await.unwrap();
+    let left = recv(&mut streams[0]).await.unwrap();
+    assert_eq!(left, r#"42["left","ok"]"#);
+    let msg = Message::Text(r#"42["msg","world"]"#.into());
+    streams[0].send(msg).await.unwrap();
+    let msg = recv(&mut streams[1]).await.unwrap();
+    assert_eq!(msg, r#"42["msg","world"]"#);
+    for stream in &mut streams {
+        assert_silent(stream).await;
+    }
+
+    // The clients of the namespace are not selected when the sender is in no room
+    let leave = Message::Text(r#"42["leave","room3"]"#.into());
+    streams[3].send(leave).await.unwrap();
+    let left = recv(&mut streams[3]).await.unwrap();
+    assert_eq!(left, r#"42["left","ok"]"#);
+    let msg = Message::Text(r#"42["msg","alone"]"#.into());
+    streams[3].send(msg).await.unwrap();
+    for stream in &mut streams {
+        assert_silent(stream).await;
+    }
+}
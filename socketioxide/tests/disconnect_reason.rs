@@ -4,19 +4,26 @@
 //! * Transport close
 //! * Multiple http polling
 //! * Packet parsing
+//! * Unresponsive client, on websocket transport
 //!
 //! * Client namespace disconnect
 //! * Server namespace disconnect
+//! * Server namespace disconnect with a reason
+//! * Server namespace disconnect with a close code, on polling and websocket transports
 
 use std::time::Duration;
 
 use futures::{SinkExt, StreamExt};
-use socketioxide::{extract::SocketRef, socket::DisconnectReason, SocketIo};
+use socketioxide::{
+    extract::{Data, SocketRef},
+    socket::DisconnectReason,
+    CircuitBreaker, ParseErrorPolicy, SocketIo,
+};
 use tokio::sync::mpsc;
 
 mod fixture;
 
-use fixture::{create_server, send_req};
+use fixture::{create_server, send_req, spawn_server};
 use tokio_tungstenite::tungstenite::Message;
 
 use crate::fixture::{create_polling_connection, create_ws_connection};
@@ -64,6 +71,26 @@ pub async fn ws_heartbeat_timeout() {
     assert_eq!(data, DisconnectReason::HeartbeatTimeout);
 }
 
+#[tokio::test]
+pub async fn ws_unresponsive_client() {
+    let (svc, io) = SocketIo::builder()
+        .ping_interval(Duration::from_millis(300))
+        .ping_timeout(Duration::from_millis(1000))
+        .circuit_breaker(CircuitBreaker::new(2, Duration::from_millis(50)))
+        .build_svc();
+    spawn_server(2306, svc).await;
+    let mut rx = attach_handler(&io, 1);
+    let _stream = create_ws_connection(2306).await;
+
+    // The client never answers the pings, it is disconnected well before the ping timeout
+    let data = tokio::time::timeout(Duration::from_millis(600), rx.recv())
+        .await
+        .expect("timeout waiting for DisconnectReason::UnresponsiveClient")
+        .unwrap();
+
+    assert_eq!(data, DisconnectReason::UnresponsiveClient);
+}
+
 #[tokio::test]
 pub async fn polling_transport_closed() {
     let io = create_server(1235).await;
@@ -179,6 +206,53 @@ pub async fn ws_packet_parsing() {
     assert_eq!(data, DisconnectReason::PacketParsingError);
 }
 
+#[tokio::test]
+pub async fn ws_socketio_packet_parsing() {
+    let io = create_server(2260).await;
+    let mut rx = attach_handler(&io, 1);
+    let mut stream = create_ws_connection(2260).await;
+    // A valid engine.io message holding an event without event name
+    stream.send(Message::Text("42[1]".into())).await.unwrap();
+
+    let data = tokio::time::timeout(Duration::from_millis(50), rx.recv())
+        .await
+        .expect("timeout waiting for DisconnectReason::PacketParsingError")
+        .unwrap();
+
+    assert_eq!(data, DisconnectReason::PacketParsingError);
+}
+
+#[tokio::test]
+pub async fn ws_socketio_packet_parsing_ignored() {
+    let (svc, io) = SocketIo::builder()
+        .parse_error_policy(ParseErrorPolicy::Ignore)
+        .build_svc();
+    spawn_server(2261, svc).await;
+    let (tx, mut rx) = mpsc::channel::<DisconnectReason>(1);
+    io.ns("/", move |s: SocketRef| {
+        s.on("echo", |s: SocketRef, Data::<String>(data)| {
+            s.emit("echo", data).ok();
+        });
+        let tx = tx.clone();
+        s.on_disconnect(move |reason: DisconnectReason| tx.try_send(reason).unwrap());
+    });
+    let mut stream = create_ws_connection(2261).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    for packet in ["42[1]", r#"45["echo"]"#, r#"42["echo",]"#] {
+        stream.send(Message::Text(packet.into())).await.unwrap();
+    }
+    // The malformed packets are dropped and the connection is kept open
+    stream
+        .send(Message::Text(r#"42["echo","hello"]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["echo","hello"]"#);
+    assert!(rx.try_recv().is_err());
+}
+
 // Socket IO Disconnect Reason Tests
 
 #[tokio::test]
@@ -209,7 +283,7 @@ pub async fn server_ns_disconnect() {
 
         tokio::spawn(async move {
             tokio::time::sleep(Duration::from_millis(10)).await;
-            let s = io2.sockets().unwrap().into_iter().nth(0).unwrap();
+            let s = io2.sockets().await.unwrap().into_iter().next().unwrap();
             s.disconnect().unwrap();
         });
 
@@ -228,6 +302,102 @@ pub async fn server_ns_disconnect() {
     assert_eq!(data, DisconnectReason::ServerNSDisconnect);
 }
 
+#[tokio::test]
+pub async fn server_ns_disconnect_with_reason() {
+    let (tx, mut rx) = mpsc::channel::<DisconnectReason>(1);
+    let io = create_server(12352).await;
+    io.ns("/", move |socket: SocketRef| {
+        let tx = tx.clone();
+        socket.on("login", |socket: SocketRef| {
+            socket.disconnect_with_reason("duplicate login").unwrap();
+        });
+        socket.on_disconnect(move |reason: DisconnectReason| {
+            tx.try_send(reason).unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(12352).await;
+    stream.next().await.unwrap().unwrap(); // engine.io open packet
+    stream.next().await.unwrap().unwrap(); // socket.io connect packet
+
+    stream
+        .send(Message::Text(r#"42["login"]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"41{"reason":"duplicate login"}"#);
+
+    let data = tokio::time::timeout(Duration::from_millis(20), rx.recv())
+        .await
+        .expect("timeout waiting for DisconnectReason::ServerNSDisconnect")
+        .unwrap();
+    assert_eq!(data, DisconnectReason::ServerNSDisconnect);
+}
+
+#[tokio::test]
+pub async fn ws_server_disconnect_with_code() {
+    let (tx, mut rx) = mpsc::channel::<DisconnectReason>(1);
+    let io = create_server(12353).await;
+    io.ns("/", move |socket: SocketRef| {
+        let tx = tx.clone();
+        socket.on("kick", |socket: SocketRef| {
+            socket.disconnect_with_code(4001, "kicked").unwrap();
+        });
+        socket.on_disconnect(move |reason: DisconnectReason| {
+            tx.try_send(reason).unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(12353).await;
+    stream.next().await.unwrap().unwrap(); // engine.io open packet
+    stream.next().await.unwrap().unwrap(); // socket.io connect packet
+
+    stream
+        .send(Message::Text(r#"42["kick"]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"41{"reason":"kicked"}"#);
+
+    let msg = stream.next().await.unwrap().unwrap();
+    let Message::Close(Some(frame)) = msg else {
+        panic!("expected a close frame, got {msg:?}");
+    };
+    assert_eq!(u16::from(frame.code), 4001);
+    assert_eq!(frame.reason, "kicked");
+
+    let data = tokio::time::timeout(Duration::from_millis(20), rx.recv())
+        .await
+        .expect("timeout waiting for DisconnectReason::ServerNSDisconnect")
+        .unwrap();
+    assert_eq!(data, DisconnectReason::ServerNSDisconnect);
+}
+
+#[tokio::test]
+pub async fn polling_server_disconnect_with_code() {
+    let io = create_server(12354).await;
+    io.ns("/", |socket: SocketRef| {
+        socket.on("kick", |socket: SocketRef| {
+            socket.disconnect_with_code(4001, "kicked").unwrap();
+        });
+    });
+
+    let sid = create_polling_connection(12354).await;
+    let params = format!("transport=polling&sid={sid}");
+    send_req(
+        12354,
+        params.clone(),
+        http::Method::POST,
+        Some(r#"42["kick"]"#.into()),
+    )
+    .await;
+    // The code is ignored, the client receives the disconnect packet after the connect packet
+    let body = send_req(12354, params, http::Method::GET, None).await;
+    let packets: Vec<_> = body.split('\x1e').collect();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[1], r#"41{"reason":"kicked"}"#);
+}
+
 #[tokio::test]
 pub async fn server_ws_closing() {
     let io = create_server(12350).await;
@@ -0,0 +1,62 @@
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::{NsParams, SocketRef};
+use tokio::sync::mpsc;
+
+mod fixture;
+use fixture::create_server;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+pub async fn dyn_ns_params() {
+    let io = create_server(2010).await;
+    let (tx, mut rx) = mpsc::channel::<(String, Option<String>)>(4);
+    let tx1 = tx.clone();
+    io.dyn_ns("/chat-{room}", move |s: SocketRef, NsParams(params)| {
+        tx1.try_send((s.ns().to_string(), params.get("room").cloned()))
+            .unwrap();
+    })
+    .unwrap();
+    // Exact namespaces are preferred over dynamic ones
+    io.ns("/chat-main", move |s: SocketRef, NsParams(params)| {
+        assert!(params.is_empty());
+        tx.try_send((s.ns().to_string(), None)).unwrap();
+    });
+
+    let (mut stream, _) = tokio_tungstenite::connect_async(
+        "ws://127.0.0.1:2010/socket.io/?EIO=4&transport=websocket",
+    )
+    .await
+    .unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+
+    stream
+        .send(Message::Text("40/chat-42,{}".to_string()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(msg.starts_with("40/chat-42,"), "unexpected message: {msg}");
+    assert_eq!(
+        rx.recv().await.unwrap(),
+        ("/chat-42".to_string(), Some("42".to_string()))
+    );
+
+    stream
+        .send(Message::Text("40/chat-main,{}".to_string()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(
+        msg.starts_with("40/chat-main,"),
+        "unexpected message: {msg}"
+    );
+    assert_eq!(rx.recv().await.unwrap(), ("/chat-main".to_string(), None));
+
+    // Non matching namespaces are still rejected
+    stream
+        .send(Message::Text("40/admin,{}".to_string()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(msg.starts_with("44/admin,"), "unexpected message: {msg}");
+    stream.close(None).await.unwrap();
+}
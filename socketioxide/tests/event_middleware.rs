@@ -0,0 +1,61 @@
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use socketioxide::extract::{AckSender, Data, SocketRef};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn event_middleware_rejects_invalid_schema() {
+    let io = create_server(2070).await;
+    let (tx, mut rx) = mpsc::channel::<&'static str>(8);
+    let tx1 = tx.clone();
+    let tx2 = tx.clone();
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        s.on("message", move |Data::<Value>(data), ack: AckSender| {
+            tx.try_send("handler").unwrap();
+            ack.send(data).ok();
+        });
+    })
+    .on_any(move |_: SocketRef, event: &str, data: &mut Value| {
+        tx1.try_send("validate").unwrap();
+        let valid = event == "message" && data[0]["name"].is_string();
+        match valid {
+            true => Ok(()),
+            false => Err("invalid schema"),
+        }
+    })
+    .on_any(move |_: SocketRef, _: &str, data: &mut Value| {
+        tx2.try_send("transform").unwrap();
+        data[0]["checked"] = Value::Bool(true);
+        Ok::<_, std::convert::Infallible>(())
+    });
+
+    let mut stream = create_ws_connection(2070).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    // The payload is mutated by the middlewares before reaching the handler
+    stream
+        .send(Message::Text(r#"421["message",{"name":"foo"}]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"431[{"checked":true,"name":"foo"}]"#);
+    assert_eq!(rx.recv().await.unwrap(), "validate");
+    assert_eq!(rx.recv().await.unwrap(), "transform");
+    assert_eq!(rx.recv().await.unwrap(), "handler");
+
+    // Invalid payloads are vetoed and the error is sent back with the ack
+    stream
+        .send(Message::Text(r#"422["message",{"age":3}]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"432[{"message":"invalid schema"}]"#);
+    assert_eq!(rx.recv().await.unwrap(), "validate");
+    assert!(rx.try_recv().is_err());
+}
@@ -1,43 +1,46 @@
-use futures::SinkExt;
-use socketioxide::extract::{Data, SocketRef, State};
-use tokio::sync::mpsc;
+use std::net::{IpAddr, Ipv4Addr};
+
+use futures::{SinkExt, StreamExt};
+use hyper::{server::conn::http1, service::Service};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use socketioxide::{
+    extract::{
+        AckSender, Arg, AuthData, ClientIp, Data, PacketMeta, Query, QueryMap, SocketData,
+        SocketRef,
+    },
+    ClientIpConfig, SocketIo,
+};
+use tokio::{net::TcpListener, sync::mpsc};
 
 mod fixture;
-use fixture::{create_server, create_server_with_state, create_ws_connection};
-use tokio_tungstenite::tungstenite::Message;
+use fixture::{create_server, create_ws_connection, create_ws_connection_with_auth};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 
 #[tokio::test]
-pub async fn state_extractor() {
-    let state = 1112i32;
-    let io = create_server_with_state(2000, state).await;
+pub async fn data_extractor() {
+    let io = create_server(2001).await;
     let (tx, mut rx) = mpsc::channel::<i32>(4);
-    io.ns("/", move |socket: SocketRef, state: State<i32>| {
+    io.ns("/", move |socket: SocketRef| {
         println!("Socket connected on / namespace with id: {}", socket.id);
-        tx.try_send(*state).unwrap();
-
         let tx1 = tx.clone();
         let tx2 = tx.clone();
-        let tx3 = tx.clone();
-        socket.on("test", move |State(state): State<i32>| {
+        socket.on("test", move |Data(data): Data<i32>| {
             println!("test event received");
-            tx.try_send(*state).unwrap();
+            tx1.try_send(data).unwrap();
         });
-        socket.on("async_test", move |State(state): State<i32>| async move {
+        socket.on("async_test", move |Data(data): Data<i32>| async move {
             println!("async_test event received");
-            tx2.try_send(*state).unwrap();
+            tx2.try_send(data).unwrap();
         });
         // This handler should not be called
-        socket.on("ko_test", move |State(_): State<String>| {
+        socket.on("ko_test", move |Data(_): Data<String>| {
             println!("ko_test event received");
-            tx3.try_send(1213231).unwrap();
-        });
-        socket.on_disconnect(move |State(state): State<i32>| {
-            println!("close event received");
-            tx1.try_send(*state).unwrap();
+            tx.try_send(1213231).unwrap();
         });
     });
 
-    let mut stream = create_ws_connection(2000).await;
+    let mut stream = create_ws_connection(2001).await;
     stream
         .send(Message::Text("42[\"test\", 1]".to_string()))
         .await
@@ -50,50 +53,360 @@ pub async fn state_extractor() {
         .send(Message::Text("42[\"ko_test\", 2]".to_string()))
         .await
         .unwrap();
-    assert_eq!(rx.recv().await.unwrap(), state);
-    assert_eq!(rx.recv().await.unwrap(), state);
-    assert_eq!(rx.recv().await.unwrap(), state);
+    assert_eq!(rx.recv().await.unwrap(), 1);
+    assert_eq!(rx.recv().await.unwrap(), 2);
     stream.close(None).await.unwrap();
-    assert_eq!(rx.recv().await.unwrap(), state);
 }
 
 #[tokio::test]
-pub async fn data_extractor() {
-    let io = create_server(2001).await;
-    let (tx, mut rx) = mpsc::channel::<i32>(4);
+pub async fn auth_data_extractor() {
+    #[derive(Debug, serde::Deserialize)]
+    struct Auth {
+        token: String,
+    }
+    let io = create_server(2002).await;
+    let (tx, mut rx) = mpsc::channel::<String>(4);
+    io.ns("/", move |AuthData(auth): AuthData<Auth>| {
+        tx.try_send(auth.token).unwrap();
+    });
+
+    // Valid auth data: the connection is accepted and the handler is called
+    let mut stream = create_ws_connection_with_auth(2002, r#"{"token":"abc"}"#).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    assert_eq!(rx.recv().await.unwrap(), "abc");
+    stream.close(None).await.unwrap();
+
+    // Invalid auth data: the connection is rejected and the handler is not called
+    let mut stream = create_ws_connection_with_auth(2002, r#"{"foo":1}"#).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(
+        msg.starts_with("44{\"message\":\"missing field `token`"),
+        "unexpected message: {msg}"
+    );
+    assert!(rx.try_recv().is_err());
+    stream.close(None).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn packet_meta_extractor() {
+    let io = create_server(2120).await;
+    let (tx, mut rx) = mpsc::channel::<PacketMeta>(4);
     io.ns("/", move |socket: SocketRef| {
-        println!("Socket connected on / namespace with id: {}", socket.id);
+        let tx = tx.clone();
+        socket.on("test", move |meta: PacketMeta, ack: AckSender| {
+            if meta.ack_requested() {
+                ack.send(&meta.event).unwrap();
+            }
+            tx.try_send(meta).unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(2120).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    // Without ack
+    stream
+        .send(Message::Text(r#"42["test",1]"#.to_string()))
+        .await
+        .unwrap();
+    let meta = rx.recv().await.unwrap();
+    assert_eq!(meta.ns, "/");
+    assert_eq!(meta.event, "test");
+    assert_eq!(meta.ack_id, None);
+
+    // With ack
+    stream
+        .send(Message::Text(r#"427["test",1]"#.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(rx.recv().await.unwrap().ack_id, Some(7));
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"437["test"]"#);
+    stream.close(None).await.unwrap();
+}
+
+/// Spawns a server inserting the peer address of each connection in the request extensions
+async fn spawn_server_with_peer_addr(port: u16, config: ClientIpConfig) -> SocketIo {
+    let (svc, io) = SocketIo::builder().client_ip(config).build_svc();
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let svc = svc.clone();
+            let svc = hyper::service::service_fn(move |mut req: http::Request<_>| {
+                req.extensions_mut().insert(peer);
+                svc.call(req)
+            });
+            tokio::spawn(
+                http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), svc)
+                    .with_upgrades(),
+            );
+        }
+    });
+    io
+}
+
+/// Connects to the root namespace with an optional `x-forwarded-for` header and returns the extracted ip
+async fn connect_with_forwarded_for(
+    port: u16,
+    header: Option<&str>,
+    rx: &mut mpsc::Receiver<Option<IpAddr>>,
+) -> Option<IpAddr> {
+    let url = format!("ws://127.0.0.1:{port}/socket.io/?EIO=4&transport=websocket");
+    let mut req = url.into_client_request().unwrap();
+    if let Some(header) = header {
+        req.headers_mut()
+            .insert("x-forwarded-for", header.parse().unwrap());
+    }
+    let (mut stream, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    rx.recv().await.unwrap()
+}
+
+#[tokio::test]
+pub async fn client_ip_extractor() {
+    const LOCALHOST: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let client: Option<IpAddr> = Some("1.2.3.4".parse().unwrap());
+
+    // The local client is a trusted proxy
+    let config = ClientIpConfig::new("x-forwarded-for").trusted_proxies(LOCALHOST);
+    let io = spawn_server_with_peer_addr(2220, config).await;
+    let (tx, mut rx) = mpsc::channel::<Option<IpAddr>>(4);
+    io.ns("/", move |ClientIp(ip): ClientIp| tx.try_send(ip).unwrap());
+
+    let ip = connect_with_forwarded_for(2220, None, &mut rx).await;
+    assert_eq!(ip, LOCALHOST);
+    let ip = connect_with_forwarded_for(2220, Some("1.2.3.4"), &mut rx).await;
+    assert_eq!(ip, client);
+
+    // The local client is not trusted, the header is ignored
+    let config = ClientIpConfig::new("x-forwarded-for");
+    let io = spawn_server_with_peer_addr(2221, config).await;
+    let (tx, mut rx) = mpsc::channel::<Option<IpAddr>>(4);
+    io.ns("/", move |ClientIp(ip): ClientIp| tx.try_send(ip).unwrap());
+
+    let ip = connect_with_forwarded_for(2221, Some("1.2.3.4"), &mut rx).await;
+    assert_eq!(ip, LOCALHOST);
+
+    // Without the peer address set by the server, the ip is unknown
+    let io = create_server(2222).await;
+    let (tx, mut rx) = mpsc::channel::<Option<IpAddr>>(4);
+    io.ns("/", move |ClientIp(ip): ClientIp| tx.try_send(ip).unwrap());
+    let _stream = create_ws_connection(2222).await;
+    assert_eq!(rx.recv().await.unwrap(), None);
+}
+
+#[tokio::test]
+pub async fn socket_data_extractor() {
+    #[derive(Debug, serde::Deserialize)]
+    struct User {
+        id: u32,
+    }
+    let io = create_server(2223).await;
+    let (tx, mut rx) = mpsc::channel::<u32>(4);
+    io.ns("/", move |socket: SocketRef, Data::<User>(user)| {
+        socket.set_data(user);
         let tx1 = tx.clone();
-        let tx2 = tx.clone();
-        socket.on("test", move |Data(data): Data<i32>| {
-            println!("test event received");
-            tx1.try_send(data).unwrap();
+        socket.on("whoami", move |user: SocketData<User>| {
+            tx1.try_send(user.id).unwrap();
         });
-        socket.on("async_test", move |Data(data): Data<i32>| async move {
-            println!("async_test event received");
-            tx2.try_send(data).unwrap();
+        // This handler should not be called as no data of this type was set
+        let tx = tx.clone();
+        socket.on("ko_test", move |_: SocketData<String>| {
+            tx.try_send(0).unwrap();
         });
-        // This handler should not be called
-        socket.on("ko_test", move |Data(_): Data<String>| {
-            println!("ko_test event received");
-            tx.try_send(1213231).unwrap();
+    });
+
+    let mut stream = create_ws_connection_with_auth(2223, r#"{"id":42}"#).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream
+        .send(Message::Text("42[\"ko_test\"]".to_string()))
+        .await
+        .unwrap();
+    stream
+        .send(Message::Text("42[\"whoami\"]".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(rx.recv().await.unwrap(), 42);
+    stream.close(None).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn ack_stream_sender() {
+    let io = create_server(2224).await;
+    io.ns("/", |socket: SocketRef| {
+        socket.on("count", |Data::<u32>(n), ack: AckSender| async move {
+            let mut stream = ack.stream();
+            for i in 1..=n {
+                tokio::task::yield_now().await;
+                stream.send(i).unwrap();
+            }
+            stream.end().unwrap();
+        });
+        // The end marker is sent when the sender is dropped
+        socket.on("drop", |ack: AckSender| {
+            ack.stream().send([1, 2]).unwrap();
         });
     });
 
-    let mut stream = create_ws_connection(2001).await;
+    let mut stream = create_ws_connection(2224).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
     stream
-        .send(Message::Text("42[\"test\", 1]".to_string()))
+        .send(Message::Text(r#"421["count",3]"#.into()))
         .await
         .unwrap();
+    for expected in ["431[1]", "431[2]", "431[3]", "431[]"] {
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.to_string(), expected);
+    }
+
     stream
-        .send(Message::Text("42[\"async_test\", 2]".to_string()))
+        .send(Message::Text(r#"422["drop"]"#.into()))
         .await
         .unwrap();
+    for expected in ["432[[1,2]]", "432[]"] {
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.to_string(), expected);
+    }
+}
+
+#[tokio::test]
+pub async fn arg_extractor() {
+    let io = create_server(2225).await;
+    let (tx, mut rx) = mpsc::channel::<(String, i32)>(4);
+    io.ns("/", move |socket: SocketRef| {
+        let tx1 = tx.clone();
+        socket.on("move", move |Arg(name): Arg<String>, Arg(x): Arg<i32>| {
+            tx1.try_send((name, x)).unwrap();
+        });
+        // The data is left intact for the arg extractors following a data extractor
+        socket.on(
+            "data",
+            move |Data(data): Data<Value>, Arg(name): Arg<String>, Arg(x): Arg<i32>| async move {
+                assert_eq!(data, serde_json::json!(["b", 2]));
+                tx.try_send((name, x)).unwrap();
+            },
+        );
+    });
+
+    let mut stream = create_ws_connection(2225).await;
+    for msg in [
+        r#"42["move","a",1]"#,
+        // Missing argument
+        r#"42["move","a"]"#,
+        // Invalid argument
+        r#"42["move",1,"a"]"#,
+        r#"42["data","b",2]"#,
+    ] {
+        stream.send(Message::Text(msg.into())).await.unwrap();
+    }
+    assert_eq!(rx.recv().await.unwrap(), ("a".into(), 1));
+    assert_eq!(rx.recv().await.unwrap(), ("b".into(), 2));
+    stream.close(None).await.unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+/// Connects to the root namespace with the given query params and returns the first message received
+/// after the open packet
+async fn connect_with_query(port: u16, query: &str) -> String {
+    let url = format!("ws://127.0.0.1:{port}/socket.io/?EIO=4&transport=websocket&{query}");
+    let (mut stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    stream.next().await.unwrap().unwrap().to_string()
+}
+
+#[tokio::test]
+pub async fn query_extractor() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Params {
+        token: String,
+        version: Option<u32>,
+    }
+    let io = create_server(2300).await;
+    let (tx, mut rx) = mpsc::channel::<Params>(4);
+    io.ns("/", move |Query(params): Query<Params>| {
+        tx.try_send(params).unwrap();
+    });
+
+    // The engine.io params and the extra params are ignored
+    let msg = connect_with_query(2300, "token=abc&version=2&foo=bar").await;
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    let params = rx.recv().await.unwrap();
+    assert_eq!(
+        params,
+        Params {
+            token: "abc".into(),
+            version: Some(2)
+        }
+    );
+
+    // Missing optional param
+    let msg = connect_with_query(2300, "token=a%20b").await;
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    let params = rx.recv().await.unwrap();
+    assert_eq!(
+        params,
+        Params {
+            token: "a b".into(),
+            version: None
+        }
+    );
+
+    // Missing required param: the connection is rejected and the handler is not called
+    let msg = connect_with_query(2300, "version=2").await;
+    assert!(
+        msg.starts_with("44{\"message\":\"missing field `token`"),
+        "unexpected message: {msg}"
+    );
+
+    // Invalid param
+    let msg = connect_with_query(2300, "token=abc&version=foo").await;
+    assert!(
+        msg.starts_with("44{\"message\":"),
+        "unexpected message: {msg}"
+    );
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+pub async fn query_map_extractor() {
+    let io = create_server(2301).await;
+    let (tx, mut rx) = mpsc::channel::<std::collections::HashMap<String, String>>(4);
+    io.ns("/", move |s: SocketRef, QueryMap(query): QueryMap| {
+        tx.try_send(query.clone()).unwrap();
+        let tx = tx.clone();
+        s.on("query", move |QueryMap(query): QueryMap| {
+            tx.try_send(query).unwrap();
+        });
+    });
+
+    let url = "ws://127.0.0.1:2301/socket.io/?EIO=4&transport=websocket&token=abc&empty=";
+    let (mut stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    let query = rx.recv().await.unwrap();
+    assert_eq!(query.len(), 4);
+    assert_eq!(query["EIO"], "4");
+    assert_eq!(query["transport"], "websocket");
+    assert_eq!(query["token"], "abc");
+    assert_eq!(query["empty"], "");
+
+    // The query is also available in the message handlers
+    let _connect = stream.next().await.unwrap().unwrap();
     stream
-        .send(Message::Text("42[\"ko_test\", 2]".to_string()))
+        .send(Message::Text(r#"42["query"]"#.into()))
         .await
         .unwrap();
-    assert_eq!(rx.recv().await.unwrap(), 1);
-    assert_eq!(rx.recv().await.unwrap(), 2);
+    assert_eq!(rx.recv().await.unwrap(), query);
     stream.close(None).await.unwrap();
 }
@@ -16,7 +16,7 @@ use hyper_util::{
     rt::{TokioExecutor, TokioIo},
 };
 use serde::{Deserialize, Serialize};
-use socketioxide::{adapter::LocalAdapter, service::SocketIoService, SocketIo};
+use socketioxide::{adapter::Adapter, service::SocketIoService, SocketIo};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 /// An OpenPacket is used to initiate a connection
@@ -72,7 +72,13 @@ pub async fn send_req(
 }
 
 pub async fn create_polling_connection(port: u16) -> String {
-    let body = send_req(port, format!("transport=polling"), http::Method::GET, None).await;
+    let body = send_req(
+        port,
+        "transport=polling".to_string(),
+        http::Method::GET,
+        None,
+    )
+    .await;
     let open_packet: OpenPacket = serde_json::from_str(&body).unwrap();
 
     send_req(
@@ -86,6 +92,14 @@ pub async fn create_polling_connection(port: u16) -> String {
     open_packet.sid
 }
 pub async fn create_ws_connection(port: u16) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    create_ws_connection_with_auth(port, "{}").await
+}
+
+/// Creates a websocket connection and connects to the root namespace with the given auth payload.
+pub async fn create_ws_connection_with_auth(
+    port: u16,
+    auth: &str,
+) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
     let mut ws = tokio_tungstenite::connect_async(format!(
         "ws://127.0.0.1:{port}/socket.io/?EIO=4&transport=websocket"
     ))
@@ -93,7 +107,7 @@ pub async fn create_ws_connection(port: u16) -> WebSocketStream<MaybeTlsStream<T
     .unwrap()
     .0;
 
-    ws.send(Message::Text("40{}".to_string())).await.unwrap();
+    ws.send(Message::Text(format!("40{auth}"))).await.unwrap();
 
     ws
 }
@@ -120,7 +134,7 @@ pub async fn create_server(port: u16) -> SocketIo {
     io
 }
 
-async fn spawn_server(port: u16, svc: SocketIoService<NotFoundService, LocalAdapter>) {
+pub async fn spawn_server<A: Adapter>(port: u16, svc: SocketIoService<NotFoundService, A>) {
     let addr = &SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
     let listener = TcpListener::bind(&addr).await.unwrap();
     tokio::spawn(async move {
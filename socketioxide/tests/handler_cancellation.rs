@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::SocketRef;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+/// Sends its name when it is dropped, i.e. when the future owning it is cancelled
+struct DropGuard(&'static str, mpsc::UnboundedSender<&'static str>);
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        self.1.send(self.0).ok();
+    }
+}
+
+#[tokio::test]
+pub async fn handlers_cancelled_on_disconnect() {
+    let io = create_server(2309).await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| {
+        let guard = DropGuard("connect", tx.clone());
+        let tx = tx.clone();
+        s.on("work", move |s: SocketRef| async move {
+            let _guard = DropGuard("work", tx);
+            s.emit("working", 1).ok();
+            std::future::pending::<()>().await;
+        });
+        async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+        }
+    })
+    .with_handler_cancellation();
+
+    let mut stream = create_ws_connection(2309).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream
+        .send(Message::Text(r#"42["work"]"#.into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"42["working",1]"#);
+    assert!(rx.try_recv().is_err());
+
+    stream.send(Message::Text("41".into())).await.unwrap();
+
+    let mut cancelled = Vec::new();
+    for _ in 0..2 {
+        let name = tokio::time::timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .expect("timeout waiting for the handlers to be cancelled")
+            .unwrap();
+        cancelled.push(name);
+    }
+    cancelled.sort();
+    assert_eq!(cancelled, ["connect", "work"]);
+}
+
+#[tokio::test]
+pub async fn socket_disconnected() {
+    let io = create_server(2310).await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        async move {
+            s.disconnected().await;
+            tx.send(s.id).unwrap();
+            // It resolves right away once the socket is disconnected
+            s.disconnected().await;
+            tx.send(s.id).unwrap();
+        }
+    });
+
+    let mut stream = create_ws_connection(2310).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("41".into())).await.unwrap();
+
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .expect("timeout waiting for the disconnection")
+            .unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+//! Tests for the concurrency limit of the async event handlers, see [`NsHandle::with_handler_concurrency`]
+use std::time::Duration;
+
+use socketioxide::{
+    extract::{Data, SocketRef},
+    testing::TestClient,
+    SocketIo,
+};
+
+/// Registers a handler answering each event after a delay decreasing with its index,
+/// so that concurrent handlers answer in the reverse order
+fn create_ns(io: &SocketIo, concurrency: Option<usize>) {
+    let ns = io.ns("/", |s: SocketRef| {
+        s.on("job", |s: SocketRef, Data::<u64>(i)| async move {
+            tokio::time::sleep(Duration::from_millis((5 - i) * 30)).await;
+            s.emit("done", i).ok();
+        });
+    });
+    if let Some(concurrency) = concurrency {
+        ns.with_handler_concurrency(concurrency);
+    }
+}
+
+async fn run_jobs(io: &SocketIo) -> Vec<u64> {
+    let mut client = TestClient::connect(io, "/")
+        .await
+        .unwrap()
+        .timeout(Duration::from_secs(1));
+    for i in 0..5 {
+        client.emit("job", i).unwrap();
+    }
+    let mut done = Vec::new();
+    for _ in 0..5 {
+        done.push(client.recv_event::<u64>("done").await.unwrap());
+    }
+    done
+}
+
+#[tokio::test]
+pub async fn handler_concurrency_in_order() {
+    let (_, io) = SocketIo::new_svc();
+    create_ns(&io, Some(1));
+    assert_eq!(run_jobs(&io).await, [0, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+pub async fn handler_concurrency_unbounded() {
+    let (_, io) = SocketIo::new_svc();
+    create_ns(&io, None);
+    assert_eq!(run_jobs(&io).await, [4, 3, 2, 1, 0]);
+}
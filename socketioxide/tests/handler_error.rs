@@ -0,0 +1,68 @@
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::{AckSender, Data, SocketRef};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+fn panic_handler() {
+    panic!("sync handler panic");
+}
+
+async fn async_panic_handler() {
+    panic!("async handler panic");
+}
+
+/// Sends a packet and returns the next received message
+async fn emit(stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>, packet: &str) -> String {
+    stream.send(Message::Text(packet.into())).await.unwrap();
+    stream.next().await.unwrap().unwrap().to_string()
+}
+
+#[tokio::test]
+pub async fn handler_error_ack() {
+    let io = create_server(2210).await;
+    io.ns("/", |s: SocketRef| {
+        s.on("divide", |Data::<(i32, i32)>((a, b)), ack: AckSender| {
+            if b == 0 {
+                return Err("division by zero");
+            }
+            ack.send(a / b).ok();
+            Ok(())
+        });
+        s.on("parse", |Data::<String>(data), ack: AckSender| async move {
+            let num: u32 = data.parse()?;
+            ack.send(num).ok();
+            Ok::<_, std::num::ParseIntError>(())
+        });
+        s.on("panic", panic_handler);
+        s.on("async_panic", async_panic_handler);
+    });
+
+    let mut stream = create_ws_connection(2210).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    assert_eq!(emit(&mut stream, r#"421["divide",6,3]"#).await, "431[2]");
+    assert_eq!(
+        emit(&mut stream, r#"422["divide",6,0]"#).await,
+        r#"432[{"message":"division by zero"}]"#
+    );
+    assert_eq!(emit(&mut stream, r#"423["parse","12"]"#).await, "433[12]");
+    assert_eq!(
+        emit(&mut stream, r#"424["parse","abc"]"#).await,
+        r#"434[{"message":"invalid digit found in string"}]"#
+    );
+    assert_eq!(
+        emit(&mut stream, r#"425["panic"]"#).await,
+        r#"435[{"message":"event handler panicked"}]"#
+    );
+    assert_eq!(
+        emit(&mut stream, r#"426["async_panic"]"#).await,
+        r#"436[{"message":"event handler panicked"}]"#
+    );
+
+    // The socket is still usable after a panic
+    assert_eq!(emit(&mut stream, r#"427["divide",9,3]"#).await, "437[3]");
+}
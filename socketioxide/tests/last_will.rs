@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::SocketRef;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Returns the next message of the stream, answering the heartbeat pings received meanwhile.
+/// Returns `None` if no message is received within 50ms.
+async fn recv(stream: &mut WsStream) -> Option<String> {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+        match msg.ok()?.unwrap().unwrap().to_string() {
+            ping if ping == "2" => stream.send(Message::Text("3".into())).await.unwrap(),
+            msg => return Some(msg),
+        }
+    }
+}
+
+/// Connects a client to the root namespace and sends it the given events, waiting for their answers
+async fn connect(port: u16, events: &[&str]) -> WsStream {
+    let mut stream = create_ws_connection(port).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    for event in events {
+        let msg = Message::Text(format!(r#"42["{event}"]"#));
+        stream.send(msg).await.unwrap();
+        assert_eq!(recv(&mut stream).await.unwrap(), r#"42["ok",true]"#);
+    }
+    stream
+}
+
+#[tokio::test]
+pub async fn last_will() {
+    const PORT: u16 = 2280;
+    let io = create_server(PORT).await;
+    io.ns("/", |s: SocketRef| async move {
+        s.join("sensors").await.unwrap();
+        s.on("will", |s: SocketRef| {
+            s.set_last_will("sensors", "offline", "bye").unwrap();
+            s.emit("ok", true).unwrap();
+        });
+        s.on("cancel", |s: SocketRef| {
+            assert!(s.cancel_last_will());
+            s.emit("ok", true).unwrap();
+        });
+    });
+
+    let mut listener = connect(PORT, &[]).await;
+
+    // The connection is dropped without any close frame
+    let stream = connect(PORT, &["will"]).await;
+    drop(stream);
+    let msg = recv(&mut listener).await.unwrap();
+    assert_eq!(msg, r#"42["offline","bye"]"#);
+
+    // The last will is not emitted once cancelled
+    let stream = connect(PORT, &["will", "cancel"]).await;
+    drop(stream);
+    let res = recv(&mut listener).await;
+    assert!(res.is_none(), "unexpected message: {res:?}");
+
+    // The last will is not emitted when the client disconnects on purpose
+    let mut stream = connect(PORT, &["will"]).await;
+    stream.send(Message::Text("41".into())).await.unwrap();
+    let res = recv(&mut listener).await;
+    assert!(res.is_none(), "unexpected message: {res:?}");
+}
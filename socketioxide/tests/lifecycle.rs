@@ -0,0 +1,52 @@
+//! Tests for the [`SocketIo::lifecycle_events`] stream
+use std::{str::FromStr, time::Duration};
+
+use futures::{stream::BoxStream, SinkExt, StreamExt};
+use socketioxide::{extract::SocketRef, lifecycle::LifecycleEvent, socket::DisconnectReason};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_polling_connection, create_server};
+
+async fn next_event(events: &mut BoxStream<'static, LifecycleEvent>) -> LifecycleEvent {
+    tokio::time::timeout(Duration::from_millis(200), events.next())
+        .await
+        .expect("timeout waiting for a lifecycle event")
+        .unwrap()
+}
+
+#[tokio::test]
+pub async fn lifecycle_events_in_order() {
+    let io = create_server(2180).await;
+    io.ns("/", |_: SocketRef| {});
+    let mut events = io.lifecycle_events();
+
+    let sid = create_polling_connection(2180).await;
+    let sid = engineioxide::sid::Sid::from_str(&sid).unwrap();
+    let connected = LifecycleEvent::Connected {
+        sid,
+        ns: "/".into(),
+    };
+    assert_eq!(next_event(&mut events).await, connected);
+
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:2180/socket.io/?EIO=4&transport=websocket&sid={sid}"
+    ))
+    .await
+    .unwrap();
+    stream.send(Message::Text("2probe".into())).await.unwrap();
+    let _pong = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("5".into())).await.unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        LifecycleEvent::UpgradeCompleted { sid }
+    );
+
+    stream.send(Message::Text("41".into())).await.unwrap();
+    let disconnected = LifecycleEvent::Disconnected {
+        sid,
+        ns: "/".into(),
+        reason: DisconnectReason::ClientNSDisconnect,
+    };
+    assert_eq!(next_event(&mut events).await, disconnected);
+}
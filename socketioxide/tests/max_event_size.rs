@@ -0,0 +1,56 @@
+use futures::{SinkExt, StreamExt};
+use socketioxide::{extract::SocketRef, SocketIo};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_ws_connection, spawn_server};
+
+#[tokio::test]
+pub async fn reject_too_large_events() {
+    let (svc, io) = SocketIo::builder().max_event_size(100).build_svc();
+    spawn_server(2311, svc).await;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        s.on("upload", move || {
+            tx.send(()).unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(2311).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    // Each attachment is small but the reassembled event exceeds the limit
+    stream
+        .send(Message::Text(
+            r#"452-1["upload",{"_placeholder":true,"num":0},{"_placeholder":true,"num":1}]"#.into(),
+        ))
+        .await
+        .unwrap();
+    stream.send(Message::Binary(vec![0; 60])).await.unwrap();
+    stream.send(Message::Binary(vec![0; 60])).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"431[{"message":"event too large"}]"#);
+
+    let big = "a".repeat(100);
+    stream
+        .send(Message::Text(format!(r#"422["upload","{big}"]"#)))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"432[{"message":"event too large"}]"#);
+    assert!(rx.try_recv().is_err());
+
+    // Events under the limit still reach their handler
+    stream
+        .send(Message::Text(r#"42["upload","small"]"#.into()))
+        .await
+        .unwrap();
+    tokio::time::timeout(std::time::Duration::from_millis(100), rx.recv())
+        .await
+        .expect("timeout waiting for the event")
+        .unwrap();
+}
@@ -0,0 +1,66 @@
+//! Tests for the [`Metrics`] snapshot of the server
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::{extract::SocketRef, Metrics};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_polling_connection, create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn metrics() {
+    let io = create_server(2150).await;
+    io.ns("/", |socket: SocketRef| async move {
+        socket.join(["room1", "room2"]).await.unwrap();
+    });
+    io.ns("/admin", |socket: SocketRef| async move {
+        socket.join("room1").await.unwrap();
+    });
+    assert_eq!(
+        io.metrics().await.unwrap(),
+        Metrics {
+            namespaces: 2,
+            ..Default::default()
+        }
+    );
+
+    // A websocket client connected to both namespaces
+    let mut stream = create_ws_connection(2150).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream
+        .send(Message::Text("40/admin,{}".to_string()))
+        .await
+        .unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    // A polling client that does not poll, its connect packet stays buffered
+    create_polling_connection(2150).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let metrics = io.metrics().await.unwrap();
+    assert_eq!(
+        metrics,
+        Metrics {
+            sockets: 3,
+            namespaces: 2,
+            rooms: 3,
+            buffered_packets: 1,
+            rejected_events: 0,
+        }
+    );
+
+    io.to("room2").emit("msg", "hello").await.unwrap();
+    let _msg = stream.next().await.unwrap().unwrap();
+    assert_eq!(io.metrics().await.unwrap().buffered_packets, 2);
+
+    #[cfg(feature = "prometheus")]
+    {
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE socketio_sockets gauge\nsocketio_sockets 3\n"));
+        assert!(text.contains("\nsocketio_rooms 3\n"));
+        assert!(text.contains("# TYPE socketio_rejected_events counter\n"));
+    }
+    stream.close(None).await.unwrap();
+}
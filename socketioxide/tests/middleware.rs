@@ -0,0 +1,95 @@
+use futures::{SinkExt, StreamExt};
+use socketioxide::{extract::SocketRef, handler::ConnectErrorKind};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+mod fixture;
+use fixture::create_server;
+
+#[tokio::test]
+pub async fn connect_middleware_rejects() {
+    let io = create_server(2030).await;
+    let (tx, mut rx) = mpsc::channel::<&'static str>(4);
+    let tx1 = tx.clone();
+    let tx2 = tx.clone();
+    io.ns("/", move |_: SocketRef| {
+        tx.try_send("handler").unwrap();
+    })
+    .with_middleware(move |s: SocketRef| {
+        tx1.try_send("mw1").unwrap();
+        match s.req_parts().headers.get("x-api-key") {
+            Some(_) => Ok(()),
+            None => Err("missing api key"),
+        }
+    })
+    .with_middleware(move || {
+        tx2.try_send("mw2").unwrap();
+        Ok::<_, std::convert::Infallible>(())
+    });
+
+    const URL: &str = "ws://127.0.0.1:2030/socket.io/?EIO=4&transport=websocket";
+
+    // Without the header, the connection is rejected by the first middleware
+    let (mut stream, _) = tokio_tungstenite::connect_async(URL).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"44{"message":"missing api key"}"#);
+    assert_eq!(rx.recv().await.unwrap(), "mw1");
+    assert!(rx.try_recv().is_err());
+
+    // With the header, all the middlewares and then the handler are called
+    let mut req = URL.into_client_request().unwrap();
+    req.headers_mut()
+        .insert("x-api-key", "secret".parse().unwrap());
+    let (mut stream, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    assert_eq!(rx.recv().await.unwrap(), "mw1");
+    assert_eq!(rx.recv().await.unwrap(), "mw2");
+    assert_eq!(rx.recv().await.unwrap(), "handler");
+}
+
+#[tokio::test]
+pub async fn connect_error_handler() {
+    let io = create_server(2160).await;
+    let (tx, mut rx) = mpsc::unbounded_channel::<(ConnectErrorKind, String, Option<String>)>();
+    io.on_connect_error(move |failure| {
+        let ua = failure.req_parts.headers.get("user-agent");
+        let ua = ua.map(|ua| ua.to_str().unwrap().to_string());
+        tx.send((failure.kind.clone(), failure.ns.to_string(), ua))
+            .unwrap();
+    });
+    io.ns("/", |_: SocketRef| {})
+        .with_middleware(|| Err::<(), _>("banned"));
+
+    const URL: &str = "ws://127.0.0.1:2160/socket.io/?EIO=4&transport=websocket";
+    let mut req = URL.into_client_request().unwrap();
+    req.headers_mut()
+        .insert("user-agent", "scanner".parse().unwrap());
+    let (mut stream, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+
+    // Rejected by a middleware
+    stream.send(Message::Text("40{}".into())).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"44{"message":"banned"}"#);
+    let (kind, ns, ua) = rx.recv().await.unwrap();
+    assert_eq!(kind, ConnectErrorKind::Middleware("banned".into()));
+    assert_eq!(ns, "/");
+    assert_eq!(ua.as_deref(), Some("scanner"));
+
+    // Unknown namespace
+    stream
+        .send(Message::Text("40/unknown,{}".into()))
+        .await
+        .unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"44/unknown,{"message":"Invalid namespace"}"#);
+    let (kind, ns, _) = rx.recv().await.unwrap();
+    assert_eq!(kind, ConnectErrorKind::InvalidNamespace);
+    assert_eq!(ns, "/unknown");
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,104 @@
+//! Tests for the [`MsgPackParser`] negotiated by the clients sending binary packets
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use socketioxide::{
+    extract::{Bin, Data, SocketRef},
+    parser::MsgPackParser,
+    SocketIo,
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Encodes a msgpack string
+fn str(s: &str) -> Vec<u8> {
+    let mut buf = vec![0xa0 | s.len() as u8];
+    buf.extend_from_slice(s.as_bytes());
+    buf
+}
+
+/// Encodes an event packet to the root namespace with a string, a number and a binary argument
+fn event(name: &str) -> Vec<u8> {
+    [
+        vec![0x83],
+        str("type"),
+        vec![0x02],
+        str("data"),
+        vec![0x94],
+        str(name),
+        str("hello"),
+        vec![0x2a, 0xc4, 0x03, 1, 2, 3],
+        str("nsp"),
+        str("/"),
+    ]
+    .concat()
+}
+
+async fn next_msg(stream: &mut WsStream) -> Message {
+    tokio::time::timeout(Duration::from_millis(200), stream.next())
+        .await
+        .expect("timeout waiting for a message")
+        .unwrap()
+        .unwrap()
+}
+
+async fn create_server(port: u16) -> SocketIo {
+    let (svc, io) = SocketIo::builder().with_parser(MsgPackParser).build_svc();
+    fixture::spawn_server(port, svc).await;
+    io.ns("/", |socket: SocketRef| {
+        socket.on(
+            "event",
+            |socket: SocketRef, Data::<Value>(data), Bin(bin)| async move {
+                socket.bin(bin).emit("echo", data).await.ok();
+            },
+        );
+    });
+    io
+}
+
+#[tokio::test]
+pub async fn msgpack_event_round_trip() {
+    create_server(2110).await;
+    let mut stream = tokio_tungstenite::connect_async(
+        "ws://127.0.0.1:2110/socket.io/?EIO=4&transport=websocket",
+    )
+    .await
+    .unwrap()
+    .0;
+    let _open = next_msg(&mut stream).await;
+
+    let connect = [vec![0x82], str("type"), vec![0x00], str("nsp"), str("/")].concat();
+    stream.send(Message::Binary(connect)).await.unwrap();
+    let Message::Binary(connect) = next_msg(&mut stream).await else {
+        panic!("expected a binary connect packet");
+    };
+    let prefix = [vec![0x83], str("type"), vec![0x00], str("data")].concat();
+    assert!(connect.starts_with(&prefix));
+
+    stream.send(Message::Binary(event("event"))).await.unwrap();
+    assert_eq!(next_msg(&mut stream).await, Message::Binary(event("echo")));
+
+    stream.send(Message::Close(None)).await.ok();
+}
+
+#[tokio::test]
+pub async fn msgpack_server_accepts_default_clients() {
+    create_server(2111).await;
+    let mut stream = fixture::create_ws_connection(2111).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+
+    let msg = r#"451-["event","hello",42,{"_placeholder":true,"num":0}]"#;
+    stream.send(Message::Text(msg.into())).await.unwrap();
+    stream.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+
+    let echo = r#"451-["echo","hello",42,{"_placeholder":true,"num":0}]"#;
+    assert_eq!(next_msg(&mut stream).await, Message::Text(echo.into()));
+    assert_eq!(next_msg(&mut stream).await, Message::Binary(vec![1, 2, 3]));
+
+    stream.send(Message::Close(None)).await.ok();
+}
@@ -0,0 +1,65 @@
+//! Tests for the emission to several namespaces with [`SocketIo::of_many`]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Serialize, Serializer};
+use socketioxide::extract::SocketRef;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::create_server;
+
+static SERIALIZED: AtomicUsize = AtomicUsize::new(0);
+
+/// A payload counting the number of times it is serialized
+struct Counted;
+impl Serialize for Counted {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SERIALIZED.fetch_add(1, Ordering::SeqCst);
+        serializer.serialize_str("hello")
+    }
+}
+
+#[tokio::test]
+pub async fn emit_to_many_namespaces() {
+    let io = create_server(2170).await;
+    io.ns("/a", |_: SocketRef| {});
+    io.ns("/b", |_: SocketRef| {});
+
+    // Connected to both namespaces
+    let mut both = fixture::create_ws_connection(2170).await;
+    let _open = both.next().await.unwrap().unwrap();
+    let _connect = both.next().await.unwrap().unwrap();
+    for ns in ["/a", "/b"] {
+        both.send(Message::Text(format!("40{ns},{{}}")))
+            .await
+            .unwrap();
+        let _connect = both.next().await.unwrap().unwrap();
+    }
+
+    // Only connected to the "/b" namespace
+    let mut b = fixture::create_ws_connection(2170).await;
+    let _open = b.next().await.unwrap().unwrap();
+    let _connect = b.next().await.unwrap().unwrap();
+    b.send(Message::Text("40/b,{}".into())).await.unwrap();
+    let _connect = b.next().await.unwrap().unwrap();
+
+    let count = io
+        .of_many(["/a", "/b", "/unknown"])
+        .emit("event", Counted)
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(SERIALIZED.load(Ordering::SeqCst), 1);
+
+    let msg = both.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text(r#"42/a,["event","hello"]"#.into()));
+    let msg = b.next().await.unwrap().unwrap();
+    assert_eq!(msg, Message::Text(r#"42/b,["event","hello"]"#.into()));
+
+    // The client connected to both namespaces only receives the message once
+    tokio::time::timeout(Duration::from_millis(100), both.next())
+        .await
+        .unwrap_err();
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde_json::Value;
+use socketioxide::extract::SocketRef;
+
+mod fixture;
+use fixture::create_server;
+
+/// Opens a websocket session and returns the heartbeat parameters of its open packet
+async fn handshake_heartbeat(query: &str) -> (u64, u64) {
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:2050/socket.io/?EIO=4&transport=websocket{query}"
+    ))
+    .await
+    .unwrap();
+    let open = stream.next().await.unwrap().unwrap().to_string();
+    let open: Value = serde_json::from_str(&open[1..]).unwrap();
+    (
+        open["pingInterval"].as_u64().unwrap(),
+        open["pingTimeout"].as_u64().unwrap(),
+    )
+}
+
+#[tokio::test]
+pub async fn ns_heartbeat_override() {
+    let io = create_server(2050).await;
+    io.ns("/", |_: SocketRef| {});
+    io.ns("/iot", |_: SocketRef| {})
+        .with_ping_interval(Duration::from_millis(1234));
+
+    // The global config is used by default
+    assert_eq!(handshake_heartbeat("").await, (300, 200));
+    // The unset timeout falls back to the global config
+    assert_eq!(handshake_heartbeat("&ns=/iot").await, (1234, 200));
+    assert_eq!(handshake_heartbeat("&ns=%2Fiot").await, (1234, 200));
+    // Unknown namespaces use the global config
+    assert_eq!(handshake_heartbeat("&ns=/chat").await, (300, 200));
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use socketioxide::{extract::SocketRef, handler::ConnectErrorKind};
+use tokio::sync::mpsc;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn ns_max_connections() {
+    let io = create_server(2200).await;
+    let (tx, mut rx) = mpsc::unbounded_channel::<ConnectErrorKind>();
+    io.on_connect_error(move |failure| tx.send(failure.kind.clone()).unwrap());
+    let (disconnect_tx, mut disconnect_rx) = mpsc::unbounded_channel::<()>();
+    io.ns("/", move |s: SocketRef| {
+        let disconnect_tx = disconnect_tx.clone();
+        s.on_disconnect(move || disconnect_tx.send(()).unwrap());
+    })
+    .with_max_connections(2);
+
+    let connect = || async {
+        let mut stream = create_ws_connection(2200).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let msg = stream.next().await.unwrap().unwrap().to_string();
+        (stream, msg)
+    };
+
+    let (stream1, msg) = connect().await;
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    let (_stream2, msg) = connect().await;
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+
+    // The limit is reached
+    let (_stream3, msg) = connect().await;
+    assert_eq!(msg, r#"44{"message":"too many connections"}"#);
+    assert_eq!(
+        rx.recv().await.unwrap(),
+        ConnectErrorKind::TooManyConnections
+    );
+
+    // The connection is abruptly dropped, freeing a slot
+    drop(stream1);
+    tokio::time::timeout(Duration::from_secs(1), disconnect_rx.recv())
+        .await
+        .expect("socket should be disconnected")
+        .unwrap();
+
+    let (_stream4, msg) = connect().await;
+    assert!(msg.starts_with("40{\"sid\":"), "unexpected message: {msg}");
+    assert_eq!(io.sockets().await.unwrap().len(), 2);
+}
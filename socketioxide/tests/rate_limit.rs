@@ -0,0 +1,95 @@
+//! Tests for the [`RateLimit`] applied to the events received by each socket
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::{
+    extract::SocketRef, socket::DisconnectReason, RateLimit, RateLimitPolicy, SocketIo,
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+use fixture::{create_ws_connection, spawn_server};
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Received,
+    Disconnected(DisconnectReason),
+}
+
+async fn create_server(port: u16, policy: RateLimitPolicy) -> mpsc::UnboundedReceiver<Event> {
+    let (svc, io) = SocketIo::builder()
+        .rate_limit(RateLimit::new(10.0, 3).policy(policy))
+        .build_svc();
+    spawn_server(port, svc).await;
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    io.ns("/", move |socket: SocketRef| {
+        let tx1 = tx.clone();
+        let tx2 = tx.clone();
+        socket.on("test", move || tx1.send(Event::Received).unwrap());
+        socket.on_disconnect(move |reason: DisconnectReason| {
+            tx2.send(Event::Disconnected(reason)).unwrap();
+        });
+    });
+    rx
+}
+
+/// Sends a burst of `count` events
+async fn send_burst(port: u16, count: usize) -> WsStream {
+    let mut stream = create_ws_connection(port).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    for _ in 0..count {
+        let msg = Message::Text(r#"42["test"]"#.to_string());
+        stream.send(msg).await.unwrap();
+    }
+    stream
+}
+
+/// Collects the events received by the server during 50ms
+async fn collect(rx: &mut mpsc::UnboundedReceiver<Event>) -> Vec<Event> {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut events = vec![];
+    while let Ok(event) = rx.try_recv() {
+        events.push(event);
+    }
+    events
+}
+
+#[tokio::test]
+pub async fn rate_limit_drop() {
+    let mut rx = create_server(2140, RateLimitPolicy::Drop).await;
+    let mut stream = send_burst(2140, 5).await;
+    let events = collect(&mut rx).await;
+    assert_eq!(events, (0..3).map(|_| Event::Received).collect::<Vec<_>>());
+
+    // Tokens are refilled over time
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stream
+        .send(Message::Text(r#"42["test"]"#.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(collect(&mut rx).await, [Event::Received]);
+    stream.close(None).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn rate_limit_disconnect() {
+    let mut rx = create_server(2141, RateLimitPolicy::Disconnect).await;
+    let mut stream = send_burst(2141, 5).await;
+    let events = collect(&mut rx).await;
+    assert_eq!(
+        events,
+        [
+            Event::Received,
+            Event::Received,
+            Event::Received,
+            Event::Disconnected(DisconnectReason::RateLimitExceeded),
+        ]
+    );
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, "41");
+}
@@ -0,0 +1,185 @@
+//! Tests for the [`RedisAdapter`] with two servers sharing an in-process redis mock
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, stream::BoxStream, SinkExt, StreamExt};
+use socketioxide::{
+    adapter::redis::{RedisAdapter, RedisDriver, RedisError},
+    extract::SocketRef,
+    SocketIo,
+};
+use tokio::sync::{broadcast, Notify};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The redis mock shared by the servers of a test
+#[derive(Default)]
+struct Redis {
+    /// The pub/sub bus, `None` when redis is down
+    bus: Mutex<Option<broadcast::Sender<Vec<u8>>>>,
+    /// Notified each time a server subscribed or handled a message
+    event: Notify,
+}
+
+thread_local! {
+    // Each test runs on its own thread, so it gets its own redis mock
+    static REDIS: Arc<Redis> = Arc::default();
+}
+
+/// Starts or stops the redis mock, stopping it ends all the subscriptions
+fn set_redis_up(up: bool) {
+    let redis = REDIS.with(Arc::clone);
+    *redis.bus.lock().unwrap() = up.then(|| broadcast::channel(256).0);
+}
+
+/// Returns the number of servers subscribed to the redis mock
+fn subscribers() -> usize {
+    let redis = REDIS.with(Arc::clone);
+    let bus = redis.bus.lock().unwrap();
+    bus.as_ref().map_or(0, broadcast::Sender::receiver_count)
+}
+
+/// Waits until the condition holds, it is checked again each time a server subscribed or handled a message
+async fn until<F: Future<Output = bool>>(condition: impl Fn() -> F) {
+    let redis = REDIS.with(Arc::clone);
+    let wait = async {
+        loop {
+            let event = redis.event.notified();
+            if condition().await {
+                return;
+            }
+            event.await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("condition not met");
+}
+
+struct MockRedis(Arc<Redis>);
+
+impl Default for MockRedis {
+    fn default() -> Self {
+        Self(REDIS.with(Arc::clone))
+    }
+}
+
+impl RedisDriver for MockRedis {
+    fn publish(&self, _: String, message: Vec<u8>) -> BoxFuture<'static, Result<(), RedisError>> {
+        let bus = self.0.bus.lock().unwrap().clone();
+        Box::pin(async move {
+            let bus = bus.ok_or("redis is down")?;
+            bus.send(message).ok();
+            Ok(())
+        })
+    }
+
+    fn subscribe(
+        &self,
+        _: String,
+    ) -> BoxFuture<'static, Result<BoxStream<'static, Vec<u8>>, RedisError>> {
+        let redis = self.0.clone();
+        let rx = redis
+            .bus
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|bus| bus.subscribe());
+        Box::pin(async move {
+            let rx = rx.ok_or("redis is down")?;
+            // The next message is only requested once the previous one is handled
+            let stream = futures::stream::unfold(rx, move |mut rx| {
+                redis.event.notify_waiters();
+                async move { rx.recv().await.ok().map(|message| (message, rx)) }
+            });
+            Ok(stream.boxed())
+        })
+    }
+}
+
+async fn create_node(port: u16) -> SocketIo<RedisAdapter<MockRedis>> {
+    let (svc, io) = SocketIo::builder()
+        .with_adapter::<RedisAdapter<MockRedis>>()
+        .build_svc();
+    fixture::spawn_server(port, svc).await;
+    io.ns(
+        "/",
+        |socket: SocketRef<RedisAdapter<MockRedis>>| async move {
+            socket.join("room1").await.ok();
+        },
+    );
+    io
+}
+
+async fn connect(port: u16) -> WsStream {
+    let mut stream = fixture::create_ws_connection(port).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream
+}
+
+async fn next_msg(stream: &mut WsStream) -> Option<String> {
+    tokio::time::timeout(Duration::from_millis(200), stream.next())
+        .await
+        .ok()
+        .map(|msg| msg.unwrap().unwrap().to_string())
+}
+
+#[tokio::test]
+pub async fn redis_adapter() {
+    set_redis_up(true);
+    let io1 = create_node(2100).await;
+    let io2 = create_node(2101).await;
+    let mut client1 = connect(2100).await;
+    let mut client2 = connect(2101).await;
+    until(|| async { io1.within("room1").len().await.unwrap() == 2 }).await;
+    until(|| async { io2.within("room1").len().await.unwrap() == 2 }).await;
+
+    // Broadcasts reach the sockets of both servers
+    io1.to("room1").emit("msg", "hello").await.unwrap();
+    assert_eq!(
+        next_msg(&mut client1).await.unwrap(),
+        r#"42["msg","hello"]"#
+    );
+    assert_eq!(
+        next_msg(&mut client2).await.unwrap(),
+        r#"42["msg","hello"]"#
+    );
+
+    // The local flag only targets the sockets of the current server
+    io2.local().emit("msg", "local").await.unwrap();
+    assert_eq!(
+        next_msg(&mut client2).await.unwrap(),
+        r#"42["msg","local"]"#
+    );
+    assert_eq!(next_msg(&mut client1).await, None);
+
+    // It can be combined with the room selection
+    io1.to("room1").local().emit("msg", "room").await.unwrap();
+    assert_eq!(next_msg(&mut client1).await.unwrap(), r#"42["msg","room"]"#);
+    assert_eq!(next_msg(&mut client2).await, None);
+    assert_eq!(io1.within("room1").len().await.unwrap(), 2);
+    assert_eq!(io1.within("room1").local().len().await.unwrap(), 1);
+
+    // The local sockets still receive the broadcasts while redis is down
+    set_redis_up(false);
+    io1.to("room1").emit("msg", "down").await.unwrap();
+    assert_eq!(next_msg(&mut client1).await.unwrap(), r#"42["msg","down"]"#);
+    assert_eq!(next_msg(&mut client2).await, None);
+
+    // The servers subscribe again once redis is back
+    set_redis_up(true);
+    until(|| async { subscribers() == 2 }).await;
+    io2.to("room1").emit("msg", "up").await.unwrap();
+    assert_eq!(next_msg(&mut client1).await.unwrap(), r#"42["msg","up"]"#);
+    assert_eq!(next_msg(&mut client2).await.unwrap(), r#"42["msg","up"]"#);
+
+    client1.send(Message::Close(None)).await.ok();
+    client2.send(Message::Close(None)).await.ok();
+}
@@ -0,0 +1,113 @@
+//! Tests for several socket.io instances mounted on the same server with distinct paths
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use futures::{SinkExt, StreamExt};
+use http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::server::conn::http1;
+use hyper_util::{
+    client::legacy::Client,
+    rt::{TokioExecutor, TokioIo},
+};
+use socketioxide::{extract::SocketRef, SocketIo};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const PORT: u16 = 2250;
+
+/// Serves the `/chat-admin` instance behind the `/chat` one
+async fn create_servers() -> (SocketIo, SocketIo) {
+    let (admin_svc, admin) = SocketIo::builder().req_path("/chat-admin").build_svc();
+    let (svc, chat) = SocketIo::builder()
+        .req_path("/chat")
+        .build_with_inner_svc(admin_svc);
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), PORT);
+    let listener = TcpListener::bind(&addr).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+            let svc = svc.clone();
+            tokio::spawn(async move {
+                http1::Builder::new()
+                    .serve_connection(io, svc)
+                    .with_upgrades()
+                    .await
+                    .ok();
+            });
+        }
+    });
+    (chat, admin)
+}
+
+async fn ws_connect(path: &str) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let url = format!("ws://127.0.0.1:{PORT}{path}/?EIO=4&transport=websocket");
+    let mut ws = tokio_tungstenite::connect_async(url).await.unwrap().0;
+    let _open = ws.next().await.unwrap().unwrap();
+    ws.send(Message::Text("40{}".into())).await.unwrap();
+    let _connect = ws.next().await.unwrap().unwrap();
+    ws
+}
+
+async fn polling_req(path: &str, params: &str, body: &'static str) -> (StatusCode, String) {
+    let method = if body.is_empty() {
+        http::Method::GET
+    } else {
+        http::Method::POST
+    };
+    let req = Request::builder()
+        .method(method)
+        .uri(format!(
+            "http://127.0.0.1:{PORT}{path}/?EIO=4&transport=polling&{params}"
+        ))
+        .body(Full::new(body.as_bytes()))
+        .unwrap();
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await
+        .unwrap();
+    let status = res.status();
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+pub async fn req_path_isolation() {
+    let (chat, admin) = create_servers().await;
+    chat.ns("/", |s: SocketRef| s.emit("welcome", "chat").unwrap());
+    admin.ns("/", |s: SocketRef| s.emit("welcome", "admin").unwrap());
+
+    let mut chat_ws = ws_connect("/chat").await;
+    let mut admin_ws = ws_connect("/chat-admin").await;
+    let msg = chat_ws.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["welcome","chat"]"#);
+    let msg = admin_ws.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["welcome","admin"]"#);
+    assert_eq!(chat.sockets().await.unwrap().len(), 1);
+    assert_eq!(admin.sockets().await.unwrap().len(), 1);
+
+    // A broadcast only reaches the sockets of its instance
+    admin.emit("news", "for admins").await.unwrap();
+    let msg = admin_ws.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["news","for admins"]"#);
+    chat.emit("news", "for everyone").await.unwrap();
+    let msg = chat_ws.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["news","for everyone"]"#);
+
+    // A polling session is only known by the instance that opened it
+    let (status, body) = polling_req("/chat-admin", "", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let sid = body.split('"').nth(3).unwrap().to_string();
+    let params = format!("sid={sid}");
+    let (status, _) = polling_req("/chat", &params, "40{}").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = polling_req("/chat-admin", &params, "40{}").await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "ok"));
+
+    // The other paths are forwarded to the inner service
+    let (status, _) = polling_req("/chat2", "", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
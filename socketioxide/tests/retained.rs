@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use socketioxide::extract::SocketRef;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn emit_buffered_before_connect() {
+    let io = create_server(2307).await;
+    io.ns("/", |_: SocketRef| {});
+
+    // No socket is connected yet
+    assert_eq!(io.emit_buffered("config", "light").await.unwrap(), 0);
+    assert_eq!(io.emit_buffered("config", "dark").await.unwrap(), 0);
+    assert_eq!(io.emit("tick", 1).await.unwrap(), 0);
+
+    let mut stream = create_ws_connection(2307).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let connect = stream.next().await.unwrap().unwrap().to_string();
+    assert!(connect.starts_with("40"), "{connect}");
+
+    // Only the last retained message is delivered, the other events are not retained
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"42["config","dark"]"#);
+    let res = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(res.is_err(), "unexpected message {res:?}");
+
+    // The connected sockets receive the buffered messages as a regular broadcast
+    assert_eq!(io.emit_buffered("config", "blue").await.unwrap(), 1);
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"42["config","blue"]"#);
+
+    assert!(io.clear_retained("config"));
+    assert!(!io.clear_retained("config"));
+
+    let mut stream = create_ws_connection(2307).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    let res = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+    assert!(res.is_err(), "unexpected message {res:?}");
+}
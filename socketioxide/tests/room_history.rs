@@ -0,0 +1,46 @@
+use futures::{SinkExt, StreamExt};
+use socketioxide::extract::{Data, SocketRef};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn room_history_replay_on_join() {
+    let io = create_server(2240).await;
+    io.ns("/", |s: SocketRef| async move {
+        s.join_with_history("chat").await.unwrap();
+        s.on("message", |s: SocketRef, Data::<String>(msg)| async move {
+            s.within("chat").emit("message", msg).await.unwrap();
+        });
+    })
+    .with_room_history("chat", 2);
+
+    let mut stream1 = create_ws_connection(2240).await;
+    let _open = stream1.next().await.unwrap().unwrap();
+    let _connect = stream1.next().await.unwrap().unwrap();
+
+    for msg in ["one", "two", "three"] {
+        let packet = format!(r#"42["message","{msg}"]"#);
+        stream1.send(Message::Text(packet.clone())).await.unwrap();
+        let echo = stream1.next().await.unwrap().unwrap();
+        assert_eq!(echo.to_string(), packet);
+    }
+    // Not kept in the history as it is not emitted to the room
+    io.emit("message", "everyone").await.unwrap();
+    let _msg = stream1.next().await.unwrap().unwrap();
+
+    // The late joiner receives the last two messages of the room
+    let mut stream2 = create_ws_connection(2240).await;
+    let _open = stream2.next().await.unwrap().unwrap();
+    let _connect = stream2.next().await.unwrap().unwrap();
+    for msg in ["two", "three"] {
+        let packet = stream2.next().await.unwrap().unwrap();
+        assert_eq!(packet.to_string(), format!(r#"42["message","{msg}"]"#));
+    }
+
+    // New messages are received live
+    io.to("chat").emit("message", "four").await.unwrap();
+    let packet = stream2.next().await.unwrap().unwrap();
+    assert_eq!(packet.to_string(), r#"42["message","four"]"#);
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::{
+    extract::{AckSender, SocketRef},
+    AckError, BroadcastError, SendError,
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+/// A map with non-string keys can't be serialized to JSON
+fn unserializable() -> HashMap<(i32, i32), i32> {
+    HashMap::from([((1, 2), 3)])
+}
+
+#[tokio::test]
+pub async fn emit_serialize_error() {
+    let io = create_server(2303).await;
+    let (tx, mut rx) = mpsc::channel::<&'static str>(8);
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        s.on("test", move |s: SocketRef, ack: AckSender| async move {
+            let res = s.emit("msg", unserializable());
+            assert!(matches!(res, Err(SendError::Serialize(_))));
+            tx.try_send("emit").unwrap();
+
+            let res = s.emit_with_ack::<()>("msg", unserializable()).await;
+            assert!(matches!(res, Err(AckError::Serialize(_))));
+            tx.try_send("emit_with_ack").unwrap();
+
+            let res = s.broadcast().emit("msg", unserializable()).await;
+            assert!(matches!(res, Err(BroadcastError::Serialize(_))));
+            let res = s.within("room").emit("msg", unserializable()).await;
+            assert!(matches!(res, Err(BroadcastError::Serialize(_))));
+            tx.try_send("broadcast").unwrap();
+
+            let res = s
+                .broadcast_rooms()
+                .emit_with_ack::<()>("msg", unserializable())
+                .await;
+            assert!(matches!(res, Err(BroadcastError::Serialize(_))));
+            tx.try_send("broadcast_with_ack").unwrap();
+
+            let res = ack.send(unserializable());
+            assert!(matches!(res, Err(SendError::Serialize(_))));
+            tx.try_send("ack").unwrap();
+
+            // The socket is still usable
+            s.emit("msg", "ok").unwrap();
+        });
+    });
+
+    let mut stream = create_ws_connection(2303).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    stream
+        .send(Message::Text(r#"421["test"]"#.into()))
+        .await
+        .unwrap();
+
+    for expected in [
+        "emit",
+        "emit_with_ack",
+        "broadcast",
+        "broadcast_with_ack",
+        "ack",
+    ] {
+        assert_eq!(rx.recv().await.unwrap(), expected);
+    }
+    // Nothing was sent before the last message
+    let msg = stream.next().await.unwrap().unwrap();
+    assert_eq!(msg.to_string(), r#"42["msg","ok"]"#);
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde_json::Value;
+use socketioxide::{extract::SocketRef, session::MemorySessionStore, SocketIo};
+use tokio::sync::mpsc;
+
+mod fixture;
+use fixture::{create_ws_connection_with_auth, spawn_server};
+
+#[tokio::test]
+pub async fn session_recovery() {
+    let (svc, io) = SocketIo::builder()
+        .with_session_store(MemorySessionStore::default())
+        .build_svc();
+    spawn_server(2080, svc).await;
+
+    let (tx, mut rx) = mpsc::channel::<bool>(4);
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        async move {
+            if !s.recovered() {
+                s.join("room1").await.unwrap();
+            }
+            tx.try_send(s.recovered()).unwrap();
+        }
+    });
+
+    let mut stream = create_ws_connection_with_auth(2080, "{}").await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let connect = stream.next().await.unwrap().unwrap().to_string();
+    let connect: Value = serde_json::from_str(&connect[2..]).unwrap();
+    let pid = connect["pid"].as_str().unwrap().to_string();
+    assert!(!rx.recv().await.unwrap());
+
+    // The connection is abruptly dropped, the session is saved
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(io.sockets().await.unwrap().is_empty());
+
+    io.to("room1").emit("missed", "hello").await.unwrap();
+    io.to("room2").emit("other", "room").await.unwrap();
+    io.emit("all", 1).await.unwrap();
+
+    let mut stream = create_ws_connection_with_auth(2080, &format!(r#"{{"pid":"{pid}"}}"#)).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let connect = stream.next().await.unwrap().unwrap().to_string();
+    let connect: Value = serde_json::from_str(&connect[2..]).unwrap();
+    assert!(rx.recv().await.unwrap());
+
+    // The room membership is restored and the missed packets are replayed
+    let sockets = io.within("room1").sockets().await.unwrap();
+    assert_eq!(sockets.len(), 1);
+    assert_eq!(sockets[0].id.to_string(), connect["sid"].as_str().unwrap());
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"42["missed","hello"]"#);
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"42["all",1]"#);
+
+    // A session can only be restored once
+    let mut stream = create_ws_connection_with_auth(2080, &format!(r#"{{"pid":"{pid}"}}"#)).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    assert!(!rx.recv().await.unwrap());
+}
+
+#[tokio::test]
+pub async fn disconnect_grace_period() {
+    let (svc, io) = SocketIo::builder()
+        .with_session_store(MemorySessionStore::default())
+        .disconnect_grace_period(Duration::from_millis(200))
+        .build_svc();
+    spawn_server(2130, svc).await;
+
+    let (tx, mut rx) = mpsc::channel::<&'static str>(8);
+    io.ns("/", move |s: SocketRef| {
+        #[cfg(feature = "extensions")]
+        if s.extensions.get::<u32>().is_none() {
+            s.extensions.insert(1u32);
+        } else {
+            tx.try_send("state preserved").unwrap();
+        }
+        tx.try_send(if s.recovered() {
+            "recovered"
+        } else {
+            "connected"
+        })
+        .unwrap();
+        let tx = tx.clone();
+        s.on_disconnect(move || tx.try_send("disconnected").unwrap());
+    });
+
+    let connect = |auth: String| async move {
+        let mut stream = create_ws_connection_with_auth(2130, &auth).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let connect = stream.next().await.unwrap().unwrap().to_string();
+        let connect: Value = serde_json::from_str(&connect[2..]).unwrap();
+        (stream, connect["pid"].as_str().unwrap().to_string())
+    };
+
+    let (stream, pid) = connect("{}".into()).await;
+    assert_eq!(rx.recv().await.unwrap(), "connected");
+    // The connection is abruptly dropped, the socket is kept during the grace period
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+
+    // Reconnecting within the grace period takes over the previous socket
+    let (stream, pid) = connect(format!(r#"{{"pid":"{pid}"}}"#)).await;
+    #[cfg(feature = "extensions")]
+    assert_eq!(rx.recv().await.unwrap(), "state preserved");
+    assert_eq!(rx.recv().await.unwrap(), "recovered");
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(rx.try_recv().is_err());
+
+    // Once the grace period elapses, the socket is torn down
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(rx.try_recv().unwrap(), "disconnected");
+    let (_stream, _) = connect(format!(r#"{{"pid":"{pid}"}}"#)).await;
+    assert_eq!(rx.recv().await.unwrap(), "recovered");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use socketioxide::{extract::SocketRef, ShutdownSummary};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+#[tokio::test]
+pub async fn shutdown_drains_acks() {
+    let io = create_server(2040).await;
+    io.ns("/", |s: SocketRef| async move {
+        let answered = s.emit_with_ack::<Value>("answered", ());
+        let ignored = s.emit_with_ack::<Value>("ignored", ());
+        let (answered, ignored) = futures::join!(answered, ignored);
+        assert!(answered.is_ok());
+        assert!(ignored.is_err());
+    });
+
+    let mut stream = create_ws_connection(2040).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let _connect = stream.next().await.unwrap().unwrap();
+    let mut events = Vec::new();
+    for _ in 0..2 {
+        events.push(stream.next().await.unwrap().unwrap().to_string());
+    }
+    events.sort();
+    assert!(events[0].starts_with("42") && events[0].contains("answered"));
+    let answered_id = &events[0][2..events[0].find('[').unwrap()];
+    let answered_id = answered_id.to_string();
+
+    let shutdown = tokio::spawn({
+        let io = io.clone();
+        async move { io.shutdown(Duration::from_millis(300)).await }
+    });
+
+    // The server notifies the client before waiting for the acks
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, "41");
+    stream
+        .send(Message::Text(format!("43{answered_id}[\"ok\"]")))
+        .await
+        .unwrap();
+
+    let summary = tokio::time::timeout(Duration::from_millis(500), shutdown)
+        .await
+        .expect("shutdown should not exceed its grace period")
+        .unwrap();
+    assert_eq!(
+        summary,
+        ShutdownSummary {
+            completed_acks: 1,
+            abandoned_acks: 1,
+        }
+    );
+
+    // New connections are refused
+    let mut stream = create_ws_connection(2040).await;
+    let _open = stream.next().await.unwrap().unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, r#"44{"message":"server is shutting down"}"#);
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use socketioxide::extract::{Data, SocketRef};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod fixture;
+use fixture::create_server;
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Connects to the `/admin` namespace and returns the stream with the socket id
+async fn connect_admin() -> (WsStream, String) {
+    let (mut stream, _) = tokio_tungstenite::connect_async(
+        "ws://127.0.0.1:2060/socket.io/?EIO=4&transport=websocket",
+    )
+    .await
+    .unwrap();
+    let _open = stream.next().await.unwrap().unwrap();
+    stream
+        .send(Message::Text("40/admin,{}".into()))
+        .await
+        .unwrap();
+    let connect = stream.next().await.unwrap().unwrap().to_string();
+    let connect: Value = serde_json::from_str(connect.trim_start_matches("40/admin,")).unwrap();
+    let sid = connect["sid"].as_str().unwrap().to_string();
+    (stream, sid)
+}
+
+#[tokio::test]
+pub async fn namespace_sockets() {
+    let io = create_server(2060).await;
+    io.ns("/", |_: SocketRef| {});
+    io.ns("/admin", |_: SocketRef| {});
+
+    let mut streams = Vec::new();
+    let mut sids = Vec::new();
+    for _ in 0..3 {
+        let (stream, sid) = connect_admin().await;
+        streams.push(stream);
+        sids.push(sid);
+    }
+    let mut root = fixture::create_ws_connection(2060).await;
+    let _open = root.next().await.unwrap().unwrap();
+    let _connect = root.next().await.unwrap().unwrap();
+
+    let mut ids: Vec<String> = io
+        .of("/admin")
+        .unwrap()
+        .sockets()
+        .await
+        .unwrap()
+        .iter()
+        .map(|s| s.id.to_string())
+        .collect();
+    ids.sort();
+    sids.sort();
+    assert_eq!(ids, sids);
+    assert_eq!(io.sockets().await.unwrap().len(), 1);
+
+    // Disconnected sockets are not returned anymore
+    streams[0]
+        .send(Message::Text("41/admin,".into()))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(io.of("/admin").unwrap().sockets().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+pub async fn room_len() {
+    const PORT: u16 = 2302;
+    let io = create_server(PORT).await;
+    io.ns("/", |s: SocketRef| {
+        s.on("join", |s: SocketRef, Data::<String>(room)| async move {
+            s.join(room).await.unwrap();
+            s.emit("joined", "ok").unwrap();
+        });
+        s.on("leave", |s: SocketRef, Data::<String>(room)| async move {
+            s.leave(room).await.unwrap();
+            s.emit("left", "ok").unwrap();
+        });
+    });
+
+    /// Sends the event and waits for the response of the handler
+    async fn send(stream: &mut WsStream, event: &str, room: &str) {
+        let msg = format!(r#"42["{event}","{room}"]"#);
+        stream.send(Message::Text(msg)).await.unwrap();
+        stream.next().await.unwrap().unwrap();
+    }
+
+    let mut streams = Vec::new();
+    for _ in 0..3 {
+        let mut stream = fixture::create_ws_connection(PORT).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let _connect = stream.next().await.unwrap().unwrap();
+        streams.push(stream);
+    }
+    assert_eq!(io.to("room1").len().await.unwrap(), 0);
+    assert!(io.to("room1").is_empty().await.unwrap());
+
+    for stream in &mut streams {
+        send(stream, "join", "room1").await;
+    }
+    send(&mut streams[0], "join", "room2").await;
+    assert_eq!(io.to("room1").len().await.unwrap(), 3);
+    assert_eq!(io.to("room2").len().await.unwrap(), 1);
+    // A socket in both rooms is only counted once
+    assert_eq!(io.to(["room1", "room2"]).len().await.unwrap(), 3);
+    assert_eq!(io.to("room1").except("room2").len().await.unwrap(), 2);
+
+    send(&mut streams[1], "leave", "room1").await;
+    assert_eq!(io.to("room1").len().await.unwrap(), 2);
+
+    // Disconnected sockets leave their rooms
+    streams[0].close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(io.to("room1").len().await.unwrap(), 1);
+    assert!(io.to("room2").is_empty().await.unwrap());
+}
+
+#[tokio::test]
+pub async fn rooms_snapshot() {
+    const PORT: u16 = 2304;
+    let io = create_server(PORT).await;
+    io.ns("/", |s: SocketRef, Data::<Vec<String>>(rooms)| async move {
+        s.join(rooms).await.unwrap();
+    });
+    io.ns("/admin", |_: SocketRef| {});
+
+    let mut sids = Vec::new();
+    let mut streams = Vec::new();
+    for auth in [r#"["room1","room2"]"#, r#"["room1"]"#, "[]"] {
+        let mut stream = fixture::create_ws_connection_with_auth(PORT, auth).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let connect = stream.next().await.unwrap().unwrap().to_string();
+        let connect: Value = serde_json::from_str(connect.trim_start_matches("40")).unwrap();
+        sids.push(connect["sid"].as_str().unwrap().to_string());
+        streams.push(stream);
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut rooms: Vec<(String, Vec<String>)> = io
+        .rooms("/")
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|(room, sids)| {
+            let mut sids: Vec<String> = sids.iter().map(|sid| sid.to_string()).collect();
+            sids.sort();
+            (room.to_string(), sids)
+        })
+        .collect();
+    rooms.sort();
+    let mut room1 = vec![sids[0].clone(), sids[1].clone()];
+    room1.sort();
+    assert_eq!(
+        rooms,
+        [
+            ("room1".to_string(), room1),
+            ("room2".to_string(), vec![sids[0].clone()])
+        ]
+    );
+
+    assert!(io.rooms("/admin").await.unwrap().unwrap().is_empty());
+    assert!(io.rooms("/unknown").await.is_none());
+}
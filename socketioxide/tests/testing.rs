@@ -0,0 +1,135 @@
+//! Tests for the in-memory [`TestClient`] of the `testing` module
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use socketioxide::{
+    extract::{AckSender, Data, SocketRef},
+    socket::DisconnectReason,
+    testing::{TestClient, TestClientError},
+    SocketIo,
+};
+use tokio::sync::mpsc;
+
+#[tokio::test]
+pub async fn emit_ack_round_trip() {
+    let (_, io) = SocketIo::new_svc();
+    io.ns("/", |s: SocketRef| {
+        s.emit("welcome", "hello").unwrap();
+        s.on(
+            "echo",
+            |s: SocketRef, ack: AckSender, Data::<Value>(data)| {
+                s.emit("echoed", &data).unwrap();
+                ack.send(data).unwrap();
+            },
+        );
+    });
+
+    let mut client = TestClient::connect(&io, "/")
+        .await
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+    let msg: String = client.recv_event("welcome").await.unwrap();
+    assert_eq!(msg, "hello");
+
+    let res: Value = client
+        .emit_with_ack("echo", json!({ "count": 1 }))
+        .await
+        .unwrap();
+    assert_eq!(res, json!({ "count": 1 }));
+    // The event emitted before the ack is kept
+    let event = client.recv().await.unwrap();
+    assert_eq!(event.event, "echoed");
+    assert_eq!(event.data, json!({ "count": 1 }));
+
+    client.emit("echo", "no ack").unwrap();
+    let msg: String = client.recv_event("echoed").await.unwrap();
+    assert_eq!(msg, "no ack");
+    let res = client.recv().await;
+    assert!(matches!(res, Err(TestClientError::Timeout)), "{res:?}");
+}
+
+#[tokio::test]
+pub async fn server_ack() {
+    let (_, io) = SocketIo::new_svc();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            // The ack data is received as an array of arguments
+            let res = s.emit_with_ack::<[String; 1]>("ping", 1).await.unwrap();
+            tx.send(res.data).unwrap();
+        });
+    });
+
+    let mut client = TestClient::connect(&io, "/").await.unwrap();
+    let event = client.recv().await.unwrap();
+    assert_eq!(event.event, "ping");
+    client.ack(event.ack_id.unwrap(), "pong").unwrap();
+    assert_eq!(rx.recv().await.unwrap(), ["pong"]);
+}
+
+#[tokio::test]
+pub async fn binary_ack_round_trip() {
+    let (_, io) = SocketIo::new_svc();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| {
+        s.on("thumbnail", |ack: AckSender, Data::<String>(name)| {
+            ack.bin(vec![vec![1, 2, 3]]).send(name).unwrap();
+        });
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let res = s.emit_with_ack::<[String; 1]>("upload", ()).await.unwrap();
+            tx.send(res).unwrap();
+        });
+    });
+
+    let mut client = TestClient::connect(&io, "/").await.unwrap();
+    let res = client
+        .emit_with_bin_ack::<String>("thumbnail", "cat.png")
+        .await
+        .unwrap();
+    assert_eq!(res.data, "cat.png");
+    assert_eq!(res.binary, [vec![1, 2, 3]]);
+
+    let event = client.recv().await.unwrap();
+    assert_eq!(event.event, "upload");
+    client
+        .ack_bin(event.ack_id.unwrap(), "done", vec![vec![4, 5], vec![6]])
+        .unwrap();
+    let res = rx.recv().await.unwrap();
+    assert_eq!(res.data, ["done"]);
+    assert_eq!(res.binary, [vec![4, 5], vec![6]]);
+}
+
+#[tokio::test]
+pub async fn connect_and_disconnect() {
+    let (_, io) = SocketIo::new_svc();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/admin", move |s: SocketRef, Data::<Value>(auth)| {
+        assert_eq!(auth, json!({ "token": "secret" }));
+        let tx = tx.clone();
+        s.on_disconnect(move |reason: DisconnectReason| tx.send(reason).unwrap());
+    });
+
+    let res = TestClient::connect(&io, "/unknown").await;
+    assert!(matches!(res, Err(TestClientError::ConnectError(_))));
+
+    let auth = json!({ "token": "secret" });
+    let client = TestClient::connect_with_auth(&io, "/admin", &auth)
+        .await
+        .unwrap();
+    assert_eq!(io.of("/admin").unwrap().sockets().await.unwrap().len(), 1);
+    client.disconnect().unwrap();
+    assert_eq!(
+        rx.recv().await.unwrap(),
+        DisconnectReason::ClientNSDisconnect
+    );
+
+    // A dropped client is abruptly disconnected
+    let client = TestClient::connect_with_auth(&io, "admin", &auth)
+        .await
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+    drop(client);
+    assert_eq!(rx.recv().await.unwrap(), DisconnectReason::TransportError);
+}
@@ -0,0 +1,61 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use serde::{Serialize, Serializer};
+use socketioxide::extract::SocketRef;
+use tokio::sync::mpsc;
+
+mod fixture;
+use fixture::{create_server, create_ws_connection};
+
+static SERIALIZE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the number of times it is serialized
+struct Counted;
+impl Serialize for Counted {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SERIALIZE_COUNT.fetch_add(1, Ordering::SeqCst);
+        serializer.serialize_str("counted")
+    }
+}
+
+#[tokio::test]
+pub async fn to_sockets_serializes_once() {
+    const PORT: u16 = 2230;
+    let io = create_server(PORT).await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    io.ns("/", move |s: SocketRef| tx.send(s.id).unwrap());
+
+    let mut streams = Vec::new();
+    let mut sids = Vec::new();
+    for _ in 0..4 {
+        let mut stream = create_ws_connection(PORT).await;
+        let _open = stream.next().await.unwrap().unwrap();
+        let _connect = stream.next().await.unwrap().unwrap();
+        streams.push(stream);
+        sids.push(rx.recv().await.unwrap());
+    }
+
+    // The last socket is not selected, the first one is given twice
+    let selected = [sids[0], sids[1], sids[2], sids[0]];
+    let count = io.to_sockets(selected).emit("msg", Counted).unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(SERIALIZE_COUNT.load(Ordering::SeqCst), 1);
+
+    for stream in &mut streams[..3] {
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg.to_string(), r#"42["msg","counted"]"#);
+    }
+    let res = tokio::time::timeout(Duration::from_millis(50), streams[3].next()).await;
+    assert!(res.is_err(), "unselected socket received a message");
+
+    // The sockets which are gone are ignored
+    drop(streams.remove(0));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let count = io.to_sockets(sids.clone()).emit("msg", Counted).unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(SERIALIZE_COUNT.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use socketioxide::{extract::SocketRef, TransportType};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixture;
+use fixture::{create_polling_connection, create_server, create_ws_connection, send_req};
+
+#[tokio::test]
+pub async fn transport_type_after_upgrade() {
+    let io = create_server(2090).await;
+    io.ns("/", |_: SocketRef| {});
+
+    let sid = create_polling_connection(2090).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let socket = io.sockets().await.unwrap().pop().unwrap();
+    assert_eq!(socket.transport_type(), TransportType::Polling);
+
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:2090/socket.io/?EIO=4&transport=websocket&sid={sid}"
+    ))
+    .await
+    .unwrap();
+    stream.send(Message::Text("2probe".into())).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, "3probe");
+    stream.send(Message::Text("5".into())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    assert_eq!(socket.transport_type(), TransportType::Websocket);
+}
+
+#[tokio::test]
+pub async fn force_polling() {
+    const PORT: u16 = 2290;
+    let io = create_server(PORT).await;
+    io.ns("/", |_: SocketRef| {});
+
+    let sid = create_polling_connection(PORT).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let socket = io.sockets().await.unwrap().pop().unwrap();
+    assert!(socket.force_polling());
+
+    // The upgrade probe fails
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{PORT}/socket.io/?EIO=4&transport=websocket&sid={sid}"
+    ))
+    .await
+    .unwrap();
+    stream.send(Message::Text("2probe".into())).await.unwrap();
+    let msg = stream.next().await;
+    assert!(!matches!(msg, Some(Ok(Message::Text(_)))), "{msg:?}");
+    assert_eq!(socket.transport_type(), TransportType::Polling);
+
+    // The following packets are received with polling
+    let params = format!("transport=polling&sid={sid}");
+    let _connect = send_req(PORT, params.clone(), http::Method::GET, None).await;
+    socket.emit("msg", "hello").unwrap();
+    let msg = send_req(PORT, params, http::Method::GET, None).await;
+    assert!(msg.ends_with(r#"2["msg","hello"]"#), "{msg}");
+
+    // An upgraded connection can't be downgraded
+    let _stream = create_ws_connection(PORT).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let socket = io
+        .sockets()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|s| s.transport_type() == TransportType::Websocket)
+        .unwrap();
+    assert!(!socket.force_polling());
+}
+
+/// Extracts the ids of the `msg` events of a payload or a websocket message
+fn msg_ids(payload: &str) -> Vec<i32> {
+    payload
+        .split('\x1e')
+        .filter_map(|p| {
+            p.split_once(r#"["msg","#)?
+                .1
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect()
+}
+
+#[tokio::test]
+pub async fn upgrade_keeps_order() {
+    const PORT: u16 = 2308;
+    let io = create_server(PORT).await;
+    io.ns("/", |_: SocketRef| {});
+
+    let sid = create_polling_connection(PORT).await;
+    let params = format!("transport=polling&sid={sid}");
+    let _connect = send_req(PORT, params.clone(), http::Method::GET, None).await;
+    let socket = io.sockets().await.unwrap().pop().unwrap();
+
+    let (mut stream, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{PORT}/socket.io/?EIO=4&transport=websocket&sid={sid}"
+    ))
+    .await
+    .unwrap();
+    stream.send(Message::Text("2probe".into())).await.unwrap();
+    let msg = stream.next().await.unwrap().unwrap().to_string();
+    assert_eq!(msg, "3probe");
+
+    // The messages are emitted while the client drains the polling transport and upgrades
+    let emitter = tokio::spawn(async move {
+        for i in 0..100 {
+            socket.emit("msg", i).unwrap();
+            tokio::task::yield_now().await;
+        }
+    });
+    let payload = send_req(PORT, params, http::Method::GET, None).await;
+    stream.send(Message::Text("5".into())).await.unwrap();
+    emitter.await.unwrap();
+
+    let mut received = msg_ids(&payload);
+    while received.last() != Some(&99) {
+        let msg = tokio::time::timeout(Duration::from_millis(200), stream.next())
+            .await
+            .expect("timeout waiting for the websocket messages")
+            .unwrap()
+            .unwrap();
+        received.extend(msg_ids(&msg.to_string()));
+    }
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
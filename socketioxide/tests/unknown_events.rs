@@ -0,0 +1,51 @@
+//! Tests for the events without a registered handler, see [`SocketIoBuilder::reject_unknown_events`]
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use socketioxide::{
+    extract::{AckSender, SocketRef},
+    testing::{TestClient, TestClientError},
+    SocketIo,
+};
+
+fn create_io(reject: bool) -> SocketIo {
+    let (_, io) = SocketIo::builder()
+        .reject_unknown_events(reject)
+        .build_svc();
+    io.ns("/", |s: SocketRef| {
+        s.on("known", |ack: AckSender| {
+            ack.send("ok").ok();
+        });
+    });
+    io
+}
+
+#[tokio::test]
+pub async fn reject_unknown_events() {
+    let io = create_io(true);
+    let mut client = TestClient::connect(&io, "/")
+        .await
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+
+    let res: Value = client.emit_with_ack("unknown", ()).await.unwrap();
+    assert_eq!(res, json!({ "message": "unknown event" }));
+    client.emit("unknown", ()).unwrap();
+    let res: String = client.emit_with_ack("known", ()).await.unwrap();
+    assert_eq!(res, "ok");
+
+    assert_eq!(io.metrics().await.unwrap().rejected_events, 2);
+}
+
+#[tokio::test]
+pub async fn drop_unknown_events() {
+    let io = create_io(false);
+    let mut client = TestClient::connect(&io, "/")
+        .await
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+
+    let res = client.emit_with_ack::<Value>("unknown", ()).await;
+    assert!(matches!(res, Err(TestClientError::Timeout)), "{res:?}");
+    assert_eq!(io.metrics().await.unwrap().rejected_events, 0);
+}